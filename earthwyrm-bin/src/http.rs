@@ -0,0 +1,74 @@
+// http.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use anyhow::{Context, Result};
+use axum::{
+    body::HttpBody,
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use earthwyrm::HttpCfg;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Static response headers
+#[derive(Clone, Default)]
+pub struct ResponseHeaders(Arc<Vec<(HeaderName, HeaderValue)>>);
+
+impl TryFrom<&HttpCfg> for ResponseHeaders {
+    type Error = anyhow::Error;
+
+    fn try_from(cfg: &HttpCfg) -> Result<Self> {
+        let mut headers = Vec::new();
+        for (name, value) in cfg.headers()? {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("header name: {name}"))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("header value: {value}"))?;
+            headers.push((name, value));
+        }
+        Ok(ResponseHeaders(Arc::new(headers)))
+    }
+}
+
+impl ResponseHeaders {
+    /// Check if there are no headers
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Middleware to add static headers to responses
+pub async fn add_headers(
+    State(headers): State<ResponseHeaders>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mut res = next.run(req).await;
+    for (name, value) in headers.0.iter() {
+        res.headers_mut().insert(name.clone(), value.clone());
+    }
+    res
+}
+
+/// Middleware to log requests
+pub async fn access_log(req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let t = Instant::now();
+    let res = next.run(req).await;
+    // streamed responses have no exact length
+    let bytes = match res.body().size_hint().exact() {
+        Some(len) => len.to_string(),
+        None => "-".to_string(),
+    };
+    log::info!(
+        "{method} {path} {} {:?} {bytes}",
+        res.status().as_u16(),
+        t.elapsed()
+    );
+    res
+}
@@ -0,0 +1,30 @@
+// lib.rs
+//
+// Copyright (c) 2021-2024  Minnesota Department of Transportation
+//
+//! Axum routes for serving earthwyrm tiles.
+//!
+//! The routes can be nested within another axum application:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use axum::Router;
+//! # use earthwyrm::{Wyrm, WyrmCfg};
+//! # use earthwyrm_axum::{earthwyrm_router, RouterOpts};
+//! # fn main() -> anyhow::Result<()> {
+//! let cfg = WyrmCfg::load()?;
+//! let wyrm = Arc::new(Wyrm::try_from(&cfg)?);
+//! let opts = RouterOpts::default().with_http(&cfg.http)?;
+//! let app: Router = Router::new().nest("/maps", earthwyrm_router(wyrm, opts));
+//! # Ok(())
+//! # }
+//! ```
+#![forbid(unsafe_code)]
+
+mod http;
+mod live;
+mod router;
+mod stream;
+
+pub use live::{watch_loam, Live};
+pub use router::{earthwyrm_router, live_router, RouterOpts};
@@ -0,0 +1,183 @@
+// live.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use earthwyrm::{Error, Manifest, Wyrm, WyrmCfg, MANIFEST};
+use serde_json::json;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_stream::{wrappers::WatchStream, Stream, StreamExt};
+
+/// Live tile state, reloaded after each completed dig
+pub struct Live {
+    /// Current wyrm
+    wyrm: RwLock<Arc<Wyrm>>,
+
+    /// Reload notifications (number of reloads)
+    reload: watch::Sender<u64>,
+
+    /// Loam files not matching dig manifest (still serving previous)
+    mismatch: RwLock<Option<String>>,
+}
+
+impl From<Arc<Wyrm>> for Live {
+    fn from(wyrm: Arc<Wyrm>) -> Self {
+        let (reload, _rx) = watch::channel(0);
+        Live {
+            wyrm: RwLock::new(wyrm),
+            reload,
+            mismatch: RwLock::new(None),
+        }
+    }
+}
+
+impl Live {
+    /// Create live state
+    pub fn new(wyrm: Wyrm) -> Self {
+        Live::from(Arc::new(wyrm))
+    }
+
+    /// Get the current wyrm
+    pub fn wyrm(&self) -> Arc<Wyrm> {
+        let wyrm = self.wyrm.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&wyrm)
+    }
+
+    /// Replace the wyrm, notifying subscribers
+    fn replace(&self, wyrm: Wyrm) {
+        *self.wyrm.write().unwrap_or_else(PoisonError::into_inner) =
+            Arc::new(wyrm);
+        self.reload.send_modify(|n| *n += 1);
+    }
+
+    /// Set (or clear) loam mismatch
+    fn set_mismatch(&self, mismatch: Option<String>) {
+        *self
+            .mismatch
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = mismatch;
+    }
+
+    /// Get loam mismatch, if any
+    fn mismatch(&self) -> Option<String> {
+        self.mismatch
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Subscribe to reload notifications.
+    ///
+    /// The value is the number of reloads; it changes after the new layers
+    /// are being served.
+    pub fn subscribe_reload(&self) -> watch::Receiver<u64> {
+        self.reload.subscribe()
+    }
+}
+
+/// Get the dig identity from the loam directory manifest.
+///
+/// The manifest is moved into place after all other loam files, so this
+/// only changes once a dig has completed.
+fn manifest_stamp(dir: &Path) -> Option<String> {
+    let manifest = std::fs::read_to_string(dir.join(MANIFEST)).ok()?;
+    let manifest: Manifest = muon_rs::from_str(&manifest).ok()?;
+    Some(format!("{} {}", manifest.dig_id, manifest.dig_time))
+}
+
+/// Poll the loam directory, reloading layers after each completed dig.
+///
+/// Digs are detected by a change to the dig manifest; loam directories
+/// without one are not reloaded.
+pub async fn watch_loam(
+    live: Arc<Live>,
+    cfg: Arc<WyrmCfg>,
+    interval: Duration,
+) {
+    let dir = cfg.loam_dir();
+    let mut stamp = manifest_stamp(&dir);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let current = manifest_stamp(&dir);
+        if current.is_none() || current == stamp {
+            continue;
+        }
+        stamp = current;
+        let cfg = Arc::clone(&cfg);
+        let res =
+            tokio::task::spawn_blocking(move || Wyrm::try_from(&*cfg)).await;
+        match res {
+            Ok(Ok(wyrm)) => {
+                log::info!("reloaded loam layers from {dir:?}");
+                live.set_mismatch(None);
+                live.replace(wyrm);
+            }
+            Ok(Err(err @ Error::LoamMismatch(_))) => {
+                log::error!("reload: {err}; serving previous layers");
+                live.set_mismatch(Some(err.to_string()));
+                // files may still be copying; check again next time
+                stamp = None;
+            }
+            Ok(Err(err)) => log::warn!("reload: {err}"),
+            Err(err) => log::warn!("reload task: {err:?}"),
+        }
+    }
+}
+
+/// Make a reload event, with current group versions
+fn reload_event(wyrm: &Wyrm) -> Event {
+    let groups: Vec<_> = wyrm
+        .groups()
+        .map(|group| {
+            json!({
+                "name": group.name(),
+                "version": group.version(),
+            })
+        })
+        .collect();
+    Event::default()
+        .event("reload")
+        .data(json!({ "groups": groups }).to_string())
+}
+
+/// Router for `/readyz` (readiness check).
+///
+/// Responds `503 Service Unavailable` while the loam directory does not
+/// match its dig manifest, such as during a partial copy.
+pub fn readyz(live: Arc<Live>) -> Router {
+    async fn handler(State(live): State<Arc<Live>>) -> impl IntoResponse {
+        match live.mismatch() {
+            Some(mismatch) => (StatusCode::SERVICE_UNAVAILABLE, mismatch),
+            None => (StatusCode::OK, "ready".to_string()),
+        }
+    }
+    Router::new()
+        .route("/readyz", get(handler))
+        .with_state(live)
+}
+
+/// Router for `/events` (server-sent reload events)
+pub fn events(live: Arc<Live>) -> Router {
+    async fn handler(
+        State(live): State<Arc<Live>>,
+    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let stream = WatchStream::from_changes(live.subscribe_reload())
+            .map(move |_n| Ok(reload_event(&live.wyrm())));
+        Sse::new(stream).keep_alive(KeepAlive::default())
+    }
+    Router::new()
+        .route("/events", get(handler))
+        .with_state(live)
+}
@@ -4,49 +4,44 @@
 //
 #![forbid(unsafe_code)]
 
+mod sink;
+
 use anyhow::{anyhow, Context, Result};
 use argh::FromArgs;
-use axum::{
-    extract::{Path as AxumPath, State},
-    http::{header, StatusCode},
-    response::IntoResponse,
-    routing::get,
-    Router,
+use axum::Router;
+use earthwyrm::{
+    decode_summary, diff_tiles, loam_names, migrate_loam, BindAddr,
+    ConfigIssue, ConfigReport, DigStage, IdPolicy, LayerDef, LogFormat,
+    MigrateStatus, ObjMatch, TileFetch, TileGrid, TileId, ValidationReport,
+    Wyrm, WyrmCfg, LOAM_FORMAT,
 };
-use earthwyrm::{TileId, Wyrm, WyrmCfg};
+use earthwyrm_axum::{live_router, watch_loam, Live, RouterOpts};
+use listenfd::ListenFd;
 use mvt::{WebMercatorPos, Wgs84Pos};
 use pointy::BBox;
-use serde::Deserialize;
-use std::fs::{DirEntry, File};
-use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
+use sd_notify::NotifyState;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs::{read, File};
+use std::io::{BufWriter, Write};
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::task::JoinSet;
 
-/// Get path to the newest OSM file
-fn osm_newest() -> Result<PathBuf> {
-    let path = Path::new("osm");
-    path.read_dir()
-        .with_context(|| format!("reading directory: {path:?}"))?
-        .filter_map(Result::ok)
-        .filter(is_pbf_file)
-        .max_by_key(|de| de.metadata().unwrap().modified().unwrap())
-        .map(|de| path.join(de.file_name()))
-        .ok_or_else(|| anyhow!("no OSM file found"))
-}
+#[cfg(feature = "s3")]
+use sink::S3Sink;
+use sink::{DirSink, TileSink};
 
-/// Check if a directory entry is a PBF file
-fn is_pbf_file(de: &DirEntry) -> bool {
-    match de.file_type() {
-        Ok(ft) if ft.is_file() => {
-            let name = de.file_name();
-            let path: &Path = name.as_ref();
-            path.extension().unwrap_or_default() == "pbf"
-        }
-        _ => false,
-    }
-}
+/// Exit status for an invalid configuration
+const CONFIG_INVALID: i32 = 1;
+
+/// Exit status for an unreadable configuration file
+const CONFIG_UNREADABLE: i32 = 2;
 
 /// Command-line arguments
 #[derive(FromArgs, PartialEq, Debug)]
@@ -70,17 +65,86 @@ enum Command {
 
     /// Serve tiles with http
     Serve(ServeCommand),
+
+    /// Decode and dump an MVT tile
+    Dump(DumpCommand),
+
+    /// Seed tiles into a directory
+    Seed(SeedCommand),
+
+    /// Match layer tag patterns against an OSM file
+    Match(MatchCommand),
+
+    /// Validate polygon geometry in loam files
+    Validate(ValidateCommand),
+
+    /// Export a loam layer as GeoJSON
+    Export(ExportCommand),
+
+    /// Compare tiles from two loam directories
+    Diff(DiffCommand),
+
+    /// Check configuration, or describe its schema
+    Config(ConfigCommand),
+
+    /// Analyze sampled tiles to choose tile and edge extents
+    Analyze(AnalyzeCommand),
+
+    /// Migrate loam files to the current format
+    Migrate(MigrateCommand),
 }
 
 /// Initialize earthwyrm configuration
-#[derive(Clone, Copy, FromArgs, PartialEq, Debug)]
+#[derive(Clone, FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "init")]
-struct InitCommand {}
+struct InitCommand {
+    /// target directory
+    #[argh(option, default = "PathBuf::from(\".\")")]
+    dir: PathBuf,
+
+    /// overwrite existing files
+    #[argh(switch)]
+    force: bool,
+
+    /// server bind address
+    #[argh(option, default = "String::from(\"127.0.0.1:3030\")")]
+    bind: String,
+
+    /// service user name
+    #[argh(option, default = "String::from(\"earthwyrm\")")]
+    user: String,
+}
 
 /// Dig loam layers from OSM file
-#[derive(Clone, Copy, FromArgs, PartialEq, Debug)]
+#[derive(Clone, FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "dig")]
-struct DigCommand {}
+struct DigCommand {
+    /// OSM file, repeated to merge files (default: `osm_files`, or newest
+    /// in `osm` directory)
+    #[argh(option)]
+    osm: Vec<PathBuf>,
+
+    /// policy for OSM IDs in more than one file: `last_wins`, `error` or
+    /// `namespace` (default: `osm_id_policy`)
+    #[argh(option)]
+    id_policy: Option<IdPolicy>,
+
+    /// dig stage: `extract`, `geometry` or `all` (default)
+    #[argh(option, default = "DigStage::All")]
+    stage: DigStage,
+
+    /// keep staging directory on failure (for debugging)
+    #[argh(switch)]
+    keep_staging: bool,
+
+    /// rebuild all layers, even if unchanged
+    #[argh(switch)]
+    force: bool,
+
+    /// log skipped OSM objects to a file (layer, object and reason)
+    #[argh(option)]
+    explain_skips: Option<PathBuf>,
+}
 
 /// Query a map layer
 #[derive(Clone, Copy, FromArgs, PartialEq, Debug)]
@@ -91,6 +155,14 @@ struct QueryCommand {
 
     #[argh(positional)]
     lon: f64,
+
+    /// search radius in meters (default 1)
+    #[argh(option, short = 'r', default = "1.0")]
+    radius: f64,
+
+    /// output features as GeoJSON (including geometry)
+    #[argh(switch, short = 'g')]
+    geojson: bool,
 }
 
 /// Serve tiles using http
@@ -100,47 +172,509 @@ struct ServeCommand {
     /// include leaflet map for testing
     #[argh(switch, short = 'l')]
     leaflet: bool,
+
+    /// reload layers after each completed dig (manifest change), polling
+    /// every N seconds (also enables `/events` reload notifications)
+    #[argh(option)]
+    reload: Option<u64>,
+
+    /// check full loam file hashes against the dig manifest; by default,
+    /// only sizes and the first and last 64 KiB of each file are checked,
+    /// which misses same-size changes in the middle of a file
+    #[argh(switch)]
+    full_check: bool,
+}
+
+/// Decode and dump an MVT tile
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "dump")]
+struct DumpCommand {
+    /// fetch tile using configured layers (group z x y)
+    #[argh(switch, short = 'f')]
+    fetch: bool,
+
+    /// output summary as JSON
+    #[argh(switch, short = 'j')]
+    json: bool,
+
+    /// filter output (layer=name)
+    #[argh(option)]
+    filter: Option<String>,
+
+    /// tile file path, or group z x y with --fetch
+    #[argh(positional)]
+    tile: Vec<String>,
+}
+
+/// Seed tiles into a directory
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "seed")]
+struct SeedCommand {
+    /// layer group name
+    #[argh(positional)]
+    group: String,
+
+    /// zoom levels (ex. 10-14)
+    #[argh(positional)]
+    zoom: String,
+
+    /// output directory
+    #[argh(option, short = 'd', default = "PathBuf::from(\"tiles\")")]
+    dir: PathBuf,
+
+    /// output object store (ex. s3://bucket/prefix), instead of directory
+    #[argh(option)]
+    sink: Option<String>,
+
+    /// delete stale tiles which are now empty
+    #[argh(switch)]
+    delete_empty: bool,
+
+    /// resume from tile ID (z/x/y), within the zoom range and extent
+    #[argh(option)]
+    resume_from: Option<String>,
+}
+
+/// Match layer tag patterns against an OSM file (without digging)
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "match")]
+struct MatchCommand {
+    /// layer name
+    #[argh(option, short = 'l')]
+    layer: String,
+
+    /// OSM file (default: newest in `osm` directory)
+    #[argh(positional)]
+    osm: Option<PathBuf>,
+
+    /// maximum number of matching objects to print
+    #[argh(option, default = "20")]
+    limit: usize,
+
+    /// explain matching of one object (ex. way/123)
+    #[argh(option)]
+    explain: Option<String>,
+}
+
+/// Validate polygon geometry in loam files (without digging)
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "validate")]
+struct ValidateCommand {
+    /// layer group name (default: all groups)
+    #[argh(option, short = 'g')]
+    group: Option<String>,
+
+    /// maximum number of problems allowed
+    #[argh(option, default = "0")]
+    max_errors: usize,
+}
+
+/// Export a loam layer as GeoJSON
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "export")]
+struct ExportCommand {
+    /// layer group name
+    #[argh(option, short = 'g')]
+    group: String,
+
+    /// layer name
+    #[argh(option, short = 'l')]
+    layer: String,
+
+    /// bounding box (west,south,east,north in degrees)
+    #[argh(option, from_str_fn(parse_bbox))]
+    bbox: Option<BBox<f64>>,
+
+    /// output file (default: stdout)
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+/// Compare tiles from two loam directories (or loam and seeded tiles)
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "diff")]
+struct DiffCommand {
+    /// layer group name
+    #[argh(positional)]
+    group: String,
+
+    /// zoom levels (ex. 10-14)
+    #[argh(positional)]
+    zoom: String,
+
+    /// first loam directory
+    #[argh(positional)]
+    a: PathBuf,
+
+    /// second loam directory (or seeded tile directory, with --tiles)
+    #[argh(positional)]
+    b: PathBuf,
+
+    /// second directory contains seeded tiles
+    #[argh(switch)]
+    tiles: bool,
+
+    /// bounding box (west,south,east,north in degrees)
+    #[argh(option, from_str_fn(parse_bbox))]
+    bbox: Option<BBox<f64>>,
+
+    /// output as JSON
+    #[argh(switch, short = 'j')]
+    json: bool,
+
+    /// number of most-changed tiles to summarize
+    #[argh(option, default = "10")]
+    top: usize,
+}
+
+/// Analyze sampled tiles to choose tile and edge extents
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "analyze")]
+struct AnalyzeCommand {
+    /// layer group name
+    #[argh(option, short = 'g')]
+    group: String,
+
+    /// zoom levels (ex. 10-14; default: group zoom range)
+    #[argh(option)]
+    zoom: Option<String>,
+
+    /// number of tiles sampled at each zoom level
+    #[argh(option, default = "20")]
+    samples: usize,
+
+    /// random seed for sampling tiles
+    #[argh(option, default = "1")]
+    seed: u64,
+}
+
+/// Migrate loam files to the current format, without digging
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "migrate")]
+struct MigrateCommand {}
+
+/// Check configuration, or describe its schema
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "config")]
+struct ConfigCommand {
+    #[argh(subcommand)]
+    cmd: ConfigSubCommand,
+}
+
+/// Config sub-commands
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ConfigSubCommand {
+    /// Check configuration file (exit status 1 if invalid, 2 if unreadable)
+    Check(ConfigCheckCommand),
+
+    /// Describe accepted configuration fields and tag patterns as JSON
+    Schema(ConfigSchemaCommand),
+}
+
+/// Check configuration file (exit status 1 if invalid, 2 if unreadable)
+#[derive(Clone, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "check")]
+struct ConfigCheckCommand {
+    /// configuration file (default `earthwyrm.muon`)
+    #[argh(option, default = "PathBuf::from(\"earthwyrm.muon\")")]
+    file: PathBuf,
+
+    /// output report as JSON
+    #[argh(switch, short = 'j')]
+    json: bool,
 }
 
+/// Describe accepted configuration fields and tag patterns as JSON
+#[derive(Clone, Copy, FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "schema")]
+struct ConfigSchemaCommand {}
+
 impl InitCommand {
     /// Initialize earthwyrm configuration
     fn init(self) -> Result<()> {
-        let home_path = Path::new(".");
+        let home_path = &self.dir;
+        std::fs::create_dir_all(home_path)?;
         // Set home directory permissions: drwxr-x---
         std::fs::set_permissions(home_path, PermissionsExt::from_mode(0o750))?;
-        let osm_path = Path::new("osm");
+        let osm_path = home_path.join("osm");
         std::fs::create_dir_all(osm_path)?;
-        let loam_path = Path::new("loam");
-        std::fs::create_dir_all(loam_path)?;
+        let loam_path = home_path.join("loam");
+        std::fs::create_dir_all(&loam_path)?;
         // Set loam directory permissions: drwxrwxr-x
         std::fs::set_permissions(loam_path, PermissionsExt::from_mode(0o775))?;
-        write_file(
-            Path::new("earthwyrm.muon"),
-            include_bytes!("../res/earthwyrm.muon"),
+        let dir = std::fs::canonicalize(home_path)?;
+        let vars = [
+            ("bind", self.bind.as_str()),
+            ("user", self.user.as_str()),
+            ("dir", &dir.to_string_lossy()),
+        ];
+        self.write_file(
+            &home_path.join("earthwyrm.muon"),
+            &fill_template(include_str!("../res/earthwyrm.muon"), &vars),
         )?;
-        write_file(
-            Path::new("earthwyrm.service"),
-            include_bytes!("../res/earthwyrm.service"),
+        self.write_file(
+            &home_path.join("earthwyrm.service"),
+            &fill_template(include_str!("../res/earthwyrm.service"), &vars),
         )?;
         Ok(())
     }
+
+    /// Write a file to specified path.
+    ///
+    /// Existing files are only replaced with `--force`; otherwise, a diff
+    /// against the new contents is printed.
+    fn write_file(&self, path: &Path, contents: &str) -> Result<()> {
+        if path.exists() && !self.force {
+            let existing = std::fs::read_to_string(path)
+                .with_context(|| format!("reading: {path:?}"))?;
+            if existing == contents {
+                println!("Unchanged file: {path:?}");
+            } else {
+                println!("Existing file: {path:?} (use --force to replace)");
+                print_diff(&existing, contents);
+            }
+            return Ok(());
+        }
+        println!("Writing file: {path:?}");
+        let mut file = File::create(path)?;
+        Ok(file.write_all(contents.as_bytes())?)
+    }
 }
 
-/// Write a file to specified path
-fn write_file<P>(path: P, contents: &[u8]) -> Result<()>
-where
-    P: AsRef<Path> + core::fmt::Debug,
-{
-    println!("Writing file: {path:?}");
-    let mut file = File::options().create_new(true).write(true).open(&path)?;
-    Ok(file.write_all(contents)?)
+/// Fill `{{name}}` placeholders in a template
+fn fill_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in vars {
+        text = text.replace(&format!("{{{{{name}}}}}"), value);
+    }
+    text
+}
+
+/// Print a line diff (`-` existing, `+` new)
+fn print_diff(existing: &str, new: &str) {
+    let a: Vec<&str> = existing.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // longest common subsequence lengths, from the end
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j < b.len()
+            && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j])
+        {
+            println!("+{}", b[j]);
+            j += 1;
+        } else {
+            println!("-{}", a[i]);
+            i += 1;
+        }
+    }
+}
+
+impl MatchCommand {
+    /// Find layer definition in configuration
+    fn layer_def(&self, cfg: &WyrmCfg) -> Result<LayerDef> {
+        for group in &cfg.layer_group {
+            for layer in &group.layer {
+                if layer.name == self.layer {
+                    return Ok(LayerDef::try_from(layer)?
+                        .with_name_languages(&group.name_languages));
+                }
+            }
+        }
+        Err(anyhow!("unknown layer: {}", self.layer))
+    }
+
+    /// Match layer tag patterns against an OSM file
+    fn scan(&self, cfg: WyrmCfg) -> Result<()> {
+        let layer = self.layer_def(&cfg)?;
+        let osm = match &self.osm {
+            Some(osm) => osm.clone(),
+            None => cfg.find_osm()?,
+        };
+        if let Some(obj) = &self.explain {
+            let (kind, id) = obj
+                .split_once('/')
+                .ok_or_else(|| anyhow!("invalid object: {obj}"))?;
+            match layer.explain_match(&osm, kind, id.parse()?)? {
+                Some(m) => print_match(&m, true),
+                None => println!("{obj}: not found"),
+            }
+            return Ok(());
+        }
+        let n_match = layer.scan_matches(&osm, self.limit, |m| {
+            print_match(m, false);
+        })?;
+        println!("{n_match} objects matched layer {}", self.layer);
+        Ok(())
+    }
+}
+
+/// Print an object checked against layer patterns
+///
+/// * `explain` Print all patterns (not only those with values).
+fn print_match(m: &ObjMatch, explain: bool) {
+    let status = if m.matched { "matched" } else { "not matched" };
+    println!("{}/{}: {status}", m.kind, m.id);
+    for check in &m.checks {
+        if !explain && check.matched && check.value.is_none() {
+            continue;
+        }
+        let mark = if check.matched { "ok" } else { "REJECTED" };
+        match &check.value {
+            Some(val) => println!("  {mark:8} {} ({val:?})", check.pattern),
+            None => println!("  {mark:8} {} (no value)", check.pattern),
+        }
+    }
+    if explain && !m.matched && m.checks.iter().all(|c| c.matched) {
+        println!("  rejected by geometry type rules (object type / area)");
+    }
+}
+
+impl MigrateCommand {
+    /// Migrate all dug layers
+    fn migrate(&self, cfg: WyrmCfg) -> Result<()> {
+        println!("loam format: {LOAM_FORMAT}");
+        let mut done = HashSet::new();
+        let mut n_dig = 0;
+        for group in &cfg.layer_group {
+            for layer in &group.layer {
+                // shared loam layers have no files of their own
+                if layer.loam.is_some() {
+                    continue;
+                }
+                // ranked layers have one file per zoom bucket
+                for name in loam_names(layer)? {
+                    if !done.insert(name.clone()) {
+                        continue;
+                    }
+                    let loam = cfg.loam_path(&name);
+                    match migrate_loam(&loam)? {
+                        MigrateStatus::NoMeta => {
+                            println!("  layer: {name} (not dug)")
+                        }
+                        MigrateStatus::Current => {
+                            println!("  layer: {name} (current)")
+                        }
+                        MigrateStatus::Migrated(from) => {
+                            println!("  layer: {name} (migrated from {from})")
+                        }
+                        MigrateStatus::Unsupported(from) => {
+                            println!(
+                                "  layer: {name} ({from}: no migration, \
+                                dig required)"
+                            );
+                            n_dig += 1;
+                        }
+                    }
+                }
+            }
+        }
+        if n_dig > 0 {
+            return Err(anyhow!("{n_dig} layers must be dug again"));
+        }
+        Ok(())
+    }
+}
+
+impl AnalyzeCommand {
+    /// Analyze sampled tiles
+    fn analyze(&self, cfg: WyrmCfg) -> Result<()> {
+        let wyrm = Wyrm::try_from(&cfg)?;
+        let zoom = match &self.zoom {
+            Some(zoom) => parse_zoom_range(zoom)?,
+            None => {
+                let (zmin, zmax) = wyrm
+                    .group_zoom(&self.group)
+                    .ok_or_else(|| anyhow!("unknown group: {}", self.group))?;
+                zmin..=zmax
+            }
+        };
+        let tids =
+            wyrm.sample_tiles(&self.group, zoom, self.samples, self.seed)?;
+        let analysis = wyrm.analyze(&self.group, &tids)?;
+        print!("{analysis}");
+        Ok(())
+    }
+}
+
+impl ValidateCommand {
+    /// Validate polygon layers
+    fn validate(&self, cfg: WyrmCfg) -> Result<()> {
+        let wyrm = Wyrm::try_from(&cfg)?;
+        let mut n_problems = 0;
+        for group in wyrm.groups() {
+            if self.group.as_ref().is_some_and(|g| g != group.name()) {
+                continue;
+            }
+            for report in wyrm.validate(group.name())? {
+                print_report(&report);
+                n_problems += report.n_problems();
+            }
+        }
+        println!("{n_problems} problems");
+        if n_problems > self.max_errors {
+            return Err(anyhow!(
+                "{n_problems} problems exceed max-errors {}",
+                self.max_errors
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Print a validation report
+fn print_report(report: &ValidationReport) {
+    println!(
+        "layer: {} ({} polygons, {} problems)",
+        report.layer,
+        report.features,
+        report.n_problems()
+    );
+    for (problem, count) in &report.problems {
+        if count.osm_ids.is_empty() {
+            println!("  {problem}: {}", count.count);
+        } else {
+            println!(
+                "  {problem}: {} (osm_id {})",
+                count.count,
+                count.osm_ids.join(", ")
+            );
+        }
+    }
 }
 
 impl DigCommand {
     /// Dig loam layers from OSM file
-    fn dig(self, cfg: WyrmCfg) -> Result<()> {
-        let osm = osm_newest()?;
-        Ok(cfg.extract_osm(osm)?)
+    fn dig(self, mut cfg: WyrmCfg) -> Result<()> {
+        if let Some(path) = self.explain_skips {
+            cfg.skip_log = Some(path);
+        }
+        if let Some(policy) = self.id_policy {
+            cfg.osm_id_policy = policy;
+        }
+        let osm = if self.osm.is_empty() {
+            cfg.osm_files()?
+        } else {
+            self.osm
+        };
+        cfg.extract_osm(&osm, self.stage, self.keep_staging, self.force)?;
+        if self.stage == DigStage::All {
+            Wyrm::try_from(&cfg)?.build_tile_bitmaps()?;
+        }
+        Ok(())
     }
 }
 
@@ -148,119 +682,532 @@ impl QueryCommand {
     /// Query a lat/lon position
     fn query(&self, cfg: WyrmCfg) -> Result<()> {
         let wyrm = Wyrm::try_from(&cfg)?;
-        let pos = Wgs84Pos::new(self.lat, self.lon);
-        let pos = WebMercatorPos::from(pos);
-        let bbox = BBox::new([pos]);
-        wyrm.query_features(bbox)?;
+        if self.geojson {
+            let json =
+                wyrm.query_geojson_near(self.lat, self.lon, self.radius)?;
+            println!("{json}");
+        } else {
+            let bbox = Wyrm::bbox_around(self.lat, self.lon, self.radius)?;
+            wyrm.query_features(bbox)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExportCommand {
+    /// Export a layer as GeoJSON
+    fn export(&self, cfg: WyrmCfg) -> Result<()> {
+        let wyrm = Wyrm::try_from(&cfg)?;
+        let n_features = match &self.output {
+            Some(path) => {
+                let file = File::create(path)
+                    .with_context(|| format!("creating: {path:?}"))?;
+                wyrm.export_geojson(
+                    &self.group,
+                    &self.layer,
+                    self.bbox,
+                    BufWriter::new(file),
+                )?
+            }
+            None => wyrm.export_geojson(
+                &self.group,
+                &self.layer,
+                self.bbox,
+                BufWriter::new(std::io::stdout().lock()),
+            )?,
+        };
+        log::info!("exported {n_features} features");
         Ok(())
     }
 }
 
 impl ServeCommand {
     /// Serve tiles using http
-    fn serve(&self, cfg: WyrmCfg) -> Result<()> {
-        let wyrm = Arc::new(Wyrm::try_from(&cfg)?);
+    fn serve(&self, mut cfg: WyrmCfg) -> Result<()> {
+        if self.full_check {
+            cfg = cfg.with_full_check();
+        }
+        let live = Arc::new(Live::new(Wyrm::try_from(&cfg)?));
+        let inherited = inherited_listeners()?;
+        let addrs = cfg.bind_addrs()?;
+        let mode = cfg.socket_mode()?;
+        let opts = RouterOpts::default()
+            .with_http(&cfg.http)?
+            .with_leaflet(self.leaflet)
+            .with_events(self.reload.is_some());
+        let cfg = Arc::new(cfg);
         let rt = tokio::runtime::Runtime::new()?;
         rt.block_on(async {
-            let mut app = Router::new();
-            if self.leaflet {
-                app = app.merge(index_html()).merge(map_css()).merge(map_js());
+            // bind every listener before serving on any of them
+            let listeners = if inherited.is_empty() {
+                bind_all(addrs, mode).await?
+            } else {
+                log::info!("socket activated ({} listeners)", inherited.len());
+                inherited
+                    .into_iter()
+                    .map(Listener::try_from)
+                    .collect::<Result<_>>()?
+            };
+            if let Some(secs) = self.reload {
+                let interval = Duration::from_secs(secs.max(1));
+                tokio::spawn(watch_loam(Arc::clone(&live), cfg, interval));
+            }
+            let app = live_router(live, opts);
+            notify_ready();
+            serve_listeners(listeners, app).await
+        })
+    }
+}
+
+/// Bound listener, ready to serve
+enum Listener {
+    /// TCP listener
+    Tcp(TcpListener),
+
+    /// Unix socket listener
+    Unix(UnixListener),
+}
+
+/// Listener inherited by systemd socket activation
+enum Inherited {
+    /// TCP listener
+    Tcp(std::net::TcpListener),
+
+    /// Unix socket listener
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Take listeners inherited by socket activation (`LISTEN_FDS` and
+/// `LISTEN_PID`).
+///
+/// Returns no listeners when not socket activated, so `bind_address` is
+/// used instead.
+fn inherited_listeners() -> Result<Vec<Inherited>> {
+    let mut fds = ListenFd::from_env();
+    let mut listeners = Vec::with_capacity(fds.len());
+    for i in 0..fds.len() {
+        if let Ok(Some(listener)) = fds.take_tcp_listener(i) {
+            listeners.push(Inherited::Tcp(listener));
+            continue;
+        }
+        match fds.take_unix_listener(i) {
+            Ok(Some(listener)) => listeners.push(Inherited::Unix(listener)),
+            Ok(None) => return Err(anyhow!("inherited fd {i} already taken")),
+            Err(e) => return Err(anyhow!("inherited fd {i}: {e}")),
+        }
+    }
+    Ok(listeners)
+}
+
+/// Notify systemd that the server is ready (when `NOTIFY_SOCKET` is set)
+fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        log::warn!("sd_notify: {e}");
+    }
+}
+
+impl TryFrom<Inherited> for Listener {
+    type Error = anyhow::Error;
+
+    fn try_from(listener: Inherited) -> Result<Self> {
+        match listener {
+            Inherited::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                log::info!("listening on inherited {}", listener.local_addr()?);
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+            }
+            Inherited::Unix(listener) => {
+                listener.set_nonblocking(true)?;
+                log::info!("listening on inherited unix socket");
+                Ok(Listener::Unix(UnixListener::from_std(listener)?))
+            }
+        }
+    }
+}
+
+/// Bind all addresses, failing if any cannot be bound
+async fn bind_all(
+    addrs: Vec<BindAddr>,
+    mode: Option<u32>,
+) -> Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        listeners.push(bind_addr(addr, mode).await?);
+    }
+    Ok(listeners)
+}
+
+/// Bind one address
+async fn bind_addr(addr: BindAddr, mode: Option<u32>) -> Result<Listener> {
+    log::info!("binding {addr}");
+    match addr {
+        BindAddr::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("binding {addr}"))?;
+            Ok(Listener::Tcp(listener))
+        }
+        BindAddr::Unix(path) => {
+            remove_stale_socket(&path)?;
+            let listener = UnixListener::bind(&path)
+                .with_context(|| format!("binding {path:?}"))?;
+            if let Some(mode) = mode {
+                std::fs::set_permissions(
+                    &path,
+                    PermissionsExt::from_mode(mode),
+                )?;
+            }
+            Ok(Listener::Unix(listener))
+        }
+    }
+}
+
+/// Serve tiles on all listeners.
+///
+/// Returns the first error from any listener, without waiting for the
+/// others (which are aborted).
+async fn serve_listeners(listeners: Vec<Listener>, app: Router) -> Result<()> {
+    let mut tasks = JoinSet::new();
+    for listener in listeners {
+        let app = app.clone();
+        tasks.spawn(async move {
+            match listener {
+                Listener::Tcp(listener) => axum::serve(listener, app).await,
+                Listener::Unix(listener) => axum::serve(listener, app).await,
             }
-            app = app.merge(tile_mvt(wyrm));
-            let listener = TcpListener::bind(cfg.bind_address).await.unwrap();
-            axum::serve(listener, app).await.unwrap();
         });
-        Ok(())
     }
+    while let Some(res) = tasks.join_next().await {
+        res??;
+    }
+    Ok(())
 }
 
-/// Router for `indexl.html`
-fn index_html() -> Router {
-    async fn handler() -> impl IntoResponse {
-        (
-            [(header::CONTENT_TYPE, "text/html")],
-            include_str!("../res/index.html"),
-        )
+/// Remove a stale unix socket file
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow!("not a socket: {path:?}"));
+        }
+        log::debug!("removing stale socket: {path:?}");
+        std::fs::remove_file(path)?;
     }
-    Router::new()
-        .route("/", get(handler))
-        .route("/index.html", get(handler))
+    Ok(())
 }
 
-/// Router for `map.css`
-fn map_css() -> Router {
-    async fn handler() -> impl IntoResponse {
-        ([(header::CONTENT_TYPE, "text/css")], include_str!("../res/map.css"))
+impl DumpCommand {
+    /// Decode and dump an MVT tile
+    fn dump(&self) -> Result<()> {
+        let bytes = if self.fetch {
+            self.fetch_tile(WyrmCfg::load()?)?
+        } else {
+            match &self.tile[..] {
+                [path] => {
+                    read(path).with_context(|| format!("reading: {path:?}"))?
+                }
+                _ => return Err(anyhow!("expected tile file path")),
+            }
+        };
+        let mut summary = decode_summary(&bytes)?;
+        if let Some(filter) = &self.filter {
+            let layer = filter
+                .strip_prefix("layer=")
+                .ok_or_else(|| anyhow!("invalid filter: {filter}"))?;
+            summary.retain_layer(layer);
+        }
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else {
+            print!("{summary}");
+        }
+        Ok(())
+    }
+
+    /// Fetch a tile using the library
+    fn fetch_tile(&self, cfg: WyrmCfg) -> Result<Vec<u8>> {
+        let [group, z, x, y] = &self.tile[..] else {
+            return Err(anyhow!("expected group z x y"));
+        };
+        let wyrm = Wyrm::try_from(&cfg)?;
+        let grid = wyrm.tile_grid(group).unwrap_or_default();
+        let tid =
+            TileId::new(x.parse()?, y.parse()?, grid.tile_zoom(z.parse()?))?;
+        match wyrm.try_fetch_tile(group, tid)? {
+            TileFetch::Tile(tile) => Ok(tile),
+            TileFetch::Empty => Err(anyhow!("tile {tid} empty")),
+            TileFetch::UnknownGroup => Err(anyhow!("unknown group: {group}")),
+        }
     }
-    Router::new().route("/map.css", get(handler))
 }
 
-/// Router for `map.js`
-fn map_js() -> Router {
-    async fn handler() -> impl IntoResponse {
-        (
-            [(header::CONTENT_TYPE, "text/javascript")],
-            include_str!("../res/map.js"),
-        )
+impl SeedCommand {
+    /// Make tile sink
+    fn tile_sink(
+        &self,
+        cfg: &WyrmCfg,
+        grid: TileGrid,
+    ) -> Result<Box<dyn TileSink>> {
+        match &self.sink {
+            None => Ok(Box::new(
+                DirSink::new(self.dir.clone(), &self.group).with_grid(grid),
+            )),
+            #[cfg(feature = "s3")]
+            Some(url) => {
+                let endpoint = cfg
+                    .s3_endpoint
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("s3_endpoint not configured"))?;
+                let region = cfg.s3_region.as_deref().unwrap_or("us-east-1");
+                Ok(Box::new(
+                    S3Sink::new(url, &self.group, endpoint, region)?
+                        .with_grid(grid),
+                ))
+            }
+            #[cfg(not(feature = "s3"))]
+            Some(url) => {
+                let _ = cfg;
+                Err(anyhow!("sink {url} requires `s3` feature"))
+            }
+        }
     }
-    Router::new().route("/map.js", get(handler))
-}
-
-/// Get a tile `.mvt` as response
-fn tile_mvt(wyrm: Arc<Wyrm>) -> Router {
-    async fn handler(
-        AxumPath(params): AxumPath<TileParams>,
-        State(state): State<Arc<Wyrm>>,
-    ) -> impl IntoResponse {
-        log::debug!(
-            "req: {}/{}/{}/{}",
-            &params.group,
-            params.z,
-            params.x,
-            params.tail
-        );
-        let Ok(tid) = TileId::try_from(&params) else {
-            return (StatusCode::NOT_FOUND, "Not Found".into_response());
+
+    /// Seed tiles into a directory or object store
+    fn seed(&self, cfg: WyrmCfg) -> Result<()> {
+        let zoom = parse_zoom_range(&self.zoom)?;
+        let resume = match &self.resume_from {
+            Some(tid) => Some(parse_tid(tid)?),
+            None => None,
         };
-        let mut out = vec![];
-        match state.fetch_tile(&mut out, &params.group, tid) {
-            Ok(()) => (StatusCode::OK, out.into_response()),
-            Err(earthwyrm::Error::TileEmpty()) => {
-                (StatusCode::NOT_FOUND, "Not Found".into_response())
+        let wyrm = Wyrm::try_from(&cfg)?;
+        let grid = wyrm.tile_grid(&self.group).unwrap_or_default();
+        let mut sink = self.tile_sink(&cfg, grid)?;
+        let mut tiles = wyrm.tile_iter(&self.group, zoom)?.peekable();
+        let (mut n_tile, mut n_written) = (0, 0);
+        if let Some(resume) = resume {
+            n_tile = skip_to(&mut tiles, resume).with_context(|| {
+                format!("resume tile {resume} not in group {}", self.group)
+            })?;
+        }
+        for tid in tiles {
+            n_tile += 1;
+            if !sink.contains(tid) {
+                match wyrm.try_fetch_tile(&self.group, tid)? {
+                    TileFetch::Tile(tile) => {
+                        sink.put(tid, &tile)?;
+                        n_written += 1;
+                    }
+                    TileFetch::Empty => {
+                        if self.delete_empty {
+                            sink.delete(tid)?;
+                        }
+                    }
+                    TileFetch::UnknownGroup => {
+                        return Err(anyhow!("unknown group: {}", self.group));
+                    }
+                }
             }
-            Err(err) => {
-                log::warn!("fetch_tile: {err:?}");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Error".into_response(),
-                )
+            if n_tile % 1000 == 0 {
+                println!("{n_tile} tiles ({n_written} written), at {tid}");
             }
         }
+        println!("{n_tile} tiles ({n_written} written)");
+        Ok(())
     }
-    Router::new()
-        .route("/:group/:z/:x/:tail", get(handler))
-        .with_state(wyrm)
 }
 
-/// Tile route parameters
-#[derive(Deserialize)]
-struct TileParams {
-    group: String,
-    z: u32,
-    x: u32,
-    tail: String,
+/// Get sort key of a tile in seed order (by zoom, then row-major)
+fn seed_order(tid: TileId) -> (u32, u32, u32) {
+    (tid.z(), tid.y(), tid.x())
+}
+
+/// Skip tiles before a resume tile, in seed order.
+///
+/// Returns the number of skipped tiles.  The resume tile must be within
+/// the zoom range and extent, so that no tiles are skipped by mistake.
+fn skip_to<I>(tiles: &mut Peekable<I>, resume: TileId) -> Result<usize>
+where
+    I: Iterator<Item = TileId>,
+{
+    let mut n_skip = 0;
+    while tiles
+        .next_if(|tid| seed_order(*tid) < seed_order(resume))
+        .is_some()
+    {
+        n_skip += 1;
+    }
+    match tiles.peek() {
+        Some(tid) if *tid == resume => Ok(n_skip),
+        _ => Err(anyhow!("outside of zoom range or extent")),
+    }
 }
 
-impl TryFrom<&TileParams> for TileId {
-    type Error = mvt::Error;
+impl DiffCommand {
+    /// Compare tiles
+    fn diff(&self, cfg: WyrmCfg) -> Result<()> {
+        let zoom = parse_zoom_range(&self.zoom)?;
+        let wyrm_a = Wyrm::try_from(&cfg.with_loam_dir(self.a.clone()))?;
+        let wyrm_b = if self.tiles {
+            None
+        } else {
+            Some(Wyrm::try_from(
+                &WyrmCfg::load()?.with_loam_dir(self.b.clone()),
+            )?)
+        };
+        let mut tids: Vec<_> = wyrm_a
+            .tile_iter_bbox(&self.group, zoom.clone(), self.bbox)?
+            .collect();
+        if let Some(wyrm_b) = &wyrm_b {
+            tids.extend(wyrm_b.tile_iter_bbox(&self.group, zoom, self.bbox)?);
+        }
+        tids.sort_by_key(|tid| (tid.z(), tid.x(), tid.y()));
+        tids.dedup();
+        let grid = wyrm_a.tile_grid(&self.group).unwrap_or_default();
+        let sink = DirSink::new(self.b.clone(), &self.group).with_grid(grid);
+        let (mut n_tile, mut diffs) = (0, Vec::new());
+        for tid in tids {
+            n_tile += 1;
+            let a = self.fetch_tile(&wyrm_a, tid)?;
+            let b = match &wyrm_b {
+                Some(wyrm_b) => self.fetch_tile(wyrm_b, tid)?,
+                None => {
+                    let path = sink.path(tid);
+                    if path.exists() {
+                        read(&path)
+                            .with_context(|| format!("reading: {path:?}"))?
+                    } else {
+                        Vec::new()
+                    }
+                }
+            };
+            let diff = diff_tiles(&a, &b)?;
+            if !diff.is_empty() {
+                if !self.json {
+                    println!(
+                        "{tid}: {} -> {} bytes",
+                        diff.bytes_a, diff.bytes_b
+                    );
+                    print!("{diff}");
+                }
+                diffs.push((tid, diff));
+            }
+        }
+        if self.json {
+            let tiles: Vec<_> = diffs
+                .iter()
+                .map(|(tid, diff)| json!({ "tile": tid.to_string(), "diff": diff }))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&tiles)?);
+            return Ok(());
+        }
+        println!("{n_tile} tiles ({} changed)", diffs.len());
+        diffs.sort_by_key(|(_tid, diff)| std::cmp::Reverse(diff.n_changed()));
+        for (tid, diff) in diffs.iter().take(self.top) {
+            println!("  {tid}: {} features changed", diff.n_changed());
+        }
+        Ok(())
+    }
+
+    /// Fetch one tile (empty tiles have no bytes)
+    fn fetch_tile(&self, wyrm: &Wyrm, tid: TileId) -> Result<Vec<u8>> {
+        match wyrm.try_fetch_tile(&self.group, tid)? {
+            TileFetch::Tile(tile) => Ok(tile),
+            TileFetch::Empty => Ok(Vec::new()),
+            TileFetch::UnknownGroup => {
+                Err(anyhow!("unknown group: {}", self.group))
+            }
+        }
+    }
+}
 
-    fn try_from(params: &TileParams) -> Result<Self, Self::Error> {
-        if let Some(y) = params.tail.strip_suffix(".mvt") {
-            if let Ok(y) = y.parse::<u32>() {
-                return TileId::new(params.x, y, params.z);
+impl ConfigCommand {
+    /// Run config sub-command
+    fn run(&self) -> Result<()> {
+        match &self.cmd {
+            ConfigSubCommand::Check(cmd) => cmd.check(),
+            ConfigSubCommand::Schema(_) => {
+                let schema = WyrmCfg::schema();
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+                Ok(())
             }
         }
-        Err(mvt::Error::InvalidTid())
+    }
+}
+
+impl ConfigCheckCommand {
+    /// Check configuration file, exiting with status code
+    fn check(&self) -> Result<()> {
+        let report = match WyrmCfg::check_path(&self.file) {
+            Ok(report) => report,
+            Err(e) => {
+                if self.json {
+                    let report = ConfigReport {
+                        errors: vec![ConfigIssue {
+                            message: e.to_string(),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    };
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    eprintln!("{:?}: {e}", self.file);
+                }
+                std::process::exit(CONFIG_UNREADABLE);
+            }
+        };
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for error in &report.errors {
+                println!("error: {error}");
+            }
+            for warning in &report.warnings {
+                println!("warning: {warning}");
+            }
+            if report.valid {
+                println!("{:?}: valid", self.file);
+            }
+        }
+        if !report.valid {
+            std::process::exit(CONFIG_INVALID);
+        }
+        Ok(())
+    }
+}
+
+/// Parse a zoom range (ex. `10-14` or `12`)
+fn parse_zoom_range(zoom: &str) -> Result<RangeInclusive<u32>> {
+    match zoom.split_once('-') {
+        Some((a, b)) => Ok(a.parse()?..=b.parse()?),
+        None => {
+            let z = zoom.parse()?;
+            Ok(z..=z)
+        }
+    }
+}
+
+/// Parse a WGS84 bounding box (west,south,east,north)
+fn parse_bbox(bbox: &str) -> std::result::Result<BBox<f64>, String> {
+    let coords: Vec<f64> = bbox
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|e| format!("invalid bbox: {e}"))?;
+    let [west, south, east, north] = coords[..] else {
+        return Err(format!("invalid bbox: {bbox}"));
+    };
+    let sw = WebMercatorPos::from(Wgs84Pos::new(south, west));
+    let ne = WebMercatorPos::from(Wgs84Pos::new(north, east));
+    Ok(BBox::new([sw, ne]))
+}
+
+/// Parse a tile ID (z/x/y)
+fn parse_tid(tid: &str) -> Result<TileId> {
+    let mut parts = tid.split('/');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(z), Some(x), Some(y), None) => {
+            Ok(TileId::new(x.parse()?, y.parse()?, z.parse()?)?)
+        }
+        _ => Err(anyhow!("invalid tile ID: {tid}")),
     }
 }
 
@@ -268,18 +1215,273 @@ impl Args {
     /// Run selected command
     fn run(self) -> Result<()> {
         match &self.cmd {
-            Command::Init(cmd) => cmd.init(),
-            Command::Dig(cmd) => cmd.dig(WyrmCfg::load()?),
-            Command::Query(cmd) => cmd.query(WyrmCfg::load()?),
-            Command::Serve(cmd) => cmd.serve(WyrmCfg::load()?),
+            Command::Init(cmd) => {
+                init_logging(LogFormat::Log);
+                cmd.init()
+            }
+            Command::Dig(cmd) => cmd.dig(load_cfg()?),
+            Command::Query(cmd) => cmd.query(load_cfg()?),
+            Command::Serve(cmd) => cmd.serve(load_cfg()?),
+            Command::Dump(cmd) => {
+                init_logging(LogFormat::Log);
+                cmd.dump()
+            }
+            Command::Seed(cmd) => cmd.seed(load_cfg()?),
+            Command::Match(cmd) => cmd.scan(load_cfg()?),
+            Command::Validate(cmd) => cmd.validate(load_cfg()?),
+            Command::Export(cmd) => cmd.export(load_cfg()?),
+            Command::Diff(cmd) => cmd.diff(load_cfg()?),
+            Command::Config(cmd) => {
+                init_logging(LogFormat::Log);
+                cmd.run()
+            }
+            Command::Analyze(cmd) => cmd.analyze(load_cfg()?),
+            Command::Migrate(cmd) => cmd.migrate(load_cfg()?),
         }
     }
 }
 
+/// Load configuration and initialize logging
+fn load_cfg() -> Result<WyrmCfg> {
+    let cfg = WyrmCfg::load()?;
+    init_logging(cfg.log_format);
+    Ok(cfg)
+}
+
+/// Initialize logging.
+///
+/// Structured formats use `tracing` spans; `log` records are forwarded to
+/// the subscriber.  Filtering uses `RUST_LOG` in all formats.
+fn init_logging(format: LogFormat) {
+    use tracing_subscriber::EnvFilter;
+    match format {
+        LogFormat::Log => env_logger::builder().format_timestamp(None).init(),
+        LogFormat::Text => tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(EnvFilter::from_default_env())
+            .init(),
+    }
+}
+
 /// Main entry point
 fn main() -> Result<()> {
-    env_logger::builder().format_timestamp(None).init();
     let args: Args = argh::from_env();
     args.run()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_all_fails_fast() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let any = || BindAddr::Tcp("127.0.0.1:0".parse().unwrap());
+        let listeners = rt.block_on(bind_all(vec![any(), any()], None));
+        assert_eq!(listeners.unwrap().len(), 2);
+        // an address in use fails before serving on the others
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![any(), BindAddr::Tcp(taken.local_addr().unwrap())];
+        let err = rt.block_on(bind_all(addrs, None)).err().unwrap();
+        assert!(err.to_string().starts_with("binding 127.0.0.1:"));
+    }
+
+    #[test]
+    fn init_template_valid() {
+        let vars = [
+            ("bind", "127.0.0.1:3030"),
+            ("user", "earthwyrm"),
+            ("dir", "/var/local/earthwyrm"),
+        ];
+        let muon = fill_template(include_str!("../res/earthwyrm.muon"), &vars);
+        assert!(!muon.contains("{{"));
+        let mut cfg: WyrmCfg = muon_rs::from_str(&muon).unwrap();
+        cfg.validate().unwrap();
+        assert!(cfg.check().valid);
+        cfg.expand_layers().unwrap();
+        assert!(!cfg.layer_group.is_empty());
+        for group in &cfg.layer_group {
+            for layer in &group.layer {
+                LayerDef::try_from(layer).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn init_loads() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-init", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let init = InitCommand {
+            dir: dir.clone(),
+            force: false,
+            bind: "127.0.0.1:4040".into(),
+            user: "mapper".into(),
+        };
+        init.clone().init().unwrap();
+        let cfg = WyrmCfg::load_path(&dir.join("earthwyrm.muon")).unwrap();
+        assert_eq!(cfg.bind_address, ["127.0.0.1:4040"]);
+        assert_eq!(cfg.loam_dir(), dir.join("loam"));
+        let service =
+            std::fs::read_to_string(dir.join("earthwyrm.service")).unwrap();
+        assert!(service.contains("User=mapper\n"));
+        assert!(!service.contains("{{"));
+        // existing files are kept without `--force`
+        std::fs::write(dir.join("earthwyrm.muon"), "# edited\n").unwrap();
+        init.init().unwrap();
+        let muon = std::fs::read_to_string(dir.join("earthwyrm.muon")).unwrap();
+        assert_eq!(muon, "# edited\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Send a GET request, returning the (lowercase) head and body
+    fn http_get(addr: std::net::SocketAddr, path: &str) -> (String, Vec<u8>) {
+        use std::io::Read;
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        stream.write_all(b"Connection: close\r\n\r\n").unwrap();
+        let mut res = Vec::new();
+        stream.read_to_end(&mut res).unwrap();
+        let end = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&res[..end]).to_ascii_lowercase();
+        (head, res[end + 4..].to_vec())
+    }
+
+    #[test]
+    fn seed_then_serve() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-seed-serve", std::process::id()));
+        let loam = dir.join("loam");
+        let tiles = dir.join("tiles");
+        std::fs::create_dir_all(&loam).unwrap();
+        // water polygon within tile 4/3/5 (and 5/7/11)
+        let (x, y) = (-10_350_000.0, 5_615_000.0);
+        let ring = vec![(x, y), (x + 50.0, y), (x + 50.0, y + 50.0), (x, y)];
+        earthwyrm::GeomTree::write_loam(
+            loam.join("water.loam"),
+            mvt::GeomType::Polygon,
+            vec![(vec![Some("water".into()), None], vec![ring])],
+        )
+        .unwrap();
+        let muon = format!(
+            "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+            layer_group: base\n  static_tiles: {}\n  layer: water\n    \
+            geom_type: polygon\n    zoom: 4-12\n    \
+            tags: .natural=water ?name\n",
+            tiles.join("base").display()
+        );
+        let cfg = || {
+            let cfg: WyrmCfg = muon_rs::from_str(&muon).unwrap();
+            cfg.with_loam_dir(loam.clone())
+        };
+        let seed = SeedCommand {
+            group: "base".into(),
+            zoom: "4".into(),
+            dir: tiles.clone(),
+            sink: None,
+            delete_empty: false,
+            resume_from: None,
+        };
+        seed.seed(cfg()).unwrap();
+        let seeded = std::fs::read(tiles.join("base/4/3/5.mvt")).unwrap();
+        // empty tiles are not written
+        assert!(!tiles.join("base/4/3/6.mvt").exists());
+        assert!(!tiles.join("base/5").exists());
+
+        let wyrm = Arc::new(Wyrm::try_from(&cfg()).unwrap());
+        let app = earthwyrm_axum::earthwyrm_router(wyrm, RouterOpts::default());
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        rt.spawn(async move { axum::serve(listener, app).await });
+        // seeded tile is served from the static directory
+        let (head, body) = http_get(addr, "/base/4/3/5.mvt");
+        assert!(head.starts_with("http/1.1 200"), "{head}");
+        assert!(head.contains("cache-control: public, max-age=604800"));
+        assert_eq!(body, seeded);
+        // unseeded zoom falls back to live rendering
+        let (head, body) = http_get(addr, "/base/5/7/11.mvt");
+        assert!(head.starts_with("http/1.1 200"), "{head}");
+        assert!(head.contains("cache-control: public, max-age=300"));
+        assert!(!body.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Make tile IDs in seed order, at zoom 3-4 within columns and rows 2-4
+    fn seed_tiles() -> Vec<TileId> {
+        let mut tids = Vec::new();
+        for z in 3..=4 {
+            for y in 2..=4 {
+                for x in 2..=4 {
+                    tids.push(TileId::new(x, y, z).unwrap());
+                }
+            }
+        }
+        tids
+    }
+
+    #[test]
+    fn resume_from() {
+        let tids = seed_tiles();
+        let tid = |z, x, y| TileId::new(x, y, z).unwrap();
+        let mut tiles = tids.iter().copied().peekable();
+        assert_eq!(skip_to(&mut tiles, tid(3, 2, 2)).unwrap(), 0);
+        assert_eq!(tiles.count(), 18);
+        let mut tiles = tids.iter().copied().peekable();
+        assert_eq!(skip_to(&mut tiles, tid(4, 3, 2)).unwrap(), 10);
+        assert_eq!(tiles.next(), Some(tid(4, 3, 2)));
+        assert_eq!(tiles.next(), Some(tid(4, 4, 2)));
+        assert_eq!(tiles.next(), Some(tid(4, 2, 3)));
+        // outside of zoom range or extent
+        for resume in [tid(2, 2, 2), tid(5, 2, 2), tid(3, 5, 2), tid(4, 1, 4)] {
+            let mut tiles = tids.iter().copied().peekable();
+            assert!(skip_to(&mut tiles, resume).is_err(), "{resume}");
+        }
+        assert_eq!(parse_tid("4/3/2").unwrap(), tid(4, 3, 2));
+        assert!(parse_tid("4/3").is_err());
+        assert!(parse_tid("4/16/2").is_err());
+    }
+
+    #[test]
+    fn inherited_env() {
+        use std::env::{remove_var, set_var};
+        let pid = std::process::id();
+        // not socket activated: bind addresses are used
+        remove_var("LISTEN_FDS");
+        remove_var("LISTEN_PID");
+        assert!(inherited_listeners().unwrap().is_empty());
+        // activated for a different process
+        set_var("LISTEN_FDS", "1");
+        set_var("LISTEN_PID", (pid + 1).to_string());
+        assert!(inherited_listeners().unwrap().is_empty());
+        // invalid or zero counts
+        set_var("LISTEN_PID", pid.to_string());
+        for count in ["", "two", "-1", "0"] {
+            set_var("LISTEN_FDS", count);
+            assert!(inherited_listeners().unwrap().is_empty(), "{count}");
+        }
+        remove_var("LISTEN_FDS");
+        remove_var("LISTEN_PID");
+    }
+
+    #[test]
+    fn inherited_tcp_listener() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let inner = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = inner.local_addr().unwrap();
+        let listener = rt
+            .block_on(async { Listener::try_from(Inherited::Tcp(inner)) })
+            .unwrap();
+        let Listener::Tcp(listener) = listener else {
+            panic!("expected TCP listener");
+        };
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+}
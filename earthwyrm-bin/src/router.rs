@@ -0,0 +1,1222 @@
+// router.rs
+//
+// Copyright (c) 2021-2024  Minnesota Department of Transportation
+//
+use crate::http::{self, ResponseHeaders};
+use crate::live::{self, Live};
+use crate::stream;
+use anyhow::Result;
+use axum::{
+    extract::{OriginalUri, Path as AxumPath, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use earthwyrm::{
+    EmptyTile, HttpCfg, LayerDef, RequestCtx, TileFetch, TileGrid, TileId,
+    TilePath, Wyrm,
+};
+use mvt::GeomType;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Cache-Control for pre-rendered (static) tiles
+const STATIC_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// Cache-Control for live tiles
+const LIVE_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// Cache-Control for tiles truncated at their deadline
+const TRUNCATED_CACHE_CONTROL: &str = "no-store";
+
+/// Options for building tile routes
+#[derive(Clone, Default)]
+pub struct RouterOpts {
+    /// Include leaflet demo map (`/`, `map.css`, `map.js`, `style.json`)
+    leaflet: bool,
+
+    /// Include `/events` (server-sent reload events)
+    events: bool,
+
+    /// Exclude JSON routes (`groups.json`, tilejson, feature and batch)
+    no_json: bool,
+
+    /// Exclude `/readyz` (readiness check)
+    no_readyz: bool,
+
+    /// Static headers for tile responses
+    headers: ResponseHeaders,
+
+    /// Log each request
+    access_log: bool,
+
+    /// Public base URL for TileJSON
+    public_url: Option<Arc<str>>,
+}
+
+impl RouterOpts {
+    /// Include leaflet demo map routes
+    pub fn with_leaflet(mut self, leaflet: bool) -> Self {
+        self.leaflet = leaflet;
+        self
+    }
+
+    /// Include `/events` route, notifying clients of reloads.
+    ///
+    /// Only useful with [live_router], when loam files are watched.
+    pub fn with_events(mut self, events: bool) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Include JSON routes (default: `true`)
+    pub fn with_json(mut self, json: bool) -> Self {
+        self.no_json = !json;
+        self
+    }
+
+    /// Include `/readyz` route (default: `true`)
+    pub fn with_readyz(mut self, readyz: bool) -> Self {
+        self.no_readyz = !readyz;
+        self
+    }
+
+    /// Use headers, access log and public URL settings from `http`
+    /// configuration
+    pub fn with_http(mut self, cfg: &HttpCfg) -> Result<Self> {
+        self.headers = ResponseHeaders::try_from(cfg)?;
+        self.access_log = cfg.access_log;
+        self.public_url = cfg
+            .public_url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/').into());
+        Ok(self)
+    }
+}
+
+/// Build a router for tile routes.
+///
+/// The router can be nested within another application, such as
+/// `Router::new().nest("/maps", earthwyrm_router(wyrm, opts))`.
+pub fn earthwyrm_router(wyrm: Arc<Wyrm>, opts: RouterOpts) -> Router {
+    live_router(Arc::new(Live::from(wyrm)), opts)
+}
+
+/// Build a router for tile routes, with live (reloadable) layers
+pub fn live_router(live: Arc<Live>, opts: RouterOpts) -> Router {
+    let mut app = Router::new();
+    if opts.leaflet {
+        app = app
+            .merge(index_html())
+            .merge(map_css())
+            .merge(map_js())
+            .merge(style_json(Arc::clone(&live)));
+    }
+    if opts.events {
+        app = app.merge(live::events(Arc::clone(&live)));
+    }
+    if !opts.no_readyz {
+        app = app.merge(live::readyz(Arc::clone(&live)));
+    }
+    if !opts.no_json {
+        app = app.merge(groups_json(Arc::clone(&live), !opts.leaflet));
+        app = app.merge(tile_json(Arc::clone(&live), opts.public_url));
+        app = app.merge(feature_json(Arc::clone(&live)));
+        app = app.merge(batch_json(Arc::clone(&live)));
+    }
+    let mut tiles = tile_mvt(live);
+    if !opts.headers.is_empty() {
+        tiles = tiles.layer(middleware::from_fn_with_state(
+            opts.headers,
+            http::add_headers,
+        ));
+    }
+    app = app.merge(tiles);
+    if opts.access_log {
+        app = app.layer(middleware::from_fn(http::access_log));
+    }
+    app
+}
+
+/// Get path prefix of a nested router (empty if not nested)
+fn nest_prefix<'a>(original: &'a Uri, uri: &Uri) -> &'a str {
+    original.path().strip_suffix(uri.path()).unwrap_or_default()
+}
+
+/// Router for `indexl.html`
+fn index_html() -> Router {
+    async fn handler() -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "text/html")],
+            include_str!("../res/index.html"),
+        )
+    }
+    Router::new()
+        .route("/", get(handler))
+        .route("/index.html", get(handler))
+}
+
+/// Router for `map.css`
+fn map_css() -> Router {
+    async fn handler() -> impl IntoResponse {
+        ([(header::CONTENT_TYPE, "text/css")], include_str!("../res/map.css"))
+    }
+    Router::new().route("/map.css", get(handler))
+}
+
+/// Router for `map.js`
+fn map_js() -> Router {
+    async fn handler() -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "text/javascript")],
+            include_str!("../res/map.js"),
+        )
+    }
+    Router::new().route("/map.js", get(handler))
+}
+
+/// Router for `style.json` (leaflet demo map)
+fn style_json(live: Arc<Live>) -> Router {
+    async fn handler(State(live): State<Arc<Live>>) -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            style_value(&live.wyrm()).to_string(),
+        )
+    }
+    Router::new()
+        .route("/style.json", get(handler))
+        .with_state(live)
+}
+
+/// Build leaflet styles for all layers (first layer of each name).
+///
+/// Layers without a `style` get a default for their geometry type.
+fn style_value(wyrm: &Wyrm) -> Value {
+    let mut layers = serde_json::Map::new();
+    for layer in wyrm.groups().flat_map(|group| group.layers()) {
+        if layers.contains_key(layer.name()) {
+            continue;
+        }
+        let mut style = default_style(layer.geom_tp());
+        if let Some(cfg) = layer.style() {
+            let opts = [
+                ("color", cfg.color.as_ref().map(|c| json!(c))),
+                ("opacity", cfg.opacity.map(|o| json!(o))),
+                ("weight", cfg.weight.map(|w| json!(w))),
+                ("fillColor", cfg.fill.as_ref().map(|c| json!(c))),
+                ("fillOpacity", cfg.fill_opacity.map(|o| json!(o))),
+            ];
+            for (key, val) in opts {
+                if let Some(val) = val {
+                    style[key] = val;
+                }
+            }
+        }
+        let minzoom = layer
+            .style()
+            .and_then(|cfg| cfg.minzoom)
+            .unwrap_or(layer.zoom_min());
+        style["minzoom"] = json!(minzoom);
+        layers.insert(layer.name().to_string(), style);
+    }
+    json!({
+        "default": default_style(GeomType::Polygon),
+        "layers": layers,
+    })
+}
+
+/// Get default leaflet style for a geometry type
+fn default_style(geom_tp: GeomType) -> Value {
+    match geom_tp {
+        GeomType::Point => json!({
+            "radius": 4,
+            "weight": 1,
+            "color": "#666",
+            "fill": true,
+            "fillColor": "#999",
+            "fillOpacity": 0.6,
+        }),
+        GeomType::Linestring => json!({
+            "weight": 1,
+            "color": "#888",
+            "opacity": 0.8,
+        }),
+        GeomType::Polygon => json!({
+            "weight": 0.5,
+            "color": "#888",
+            "opacity": 0.6,
+            "fill": true,
+            "fillColor": "#ccc",
+            "fillOpacity": 0.4,
+        }),
+    }
+}
+
+/// Router for `groups.json` index
+fn groups_json(live: Arc<Live>, root: bool) -> Router {
+    async fn handler(State(live): State<Arc<Live>>) -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            groups_index(&live.wyrm()).to_string(),
+        )
+    }
+    let mut router = Router::new().route("/groups.json", get(handler));
+    if root {
+        router = router.route("/", get(handler));
+    }
+    router.with_state(live)
+}
+
+/// Build JSON index of layer groups
+fn groups_index(wyrm: &Wyrm) -> Value {
+    let groups: Vec<Value> = wyrm
+        .groups()
+        .map(|group| {
+            let layers: Vec<Value> = group
+                .layers()
+                .map(|layer| {
+                    json!({
+                        "name": layer.name(),
+                        "order": layer.order(),
+                        "geom_type": geom_type_name(layer.geom_tp()),
+                        "zoom_min": layer.zoom_min(),
+                        "zoom_max": layer.zoom_max(),
+                        "tags": layer.tags().collect::<Vec<_>>(),
+                        "attribution": layer.attribution(),
+                        "license": layer.license(),
+                    })
+                })
+                .collect();
+            let zoom = group.zoom_range();
+            json!({
+                "name": group.name(),
+                "version": group.version(),
+                "zoom_min": zoom.map(|(zmin, _zmax)| zmin),
+                "zoom_max": zoom.map(|(_zmin, zmax)| zmax),
+                "attribution": group.attribution(),
+                "license": group.license(),
+                "layers": layers,
+            })
+        })
+        .collect();
+    let data = wyrm.manifest().map(|manifest| {
+        json!({
+            "status": manifest.current_as_of(),
+            "manifest": manifest,
+        })
+    });
+    json!({ "groups": groups, "data": data })
+}
+
+/// Router for `{group}/tilejson.json`
+///
+/// * `public_url` Base URL for tiles; if `None`, `http://` with the `Host`
+///                header and nested prefix is used.
+fn tile_json(live: Arc<Live>, public_url: Option<Arc<str>>) -> Router {
+    async fn handler(
+        AxumPath(group): AxumPath<String>,
+        State((live, public_url)): State<(Arc<Live>, Option<Arc<str>>)>,
+        OriginalUri(original): OriginalUri,
+        uri: Uri,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let base = match public_url {
+            Some(url) => url.to_string(),
+            None => {
+                let host = headers
+                    .get(header::HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("127.0.0.1:3030");
+                format!("http://{host}{}", nest_prefix(&original, &uri))
+            }
+        };
+        match tile_json_value(&live.wyrm(), &group, &base) {
+            Some(tj) => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/json")],
+                tj.to_string(),
+            ),
+            None => (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "text/plain")],
+                "Not Found".to_string(),
+            ),
+        }
+    }
+    Router::new()
+        .route("/{group}/tilejson.json", get(handler))
+        .with_state((live, public_url))
+}
+
+/// Router for `feature/{layer}/{id}` lookups
+fn feature_json(live: Arc<Live>) -> Router {
+    async fn handler(
+        AxumPath((layer, id)): AxumPath<(String, String)>,
+        State(live): State<Arc<Live>>,
+    ) -> impl IntoResponse {
+        let Ok(osm_id) = id.parse::<i64>() else {
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        };
+        let wyrm = live.wyrm();
+        let res = wyrm
+            .run_blocking(move |wyrm| wyrm.find_feature(&layer, osm_id))
+            .await;
+        match res {
+            Ok(Ok(Some(info))) => {
+                let collection = json!({
+                    "type": "FeatureCollection",
+                    "features": info.features,
+                });
+                (
+                    StatusCode::OK,
+                    (
+                        [(header::CONTENT_TYPE, "application/geo+json")],
+                        collection.to_string(),
+                    )
+                        .into_response(),
+                )
+            }
+            Ok(Ok(None)) | Ok(Err(earthwyrm::Error::UnknownLayerName(_))) => {
+                (StatusCode::NOT_FOUND, "Not Found".into_response())
+            }
+            Ok(Err(err @ earthwyrm::Error::NoIdIndex(_))) => {
+                (StatusCode::NOT_FOUND, err.to_string().into_response())
+            }
+            Ok(Err(err)) => {
+                log::warn!("feature lookup: {err:?}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".into_response(),
+                )
+            }
+            Err(err) => {
+                log::warn!("feature lookup task: {err:?}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".into_response(),
+                )
+            }
+        }
+    }
+    Router::new()
+        .route("/feature/{layer}/{id}", get(handler))
+        .with_state(live)
+}
+
+/// Batch query parameters
+#[derive(Deserialize)]
+struct BatchQuery {
+    /// Search radius (meters)
+    #[serde(default = "default_batch_radius")]
+    radius: f64,
+}
+
+/// Default batch query radius (meters)
+fn default_batch_radius() -> f64 {
+    10.0
+}
+
+/// Router for `batch/{layer}` point queries
+fn batch_json(live: Arc<Live>) -> Router {
+    async fn handler(
+        AxumPath(layer): AxumPath<String>,
+        Query(query): Query<BatchQuery>,
+        State(live): State<Arc<Live>>,
+        Json(points): Json<Vec<(f64, f64)>>,
+    ) -> impl IntoResponse {
+        let wyrm = live.wyrm();
+        let radius_ok = query.radius.is_finite() && query.radius >= 0.0;
+        if points.len() > wyrm.max_batch_points() || !radius_ok {
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        }
+        let res = wyrm
+            .run_blocking(move |wyrm| {
+                wyrm.query_batch(&layer, &points, query.radius)
+            })
+            .await;
+        match res {
+            Ok(Ok(matches)) => {
+                let matches: Vec<Vec<Value>> = matches
+                    .into_iter()
+                    .map(|infos| {
+                        infos.into_iter().flat_map(|i| i.features).collect()
+                    })
+                    .collect();
+                (
+                    StatusCode::OK,
+                    (
+                        [(header::CONTENT_TYPE, "application/json")],
+                        json!(matches).to_string(),
+                    )
+                        .into_response(),
+                )
+            }
+            Ok(Err(earthwyrm::Error::UnknownLayerName(_))) => {
+                (StatusCode::NOT_FOUND, "Not Found".into_response())
+            }
+            Ok(Err(err)) => {
+                log::warn!("batch query: {err:?}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".into_response(),
+                )
+            }
+            Err(err) => {
+                log::warn!("batch query task: {err:?}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".into_response(),
+                )
+            }
+        }
+    }
+    Router::new()
+        .route("/batch/{layer}", post(handler))
+        .with_state(live)
+}
+
+/// Build TileJSON for a layer group.
+///
+/// * `base` Base URL, with prefix path if nested.
+fn tile_json_value(wyrm: &Wyrm, name: &str, base: &str) -> Option<Value> {
+    let group = wyrm.groups().find(|g| g.name() == name)?;
+    let tiles = tiles_url(base, group.version(), name);
+    let (minzoom, maxzoom) = group.zoom_range().unwrap_or((0, 0));
+    let vector_layers: Vec<Value> = group
+        .layers()
+        .map(|layer| {
+            json!({
+                "id": layer.name(),
+                "fields": layer_fields(layer),
+                "minzoom": layer.zoom_min(),
+                "maxzoom": layer.zoom_max(),
+                "attribution": layer.attribution(),
+                "license": layer.license(),
+            })
+        })
+        .collect();
+    let attributions = group.attributions();
+    let attribution =
+        (!attributions.is_empty()).then(|| attributions.join(", "));
+    Some(json!({
+        "tilejson": "3.0.0",
+        "name": name,
+        "tiles": [tiles],
+        "minzoom": minzoom,
+        "maxzoom": maxzoom,
+        "attribution": attribution,
+        "license": group.license(),
+        "vector_layers": vector_layers,
+    }))
+}
+
+/// Make tile URL template for a layer group
+fn tiles_url(base: &str, version: Option<&str>, name: &str) -> String {
+    match version {
+        Some(version) => {
+            format!("{base}/{version}/{name}/{{z}}/{{x}}/{{y}}.mvt")
+        }
+        None => format!("{base}/{name}/{{z}}/{{x}}/{{y}}.mvt"),
+    }
+}
+
+/// Make TileJSON fields for a layer (tag name to type)
+fn layer_fields(layer: &LayerDef) -> serde_json::Map<String, Value> {
+    layer
+        .tags()
+        .map(|tag| {
+            let tp = match layer.sint_tag_index(tag) {
+                Some(_) => "Number",
+                None => "String",
+            };
+            (tag.to_string(), json!(tp))
+        })
+        .chain(
+            layer
+                .synthetic_tags()
+                .map(|tag| (tag.to_string(), json!("Number"))),
+        )
+        .chain(
+            layer
+                .emit_minzoom()
+                .then(|| ("minzoom".to_string(), json!("Number"))),
+        )
+        .collect()
+}
+
+/// Get geometry type name
+fn geom_type_name(geom_tp: GeomType) -> &'static str {
+    match geom_tp {
+        GeomType::Point => "point",
+        GeomType::Linestring => "linestring",
+        GeomType::Polygon => "polygon",
+    }
+}
+
+/// Get a tile `.mvt` as response
+fn tile_mvt(live: Arc<Live>) -> Router {
+    async fn handler(
+        AxumPath(params): AxumPath<TileParams>,
+        Query(mut query): Query<TileQuery>,
+        State(live): State<Arc<Live>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let wyrm = live.wyrm();
+        let req_id = request_id(&headers);
+        query.deadline = tile_deadline(&wyrm, &params.group, &headers);
+        tile_response(wyrm, params, query, req_id, fetch_response).await
+    }
+    async fn composite_handler(
+        AxumPath(params): AxumPath<TileParams>,
+        Query(mut query): Query<TileQuery>,
+        State(live): State<Arc<Live>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let wyrm = live.wyrm();
+        let req_id = request_id(&headers);
+        query.deadline = tile_deadline(&wyrm, &params.group, &headers);
+        tile_response(wyrm, params, query, req_id, fetch_composite).await
+    }
+    async fn versioned_handler(
+        AxumPath(params): AxumPath<VersionedTileParams>,
+        Query(mut query): Query<TileQuery>,
+        State(live): State<Arc<Live>>,
+        OriginalUri(original): OriginalUri,
+        uri: Uri,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let (version, params) = params.split();
+        let wyrm = live.wyrm();
+        if wyrm.tile_grid(&params.group).is_none() {
+            return (StatusCode::NOT_FOUND, "Not Found".into_response());
+        }
+        let current = wyrm.group_version(&params.group).map(str::to_string);
+        if current.as_deref() == Some(&version[..]) {
+            let req_id = request_id(&headers);
+            query.deadline = tile_deadline(&wyrm, &params.group, &headers);
+            return tile_response(wyrm, params, query, req_id, fetch_response)
+                .await;
+        }
+        // stale version, or group without one: redirect to current URL
+        let url = tile_url(
+            &nest_prefix(&original, &uri),
+            current.as_deref(),
+            &params,
+        );
+        log::debug!("stale version {version}, redirect: {url}");
+        (
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, url)].into_response(),
+        )
+    }
+    async fn quadkey_handler(
+        AxumPath(params): AxumPath<QuadkeyParams>,
+        Query(mut query): Query<TileQuery>,
+        State(live): State<Arc<Live>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let wyrm = live.wyrm();
+        // quadkeys address the Web Mercator grid only
+        if wyrm.tile_grid(&params.group) == Some(TileGrid::Wgs84) {
+            log::debug!("{}/q/{}: not Web Mercator", params.group, params.tail);
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        }
+        let Some(params) = params.tile_params() else {
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        };
+        let req_id = request_id(&headers);
+        query.deadline = tile_deadline(&wyrm, &params.group, &headers);
+        tile_response(wyrm, params, query, req_id, fetch_response).await
+    }
+    Router::new()
+        .route("/{group}/{z}/{x}/{tail}", get(handler))
+        .route("/{group}/q/{tail}", get(quadkey_handler))
+        .route("/composite/{group}/{z}/{x}/{tail}", get(composite_handler))
+        .route("/{version}/{group}/{z}/{x}/{tail}", get(versioned_handler))
+        .with_state(live)
+}
+
+/// Make a tile URL path, with an optional version segment
+fn tile_url(
+    prefix: &str,
+    version: Option<&str>,
+    params: &TileParams,
+) -> String {
+    let TileParams { group, z, x, tail } = params;
+    match version {
+        Some(version) => format!("{prefix}/{version}/{group}/{z}/{x}/{tail}"),
+        None => format!("{prefix}/{group}/{z}/{x}/{tail}"),
+    }
+}
+
+/// Get request ID from `X-Request-Id` header
+fn request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Get tile deadline from group time budgets and `X-Tile-Deadline` header.
+///
+/// The header (ms) can only shorten the budget; for composite groups
+/// (`+`-separated), the shortest budget is used.
+fn tile_deadline(
+    wyrm: &Wyrm,
+    group: &str,
+    headers: &HeaderMap,
+) -> Option<Instant> {
+    let budget = group.split('+').filter_map(|g| wyrm.tile_deadline(g)).min();
+    let requested = headers
+        .get("x-tile-deadline")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis);
+    let budget = match (budget, requested) {
+        (Some(b), Some(r)) => Some(b.min(r)),
+        (b, r) => b.or(r),
+    };
+    budget.map(|b| Instant::now() + b)
+}
+
+/// Blocking tile fetch function
+type FetchFn =
+    fn(&Arc<Wyrm>, &TileParams, &TileQuery) -> (StatusCode, Response);
+
+/// Make response for a tile request
+///
+/// Tile fetching reads file-backed trees, so it runs on a blocking thread
+/// to avoid stalling the async runtime.
+async fn tile_response(
+    wyrm: Arc<Wyrm>,
+    params: TileParams,
+    query: TileQuery,
+    req_id: Option<String>,
+    fetch: FetchFn,
+) -> (StatusCode, Response) {
+    let span =
+        tracing::info_span!("request", request_id = tracing::field::Empty,);
+    if let Some(req_id) = &req_id {
+        span.record("request_id", req_id.as_str());
+    }
+    let res = wyrm
+        .run_blocking(move |wyrm| {
+            span.in_scope(|| fetch(&wyrm, &params, &query))
+        })
+        .await;
+    match res {
+        Ok(res) => res,
+        Err(err) => {
+            log::warn!("fetch_tile task: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Error".into_response(),
+            )
+        }
+    }
+}
+
+/// Fetch a tile and make response (blocking).
+///
+/// Tiles estimated to reach the stream threshold are streamed; others are
+/// buffered, so that their cache headers reflect truncation.
+fn fetch_response(
+    wyrm: &Arc<Wyrm>,
+    params: &TileParams,
+    query: &TileQuery,
+) -> (StatusCode, Response) {
+    log::debug!(
+        "req: {}/{}/{}/{}",
+        &params.group,
+        params.z,
+        params.x,
+        params.tail
+    );
+    if params.tail.ends_with(".json") {
+        return fetch_geojson(wyrm, params, query);
+    }
+    let grid = wyrm.tile_grid(&params.group).unwrap_or_default();
+    let (tid, scale) = match TileId::parse_path_scaled(
+        &params.group,
+        grid.tile_zoom(params.z),
+        params.x,
+        &params.tail,
+    ) {
+        Ok(res) => res,
+        Err(err) => {
+            log::debug!("{err}");
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        }
+    };
+    // static tiles are only valid for unscaled, default requests
+    if scale == 1 && query.lang.is_none() {
+        match wyrm.static_tile(&params.group, tid) {
+            Ok(Some(tile)) => {
+                let headers = [(header::CACHE_CONTROL, STATIC_CACHE_CONTROL)];
+                return (StatusCode::OK, (headers, tile).into_response());
+            }
+            Ok(None) => (),
+            Err(earthwyrm::Error::UnknownGroupName()) => {
+                return (StatusCode::NOT_FOUND, "Not Found".into_response());
+            }
+            Err(err) => log::warn!("static_tile: {err:?}"),
+        }
+    }
+    let streamed = wyrm
+        .estimate_tile_size(&params.group, tid)
+        .is_some_and(|size| size >= wyrm.stream_threshold());
+    if streamed {
+        return tile_stream_response(wyrm, params, query, tid, scale);
+    }
+    let ctx = RequestCtx {
+        lang: query.lang.as_deref(),
+        scale: Some(scale),
+        deadline: query.deadline,
+        ..Default::default()
+    };
+    let mut tile = Vec::new();
+    match wyrm.write_tile_stream(&mut tile, &params.group, tid, &ctx) {
+        Ok(TileFetch::Tile(written)) => {
+            let cache_control = if written.truncated() {
+                TRUNCATED_CACHE_CONTROL
+            } else {
+                LIVE_CACHE_CONTROL
+            };
+            let headers = [(header::CACHE_CONTROL, cache_control)];
+            (StatusCode::OK, (headers, tile).into_response())
+        }
+        Ok(TileFetch::Empty) => empty_response(wyrm, params),
+        Ok(TileFetch::UnknownGroup) => {
+            (StatusCode::NOT_FOUND, "Not Found".into_response())
+        }
+        Err(err) => fetch_error(err),
+    }
+}
+
+/// Make response for a tile fetch error
+fn fetch_error(err: earthwyrm::Error) -> (StatusCode, Response) {
+    match err {
+        earthwyrm::Error::TileOutOfRange(_) => {
+            log::debug!("{err}");
+            (StatusCode::BAD_REQUEST, err.to_string().into_response())
+        }
+        _ => {
+            log::warn!("fetch_tile: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Error".into_response(),
+            )
+        }
+    }
+}
+
+/// Fetch a GeoJSON tile and make response (blocking).
+///
+/// Only served when `geojson_tiles` is enabled in the `http` options.
+fn fetch_geojson(
+    wyrm: &Wyrm,
+    params: &TileParams,
+    query: &TileQuery,
+) -> (StatusCode, Response) {
+    if !wyrm.geojson_tiles() {
+        return (StatusCode::NOT_FOUND, "Not Found".into_response());
+    }
+    let grid = wyrm.tile_grid(&params.group).unwrap_or_default();
+    let tid = match TileId::parse_path_geojson(
+        &params.group,
+        grid.tile_zoom(params.z),
+        params.x,
+        &params.tail,
+    ) {
+        Ok(tid) => tid,
+        Err(err) => {
+            log::debug!("{err}");
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        }
+    };
+    let ctx = RequestCtx {
+        lang: query.lang.as_deref(),
+        deadline: query.deadline,
+        ..Default::default()
+    };
+    match wyrm.fetch_tile_geojson(&params.group, tid, &ctx) {
+        Ok(TileFetch::Tile(json)) => {
+            let headers = [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::CACHE_CONTROL, LIVE_CACHE_CONTROL),
+            ];
+            (StatusCode::OK, (headers, json).into_response())
+        }
+        // blank tiles are MVT, so GeoJSON tiles have no content instead
+        Ok(TileFetch::Empty) => match wyrm.empty_tile() {
+            EmptyTile::NotFound => {
+                (StatusCode::NOT_FOUND, "Not Found".into_response())
+            }
+            _ => (StatusCode::NO_CONTENT, ().into_response()),
+        },
+        Ok(TileFetch::UnknownGroup) => {
+            (StatusCode::NOT_FOUND, "Not Found".into_response())
+        }
+        Err(err @ earthwyrm::Error::TileOutOfRange(_)) => {
+            log::debug!("{err}");
+            (StatusCode::BAD_REQUEST, err.to_string().into_response())
+        }
+        Err(err) => {
+            log::warn!("fetch_tile_geojson: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Error".into_response(),
+            )
+        }
+    }
+}
+
+/// Fetch a composite tile and make response (blocking).
+///
+/// The `group` parameter is a `+`-separated list of group names.
+fn fetch_composite(
+    wyrm: &Arc<Wyrm>,
+    params: &TileParams,
+    query: &TileQuery,
+) -> (StatusCode, Response) {
+    log::debug!(
+        "req: composite/{}/{}/{}/{}",
+        &params.group,
+        params.z,
+        params.x,
+        params.tail
+    );
+    let groups: Vec<&str> = params.group.split('+').collect();
+    let grid = wyrm.tile_grid(groups[0]).unwrap_or_default();
+    let (tid, scale) = match TileId::parse_path_scaled(
+        &params.group,
+        grid.tile_zoom(params.z),
+        params.x,
+        &params.tail,
+    ) {
+        Ok(res) => res,
+        Err(err) => {
+            log::debug!("{err}");
+            return (StatusCode::BAD_REQUEST, "Bad Request".into_response());
+        }
+    };
+    let ctx = RequestCtx {
+        lang: query.lang.as_deref(),
+        scale: Some(scale),
+        deadline: query.deadline,
+        ..Default::default()
+    };
+    match wyrm.try_fetch_tile_multi_ctx(&groups, tid, &ctx) {
+        Ok(TileFetch::Tile(tile)) => {
+            let headers = [(header::CACHE_CONTROL, LIVE_CACHE_CONTROL)];
+            let mut resp = (headers, tile).into_response();
+            if let Some(etag) = composite_etag(wyrm, &groups) {
+                resp.headers_mut().insert(header::ETAG, etag);
+            }
+            (StatusCode::OK, resp)
+        }
+        // blank tiles are per group, so composites have no content instead
+        Ok(TileFetch::Empty) => match wyrm.empty_tile() {
+            EmptyTile::NotFound => {
+                (StatusCode::NOT_FOUND, "Not Found".into_response())
+            }
+            _ => (StatusCode::NO_CONTENT, ().into_response()),
+        },
+        Ok(TileFetch::UnknownGroup) => {
+            (StatusCode::NOT_FOUND, "Not Found".into_response())
+        }
+        Err(
+            err @ (earthwyrm::Error::DuplicateLayerName(_)
+            | earthwyrm::Error::TileOutOfRange(_)
+            | earthwyrm::Error::GridMismatch(_)),
+        ) => {
+            log::debug!("{err}");
+            (StatusCode::BAD_REQUEST, err.to_string().into_response())
+        }
+        Err(err) => {
+            log::warn!("fetch_tile_multi: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Error".into_response(),
+            )
+        }
+    }
+}
+
+/// Make ETag for a composite tile, from versions of all member groups
+fn composite_etag(wyrm: &Wyrm, groups: &[&str]) -> Option<HeaderValue> {
+    let versions = groups
+        .iter()
+        .map(|g| wyrm.group_version(g))
+        .collect::<Option<Vec<_>>>()?;
+    HeaderValue::from_str(&format!("\"{}\"", versions.join("+"))).ok()
+}
+
+/// Make response for a tile, streaming each layer as it is encoded
+/// (blocking).
+///
+/// Headers are sent with the first layer, before a deadline could cut off
+/// later ones, so streamed tiles with a deadline are never cached.
+fn tile_stream_response(
+    wyrm: &Arc<Wyrm>,
+    params: &TileParams,
+    query: &TileQuery,
+    tid: TileId,
+    scale: u32,
+) -> (StatusCode, Response) {
+    log::debug!("streaming tile {}/{tid}", &params.group);
+    let fetcher = Arc::clone(wyrm);
+    let group = params.group.clone();
+    let lang = query.lang.clone();
+    let deadline = query.deadline;
+    let (begin, body) = stream::tile_body(move |mut out| {
+        let ctx = RequestCtx {
+            lang: lang.as_deref(),
+            scale: Some(scale),
+            deadline,
+            ..Default::default()
+        };
+        fetcher.write_tile_stream(&mut out, &group, tid, &ctx)
+    });
+    match begin {
+        Ok(TileFetch::Tile(())) => {
+            let cache_control = if deadline.is_some() {
+                TRUNCATED_CACHE_CONTROL
+            } else {
+                LIVE_CACHE_CONTROL
+            };
+            let headers = [(header::CACHE_CONTROL, cache_control)];
+            (StatusCode::OK, (headers, body).into_response())
+        }
+        Ok(TileFetch::Empty) => empty_response(wyrm, params),
+        Ok(TileFetch::UnknownGroup) => {
+            (StatusCode::NOT_FOUND, "Not Found".into_response())
+        }
+        Err(err) => fetch_error(err),
+    }
+}
+
+/// Make response for an empty tile
+fn empty_response(wyrm: &Wyrm, params: &TileParams) -> (StatusCode, Response) {
+    match wyrm.empty_tile() {
+        EmptyTile::NotFound => {
+            (StatusCode::NOT_FOUND, "Not Found".into_response())
+        }
+        EmptyTile::NoContent => (StatusCode::NO_CONTENT, ().into_response()),
+        EmptyTile::Blank => match wyrm.empty_tile_bytes(&params.group) {
+            Ok(blank) => (StatusCode::OK, blank.to_vec().into_response()),
+            Err(_) => (StatusCode::NOT_FOUND, "Not Found".into_response()),
+        },
+    }
+}
+
+/// Tile route parameters
+#[derive(Deserialize)]
+struct TileParams {
+    group: String,
+    z: u32,
+    x: u32,
+    tail: String,
+}
+
+/// Quadkey tile route parameters
+#[derive(Deserialize)]
+struct QuadkeyParams {
+    group: String,
+    tail: String,
+}
+
+impl QuadkeyParams {
+    /// Convert to tile parameters (`<quadkey>.mvt`)
+    fn tile_params(self) -> Option<TileParams> {
+        let Some(quadkey) = self.tail.strip_suffix(".mvt") else {
+            log::debug!("{}/q/{}: extension", self.group, self.tail);
+            return None;
+        };
+        let tid = match TileId::from_quadkey(quadkey) {
+            Ok(tid) => tid,
+            Err(err) => {
+                log::debug!("{err}");
+                return None;
+            }
+        };
+        Some(TileParams {
+            group: self.group,
+            z: tid.z(),
+            x: tid.x(),
+            tail: format!("{}.mvt", tid.y()),
+        })
+    }
+}
+
+/// Tile query parameters
+#[derive(Deserialize)]
+struct TileQuery {
+    /// Preferred name language
+    lang: Option<String>,
+
+    /// Deadline for making the tile (from headers)
+    #[serde(skip)]
+    deadline: Option<Instant>,
+}
+
+/// Versioned tile route parameters
+#[derive(Deserialize)]
+struct VersionedTileParams {
+    version: String,
+    group: String,
+    z: u32,
+    x: u32,
+    tail: String,
+}
+
+impl VersionedTileParams {
+    /// Split into version and tile parameters
+    fn split(self) -> (String, TileParams) {
+        let params = TileParams {
+            group: self.group,
+            z: self.z,
+            x: self.x,
+            tail: self.tail,
+        };
+        (self.version, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use earthwyrm::{GeomTree, LayerCfg, WyrmCfg};
+
+    /// Make a wyrm with `tile` and `base` groups, in a temp loam directory
+    fn test_wyrm(name: &str) -> Wyrm {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+            layer_group: tile\n  url_version: v2\n  layer: pois\n    \
+            geom_type: point\n    zoom: 10+\n    tags: ?name\n\
+            layer_group: base\n  layer: water\n    \
+            geom_type: polygon\n    zoom: 4-12\n    \
+            tags: .natural=water ?name\n";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let cfg = cfg.with_loam_dir(dir.clone());
+        let pt = (-10_350_000.0, 5_615_000.0);
+        GeomTree::write_loam(
+            dir.join("pois.loam"),
+            GeomType::Point,
+            vec![(vec![Some("Cafe".into())], vec![vec![pt]])],
+        )
+        .unwrap();
+        let (x, y) = pt;
+        let ring = vec![(x, y), (x + 50.0, y), (x + 50.0, y + 50.0), (x, y)];
+        GeomTree::write_loam(
+            dir.join("water.loam"),
+            GeomType::Polygon,
+            vec![(vec![Some("water".into()), None], vec![ring])],
+        )
+        .unwrap();
+        let wyrm = Wyrm::try_from(&cfg).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        wyrm
+    }
+
+    #[test]
+    fn groups_index_json() {
+        let wyrm = test_wyrm("groups-index");
+        let index = groups_index(&wyrm);
+        let expected = json!([
+            {
+                "name": "tile",
+                "version": "v2",
+                "zoom_min": 10,
+                "zoom_max": 30,
+                "attribution": null,
+                "license": null,
+                "layers": [{
+                    "name": "pois",
+                    "order": 0,
+                    "geom_type": "point",
+                    "zoom_min": 10,
+                    "zoom_max": 30,
+                    "tags": ["name"],
+                    "attribution": null,
+                    "license": null,
+                }],
+            },
+            {
+                "name": "base",
+                "version": null,
+                "zoom_min": 4,
+                "zoom_max": 12,
+                "attribution": null,
+                "license": null,
+                "layers": [{
+                    "name": "water",
+                    "order": 0,
+                    "geom_type": "polygon",
+                    "zoom_min": 4,
+                    "zoom_max": 12,
+                    "tags": ["natural", "name"],
+                    "attribution": null,
+                    "license": null,
+                }],
+            },
+        ]);
+        assert_eq!(index["groups"], expected);
+        // no dig manifest
+        assert_eq!(index["data"], Value::Null);
+    }
+
+    #[test]
+    fn tile_json_fields() {
+        let muon = "name: roads\ngeom_type: linestring\nzoom: 10+\n\
+            tags: highway=primary\ntags: ?name\ntags: $lanes\ntags: $angle\n";
+        let cfg: LayerCfg = muon_rs::from_str(muon).unwrap();
+        let layer = LayerDef::try_from(&cfg).unwrap();
+        let fields = layer_fields(&layer);
+        assert_eq!(fields["name"], "String");
+        assert_eq!(fields["lanes"], "Number");
+        assert_eq!(fields["angle"], "Number");
+        assert!(!fields.contains_key("highway"));
+    }
+
+    #[test]
+    fn tile_json_public_url() {
+        let opts = RouterOpts::default()
+            .with_http(&HttpCfg {
+                public_url: Some("https://example.com/maps/".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        let base = opts.public_url.unwrap();
+        assert_eq!(
+            tiles_url(&base, None, "tile"),
+            "https://example.com/maps/tile/{z}/{x}/{y}.mvt"
+        );
+        assert_eq!(
+            tiles_url(&base, Some("v2"), "tile"),
+            "https://example.com/maps/v2/tile/{z}/{x}/{y}.mvt"
+        );
+    }
+
+    #[test]
+    fn versioned_redirect_url() {
+        let params = TileParams {
+            group: "tile".into(),
+            z: 12,
+            x: 654,
+            tail: "1432.mvt".into(),
+        };
+        assert_eq!(
+            tile_url("/maps", Some("m1a2b"), &params),
+            "/maps/m1a2b/tile/12/654/1432.mvt"
+        );
+        // groups without a version redirect to the unversioned path
+        assert_eq!(tile_url("", None, &params), "/tile/12/654/1432.mvt");
+    }
+}
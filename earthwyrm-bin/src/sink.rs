@@ -0,0 +1,371 @@
+// sink.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use anyhow::Result;
+use earthwyrm::{TileGrid, TileId};
+use std::path::PathBuf;
+
+/// Output for seeded tiles
+pub trait TileSink {
+    /// Check if a tile has already been stored
+    fn contains(&self, _tid: TileId) -> bool {
+        false
+    }
+
+    /// Store one tile
+    fn put(&mut self, tid: TileId, tile: &[u8]) -> Result<()>;
+
+    /// Delete a (stale) tile
+    fn delete(&mut self, tid: TileId) -> Result<()>;
+}
+
+/// Tile sink writing to a directory
+pub struct DirSink {
+    /// Base directory (including group)
+    dir: PathBuf,
+
+    /// Tile grid (for zoom level of tile paths)
+    grid: TileGrid,
+}
+
+impl DirSink {
+    /// Create a new directory sink
+    pub fn new(dir: PathBuf, group: &str) -> Self {
+        DirSink {
+            dir: dir.join(group),
+            grid: TileGrid::default(),
+        }
+    }
+
+    /// Set the tile grid
+    pub fn with_grid(mut self, grid: TileGrid) -> Self {
+        self.grid = grid;
+        self
+    }
+
+    /// Get path to a tile file
+    pub fn path(&self, tid: TileId) -> PathBuf {
+        let z = self.grid.zoom(tid);
+        self.dir.join(format!("{z}/{}/{}.mvt", tid.x(), tid.y()))
+    }
+}
+
+impl TileSink for DirSink {
+    fn contains(&self, tid: TileId) -> bool {
+        self.path(tid).exists()
+    }
+
+    fn put(&mut self, tid: TileId, tile: &[u8]) -> Result<()> {
+        let path = self.path(tid);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, tile)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, tid: TileId) -> Result<()> {
+        let path = self.path(tid);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use s3::S3Sink;
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::TileSink;
+    use anyhow::{anyhow, Result};
+    use earthwyrm::{civil_from_days, TileGrid, TileId};
+    use flate2::{write::GzEncoder, Compression};
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+    use std::time::{Duration, SystemTime};
+
+    /// Maximum number of attempts for one request
+    const ATTEMPTS: u32 = 5;
+
+    /// MVT content type
+    const CONTENT_TYPE: &str = "application/vnd.mapbox-vector-tile";
+
+    /// Tile sink writing to an S3-compatible object store
+    pub struct S3Sink {
+        /// Endpoint URL (ex. `https://s3.us-east-1.amazonaws.com`)
+        endpoint: String,
+
+        /// Host name of endpoint
+        host: String,
+
+        /// Region name
+        region: String,
+
+        /// Bucket name
+        bucket: String,
+
+        /// Key prefix (including group)
+        prefix: String,
+
+        /// Access key ID
+        access_key: String,
+
+        /// Secret access key
+        secret_key: String,
+
+        /// Session token (temporary credentials)
+        session_token: Option<String>,
+
+        /// HTTP agent
+        agent: ureq::Agent,
+
+        /// Tile grid (for zoom level of object keys)
+        grid: TileGrid,
+    }
+
+    /// Make hex-encoded SHA-256 hash
+    fn sha256_hex(data: &[u8]) -> String {
+        hex(&Sha256::digest(data))
+    }
+
+    /// Make HMAC-SHA256
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .expect("HMAC accepts any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Encode bytes as lowercase hex
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// URI-encode an object key (keeping `/`)
+    fn uri_encode(key: &str) -> String {
+        let mut enc = String::with_capacity(key.len());
+        for b in key.bytes() {
+            match b {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'-'
+                | b'_'
+                | b'.'
+                | b'~'
+                | b'/' => enc.push(char::from(b)),
+                _ => enc.push_str(&format!("%{b:02X}")),
+            }
+        }
+        enc
+    }
+
+    /// Format current UTC time as (`YYYYMMDD`, `YYYYMMDDTHHMMSSZ`)
+    fn amz_date() -> (String, String) {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let days = secs.div_euclid(86_400);
+        let sod = secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        let date = format!("{year:04}{month:02}{day:02}");
+        let time = format!(
+            "{date}T{:02}{:02}{:02}Z",
+            sod / 3600,
+            (sod / 60) % 60,
+            sod % 60
+        );
+        (date, time)
+    }
+
+    /// Check if an HTTP status is worth retrying
+    fn is_transient(status: u16) -> bool {
+        status == 429 || status >= 500
+    }
+
+    impl S3Sink {
+        /// Create a new S3 sink.
+        ///
+        /// * `url` Sink URL (`s3://bucket/prefix`).
+        /// * `group` Layer group name.
+        /// * `endpoint` Endpoint URL.
+        /// * `region` Region name.
+        ///
+        /// Credentials are read from `AWS_ACCESS_KEY_ID`,
+        /// `AWS_SECRET_ACCESS_KEY` and (optional) `AWS_SESSION_TOKEN`.
+        pub fn new(
+            url: &str,
+            group: &str,
+            endpoint: &str,
+            region: &str,
+        ) -> Result<Self> {
+            let path = url
+                .strip_prefix("s3://")
+                .ok_or_else(|| anyhow!("invalid sink: {url}"))?;
+            let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+            if bucket.is_empty() {
+                return Err(anyhow!("invalid sink bucket: {url}"));
+            }
+            let prefix = prefix.trim_matches('/');
+            let prefix = if prefix.is_empty() {
+                group.to_string()
+            } else {
+                format!("{prefix}/{group}")
+            };
+            let endpoint = endpoint.trim_end_matches('/').to_string();
+            let host = endpoint
+                .split_once("://")
+                .map(|(_scheme, host)| host)
+                .unwrap_or(&endpoint)
+                .to_string();
+            let access_key = std::env::var("AWS_ACCESS_KEY_ID")
+                .map_err(|_| anyhow!("AWS_ACCESS_KEY_ID not set"))?;
+            let secret_key = std::env::var("AWS_SECRET_ACCESS_KEY")
+                .map_err(|_| anyhow!("AWS_SECRET_ACCESS_KEY not set"))?;
+            let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+            Ok(S3Sink {
+                endpoint,
+                host,
+                region: region.to_string(),
+                bucket: bucket.to_string(),
+                prefix,
+                access_key,
+                secret_key,
+                session_token,
+                agent: ureq::Agent::new(),
+                grid: TileGrid::default(),
+            })
+        }
+
+        /// Set the tile grid
+        pub fn with_grid(mut self, grid: TileGrid) -> Self {
+            self.grid = grid;
+            self
+        }
+
+        /// Get URI path of a tile object
+        fn uri(&self, tid: TileId) -> String {
+            uri_encode(&format!(
+                "/{}/{}/{}/{}/{}.mvt",
+                self.bucket,
+                self.prefix,
+                self.grid.zoom(tid),
+                tid.x(),
+                tid.y()
+            ))
+        }
+
+        /// Make signed request headers (AWS Signature Version 4)
+        fn signed_headers(
+            &self,
+            method: &str,
+            uri: &str,
+            body: &[u8],
+            mut headers: Vec<(&'static str, String)>,
+        ) -> Vec<(&'static str, String)> {
+            let (date, time) = amz_date();
+            let payload = sha256_hex(body);
+            headers.push(("host", self.host.clone()));
+            headers.push(("x-amz-content-sha256", payload.clone()));
+            headers.push(("x-amz-date", time.clone()));
+            if let Some(token) = &self.session_token {
+                headers.push(("x-amz-security-token", token.clone()));
+            }
+            headers.sort();
+            let canonical_headers: String = headers
+                .iter()
+                .map(|(k, v)| format!("{k}:{}\n", v.trim()))
+                .collect();
+            let signed: Vec<&str> = headers.iter().map(|(k, _v)| *k).collect();
+            let signed = signed.join(";");
+            let request = format!(
+                "{method}\n{uri}\n\n{canonical_headers}\n{signed}\n{payload}"
+            );
+            let scope = format!("{date}/{}/s3/aws4_request", self.region);
+            let to_sign = format!(
+                "AWS4-HMAC-SHA256\n{time}\n{scope}\n{}",
+                sha256_hex(request.as_bytes())
+            );
+            let key = format!("AWS4{}", self.secret_key);
+            let key = hmac(key.as_bytes(), &date);
+            let key = hmac(&key, &self.region);
+            let key = hmac(&key, "s3");
+            let key = hmac(&key, "aws4_request");
+            let signature = hex(&hmac(&key, &to_sign));
+            headers.push((
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, \
+                    SignedHeaders={signed}, Signature={signature}",
+                    self.access_key
+                ),
+            ));
+            headers.retain(|(k, _v)| *k != "host");
+            headers
+        }
+
+        /// Send a request, retrying transient failures with backoff
+        fn send(
+            &self,
+            method: &str,
+            uri: &str,
+            body: &[u8],
+            headers: Vec<(&'static str, String)>,
+        ) -> Result<()> {
+            let url = format!("{}{uri}", self.endpoint);
+            let mut delay = Duration::from_millis(250);
+            for attempt in 1..=ATTEMPTS {
+                let mut req = self.agent.request(method, &url);
+                for (k, v) in
+                    self.signed_headers(method, uri, body, headers.clone())
+                {
+                    req = req.set(k, &v);
+                }
+                let err = match req.send_bytes(body) {
+                    Ok(_res) => return Ok(()),
+                    Err(ureq::Error::Status(status, _res))
+                        if is_transient(status) =>
+                    {
+                        anyhow!("{method} {url}: status {status}")
+                    }
+                    Err(ureq::Error::Status(status, res)) => {
+                        let msg = res.into_string().unwrap_or_default();
+                        return Err(anyhow!(
+                            "{method} {url}: status {status} {msg}"
+                        ));
+                    }
+                    Err(e @ ureq::Error::Transport(_)) => anyhow!(e),
+                };
+                if attempt == ATTEMPTS {
+                    return Err(err);
+                }
+                log::warn!("{err}, retrying in {delay:?}");
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            unreachable!()
+        }
+    }
+
+    impl TileSink for S3Sink {
+        fn put(&mut self, tid: TileId, tile: &[u8]) -> Result<()> {
+            let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(tile)?;
+            let body = enc.finish()?;
+            let headers = vec![
+                ("content-encoding", "gzip".to_string()),
+                ("content-type", CONTENT_TYPE.to_string()),
+            ];
+            self.send("PUT", &self.uri(tid), &body, headers)
+        }
+
+        fn delete(&mut self, tid: TileId) -> Result<()> {
+            self.send("DELETE", &self.uri(tid), &[], Vec::new())
+        }
+    }
+}
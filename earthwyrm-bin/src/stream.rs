@@ -0,0 +1,116 @@
+// stream.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use axum::body::{Body, Bytes};
+use earthwyrm::{Error, StreamedTile, TileFetch};
+use std::io::{self, BufWriter, Write};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Size of streamed body chunks
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of chunks buffered before encoding waits for the client
+const CHUNKS_BUFFERED: usize = 4;
+
+/// Body chunk sender
+type ChunkSender = mpsc::Sender<io::Result<Bytes>>;
+
+/// Start of a streamed tile.
+///
+/// `Tile` once the first chunk is written; otherwise, the result of a
+/// fetch which wrote nothing.
+pub type Begin = Result<TileFetch<()>, Error>;
+
+/// Writer which sends chunks to a bounded channel
+struct ChannelWriter {
+    /// Chunk sender
+    tx: ChunkSender,
+
+    /// Start sender, until the first chunk is written
+    begin: Option<oneshot::Sender<Begin>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(begin) = self.begin.take() {
+            let _ = begin.send(Ok(TileFetch::Tile(())));
+        }
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Make a response body which streams a tile while it is written.
+///
+/// `write` runs on another blocking thread, waiting when the channel is
+/// full.  Chunks are sent whenever it flushes (after each layer), or the
+/// chunk buffer fills.
+///
+/// This blocks until the first chunk is written, or `write` returns
+/// without writing anything, so that the response status is known.  If
+/// writing fails after the response has started, the body is aborted,
+/// which resets the connection.
+pub fn tile_body<F>(write: F) -> (Begin, Body)
+where
+    F: FnOnce(&mut dyn Write) -> Result<TileFetch<StreamedTile>, Error>
+        + Send
+        + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHUNKS_BUFFERED);
+    let (begin_tx, begin_rx) = oneshot::channel();
+    let chunks = tx.clone();
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            tx: chunks,
+            begin: Some(begin_tx),
+        };
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, writer);
+        let res = write(&mut writer).and_then(|fetch| {
+            writer.flush()?;
+            Ok(fetch)
+        });
+        // unwritten chunks are dropped on error
+        let (writer, _unwritten) = writer.into_parts();
+        match (writer.begin, res) {
+            (Some(begin), Ok(fetch)) => {
+                let fetch = match fetch {
+                    TileFetch::Tile(_tile) => TileFetch::Tile(()),
+                    TileFetch::Empty => TileFetch::Empty,
+                    TileFetch::UnknownGroup => TileFetch::UnknownGroup,
+                };
+                let _ = begin.send(Ok(fetch));
+            }
+            (Some(begin), Err(err)) => {
+                let _ = begin.send(Err(err));
+            }
+            (None, Ok(TileFetch::Tile(tile))) => {
+                log::debug!(
+                    "streamed tile {} ({} bytes)",
+                    tile.tid(),
+                    tile.size()
+                );
+            }
+            (None, Ok(_)) => (),
+            (None, Err(err)) => {
+                log::warn!("tile stream: {err}");
+                let _ =
+                    tx.blocking_send(Err(io::Error::other(err.to_string())));
+            }
+        }
+    });
+    let begin = begin_rx.blocking_recv().unwrap_or_else(|_| {
+        Err(Error::Io(io::Error::other("tile stream task failed")))
+    });
+    (begin, Body::from_stream(ReceiverStream::new(rx)))
+}
@@ -0,0 +1,80 @@
+// events.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use earthwyrm::{Manifest, Wyrm, WyrmCfg};
+use earthwyrm_axum::{live_router, watch_loam, Live, RouterOpts};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Make a config with one (empty) layer group, in a temp loam directory
+fn wyrm_cfg(dir: PathBuf) -> WyrmCfg {
+    let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+        layer_group: tile\n  osm: true\n  layer: water\n    \
+        geom_type: polygon\n    zoom: 0+\n    tags: .natural=water\n";
+    let mut cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+    cfg.layer_group[0].layer.clear();
+    cfg.with_loam_dir(dir)
+}
+
+/// Read from a stream until some text is found (or timeout)
+fn read_until(stream: &mut TcpStream, text: &str) -> String {
+    let start = Instant::now();
+    let mut received = String::new();
+    let mut buf = [0; 1024];
+    while !received.contains(text) {
+        assert!(start.elapsed() < Duration::from_secs(10), "{received}");
+        match stream.read(&mut buf) {
+            Ok(0) => panic!("closed: {received}"),
+            Ok(n) => received.push_str(&String::from_utf8_lossy(&buf[..n])),
+            Err(e) => panic!("{e}: {received}"),
+        }
+    }
+    received
+}
+
+#[test]
+fn reload_event() {
+    let dir = std::env::temp_dir()
+        .join(format!("earthwyrm-{}-events", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cfg = Arc::new(wyrm_cfg(dir.clone()));
+    let live = Arc::new(Live::new(Wyrm::try_from(&*cfg).unwrap()));
+    let mut reloads = live.subscribe_reload();
+    let app =
+        live_router(Arc::clone(&live), RouterOpts::default().with_events(true));
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let listener = rt
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    rt.spawn(async move { axum::serve(listener, app).await });
+    rt.spawn(watch_loam(live, cfg, Duration::from_millis(20)));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let headers = read_until(&mut stream, "\r\n\r\n");
+    assert!(headers.starts_with("HTTP/1.1 200"), "{headers}");
+    assert!(headers.contains("text/event-stream"), "{headers}");
+    assert!(!reloads.has_changed().unwrap());
+
+    // a completed dig writes the manifest last
+    let manifest = Manifest {
+        dig_id: "dig-1".into(),
+        ..Default::default()
+    };
+    manifest.write(&dir).unwrap();
+    let event = read_until(&mut stream, "\n\n");
+    assert!(event.contains("event: reload"), "{event}");
+    assert!(event.contains(r#""name":"tile""#), "{event}");
+    assert_eq!(*reloads.borrow_and_update(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
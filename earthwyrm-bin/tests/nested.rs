@@ -0,0 +1,83 @@
+// nested.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use axum::Router;
+use earthwyrm::{decode_summary, GeomTree, TileId, Wyrm, WyrmCfg};
+use earthwyrm_axum::{earthwyrm_router, RouterOpts};
+use mvt::{GeomType, MapGrid};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Make a config with one point layer, in a loam directory
+fn wyrm_cfg(dir: std::path::PathBuf) -> WyrmCfg {
+    let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+        layer_group: tile\n  layer: pois\n    \
+        geom_type: point\n    zoom: 0+\n    tags: ?name\n";
+    let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+    cfg.with_loam_dir(dir)
+}
+
+/// Send a GET request, returning the status code and body.
+///
+/// HTTP/1.0 is used so that the body is not chunked.
+fn http_get(addr: SocketAddr, path: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    write!(stream, "GET {path} HTTP/1.0\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut res = Vec::new();
+    stream.read_to_end(&mut res).unwrap();
+    let end = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&res[..end]);
+    let status = head.split_whitespace().nth(1).unwrap().to_string();
+    (status, res[end + 4..].to_vec())
+}
+
+#[test]
+fn nested_tile() {
+    let dir = std::env::temp_dir()
+        .join(format!("earthwyrm-{}-nested", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // one point in the center of tile 10/300/400
+    let tid = TileId::new(300, 400, 10).unwrap();
+    let bbox = MapGrid::default().tile_bbox(tid);
+    let pt = (
+        (bbox.x_min() + bbox.x_max()) / 2.0,
+        (bbox.y_min() + bbox.y_max()) / 2.0,
+    );
+    GeomTree::write_loam(
+        dir.join("pois.loam"),
+        GeomType::Point,
+        vec![(vec![Some("center".into())], vec![vec![pt]])],
+    )
+    .unwrap();
+    let wyrm = Arc::new(Wyrm::try_from(&wyrm_cfg(dir.clone())).unwrap());
+    // embedding application, with its own routes
+    let app = Router::new()
+        .route("/", axum::routing::get(|| async { "home" }))
+        .nest("/maps", earthwyrm_router(wyrm, RouterOpts::default()));
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let listener = rt
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    rt.spawn(async move { axum::serve(listener, app).await });
+
+    let (status, body) = http_get(addr, "/maps/tile/10/300/400.mvt");
+    assert_eq!(status, "200");
+    let summary = decode_summary(&body).unwrap();
+    assert_eq!(summary.layers.len(), 1);
+    assert_eq!(summary.layers[0].name, "pois");
+    assert_eq!(summary.layers[0].features.len(), 1);
+    // tile routes are only within the nested path
+    let (status, _body) = http_get(addr, "/tile/10/300/400.mvt");
+    assert_eq!(status, "404");
+    let (status, body) = http_get(addr, "/");
+    assert_eq!(status, "200");
+    assert_eq!(body, b"home");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
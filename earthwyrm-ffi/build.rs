@@ -0,0 +1,10 @@
+// build.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+fn main() {
+    // target triple, for compiling the C smoke test with `cc`
+    let target = std::env::var("TARGET").unwrap();
+    println!("cargo:rustc-env=TARGET={target}");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,60 @@
+/*
+ * earthwyrm.h
+ *
+ * Copyright (c) 2024  Minnesota Department of Transportation
+ *
+ * C ABI for fetching MVT tiles from earthwyrm loam files.
+ */
+#ifndef EARTHWYRM_H
+#define EARTHWYRM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes */
+#define WYRM_OK                 0   /* tile fetched */
+#define WYRM_EMPTY              1   /* tile empty (no buffer returned) */
+#define WYRM_ERR_NULL          -1   /* null pointer argument */
+#define WYRM_ERR_UTF8          -2   /* string argument not valid UTF-8 */
+#define WYRM_ERR_UNKNOWN_GROUP -3   /* unknown layer group name */
+#define WYRM_ERR_INVALID_TILE  -4   /* invalid zoom level or tile */
+#define WYRM_ERR_IO            -5   /* I/O error */
+#define WYRM_ERR_LOAM          -6   /* loam file error */
+#define WYRM_ERR_CONFIG        -7   /* invalid configuration */
+#define WYRM_ERR_MVT           -8   /* MVT encoding error */
+#define WYRM_ERR_PANIC         -9   /* panic caught at boundary */
+#define WYRM_ERR_OTHER        -99   /* other error */
+
+/* Opaque tile generator */
+typedef struct Wyrm Wyrm;
+
+/*
+ * Create a wyrm from a configuration file (earthwyrm.muon).  The loam
+ * directory is relative to the configuration file.  Returns NULL on error.
+ */
+Wyrm *wyrm_new(const char *config_path);
+
+/*
+ * Fetch one tile.  On WYRM_OK, out_buf / out_len are set to an encoded MVT
+ * buffer, which must be released with wyrm_free_tile.  Otherwise, out_buf
+ * is set to NULL and out_len to 0.
+ */
+int wyrm_fetch_tile(const Wyrm *wyrm, const char *group, uint32_t z,
+                    uint32_t x, uint32_t y, uint8_t **out_buf,
+                    size_t *out_len);
+
+/* Release a tile buffer from wyrm_fetch_tile */
+void wyrm_free_tile(uint8_t *buf, size_t len);
+
+/* Release a wyrm from wyrm_new */
+void wyrm_free(Wyrm *wyrm);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* EARTHWYRM_H */
@@ -0,0 +1,196 @@
+// lib.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+//! C ABI for fetching tiles from loam files.
+//!
+//! See `include/earthwyrm.h` for the C declarations.  All panics are caught
+//! at the boundary and returned as [WYRM_ERR_PANIC].
+//!
+//! The exported functions require the `ffi` feature (enabled by default).
+use std::ffi::c_int;
+#[cfg(feature = "ffi")]
+use {
+    earthwyrm::{Error, TileFetch, TileId, Wyrm, WyrmCfg},
+    std::ffi::{c_char, CStr},
+    std::panic::{catch_unwind, AssertUnwindSafe},
+    std::path::Path,
+    std::ptr,
+};
+
+/// Tile fetched
+pub const WYRM_OK: c_int = 0;
+
+/// Tile empty (no features); no buffer is returned
+pub const WYRM_EMPTY: c_int = 1;
+
+/// Null pointer argument
+pub const WYRM_ERR_NULL: c_int = -1;
+
+/// String argument not valid UTF-8
+pub const WYRM_ERR_UTF8: c_int = -2;
+
+/// Unknown layer group name
+pub const WYRM_ERR_UNKNOWN_GROUP: c_int = -3;
+
+/// Invalid zoom level or tile out of range
+pub const WYRM_ERR_INVALID_TILE: c_int = -4;
+
+/// I/O error
+pub const WYRM_ERR_IO: c_int = -5;
+
+/// Loam file error (or mismatched configuration)
+pub const WYRM_ERR_LOAM: c_int = -6;
+
+/// Invalid configuration
+pub const WYRM_ERR_CONFIG: c_int = -7;
+
+/// MVT encoding error
+pub const WYRM_ERR_MVT: c_int = -8;
+
+/// Panic caught at boundary
+pub const WYRM_ERR_PANIC: c_int = -9;
+
+/// Other error
+pub const WYRM_ERR_OTHER: c_int = -99;
+
+/// Get status code for an error
+#[cfg(feature = "ffi")]
+fn status(err: &Error) -> c_int {
+    match err {
+        Error::UnknownGroupName() => WYRM_ERR_UNKNOWN_GROUP,
+        Error::InvalidZoomLevel(_) | Error::TileOutOfRange(_) => {
+            WYRM_ERR_INVALID_TILE
+        }
+        Error::Io(_) => WYRM_ERR_IO,
+        Error::Loam(_) | Error::LoamMismatch(_) => WYRM_ERR_LOAM,
+        Error::Muon(_)
+        | Error::InvalidConfig(_)
+        | Error::LayerConfig { .. } => WYRM_ERR_CONFIG,
+        Error::Mvt(_) => WYRM_ERR_MVT,
+        _ => WYRM_ERR_OTHER,
+    }
+}
+
+/// Borrow a C string argument
+///
+/// # Safety
+///
+/// `s` must be null or a valid nul-terminated string.
+#[cfg(feature = "ffi")]
+unsafe fn c_str<'a>(s: *const c_char) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(WYRM_ERR_NULL);
+    }
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| WYRM_ERR_UTF8)
+}
+
+/// Create a wyrm from a configuration file (`earthwyrm.muon`).
+///
+/// The `loam` directory is relative to the configuration file.  Returns
+/// null on error.
+///
+/// # Safety
+///
+/// `config_path` must be a valid nul-terminated string.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub unsafe extern "C" fn wyrm_new(config_path: *const c_char) -> *mut Wyrm {
+    let Ok(path) = (unsafe { c_str(config_path) }) else {
+        return ptr::null_mut();
+    };
+    let res = catch_unwind(|| {
+        let cfg = WyrmCfg::load_path(Path::new(path))?;
+        Wyrm::try_from(&cfg)
+    });
+    match res {
+        Ok(Ok(wyrm)) => Box::into_raw(Box::new(wyrm)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Fetch one tile.
+///
+/// On `WYRM_OK`, `out_buf` and `out_len` are set to an encoded MVT buffer,
+/// which must be released with [wyrm_free_tile].  Otherwise, `out_buf` is
+/// set to null and `out_len` to 0.
+///
+/// # Safety
+///
+/// `wyrm` must be from [wyrm_new], `group` a valid nul-terminated string,
+/// and `out_buf` / `out_len` valid for writes.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub unsafe extern "C" fn wyrm_fetch_tile(
+    wyrm: *const Wyrm,
+    group: *const c_char,
+    z: u32,
+    x: u32,
+    y: u32,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    if wyrm.is_null() || out_buf.is_null() || out_len.is_null() {
+        return WYRM_ERR_NULL;
+    }
+    unsafe {
+        *out_buf = ptr::null_mut();
+        *out_len = 0;
+    }
+    let group = match unsafe { c_str(group) } {
+        Ok(group) => group,
+        Err(code) => return code,
+    };
+    let Ok(tid) = TileId::new(x, y, z) else {
+        return WYRM_ERR_INVALID_TILE;
+    };
+    let wyrm = unsafe { &*wyrm };
+    let res =
+        catch_unwind(AssertUnwindSafe(|| wyrm.try_fetch_tile(group, tid)));
+    match res {
+        Ok(Ok(TileFetch::Tile(tile))) => {
+            let tile = tile.into_boxed_slice();
+            unsafe {
+                *out_len = tile.len();
+                *out_buf = Box::into_raw(tile).cast();
+            }
+            WYRM_OK
+        }
+        Ok(Ok(TileFetch::Empty)) => WYRM_EMPTY,
+        Ok(Ok(TileFetch::UnknownGroup)) => WYRM_ERR_UNKNOWN_GROUP,
+        Ok(Err(err)) => status(&err),
+        Err(_) => WYRM_ERR_PANIC,
+    }
+}
+
+/// Release a tile buffer from [wyrm_fetch_tile]
+///
+/// # Safety
+///
+/// `buf` and `len` must be from one successful [wyrm_fetch_tile] call
+/// (or `buf` null).
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub unsafe extern "C" fn wyrm_free_tile(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        let slice = ptr::slice_from_raw_parts_mut(buf, len);
+        drop(unsafe { Box::from_raw(slice) });
+    }
+}
+
+/// Release a wyrm from [wyrm_new]
+///
+/// # Safety
+///
+/// `wyrm` must be from [wyrm_new] (or null), and not used afterwards.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub unsafe extern "C" fn wyrm_free(wyrm: *mut Wyrm) {
+    if !wyrm.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            drop(unsafe { Box::from_raw(wyrm) });
+        }));
+    }
+}
@@ -0,0 +1,61 @@
+/*
+ * smoke.c
+ *
+ * Copyright (c) 2024  Minnesota Department of Transportation
+ *
+ * Smoke test linking the earthwyrm C ABI, run by `tests/smoke.rs`.
+ * Usage: smoke <config_path>
+ */
+#include <stdio.h>
+#include "earthwyrm.h"
+
+#define CHECK(cond) do { \
+    if (!(cond)) { \
+        fprintf(stderr, "smoke.c:%d: check failed: %s\n", __LINE__, #cond); \
+        return 1; \
+    } \
+} while (0)
+
+int main(int argc, char *argv[]) {
+    Wyrm *wyrm;
+    uint8_t *buf;
+    size_t len;
+    int st;
+
+    CHECK(argc == 2);
+    CHECK(wyrm_new(NULL) == NULL);
+    CHECK(wyrm_new("missing/earthwyrm.muon") == NULL);
+    wyrm = wyrm_new(argv[1]);
+    CHECK(wyrm != NULL);
+
+    st = wyrm_fetch_tile(wyrm, "smoke", 0, 0, 0, &buf, &len);
+    CHECK(st == WYRM_OK || st == WYRM_EMPTY);
+    if (st == WYRM_OK) {
+        CHECK(buf != NULL && len > 0);
+        wyrm_free_tile(buf, len);
+    } else {
+        CHECK(buf == NULL && len == 0);
+    }
+
+    st = wyrm_fetch_tile(wyrm, "unknown", 0, 0, 0, &buf, &len);
+    CHECK(st == WYRM_ERR_UNKNOWN_GROUP);
+    CHECK(buf == NULL && len == 0);
+    st = wyrm_fetch_tile(wyrm, "smoke", 1, 2, 0, &buf, &len);
+    CHECK(st == WYRM_ERR_INVALID_TILE);
+    st = wyrm_fetch_tile(wyrm, "smoke", 1, 0, 2, &buf, &len);
+    CHECK(st == WYRM_ERR_INVALID_TILE);
+    st = wyrm_fetch_tile(wyrm, "\xff", 0, 0, 0, &buf, &len);
+    CHECK(st == WYRM_ERR_UTF8);
+    st = wyrm_fetch_tile(wyrm, NULL, 0, 0, 0, &buf, &len);
+    CHECK(st == WYRM_ERR_NULL);
+    st = wyrm_fetch_tile(NULL, "smoke", 0, 0, 0, &buf, &len);
+    CHECK(st == WYRM_ERR_NULL);
+    st = wyrm_fetch_tile(wyrm, "smoke", 0, 0, 0, NULL, &len);
+    CHECK(st == WYRM_ERR_NULL);
+
+    wyrm_free_tile(NULL, 0);
+    wyrm_free(wyrm);
+    wyrm_free(NULL);
+    puts("smoke: ok");
+    return 0;
+}
@@ -0,0 +1,66 @@
+// smoke.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+//! Compile `tests/smoke.c` against `include/earthwyrm.h`, link it with the
+//! shared library and run it.
+#![cfg(all(unix, feature = "ffi"))]
+
+use std::fs::write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Configuration with one group and no layers
+const MUON: &str = &r#"
+bind_address:
+tile_extent: 256
+layer_group: smoke
+  osm: false
+  layer:
+"#;
+
+/// Get directory containing `libearthwyrm_ffi`
+///
+/// Test executables are built in `target/<profile>/deps`, and the library
+/// is copied up to `target/<profile>`.
+fn lib_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().and_then(Path::parent).unwrap().to_path_buf()
+}
+
+#[test]
+fn c_smoke() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let cfg = tmp.join("earthwyrm.muon");
+    write(&cfg, MUON).unwrap();
+    let exe = tmp.join("smoke");
+    let lib = lib_dir();
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .opt_level(0)
+        .host(env!("TARGET"))
+        .target(env!("TARGET"))
+        .get_compiler();
+    let status = compiler
+        .to_command()
+        .arg(crate_dir.join("tests/smoke.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&lib)
+        .arg("-learthwyrm_ffi")
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling smoke.c failed");
+    let out = Command::new(&exe).arg(&cfg).output().unwrap();
+    assert!(
+        out.status.success(),
+        "smoke failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "smoke: ok\n");
+}
@@ -0,0 +1,61 @@
+// batch_query.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+// Compare a batch point query against individual queries, using clustered
+// points around a center position.  Run from a directory containing
+// `earthwyrm.muon` and dug loam files:
+//
+//   cargo run --release --example batch_query -- segments -93.26 44.98
+//
+use earthwyrm::{Wyrm, WyrmCfg};
+use std::env;
+use std::time::Instant;
+
+/// Number of points to query
+const N_POINTS: usize = 5000;
+
+/// Search radius (meters)
+const RADIUS: f64 = 10.0;
+
+/// Make clustered points around a center position
+fn clustered_points(lon: f64, lat: f64) -> Vec<(f64, f64)> {
+    (0..N_POINTS)
+        .map(|i| {
+            let a = i as f64 * 0.618_034;
+            let d = (i % 100) as f64 * 0.0002;
+            (lon + d * a.cos(), lat + d * a.sin())
+        })
+        .collect()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args();
+    args.next().unwrap();
+    let layer = args.next().expect("missing layer");
+    let lon = args.next().expect("missing lon").parse()?;
+    let lat = args.next().expect("missing lat").parse()?;
+    let wyrm_cfg = WyrmCfg::load()?;
+    let wyrm = Wyrm::try_from(&wyrm_cfg)?;
+    let points = clustered_points(lon, lat);
+
+    let t = Instant::now();
+    let mut n_single = 0;
+    for pt in &points {
+        let matches = wyrm.query_batch(&layer, &[*pt], RADIUS)?;
+        n_single += matches.iter().map(Vec::len).sum::<usize>();
+    }
+    let single = t.elapsed();
+
+    let t = Instant::now();
+    let matches = wyrm.query_batch(&layer, &points, RADIUS)?;
+    let n_batch = matches.iter().map(Vec::len).sum::<usize>();
+    let batch = t.elapsed();
+
+    println!("individual: {n_single} matches in {single:?}");
+    println!("batch:      {n_batch} matches in {batch:?}");
+    println!("speedup:    {:.1}x", single.as_secs_f64() / batch.as_secs_f64());
+    // query limit applies to each point, so results must be the same
+    assert_eq!(n_single, n_batch, "batch matches differ");
+    Ok(())
+}
@@ -0,0 +1,477 @@
+// analyze.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use std::fmt;
+
+/// Candidate tile extents (pixels)
+pub const CANDIDATE_EXTENTS: [u32; 4] = [256, 1024, 4096, 8192];
+
+/// Candidate edge extents, as a fraction of tile size
+pub const CANDIDATE_EDGES: [f64; 5] =
+    [1.0 / 256.0, 1.0 / 64.0, 1.0 / 32.0, 1.0 / 16.0, 1.0 / 8.0];
+
+/// Fraction of vertices (compared to the largest extent) which must be
+/// distinct after quantizing, for a recommended tile extent
+const KEPT_RATIO: f64 = 0.95;
+
+/// Maximum fraction of vertices within the edge buffer, for a recommended
+/// edge extent
+const BUFFER_RATIO: f64 = 0.10;
+
+/// Random number generator (SplitMix64), for sampling tiles
+pub(crate) struct SplitMix(u64);
+
+impl SplitMix {
+    /// Create a generator from a seed
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix(seed)
+    }
+
+    /// Get the next random number
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Get a random number in a range
+    pub(crate) fn in_range(&mut self, lo: u32, hi: u32) -> u32 {
+        let n = u64::from(hi - lo) + 1;
+        lo + (self.next_u64() % n) as u32
+    }
+}
+
+/// Encoding statistics for one tile, recorded by instrumentation hooks.
+///
+/// Vertices are recorded before snapping, as fractions of the tile
+/// outline, so that one encoding pass covers all candidate extents.
+#[derive(Debug)]
+pub(crate) struct EncodeStats {
+    /// Tile width, in ground meters
+    tile_m: f64,
+
+    /// Previous quantized vertex of the current run, for each extent
+    prev: [Option<(i64, i64)>; CANDIDATE_EXTENTS.len()],
+
+    /// Vertices of the current feature
+    feature_vertices: usize,
+
+    /// Current feature has vertices within each edge buffer
+    feature_buffers: [bool; CANDIDATE_EDGES.len()],
+
+    /// Statistics for all features
+    zoom: ZoomAnalysis,
+}
+
+/// Quantization statistics at a candidate tile extent
+#[derive(Clone, Debug, Default)]
+pub struct ExtentStats {
+    /// Tile extent (pixels)
+    pub extent: u32,
+
+    /// Vertices remaining after removing quantized duplicates
+    pub kept: u64,
+
+    /// Sum of quantization errors (ground meters)
+    pub error_sum: f64,
+
+    /// Maximum quantization error (ground meters)
+    pub error_max: f64,
+}
+
+/// Edge buffer statistics at a candidate edge extent
+#[derive(Clone, Debug, Default)]
+pub struct EdgeStats {
+    /// Edge extent, as a fraction of tile size
+    pub edge: f64,
+
+    /// Features with vertices in the edge buffer
+    pub features: u64,
+
+    /// Vertices in the edge buffer
+    pub vertices: u64,
+}
+
+/// Encoding statistics for sampled tiles at one zoom level
+#[derive(Clone, Debug, Default)]
+pub struct ZoomAnalysis {
+    /// Zoom level
+    pub zoom: u32,
+
+    /// Number of sampled tiles
+    pub tiles: usize,
+
+    /// Number of sampled tiles with features
+    pub tiles_with_data: usize,
+
+    /// Vertex count of each feature
+    pub feature_vertices: Vec<usize>,
+
+    /// Total vertices
+    pub vertices: u64,
+
+    /// Statistics for each candidate tile extent
+    pub extents: Vec<ExtentStats>,
+
+    /// Statistics for each candidate edge extent
+    pub edges: Vec<EdgeStats>,
+}
+
+/// Analysis of tile / edge extents for a layer group
+#[derive(Clone, Debug)]
+pub struct ExtentAnalysis {
+    /// Layer group name
+    pub group: String,
+
+    /// Configured tile extent
+    pub tile_extent: u32,
+
+    /// Statistics by zoom level
+    pub zooms: Vec<ZoomAnalysis>,
+}
+
+impl EncodeStats {
+    /// Create encode stats for a tile.
+    ///
+    /// * `zoom` Zoom level of tile.
+    /// * `tile_m` Tile width, in ground meters.
+    pub(crate) fn new(zoom: u32, tile_m: f64) -> Self {
+        EncodeStats {
+            tile_m,
+            prev: Default::default(),
+            feature_vertices: 0,
+            feature_buffers: Default::default(),
+            zoom: ZoomAnalysis::new(zoom),
+        }
+    }
+
+    /// Record a vertex.
+    ///
+    /// * `u` X position, as a fraction of tile width.
+    /// * `v` Y position, as a fraction of tile height.
+    pub(crate) fn vertex(&mut self, u: f64, v: f64) {
+        if !(u.is_finite() && v.is_finite()) {
+            return;
+        }
+        self.feature_vertices += 1;
+        self.zoom.vertices += 1;
+        for (i, ext) in CANDIDATE_EXTENTS.iter().enumerate() {
+            let e = f64::from(*ext);
+            let (x, y) = (u * e, v * e);
+            let q = (x.round() as i64, y.round() as i64);
+            let stats = &mut self.zoom.extents[i];
+            if self.prev[i] != Some(q) {
+                stats.kept += 1;
+                self.prev[i] = Some(q);
+            }
+            let err = (x - x.round()).hypot(y - y.round()) * self.tile_m / e;
+            stats.error_sum += err;
+            stats.error_max = stats.error_max.max(err);
+        }
+        let outside = (-u).max(u - 1.0).max(-v).max(v - 1.0);
+        if outside > 0.0 {
+            for (i, edge) in CANDIDATE_EDGES.iter().enumerate() {
+                if outside <= *edge {
+                    self.zoom.edges[i].vertices += 1;
+                    self.feature_buffers[i] = true;
+                }
+            }
+        }
+    }
+
+    /// End a run of connected vertices (linestring run or polygon ring)
+    pub(crate) fn end_run(&mut self) {
+        self.prev = Default::default();
+    }
+
+    /// End a feature; features with no vertices are not counted
+    pub(crate) fn end_feature(&mut self) {
+        self.end_run();
+        if self.feature_vertices > 0 {
+            self.zoom.feature_vertices.push(self.feature_vertices);
+            for (i, buffer) in self.feature_buffers.iter().enumerate() {
+                if *buffer {
+                    self.zoom.edges[i].features += 1;
+                }
+            }
+        }
+        self.feature_vertices = 0;
+        self.feature_buffers = Default::default();
+    }
+
+    /// Finish recording, getting statistics
+    pub(crate) fn finish(mut self) -> ZoomAnalysis {
+        self.end_feature();
+        self.zoom
+    }
+}
+
+impl ZoomAnalysis {
+    /// Create empty statistics for a zoom level
+    pub(crate) fn new(zoom: u32) -> Self {
+        ZoomAnalysis {
+            zoom,
+            extents: CANDIDATE_EXTENTS
+                .iter()
+                .map(|extent| ExtentStats {
+                    extent: *extent,
+                    ..Default::default()
+                })
+                .collect(),
+            edges: CANDIDATE_EDGES
+                .iter()
+                .map(|edge| EdgeStats {
+                    edge: *edge,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Merge statistics from a sampled tile
+    pub(crate) fn merge(&mut self, tile: ZoomAnalysis) {
+        self.tiles += 1;
+        if !tile.feature_vertices.is_empty() {
+            self.tiles_with_data += 1;
+        }
+        self.feature_vertices.extend(tile.feature_vertices);
+        self.vertices += tile.vertices;
+        for (ext, t) in self.extents.iter_mut().zip(tile.extents) {
+            ext.kept += t.kept;
+            ext.error_sum += t.error_sum;
+            ext.error_max = ext.error_max.max(t.error_max);
+        }
+        for (edge, t) in self.edges.iter_mut().zip(tile.edges) {
+            edge.features += t.features;
+            edge.vertices += t.vertices;
+        }
+    }
+
+    /// Get a percentile of feature vertex counts
+    pub fn vertex_percentile(&self, pct: f64) -> usize {
+        let mut counts = self.feature_vertices.clone();
+        counts.sort_unstable();
+        match counts.len() {
+            0 => 0,
+            n => {
+                let i = ((pct / 100.0) * (n - 1) as f64).round() as usize;
+                counts[i.min(n - 1)]
+            }
+        }
+    }
+}
+
+impl ExtentStats {
+    /// Get the mean quantization error (ground meters)
+    pub fn error_mean(&self, vertices: u64) -> f64 {
+        if vertices > 0 {
+            self.error_sum / vertices as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+impl ExtentAnalysis {
+    /// Recommend a tile extent.
+    ///
+    /// This is the smallest candidate which keeps most distinct vertices
+    /// (compared to the largest candidate) at the highest sampled zoom.
+    pub fn recommend_extent(&self) -> Option<u32> {
+        let zoom = self.zooms.iter().rev().find(|z| z.vertices > 0)?;
+        let max_kept = zoom.extents.last()?.kept as f64;
+        zoom.extents
+            .iter()
+            .find(|ext| ext.kept as f64 >= max_kept * KEPT_RATIO)
+            .map(|ext| ext.extent)
+    }
+
+    /// Recommend an edge extent, as a fraction of tile size.
+    ///
+    /// This is the largest candidate with a limited number of vertices
+    /// within the edge buffer (for all sampled zooms).
+    pub fn recommend_edge(&self) -> Option<f64> {
+        let vertices: u64 = self.zooms.iter().map(|z| z.vertices).sum();
+        if vertices == 0 {
+            return None;
+        }
+        CANDIDATE_EDGES
+            .iter()
+            .enumerate()
+            .filter(|(i, _edge)| {
+                let buffer: u64 =
+                    self.zooms.iter().map(|z| z.edges[*i].vertices).sum();
+                buffer as f64 <= vertices as f64 * BUFFER_RATIO
+            })
+            .map(|(_i, edge)| *edge)
+            .last()
+    }
+}
+
+/// Format a count as a percentage of a total
+fn percent(n: u64, total: u64) -> f64 {
+    if total > 0 {
+        100.0 * n as f64 / total as f64
+    } else {
+        0.0
+    }
+}
+
+impl fmt::Display for ExtentAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "group {} (tile_extent {})", self.group, self.tile_extent)?;
+        writeln!(f, "\nFeature vertices:")?;
+        writeln!(
+            f,
+            "  zoom  tiles  data features     p50     p90     p99     max"
+        )?;
+        for z in &self.zooms {
+            writeln!(
+                f,
+                "  {:>4} {:>6} {:>5} {:>8} {:>7} {:>7} {:>7} {:>7}",
+                z.zoom,
+                z.tiles,
+                z.tiles_with_data,
+                z.feature_vertices.len(),
+                z.vertex_percentile(50.0),
+                z.vertex_percentile(90.0),
+                z.vertex_percentile(99.0),
+                z.vertex_percentile(100.0),
+            )?;
+        }
+        writeln!(f, "\nQuantization (by tile extent):")?;
+        writeln!(f, "  zoom  extent   kept %   mean err m    max err m")?;
+        for z in &self.zooms {
+            for ext in &z.extents {
+                writeln!(
+                    f,
+                    "  {:>4} {:>7} {:>8.1} {:>12.3} {:>12.3}",
+                    z.zoom,
+                    ext.extent,
+                    percent(ext.kept, z.vertices),
+                    ext.error_mean(z.vertices),
+                    ext.error_max,
+                )?;
+            }
+        }
+        writeln!(f, "\nEdge buffer (by edge extent):")?;
+        writeln!(f, "  zoom  edge px  features %  vertices %")?;
+        for z in &self.zooms {
+            let features = z.feature_vertices.len() as u64;
+            for edge in &z.edges {
+                writeln!(
+                    f,
+                    "  {:>4} {:>8} {:>11.1} {:>11.1}",
+                    z.zoom,
+                    (edge.edge * f64::from(self.tile_extent)).round(),
+                    percent(edge.features, features),
+                    percent(edge.vertices, z.vertices),
+                )?;
+            }
+        }
+        writeln!(f, "\nRecommendation:")?;
+        match self.recommend_extent() {
+            Some(extent) => writeln!(f, "  tile_extent: {extent}")?,
+            None => writeln!(f, "  tile_extent: (no data sampled)")?,
+        }
+        if let Some(edge) = self.recommend_edge() {
+            let extent = self.recommend_extent().unwrap_or(self.tile_extent);
+            let edge = (edge * f64::from(extent)).round();
+            writeln!(f, "  edge_extent: {edge}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_counts() {
+        let mut stats = EncodeStats::new(10, 256.0);
+        stats.vertex(0.1, 0.1);
+        stats.vertex(0.2, 0.2);
+        stats.vertex(0.3, 0.3);
+        stats.end_feature();
+        // features with no (finite) vertices are not counted
+        stats.vertex(f64::NAN, 0.5);
+        stats.end_feature();
+        stats.vertex(0.4, 0.4);
+        stats.end_run();
+        stats.vertex(0.5, 0.5);
+        let zoom = stats.finish();
+        assert_eq!(zoom.zoom, 10);
+        assert_eq!(zoom.feature_vertices, [3, 2]);
+        assert_eq!(zoom.vertices, 5);
+        assert_eq!(zoom.vertex_percentile(0.0), 2);
+        assert_eq!(zoom.vertex_percentile(100.0), 3);
+    }
+
+    #[test]
+    fn quantized_duplicates() {
+        let mut stats = EncodeStats::new(10, 256.0);
+        // same pixel at extent 256, but not at 8192
+        stats.vertex(0.5, 0.5);
+        stats.vertex(0.5001, 0.5);
+        // a new run does not continue the previous one
+        stats.end_run();
+        stats.vertex(0.5001, 0.5);
+        let zoom = stats.finish();
+        let kept: Vec<u64> = zoom.extents.iter().map(|e| e.kept).collect();
+        assert_eq!(kept, [2, 2, 2, 3]);
+    }
+
+    #[test]
+    fn quantization_error() {
+        // tile is 256 m wide, so 1 pixel is 1 m at extent 256
+        let mut stats = EncodeStats::new(10, 256.0);
+        stats.vertex(1.0 / 512.0, 0.0);
+        let zoom = stats.finish();
+        assert_eq!(zoom.extents[0].extent, 256);
+        assert_eq!(zoom.extents[0].error_max, 0.5);
+        assert_eq!(zoom.extents[0].error_mean(zoom.vertices), 0.5);
+        for ext in &zoom.extents[1..] {
+            assert_eq!(ext.error_max, 0.0, "{}", ext.extent);
+        }
+    }
+
+    #[test]
+    fn edge_buffers() {
+        let mut stats = EncodeStats::new(10, 256.0);
+        // 1% of tile size outside the right edge
+        stats.vertex(1.01, 0.5);
+        stats.vertex(0.5, 0.5);
+        stats.end_feature();
+        // 10% of tile size above the top edge
+        stats.vertex(0.5, -0.1);
+        stats.vertex(0.5, -0.1);
+        let zoom = stats.finish();
+        let features: Vec<u64> =
+            zoom.edges.iter().map(|e| e.features).collect();
+        let vertices: Vec<u64> =
+            zoom.edges.iter().map(|e| e.vertices).collect();
+        // edges: 1/256, 1/64, 1/32, 1/16, 1/8
+        assert_eq!(features, [0, 1, 1, 1, 2]);
+        assert_eq!(vertices, [0, 1, 1, 1, 3]);
+    }
+
+    #[test]
+    fn merge_tiles() {
+        let mut zoom = ZoomAnalysis::new(12);
+        let mut stats = EncodeStats::new(12, 100.0);
+        stats.vertex(0.25, 0.25);
+        stats.vertex(0.75, 0.75);
+        zoom.merge(stats.finish());
+        zoom.merge(EncodeStats::new(12, 100.0).finish());
+        assert_eq!(zoom.tiles, 2);
+        assert_eq!(zoom.tiles_with_data, 1);
+        assert_eq!(zoom.feature_vertices, [2]);
+        assert_eq!(zoom.vertices, 2);
+        assert!(zoom.extents.iter().all(|e| e.kept == 2));
+    }
+}
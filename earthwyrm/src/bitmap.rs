@@ -0,0 +1,284 @@
+// bitmap.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::Result;
+use mvt::TileId;
+use pointy::BBox;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Magic number of tile bitmap files
+const MAGIC: &[u8; 4] = b"EWTB";
+
+/// Tile bitmap file format version
+const VERSION: u32 = 1;
+
+/// Margin added to feature bounds, to cover rounding (meters)
+const MARGIN_TOLERANCE: f64 = 0.001;
+
+/// Maximum zoom levels below a tile checked for size estimates
+const FRACTION_DEPTH: u32 = 6;
+
+/// Hierarchical bitmap of tiles which may contain data.
+///
+/// There is one bit per tile at each zoom level, up to a maximum.  Tiles
+/// beyond the maximum zoom check their ancestor at that zoom.  A set bit
+/// means the tile *may* have data; a clear bit means it has none.
+#[derive(Debug)]
+pub struct TileBitmap {
+    /// Maximum zoom level
+    zoom: u32,
+
+    /// Bit words for each zoom level
+    levels: Vec<Vec<u64>>,
+
+    /// Number of set bits at each zoom level
+    counts: Vec<u64>,
+}
+
+/// Get the number of 64-bit words for a zoom level
+fn level_words(z: u32) -> usize {
+    ((1usize << (2 * z)) + 63) / 64
+}
+
+/// Read a little-endian `u32`
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a little-endian `u64`
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl TileBitmap {
+    /// Create an empty tile bitmap.
+    ///
+    /// * `zoom` Maximum zoom level.
+    pub fn new(zoom: u32) -> Self {
+        let levels = (0..=zoom).map(|z| vec![0; level_words(z)]).collect();
+        let counts = vec![0; zoom as usize + 1];
+        TileBitmap {
+            zoom,
+            levels,
+            counts,
+        }
+    }
+
+    /// Get the maximum zoom level
+    pub fn zoom(&self) -> u32 {
+        self.zoom
+    }
+
+    /// Mark tiles at one zoom level which intersect a bounding box.
+    ///
+    /// * `world` Bounds of the tile grid.
+    /// * `z` Zoom level.
+    /// * `bbox` Bounding box, expanded by `margin` on all sides.
+    /// * `margin` Margin (edge buffer) for the zoom level.
+    pub fn mark(
+        &mut self,
+        world: BBox<f64>,
+        z: u32,
+        bbox: BBox<f64>,
+        margin: f64,
+    ) {
+        let Some(level) = self.levels.get_mut(z as usize) else {
+            return;
+        };
+        let n = 1u32 << z;
+        let size = (world.x_max() - world.x_min()) / f64::from(n);
+        let margin = margin + MARGIN_TOLERANCE;
+        let tile =
+            |v: f64| (v / size).floor().clamp(0.0, f64::from(n - 1)) as u32;
+        let x0 = tile(bbox.x_min() - margin - world.x_min());
+        let x1 = tile(bbox.x_max() + margin - world.x_min());
+        // tile Y increases southward
+        let y0 = tile(world.y_max() - bbox.y_max() - margin);
+        let y1 = tile(world.y_max() - bbox.y_min() + margin);
+        let mut count = 0;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let i = (y as usize) * (n as usize) + x as usize;
+                let bit = 1 << (i % 64);
+                if level[i / 64] & bit == 0 {
+                    level[i / 64] |= bit;
+                    count += 1;
+                }
+            }
+        }
+        self.counts[z as usize] += count;
+    }
+
+    /// Check one bit of a zoom level (set if out of range)
+    fn bit(&self, z: u32, x: u32, y: u32) -> bool {
+        let n = 1usize << z;
+        let i = (y as usize) * n + x as usize;
+        self.levels
+            .get(z as usize)
+            .and_then(|level| level.get(i / 64))
+            .map_or(true, |word| word & (1 << (i % 64)) != 0)
+    }
+
+    /// Check whether a tile may have data
+    pub fn has_data(&self, tid: TileId) -> bool {
+        if tid.z() > self.zoom {
+            let dz = tid.z() - self.zoom;
+            self.bit(self.zoom, tid.x() >> dz, tid.y() >> dz)
+        } else {
+            self.bit(tid.z(), tid.x(), tid.y())
+        }
+    }
+
+    /// Count the tiles which may have data at a zoom level
+    pub fn count(&self, z: u32) -> u64 {
+        self.counts.get(z as usize).copied().unwrap_or_default()
+    }
+
+    /// Estimate the fraction of all data within a tile.
+    ///
+    /// Marked tiles are counted up to `FRACTION_DEPTH` zoom levels below
+    /// the tile, and assumed to hold equal amounts of data.  Beyond the
+    /// maximum zoom, data is assumed to be spread evenly within the marked
+    /// ancestor tile.
+    pub fn fraction(&self, tid: TileId) -> f64 {
+        let z = self.zoom.min(tid.z() + FRACTION_DEPTH);
+        let total = self.count(z);
+        if total == 0 {
+            return 0.0;
+        }
+        if tid.z() >= self.zoom {
+            if !self.has_data(tid) {
+                return 0.0;
+            }
+            let dz = tid.z() - self.zoom;
+            return 0.25_f64.powi(dz as i32) / total as f64;
+        }
+        let dz = z - tid.z();
+        let (x0, y0) = (tid.x() << dz, tid.y() << dz);
+        let span = 1 << dz;
+        let mut marked = 0;
+        for y in y0..y0 + span {
+            for x in x0..x0 + span {
+                if self.bit(z, x, y) {
+                    marked += 1;
+                }
+            }
+        }
+        f64::from(marked) / total as f64
+    }
+
+    /// Read a bitmap file.
+    ///
+    /// Returns `None` if the file is missing, or was made for a different
+    /// zoom level or key (loam files and tile extents).
+    pub fn read(path: &Path, zoom: u32, key: u64) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC
+            || read_u32(&mut reader)? != VERSION
+            || read_u32(&mut reader)? != zoom
+            || read_u64(&mut reader)? != key
+        {
+            return Ok(None);
+        }
+        let mut bitmap = TileBitmap::new(zoom);
+        // sparse: (level, word index, word) for non-zero words
+        for (level, count) in
+            bitmap.levels.iter_mut().zip(bitmap.counts.iter_mut())
+        {
+            let n_words = read_u32(&mut reader)?;
+            for _ in 0..n_words {
+                let i = read_u32(&mut reader)? as usize;
+                let word = read_u64(&mut reader)?;
+                match level.get_mut(i) {
+                    Some(w) => *w = word,
+                    None => return Ok(None),
+                }
+                *count += u64::from(word.count_ones());
+            }
+        }
+        Ok(Some(bitmap))
+    }
+
+    /// Write a bitmap file.
+    ///
+    /// Only non-zero words are written, so sparse data makes small files.
+    pub fn write(&self, path: &Path, key: u64) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.zoom.to_le_bytes())?;
+        writer.write_all(&key.to_le_bytes())?;
+        for level in &self.levels {
+            let n_words = level.iter().filter(|w| **w != 0).count() as u32;
+            writer.write_all(&n_words.to_le_bytes())?;
+            for (i, word) in level.iter().enumerate() {
+                if *word != 0 {
+                    writer.write_all(&(i as u32).to_le_bytes())?;
+                    writer.write_all(&word.to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mvt::MapGrid;
+
+    #[test]
+    fn count_fraction() {
+        let grid = MapGrid::default();
+        let world = grid.bbox();
+        let a = grid.tile_bbox(TileId::new(300, 400, 10).unwrap());
+        let b = grid.tile_bbox(TileId::new(302, 400, 10).unwrap());
+        let mut bitmap = TileBitmap::new(10);
+        for bbox in [a, b, a] {
+            let pt = |fx: f64, fy: f64| {
+                let w = bbox.x_max() - bbox.x_min();
+                (bbox.x_min() + fx * w, bbox.y_min() + fy * w)
+            };
+            let bbox = BBox::new([pt(0.25, 0.25), pt(0.75, 0.75)]);
+            for z in 0..=10 {
+                bitmap.mark(world, z, bbox, 0.0);
+            }
+        }
+        assert_eq!(bitmap.count(0), 1);
+        assert_eq!(bitmap.count(9), 2);
+        assert_eq!(bitmap.count(10), 2);
+        let fraction = |x, y, z| bitmap.fraction(TileId::new(x, y, z).unwrap());
+        assert_eq!(fraction(0, 0, 0), 1.0);
+        assert_eq!(fraction(300, 400, 10), 0.5);
+        assert_eq!(fraction(301, 400, 10), 0.0);
+        assert_eq!(fraction(605, 801, 11), 0.125);
+        assert_eq!(fraction(2400, 3200, 13), 0.5 / 64.0);
+        // file round trip keeps counts
+        let path = std::env::temp_dir()
+            .join(format!("earthwyrm-{}.bitmap", std::process::id()));
+        bitmap.write(&path, 7).unwrap();
+        let read = TileBitmap::read(&path, 10, 7).unwrap().unwrap();
+        assert!(TileBitmap::read(&path, 10, 8).unwrap().is_none());
+        std::fs::remove_file(&path).unwrap();
+        for z in 0..=10 {
+            assert_eq!(read.count(z), bitmap.count(z));
+        }
+        assert_eq!(read.fraction(TileId::new(150, 200, 9).unwrap()), 0.5);
+        assert_eq!(read.fraction(TileId::new(75, 100, 8).unwrap()), 1.0);
+    }
+}
@@ -0,0 +1,394 @@
+// compact.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::layer::{is_area, LayerDef, OsmTags};
+use mvt::GeomType;
+use osmpbfreader::{NodeId, OsmId, OsmObj, RelationId, Tags, WayId};
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+/// Compact OSM object map
+pub type CompactMap = BTreeMap<OsmId, CompactObj>;
+
+/// Pool of interned strings
+#[derive(Default)]
+struct StrPool {
+    /// Interned strings
+    strings: BTreeSet<Rc<str>>,
+}
+
+/// Compact tags, containing only keys referenced by a layer
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompactTags(Vec<(Rc<str>, Rc<str>)>);
+
+/// Compact OSM node
+#[derive(Clone, Debug)]
+pub struct CompactNode {
+    /// Node ID
+    pub id: NodeId,
+
+    /// Latitude (1e-7 degrees)
+    pub decimicro_lat: i32,
+
+    /// Longitude (1e-7 degrees)
+    pub decimicro_lon: i32,
+
+    /// Reduced tags
+    pub tags: CompactTags,
+}
+
+/// Compact OSM way
+#[derive(Clone, Debug)]
+pub struct CompactWay {
+    /// Way ID
+    pub id: WayId,
+
+    /// Node IDs
+    pub nodes: Vec<NodeId>,
+
+    /// Reduced tags
+    pub tags: CompactTags,
+}
+
+/// Compact OSM relation member reference
+#[derive(Clone, Debug)]
+pub struct CompactRef {
+    /// Member object ID
+    pub member: OsmId,
+
+    /// Member role (interned)
+    pub role: Rc<str>,
+}
+
+/// Compact OSM relation
+#[derive(Clone, Debug)]
+pub struct CompactRelation {
+    /// Relation ID
+    pub id: RelationId,
+
+    /// Member references
+    pub refs: Vec<CompactRef>,
+
+    /// Reduced tags
+    pub tags: CompactTags,
+}
+
+/// Dependency objects, stored without tags.
+///
+/// For linestring and polygon layers, node tags are never checked, nor are
+/// tags of some relation member ways.  Only node positions and way node IDs
+/// are needed to make geometry.
+#[derive(Debug, Default)]
+pub struct CompactDeps {
+    /// Node positions (1e-7 degrees latitude / longitude), in ID order
+    nodes: Vec<(NodeId, i32, i32)>,
+
+    /// Node IDs of dependency ways
+    ways: BTreeMap<WayId, Vec<NodeId>>,
+}
+
+/// Compact OSM object, with reduced tags
+#[derive(Clone, Debug)]
+pub enum CompactObj {
+    /// Node object
+    Node(CompactNode),
+
+    /// Way object
+    Way(CompactWay),
+
+    /// Relation object
+    Relation(CompactRelation),
+}
+
+impl StrPool {
+    /// Intern a string
+    fn intern(&mut self, s: &str) -> Rc<str> {
+        match self.strings.get(s) {
+            Some(v) => Rc::clone(v),
+            None => {
+                let v: Rc<str> = Rc::from(s);
+                self.strings.insert(Rc::clone(&v));
+                v
+            }
+        }
+    }
+}
+
+impl OsmTags for CompactTags {
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.0
+            .binary_search_by(|(k, _v)| k.as_ref().cmp(key))
+            .ok()
+            .map(|i| self.0[i].1.as_ref())
+    }
+}
+
+impl CompactTags {
+    /// Get an iterator of tag keys / values
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_ref(), v.as_ref()))
+    }
+
+    /// Check if there are no tags
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl CompactNode {
+    /// Get latitude (degrees)
+    pub fn lat(&self) -> f64 {
+        f64::from(self.decimicro_lat) * 1e-7
+    }
+
+    /// Get longitude (degrees)
+    pub fn lon(&self) -> f64 {
+        f64::from(self.decimicro_lon) * 1e-7
+    }
+}
+
+impl CompactWay {
+    /// Check if the way is closed
+    pub fn is_closed(&self) -> bool {
+        self.nodes.first() == self.nodes.last()
+    }
+
+    /// Check if the way is open
+    pub fn is_open(&self) -> bool {
+        !self.is_closed()
+    }
+}
+
+impl CompactObj {
+    /// Get tags
+    pub fn tags(&self) -> &CompactTags {
+        match self {
+            CompactObj::Node(node) => &node.tags,
+            CompactObj::Way(way) => &way.tags,
+            CompactObj::Relation(rel) => &rel.tags,
+        }
+    }
+
+    /// Get node, if object is a node
+    pub fn node(&self) -> Option<&CompactNode> {
+        match self {
+            CompactObj::Node(node) => Some(node),
+            _ => None,
+        }
+    }
+
+    /// Get way, if object is a way
+    pub fn way(&self) -> Option<&CompactWay> {
+        match self {
+            CompactObj::Way(way) => Some(way),
+            _ => None,
+        }
+    }
+
+    /// Get relation, if object is a relation
+    pub fn relation(&self) -> Option<&CompactRelation> {
+        match self {
+            CompactObj::Relation(rel) => Some(rel),
+            _ => None,
+        }
+    }
+}
+
+impl CompactDeps {
+    /// Get the position of a node (degrees latitude / longitude)
+    pub fn node(&self, id: NodeId) -> Option<(f64, f64)> {
+        let i = self.nodes.binary_search_by_key(&id, |(n, ..)| *n).ok()?;
+        let (_id, lat, lon) = self.nodes[i];
+        Some((f64::from(lat) * 1e-7, f64::from(lon) * 1e-7))
+    }
+
+    /// Get the node IDs of a dependency way
+    pub fn way_nodes(&self, id: WayId) -> Option<&[NodeId]> {
+        self.ways.get(&id).map(Vec::as_slice)
+    }
+
+    /// Get an iterator of nodes (ID, 1e-7 degrees latitude / longitude)
+    pub fn nodes(&self) -> impl Iterator<Item = &(NodeId, i32, i32)> {
+        self.nodes.iter()
+    }
+
+    /// Get an iterator of ways (ID, node IDs)
+    pub fn ways(&self) -> impl Iterator<Item = (&WayId, &Vec<NodeId>)> {
+        self.ways.iter()
+    }
+}
+
+/// Tag compactor for one layer
+struct Compactor<'a> {
+    /// Keys referenced by layer (all keys if `None`)
+    keys: Option<BTreeSet<&'a str>>,
+
+    /// String pool
+    pool: StrPool,
+}
+
+impl<'a> Compactor<'a> {
+    /// Create a compactor for a layer
+    fn new(layer: &'a LayerDef) -> Self {
+        Compactor {
+            keys: Some(layer.referenced_keys().collect()),
+            pool: StrPool::default(),
+        }
+    }
+
+    /// Reduce tags to referenced keys
+    fn tags(&mut self, tags: &Tags) -> CompactTags {
+        let mut compact: Vec<_> = tags
+            .iter()
+            .filter(|(k, _v)| {
+                self.keys
+                    .as_ref()
+                    .is_none_or(|keys| keys.contains(k.as_str()))
+            })
+            .map(|(k, v)| (self.pool.intern(k), self.pool.intern(v)))
+            .collect();
+        compact.sort();
+        CompactTags(compact)
+    }
+
+    /// Compact an OSM object
+    fn obj(&mut self, obj: OsmObj) -> CompactObj {
+        match obj {
+            OsmObj::Node(node) => CompactObj::Node(CompactNode {
+                id: node.id,
+                decimicro_lat: node.decimicro_lat,
+                decimicro_lon: node.decimicro_lon,
+                tags: self.tags(&node.tags),
+            }),
+            OsmObj::Way(way) => CompactObj::Way(CompactWay {
+                id: way.id,
+                tags: self.tags(&way.tags),
+                nodes: way.nodes,
+            }),
+            OsmObj::Relation(rel) => CompactObj::Relation(CompactRelation {
+                id: rel.id,
+                tags: self.tags(&rel.tags),
+                refs: rel
+                    .refs
+                    .iter()
+                    .map(|rf| CompactRef {
+                        member: rf.member,
+                        role: self.pool.intern(&rf.role),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+}
+
+/// Compact extracted objects, keeping only tags referenced by a layer.
+///
+/// For linestring and polygon layers, nodes and member ways whose tags are
+/// never checked are moved into dependencies, without tags.
+pub fn compact_objs(
+    layer: &LayerDef,
+    mut objs: BTreeMap<OsmId, OsmObj>,
+) -> (CompactMap, CompactDeps) {
+    let mut compactor = Compactor::new(layer);
+    let mut deps = CompactDeps::default();
+    if layer.geom_tp() == GeomType::Point {
+        let objs = objs
+            .into_iter()
+            .map(|(id, obj)| (id, compactor.obj(obj)))
+            .collect();
+        return (objs, deps);
+    }
+    // relations sort last; compact them first to find tagged member ways
+    let rels = objs.split_off(&OsmId::Relation(RelationId(i64::MIN)));
+    let mut compact: CompactMap = rels
+        .into_iter()
+        .map(|(id, obj)| (id, compactor.obj(obj)))
+        .collect();
+    let members = relation_members(layer, &compact);
+    for (id, obj) in objs {
+        match obj {
+            OsmObj::Node(node) => deps.nodes.push((
+                node.id,
+                node.decimicro_lat,
+                node.decimicro_lon,
+            )),
+            obj => match compactor.obj(obj) {
+                CompactObj::Way(way) if !needs_tags(layer, &way, &members) => {
+                    deps.ways.insert(way.id, way.nodes);
+                }
+                obj => {
+                    compact.insert(id, obj);
+                }
+            },
+        }
+    }
+    (compact, deps)
+}
+
+/// Convert extracted objects without compacting.
+///
+/// All tags are kept, and there are no dependencies; geometry made from
+/// these must be identical to [compact_objs].
+#[cfg(test)]
+pub fn uncompacted_objs(
+    objs: BTreeMap<OsmId, OsmObj>,
+) -> (CompactMap, CompactDeps) {
+    let mut compactor = Compactor {
+        keys: None,
+        pool: StrPool::default(),
+    };
+    let objs = objs
+        .into_iter()
+        .map(|(id, obj)| (id, compactor.obj(obj)))
+        .collect();
+    (objs, CompactDeps::default())
+}
+
+/// Get relation member ways which need special handling.
+///
+/// For linestring layers, these are members of matching routes; for
+/// polygon layers, outer members of old-style multipolygons.
+fn relation_members(layer: &LayerDef, objs: &CompactMap) -> BTreeSet<WayId> {
+    let linestring = layer.geom_tp() == GeomType::Linestring;
+    let mut ways = BTreeSet::new();
+    for rel in objs.values().filter_map(CompactObj::relation) {
+        let tp = rel.tags.tag("type");
+        let route = tp == Some("route") && layer.check_tags(&rel.tags);
+        let old_style = tp == Some("multipolygon")
+            && rel.tags.iter().all(|(k, _v)| k == "type");
+        for rf in &rel.refs {
+            let OsmId::Way(id) = rf.member else {
+                continue;
+            };
+            let special = if linestring {
+                route
+            } else {
+                old_style && &*rf.role == "outer"
+            };
+            if special {
+                ways.insert(id);
+            }
+        }
+    }
+    ways
+}
+
+/// Check if tags of a way are needed to make linestring or polygon geometry
+fn needs_tags(
+    layer: &LayerDef,
+    way: &CompactWay,
+    members: &BTreeSet<WayId>,
+) -> bool {
+    let matched = layer.check_tags(&way.tags);
+    if layer.geom_tp() == GeomType::Linestring {
+        // areas and unmatched route members are not made into linestrings
+        !is_area(&way.tags, &[]) && (matched || !members.contains(&way.id))
+    } else {
+        // closed areas can be made into polygons, and outer way tags are
+        // used by old-style multipolygons
+        matched
+            || (way.is_closed() && layer.check_area(&way.tags))
+            || members.contains(&way.id)
+    }
+}
@@ -2,23 +2,244 @@
 //
 // Copyright (c) 2019-2024  Minnesota Department of Transportation
 //
-use crate::error::Result;
-use serde_derive::Deserialize;
+use crate::error::{Error, Result};
+use crate::layer::{
+    check_layer_cfg, expand_layer_cfg, parse_zoom_range, SYNTHETIC_TAGS,
+};
+use crate::schema::{config_schema, ConfigSchema};
+use serde_derive::{Deserialize, Serialize};
 use std::fmt;
-use std::fs::read_to_string;
+use std::fs::{read_to_string, DirEntry};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Default practical maximum zoom level
+const PRACTICAL_ZOOM_MAX: u32 = 22;
 
 /// Configuration for Earthwyrm tile layers.
 #[derive(Debug, Deserialize)]
 pub struct WyrmCfg {
-    /// Address to bind server
-    pub bind_address: String,
+    /// Addresses to bind server (TCP or `unix:` socket path)
+    pub bind_address: Vec<String>,
+
+    /// Permission mode for unix sockets (octal)
+    #[serde(default)]
+    pub socket_mode: Option<String>,
 
     /// Tile extent; width and height
     pub tile_extent: u32,
 
+    /// Edge extent; buffer around tiles in pixels (default: by zoom level)
+    #[serde(default)]
+    pub edge_extent: Option<u32>,
+
+    /// Response for empty tiles
+    #[serde(default)]
+    pub empty_tile: EmptyTile,
+
+    /// Log output format
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    /// Maximum features queried per layer (default 1000000)
+    #[serde(default)]
+    pub query_limit: Option<usize>,
+
+    /// Estimated tile size (bytes) above which responses are streamed
+    /// (default 1 MiB)
+    #[serde(default)]
+    pub stream_threshold: Option<usize>,
+
+    /// Maximum linestring span, in z10 tiles; wider ways are split at dig
+    /// time (default 4)
+    #[serde(default)]
+    pub max_line_span: Option<f64>,
+
+    /// Maximum tag value length, in bytes (default 256)
+    #[serde(default)]
+    pub max_value_len: Option<usize>,
+
+    /// Maximum points in a batch query request (default 10000)
+    #[serde(default)]
+    pub max_batch_points: Option<usize>,
+
+    /// Highest zoom level requested by clients, for warnings (default 22)
+    #[serde(default)]
+    pub practical_zoom_max: Option<u32>,
+
+    /// Directory of SRTM `.hgt` files for elevation sampling
+    #[serde(default)]
+    pub dem_dir: Option<String>,
+
+    /// S3-compatible endpoint URL for seeding tiles
+    #[serde(default)]
+    pub s3_endpoint: Option<String>,
+
+    /// S3 region name
+    #[serde(default)]
+    pub s3_region: Option<String>,
+
+    /// Free space needed to dig, as a multiple of OSM file size
+    #[serde(default)]
+    pub dig_space_factor: Option<f64>,
+
+    /// File to log OSM objects skipped during a dig
+    #[serde(default)]
+    pub skip_log: Option<PathBuf>,
+
+    /// OSM files to dig (default: newest in `osm` directory)
+    #[serde(default)]
+    pub osm_files: Vec<PathBuf>,
+
+    /// Policy for OSM IDs found in more than one file
+    #[serde(default)]
+    pub osm_id_policy: IdPolicy,
+
+    /// Reorder match patterns by selectivity while digging (default true)
+    #[serde(default)]
+    pub reorder_patterns: Option<bool>,
+
+    /// Allow group and layer names with any characters (default false)
+    #[serde(default)]
+    pub allow_any_names: Option<bool>,
+
+    /// HTTP server options
+    #[serde(default)]
+    pub http: HttpCfg,
+
     /// Configuration for all layer groups
     pub layer_group: Vec<LayerGroupCfg>,
+
+    /// Loam directory (not configured; default `loam`)
+    #[serde(skip)]
+    loam_dir: Option<PathBuf>,
+
+    /// Object cache directory (not configured; default `cache`)
+    #[serde(skip)]
+    cache_dir: Option<PathBuf>,
+
+    /// Check full loam file hashes against the manifest (not configured)
+    #[serde(skip)]
+    full_check: bool,
+}
+
+/// HTTP server configuration
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct HttpCfg {
+    /// Static headers for tile responses (`Name: value`)
+    #[serde(default)]
+    pub header: Vec<String>,
+
+    /// Log method, path, status, duration and bytes of each request
+    #[serde(default)]
+    pub access_log: bool,
+
+    /// Serve GeoJSON tiles (`group/z/x/y.json`)
+    #[serde(default)]
+    pub geojson_tiles: bool,
+
+    /// Public base URL for TileJSON tile URLs (ex. `https://example.com/maps`)
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+/// Layer style for the leaflet demo map
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct LayerStyleCfg {
+    /// Stroke color
+    #[serde(default)]
+    pub color: Option<String>,
+
+    /// Stroke opacity (0 to 1)
+    #[serde(default)]
+    pub opacity: Option<f64>,
+
+    /// Stroke width (pixels)
+    #[serde(default)]
+    pub weight: Option<f64>,
+
+    /// Fill color (polygons and points)
+    #[serde(default)]
+    pub fill: Option<String>,
+
+    /// Fill opacity (0 to 1)
+    #[serde(default)]
+    pub fill_opacity: Option<f64>,
+
+    /// Minimum zoom to display (overrides layer zoom range)
+    #[serde(default)]
+    pub minzoom: Option<u32>,
+}
+
+/// Server bind address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BindAddr {
+    /// TCP socket address
+    Tcp(SocketAddr),
+
+    /// Unix domain socket path
+    Unix(PathBuf),
+}
+
+/// Response for empty tiles
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyTile {
+    /// Respond with `404 Not Found`
+    #[default]
+    NotFound,
+
+    /// Respond with `204 No Content`
+    NoContent,
+
+    /// Respond with a valid MVT containing no features
+    Blank,
+}
+
+/// Policy for OSM IDs found in more than one input file.
+///
+/// Objects which are identical in both files are never collisions.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdPolicy {
+    /// Object from the last file replaces earlier ones
+    #[default]
+    LastWins,
+
+    /// Collisions are an error
+    Error,
+
+    /// Colliding objects are given IDs offset by input file index
+    Namespace,
+}
+
+/// Tile grid of a layer group
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TileGrid {
+    /// Web Mercator (EPSG:3857), one root tile
+    #[default]
+    WebMercator,
+
+    /// WGS84 (EPSG:4326), two root tiles
+    Wgs84,
+}
+
+/// Log output format
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Plain log lines (`env_logger`)
+    #[default]
+    Log,
+
+    /// Structured text, with tracing spans
+    Text,
+
+    /// Structured JSON, with tracing spans
+    Json,
 }
 
 /// Layer Group configuration
@@ -30,12 +251,64 @@ pub struct LayerGroupCfg {
     /// OpenStreetMap data source
     pub osm: bool,
 
+    /// Include `debug` layer with tile outline
+    #[serde(default)]
+    pub debug_outline: bool,
+
+    /// Preferred name languages, in fallback order (ex. `es en`)
+    #[serde(default)]
+    pub name_languages: Vec<String>,
+
+    /// Tile URL version (`mtime` to derive from loam files)
+    #[serde(default)]
+    pub url_version: Option<String>,
+
+    /// Include layers with no features in tiles (within each layer's zoom
+    /// range).  Tiles with no features in any layer are still empty.
+    #[serde(default)]
+    pub keep_empty_layers: bool,
+
+    /// Directory of pre-rendered tiles (`z/x/y.mvt`), served when present
+    #[serde(default)]
+    pub static_tiles: Option<String>,
+
+    /// Zoom levels to overzoom missing static tiles from a parent tile
+    /// (default 0)
+    #[serde(default)]
+    pub static_overzoom: Option<u32>,
+
+    /// Data attribution (HTML)
+    #[serde(default)]
+    pub attribution: Option<String>,
+
+    /// Data license
+    #[serde(default)]
+    pub license: Option<String>,
+
+    /// Tile size budget (bytes); larger tiles are degraded to fit
+    #[serde(default)]
+    pub max_tile_bytes: Option<usize>,
+
+    /// Maximum zoom of tile bitmap, to skip queries for empty tiles
+    /// (default 10)
+    #[serde(default)]
+    pub tile_bitmap_zoom: Option<u32>,
+
+    /// Time budget for making a tile (ms); features are no longer added
+    /// after it passes
+    #[serde(default)]
+    pub tile_deadline_ms: Option<u64>,
+
+    /// Tile grid (`web_mercator` or `wgs84`)
+    #[serde(default)]
+    pub grid: TileGrid,
+
     /// Layers in group
     pub layer: Vec<LayerCfg>,
 }
 
 /// Layer configuration
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct LayerCfg {
     /// Layer name
     pub name: String,
@@ -48,6 +321,594 @@ pub struct LayerCfg {
 
     /// Tag patterns
     pub tags: Vec<String>,
+
+    /// Layer order within tiles (ties in config order)
+    #[serde(default)]
+    pub order: i32,
+
+    /// Include all closed ways in polygon layer (ignoring area tags)
+    #[serde(default)]
+    pub force_polygon: bool,
+
+    /// Tag keys which imply closed ways are areas (overrides default list)
+    #[serde(default)]
+    pub area_keys: Vec<String>,
+
+    /// Relation member role for point layers (`label` or `admin_centre`)
+    #[serde(default)]
+    pub member_role: Option<String>,
+
+    /// Drop features with no included values (other than `osm_id`)
+    #[serde(default)]
+    pub drop_empty: bool,
+
+    /// Salvage incomplete relation polygons by closing unconnected chains
+    #[serde(default)]
+    pub allow_partial_polygons: bool,
+
+    /// Skip member ways of relation polygons with the same values as the
+    /// relation (default `true`)
+    #[serde(default)]
+    pub dedupe_members: Option<bool>,
+
+    /// Derived point features (`intersections`)
+    #[serde(default)]
+    pub derive: Option<String>,
+
+    /// Source linestring layer name for derived features, or `generated`
+    #[serde(default)]
+    pub source: Option<String>,
+
+    /// Shape of generated features (`extent`)
+    #[serde(default)]
+    pub shape: Option<String>,
+
+    /// Bounds of generated `extent` (`west south east north`, in degrees;
+    /// default OSM file bounds)
+    #[serde(default)]
+    pub extent: Option<String>,
+
+    /// Include dead-end nodes in derived `intersections`
+    #[serde(default)]
+    pub include_ends: bool,
+
+    /// Merge linestrings with equal tag values into one feature per tile
+    #[serde(default)]
+    pub merge_lines: bool,
+
+    /// Skip objects matching an earlier layer in the group
+    #[serde(default)]
+    pub exclude_if_matches: Option<String>,
+
+    /// Sint tag for ranking point features into zoom buckets
+    #[serde(default)]
+    pub rank_by: Option<String>,
+
+    /// Zoom buckets (`zoom=min_rank`, with only `zoom` for the last)
+    #[serde(default)]
+    pub rank_zoom: Vec<String>,
+
+    /// Maximum features queried (overrides global `query_limit`)
+    #[serde(default)]
+    pub query_limit: Option<usize>,
+
+    /// Maximum relation members (larger relations are skipped)
+    #[serde(default)]
+    pub max_relation_members: Option<usize>,
+
+    /// Maximum nodes in relation member ways (larger relations are skipped)
+    #[serde(default)]
+    pub max_ring_nodes: Option<usize>,
+
+    /// Maximum nodes per linestring (longer ways are split)
+    #[serde(default)]
+    pub max_geometry_nodes: Option<usize>,
+
+    /// Maximum linestring span, in z10 tiles (overrides global
+    /// `max_line_span`)
+    #[serde(default)]
+    pub max_line_span: Option<f64>,
+
+    /// Maximum tag value length, in bytes (overrides global
+    /// `max_value_len`)
+    #[serde(default)]
+    pub max_value_len: Option<usize>,
+
+    /// Data attribution (HTML)
+    #[serde(default)]
+    pub attribution: Option<String>,
+
+    /// Data license
+    #[serde(default)]
+    pub license: Option<String>,
+
+    /// Style for the leaflet demo map
+    #[serde(default)]
+    pub style: Option<LayerStyleCfg>,
+
+    /// Build an OSM ID index for feature lookups (requires `osm_id` tag)
+    #[serde(default)]
+    pub id_index: bool,
+
+    /// Add a `minzoom` tag with the feature's minimum zoom level
+    #[serde(default)]
+    pub emit_minzoom: bool,
+
+    /// Geometry storage precision (`f32` or `f64`; default `f64`)
+    #[serde(default)]
+    pub precision: Option<String>,
+
+    /// Name of layer whose loam file is shared (not dug for this layer)
+    #[serde(default)]
+    pub loam: Option<String>,
+
+    /// Tag patterns to filter stored features at serve time
+    /// (ex. `amenity=school`); tags must be included
+    #[serde(default)]
+    pub filter: Vec<String>,
+
+    /// Constant tags added to every feature (ex. `region=mn`).
+    ///
+    /// Values may contain `${ENV_VAR}`, substituted at config load.
+    #[serde(default)]
+    pub constants: Vec<String>,
+
+    /// Split into one layer per tag value (ex. `admin_level=4|6|8`).
+    ///
+    /// Expanded by [WyrmCfg::expand_layers] into layers named
+    /// `<name>_<value>`.
+    #[serde(default)]
+    pub split_by: Option<String>,
+}
+
+/// Fields of `WyrmCfg`: (name, type, description)
+pub(crate) const WYRM_FIELDS: &[(&str, &str, &str)] = &[
+    (
+        "bind_address",
+        "list of strings",
+        "Addresses to bind server (TCP or `unix:` socket path)",
+    ),
+    ("socket_mode", "string", "Permission mode for unix sockets (octal)"),
+    ("tile_extent", "integer", "Tile extent; width and height"),
+    (
+        "edge_extent",
+        "integer",
+        "Edge extent; buffer around tiles in pixels (default: by zoom level)",
+    ),
+    ("empty_tile", "enum", "Response for empty tiles"),
+    ("log_format", "enum", "Log output format"),
+    (
+        "query_limit",
+        "integer",
+        "Maximum features queried per layer (default 1000000)",
+    ),
+    (
+        "stream_threshold",
+        "integer",
+        "Estimated tile size (bytes) above which responses are streamed \
+        (default 1 MiB)",
+    ),
+    (
+        "max_line_span",
+        "number",
+        "Maximum linestring span, in z10 tiles (default 4)",
+    ),
+    (
+        "max_value_len",
+        "integer",
+        "Maximum tag value length, in bytes (default 256)",
+    ),
+    (
+        "max_batch_points",
+        "integer",
+        "Maximum points in a batch query request (default 10000)",
+    ),
+    (
+        "practical_zoom_max",
+        "integer",
+        "Highest zoom level requested by clients, for warnings (default 22)",
+    ),
+    (
+        "dem_dir",
+        "string",
+        "Directory of SRTM `.hgt` files for elevation sampling",
+    ),
+    ("s3_endpoint", "string", "S3-compatible endpoint URL for seeding tiles"),
+    ("s3_region", "string", "S3 region name"),
+    (
+        "dig_space_factor",
+        "number",
+        "Free space needed to dig, as a multiple of OSM file size",
+    ),
+    ("skip_log", "path", "File to log OSM objects skipped during a dig"),
+    (
+        "osm_files",
+        "list of paths",
+        "OSM files to dig (default: newest in `osm` directory)",
+    ),
+    ("osm_id_policy", "enum", "Policy for OSM IDs found in more than one file"),
+    (
+        "reorder_patterns",
+        "bool",
+        "Reorder match patterns by selectivity while digging (default true)",
+    ),
+    (
+        "allow_any_names",
+        "bool",
+        "Allow group and layer names with any characters (default false)",
+    ),
+    ("http", "table", "HTTP server options"),
+    ("layer_group", "list of tables", "Configuration for all layer groups"),
+];
+
+/// Fields of `HttpCfg`: (name, type, description)
+pub(crate) const HTTP_FIELDS: &[(&str, &str, &str)] = &[
+    (
+        "header",
+        "list of strings",
+        "Static headers for tile responses (`Name: value`)",
+    ),
+    (
+        "access_log",
+        "bool",
+        "Log method, path, status, duration and bytes of each request",
+    ),
+    ("geojson_tiles", "bool", "Serve GeoJSON tiles (`group/z/x/y.json`)"),
+    (
+        "public_url",
+        "string",
+        "Public base URL for TileJSON tile URLs (ex. `https://example.com/maps`)",
+    ),
+];
+
+/// Fields of `LayerGroupCfg`: (name, type, description)
+pub(crate) const GROUP_FIELDS: &[(&str, &str, &str)] = &[
+    ("name", "string", "Layer group name"),
+    ("osm", "bool", "OpenStreetMap data source"),
+    ("debug_outline", "bool", "Include `debug` layer with tile outline"),
+    (
+        "name_languages",
+        "list of strings",
+        "Preferred name languages, in fallback order (ex. `es en`)",
+    ),
+    (
+        "url_version",
+        "string",
+        "Tile URL version (`mtime` to derive from loam files)",
+    ),
+    ("keep_empty_layers", "bool", "Include layers with no features in tiles"),
+    (
+        "static_tiles",
+        "string",
+        "Directory of pre-rendered tiles (`z/x/y.mvt`), served when present",
+    ),
+    (
+        "static_overzoom",
+        "integer",
+        "Zoom levels to overzoom missing static tiles from a parent tile (default 0)",
+    ),
+    ("attribution", "string", "Data attribution (HTML)"),
+    ("license", "string", "Data license"),
+    (
+        "max_tile_bytes",
+        "integer",
+        "Tile size budget (bytes); larger tiles are degraded to fit",
+    ),
+    (
+        "tile_bitmap_zoom",
+        "integer",
+        "Maximum zoom of tile bitmap, to skip queries for empty tiles",
+    ),
+    (
+        "tile_deadline_ms",
+        "integer",
+        "Time budget for making a tile (ms)",
+    ),
+    ("grid", "enum", "Tile grid (`web_mercator` or `wgs84`)"),
+    ("layer", "list of tables", "Layers in group"),
+];
+
+/// Fields of `LayerCfg`: (name, type, description)
+pub(crate) const LAYER_FIELDS: &[(&str, &str, &str)] = &[
+    ("name", "string", "Layer name"),
+    (
+        "geom_type",
+        "string",
+        "Type for geometry (`point`, `linestring` or `polygon`)",
+    ),
+    ("zoom", "string", "Zoom range"),
+    ("tags", "list of strings", "Tag patterns"),
+    ("order", "integer", "Layer order within tiles (ties in config order)"),
+    (
+        "force_polygon",
+        "bool",
+        "Include all closed ways in polygon layer (ignoring area tags)",
+    ),
+    (
+        "area_keys",
+        "list of strings",
+        "Tag keys which imply closed ways are areas (overrides default list)",
+    ),
+    (
+        "member_role",
+        "string",
+        "Relation member role for point layers (`label` or `admin_centre`)",
+    ),
+    (
+        "drop_empty",
+        "bool",
+        "Drop features with no included values (other than `osm_id`)",
+    ),
+    (
+        "allow_partial_polygons",
+        "bool",
+        "Salvage incomplete relation polygons by closing unconnected chains",
+    ),
+    (
+        "dedupe_members",
+        "bool",
+        "Skip member ways with relation polygon values (default `true`)",
+    ),
+    ("derive", "string", "Derived point features (`intersections`)"),
+    (
+        "source",
+        "string",
+        "Source linestring layer name for derived features, or `generated`",
+    ),
+    ("shape", "enum", "Shape of generated features (`extent`)"),
+    (
+        "extent",
+        "string",
+        "Bounds of generated `extent` (`west south east north`, in degrees)",
+    ),
+    (
+        "include_ends",
+        "bool",
+        "Include dead-end nodes in derived `intersections`",
+    ),
+    (
+        "merge_lines",
+        "bool",
+        "Merge linestrings with equal tag values into one feature per tile",
+    ),
+    (
+        "exclude_if_matches",
+        "string",
+        "Skip objects matching an earlier layer in the group",
+    ),
+    (
+        "rank_by",
+        "string",
+        "Sint tag for ranking point features into zoom buckets",
+    ),
+    (
+        "rank_zoom",
+        "list of strings",
+        "Zoom buckets (`zoom=min_rank`, with only `zoom` for the last)",
+    ),
+    (
+        "query_limit",
+        "integer",
+        "Maximum features queried (overrides global `query_limit`)",
+    ),
+    (
+        "max_relation_members",
+        "integer",
+        "Maximum relation members (larger relations are skipped)",
+    ),
+    (
+        "max_ring_nodes",
+        "integer",
+        "Maximum nodes in relation member ways (larger relations are skipped)",
+    ),
+    (
+        "max_geometry_nodes",
+        "integer",
+        "Maximum nodes per linestring (longer ways are split)",
+    ),
+    (
+        "max_line_span",
+        "number",
+        "Maximum linestring span, in z10 tiles (overrides global `max_line_span`)",
+    ),
+    (
+        "max_value_len",
+        "integer",
+        "Maximum tag value length, in bytes (overrides global `max_value_len`)",
+    ),
+    ("attribution", "string", "Data attribution (HTML)"),
+    ("license", "string", "Data license"),
+    ("style", "table", "Style for the leaflet demo map"),
+    (
+        "id_index",
+        "bool",
+        "Build an OSM ID index for feature lookups (requires `osm_id` tag)",
+    ),
+    (
+        "emit_minzoom",
+        "bool",
+        "Add a `minzoom` tag with the feature's minimum zoom level",
+    ),
+    (
+        "precision",
+        "enum",
+        "Geometry storage precision (`f32` or `f64`; default `f64`)",
+    ),
+    (
+        "loam",
+        "string",
+        "Name of layer whose loam file is shared (not dug for this layer)",
+    ),
+    (
+        "filter",
+        "list of strings",
+        "Tag patterns to filter stored features at serve time",
+    ),
+    (
+        "constants",
+        "list of strings",
+        "Constant tags added to every feature (`tag=value`)",
+    ),
+    (
+        "split_by",
+        "string",
+        "Split into one layer per tag value (ex. `admin_level=4|6|8`)",
+    ),
+];
+
+/// Fields of `LayerStyleCfg`: (name, type, description)
+pub(crate) const STYLE_FIELDS: &[(&str, &str, &str)] = &[
+    ("color", "string", "Stroke color"),
+    ("opacity", "number", "Stroke opacity (0 to 1)"),
+    ("weight", "number", "Stroke width (pixels)"),
+    ("fill", "string", "Fill color (polygons and points)"),
+    ("fill_opacity", "number", "Fill opacity (0 to 1)"),
+    (
+        "minzoom",
+        "integer",
+        "Minimum zoom to display (overrides layer zoom range)",
+    ),
+];
+
+/// Values of `empty_tile`
+pub(crate) const EMPTY_TILE_VALUES: &[&str] =
+    &["not_found", "no_content", "blank"];
+
+/// Values of `log_format`
+pub(crate) const LOG_FORMAT_VALUES: &[&str] = &["log", "text", "json"];
+
+/// Values of `osm_id_policy`
+pub(crate) const ID_POLICY_VALUES: &[&str] =
+    &["last_wins", "error", "namespace"];
+
+/// Values of `grid`
+pub(crate) const GRID_VALUES: &[&str] = &["web_mercator", "wgs84"];
+
+/// Configuration problem, located by group, layer and field
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ConfigIssue {
+    /// Layer group name
+    pub group: Option<String>,
+
+    /// Layer name
+    pub layer: Option<String>,
+
+    /// Configuration field
+    pub field: Option<String>,
+
+    /// Problem description
+    pub message: String,
+}
+
+/// Report of a configuration check
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigReport {
+    /// Configuration is valid (no errors)
+    pub valid: bool,
+
+    /// All errors found
+    pub errors: Vec<ConfigIssue>,
+
+    /// All warnings found
+    pub warnings: Vec<ConfigIssue>,
+}
+
+/// Check if a directory entry is an OSM file (`.pbf`, or `.osm` and
+/// `.osm.bz2` with the `xml` feature)
+fn is_osm_file(entry: &DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy().to_lowercase();
+    let xml = cfg!(feature = "xml")
+        && (name.ends_with(".osm") || name.ends_with(".osm.bz2"));
+    // skip hidden and partial downloads (ex. `.region.pbf.part`)
+    (name.ends_with(".pbf") || xml)
+        && !name.starts_with('.')
+        && entry.file_type().is_ok_and(|ft| ft.is_file())
+}
+
+/// Find the newest OSM file in a directory
+fn newest_osm(dir: &Path) -> Result<PathBuf> {
+    let mut names = Vec::new();
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    let entries = dir
+        .read_dir()
+        .map_err(|e| Error::NoOsmFile(format!("{dir:?}: {e}")))?;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::warn!("{dir:?}: {err}");
+                continue;
+            }
+        };
+        names.push(entry.file_name().to_string_lossy().into_owned());
+        if !is_osm_file(&entry) {
+            continue;
+        }
+        let modified = match entry.metadata().and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                log::warn!("{:?}: {err}", entry.path());
+                continue;
+            }
+        };
+        match &newest {
+            Some((m, _)) if *m >= modified => (),
+            _ => newest = Some((modified, entry.path())),
+        }
+    }
+    match newest {
+        Some((_, path)) => Ok(path),
+        None => {
+            names.sort();
+            Err(Error::NoOsmFile(format!(
+                "{dir:?} contains [{}]",
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+/// Check that an excluded layer is earlier in the group
+fn check_exclude(earlier: &[LayerCfg], layer: &LayerCfg) -> Result<()> {
+    match &layer.exclude_if_matches {
+        Some(name) if !earlier.iter().any(|l| &l.name == name) => {
+            Err(Error::InvalidExclude(format!(
+                "'{name}' must be an earlier layer in the group"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that a shared loam layer exists and is compatible
+fn check_loam(cfg: &WyrmCfg, layer: &LayerCfg) -> Result<()> {
+    let Some(name) = &layer.loam else {
+        return Ok(());
+    };
+    let base = cfg
+        .layer_group
+        .iter()
+        .flat_map(|g| &g.layer)
+        .find(|l| &l.name == name)
+        .ok_or_else(|| Error::InvalidLoam(format!("{name}: unknown layer")))?;
+    if base.loam.is_some() {
+        return Err(Error::InvalidLoam(format!("{name}: also shares loam")));
+    }
+    if base.geom_type != layer.geom_type {
+        return Err(Error::InvalidLoam(format!(
+            "{name}: geom_type is {}",
+            base.geom_type
+        )));
+    }
+    if base.rank_by.is_some() || layer.rank_by.is_some() {
+        return Err(Error::InvalidLoam(format!("{name}: rank_by not allowed")));
+    }
+    if layer.derive.is_some() || layer.split_by.is_some() {
+        return Err(Error::InvalidLoam(format!(
+            "{name}: derive / split_by not allowed"
+        )));
+    }
+    Ok(())
 }
 
 impl fmt::Display for LayerGroupCfg {
@@ -60,20 +921,772 @@ impl fmt::Display for LayerGroupCfg {
     }
 }
 
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(group) = &self.group {
+            write!(f, "group '{group}', ")?;
+        }
+        if let Some(layer) = &self.layer {
+            write!(f, "layer '{layer}', ")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, "{field}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<&Error> for ConfigIssue {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::LayerConfig {
+                group,
+                layer,
+                field,
+                source,
+            } => ConfigIssue {
+                group: Some(group.clone()),
+                layer: Some(layer.clone()),
+                field: Some(field.to_string()),
+                message: source.to_string(),
+            },
+            Error::InvalidHeader(_) => ConfigIssue {
+                field: Some("http.header".into()),
+                message: err.to_string(),
+                ..Default::default()
+            },
+            _ => ConfigIssue {
+                message: err.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl TryFrom<&str> for BindAddr {
+    type Error = Error;
+
+    fn try_from(addr: &str) -> Result<Self> {
+        match addr.strip_prefix("unix:") {
+            Some(path) => Ok(BindAddr::Unix(PathBuf::from(path))),
+            None => Ok(BindAddr::Tcp(addr.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindAddr::Tcp(addr) => write!(f, "{addr}"),
+            BindAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for IdPolicy {
+    type Err = Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "last_wins" => Ok(IdPolicy::LastWins),
+            "error" => Ok(IdPolicy::Error),
+            "namespace" => Ok(IdPolicy::Namespace),
+            _ => Err(Error::InvalidIdPolicy(policy.into())),
+        }
+    }
+}
+
+impl HttpCfg {
+    /// Parse and validate response headers (name, value)
+    pub fn headers(&self) -> Result<Vec<(&str, &str)>> {
+        self.header
+            .iter()
+            .map(|h| {
+                parse_header(h).ok_or_else(|| Error::InvalidHeader(h.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Check if a name has uppercase characters
+fn has_uppercase(name: &str) -> bool {
+    name.chars().any(|c| c.is_ascii_uppercase())
+}
+
+/// Check a group or layer name.
+///
+/// Names appear in URLs and loam file names, so they are limited to `a-z`,
+/// `0-9`, `_` and `-`.  Uppercase is still allowed (with a warning).
+fn check_name(kind: &'static str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName {
+            kind,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Check the name of a layer, and layer names made by `split_by`
+fn check_layer_names(layer: &LayerCfg) -> Result<()> {
+    check_name("layer", &layer.name)?;
+    // split values are checked by `check_layer_cfg`
+    if let Ok(layers) = expand_layer_cfg(layer) {
+        for split in layers {
+            check_name("layer", &split.name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse a response header (`Name: value`), checking name and value
+fn parse_header(header: &str) -> Option<(&str, &str)> {
+    let (name, value) = header.split_once(':')?;
+    let value = value.trim();
+    let token =
+        |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    let visible = |b: u8| b == b'\t' || (b' '..=b'~').contains(&b);
+    (!name.is_empty() && name.bytes().all(token) && value.bytes().all(visible))
+        .then_some((name, value))
+}
+
 impl WyrmCfg {
+    /// Parse and validate bind addresses
+    pub fn bind_addrs(&self) -> Result<Vec<BindAddr>> {
+        self.bind_address
+            .iter()
+            .map(|addr| BindAddr::try_from(addr.as_str()))
+            .collect()
+    }
+
+    /// Parse permission mode for unix sockets
+    pub fn socket_mode(&self) -> Result<Option<u32>> {
+        match &self.socket_mode {
+            Some(mode) => Ok(Some(u32::from_str_radix(mode, 8)?)),
+            None => Ok(None),
+        }
+    }
+
     /// Read the configuration file
     pub fn load() -> Result<Self> {
-        let path = Path::new("earthwyrm.muon");
+        Self::load_path(Path::new("earthwyrm.muon"))
+    }
+
+    /// Read a configuration file from a path.
+    ///
+    /// The loam directory is relative to the file's directory, instead of
+    /// the current directory.
+    pub fn load_path(path: &Path) -> Result<Self> {
+        let cfg = read_to_string(path)?;
+        let mut cfg: Self = muon_rs::from_str(&cfg)?;
+        cfg.validate()?;
+        cfg.expand_layers()?;
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => {
+                Ok(cfg.with_loam_dir(dir.join("loam")))
+            }
+            _ => Ok(cfg),
+        }
+    }
+
+    /// Expand layers with `split_by` into one layer per split value.
+    ///
+    /// Called by [load](Self::load); layers without `split_by` are kept.
+    pub fn expand_layers(&mut self) -> Result<()> {
+        for group in &mut self.layer_group {
+            let mut layers = Vec::with_capacity(group.layer.len());
+            for layer in &group.layer {
+                layers.extend(expand_layer_cfg(layer)?);
+            }
+            group.layer = layers;
+        }
+        Ok(())
+    }
+
+    /// Describe all accepted configuration fields and tag patterns
+    pub fn schema() -> ConfigSchema {
+        config_schema()
+    }
+
+    /// Read and check a configuration file, without loading it.
+    ///
+    /// Only unreadable files (I/O or syntax errors) are returned as errors;
+    /// invalid configurations are described by the report.
+    pub fn check_path(path: &Path) -> Result<ConfigReport> {
         let cfg = read_to_string(path)?;
         let cfg: Self = muon_rs::from_str(&cfg)?;
-        Ok(cfg)
+        Ok(cfg.check())
+    }
+
+    /// Check the configuration, reporting all errors and warnings
+    pub fn check(&self) -> ConfigReport {
+        let errors: Vec<_> =
+            self.config_errors().iter().map(ConfigIssue::from).collect();
+        let warnings = if errors.is_empty() {
+            let mut warnings = self.zoom_issues();
+            warnings.extend(self.name_issues());
+            warnings.extend(self.synthetic_issues());
+            warnings
+        } else {
+            Vec::new()
+        };
+        ConfigReport {
+            valid: errors.is_empty(),
+            errors,
+            warnings,
+        }
+    }
+
+    /// Validate all layer configurations, reporting every error
+    pub fn validate(&self) -> Result<()> {
+        let errors = self.config_errors();
+        if errors.is_empty() {
+            for warning in self.zoom_warnings() {
+                log::warn!("{warning}");
+            }
+            for warning in self.name_issues() {
+                log::warn!("{warning}");
+            }
+            for warning in self.synthetic_issues() {
+                log::warn!("{warning}");
+            }
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(errors))
+        }
+    }
+
+    /// Find all configuration errors
+    fn config_errors(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        let any_names = self.allow_any_names.unwrap_or(false);
+        for group in &self.layer_group {
+            if !any_names {
+                if let Err(e) = check_name("group", &group.name) {
+                    errors.push(e);
+                }
+                for layer in &group.layer {
+                    if let Err(e) = check_layer_names(layer) {
+                        errors.push(e);
+                    }
+                }
+            }
+            for (i, layer) in group.layer.iter().enumerate() {
+                let mut errs = check_layer_cfg(layer);
+                if let Err(e) = check_exclude(&group.layer[..i], layer) {
+                    errs.push(("exclude_if_matches", e));
+                }
+                if let Err(e) = check_loam(self, layer) {
+                    errs.push(("loam", e));
+                }
+                // check expanded layers, so that expanding cannot fail later
+                let splits = match (&layer.split_by, errs.is_empty()) {
+                    (Some(_), true) => {
+                        expand_layer_cfg(layer).unwrap_or_default()
+                    }
+                    _ => Vec::new(),
+                };
+                let mut errs: Vec<_> = errs
+                    .into_iter()
+                    .map(|(field, source)| (&layer.name, field, source))
+                    .collect();
+                for split in &splits {
+                    errs.extend(
+                        check_layer_cfg(split).into_iter().map(
+                            |(field, source)| (&split.name, field, source),
+                        ),
+                    );
+                }
+                for (name, field, source) in errs {
+                    errors.push(Error::LayerConfig {
+                        group: group.name.clone(),
+                        layer: name.clone(),
+                        field,
+                        source: Box::new(source),
+                    });
+                }
+            }
+        }
+        for header in &self.http.header {
+            if parse_header(header).is_none() {
+                errors.push(Error::InvalidHeader(header.clone()));
+            }
+        }
+        errors
+    }
+
+    /// Check for uppercase group and layer names.
+    ///
+    /// These are still accepted, but matched case-insensitively (so
+    /// `/Tile/…` and `/tile/…` are the same group).
+    fn name_issues(&self) -> Vec<ConfigIssue> {
+        let mut warnings = Vec::new();
+        if self.allow_any_names.unwrap_or(false) {
+            return warnings;
+        }
+        let message = |name: &str| {
+            format!(
+                "uppercase name {name:?} is deprecated; \
+                matched as {:?}",
+                name.to_ascii_lowercase()
+            )
+        };
+        for group in &self.layer_group {
+            if has_uppercase(&group.name) {
+                warnings.push(ConfigIssue {
+                    group: Some(group.name.clone()),
+                    field: Some("name".into()),
+                    message: message(&group.name),
+                    ..Default::default()
+                });
+            }
+            for layer in group.layer.iter().filter(|l| has_uppercase(&l.name)) {
+                warnings.push(ConfigIssue {
+                    group: Some(group.name.clone()),
+                    layer: Some(layer.name.clone()),
+                    field: Some("name".into()),
+                    message: message(&layer.name),
+                });
+            }
+        }
+        warnings
+    }
+
+    /// Check layer zoom ranges, returning a warning for each layer which
+    /// cannot render at practical zoom levels or overlaps no other layer
+    pub fn zoom_warnings(&self) -> Vec<String> {
+        self.zoom_issues().iter().map(|w| w.to_string()).collect()
+    }
+
+    /// Check layer zoom ranges, as located issues
+    fn zoom_issues(&self) -> Vec<ConfigIssue> {
+        let zoom_max = self.practical_zoom_max.unwrap_or(PRACTICAL_ZOOM_MAX);
+        let mut warnings = Vec::new();
+        for group in &self.layer_group {
+            let ranges: Vec<_> = group
+                .layer
+                .iter()
+                .filter_map(|l| Some((l, parse_zoom_range(&l.zoom).ok()?)))
+                .collect();
+            for (i, (layer, (zmin, zmax))) in ranges.iter().enumerate() {
+                let issue = |message| ConfigIssue {
+                    group: Some(group.name.clone()),
+                    layer: Some(layer.name.clone()),
+                    field: Some("zoom".into()),
+                    message,
+                };
+                if *zmin > zoom_max {
+                    warnings.push(issue(format!(
+                        "{} above practical max {zoom_max}",
+                        layer.zoom
+                    )));
+                }
+                let overlaps = ranges
+                    .iter()
+                    .enumerate()
+                    .any(|(j, (_, (a, b)))| i != j && a <= zmax && b >= zmin);
+                if ranges.len() > 1 && !overlaps {
+                    warnings.push(issue(format!(
+                        "{} overlaps no other layer",
+                        layer.zoom
+                    )));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Check for synthetic tags (`$angle`, `$bbox_w`, `$bbox_h`).
+    ///
+    /// These are computed when encoding tiles, so they shadow any OSM tag
+    /// with the same name.
+    fn synthetic_issues(&self) -> Vec<ConfigIssue> {
+        let mut warnings = Vec::new();
+        for group in &self.layer_group {
+            for layer in &group.layer {
+                for pat in &layer.tags {
+                    let rule = pat.split('@').next().unwrap_or_default();
+                    let Some(tag) = rule.strip_prefix('$') else {
+                        continue;
+                    };
+                    if SYNTHETIC_TAGS.contains(&tag) {
+                        warnings.push(ConfigIssue {
+                            group: Some(group.name.clone()),
+                            layer: Some(layer.name.clone()),
+                            field: Some("tags".into()),
+                            message: format!(
+                                "${tag} is computed, shadowing any \
+                                OSM `{tag}` tag"
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Get path to the OSM directory
+    pub fn osm_dir(&self) -> PathBuf {
+        PathBuf::from("osm")
+    }
+
+    /// Get OSM files to dig: `osm_files`, or the newest in the OSM
+    /// directory
+    pub fn osm_files(&self) -> Result<Vec<PathBuf>> {
+        if self.osm_files.is_empty() {
+            Ok(vec![self.find_osm()?])
+        } else {
+            Ok(self.osm_files.clone())
+        }
+    }
+
+    /// Find the newest OSM file (`.pbf`, or XML with the `xml` feature) in
+    /// the OSM directory.
+    ///
+    /// Entries whose metadata cannot be read are skipped, with a warning.
+    pub fn find_osm(&self) -> Result<PathBuf> {
+        newest_osm(&self.osm_dir())
+    }
+
+    /// Get path to the loam directory
+    pub fn loam_dir(&self) -> PathBuf {
+        self.loam_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("loam"))
+    }
+
+    /// Use a different loam directory (ex. for comparing digs)
+    pub fn with_loam_dir(mut self, dir: PathBuf) -> Self {
+        self.loam_dir = Some(dir);
+        self
+    }
+
+    /// Check full loam file hashes against the dig manifest, instead of
+    /// only sizes and samples (slower)
+    pub fn with_full_check(mut self) -> Self {
+        self.full_check = true;
+        self
+    }
+
+    /// Check if full loam file hashes are checked
+    pub fn full_check(&self) -> bool {
+        self.full_check
+    }
+
+    /// Get path to the object cache directory (two-stage dig)
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("cache"))
+    }
+
+    /// Use a different object cache directory
+    pub fn with_cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
     }
 
     /// Get path to a layer .loam file
     pub fn loam_path(&self, name: &str) -> PathBuf {
-        let mut path = PathBuf::new();
-        path.push("loam");
+        let mut path = self.loam_dir();
         path.push(format!("{}.loam", name));
         path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::LayerDef;
+
+    /// Make a configuration with one group of layers
+    fn wyrm_cfg(layers: &str) -> WyrmCfg {
+        let muon = format!(
+            "bind_address: 127.0.0.1:3030\ntile_extent: 256\n\
+            layer_group: tile\n  osm: true\n{layers}"
+        );
+        muon_rs::from_str(&muon).unwrap()
+    }
+
+    /// Boundary layer split by admin level
+    const BOUNDARY: &str = "  layer: boundary
+    geom_type: polygon
+    zoom: 4+
+    tags: $admin_level ?name boundary=administrative
+    split_by: admin_level=4|6
+";
+
+    #[test]
+    fn split_sint_include() {
+        let mut cfg = wyrm_cfg(BOUNDARY);
+        cfg.validate().unwrap();
+        cfg.expand_layers().unwrap();
+        let layers = &cfg.layer_group[0].layer;
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].name, "boundary_4");
+        assert_eq!(
+            layers[0].tags,
+            [
+                "$admin_level",
+                "?name",
+                "boundary=administrative",
+                "admin_level=4"
+            ]
+        );
+        let def = LayerDef::try_from(&layers[1]).unwrap();
+        assert_eq!(def.sint_tag_index("admin_level"), Some(0));
+        // string include becomes a match pattern
+        let mut cfg =
+            wyrm_cfg(&BOUNDARY.replace("$admin_level", "?admin_level"));
+        cfg.expand_layers().unwrap();
+        let layers = &cfg.layer_group[0].layer;
+        assert_eq!(layers[1].tags[0], ".admin_level=6");
+        // sint split values must be integers
+        let cfg = wyrm_cfg(&BOUNDARY.replace("4|6", "4|state"));
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn http_headers() {
+        let mut http = HttpCfg {
+            header: vec![
+                "Access-Control-Expose-Headers: ETag".into(),
+                "X-Content-Type-Options:nosniff".into(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            http.headers().unwrap(),
+            [
+                ("Access-Control-Expose-Headers", "ETag"),
+                ("X-Content-Type-Options", "nosniff"),
+            ]
+        );
+        // malformed headers are errors, not dropped
+        for bad in ["No-Colon", "Bad Name: x", ": empty", "X-Bad: \u{7f}"] {
+            http.header.push(bad.into());
+            match http.headers() {
+                Err(Error::InvalidHeader(h)) => assert_eq!(h, bad),
+                res => panic!("{bad}: {res:?}"),
+            }
+            http.header.pop();
+        }
+    }
+
+    #[test]
+    fn split_errors_collected() {
+        let layers = format!(
+            "{}  layer: water
+    geom_type: polygon
+    zoom: 0+
+    tags: .natural=water
+    precision: f16
+",
+            BOUNDARY.replace("admin_level=4|6", "?admin_level")
+        );
+        let cfg = wyrm_cfg(&layers);
+        let Err(Error::InvalidConfig(errors)) = cfg.validate() else {
+            panic!("expected invalid config");
+        };
+        let fields: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                Error::LayerConfig { layer, field, .. } => (&layer[..], *field),
+                _ => panic!("unexpected error: {e}"),
+            })
+            .collect();
+        assert_eq!(fields, [("boundary", "split_by"), ("water", "precision")]);
+    }
+
+    #[test]
+    fn newest_osm_decoys() {
+        use std::time::Duration;
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-find-osm", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("dir.pbf")).unwrap();
+        let now = SystemTime::now();
+        let touch = |name: &str, age: u64| {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        };
+        // decoys: not OSM, hidden, partial download and a directory
+        touch("notes.txt", 0);
+        touch(".hidden.pbf", 0);
+        touch("region.pbf.part", 0);
+        touch("region.osm", 0);
+        match newest_osm(&dir) {
+            Ok(path) if cfg!(feature = "xml") => {
+                assert_eq!(path, dir.join("region.osm"));
+            }
+            Err(Error::NoOsmFile(msg)) => assert!(msg.ends_with(
+                "contains [.hidden.pbf, dir.pbf, notes.txt, region.osm, \
+                region.pbf.part]"
+            )),
+            res => panic!("unexpected: {res:?}"),
+        }
+        touch("region.osm", 300);
+        touch("old.osm.pbf", 200);
+        touch("new.PBF", 100);
+        assert_eq!(newest_osm(&dir).unwrap(), dir.join("new.PBF"));
+        touch("old.osm.pbf", 50);
+        assert_eq!(newest_osm(&dir).unwrap(), dir.join("old.osm.pbf"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(newest_osm(&dir), Err(Error::NoOsmFile(_))));
+    }
+
+    #[test]
+    fn synthetic_shadow_warning() {
+        let cfg = wyrm_cfg(
+            "  layer: roads
+    geom_type: linestring
+    zoom: 10+
+    tags: ?highway $angle@14+
+  layer: parks
+    geom_type: polygon
+    zoom: 10+
+    tags: .leisure=park $bbox_w $bbox_h $area
+",
+        );
+        let report = cfg.check();
+        assert!(report.valid);
+        let shadowed: Vec<_> = report
+            .warnings
+            .iter()
+            .filter(|w| w.message.contains("shadowing"))
+            .map(|w| w.to_string())
+            .collect();
+        assert_eq!(
+            shadowed,
+            [
+                "group 'tile', layer 'roads', tags: $angle is computed, \
+                shadowing any OSM `angle` tag",
+                "group 'tile', layer 'parks', tags: $bbox_w is computed, \
+                shadowing any OSM `bbox_w` tag",
+                "group 'tile', layer 'parks', tags: $bbox_h is computed, \
+                shadowing any OSM `bbox_h` tag",
+            ]
+        );
+    }
+
+    #[test]
+    fn layer_errors_targeted() {
+        let muon = "bind_address: 127.0.0.1:3030
+tile_extent: 256
+layer_group: tile
+  layer: roads
+    geom_type: linestring
+    zoom: 1O+
+    tags: ?name
+  layer: water
+    geom_type: polygon
+    zoom: 0+
+    tags: .natural=water ?name@abc
+layer_group: base
+  layer: pois
+    geom_type: pointt
+    zoom: 12+
+    tags: ?name
+";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let Err(Error::InvalidConfig(errors)) = cfg.validate() else {
+            panic!("expected invalid config");
+        };
+        let targets: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                Error::LayerConfig {
+                    group,
+                    layer,
+                    field,
+                    ..
+                } => (&group[..], &layer[..], *field),
+                _ => panic!("unexpected error: {e}"),
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                ("tile", "roads", "zoom"),
+                ("tile", "water", "tags"),
+                ("base", "pois", "geom_type"),
+            ]
+        );
+        let messages: Vec<_> = errors.iter().map(Error::to_string).collect();
+        assert!(messages[0].starts_with("group 'tile', layer 'roads', zoom: "));
+        assert!(messages[1].starts_with("group 'tile', layer 'water', tags: "));
+        assert!(
+            messages[2].starts_with("group 'base', layer 'pois', geom_type: ")
+        );
+    }
+
+    /// Make a configuration with one named group and layer
+    fn named_cfg(group: &str, layer: &str, any_names: bool) -> WyrmCfg {
+        let muon = format!(
+            "bind_address: 127.0.0.1:3030\ntile_extent: 256\n\
+            allow_any_names: {any_names}\n\
+            layer_group: {group}\n  osm: true\n  layer: {layer}\n    \
+            geom_type: polygon\n    zoom: 0+\n    tags: .natural=water\n"
+        );
+        muon_rs::from_str(&muon).unwrap()
+    }
+
+    #[test]
+    fn names_rejected() {
+        for (group, layer, kind, name) in [
+            ("my tiles", "water", "group", "my tiles"),
+            ("tile.v2", "water", "group", "tile.v2"),
+            ("tile", "lake.water", "layer", "lake.water"),
+            ("tile", "lakes/water", "layer", "lakes/water"),
+            ("tile", "wässer", "layer", "wässer"),
+        ] {
+            let cfg = named_cfg(group, layer, false);
+            let Err(Error::InvalidConfig(errors)) = cfg.validate() else {
+                panic!("expected invalid name: {name:?}");
+            };
+            assert!(
+                errors.iter().any(|e| matches!(
+                    e,
+                    Error::InvalidName { kind: k, name: n }
+                        if *k == kind && n == name
+                )),
+                "{errors:?}"
+            );
+            // escape hatch accepts any name
+            named_cfg(group, layer, true).validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn uppercase_names_warned() {
+        let cfg = named_cfg("Tile", "Water", false);
+        cfg.validate().unwrap();
+        let report = cfg.check();
+        assert!(report.valid);
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .filter(|w| w.field.as_deref() == Some("name"))
+            .map(|w| &w.message[..])
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "uppercase name \"Tile\" is deprecated; matched as \"tile\"",
+                "uppercase name \"Water\" is deprecated; matched as \"water\"",
+            ]
+        );
+        // no warnings when any names are allowed
+        let report = named_cfg("Tile", "Water", true).check();
+        assert!(report
+            .warnings
+            .iter()
+            .all(|w| w.field.as_deref() != Some("name")));
+    }
+}
@@ -0,0 +1,158 @@
+// decode.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::{Error, Result};
+
+/// Protobuf wire types
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WireType {
+    /// Variable-length integer
+    Varint,
+
+    /// 64-bit fixed
+    Fixed64,
+
+    /// Length-delimited bytes
+    Bytes,
+
+    /// 32-bit fixed
+    Fixed32,
+}
+
+/// Protobuf field value
+#[derive(Clone, Copy, Debug)]
+pub enum FieldValue<'a> {
+    /// Variable-length integer
+    Varint(u64),
+
+    /// 64-bit fixed
+    Fixed64(u64),
+
+    /// Length-delimited bytes
+    Bytes(&'a [u8]),
+
+    /// 32-bit fixed
+    Fixed32(u32),
+}
+
+/// Minimal protobuf message reader
+pub struct PbfReader<'a> {
+    /// Remaining message bytes
+    buf: &'a [u8],
+}
+
+impl TryFrom<u64> for WireType {
+    type Error = Error;
+
+    fn try_from(wt: u64) -> Result<Self> {
+        match wt {
+            0 => Ok(WireType::Varint),
+            1 => Ok(WireType::Fixed64),
+            2 => Ok(WireType::Bytes),
+            5 => Ok(WireType::Fixed32),
+            _ => Err(Error::MalformedTile()),
+        }
+    }
+}
+
+impl<'a> FieldValue<'a> {
+    /// Get value as an unsigned integer
+    pub fn as_u64(self) -> Result<u64> {
+        match self {
+            FieldValue::Varint(v) => Ok(v),
+            _ => Err(Error::MalformedTile()),
+        }
+    }
+
+    /// Get value as bytes
+    pub fn as_bytes(self) -> Result<&'a [u8]> {
+        match self {
+            FieldValue::Bytes(b) => Ok(b),
+            _ => Err(Error::MalformedTile()),
+        }
+    }
+
+    /// Get value as a UTF-8 string
+    pub fn as_str(self) -> Result<&'a str> {
+        std::str::from_utf8(self.as_bytes()?)
+            .map_err(|_| Error::MalformedTile())
+    }
+}
+
+impl<'a> PbfReader<'a> {
+    /// Create a new protobuf reader
+    pub fn new(buf: &'a [u8]) -> Self {
+        PbfReader { buf }
+    }
+
+    /// Read a variable-length integer
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut val = 0;
+        for (i, b) in self.buf.iter().enumerate().take(10) {
+            val |= u64::from(b & 0x7F) << (i * 7);
+            if b & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(val);
+            }
+        }
+        Err(Error::MalformedTile())
+    }
+
+    /// Take a number of bytes
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len <= self.buf.len() {
+            let (head, tail) = self.buf.split_at(len);
+            self.buf = tail;
+            Ok(head)
+        } else {
+            Err(Error::MalformedTile())
+        }
+    }
+
+    /// Read the next field number and value
+    pub fn next_field(&mut self) -> Result<Option<(u32, FieldValue<'a>)>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        let field =
+            u32::try_from(key >> 3).map_err(|_| Error::MalformedTile())?;
+        let value = match WireType::try_from(key & 0x07)? {
+            WireType::Varint => FieldValue::Varint(self.read_varint()?),
+            WireType::Fixed64 => {
+                let b = self.take(8)?;
+                let mut v = [0; 8];
+                v.copy_from_slice(b);
+                FieldValue::Fixed64(u64::from_le_bytes(v))
+            }
+            WireType::Bytes => {
+                let len = usize::try_from(self.read_varint()?)
+                    .map_err(|_| Error::MalformedTile())?;
+                FieldValue::Bytes(self.take(len)?)
+            }
+            WireType::Fixed32 => {
+                let b = self.take(4)?;
+                let mut v = [0; 4];
+                v.copy_from_slice(b);
+                FieldValue::Fixed32(u32::from_le_bytes(v))
+            }
+        };
+        Ok(Some((field, value)))
+    }
+
+    /// Read all values of a packed repeated varint field
+    pub fn packed_varints(buf: &'a [u8]) -> Result<Vec<u64>> {
+        let mut reader = PbfReader::new(buf);
+        let mut vals = Vec::new();
+        while !reader.buf.is_empty() {
+            vals.push(reader.read_varint()?);
+        }
+        Ok(vals)
+    }
+}
+
+/// Decode a zig-zag encoded signed integer
+pub fn zigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
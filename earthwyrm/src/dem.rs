@@ -0,0 +1,207 @@
+// dem.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::Result;
+use crate::meta::Fnv1a;
+use std::collections::BTreeMap;
+use std::fs::read;
+use std::path::Path;
+
+/// Void (missing data) sample value
+const VOID: i16 = -32768;
+
+/// SRTM `.hgt` tile, covering one degree of latitude / longitude
+struct HgtTile {
+    /// Number of samples per row / column
+    size: usize,
+
+    /// Elevation samples (meters), starting at north-west corner
+    samples: Vec<i16>,
+}
+
+/// Digital elevation model, made of SRTM `.hgt` tiles
+#[derive(Default)]
+pub struct Dem {
+    /// Tiles keyed by south-west corner (lat, lon)
+    tiles: BTreeMap<(i32, i32), HgtTile>,
+
+    /// Hash of tile corners and samples
+    hash: String,
+}
+
+impl HgtTile {
+    /// Parse a tile from big-endian `i16` grid data
+    fn parse(data: &[u8]) -> Option<Self> {
+        let len = data.len() / 2;
+        let size = (len as f64).sqrt() as usize;
+        if size < 2 || size * size != len || data.len() % 2 != 0 {
+            return None;
+        }
+        let samples = data
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        Some(HgtTile { size, samples })
+    }
+
+    /// Get one sample by row / column
+    fn sample(&self, row: usize, col: usize) -> Option<f64> {
+        let row = row.min(self.size - 1);
+        let col = col.min(self.size - 1);
+        match self.samples[row * self.size + col] {
+            VOID => None,
+            v => Some(f64::from(v)),
+        }
+    }
+
+    /// Interpolate elevation at a fractional offset within the tile
+    ///
+    /// * `fy` Latitude offset from south edge (0 to 1).
+    /// * `fx` Longitude offset from west edge (0 to 1).
+    fn interpolate(&self, fy: f64, fx: f64) -> Option<f64> {
+        let n = (self.size - 1) as f64;
+        let y = (1.0 - fy) * n;
+        let x = fx * n;
+        let (r, c) = (y.floor() as usize, x.floor() as usize);
+        let (dy, dx) = (y - y.floor(), x - x.floor());
+        let e00 = self.sample(r, c)?;
+        let e01 = self.sample(r, c + 1)?;
+        let e10 = self.sample(r + 1, c)?;
+        let e11 = self.sample(r + 1, c + 1)?;
+        let e0 = e00 + (e01 - e00) * dx;
+        let e1 = e10 + (e11 - e10) * dx;
+        Some(e0 + (e1 - e0) * dy)
+    }
+}
+
+/// Parse the south-west corner from a `.hgt` file name (ex. `N45W094`)
+fn parse_corner(name: &str) -> Option<(i32, i32)> {
+    let name = name.strip_suffix(".hgt")?;
+    let (lat, lon) = name.split_at_checked(3)?;
+    let lat = match lat.split_at_checked(1)? {
+        ("N", v) => v.parse::<i32>().ok()?,
+        ("S", v) => -v.parse::<i32>().ok()?,
+        _ => return None,
+    };
+    let lon = match lon.split_at_checked(1)? {
+        ("E", v) => v.parse::<i32>().ok()?,
+        ("W", v) => -v.parse::<i32>().ok()?,
+        _ => return None,
+    };
+    Some((lat, lon))
+}
+
+impl Dem {
+    /// Load all `.hgt` tiles in a directory
+    pub fn load<P>(dir: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut tiles = BTreeMap::new();
+        for entry in dir.as_ref().read_dir()? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(corner) = name.to_str().and_then(parse_corner) else {
+                continue;
+            };
+            match HgtTile::parse(&read(entry.path())?) {
+                Some(tile) => {
+                    tiles.insert(corner, tile);
+                }
+                None => log::warn!("invalid hgt file: {name:?}"),
+            }
+        }
+        log::info!("DEM: {} hgt tiles", tiles.len());
+        let mut hash = Fnv1a::default();
+        for ((lat, lon), tile) in &tiles {
+            hash.write(&lat.to_le_bytes());
+            hash.write(&lon.to_le_bytes());
+            for sample in &tile.samples {
+                hash.write(&sample.to_le_bytes());
+            }
+        }
+        let hash = hash.finish();
+        Ok(Dem { tiles, hash })
+    }
+
+    /// Get identity of the DEM data (hash of all tiles)
+    pub fn identity(&self) -> &str {
+        &self.hash
+    }
+
+    /// Sample elevation (meters) at a position, using bilinear interpolation
+    pub fn elevation(&self, lat: f64, lon: f64) -> Option<f64> {
+        let (lat0, lon0) = (lat.floor(), lon.floor());
+        let tile = self.tiles.get(&(lat0 as i32, lon0 as i32))?;
+        tile.interpolate(lat - lat0, lon - lon0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make `.hgt` data for a 3x3 grid, rising 200 m east and 20 m north
+    fn gradient_hgt(void: Option<usize>) -> Vec<u8> {
+        let mut data = Vec::new();
+        for row in 0..3 {
+            for col in 0..3 {
+                let v = if void == Some(row * 3 + col) {
+                    VOID
+                } else {
+                    (100 * col + 10 * (2 - row)) as i16
+                };
+                data.extend(v.to_be_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn hgt_gradient() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-dem", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("N45W094.hgt"), gradient_hgt(None)).unwrap();
+        std::fs::write(dir.join("S01E010.hgt"), gradient_hgt(Some(4))).unwrap();
+        // invalid files are skipped
+        std::fs::write(dir.join("N46W094.hgt"), [0; 7]).unwrap();
+        std::fs::write(dir.join("N47W094.txt"), gradient_hgt(None)).unwrap();
+        let dem = Dem::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(dem.tiles.len(), 2);
+        let cases = [
+            (45.0, -94.0, Some(0.0)),
+            (45.5, -93.5, Some(110.0)),
+            (45.25, -93.75, Some(55.0)),
+            (45.75, -94.0, Some(15.0)),
+            (45.0, -93.25, Some(150.0)),
+            (46.5, -93.5, None),
+            (44.5, -93.5, None),
+        ];
+        for (lat, lon, expected) in cases {
+            let elev = dem.elevation(lat, lon);
+            match (elev, expected) {
+                (Some(e), Some(x)) => {
+                    assert!((e - x).abs() < 1e-9, "{lat},{lon}: {e}")
+                }
+                _ => assert_eq!(elev, expected, "{lat},{lon}"),
+            }
+        }
+        // every cell touches the void center sample, except at corners
+        assert_eq!(dem.elevation(-0.5, 10.5), None);
+        assert_eq!(dem.elevation(-0.9, 10.1), None);
+        assert_eq!(dem.elevation(-1.0, 10.0), Some(0.0));
+    }
+
+    #[test]
+    fn hgt_corner() {
+        assert_eq!(parse_corner("N45W094.hgt"), Some((45, -94)));
+        assert_eq!(parse_corner("S01E010.hgt"), Some((-1, 10)));
+        assert_eq!(parse_corner("N45W094"), None);
+        assert_eq!(parse_corner("X45W094.hgt"), None);
+        assert_eq!(parse_corner("N45Q094.hgt"), None);
+        assert_eq!(parse_corner(".hgt"), None);
+    }
+}
@@ -2,6 +2,7 @@
 //
 // Copyright (c) 2019-2024  Minnesota Department of Transportation
 //
+use mvt::TileId;
 use std::net::AddrParseError;
 use std::num::ParseIntError;
 use std::{fmt, io};
@@ -13,6 +14,9 @@ pub enum Error {
     /// Duplicate tag pattern
     DuplicatePattern(String),
 
+    /// Duplicate layer name in composite tile
+    DuplicateLayerName(String),
+
     /// Invalid network address error
     InvalidAddress(AddrParseError),
 
@@ -22,6 +26,21 @@ pub enum Error {
     /// Loam error
     Loam(loam::Error),
 
+    /// Loam file does not match layer configuration
+    LoamMismatch(String),
+
+    /// Loam file written in an incompatible on-disk format
+    LoamVersionMismatch {
+        /// Layer name
+        layer: String,
+
+        /// Format of the loam file
+        written_by: String,
+
+        /// Format read by this version
+        expected: String,
+    },
+
     /// MuON error
     Muon(muon_rs::Error),
 
@@ -29,22 +48,154 @@ pub enum Error {
     Mvt(mvt::Error),
 
     /// OSM reader error
+    #[cfg(feature = "dig")]
     OsmReader(osmpbfreader::Error),
 
+    /// OSM XML file error
+    #[cfg(feature = "dig")]
+    OsmXml(String),
+
     /// Parse int error
     ParseInt(ParseIntError),
 
     /// Invalid zoom level
     InvalidZoomLevel(u32),
 
+    /// Invalid zoom range
+    InvalidZoomRange(String),
+
+    /// Invalid tile request path
+    InvalidTileRequest(String),
+
+    /// Malformed MVT tile data
+    MalformedTile(),
+
+    /// Malformed OSM PBF file header
+    MalformedOsmHeader(),
+
+    /// Invalid (or truncated) OSM PBF file
+    InvalidPbf(String),
+
+    /// Insufficient free space to dig
+    InsufficientSpace {
+        /// Bytes needed
+        needed: u64,
+
+        /// Bytes available
+        available: u64,
+    },
+
+    /// Directory not writable
+    NotWritable(String),
+
+    /// No usable OSM file found
+    NoOsmFile(String),
+
     /// Tile empty
     TileEmpty(),
 
+    /// Tile bounds or transform out of range
+    TileOutOfRange(TileId),
+
     /// Unknown geometry type
     UnknownGeometryType(),
 
     /// Unknown layer group name
     UnknownGroupName(),
+
+    /// Unknown layer name
+    UnknownLayerName(String),
+
+    /// Unknown relation member role
+    UnknownMemberRole(String),
+
+    /// Unknown geometry storage precision
+    UnknownPrecision(String),
+
+    /// Invalid derived layer
+    InvalidDerive(String),
+
+    /// Invalid generated layer
+    InvalidGenerate(String),
+
+    /// Invalid layer split
+    InvalidSplit(String),
+
+    /// Invalid layer exclusion
+    InvalidExclude(String),
+
+    /// Invalid rank buckets
+    InvalidRank(String),
+
+    /// Invalid feature ID index
+    InvalidIdIndex(String),
+
+    /// Invalid dig stage
+    InvalidDigStage(String),
+
+    /// Missing, stale or corrupt object cache
+    ObjCache(String),
+
+    /// Invalid OSM ID collision policy
+    InvalidIdPolicy(String),
+
+    /// OSM ID collision between input files
+    IdCollision(String),
+
+    /// Invalid shared loam layer
+    InvalidLoam(String),
+
+    /// Invalid constant tag
+    InvalidConstant(String),
+
+    /// Invalid serve-time filter
+    InvalidFilter(String),
+
+    /// Invalid date pattern
+    InvalidDatePattern(String),
+
+    /// Feature ID index not built for layer
+    NoIdIndex(String),
+
+    /// Invalid HTTP response header
+    InvalidHeader(String),
+
+    /// Invalid group or layer name
+    InvalidName {
+        /// Kind of name (`group` or `layer`)
+        kind: &'static str,
+
+        /// Invalid name
+        name: String,
+    },
+
+    /// Invalid WGS84 position
+    InvalidPosition(String),
+
+    /// Composite tile groups have different tile grids
+    GridMismatch(String),
+
+    /// Blocking task panicked or was cancelled
+    #[cfg(feature = "async")]
+    BlockingTask(String),
+
+    /// Invalid layer configuration
+    LayerConfig {
+        /// Layer group name
+        group: String,
+
+        /// Layer name
+        layer: String,
+
+        /// Configuration field
+        field: &'static str,
+
+        /// Source error
+        source: Box<Error>,
+    },
+
+    /// Invalid configuration (all errors found)
+    InvalidConfig(Vec<Error>),
 }
 
 /// Earthwyrm Result
@@ -54,19 +205,103 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::DuplicatePattern(v) => write!(f, "Duplicate patterm: {}", v),
+            Error::DuplicateLayerName(v) => {
+                write!(f, "Duplicate layer name: {}", v)
+            }
             Error::InvalidAddress(e) => e.fmt(f),
             Error::Io(e) => e.fmt(f),
             Error::Loam(e) => e.fmt(f),
+            Error::LoamMismatch(v) => write!(f, "Loam mismatch: {v}"),
+            Error::LoamVersionMismatch {
+                layer,
+                written_by,
+                expected,
+            } => write!(
+                f,
+                "Loam version mismatch: layer '{layer}' written as \
+                {written_by}, expected {expected}; re-run dig (or migrate)"
+            ),
             Error::Muon(e) => e.fmt(f),
             Error::Mvt(e) => e.fmt(f),
+            #[cfg(feature = "dig")]
             Error::OsmReader(e) => e.fmt(f),
+            #[cfg(feature = "dig")]
+            Error::OsmXml(v) => write!(f, "OSM XML: {v}"),
             Error::ParseInt(e) => e.fmt(f),
             Error::InvalidZoomLevel(zoom) => {
                 write!(f, "Invalid zoom level: {}", zoom)
             }
+            Error::InvalidZoomRange(v) => {
+                write!(f, "Invalid zoom range: {v}")
+            }
+            Error::InvalidTileRequest(v) => {
+                write!(f, "Invalid tile request: {v}")
+            }
+            Error::MalformedTile() => write!(f, "Malformed tile"),
+            Error::MalformedOsmHeader() => write!(f, "Malformed OSM header"),
+            Error::InvalidPbf(v) => write!(f, "Invalid PBF: {v}"),
+            Error::InsufficientSpace { needed, available } => write!(
+                f,
+                "Insufficient space: {} MiB needed, {} MiB available",
+                needed >> 20,
+                available >> 20
+            ),
+            Error::NotWritable(v) => write!(f, "Not writable: {v}"),
+            Error::NoOsmFile(v) => write!(f, "No OSM file found: {v}"),
             Error::TileEmpty() => write!(f, "Tile empty"),
+            Error::TileOutOfRange(tid) => {
+                write!(f, "Tile out of range: {tid}")
+            }
             Error::UnknownGeometryType() => write!(f, "Unknown geometry type"),
             Error::UnknownGroupName() => write!(f, "Unknown group name"),
+            Error::UnknownLayerName(v) => write!(f, "Unknown layer: {v}"),
+            Error::UnknownMemberRole(v) => {
+                write!(f, "Unknown member role: {v}")
+            }
+            Error::UnknownPrecision(v) => write!(f, "Unknown precision: {v}"),
+            Error::InvalidDerive(v) => write!(f, "Invalid derive: {v}"),
+            Error::InvalidGenerate(v) => {
+                write!(f, "Invalid generated layer: {v}")
+            }
+            Error::InvalidSplit(v) => write!(f, "Invalid split: {v}"),
+            Error::InvalidExclude(v) => write!(f, "Invalid exclude: {v}"),
+            Error::InvalidRank(v) => write!(f, "Invalid rank: {v}"),
+            Error::InvalidIdIndex(v) => write!(f, "Invalid ID index: {v}"),
+            Error::InvalidLoam(v) => write!(f, "Invalid loam: {v}"),
+            Error::InvalidDigStage(v) => write!(f, "Invalid dig stage: {v}"),
+            Error::ObjCache(v) => write!(f, "Object cache: {v}"),
+            Error::InvalidIdPolicy(v) => write!(f, "Invalid ID policy: {v}"),
+            Error::IdCollision(v) => write!(f, "OSM ID collision: {v}"),
+            Error::InvalidConstant(v) => write!(f, "Invalid constant: {v}"),
+            Error::InvalidFilter(v) => write!(f, "Invalid filter: {v}"),
+            Error::InvalidDatePattern(v) => {
+                write!(f, "Invalid date pattern: {v}")
+            }
+            Error::NoIdIndex(v) => write!(f, "ID index not built: {v}"),
+            Error::InvalidHeader(v) => write!(f, "Invalid HTTP header: {v}"),
+            Error::InvalidName { kind, name } => write!(
+                f,
+                "Invalid {kind} name: {name:?} (allowed: a-z, 0-9, _ and -)"
+            ),
+            Error::InvalidPosition(v) => write!(f, "Invalid position: {v}"),
+            Error::GridMismatch(v) => write!(f, "Tile grid mismatch: {v}"),
+            #[cfg(feature = "async")]
+            Error::BlockingTask(v) => write!(f, "Blocking task: {v}"),
+            Error::LayerConfig {
+                group,
+                layer,
+                field,
+                source,
+            } => {
+                write!(f, "group '{group}', layer '{layer}', {field}: {source}")
+            }
+            Error::InvalidConfig(errors) => {
+                write!(f, "Invalid config ({} errors)", errors.len())?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -79,8 +314,10 @@ impl std::error::Error for Error {
             Error::Loam(e) => Some(e),
             Error::Muon(e) => Some(e),
             Error::Mvt(e) => Some(e),
+            #[cfg(feature = "dig")]
             Error::OsmReader(e) => Some(e),
             Error::ParseInt(e) => Some(e),
+            Error::LayerConfig { source, .. } => Some(source.as_ref()),
             _ => None,
         }
     }
@@ -116,6 +353,7 @@ impl From<mvt::Error> for Error {
     }
 }
 
+#[cfg(feature = "dig")]
 impl From<osmpbfreader::Error> for Error {
     fn from(e: osmpbfreader::Error) -> Self {
         Error::OsmReader(e)
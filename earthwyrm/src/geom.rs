@@ -2,36 +2,256 @@
 //
 // Copyright (c) 2019-2024  Minnesota Department of Transportation
 //
-use crate::error::Result;
-use crate::layer::LayerDef;
+use crate::error::{Error, Result};
+use crate::layer::{parse_sint, LayerDef, Precision};
+use crate::meta::LoamMeta;
 use crate::tile::TileCfg;
 use mvt::{Feature, GeomData, GeomEncoder, GeomType, Layer};
-use pointy::{BBox, Bounded, Transform};
+use pointy::{BBox, Bounded, Pt, Transform};
+#[cfg(any(test, feature = "testing"))]
+use rosewood::BulkWriter;
 use rosewood::{gis, gis::Gis, RTree};
+use serde_json::{json, Map, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::Write;
 use std::path::Path;
 
+/// Web Mercator earth radius (meters)
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
+/// Maximum latitude of Web Mercator projection (degrees)
+pub(crate) const MERCATOR_LAT_MAX: f64 = 85.051_128_78;
+
+/// Maximum ring points checked for self-intersection
+const SELF_INTERSECT_MAX: usize = 4096;
+
+/// Inner ring points checked for containment within outer ring
+const CONTAIN_SAMPLES: usize = 16;
+
+/// Number of sample OSM IDs kept for each problem
+const SAMPLE_IDS: usize = 5;
+
+/// Polygon ring problem
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RingProblem {
+    /// Last point differs from first point
+    Unclosed,
+
+    /// Fewer than 3 distinct points
+    Degenerate,
+
+    /// Ring has no area
+    ZeroArea,
+
+    /// Ring crosses itself
+    SelfIntersecting,
+
+    /// Inner ring not within preceding outer ring
+    InnerOutside,
+}
+
+/// Count of one ring problem, with sample OSM IDs
+#[derive(Debug, Default)]
+pub struct ProblemCount {
+    /// Number of rings with the problem
+    pub count: usize,
+
+    /// Sample OSM IDs of offending polygons (if `osm_id` is stored)
+    pub osm_ids: Vec<String>,
+}
+
+/// Features found by OSM ID (or near a batch query point)
+#[derive(Debug)]
+pub struct FeatureInfo {
+    /// Layer name
+    pub layer: String,
+
+    /// OSM ID
+    pub osm_id: i64,
+
+    /// Bounding box of all matching features (Web Mercator)
+    pub bbox: BBox<f64>,
+
+    /// Matching GeoJSON features (more than one for split geometry)
+    pub features: Vec<Value>,
+}
+
+/// Polygon validation report for one layer
+#[derive(Debug)]
+pub struct ValidationReport {
+    /// Layer name
+    pub layer: String,
+
+    /// Number of features checked
+    pub features: usize,
+
+    /// Problems found
+    pub problems: BTreeMap<RingProblem, ProblemCount>,
+}
+
 /// Geometry which can be encoded to GeomData
 trait GisEncode {
-    /// Encode into GeomData
-    fn encode(&self, bbox: BBox<f64>, t: Transform<f64>) -> Result<GeomData>;
+    /// Encode into GeomData, clipped and snapped for a tile
+    fn encode(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Result<GeomData>;
+}
+
+/// Scratch point buffers for encoding, reused for all features of a layer.
+///
+/// Each feature still gets its own `GeomEncoder`; making one does not
+/// allocate, and its command buffer becomes the feature's `GeomData`.
+#[derive(Default)]
+struct EncodeBuf {
+    /// Snapped points of a linestring run or polygon ring
+    pts: Vec<(f64, f64)>,
+
+    /// Points with duplicates (after quantizing) removed
+    dedup: Vec<(f64, f64)>,
+}
+
+/// Geometry which can be converted to GeoJSON
+trait GeoJson {
+    /// Make GeoJSON geometry object
+    fn geometry(&self) -> Value;
+}
+
+/// Geometry which can be encoded to GeoJSON for a tile
+trait GeoJsonEncode {
+    /// Encode into a GeoJSON geometry, clipped and snapped the same as
+    /// [GisEncode::encode], with WGS84 coordinates.
+    ///
+    /// Returns the geometry and number of vertices (`None` if empty).
+    fn encode_geojson(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Option<(Value, usize)>;
+}
+
+/// Sink for features queried in a tile.
+///
+/// MVT and GeoJSON tiles share the same query loops (limits, deadlines,
+/// filters and line merging); only the geometry encoding differs.
+trait TileSink {
+    /// Encoded geometry
+    type Geom;
+
+    /// Merged linestrings of one group
+    type Lines;
+
+    /// Encode geometry, clipped and snapped for the tile (`None` if empty)
+    fn encode<G>(
+        &self,
+        geom: &G,
+        buf: &mut EncodeBuf,
+    ) -> Result<Option<Self::Geom>>
+    where
+        G: GisEncode + GeoJsonEncode;
+
+    /// Add a feature, with synthetic tag values
+    fn add(&mut self, geom: Self::Geom, values: &Values, synth: Synthetic);
+
+    /// Make empty merged linestrings
+    fn lines(&self) -> Self::Lines;
+
+    /// Add linestrings to a merged group
+    fn merge_lines<D>(
+        &self,
+        lines: &mut Self::Lines,
+        geom: &gis::Linestrings<f64, D>,
+        buf: &mut EncodeBuf,
+    ) -> Result<()>;
+
+    /// Finish merged linestrings (`None` if empty)
+    fn finish_lines(&self, lines: Self::Lines) -> Result<Option<Self::Geom>>;
+}
+
+/// Geometry with tag values
+trait TagData {
+    /// Get tag values
+    fn values(&self) -> &Values;
+}
+
+/// Geometry which can extend a bounding box
+trait GisExtent {
+    /// Extend a bounding box to include geometry
+    fn extend_bbox(&self, bbox: &mut Option<BBox<f64>>);
 }
 
 /// Tag values, in order specified by tag pattern rule
 pub type Values = Vec<Option<String>>;
 
+/// Feature for an in-memory tree: tag values and point lists.
+///
+/// Each list is a set of points, a linestring or a polygon ring (outer rings
+/// counter-clockwise, inner rings clockwise), by geometry type.
+#[cfg(any(test, feature = "testing"))]
+pub type MemFeature = (Values, Vec<Vec<(f64, f64)>>);
+
+/// Synthetic tag values, computed at tile-encode time
+#[derive(Clone, Copy, Debug, Default)]
+struct Synthetic {
+    /// Bounding box width / height, in tile pixels (polygons)
+    bbox: Option<(i64, i64)>,
+
+    /// Angle of longest segment, in degrees (linestrings)
+    angle: Option<i64>,
+}
+
+/// Geometry which can be stored with f32 coordinates
+pub(crate) trait Narrow: Sized {
+    /// Geometry with f32 coordinates
+    type F32;
+
+    /// Convert to f32 coordinates, with the number of points
+    fn narrow(&self) -> (Self::F32, usize);
+
+    /// Convert from f32 coordinates
+    fn widen(geom: Self::F32) -> Self;
+}
+
+/// Geometry which can be kept in an in-memory tree
+#[cfg_attr(not(any(test, feature = "testing")), allow(dead_code))]
+trait MemGeom: GisExtent {
+    /// Copy geometry (for query results)
+    fn copy(&self) -> Self;
+}
+
+/// R-Tree of geometry, stored with f64 or f32 coordinates.
+///
+/// Geometry from f32 trees is converted to f64 when queried, so the rest
+/// of the module only deals with f64.
+enum LoamTree<G: Narrow> {
+    /// f64 coordinates
+    F64(RTree<f64, G>),
+
+    /// f32 coordinates
+    F32(RTree<f32, G::F32>),
+
+    /// In-memory geometry, filtered linearly (for tests)
+    #[cfg(any(test, feature = "testing"))]
+    Memory(Vec<G>),
+}
+
 /// Tree of point geometry
 pub struct PointTree {
-    tree: RTree<f64, gis::Points<f64, Values>>,
+    tree: LoamTree<gis::Points<f64, Values>>,
 }
 
 /// Tree of linestring geometry
 pub struct LinestringTree {
-    tree: RTree<f64, gis::Linestrings<f64, Values>>,
+    tree: LoamTree<gis::Linestrings<f64, Values>>,
 }
 
 /// Tree of polygon geometry
 pub struct PolygonTree {
-    tree: RTree<f64, gis::Polygons<f64, Values>>,
+    tree: LoamTree<gis::Polygons<f64, Values>>,
 }
 
 /// Tree of geometry
@@ -43,283 +263,3448 @@ pub enum GeomTree {
 
 impl LayerDef {
     /// Add tag values to a feature
-    pub fn add_tags(&self, feature: &mut Feature, values: &Values) {
-        for (tag, value, sint) in self.tag_values(values) {
+    pub fn add_tags(
+        &self,
+        feature: &mut Feature,
+        values: &Values,
+        tile_cfg: &TileCfg,
+    ) {
+        let name = self.preferred_name(values, tile_cfg);
+        let minzoom = self.feature_minzoom(values);
+        for (tag, value, sint) in self.tile_values(values, tile_cfg.zoom()) {
+            if (tag == "name" && name.is_some())
+                || (tag == "minzoom" && minzoom.is_some())
+            {
+                continue;
+            }
             log::trace!("layer {}, {}={}", self.name(), tag, value);
             if sint {
-                match value.parse() {
-                    Ok(val) => feature.add_tag_sint(tag, val),
-                    Err(_) => log::warn!(
+                match parse_sint(value) {
+                    Some(val) => feature.add_tag_sint(tag, val),
+                    None => log::warn!(
                         "layer {}, {} invalid sint: {}",
                         self.name(),
                         tag,
-                        value,
+                        self.sanitize_value(value).0,
                     ),
                 }
             } else {
-                feature.add_tag_string(tag, value);
+                let (value, truncated) = self.sanitize_value(value);
+                if truncated {
+                    tile_cfg.truncate_value();
+                }
+                feature.add_tag_string(tag, &value);
+            }
+        }
+        if let Some(name) = name {
+            log::trace!("layer {}, name={}", self.name(), name);
+            let (name, truncated) = self.sanitize_value(name);
+            if truncated {
+                tile_cfg.truncate_value();
+            }
+            feature.add_tag_string("name", &name);
+        }
+        for (tag, value) in self.constants() {
+            let (value, truncated) = self.sanitize_value(value);
+            if truncated {
+                tile_cfg.truncate_value();
             }
+            feature.add_tag_string(tag, &value);
+        }
+        if let Some(minzoom) = minzoom {
+            feature.add_tag_sint("minzoom", minzoom);
         }
     }
-}
 
-impl<D> GisEncode for gis::Points<f64, D> {
-    fn encode(&self, bbox: BBox<f64>, t: Transform<f64>) -> Result<GeomData> {
-        let mut enc = GeomEncoder::new(GeomType::Point).bbox(bbox).transform(t);
-        for pt in self.iter() {
-            if pt.bounded_by(bbox) {
-                enc.add_point(pt.x, pt.y)?;
+    /// Add synthetic tag values to a feature
+    fn add_synthetic(&self, feature: &mut Feature, synth: Synthetic) {
+        for tag in self.synthetic_tags() {
+            let value = match (tag, synth.bbox) {
+                ("bbox_w", Some((w, _h))) => Some(w),
+                ("bbox_h", Some((_w, h))) => Some(h),
+                ("angle", _) => synth.angle,
+                _ => None,
+            };
+            if let Some(value) = value {
+                feature.add_tag_sint(tag, value);
             }
         }
-        Ok(enc.encode()?)
     }
 }
 
-impl PointTree {
-    /// Create a new point tree
-    fn new<P>(path: P) -> Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        log::debug!("PointTree: {:?}", path.as_ref());
-        let tree = RTree::new(path)?;
-        Ok(Self { tree })
+impl LayerDef {
+    /// Make key for merging features with equal emitted tags.
+    ///
+    /// The key has tags as they will be added by `add_tags`, without
+    /// `osm_id`.
+    fn merge_key(
+        &self,
+        values: &Values,
+        tile_cfg: &TileCfg,
+    ) -> Vec<(String, String)> {
+        let name = self.preferred_name(values, tile_cfg);
+        let mut key: Vec<_> = self
+            .tile_values(values, tile_cfg.zoom())
+            .filter(|(tag, _v, _s)| *tag != "osm_id")
+            .filter(|(tag, _v, _s)| *tag != "name" || name.is_none())
+            .map(|(tag, val, _s)| (tag.to_string(), val.to_string()))
+            .collect();
+        if let Some(name) = name {
+            key.push(("name".to_string(), name.to_string()));
+        }
+        key
     }
 
-    /// Query point features
-    fn query_features(
-        &self,
-        layer_def: &LayerDef,
-        bbox: BBox<f64>,
-    ) -> Result<()> {
-        for points in self.tree.query(bbox) {
-            let points = points?;
-            let values = points.data();
-            for (tag, value, _sint) in layer_def.tag_values(values) {
-                println!("{}: {tag}={value}", layer_def.name());
-            }
+    /// Get values of a merged feature, with `osm_id` cleared
+    fn merge_values(&self, values: &Values) -> Values {
+        let mut values = values.clone();
+        if let Some(val) =
+            self.tag_index("osm_id").and_then(|i| values.get_mut(i))
+        {
+            *val = None;
         }
-        Ok(())
+        values
     }
 
-    /// Query points in a tile
-    fn query_tile(
+    /// Get preferred name from language fallback chain.
+    ///
+    /// The requested language of the tile is checked first.  Names of
+    /// zoom-qualified tags are skipped outside their zoom range.
+    fn preferred_name<'a>(
         &self,
-        layer_def: &LayerDef,
-        mut layer: Layer,
+        values: &'a Values,
         tile_cfg: &TileCfg,
-    ) -> Result<Layer> {
-        let bbox = tile_cfg.bbox();
-        log::trace!("query_tile points: {bbox:?}");
-        let transform = tile_cfg.transform();
-        for points in self.tree.query(bbox) {
-            let points = points?;
-            let geom = points.encode(bbox, transform)?;
-            if !geom.is_empty() {
-                let mut feature = layer.into_feature(geom);
-                layer_def.add_tags(&mut feature, points.data());
-                layer = feature.into_layer();
-            }
+    ) -> Option<&'a str> {
+        let lang = tile_cfg.lang();
+        let zoom = tile_cfg.zoom();
+        if self.tag_index("name").is_none()
+            || !self.check_tag_zoom("name", zoom)
+            || (lang.is_none() && self.name_languages().is_empty())
+        {
+            return None;
         }
-        Ok(layer)
+        lang.into_iter()
+            .chain(self.name_languages().iter().map(String::as_str))
+            .map(|l| format!("name:{l}"))
+            .filter(|tag| self.check_tag_zoom(tag, zoom))
+            .find_map(|tag| self.tag_value(values, &tag))
     }
-}
 
-impl<D> GisEncode for gis::Linestrings<f64, D> {
-    fn encode(&self, bbox: BBox<f64>, t: Transform<f64>) -> Result<GeomData> {
-        let mut enc = GeomEncoder::new(GeomType::Linestring)
-            .bbox(bbox)
-            .transform(t);
-        for line in self.iter() {
-            let mut connected = false;
-            for seg in line.segments() {
-                if seg.bounded_by(bbox) {
-                    if !connected {
-                        enc.complete_geom()?;
-                        enc.add_point(seg.p0.x, seg.p0.y)?;
-                    }
-                    enc.add_point(seg.p1.x, seg.p1.y)?;
-                    connected = true;
-                } else {
-                    connected = false;
+    /// Make a GeoJSON feature for a tile.
+    ///
+    /// Properties are the same as tags added to MVT features (see
+    /// [add_tags](Self::add_tags)), including synthetic tags.
+    fn tile_geojson_feature(
+        &self,
+        geometry: Value,
+        values: &Values,
+        synth: Synthetic,
+        tile_cfg: &TileCfg,
+    ) -> Value {
+        let mut properties = Map::new();
+        let name = self.preferred_name(values, tile_cfg);
+        let minzoom = self.feature_minzoom(values);
+        for (tag, value, sint) in self.tile_values(values, tile_cfg.zoom()) {
+            if (tag == "name" && name.is_some())
+                || (tag == "minzoom" && minzoom.is_some())
+            {
+                continue;
+            }
+            if sint {
+                if let Some(val) = parse_sint(value) {
+                    properties.insert(tag.to_string(), json!(val));
+                }
+            } else {
+                let (value, truncated) = self.sanitize_value(value);
+                if truncated {
+                    tile_cfg.truncate_value();
                 }
+                properties.insert(tag.to_string(), json!(value));
             }
         }
-        Ok(enc.encode()?)
+        if let Some(name) = name {
+            let (name, truncated) = self.sanitize_value(name);
+            if truncated {
+                tile_cfg.truncate_value();
+            }
+            properties.insert("name".to_string(), json!(name));
+        }
+        for (tag, value) in self.constants() {
+            let (value, truncated) = self.sanitize_value(value);
+            if truncated {
+                tile_cfg.truncate_value();
+            }
+            properties.insert(tag.to_string(), json!(value));
+        }
+        if let Some(minzoom) = minzoom {
+            properties.insert("minzoom".to_string(), json!(minzoom));
+        }
+        for tag in self.synthetic_tags() {
+            let value = match (tag, synth.bbox) {
+                ("bbox_w", Some((w, _h))) => Some(w),
+                ("bbox_h", Some((_w, h))) => Some(h),
+                ("angle", _) => synth.angle,
+                _ => None,
+            };
+            if let Some(value) = value {
+                properties.insert(tag.to_string(), json!(value));
+            }
+        }
+        json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": properties,
+        })
+    }
+
+    /// Make a GeoJSON feature
+    fn geojson_feature(&self, geometry: Value, values: &Values) -> Value {
+        let mut properties = Map::new();
+        properties.insert("layer".to_string(), json!(self.name()));
+        for (tag, value, sint) in self.tag_values(values) {
+            let value = match (sint, parse_sint(value)) {
+                (true, Some(v)) => json!(v),
+                _ => json!(self.sanitize_value(value).0),
+            };
+            properties.insert(tag.to_string(), value);
+        }
+        for (tag, value) in self.constants() {
+            let value = self.sanitize_value(value).0;
+            properties.insert(tag.to_string(), json!(value));
+        }
+        json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": properties,
+        })
     }
 }
 
-impl LinestringTree {
-    /// Create a new linestring tree
-    fn new<P>(path: P) -> Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        log::debug!("LinestringTree: {:?}", path.as_ref());
-        let tree = RTree::new(path)?;
-        Ok(Self { tree })
+/// Extend an optional bounding box by a point
+fn extend_pt(bbox: &mut Option<BBox<f64>>, x: f64, y: f64) {
+    match bbox {
+        Some(bbox) => bbox.extend([(x, y)]),
+        None => *bbox = Some(BBox::new([(x, y)])),
     }
+}
 
-    /// Query linestring features
-    fn query_features(
-        &self,
-        layer_def: &LayerDef,
-        bbox: BBox<f64>,
-    ) -> Result<()> {
-        for lines in self.tree.query(bbox) {
-            let lines = lines?;
-            if lines.bounded_by(bbox) {
-                let values = lines.data();
-                for (tag, value, _sint) in layer_def.tag_values(values) {
-                    println!("{}: {tag}={value}", layer_def.name());
-                }
-            }
+impl<D> GisExtent for gis::Points<f64, D> {
+    fn extend_bbox(&self, bbox: &mut Option<BBox<f64>>) {
+        for pt in self.iter() {
+            extend_pt(bbox, pt.x, pt.y);
         }
-        Ok(())
     }
+}
 
-    /// Query linestrings in a tile
-    fn query_tile(
-        &self,
-        layer_def: &LayerDef,
-        mut layer: Layer,
-        tile_cfg: &TileCfg,
-    ) -> Result<Layer> {
-        let bbox = tile_cfg.bbox();
-        log::trace!("query_tile linestrings: {bbox:?}");
-        let transform = tile_cfg.transform();
-        for lines in self.tree.query(bbox) {
-            let lines = lines?;
-            let geom = lines.encode(bbox, transform)?;
-            if !geom.is_empty() {
-                let mut feature = layer.into_feature(geom);
-                layer_def.add_tags(&mut feature, lines.data());
-                layer = feature.into_layer();
+impl<D> GisExtent for gis::Linestrings<f64, D> {
+    fn extend_bbox(&self, bbox: &mut Option<BBox<f64>>) {
+        for line in self.iter() {
+            for seg in line.segments() {
+                extend_pt(bbox, seg.p0.x, seg.p0.y);
+                extend_pt(bbox, seg.p1.x, seg.p1.y);
             }
         }
-        Ok(layer)
     }
 }
 
-impl<D> GisEncode for gis::Polygons<f64, D> {
-    fn encode(&self, bbox: BBox<f64>, t: Transform<f64>) -> Result<GeomData> {
-        let mut enc =
-            GeomEncoder::new(GeomType::Polygon).bbox(bbox).transform(t);
+impl<D> GisExtent for gis::Polygons<f64, D> {
+    fn extend_bbox(&self, bbox: &mut Option<BBox<f64>>) {
         for ring in self.iter() {
-            // NOTE: this assumes that rings are well-formed
-            //       according to MVT spec
-            let mut first = true;
             for seg in ring.segments() {
-                if first {
-                    enc.complete_geom()?;
-                    enc.add_point(seg.p0.x, seg.p0.y)?;
-                    first = false;
-                }
-                enc.add_point(seg.p1.x, seg.p1.y)?;
+                extend_pt(bbox, seg.p0.x, seg.p0.y);
             }
-            enc.complete_geom()?;
         }
-        Ok(enc.encode()?)
     }
 }
 
-impl PolygonTree {
-    /// Create a new polygon tree
-    fn new<P>(path: P) -> Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        log::debug!("PolygonTree: {:?}", path.as_ref());
-        let tree = RTree::new(path)?;
-        Ok(Self { tree })
+impl fmt::Display for RingProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RingProblem::Unclosed => write!(f, "unclosed rings"),
+            RingProblem::Degenerate => write!(f, "degenerate rings"),
+            RingProblem::ZeroArea => write!(f, "zero-area rings"),
+            RingProblem::SelfIntersecting => {
+                write!(f, "self-intersecting rings")
+            }
+            RingProblem::InnerOutside => write!(f, "inner rings outside outer"),
+        }
     }
+}
 
-    /// Query polygon features
-    fn query_features(
-        &self,
-        layer_def: &LayerDef,
-        bbox: BBox<f64>,
-    ) -> Result<()> {
-        for poly in self.tree.query(bbox) {
-            let poly = poly?;
-            if poly.bounded_by(bbox) {
-                let values = poly.data();
-                for (tag, value, _sint) in layer_def.tag_values(values) {
-                    println!("{}: {tag}={value}", layer_def.name());
-                }
-            }
+impl ValidationReport {
+    /// Create an empty report
+    pub(crate) fn new(layer: &str) -> Self {
+        ValidationReport {
+            layer: layer.to_string(),
+            features: 0,
+            problems: BTreeMap::new(),
         }
-        Ok(())
     }
 
-    /// Query polygons in a tile
-    fn query_tile(
-        &self,
-        layer_def: &LayerDef,
-        mut layer: Layer,
-        tile_cfg: &TileCfg,
-    ) -> Result<Layer> {
-        let bbox = tile_cfg.bbox();
-        log::trace!("query_tile polygons: {bbox:?}");
-        let transform = tile_cfg.transform();
-        for polygon in self.tree.query(bbox) {
-            let polygon = polygon?;
-            let geom = polygon.encode(bbox, transform)?;
-            if !geom.is_empty() {
-                let mut feature = layer.into_feature(geom);
-                layer_def.add_tags(&mut feature, polygon.data());
-                layer = feature.into_layer();
+    /// Add a problem
+    fn add(&mut self, problem: RingProblem, osm_id: Option<&str>) {
+        let count = self.problems.entry(problem).or_default();
+        count.count += 1;
+        if let Some(id) = osm_id {
+            if count.osm_ids.len() < SAMPLE_IDS
+                && !count.osm_ids.iter().any(|i| i == id)
+            {
+                count.osm_ids.push(id.to_string());
             }
         }
-        Ok(layer)
     }
-}
 
-impl GeomTree {
-    /// Make a tree to read geometry
-    pub fn new<P>(geom_tp: GeomType, path: P) -> Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        match geom_tp {
-            GeomType::Point => Ok(GeomTree::Point(PointTree::new(path)?)),
-            GeomType::Linestring => {
-                Ok(GeomTree::Linestring(LinestringTree::new(path)?))
+    /// Merge another report into this one
+    pub(crate) fn merge(&mut self, other: ValidationReport) {
+        self.features += other.features;
+        for (problem, other) in other.problems {
+            let count = self.problems.entry(problem).or_default();
+            count.count += other.count;
+            for id in other.osm_ids {
+                if count.osm_ids.len() < SAMPLE_IDS
+                    && !count.osm_ids.contains(&id)
+                {
+                    count.osm_ids.push(id);
+                }
             }
-            GeomType::Polygon => Ok(GeomTree::Polygon(PolygonTree::new(path)?)),
         }
     }
 
-    /// Query geometry features
-    pub fn query_features(
-        &self,
-        layer_def: &LayerDef,
-        bbox: BBox<f64>,
-    ) -> Result<()> {
-        match self {
-            GeomTree::Point(tree) => tree.query_features(layer_def, bbox),
-            GeomTree::Linestring(tree) => tree.query_features(layer_def, bbox),
-            GeomTree::Polygon(tree) => tree.query_features(layer_def, bbox),
-        }
+    /// Get the total number of problems
+    pub fn n_problems(&self) -> usize {
+        self.problems.values().map(|p| p.count).sum()
     }
+}
 
-    /// Query geometry in a tile
-    pub fn query_tile(
-        &self,
-        layer_def: &LayerDef,
-        layer: Layer,
-        tile_cfg: &TileCfg,
-    ) -> Result<Layer> {
-        match self {
-            GeomTree::Point(tree) => {
-                tree.query_tile(layer_def, layer, tile_cfg)
-            }
-            GeomTree::Linestring(tree) => {
-                tree.query_tile(layer_def, layer, tile_cfg)
+/// Check a polygon ring (with closing point) for problems
+pub(crate) fn check_ring(pts: &[(f64, f64)]) -> Option<RingProblem> {
+    if pts.first() != pts.last() {
+        return Some(RingProblem::Unclosed);
+    }
+    let mut distinct = pts.to_vec();
+    distinct.pop();
+    distinct.dedup();
+    if distinct.len() < 3 {
+        return Some(RingProblem::Degenerate);
+    }
+    if signed_area(pts) == 0.0 {
+        return Some(RingProblem::ZeroArea);
+    }
+    if pts.len() <= SELF_INTERSECT_MAX && self_intersects(pts) {
+        return Some(RingProblem::SelfIntersecting);
+    }
+    None
+}
+
+/// Check if a closed ring crosses itself (non-adjacent segments)
+fn self_intersects(pts: &[(f64, f64)]) -> bool {
+    let segs: Vec<_> = pts.windows(2).map(|w| (w[0], w[1])).collect();
+    let n = segs.len();
+    for (i, a) in segs.iter().enumerate() {
+        for (j, b) in segs.iter().enumerate().skip(i + 2) {
+            // first and last segments are adjacent
+            if i == 0 && j == n - 1 {
+                continue;
             }
-            GeomTree::Polygon(tree) => {
-                tree.query_tile(layer_def, layer, tile_cfg)
+            if segments_cross(a.0, a.1, b.0, b.1) {
+                return true;
             }
         }
     }
+    false
+}
+
+/// Get orientation of point `c` relative to segment `a`-`b`
+fn orient(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Check if two segments cross at a single interior point
+fn segments_cross(
+    a0: (f64, f64),
+    a1: (f64, f64),
+    b0: (f64, f64),
+    b1: (f64, f64),
+) -> bool {
+    let d0 = orient(a0, a1, b0);
+    let d1 = orient(a0, a1, b1);
+    let d2 = orient(b0, b1, a0);
+    let d3 = orient(b0, b1, a1);
+    d0 * d1 < 0.0 && d2 * d3 < 0.0
+}
+
+/// Check if a point is inside a closed ring (ray casting)
+fn point_in_ring(pt: (f64, f64), ring: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    for (p0, p1) in ring.iter().zip(ring.iter().skip(1)) {
+        if (p0.1 > pt.1) != (p1.1 > pt.1)
+            && pt.0 < (p1.0 - p0.0) * (pt.1 - p0.1) / (p1.1 - p0.1) + p0.0
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Check if an inner ring is within an outer ring.
+///
+/// A sample of inner points are checked; points shared with the outer ring
+/// are allowed.
+fn ring_contains(outer: &[(f64, f64)], inner: &[(f64, f64)]) -> bool {
+    let step = (inner.len() / CONTAIN_SAMPLES).max(1);
+    inner
+        .iter()
+        .step_by(step)
+        .all(|pt| outer.contains(pt) || point_in_ring(*pt, outer))
+}
+
+/// Nest polygon rings by containment.
+///
+/// Rings within an even number of other rings are outer rings; the rest are
+/// holes of the innermost outer ring containing them.  Winding is ignored,
+/// so rings from any source nest correctly.
+fn nest_rings(rings: Vec<Vec<(f64, f64)>>) -> Vec<Vec<Vec<(f64, f64)>>> {
+    let parents: Vec<Vec<usize>> = rings
+        .iter()
+        .enumerate()
+        .map(|(i, inner)| {
+            (0..rings.len())
+                .filter(|j| *j != i && ring_contains(&rings[*j], inner))
+                .collect()
+        })
+        .collect();
+    let mut polygon_of = vec![None; rings.len()];
+    let mut polygons = Vec::new();
+    for (i, p) in parents.iter().enumerate() {
+        if p.len() % 2 == 0 {
+            polygon_of[i] = Some(polygons.len());
+            polygons.push(vec![i]);
+        }
+    }
+    for (i, p) in parents.iter().enumerate() {
+        if p.len() % 2 == 1 {
+            // the innermost outer ring has one fewer parent
+            let outer = p.iter().find(|j| parents[**j].len() + 1 == p.len());
+            match outer.and_then(|j| polygon_of[*j]) {
+                Some(n) => polygons[n].push(i),
+                None => polygons.push(vec![i]),
+            }
+        }
+    }
+    let mut rings: Vec<_> = rings.into_iter().map(Some).collect();
+    polygons
+        .into_iter()
+        .map(|polygon| {
+            polygon
+                .into_iter()
+                .filter_map(|i| rings[i].take())
+                .collect()
+        })
+        .collect()
+}
+
+/// Features of one tile layer.
+///
+/// When the layer has a feature cap (to fit a tile size budget), features
+/// are kept until `finish`, and then only the largest geometries are added.
+struct LayerFeatures<'a> {
+    /// Layer definition
+    layer_def: &'a LayerDef,
+
+    /// Tile configuration
+    tile_cfg: &'a TileCfg<'a>,
+
+    /// MVT layer (taken while adding a feature)
+    layer: Option<Layer>,
+
+    /// Maximum number of features in layer
+    cap: Option<usize>,
+
+    /// Features kept for capping
+    kept: Vec<(GeomData, Values, Synthetic)>,
+}
+
+impl<'a> LayerFeatures<'a> {
+    /// Create layer features
+    fn new(
+        layer_def: &'a LayerDef,
+        layer: Layer,
+        tile_cfg: &'a TileCfg<'a>,
+    ) -> Self {
+        let cap = tile_cfg.feature_cap(layer_def.name());
+        LayerFeatures {
+            layer_def,
+            tile_cfg,
+            layer: Some(layer),
+            cap,
+            kept: Vec::new(),
+        }
+    }
+
+    /// Create layer features, keeping all of them for a [KeptLayer]
+    fn keeping(layer_def: &'a LayerDef, tile_cfg: &'a TileCfg<'a>) -> Self {
+        LayerFeatures {
+            layer_def,
+            tile_cfg,
+            layer: None,
+            cap: Some(usize::MAX),
+            kept: Vec::new(),
+        }
+    }
+
+    /// Add a feature to the MVT layer
+    fn push(&mut self, geom: GeomData, values: &Values, synth: Synthetic) {
+        if let Some(layer) = self.layer.take() {
+            let mut feature = layer.into_feature(geom);
+            self.layer_def.add_tags(&mut feature, values, self.tile_cfg);
+            self.layer_def.add_synthetic(&mut feature, synth);
+            self.layer = Some(feature.into_layer());
+        }
+    }
+
+    /// Finish adding features, keeping the largest when capped
+    fn finish(mut self) -> Layer {
+        let kept = std::mem::take(&mut self.kept);
+        if let Some(cap) = self.cap {
+            // features from earlier zoom buckets count toward the cap
+            let n_features = self.layer.as_ref().map_or(0, Layer::num_features);
+            let cap = cap.saturating_sub(n_features);
+            let mut order: Vec<usize> = (0..kept.len()).collect();
+            order.sort_by_key(|i| Reverse(kept[*i].0.len()));
+            let mut keep = vec![false; kept.len()];
+            for i in order.into_iter().take(cap) {
+                keep[i] = true;
+            }
+            for ((geom, values, synth), keep) in kept.into_iter().zip(keep) {
+                if keep {
+                    self.push(geom, &values, synth);
+                }
+            }
+        }
+        self.layer.expect("MVT layer")
+    }
+}
+
+impl TileSink for LayerFeatures<'_> {
+    type Geom = GeomData;
+    type Lines = GeomEncoder<f64>;
+
+    fn encode<G>(
+        &self,
+        geom: &G,
+        buf: &mut EncodeBuf,
+    ) -> Result<Option<GeomData>>
+    where
+        G: GisEncode + GeoJsonEncode,
+    {
+        let geom = geom.encode(self.tile_cfg, buf)?;
+        Ok((!geom.is_empty()).then_some(geom))
+    }
+
+    fn add(&mut self, geom: GeomData, values: &Values, synth: Synthetic) {
+        if self.cap.is_some() {
+            self.kept.push((geom, values.clone(), synth));
+        } else {
+            self.push(geom, values, synth);
+        }
+    }
+
+    fn lines(&self) -> GeomEncoder<f64> {
+        GeomEncoder::new(GeomType::Linestring)
+            .bbox(self.tile_cfg.encode_bbox())
+            .transform(self.tile_cfg.transform())
+    }
+
+    fn merge_lines<D>(
+        &self,
+        enc: &mut GeomEncoder<f64>,
+        geom: &gis::Linestrings<f64, D>,
+        buf: &mut EncodeBuf,
+    ) -> Result<()> {
+        encode_lines(enc, geom, self.tile_cfg, buf)?;
+        self.tile_cfg.stats_feature();
+        Ok(())
+    }
+
+    fn finish_lines(&self, enc: GeomEncoder<f64>) -> Result<Option<GeomData>> {
+        let geom = enc.encode()?;
+        Ok((!geom.is_empty()).then_some(geom))
+    }
+}
+
+/// Features of one tile layer, kept to fit a tile size budget.
+///
+/// Trees are queried once; the layer can then be built repeatedly with
+/// fewer features, without querying again.
+#[derive(Default)]
+pub(crate) struct KeptLayer {
+    /// Encoded features, in query order
+    features: Vec<(GeomData, Values, Synthetic)>,
+
+    /// Feature indices, in the order kept when capped
+    rank: Vec<usize>,
+}
+
+impl KeptLayer {
+    /// Add features from one zoom bucket.
+    ///
+    /// Features from earlier buckets are kept first, then the largest
+    /// geometries.
+    fn push_bucket(&mut self, bucket: Vec<(GeomData, Values, Synthetic)>) {
+        let start = self.features.len();
+        self.features.extend(bucket);
+        let mut order: Vec<usize> = (start..self.features.len()).collect();
+        order.sort_by_key(|i| Reverse(self.features[*i].0.len()));
+        self.rank.extend(order);
+    }
+
+    /// Get the number of features
+    pub(crate) fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    /// Check if there are no features
+    pub(crate) fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// Build an MVT layer, with up to `cap` features
+    pub(crate) fn build(
+        &self,
+        layer_def: &LayerDef,
+        layer: Layer,
+        tile_cfg: &TileCfg,
+        cap: usize,
+    ) -> Layer {
+        let mut keep = vec![false; self.features.len()];
+        for i in self.rank.iter().take(cap) {
+            keep[*i] = true;
+        }
+        let mut layer = layer;
+        for ((geom, values, synth), keep) in self.features.iter().zip(keep) {
+            if keep {
+                let mut feature = layer.into_feature(geom.clone());
+                layer_def.add_tags(&mut feature, values, tile_cfg);
+                layer_def.add_synthetic(&mut feature, *synth);
+                layer = feature.into_layer();
+            }
+        }
+        layer
+    }
+}
+
+/// GeoJSON features of one tile layer.
+///
+/// Feature caps are applied to each zoom bucket, like [LayerFeatures].
+pub(crate) struct GeoJsonFeatures<'a> {
+    /// Layer definition
+    layer_def: &'a LayerDef,
+
+    /// Tile configuration
+    tile_cfg: &'a TileCfg<'a>,
+
+    /// Maximum number of features in layer
+    cap: Option<usize>,
+
+    /// Features of finished zoom buckets
+    features: Vec<Value>,
+
+    /// Features of current zoom bucket, with number of vertices
+    kept: Vec<(usize, Value)>,
+}
+
+impl<'a> GeoJsonFeatures<'a> {
+    /// Create GeoJSON layer features
+    pub(crate) fn new(
+        layer_def: &'a LayerDef,
+        tile_cfg: &'a TileCfg<'a>,
+    ) -> Self {
+        let cap = tile_cfg.feature_cap(layer_def.name());
+        GeoJsonFeatures {
+            layer_def,
+            tile_cfg,
+            cap,
+            features: Vec::new(),
+            kept: Vec::new(),
+        }
+    }
+
+    /// Finish a zoom bucket, keeping the largest features when capped
+    fn finish_bucket(&mut self) {
+        let mut kept = std::mem::take(&mut self.kept);
+        if let Some(cap) = self.cap {
+            // features from earlier zoom buckets count toward the cap
+            let cap = cap.saturating_sub(self.features.len());
+            let mut order: Vec<usize> = (0..kept.len()).collect();
+            order.sort_by_key(|i| Reverse(kept[*i].0));
+            let mut keep = vec![false; kept.len()];
+            for i in order.into_iter().take(cap) {
+                keep[i] = true;
+            }
+            let mut keep = keep.into_iter();
+            kept.retain(|_| keep.next().unwrap_or(false));
+        }
+        self.features
+            .extend(kept.into_iter().map(|(_n, feature)| feature));
+    }
+
+    /// Get all features
+    pub(crate) fn into_features(self) -> Vec<Value> {
+        self.features
+    }
+}
+
+impl TileSink for GeoJsonFeatures<'_> {
+    /// Geometry, with number of vertices
+    type Geom = (Value, usize);
+
+    /// Linestring runs, with number of vertices
+    type Lines = (Vec<Value>, usize);
+
+    fn encode<G>(
+        &self,
+        geom: &G,
+        buf: &mut EncodeBuf,
+    ) -> Result<Option<Self::Geom>>
+    where
+        G: GisEncode + GeoJsonEncode,
+    {
+        Ok(geom.encode_geojson(self.tile_cfg, buf))
+    }
+
+    fn add(&mut self, geom: Self::Geom, values: &Values, synth: Synthetic) {
+        let (geometry, n_vertices) = geom;
+        let feature = self.layer_def.tile_geojson_feature(
+            geometry,
+            values,
+            synth,
+            self.tile_cfg,
+        );
+        self.kept.push((n_vertices, feature));
+    }
+
+    fn lines(&self) -> Self::Lines {
+        (Vec::new(), 0)
+    }
+
+    fn merge_lines<D>(
+        &self,
+        lines: &mut Self::Lines,
+        geom: &gis::Linestrings<f64, D>,
+        buf: &mut EncodeBuf,
+    ) -> Result<()> {
+        let (runs, n_vertices) = lines;
+        *n_vertices += geojson_lines(runs, geom, self.tile_cfg, buf);
+        Ok(())
+    }
+
+    fn finish_lines(&self, lines: Self::Lines) -> Result<Option<Self::Geom>> {
+        let (runs, n_vertices) = lines;
+        if runs.is_empty() {
+            return Ok(None);
+        }
+        let geometry =
+            json!({ "type": "MultiLineString", "coordinates": runs });
+        Ok(Some((single_geometry(geometry), n_vertices)))
+    }
+}
+
+impl<G> LoamTree<G>
+where
+    G: Narrow + MemGeom + Gis<f64> + 'static,
+    G::F32: Gis<f32> + 'static,
+{
+    /// Open a tree
+    fn new<P>(path: P, precision: Precision) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(match precision {
+            Precision::F64 => LoamTree::F64(RTree::new(path)?),
+            Precision::F32 => LoamTree::F32(RTree::new(path)?),
+        })
+    }
+
+    /// Query geometry in a bounding box
+    fn query(
+        &self,
+        bbox: BBox<f64>,
+    ) -> Box<dyn Iterator<Item = Result<G>> + '_> {
+        match self {
+            LoamTree::F64(tree) => {
+                Box::new(tree.query(bbox).map(|g| -> Result<G> { Ok(g?) }))
+            }
+            LoamTree::F32(tree) => Box::new(
+                tree.query(narrow_bbox(bbox))
+                    .map(|g| -> Result<G> { Ok(G::widen(g?)) }),
+            ),
+            #[cfg(any(test, feature = "testing"))]
+            LoamTree::Memory(geoms) => Box::new(
+                geoms
+                    .iter()
+                    .filter(move |g| {
+                        let mut extent = None;
+                        g.extend_bbox(&mut extent);
+                        extent.is_some_and(|e| bbox_overlaps(e, bbox))
+                    })
+                    .map(|g| Ok(g.copy())),
+            ),
+        }
+    }
+
+    /// Query features in a tile, adding them to a sink.
+    ///
+    /// * `synth` Function to compute synthetic tag values.
+    fn query_tile<S, F>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+        synth: F,
+    ) -> Result<()>
+    where
+        G: GisEncode + GeoJsonEncode + TagData,
+        S: TileSink,
+        F: Fn(&G) -> Synthetic,
+    {
+        let bbox = tile_cfg.bbox();
+        let mut buf = EncodeBuf::default();
+        for (n, geom) in self.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                tile_cfg.truncate(layer_def);
+                break;
+            }
+            if tile_cfg.deadline_passed(n) {
+                break;
+            }
+            let geom = geom?;
+            if !tile_cfg.check_feature(layer_def, geom.values()) {
+                continue;
+            }
+            if let Some(enc) = sink.encode(&geom, &mut buf)? {
+                sink.add(enc, geom.values(), synth(&geom));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TagData for gis::Points<f64, Values> {
+    fn values(&self) -> &Values {
+        self.data()
+    }
+}
+
+impl TagData for gis::Linestrings<f64, Values> {
+    fn values(&self) -> &Values {
+        self.data()
+    }
+}
+
+impl TagData for gis::Polygons<f64, Values> {
+    fn values(&self) -> &Values {
+        self.data()
+    }
+}
+
+/// Convert a bounding box to f32, rounding outward
+fn narrow_bbox(bbox: BBox<f64>) -> BBox<f32> {
+    let pad = |v: f64| v.abs().max(1.0) * f64::from(f32::EPSILON);
+    BBox::new([
+        (
+            (bbox.x_min() - pad(bbox.x_min())) as f32,
+            (bbox.y_min() - pad(bbox.y_min())) as f32,
+        ),
+        (
+            (bbox.x_max() + pad(bbox.x_max())) as f32,
+            (bbox.y_max() + pad(bbox.y_max())) as f32,
+        ),
+    ])
+}
+
+/// Write geometry to a loam file
+#[cfg(any(test, feature = "testing"))]
+fn write_geoms<G>(path: &Path, geoms: impl Iterator<Item = G>) -> Result<()>
+where
+    G: Gis<f64>,
+{
+    let mut writer = BulkWriter::<f64, G>::new(path)?;
+    for geom in geoms {
+        writer.push(&geom)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Make points from an in-memory feature
+#[cfg(any(test, feature = "testing"))]
+fn mem_points((values, lists): MemFeature) -> gis::Points<f64, Values> {
+    let mut points = gis::Points::new(values);
+    for pt in lists.into_iter().flatten() {
+        points.push(pt);
+    }
+    points
+}
+
+/// Make linestrings from an in-memory feature
+#[cfg(any(test, feature = "testing"))]
+fn mem_linestrings(
+    (values, lists): MemFeature,
+) -> gis::Linestrings<f64, Values> {
+    let mut lines = gis::Linestrings::new(values);
+    for line in lists {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Make polygons from an in-memory feature
+#[cfg(any(test, feature = "testing"))]
+fn mem_polygons((values, lists): MemFeature) -> gis::Polygons<f64, Values> {
+    let mut polygon = gis::Polygons::new(values);
+    for ring in lists {
+        if signed_area(&ring) >= 0.0 {
+            polygon.push_outer(ring);
+        } else {
+            polygon.push_inner(ring);
+        }
+    }
+    polygon
+}
+
+/// Get the points of a linestring or ring, from its segments
+macro_rules! seg_points {
+    ($path:expr, $conv:expr) => {{
+        let mut pts = Vec::new();
+        for seg in $path.segments() {
+            if pts.is_empty() {
+                pts.push($conv(seg.p0.x, seg.p0.y));
+            }
+            pts.push($conv(seg.p1.x, seg.p1.y));
+        }
+        pts
+    }};
+}
+
+/// Convert a point to f32
+fn narrow_pt(x: f64, y: f64) -> (f32, f32) {
+    (x as f32, y as f32)
+}
+
+/// Convert a point to f64
+fn widen_pt(x: f32, y: f32) -> (f64, f64) {
+    (f64::from(x), f64::from(y))
+}
+
+impl MemGeom for gis::Points<f64, Values> {
+    fn copy(&self) -> Self {
+        let mut points = gis::Points::new(self.data().clone());
+        for pt in self.iter() {
+            points.push((pt.x, pt.y));
+        }
+        points
+    }
+}
+
+impl MemGeom for gis::Linestrings<f64, Values> {
+    fn copy(&self) -> Self {
+        let mut lines = gis::Linestrings::new(self.data().clone());
+        for line in self.iter() {
+            lines.push(seg_points!(line, |x, y| (x, y)));
+        }
+        lines
+    }
+}
+
+impl MemGeom for gis::Polygons<f64, Values> {
+    fn copy(&self) -> Self {
+        let mut polygon = gis::Polygons::new(self.data().clone());
+        for ring in self.iter() {
+            let pts: Vec<(f64, f64)> = seg_points!(ring, |x, y| (x, y));
+            if signed_area(&pts) >= 0.0 {
+                polygon.push_outer(pts);
+            } else {
+                polygon.push_inner(pts);
+            }
+        }
+        polygon
+    }
+}
+
+impl Narrow for gis::Points<f64, Values> {
+    type F32 = gis::Points<f32, Values>;
+
+    fn narrow(&self) -> (Self::F32, usize) {
+        let mut points = gis::Points::new(self.data().clone());
+        let mut n_points = 0;
+        for pt in self.iter() {
+            points.push(narrow_pt(pt.x, pt.y));
+            n_points += 1;
+        }
+        (points, n_points)
+    }
+
+    fn widen(geom: Self::F32) -> Self {
+        let mut points = gis::Points::new(geom.data().clone());
+        for pt in geom.iter() {
+            points.push(widen_pt(pt.x, pt.y));
+        }
+        points
+    }
+}
+
+impl Narrow for gis::Linestrings<f64, Values> {
+    type F32 = gis::Linestrings<f32, Values>;
+
+    fn narrow(&self) -> (Self::F32, usize) {
+        let mut lines = gis::Linestrings::new(self.data().clone());
+        let mut n_points = 0;
+        for line in self.iter() {
+            let pts = seg_points!(line, narrow_pt);
+            n_points += pts.len();
+            lines.push(pts);
+        }
+        (lines, n_points)
+    }
+
+    fn widen(geom: Self::F32) -> Self {
+        let mut lines = gis::Linestrings::new(geom.data().clone());
+        for line in geom.iter() {
+            lines.push(seg_points!(line, widen_pt));
+        }
+        lines
+    }
+}
+
+impl Narrow for gis::Polygons<f64, Values> {
+    type F32 = gis::Polygons<f32, Values>;
+
+    fn narrow(&self) -> (Self::F32, usize) {
+        let mut polygon = gis::Polygons::new(self.data().clone());
+        let mut n_points = 0;
+        for ring in self.iter() {
+            let pts: Vec<(f64, f64)> = seg_points!(ring, |x, y| (x, y));
+            let outer = signed_area(&pts) >= 0.0;
+            let pts: Vec<_> = pts.iter().map(|p| narrow_pt(p.0, p.1)).collect();
+            n_points += pts.len();
+            if outer {
+                polygon.push_outer(pts);
+            } else {
+                polygon.push_inner(pts);
+            }
+        }
+        (polygon, n_points)
+    }
+
+    fn widen(geom: Self::F32) -> Self {
+        let mut polygon = gis::Polygons::new(geom.data().clone());
+        for ring in geom.iter() {
+            let pts = seg_points!(ring, widen_pt);
+            if signed_area(&pts) >= 0.0 {
+                polygon.push_outer(pts);
+            } else {
+                polygon.push_inner(pts);
+            }
+        }
+        polygon
+    }
+}
+
+/// Log a warning for a query truncated at the layer's limit
+fn warn_truncated(layer_def: &LayerDef, bbox: BBox<f64>) {
+    log::warn!(
+        "layer {}: query truncated at {} features ({bbox:?})",
+        layer_def.name(),
+        layer_def.query_limit()
+    );
+}
+
+/// Convert a Web Mercator position to WGS84 GeoJSON coordinates
+fn wgs84(x: f64, y: f64) -> Value {
+    let (lon, lat) = lon_lat(x, y);
+    json!([lon, lat])
+}
+
+/// Convert a Web Mercator position to WGS84 longitude / latitude (degrees)
+pub(crate) fn lon_lat(x: f64, y: f64) -> (f64, f64) {
+    let lon = x / EARTH_RADIUS * 180.0 / PI;
+    let lat = (2.0 * (y / EARTH_RADIUS).exp().atan() - PI / 2.0) * 180.0 / PI;
+    (lon, lat)
+}
+
+/// Convert WGS84 coordinates to a Web Mercator position
+pub(crate) fn web_mercator(lon: f64, lat: f64) -> (f64, f64) {
+    let x = lon.to_radians() * EARTH_RADIUS;
+    let y = (PI / 4.0 + lat.to_radians() / 2.0).tan().ln() * EARTH_RADIUS;
+    (x, y)
+}
+
+/// Make a Web Mercator bounding box around a WGS84 position.
+///
+/// The radius is scaled by `1 / cos(lat)`, since Web Mercator distances
+/// grow away from the equator.  Boxes near the antimeridian are not
+/// wrapped.
+pub(crate) fn bbox_around(
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+) -> Result<BBox<f64>> {
+    if !(-90.0..=90.0).contains(&lat)
+        || !(-180.0..=180.0).contains(&lon)
+        || !(radius_m >= 0.0 && radius_m.is_finite())
+    {
+        return Err(Error::InvalidPosition(format!(
+            "{lat},{lon} (radius {radius_m})"
+        )));
+    }
+    let lat = lat.clamp(-MERCATOR_LAT_MAX, MERCATOR_LAT_MAX);
+    let (x, y) = web_mercator(lon, lat);
+    let r = radius_m / lat.to_radians().cos();
+    Ok(BBox::new([(x - r, y - r), (x + r, y + r)]))
+}
+
+/// Check if two bounding boxes overlap
+fn bbox_overlaps(a: BBox<f64>, b: BBox<f64>) -> bool {
+    a.x_min() <= b.x_max()
+        && b.x_min() <= a.x_max()
+        && a.y_min() <= b.y_max()
+        && b.y_min() <= a.y_max()
+}
+
+/// Add a batch match to each point overlapping a feature.
+///
+/// Points are (index, bounding box, feature count).  The query limit
+/// applies to each point separately: features whose bounding box overlaps
+/// a point's box are counted, as they would be by a query for that point
+/// alone.
+fn add_batch_matches(
+    layer_def: &LayerDef,
+    geometry: Value,
+    values: &Values,
+    bbox: BBox<f64>,
+    points: &mut [(usize, BBox<f64>, usize)],
+    overlaps: impl Fn(BBox<f64>) -> bool,
+    matches: &mut [Vec<FeatureInfo>],
+) {
+    let osm_id = feature_id(layer_def, values);
+    let limit = layer_def.query_limit();
+    let mut feature = None;
+    for (i, pt_bbox, n) in points.iter_mut() {
+        if !bbox_overlaps(bbox, *pt_bbox) {
+            continue;
+        }
+        *n += 1;
+        if *n > limit {
+            if *n == limit + 1 {
+                warn_truncated(layer_def, *pt_bbox);
+            }
+            continue;
+        }
+        let Some(osm_id) = osm_id else {
+            continue;
+        };
+        if overlaps(*pt_bbox) {
+            let feature = feature.get_or_insert_with(|| {
+                layer_def
+                    .geojson_feature(single_geometry(geometry.clone()), values)
+            });
+            matches[*i].push(FeatureInfo {
+                layer: layer_def.name().into(),
+                osm_id,
+                bbox,
+                features: vec![feature.clone()],
+            });
+        }
+    }
+}
+
+/// Convert a GeoJSON multi-geometry with one member to the single type
+fn single_geometry(geometry: Value) -> Value {
+    let tp = match geometry["type"].as_str() {
+        Some("MultiPoint") => "Point",
+        Some("MultiLineString") => "LineString",
+        Some("MultiPolygon") => "Polygon",
+        _ => return geometry,
+    };
+    match geometry["coordinates"].as_array() {
+        Some(coords) if coords.len() == 1 => {
+            json!({ "type": tp, "coordinates": coords[0] })
+        }
+        _ => geometry,
+    }
+}
+
+/// Get a bounding box covering all coordinates
+pub(crate) fn bbox_all() -> BBox<f64> {
+    BBox::new([(f64::MIN, f64::MIN), (f64::MAX, f64::MAX)])
+}
+
+/// Get the stored OSM ID of a feature
+fn feature_id(layer_def: &LayerDef, values: &Values) -> Option<i64> {
+    layer_def.tag_value(values, "osm_id")?.parse().ok()
+}
+
+/// Write the start of a GeoJSON FeatureCollection
+pub(crate) fn write_collection_start<W: Write>(out: &mut W) -> Result<()> {
+    out.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n")?;
+    Ok(())
+}
+
+/// Write the end of a GeoJSON FeatureCollection
+pub(crate) fn write_collection_end<W: Write>(out: &mut W) -> Result<()> {
+    out.write_all(b"\n]}\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write one GeoJSON feature of a collection
+fn write_feature<W: Write>(
+    out: &mut W,
+    layer_def: &LayerDef,
+    geometry: Value,
+    values: &Values,
+    n_features: &mut usize,
+) -> Result<()> {
+    if *n_features > 0 {
+        out.write_all(b",\n")?;
+    }
+    let feature = layer_def.geojson_feature(single_geometry(geometry), values);
+    out.write_all(feature.to_string().as_bytes())?;
+    *n_features += 1;
+    Ok(())
+}
+
+/// Make GeoJSON coordinates from snapped (projected) tile points
+fn tile_coords(tile_cfg: &TileCfg, pts: &[(f64, f64)]) -> Value {
+    pts.iter()
+        .map(|pt| {
+            let (lon, lat) = tile_cfg.lon_lat(*pt);
+            json!([lon, lat])
+        })
+        .collect()
+}
+
+/// Calculate signed area of a ring (positive is counter-clockwise)
+pub(crate) fn signed_area(pts: &[(f64, f64)]) -> f64 {
+    pts.iter()
+        .zip(pts.iter().cycle().skip(1))
+        .map(|(p0, p1)| p0.0 * p1.1 - p1.0 * p0.1)
+        .sum::<f64>()
+        / 2.0
+}
+
+impl<D> GeoJson for gis::Points<f64, D> {
+    fn geometry(&self) -> Value {
+        let coords: Vec<Value> =
+            self.iter().map(|pt| wgs84(pt.x, pt.y)).collect();
+        json!({ "type": "MultiPoint", "coordinates": coords })
+    }
+}
+
+impl<D> GeoJson for gis::Linestrings<f64, D> {
+    fn geometry(&self) -> Value {
+        let mut lines = Vec::new();
+        for line in self.iter() {
+            let mut coords = Vec::new();
+            for seg in line.segments() {
+                if coords.is_empty() {
+                    coords.push(wgs84(seg.p0.x, seg.p0.y));
+                }
+                coords.push(wgs84(seg.p1.x, seg.p1.y));
+            }
+            lines.push(Value::Array(coords));
+        }
+        json!({ "type": "MultiLineString", "coordinates": lines })
+    }
+}
+
+impl<D> GeoJson for gis::Polygons<f64, D> {
+    fn geometry(&self) -> Value {
+        let mut rings = Vec::new();
+        for ring in self.iter() {
+            let mut pts = Vec::new();
+            for seg in ring.segments() {
+                if pts.is_empty() {
+                    pts.push((seg.p0.x, seg.p0.y));
+                }
+                pts.push((seg.p1.x, seg.p1.y));
+            }
+            if pts.first() != pts.last() {
+                pts.push(pts[0]);
+            }
+            rings.push(pts);
+        }
+        let polygons: Vec<Vec<Value>> = nest_rings(rings)
+            .into_iter()
+            .map(|polygon| {
+                polygon
+                    .iter()
+                    .map(|pts| {
+                        Value::Array(
+                            pts.iter().map(|p| wgs84(p.0, p.1)).collect(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        json!({ "type": "MultiPolygon", "coordinates": polygons })
+    }
+}
+
+impl<D> GisEncode for gis::Points<f64, D> {
+    fn encode(
+        &self,
+        tile_cfg: &TileCfg,
+        _buf: &mut EncodeBuf,
+    ) -> Result<GeomData> {
+        let bbox = tile_cfg.bbox();
+        let snap = tile_cfg.snap();
+        let t = tile_cfg.transform();
+        // points are transformed here, so they are only checked once
+        let mut enc =
+            GeomEncoder::new(GeomType::Point).bbox(tile_cfg.encode_bbox());
+        for pt in self.iter() {
+            if pt.bounded_by(bbox) {
+                tile_cfg.stats_vertex((pt.x, pt.y));
+                tile_cfg.stats_run();
+                let p = t * Pt::from(snap.snap((pt.x, pt.y)));
+                if tile_cfg.check_finite(p) {
+                    enc.add_point(p.x, p.y)?;
+                }
+            }
+        }
+        tile_cfg.stats_feature();
+        Ok(enc.encode()?)
+    }
+}
+
+impl<D> GeoJsonEncode for gis::Points<f64, D> {
+    fn encode_geojson(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Option<(Value, usize)> {
+        let bbox = tile_cfg.bbox();
+        let snap = tile_cfg.snap();
+        buf.pts.clear();
+        for pt in self.iter() {
+            if pt.bounded_by(bbox) {
+                let pt = snap.snap((pt.x, pt.y));
+                if tile_cfg.check_finite(tile_cfg.transform() * Pt::from(pt)) {
+                    buf.pts.push(pt);
+                }
+            }
+        }
+        if buf.pts.is_empty() {
+            return None;
+        }
+        let coords = tile_coords(tile_cfg, &buf.pts);
+        let geometry = json!({ "type": "MultiPoint", "coordinates": coords });
+        Some((single_geometry(geometry), buf.pts.len()))
+    }
+}
+
+impl PointTree {
+    /// Create a new point tree
+    fn new<P>(path: P, precision: Precision) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!("PointTree: {:?}", path.as_ref());
+        let tree = LoamTree::new(path, precision)?;
+        Ok(Self { tree })
+    }
+
+    /// Query point features
+    fn query_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+    ) -> Result<()> {
+        for (n, points) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let points = points?;
+            let values = points.data();
+            for (tag, value, _sint) in layer_def.tag_values(values) {
+                println!("{}: {tag}={value}", layer_def.name());
+            }
+        }
+        Ok(())
+    }
+
+    /// Query features as GeoJSON
+    fn query_geojson(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for (n, points) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let points = points?;
+            features.push(
+                layer_def.geojson_feature(points.geometry(), points.data()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Export features as GeoJSON
+    fn export_features<W: Write>(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        out: &mut W,
+        n_features: &mut usize,
+    ) -> Result<()> {
+        for points in self.tree.query(bbox) {
+            let points = points?;
+            let geometry = points.geometry();
+            write_feature(out, layer_def, geometry, points.data(), n_features)?;
+        }
+        Ok(())
+    }
+
+    /// Query features near a batch of points.
+    ///
+    /// * `bbox` Bounding box of all points.
+    /// * `points` Index and bounding box of each point.
+    /// * `matches` Matches for each point, by index.
+    fn query_batch(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        points: &[(usize, BBox<f64>)],
+        matches: &mut [Vec<FeatureInfo>],
+    ) -> Result<()> {
+        let mut batch: Vec<_> =
+            points.iter().map(|(i, pb)| (*i, *pb, 0)).collect();
+        for points in self.tree.query(bbox) {
+            let points = points?;
+            let mut extent = None;
+            points.extend_bbox(&mut extent);
+            let Some(extent) = extent else {
+                continue;
+            };
+            add_batch_matches(
+                layer_def,
+                points.geometry(),
+                points.data(),
+                extent,
+                &mut batch,
+                |pb| bbox_overlaps(extent, pb),
+                matches,
+            );
+        }
+        Ok(())
+    }
+
+    /// Get OSM ID and bounding box of all features
+    fn feature_ids(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<Vec<(i64, BBox<f64>)>> {
+        let mut ids = Vec::new();
+        for points in self.tree.query(world) {
+            let points = points?;
+            let mut bbox = None;
+            points.extend_bbox(&mut bbox);
+            if let (Some(id), Some(bbox)) =
+                (feature_id(layer_def, points.data()), bbox)
+            {
+                ids.push((id, bbox));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Find features by OSM ID within a bounding box
+    fn find_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        osm_id: i64,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for points in self.tree.query(bbox) {
+            let points = points?;
+            if feature_id(layer_def, points.data()) == Some(osm_id) {
+                let geometry = single_geometry(points.geometry());
+                features
+                    .push(layer_def.geojson_feature(geometry, points.data()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the extent of all features
+    fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        let mut bbox = None;
+        for points in self.tree.query(world) {
+            points?.extend_bbox(&mut bbox);
+        }
+        Ok(bbox)
+    }
+
+    /// Call a function with the bounding box of each feature
+    fn for_each_bbox<F>(&self, world: BBox<f64>, mut f: F) -> Result<()>
+    where
+        F: FnMut(BBox<f64>),
+    {
+        for points in self.tree.query(world) {
+            let mut bbox = None;
+            points?.extend_bbox(&mut bbox);
+            if let Some(bbox) = bbox {
+                f(bbox);
+            }
+        }
+        Ok(())
+    }
+
+    /// Query points in a tile
+    fn query_tile<S: TileSink>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        log::trace!("query_tile points: {:?}", tile_cfg.bbox());
+        self.tree.query_tile(layer_def, sink, tile_cfg, |_points| {
+            Synthetic::default()
+        })
+    }
+}
+
+impl<D> GisEncode for gis::Linestrings<f64, D> {
+    fn encode(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Result<GeomData> {
+        let mut enc = GeomEncoder::new(GeomType::Linestring)
+            .bbox(tile_cfg.encode_bbox())
+            .transform(tile_cfg.transform());
+        encode_lines(&mut enc, self, tile_cfg, buf)?;
+        tile_cfg.stats_feature();
+        Ok(enc.encode()?)
+    }
+}
+
+/// Encode linestrings clipped to a tile bounding box
+fn encode_lines<D>(
+    enc: &mut GeomEncoder<f64>,
+    lines: &gis::Linestrings<f64, D>,
+    tile_cfg: &TileCfg,
+    buf: &mut EncodeBuf,
+) -> Result<()> {
+    let bbox = tile_cfg.bbox();
+    let snap = tile_cfg.snap();
+    for line in lines.iter() {
+        buf.pts.clear();
+        for seg in line.segments() {
+            if seg.bounded_by(bbox) {
+                if buf.pts.is_empty() {
+                    tile_cfg.stats_vertex((seg.p0.x, seg.p0.y));
+                    buf.pts.push(snap.snap((seg.p0.x, seg.p0.y)));
+                }
+                tile_cfg.stats_vertex((seg.p1.x, seg.p1.y));
+                buf.pts.push(snap.snap((seg.p1.x, seg.p1.y)));
+            } else {
+                encode_run(enc, tile_cfg, buf)?;
+                buf.pts.clear();
+            }
+        }
+        encode_run(enc, tile_cfg, buf)?;
+    }
+    Ok(())
+}
+
+/// Encode a run of connected linestring points (in `buf.pts`)
+fn encode_run(
+    enc: &mut GeomEncoder<f64>,
+    tile_cfg: &TileCfg,
+    buf: &mut EncodeBuf,
+) -> Result<()> {
+    tile_cfg.stats_run();
+    dedup_quantized(tile_cfg, &buf.pts, &mut buf.dedup);
+    // discard degenerate linestrings
+    if buf.dedup.len() >= 2 {
+        enc.complete_geom()?;
+        for &(x, y) in &buf.dedup {
+            enc.add_point(x, y)?;
+        }
+    }
+    Ok(())
+}
+
+impl<D> GeoJsonEncode for gis::Linestrings<f64, D> {
+    fn encode_geojson(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Option<(Value, usize)> {
+        let mut runs = Vec::new();
+        let n_vertices = geojson_lines(&mut runs, self, tile_cfg, buf);
+        if runs.is_empty() {
+            return None;
+        }
+        let geometry =
+            json!({ "type": "MultiLineString", "coordinates": runs });
+        Some((single_geometry(geometry), n_vertices))
+    }
+}
+
+/// Add GeoJSON linestrings clipped to a tile bounding box.
+///
+/// Returns the number of vertices added.
+fn geojson_lines<D>(
+    runs: &mut Vec<Value>,
+    lines: &gis::Linestrings<f64, D>,
+    tile_cfg: &TileCfg,
+    buf: &mut EncodeBuf,
+) -> usize {
+    let bbox = tile_cfg.bbox();
+    let snap = tile_cfg.snap();
+    let mut n_vertices = 0;
+    for line in lines.iter() {
+        buf.pts.clear();
+        for seg in line.segments() {
+            if seg.bounded_by(bbox) {
+                if buf.pts.is_empty() {
+                    buf.pts.push(snap.snap((seg.p0.x, seg.p0.y)));
+                }
+                buf.pts.push(snap.snap((seg.p1.x, seg.p1.y)));
+            } else {
+                n_vertices += geojson_run(runs, tile_cfg, buf);
+                buf.pts.clear();
+            }
+        }
+        n_vertices += geojson_run(runs, tile_cfg, buf);
+    }
+    n_vertices
+}
+
+/// Add a GeoJSON run of connected linestring points (in `buf.pts`).
+///
+/// Returns the number of vertices added.
+fn geojson_run(
+    runs: &mut Vec<Value>,
+    tile_cfg: &TileCfg,
+    buf: &mut EncodeBuf,
+) -> usize {
+    dedup_quantized(tile_cfg, &buf.pts, &mut buf.dedup);
+    // discard degenerate linestrings
+    if buf.dedup.len() < 2 {
+        return 0;
+    }
+    runs.push(tile_coords(tile_cfg, &buf.dedup));
+    buf.dedup.len()
+}
+
+/// Get the angle (degrees) of the longest segment within a tile.
+///
+/// The angle is in tile pixel coordinates (Y down), from -90 to 90.
+fn line_angle<D>(lines: &gis::Linestrings<f64, D>, tile_cfg: &TileCfg) -> i64 {
+    let bbox = tile_cfg.bbox();
+    let t = tile_cfg.transform();
+    let mut longest = (0.0, 0.0);
+    for line in lines.iter() {
+        for seg in line.segments().filter(|seg| seg.bounded_by(bbox)) {
+            let p0 = t * Pt::from(tile_cfg.project((seg.p0.x, seg.p0.y)));
+            let p1 = t * Pt::from(tile_cfg.project((seg.p1.x, seg.p1.y)));
+            let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+            if dx.hypot(dy) > longest.0 {
+                longest = (dx.hypot(dy), dy.atan2(dx).to_degrees());
+            }
+        }
+    }
+    // labels read the same in either direction
+    let angle = match longest.1 {
+        a if a > 90.0 => a - 180.0,
+        a if a < -90.0 => a + 180.0,
+        a => a,
+    };
+    angle.round() as i64
+}
+
+/// Get the bounding box size of a polygon within a tile (pixels)
+fn polygon_bbox<D>(
+    polygon: &gis::Polygons<f64, D>,
+    tile_cfg: &TileCfg,
+) -> Option<(i64, i64)> {
+    let mut bbox = None;
+    polygon.extend_bbox(&mut bbox);
+    let bbox = bbox?;
+    let clip = tile_cfg.bbox();
+    let t = tile_cfg.transform();
+    let p0 = t * Pt::from(tile_cfg.project((
+        bbox.x_min().max(clip.x_min()),
+        bbox.y_min().max(clip.y_min()),
+    )));
+    let p1 = t * Pt::from(tile_cfg.project((
+        bbox.x_max().min(clip.x_max()),
+        bbox.y_max().min(clip.y_max()),
+    )));
+    let w = (p1.x - p0.x).abs().round() as i64;
+    let h = (p1.y - p0.y).abs().round() as i64;
+    Some((w, h))
+}
+
+/// Quantize a point to tile coordinates
+fn quantize(pt: (f64, f64), t: Transform<f64>) -> (i64, i64) {
+    let p = t * Pt::new(pt.0, pt.1);
+    (p.x.round() as i64, p.y.round() as i64)
+}
+
+/// Remove consecutive points which quantize to the same tile coordinate.
+///
+/// Points with non-finite tile coordinates are dropped (and counted).
+///
+/// * `out` Buffer for remaining points (cleared first).
+fn dedup_quantized(
+    tile_cfg: &TileCfg,
+    pts: &[(f64, f64)],
+    out: &mut Vec<(f64, f64)>,
+) {
+    let t = tile_cfg.transform();
+    out.clear();
+    out.reserve(pts.len());
+    let mut prev = None;
+    for &pt in pts {
+        let p = t * Pt::new(pt.0, pt.1);
+        if !tile_cfg.check_finite(p) {
+            continue;
+        }
+        let q = (p.x.round() as i64, p.y.round() as i64);
+        if prev != Some(q) {
+            out.push(pt);
+            prev = Some(q);
+        }
+    }
+}
+
+impl LinestringTree {
+    /// Create a new linestring tree
+    fn new<P>(path: P, precision: Precision) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!("LinestringTree: {:?}", path.as_ref());
+        let tree = LoamTree::new(path, precision)?;
+        Ok(Self { tree })
+    }
+
+    /// Query linestring features
+    fn query_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+    ) -> Result<()> {
+        for (n, lines) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let lines = lines?;
+            if lines.bounded_by(bbox) {
+                let values = lines.data();
+                for (tag, value, _sint) in layer_def.tag_values(values) {
+                    println!("{}: {tag}={value}", layer_def.name());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Query features as GeoJSON
+    fn query_geojson(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for (n, lines) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let lines = lines?;
+            if lines.bounded_by(bbox) {
+                features.push(
+                    layer_def.geojson_feature(lines.geometry(), lines.data()),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Export features as GeoJSON
+    fn export_features<W: Write>(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        out: &mut W,
+        n_features: &mut usize,
+    ) -> Result<()> {
+        for lines in self.tree.query(bbox) {
+            let lines = lines?;
+            let geometry = lines.geometry();
+            write_feature(out, layer_def, geometry, lines.data(), n_features)?;
+        }
+        Ok(())
+    }
+
+    /// Query features near a batch of points.
+    ///
+    /// * `bbox` Bounding box of all points.
+    /// * `points` Index and bounding box of each point.
+    /// * `matches` Matches for each point, by index.
+    fn query_batch(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        points: &[(usize, BBox<f64>)],
+        matches: &mut [Vec<FeatureInfo>],
+    ) -> Result<()> {
+        let mut batch: Vec<_> =
+            points.iter().map(|(i, pb)| (*i, *pb, 0)).collect();
+        for lines in self.tree.query(bbox) {
+            let lines = lines?;
+            let mut extent = None;
+            lines.extend_bbox(&mut extent);
+            let Some(extent) = extent else {
+                continue;
+            };
+            add_batch_matches(
+                layer_def,
+                lines.geometry(),
+                lines.data(),
+                extent,
+                &mut batch,
+                |pb| lines.bounded_by(pb),
+                matches,
+            );
+        }
+        Ok(())
+    }
+
+    /// Get OSM ID and bounding box of all features
+    fn feature_ids(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<Vec<(i64, BBox<f64>)>> {
+        let mut ids = Vec::new();
+        for lines in self.tree.query(world) {
+            let lines = lines?;
+            let mut bbox = None;
+            lines.extend_bbox(&mut bbox);
+            if let (Some(id), Some(bbox)) =
+                (feature_id(layer_def, lines.data()), bbox)
+            {
+                ids.push((id, bbox));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Find features by OSM ID within a bounding box
+    fn find_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        osm_id: i64,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for lines in self.tree.query(bbox) {
+            let lines = lines?;
+            if feature_id(layer_def, lines.data()) == Some(osm_id) {
+                let geometry = single_geometry(lines.geometry());
+                features
+                    .push(layer_def.geojson_feature(geometry, lines.data()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the extent of all features
+    fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        let mut bbox = None;
+        for lines in self.tree.query(world) {
+            lines?.extend_bbox(&mut bbox);
+        }
+        Ok(bbox)
+    }
+
+    /// Call a function with the bounding box of each feature
+    fn for_each_bbox<F>(&self, world: BBox<f64>, mut f: F) -> Result<()>
+    where
+        F: FnMut(BBox<f64>),
+    {
+        for lines in self.tree.query(world) {
+            let mut bbox = None;
+            lines?.extend_bbox(&mut bbox);
+            if let Some(bbox) = bbox {
+                f(bbox);
+            }
+        }
+        Ok(())
+    }
+
+    /// Query linestrings in a tile
+    fn query_tile<S: TileSink>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        log::trace!("query_tile linestrings: {:?}", tile_cfg.bbox());
+        if layer_def.merge_lines() {
+            return self.query_tile_merged(layer_def, sink, tile_cfg);
+        }
+        let synthetic = layer_def.synthetic_tags().next().is_some();
+        self.tree
+            .query_tile(layer_def, sink, tile_cfg, |lines| Synthetic {
+                angle: synthetic.then(|| line_angle(lines, tile_cfg)),
+                ..Default::default()
+            })
+    }
+
+    /// Query linestrings in a tile, merging features with equal tags
+    fn query_tile_merged<S: TileSink>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        let bbox = tile_cfg.bbox();
+        // groups in first-seen order, for stable tiles
+        let mut groups: Vec<(Values, S::Lines)> = Vec::new();
+        let mut index = HashMap::new();
+        let mut buf = EncodeBuf::default();
+        for (n, lines) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                tile_cfg.truncate(layer_def);
+                break;
+            }
+            if tile_cfg.deadline_passed(n) {
+                break;
+            }
+            let lines = lines?;
+            if !tile_cfg.check_feature(layer_def, lines.data()) {
+                continue;
+            }
+            let key = layer_def.merge_key(lines.data(), tile_cfg);
+            // values are only cloned for the first feature of a group
+            let i = *index.entry(key).or_insert_with(|| {
+                groups
+                    .push((layer_def.merge_values(lines.data()), sink.lines()));
+                groups.len() - 1
+            });
+            sink.merge_lines(&mut groups[i].1, &lines, &mut buf)?;
+        }
+        log::trace!("query_tile merged: {} features", groups.len());
+        for (values, lines) in groups {
+            if let Some(geom) = sink.finish_lines(lines)? {
+                sink.add(geom, &values, Synthetic::default());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D> GisEncode for gis::Polygons<f64, D> {
+    fn encode(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Result<GeomData> {
+        let t = tile_cfg.transform();
+        let snap = tile_cfg.snap();
+        let mut enc = GeomEncoder::new(GeomType::Polygon)
+            .bbox(tile_cfg.encode_bbox())
+            .transform(t);
+        // inner rings of a discarded outer ring are also discarded
+        let mut skip_inner = false;
+        for ring in self.iter() {
+            // NOTE: this assumes that rings are well-formed
+            //       according to MVT spec
+            buf.pts.clear();
+            let mut area = 0.0;
+            for seg in ring.segments() {
+                if buf.pts.is_empty() {
+                    tile_cfg.stats_vertex((seg.p0.x, seg.p0.y));
+                    buf.pts.push(snap.snap((seg.p0.x, seg.p0.y)));
+                }
+                tile_cfg.stats_vertex((seg.p1.x, seg.p1.y));
+                buf.pts.push(snap.snap((seg.p1.x, seg.p1.y)));
+                area += seg.p0.x * seg.p1.y - seg.p1.x * seg.p0.y;
+            }
+            tile_cfg.stats_run();
+            // winding of the original ring, since snapping can collapse it
+            let outer = area >= 0.0;
+            if outer {
+                skip_inner = false;
+            } else if skip_inner {
+                continue;
+            }
+            dedup_quantized(tile_cfg, &buf.pts, &mut buf.dedup);
+            let pts = &mut buf.dedup;
+            // closing point is implied by ClosePath
+            if pts.len() > 1
+                && quantize(pts[0], t) == quantize(pts[pts.len() - 1], t)
+            {
+                pts.pop();
+            }
+            // discard degenerate rings
+            if pts.len() < 3 {
+                skip_inner = outer;
+                continue;
+            }
+            enc.complete_geom()?;
+            for &(x, y) in pts.iter() {
+                enc.add_point(x, y)?;
+            }
+            enc.complete_geom()?;
+        }
+        tile_cfg.stats_feature();
+        Ok(enc.encode()?)
+    }
+}
+
+impl<D> GeoJsonEncode for gis::Polygons<f64, D> {
+    fn encode_geojson(
+        &self,
+        tile_cfg: &TileCfg,
+        buf: &mut EncodeBuf,
+    ) -> Option<(Value, usize)> {
+        let t = tile_cfg.transform();
+        let snap = tile_cfg.snap();
+        let mut polygons: Vec<Vec<Value>> = Vec::new();
+        let mut n_vertices = 0;
+        // inner rings of a discarded outer ring are also discarded
+        let mut skip_inner = false;
+        for ring in self.iter() {
+            buf.pts.clear();
+            let mut area = 0.0;
+            for seg in ring.segments() {
+                if buf.pts.is_empty() {
+                    buf.pts.push(snap.snap((seg.p0.x, seg.p0.y)));
+                }
+                buf.pts.push(snap.snap((seg.p1.x, seg.p1.y)));
+                area += seg.p0.x * seg.p1.y - seg.p1.x * seg.p0.y;
+            }
+            let outer = area >= 0.0;
+            if outer {
+                skip_inner = false;
+            } else if skip_inner {
+                continue;
+            }
+            dedup_quantized(tile_cfg, &buf.pts, &mut buf.dedup);
+            let pts = &mut buf.dedup;
+            if pts.len() > 1
+                && quantize(pts[0], t) == quantize(pts[pts.len() - 1], t)
+            {
+                pts.pop();
+            }
+            // discard degenerate rings
+            if pts.len() < 3 {
+                skip_inner = outer;
+                continue;
+            }
+            n_vertices += pts.len();
+            // GeoJSON rings are explicitly closed
+            pts.push(pts[0]);
+            let coords = tile_coords(tile_cfg, pts);
+            match polygons.last_mut() {
+                // inner rings belong to the preceding outer ring
+                Some(polygon) if !outer => polygon.push(coords),
+                _ => polygons.push(vec![coords]),
+            }
+        }
+        if polygons.is_empty() {
+            return None;
+        }
+        let geometry =
+            json!({ "type": "MultiPolygon", "coordinates": polygons });
+        Some((single_geometry(geometry), n_vertices))
+    }
+}
+
+impl PolygonTree {
+    /// Create a new polygon tree
+    fn new<P>(path: P, precision: Precision) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        log::debug!("PolygonTree: {:?}", path.as_ref());
+        let tree = LoamTree::new(path, precision)?;
+        Ok(Self { tree })
+    }
+
+    /// Query polygon features
+    fn query_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+    ) -> Result<()> {
+        for (n, poly) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let poly = poly?;
+            if poly.bounded_by(bbox) {
+                let values = poly.data();
+                for (tag, value, _sint) in layer_def.tag_values(values) {
+                    println!("{}: {tag}={value}", layer_def.name());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Query features as GeoJSON
+    fn query_geojson(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for (n, poly) in self.tree.query(bbox).enumerate() {
+            if n >= layer_def.query_limit() {
+                warn_truncated(layer_def, bbox);
+                break;
+            }
+            let poly = poly?;
+            if poly.bounded_by(bbox) {
+                features.push(
+                    layer_def.geojson_feature(poly.geometry(), poly.data()),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Export features as GeoJSON
+    fn export_features<W: Write>(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        out: &mut W,
+        n_features: &mut usize,
+    ) -> Result<()> {
+        for poly in self.tree.query(bbox) {
+            let poly = poly?;
+            let geometry = poly.geometry();
+            write_feature(out, layer_def, geometry, poly.data(), n_features)?;
+        }
+        Ok(())
+    }
+
+    /// Query features near a batch of points.
+    ///
+    /// * `bbox` Bounding box of all points.
+    /// * `points` Index and bounding box of each point.
+    /// * `matches` Matches for each point, by index.
+    fn query_batch(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        points: &[(usize, BBox<f64>)],
+        matches: &mut [Vec<FeatureInfo>],
+    ) -> Result<()> {
+        let mut batch: Vec<_> =
+            points.iter().map(|(i, pb)| (*i, *pb, 0)).collect();
+        for poly in self.tree.query(bbox) {
+            let poly = poly?;
+            let mut extent = None;
+            poly.extend_bbox(&mut extent);
+            let Some(extent) = extent else {
+                continue;
+            };
+            add_batch_matches(
+                layer_def,
+                poly.geometry(),
+                poly.data(),
+                extent,
+                &mut batch,
+                |pb| poly.bounded_by(pb),
+                matches,
+            );
+        }
+        Ok(())
+    }
+
+    /// Get OSM ID and bounding box of all features
+    fn feature_ids(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<Vec<(i64, BBox<f64>)>> {
+        let mut ids = Vec::new();
+        for poly in self.tree.query(world) {
+            let poly = poly?;
+            let mut bbox = None;
+            poly.extend_bbox(&mut bbox);
+            if let (Some(id), Some(bbox)) =
+                (feature_id(layer_def, poly.data()), bbox)
+            {
+                ids.push((id, bbox));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Find features by OSM ID within a bounding box
+    fn find_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        osm_id: i64,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for poly in self.tree.query(bbox) {
+            let poly = poly?;
+            if feature_id(layer_def, poly.data()) == Some(osm_id) {
+                let geometry = single_geometry(poly.geometry());
+                features.push(layer_def.geojson_feature(geometry, poly.data()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the extent of all features
+    fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        let mut bbox = None;
+        for poly in self.tree.query(world) {
+            poly?.extend_bbox(&mut bbox);
+        }
+        Ok(bbox)
+    }
+
+    /// Call a function with the bounding box of each feature
+    fn for_each_bbox<F>(&self, world: BBox<f64>, mut f: F) -> Result<()>
+    where
+        F: FnMut(BBox<f64>),
+    {
+        for poly in self.tree.query(world) {
+            let mut bbox = None;
+            poly?.extend_bbox(&mut bbox);
+            if let Some(bbox) = bbox {
+                f(bbox);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate all polygons
+    fn validate(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<ValidationReport> {
+        let mut report = ValidationReport::new(layer_def.name());
+        let id_index = layer_def.tag_index("osm_id");
+        for poly in self.tree.query(world) {
+            let poly = poly?;
+            report.features += 1;
+            let osm_id = id_index
+                .and_then(|i| poly.data().get(i))
+                .and_then(|v| v.as_deref());
+            let mut outer: Option<Vec<(f64, f64)>> = None;
+            for ring in poly.iter() {
+                let mut pts = Vec::new();
+                for seg in ring.segments() {
+                    if pts.is_empty() {
+                        pts.push((seg.p0.x, seg.p0.y));
+                    }
+                    pts.push((seg.p1.x, seg.p1.y));
+                }
+                if let Some(problem) = check_ring(&pts) {
+                    report.add(problem, osm_id);
+                    continue;
+                }
+                if signed_area(&pts) > 0.0 {
+                    outer = Some(pts);
+                } else {
+                    match &outer {
+                        Some(outer) if ring_contains(outer, &pts) => (),
+                        _ => report.add(RingProblem::InnerOutside, osm_id),
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Query polygons in a tile
+    fn query_tile<S: TileSink>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        log::trace!("query_tile polygons: {:?}", tile_cfg.bbox());
+        let synthetic = layer_def.synthetic_tags().next().is_some();
+        self.tree
+            .query_tile(layer_def, sink, tile_cfg, |polygon| Synthetic {
+                bbox: if synthetic {
+                    polygon_bbox(polygon, tile_cfg)
+                } else {
+                    None
+                },
+                ..Default::default()
+            })
+    }
+}
+
+impl GeomTree {
+    /// Make a tree to read geometry.
+    ///
+    /// * `geom_tp` Geometry type.
+    /// * `precision` Storage precision of the loam file.
+    /// * `path` Path to loam file.
+    pub fn new<P>(
+        geom_tp: GeomType,
+        precision: Precision,
+        path: P,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        LoamMeta::check_format(path.as_ref())?;
+        match geom_tp {
+            GeomType::Point => {
+                Ok(GeomTree::Point(PointTree::new(path, precision)?))
+            }
+            GeomType::Linestring => {
+                Ok(GeomTree::Linestring(LinestringTree::new(path, precision)?))
+            }
+            GeomType::Polygon => {
+                Ok(GeomTree::Polygon(PolygonTree::new(path, precision)?))
+            }
+        }
+    }
+
+    /// Make an in-memory tree of geometry (for tests).
+    ///
+    /// Queries filter all geometry linearly by bounding box, instead of
+    /// reading a loam file.
+    ///
+    /// * `geom_tp` Geometry type.
+    /// * `geoms` Features, as tag values and point lists (see [MemFeature]).
+    #[cfg(any(test, feature = "testing"))]
+    pub fn from_geometries(geom_tp: GeomType, geoms: Vec<MemFeature>) -> Self {
+        let geoms = geoms.into_iter();
+        match geom_tp {
+            GeomType::Point => GeomTree::Point(PointTree {
+                tree: LoamTree::Memory(geoms.map(mem_points).collect()),
+            }),
+            GeomType::Linestring => GeomTree::Linestring(LinestringTree {
+                tree: LoamTree::Memory(geoms.map(mem_linestrings).collect()),
+            }),
+            GeomType::Polygon => GeomTree::Polygon(PolygonTree {
+                tree: LoamTree::Memory(geoms.map(mem_polygons).collect()),
+            }),
+        }
+    }
+
+    /// Write features to a loam file, with f64 coordinates.
+    ///
+    /// * `path` Loam file path.
+    /// * `geom_tp` Geometry type.
+    /// * `geoms` Features, as tag values and point lists (see [MemFeature]).
+    #[cfg(any(test, feature = "testing"))]
+    pub fn write_loam<P>(
+        path: P,
+        geom_tp: GeomType,
+        geoms: Vec<MemFeature>,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let geoms = geoms.into_iter();
+        match geom_tp {
+            GeomType::Point => write_geoms(path, geoms.map(mem_points)),
+            GeomType::Linestring => {
+                write_geoms(path, geoms.map(mem_linestrings))
+            }
+            GeomType::Polygon => write_geoms(path, geoms.map(mem_polygons)),
+        }
+    }
+
+    /// Query geometry features
+    pub fn query_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => tree.query_features(layer_def, bbox),
+            GeomTree::Linestring(tree) => tree.query_features(layer_def, bbox),
+            GeomTree::Polygon(tree) => tree.query_features(layer_def, bbox),
+        }
+    }
+
+    /// Query geometry features as GeoJSON
+    pub fn query_geojson(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => {
+                tree.query_geojson(layer_def, bbox, features)
+            }
+            GeomTree::Linestring(tree) => {
+                tree.query_geojson(layer_def, bbox, features)
+            }
+            GeomTree::Polygon(tree) => {
+                tree.query_geojson(layer_def, bbox, features)
+            }
+        }
+    }
+
+    /// Export features as a streamed GeoJSON FeatureCollection.
+    ///
+    /// Coordinates are converted to WGS84, and properties are the layer's
+    /// included tags.  Returns the number of features written.
+    ///
+    /// * `bbox` Web Mercator bounding box (all features if `None`).
+    pub fn export_geojson<W: Write>(
+        &self,
+        layer_def: &LayerDef,
+        bbox: Option<BBox<f64>>,
+        mut out: W,
+    ) -> Result<usize> {
+        let bbox = bbox.unwrap_or_else(bbox_all);
+        let mut n_features = 0;
+        write_collection_start(&mut out)?;
+        self.export_features(layer_def, bbox, &mut out, &mut n_features)?;
+        write_collection_end(&mut out)?;
+        Ok(n_features)
+    }
+
+    /// Query features near a batch of points
+    pub(crate) fn query_batch(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        points: &[(usize, BBox<f64>)],
+        matches: &mut [Vec<FeatureInfo>],
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => {
+                tree.query_batch(layer_def, bbox, points, matches)
+            }
+            GeomTree::Linestring(tree) => {
+                tree.query_batch(layer_def, bbox, points, matches)
+            }
+            GeomTree::Polygon(tree) => {
+                tree.query_batch(layer_def, bbox, points, matches)
+            }
+        }
+    }
+
+    /// Get OSM ID and bounding box of all features
+    pub(crate) fn feature_ids(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<Vec<(i64, BBox<f64>)>> {
+        match self {
+            GeomTree::Point(tree) => tree.feature_ids(layer_def, world),
+            GeomTree::Linestring(tree) => tree.feature_ids(layer_def, world),
+            GeomTree::Polygon(tree) => tree.feature_ids(layer_def, world),
+        }
+    }
+
+    /// Find GeoJSON features by OSM ID within a bounding box
+    pub(crate) fn find_features(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        osm_id: i64,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => {
+                tree.find_features(layer_def, bbox, osm_id, features)
+            }
+            GeomTree::Linestring(tree) => {
+                tree.find_features(layer_def, bbox, osm_id, features)
+            }
+            GeomTree::Polygon(tree) => {
+                tree.find_features(layer_def, bbox, osm_id, features)
+            }
+        }
+    }
+
+    /// Export features as GeoJSON, without the FeatureCollection wrapper
+    pub(crate) fn export_features<W: Write>(
+        &self,
+        layer_def: &LayerDef,
+        bbox: BBox<f64>,
+        out: &mut W,
+        n_features: &mut usize,
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => {
+                tree.export_features(layer_def, bbox, out, n_features)
+            }
+            GeomTree::Linestring(tree) => {
+                tree.export_features(layer_def, bbox, out, n_features)
+            }
+            GeomTree::Polygon(tree) => {
+                tree.export_features(layer_def, bbox, out, n_features)
+            }
+        }
+    }
+
+    /// Get the extent of all features.
+    ///
+    /// * `world` Bounding box of the entire map grid.
+    pub fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        match self {
+            GeomTree::Point(tree) => tree.extent(world),
+            GeomTree::Linestring(tree) => tree.extent(world),
+            GeomTree::Polygon(tree) => tree.extent(world),
+        }
+    }
+
+    /// Call a function with the bounding box of each feature.
+    ///
+    /// * `world` Bounding box of the entire map grid.
+    pub fn for_each_bbox<F>(&self, world: BBox<f64>, f: F) -> Result<()>
+    where
+        F: FnMut(BBox<f64>),
+    {
+        match self {
+            GeomTree::Point(tree) => tree.for_each_bbox(world, f),
+            GeomTree::Linestring(tree) => tree.for_each_bbox(world, f),
+            GeomTree::Polygon(tree) => tree.for_each_bbox(world, f),
+        }
+    }
+
+    /// Validate polygon geometry.
+    ///
+    /// Point and linestring trees are not checked (empty report).
+    ///
+    /// * `world` Bounding box of the entire map grid.
+    pub fn validate(
+        &self,
+        layer_def: &LayerDef,
+        world: BBox<f64>,
+    ) -> Result<ValidationReport> {
+        match self {
+            GeomTree::Polygon(tree) => tree.validate(layer_def, world),
+            _ => Ok(ValidationReport::new(layer_def.name())),
+        }
+    }
+
+    /// Query geometry in a tile
+    pub fn query_tile(
+        &self,
+        layer_def: &LayerDef,
+        layer: Layer,
+        tile_cfg: &TileCfg,
+    ) -> Result<Layer> {
+        let mut features = LayerFeatures::new(layer_def, layer, tile_cfg);
+        self.query_tile_sink(layer_def, &mut features, tile_cfg)?;
+        Ok(features.finish())
+    }
+
+    /// Query geometry in a tile, keeping features to fit a size budget
+    pub(crate) fn query_tile_kept(
+        &self,
+        layer_def: &LayerDef,
+        kept: &mut KeptLayer,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        let mut features = LayerFeatures::keeping(layer_def, tile_cfg);
+        self.query_tile_sink(layer_def, &mut features, tile_cfg)?;
+        kept.push_bucket(features.kept);
+        Ok(())
+    }
+
+    /// Query geometry in a tile as GeoJSON
+    pub(crate) fn query_tile_geojson(
+        &self,
+        layer_def: &LayerDef,
+        features: &mut GeoJsonFeatures,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        self.query_tile_sink(layer_def, features, tile_cfg)?;
+        features.finish_bucket();
+        Ok(())
+    }
+
+    /// Query geometry in a tile, adding features to a sink
+    fn query_tile_sink<S: TileSink>(
+        &self,
+        layer_def: &LayerDef,
+        sink: &mut S,
+        tile_cfg: &TileCfg,
+    ) -> Result<()> {
+        match self {
+            GeomTree::Point(tree) => tree.query_tile(layer_def, sink, tile_cfg),
+            GeomTree::Linestring(tree) => {
+                tree.query_tile(layer_def, sink, tile_cfg)
+            }
+            GeomTree::Polygon(tree) => {
+                tree.query_tile(layer_def, sink, tile_cfg)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayerCfg;
+    use crate::tile::{
+        decode_summary, FeatureSummary, RequestCtx, TagValue, TileExtent, Wyrm,
+    };
+    use mvt::{MapGrid, Tile, TileId};
+    use rosewood::BulkWriter;
+    use std::path::PathBuf;
+
+    /// Tile extent (pixels)
+    const EXTENT: f64 = 256.0;
+
+    /// Make a layer definition
+    fn layer_def(geom_type: &str, tags: &str) -> LayerDef {
+        let muon = format!(
+            "name: test\ngeom_type: {geom_type}\nzoom: 0+\ntags: {tags}\n"
+        );
+        let cfg: LayerCfg = muon_rs::from_str(&muon).unwrap();
+        LayerDef::try_from(&cfg).unwrap()
+    }
+
+    /// Test tile
+    fn tid() -> TileId {
+        TileId::new(300, 400, 10).unwrap()
+    }
+
+    /// Get a Web Mercator point from tile pixel coordinates
+    fn merc(px: f64, py: f64) -> (f64, f64) {
+        let outline = MapGrid::default().tile_bbox(tid());
+        let w = (outline.x_max() - outline.x_min()) / EXTENT;
+        let h = (outline.y_max() - outline.y_min()) / EXTENT;
+        // tile Y increases southward
+        (outline.x_min() + px * w, outline.y_max() - py * h)
+    }
+
+    /// Encode one tile from a tree
+    fn encode_tile(
+        tree: &GeomTree,
+        layer_def: &LayerDef,
+        tid: TileId,
+        edge: u32,
+    ) -> Vec<u8> {
+        let wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(edge)));
+        let ctx = RequestCtx::default();
+        let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+        let mut tile = Tile::new(256);
+        let layer = tile.create_layer(layer_def.name());
+        let layer = tree.query_tile(layer_def, layer, &tile_cfg).unwrap();
+        tile.add_layer(layer).unwrap();
+        tile.to_bytes().unwrap()
+    }
+
+    /// Encode the test tile, returning its features
+    fn encode(
+        tree: &GeomTree,
+        layer_def: &LayerDef,
+        edge: u32,
+    ) -> Vec<FeatureSummary> {
+        let tile = encode_tile(tree, layer_def, tid(), edge);
+        let summary = decode_summary(&tile).unwrap();
+        summary
+            .layers
+            .into_iter()
+            .flat_map(|layer| layer.features)
+            .collect()
+    }
+
+    /// Make an in-memory tree of features with no tags
+    fn tree(geom_tp: GeomType, lists: Vec<Vec<Vec<(f64, f64)>>>) -> GeomTree {
+        let geoms = lists.into_iter().map(|l| (vec![None], l)).collect();
+        GeomTree::from_geometries(geom_tp, geoms)
+    }
+
+    /// Get all points of encoded features
+    fn points(features: &[FeatureSummary]) -> Vec<(f64, f64)> {
+        features.iter().flat_map(|f| f.paths()).flatten().collect()
+    }
+
+    /// Check that all points are within the tile, plus an edge
+    fn assert_within(features: &[FeatureSummary], edge: f64) {
+        for (x, y) in points(features) {
+            assert!(x >= -edge && x <= EXTENT + edge, "x: {x}");
+            assert!(y >= -edge && y <= EXTENT + edge, "y: {y}");
+        }
+    }
+
+    #[test]
+    fn point_edges() {
+        let layer_def = layer_def("point", "?name");
+        let on_edge = vec![vec![merc(256.0, 128.0)]];
+        let corner = vec![vec![merc(0.0, 0.0)]];
+        let buffer = vec![vec![merc(264.0, 128.0)]];
+        let outside = vec![vec![merc(288.0, 128.0)]];
+        let tree = tree(GeomType::Point, vec![on_edge, corner]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(features.len(), 2);
+        assert_eq!(points(&features), [(256.0, 128.0), (0.0, 0.0)]);
+        let tree = self::tree(GeomType::Point, vec![buffer]);
+        assert!(encode(&tree, &layer_def, 0).is_empty());
+        let features = encode(&tree, &layer_def, 16);
+        assert_eq!(points(&features), [(264.0, 128.0)]);
+        let tree = self::tree(GeomType::Point, vec![outside]);
+        assert!(encode(&tree, &layer_def, 0).is_empty());
+        assert!(encode(&tree, &layer_def, 16).is_empty());
+        assert_eq!(encode(&tree, &layer_def, 64).len(), 1);
+        // multipoint partly outside keeps only points inside
+        let tree = self::tree(
+            GeomType::Point,
+            vec![vec![vec![merc(64.0, 64.0), merc(300.0, 64.0)]]],
+        );
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(points(&features), [(64.0, 64.0)]);
+    }
+
+    #[test]
+    fn linestring_clip() {
+        let layer_def = layer_def("linestring", "?name");
+        let line = vec![merc(128.0, 128.0), merc(356.0, 128.0)];
+        let tree = tree(GeomType::Linestring, vec![vec![line]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(features.len(), 1);
+        assert_within(&features, 0.0);
+        let pts = points(&features);
+        assert_eq!(pts.first(), Some(&(128.0, 128.0)));
+        assert_eq!(pts.last(), Some(&(256.0, 128.0)));
+        let features = encode(&tree, &layer_def, 16);
+        assert_within(&features, 16.0);
+        assert_eq!(points(&features).last(), Some(&(272.0, 128.0)));
+        // crossing the whole tile
+        let line = vec![merc(-100.0, 50.0), merc(356.0, 50.0)];
+        let tree = self::tree(GeomType::Linestring, vec![vec![line]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_within(&features, 0.0);
+        let pts = points(&features);
+        assert_eq!(pts.first(), Some(&(0.0, 50.0)));
+        assert_eq!(pts.last(), Some(&(256.0, 50.0)));
+    }
+
+    #[test]
+    fn linestring_edges() {
+        let layer_def = layer_def("linestring", "?name");
+        // along the east edge
+        let on_edge = vec![merc(256.0, 32.0), merc(256.0, 96.0)];
+        let tree = tree(GeomType::Linestring, vec![vec![on_edge]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(points(&features), [(256.0, 32.0), (256.0, 96.0)]);
+        // within edge buffer only
+        let buffer = vec![merc(264.0, 32.0), merc(264.0, 96.0)];
+        let tree = self::tree(GeomType::Linestring, vec![vec![buffer]]);
+        assert!(encode(&tree, &layer_def, 0).is_empty());
+        let features = encode(&tree, &layer_def, 16);
+        assert_eq!(points(&features), [(264.0, 32.0), (264.0, 96.0)]);
+        // just outside edge buffer
+        let outside = vec![merc(273.0, 32.0), merc(273.0, 96.0)];
+        let tree = self::tree(GeomType::Linestring, vec![vec![outside]]);
+        assert!(encode(&tree, &layer_def, 16).is_empty());
+    }
+
+    /// Get a sint tag value of each feature
+    fn sint_tags(features: &[FeatureSummary], tag: &str) -> Vec<i64> {
+        features
+            .iter()
+            .filter_map(|f| {
+                f.tags.iter().find_map(|(t, v)| match v {
+                    TagValue::Int(v) if t == tag => Some(*v),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn synthetic_angle() {
+        let layer_def = layer_def("linestring", "?name $angle");
+        let lines = [
+            // 45 degrees (tile Y is down)
+            (vec![merc(10.0, 10.0), merc(110.0, 110.0)], 45),
+            // reversed direction reads the same
+            (vec![merc(110.0, 110.0), merc(10.0, 10.0)], 45),
+            (vec![merc(10.0, 110.0), merc(110.0, 10.0)], -45),
+            // longest segment wins
+            (
+                vec![merc(10.0, 10.0), merc(110.0, 110.0), merc(120.0, 110.0)],
+                45,
+            ),
+            (vec![merc(20.0, 50.0), merc(200.0, 50.0)], 0),
+            (vec![merc(50.0, 20.0), merc(50.0, 200.0)], 90),
+        ];
+        for (line, angle) in lines {
+            let tree = tree(GeomType::Linestring, vec![vec![line]]);
+            let features = encode(&tree, &layer_def, 0);
+            assert_eq!(sint_tags(&features, "angle"), [angle]);
+        }
+    }
+
+    #[test]
+    fn synthetic_bbox() {
+        let layer_def = layer_def("polygon", "?name $bbox_w $bbox_h");
+        // 100 x 60 pixel rectangle
+        let ring = vec![
+            merc(20.0, 90.0),
+            merc(120.0, 90.0),
+            merc(120.0, 30.0),
+            merc(20.0, 30.0),
+            merc(20.0, 90.0),
+        ];
+        let tree = tree(GeomType::Polygon, vec![vec![ring]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(sint_tags(&features, "bbox_w"), [100]);
+        assert_eq!(sint_tags(&features, "bbox_h"), [60]);
+        // clipped to the tile (plus edge)
+        let ring = vec![
+            merc(200.0, 90.0),
+            merc(400.0, 90.0),
+            merc(400.0, 30.0),
+            merc(200.0, 30.0),
+            merc(200.0, 90.0),
+        ];
+        let tree = self::tree(GeomType::Polygon, vec![vec![ring]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(sint_tags(&features, "bbox_w"), [56]);
+        let features = encode(&tree, &layer_def, 16);
+        assert_eq!(sint_tags(&features, "bbox_w"), [72]);
+        assert_eq!(sint_tags(&features, "bbox_h"), [60]);
+    }
+
+    #[test]
+    fn bbox_around_hemispheres() {
+        let half = |bbox: BBox<f64>| {
+            (
+                (bbox.x_max() - bbox.x_min()) / 2.0,
+                (bbox.y_max() - bbox.y_min()) / 2.0,
+            )
+        };
+        let bbox = bbox_around(0.0, 0.0, 100.0).unwrap();
+        for (v, expected) in [
+            (bbox.x_min(), -100.0),
+            (bbox.y_min(), -100.0),
+            (bbox.x_max(), 100.0),
+            (bbox.y_max(), 100.0),
+        ] {
+            assert!((v - expected).abs() < 1e-6, "{v}");
+        }
+        // (lat, lon): NE, NW, SE, SW
+        for (lat, lon) in [
+            (44.97, 93.26),
+            (44.97, -93.26),
+            (-33.87, 151.21),
+            (-22.91, -43.17),
+        ] {
+            let bbox = bbox_around(lat, lon, 500.0).unwrap();
+            let (x, y) = web_mercator(lon, lat);
+            assert_eq!(x.signum(), lon.signum(), "{lat},{lon}");
+            assert_eq!(y.signum(), lat.signum(), "{lat},{lon}");
+            let cx = (bbox.x_min() + bbox.x_max()) / 2.0;
+            let cy = (bbox.y_min() + bbox.y_max()) / 2.0;
+            assert!((cx - x).abs() < 1e-6 && (cy - y).abs() < 1e-6);
+            let (hw, hh) = half(bbox);
+            let r = 500.0 / lat.to_radians().cos();
+            assert!((hw - r).abs() < 1e-6 && (hh - r).abs() < 1e-6);
+            assert_eq!(
+                bbox,
+                crate::tile::Wyrm::bbox_around(lat, lon, 500.0).unwrap()
+            );
+        }
+        // radius doubles at 60 degrees
+        let (hw, _hh) = half(bbox_around(-60.0, 10.0, 100.0).unwrap());
+        assert!((hw - 200.0).abs() < 1e-6);
+        // antimeridian neighborhood: not wrapped
+        let world = MapGrid::default().bbox();
+        let east = bbox_around(10.0, 179.999, 1000.0).unwrap();
+        assert!(east.x_max() > world.x_max());
+        let west = bbox_around(10.0, -179.999, 1000.0).unwrap();
+        assert!(west.x_min() < world.x_min());
+        for lon in [-180.0, 180.0] {
+            let bbox = bbox_around(-10.0, lon, 1000.0).unwrap();
+            assert!((bbox.x_max() - bbox.x_min() - 2030.0).abs() < 1.0);
+        }
+        // poles are clamped to the Web Mercator limit
+        for lat in [-90.0, 90.0] {
+            let bbox = bbox_around(lat, 0.0, 10.0).unwrap();
+            assert!(bbox.y_min().is_finite() && bbox.y_max().is_finite());
+        }
+        for (lat, lon, radius) in [
+            (90.1, 0.0, 1.0),
+            (-90.1, 0.0, 1.0),
+            (0.0, 180.1, 1.0),
+            (0.0, -180.1, 1.0),
+            (f64::NAN, 0.0, 1.0),
+            (0.0, 0.0, -1.0),
+            (0.0, 0.0, f64::INFINITY),
+        ] {
+            assert!(
+                matches!(
+                    bbox_around(lat, lon, radius),
+                    Err(Error::InvalidPosition(_))
+                ),
+                "{lat},{lon} {radius}"
+            );
+        }
+    }
+
+    #[test]
+    fn polygon_clip() {
+        let layer_def = layer_def("polygon", "?name");
+        // counter-clockwise (Web Mercator Y is northward)
+        let ring = vec![
+            merc(-100.0, 400.0),
+            merc(400.0, 400.0),
+            merc(400.0, -100.0),
+            merc(-100.0, -100.0),
+            merc(-100.0, 400.0),
+        ];
+        let tree = tree(GeomType::Polygon, vec![vec![ring]]);
+        for edge in [0, 16] {
+            let features = encode(&tree, &layer_def, edge);
+            assert_eq!(features.len(), 1);
+            assert_eq!(features[0].geom_type, "polygon");
+            let e = f64::from(edge);
+            assert_within(&features, e);
+            let pts = points(&features);
+            assert!(pts.contains(&(-e, -e)));
+            assert!(pts.contains(&(EXTENT + e, EXTENT + e)));
+        }
+        // small square inside the tile is unchanged
+        let ring = vec![
+            merc(32.0, 96.0),
+            merc(96.0, 96.0),
+            merc(96.0, 32.0),
+            merc(32.0, 32.0),
+            merc(32.0, 96.0),
+        ];
+        let tree = self::tree(GeomType::Polygon, vec![vec![ring]]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_within(&features, 0.0);
+        for pt in [(32.0, 32.0), (96.0, 32.0), (96.0, 96.0), (32.0, 96.0)] {
+            assert!(points(&features).contains(&pt), "{pt:?}");
+        }
+        // outside edge buffer
+        let ring = vec![
+            merc(300.0, 96.0),
+            merc(400.0, 96.0),
+            merc(400.0, 32.0),
+            merc(300.0, 32.0),
+            merc(300.0, 96.0),
+        ];
+        let tree = self::tree(GeomType::Polygon, vec![vec![ring]]);
+        assert!(encode(&tree, &layer_def, 16).is_empty());
+    }
+
+    /// Make a square ring from tile pixel coordinates
+    fn square(x0: f64, y0: f64, w: f64, outer: bool) -> Vec<(f64, f64)> {
+        let (x1, y1) = (x0 + w, y0 + w);
+        // counter-clockwise (Web Mercator Y is northward)
+        let mut ring = vec![
+            merc(x0, y1),
+            merc(x1, y1),
+            merc(x1, y0),
+            merc(x0, y0),
+            merc(x0, y1),
+        ];
+        if !outer {
+            ring.reverse();
+        }
+        ring
+    }
+
+    #[test]
+    fn polygon_drop_inner() {
+        let layer_def = layer_def("polygon", "?name");
+        let rings = vec![
+            // outer ring which collapses to one point
+            square(10.1, 10.1, 0.2, true),
+            square(40.0, 40.0, 20.0, false),
+            square(100.0, 100.0, 50.0, true),
+            square(110.0, 110.0, 10.0, false),
+        ];
+        let tree = tree(GeomType::Polygon, vec![rings]);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(features.len(), 1);
+        // inner ring of the collapsed outer ring is dropped too
+        let paths = features[0].paths();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].contains(&(100.0, 100.0)));
+        assert!(paths[1].contains(&(110.0, 110.0)));
+    }
+
+    #[test]
+    fn tags() {
+        let layer_def = layer_def("point", "?name ?ref $lanes $width");
+        let pt = vec![vec![merc(128.0, 128.0)]];
+        let values = |v: [Option<&str>; 4]| -> Values {
+            v.iter().map(|v| v.map(str::to_string)).collect()
+        };
+        let geoms = vec![
+            (values([Some("Main"), None, Some("3"), Some("wide")]), pt.clone()),
+            (values([None, Some("A1"), None, None]), pt),
+        ];
+        let tree = GeomTree::from_geometries(GeomType::Point, geoms);
+        let features = encode(&tree, &layer_def, 0);
+        assert_eq!(features.len(), 2);
+        // invalid sint values are dropped; name is added last
+        let tags = &features[0].tags;
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0], ("lanes".to_string(), TagValue::Int(3)));
+        assert_eq!(
+            tags[1],
+            ("name".to_string(), TagValue::String("Main".into()))
+        );
+        let tags = &features[1].tags;
+        assert_eq!(tags, &[("ref".to_string(), TagValue::String("A1".into()))]);
+    }
+
+    /// Make an empty directory for test files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write geometry to a loam file, as dig does
+    fn write_loam<G>(path: &Path, precision: Precision, geoms: &[G])
+    where
+        G: Narrow + Gis<f64>,
+        G::F32: Gis<f32>,
+    {
+        match precision {
+            Precision::F64 => {
+                let mut writer = BulkWriter::<f64, G>::new(path).unwrap();
+                for geom in geoms {
+                    writer.push(geom).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+            Precision::F32 => {
+                let mut writer = BulkWriter::<f32, G::F32>::new(path).unwrap();
+                for geom in geoms {
+                    writer.push(&geom.narrow().0).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+        }
+    }
+
+    /// Write a loam file of in-memory features
+    fn write_features(
+        path: &Path,
+        precision: Precision,
+        geom_tp: GeomType,
+        features: Vec<MemFeature>,
+    ) {
+        let features = features.into_iter();
+        match geom_tp {
+            GeomType::Point => {
+                let geoms: Vec<_> = features.map(mem_points).collect();
+                write_loam(path, precision, &geoms);
+            }
+            GeomType::Linestring => {
+                let geoms: Vec<_> = features.map(mem_linestrings).collect();
+                write_loam(path, precision, &geoms);
+            }
+            GeomType::Polygon => {
+                let geoms: Vec<_> = features.map(mem_polygons).collect();
+                write_loam(path, precision, &geoms);
+            }
+        }
+    }
+
+    #[test]
+    fn f32_layer() {
+        // zoom 14 tile at mid latitude
+        let base = TileId::new(3947, 5896, 14).unwrap();
+        let outline = MapGrid::default().tile_bbox(base);
+        // points are a quarter pixel off the zoom 14 rounding boundaries,
+        // well beyond f32 error (under 1 meter here)
+        let px = |x: f64, y: f64| {
+            let w = (outline.x_max() - outline.x_min()) / EXTENT;
+            let h = (outline.y_max() - outline.y_min()) / EXTENT;
+            (outline.x_min() + (x + 0.25) * w, outline.y_max() - (y + 0.25) * h)
+        };
+        let name = || vec![Some("test".to_string())];
+        let layers = [
+            (
+                "point",
+                GeomType::Point,
+                vec![
+                    (name(), vec![vec![px(100.0, 60.0)]]),
+                    (name(), vec![vec![px(7.0, 250.0), px(31.0, 13.0)]]),
+                ],
+            ),
+            (
+                "linestring",
+                GeomType::Linestring,
+                vec![(
+                    name(),
+                    vec![vec![
+                        px(20.0, 30.0),
+                        px(120.0, 200.0),
+                        px(230.0, 180.0),
+                    ]],
+                )],
+            ),
+            (
+                "polygon",
+                GeomType::Polygon,
+                vec![(
+                    name(),
+                    vec![
+                        vec![
+                            px(40.0, 200.0),
+                            px(200.0, 200.0),
+                            px(200.0, 40.0),
+                            px(40.0, 40.0),
+                            px(40.0, 200.0),
+                        ],
+                        vec![
+                            px(80.0, 80.0),
+                            px(120.0, 80.0),
+                            px(120.0, 120.0),
+                            px(80.0, 120.0),
+                            px(80.0, 80.0),
+                        ],
+                    ],
+                )],
+            ),
+        ];
+        let dir = test_dir("f32_layer");
+        for (geom_type, geom_tp, features) in layers {
+            let layer_def = layer_def(geom_type, "?name");
+            let loam64 = dir.join(format!("{geom_type}_f64.loam"));
+            let loam32 = dir.join(format!("{geom_type}_f32.loam"));
+            write_features(&loam64, Precision::F64, geom_tp, features.clone());
+            write_features(&loam32, Precision::F32, geom_tp, features);
+            let size64 = std::fs::metadata(&loam64).unwrap().len();
+            let size32 = std::fs::metadata(&loam32).unwrap().len();
+            assert!(size32 < size64, "{geom_type}: {size32} >= {size64}");
+            let t64 = GeomTree::new(geom_tp, Precision::F64, &loam64).unwrap();
+            let t32 = GeomTree::new(geom_tp, Precision::F32, &loam32).unwrap();
+            for z in [10, 11, 12, 13, 14] {
+                let shift = base.z() - z;
+                let tid = TileId::new(base.x() >> shift, base.y() >> shift, z)
+                    .unwrap();
+                let tile64 = encode_tile(&t64, &layer_def, tid, 0);
+                let tile32 = encode_tile(&t32, &layer_def, tid, 0);
+                let summary = decode_summary(&tile32).unwrap();
+                assert!(
+                    summary.layers.iter().any(|l| !l.features.is_empty()),
+                    "{geom_type} {tid}"
+                );
+                assert_eq!(tile32, tile64, "{geom_type} {tid}");
+            }
+            // tiles without features are empty at f32 too
+            let tid = TileId::new(base.x() + 2, base.y(), 14).unwrap();
+            let summary =
+                decode_summary(&encode_tile(&t32, &layer_def, tid, 0)).unwrap();
+            assert!(summary.layers.iter().all(|l| l.features.is_empty()));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn memory_query() {
+        let geoms = vec![
+            (vec![Some("a".into())], vec![vec![(0.0, 0.0)]]),
+            (vec![Some("b".into())], vec![vec![(10.0, 10.0)]]),
+        ];
+        let tree = GeomTree::from_geometries(GeomType::Point, geoms);
+        let GeomTree::Point(tree) = tree else {
+            panic!("not a point tree");
+        };
+        let bbox = BBox::new([(-1.0, -1.0), (1.0, 1.0)]);
+        let found: Vec<_> = tree
+            .tree
+            .query(bbox)
+            .map(|g| g.unwrap().values().clone())
+            .collect();
+        assert_eq!(found, [vec![Some("a".to_string())]]);
+        // bounding box edges are inclusive
+        let bbox = BBox::new([(0.0, 0.0), (10.0, 10.0)]);
+        assert_eq!(tree.tree.query(bbox).count(), 2);
+        let bbox = BBox::new([(10.5, 10.5), (20.0, 20.0)]);
+        assert_eq!(tree.tree.query(bbox).count(), 0);
+    }
+
+    /// Dense synthetic linestrings and polygons, crossing the test tile
+    fn dense_geometry(
+    ) -> (Vec<gis::Linestrings<f64, Values>>, Vec<gis::Polygons<f64, Values>>)
+    {
+        let mut lines = Vec::new();
+        let mut polygons = Vec::new();
+        for i in 0..400 {
+            let fi = f64::from(i);
+            let mut line = gis::Linestrings::new(vec![None]);
+            line.push(
+                (0..40)
+                    .map(|j| {
+                        let fj = f64::from(j);
+                        let y = (fi * 0.7 + (fj * 1.3).sin() * 8.0) % 300.0;
+                        merc(fj * 9.0 - 40.0, y - 20.0)
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            lines.push(line);
+            let (x, y) =
+                ((fi * 37.0) % 300.0 - 20.0, (fi * 53.0) % 300.0 - 20.0);
+            let r = 0.3 + (fi % 7.0) * 2.0;
+            let mut polygon = gis::Polygons::new(vec![None]);
+            polygon.push_outer(
+                (0..=12)
+                    .map(|j| {
+                        let a = f64::from(j % 12) * PI / 6.0;
+                        merc(x + r * a.cos(), y + r * a.sin())
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            polygons.push(polygon);
+        }
+        (lines, polygons)
+    }
+
+    /// Remove consecutive duplicate points, allocating (before buffer reuse)
+    fn old_dedup(pts: &[(f64, f64)], t: Transform<f64>) -> Vec<(f64, f64)> {
+        let mut out = Vec::with_capacity(pts.len());
+        let mut prev = None;
+        for &pt in pts {
+            let q = quantize(pt, t);
+            if prev != Some(q) {
+                out.push(pt);
+                prev = Some(q);
+            }
+        }
+        out
+    }
+
+    /// Encode a linestring run, allocating (before buffer reuse)
+    fn old_encode_run(
+        enc: &mut GeomEncoder<f64>,
+        run: &[(f64, f64)],
+        tile_cfg: &TileCfg,
+    ) {
+        let run: Vec<_> = run
+            .iter()
+            .copied()
+            .filter(|pt| {
+                tile_cfg.check_finite(tile_cfg.transform() * Pt::from(*pt))
+            })
+            .collect();
+        let pts = old_dedup(&run, tile_cfg.transform());
+        if pts.len() >= 2 {
+            enc.complete_geom().unwrap();
+            for (x, y) in pts {
+                enc.add_point(x, y).unwrap();
+            }
+        }
+    }
+
+    /// Encode linestrings, allocating (before buffer reuse)
+    fn old_encode_lines(
+        lines: &gis::Linestrings<f64, Values>,
+        tile_cfg: &TileCfg,
+    ) -> GeomData {
+        let bbox = tile_cfg.bbox();
+        let snap = tile_cfg.snap();
+        let mut enc = GeomEncoder::new(GeomType::Linestring)
+            .bbox(tile_cfg.encode_bbox())
+            .transform(tile_cfg.transform());
+        for line in lines.iter() {
+            let mut run = Vec::new();
+            for seg in line.segments() {
+                if seg.bounded_by(bbox) {
+                    if run.is_empty() {
+                        run.push(snap.snap((seg.p0.x, seg.p0.y)));
+                    }
+                    run.push(snap.snap((seg.p1.x, seg.p1.y)));
+                } else {
+                    old_encode_run(&mut enc, &run, tile_cfg);
+                    run.clear();
+                }
+            }
+            old_encode_run(&mut enc, &run, tile_cfg);
+        }
+        enc.encode().unwrap()
+    }
+
+    /// Encode polygons, allocating (before buffer reuse)
+    fn old_encode_polygons(
+        polygons: &gis::Polygons<f64, Values>,
+        tile_cfg: &TileCfg,
+    ) -> GeomData {
+        let t = tile_cfg.transform();
+        let snap = tile_cfg.snap();
+        let mut enc = GeomEncoder::new(GeomType::Polygon)
+            .bbox(tile_cfg.encode_bbox())
+            .transform(t);
+        for ring in polygons.iter() {
+            let mut pts = Vec::new();
+            for seg in ring.segments() {
+                if pts.is_empty() {
+                    pts.push(snap.snap((seg.p0.x, seg.p0.y)));
+                }
+                pts.push(snap.snap((seg.p1.x, seg.p1.y)));
+            }
+            pts.retain(|pt| tile_cfg.check_finite(t * Pt::from(*pt)));
+            let mut pts = old_dedup(&pts, t);
+            if pts.len() > 1
+                && quantize(pts[0], t) == quantize(pts[pts.len() - 1], t)
+            {
+                pts.pop();
+            }
+            if pts.len() < 3 {
+                continue;
+            }
+            enc.complete_geom().unwrap();
+            for (x, y) in pts {
+                enc.add_point(x, y).unwrap();
+            }
+            enc.complete_geom().unwrap();
+        }
+        enc.encode().unwrap()
+    }
+
+    /// Encode geometry into a one-layer tile
+    fn geom_tile(geoms: impl Iterator<Item = GeomData>) -> Vec<u8> {
+        let mut tile = Tile::new(256);
+        let mut layer = tile.create_layer("test");
+        for geom in geoms.filter(|g| !g.is_empty()) {
+            layer = layer.into_feature(geom).into_layer();
+        }
+        tile.add_layer(layer).unwrap();
+        tile.to_bytes().unwrap()
+    }
+
+    /// Encode dense geometry, with or without reusing buffers
+    fn dense_tiles(
+        lines: &[gis::Linestrings<f64, Values>],
+        polygons: &[gis::Polygons<f64, Values>],
+        tile_cfg: &TileCfg,
+        reuse: bool,
+    ) -> (Vec<u8>, Vec<u8>) {
+        if reuse {
+            let mut buf = EncodeBuf::default();
+            let lines = geom_tile(
+                lines.iter().map(|l| l.encode(tile_cfg, &mut buf).unwrap()),
+            );
+            let polygons = geom_tile(
+                polygons
+                    .iter()
+                    .map(|p| p.encode(tile_cfg, &mut buf).unwrap()),
+            );
+            (lines, polygons)
+        } else {
+            let lines =
+                geom_tile(lines.iter().map(|l| old_encode_lines(l, tile_cfg)));
+            let polygons = geom_tile(
+                polygons.iter().map(|p| old_encode_polygons(p, tile_cfg)),
+            );
+            (lines, polygons)
+        }
+    }
+
+    #[test]
+    fn reuse_buffers_identical() {
+        let (lines, polygons) = dense_geometry();
+        for edge in [0, 16] {
+            let wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(edge)));
+            let ctx = RequestCtx::default();
+            let tile_cfg = wyrm.test_tile_config(tid(), &ctx).unwrap();
+            let before = dense_tiles(&lines, &polygons, &tile_cfg, false);
+            let after = dense_tiles(&lines, &polygons, &tile_cfg, true);
+            assert!(before.0.len() > 10_000 && before.1.len() > 1_000);
+            assert_eq!(before, after);
+        }
+    }
+
+    /// Benchmark encoding a dense tile, with and without buffer reuse:
+    ///
+    ///   cargo test --release -p earthwyrm bench_dense_tile -- --ignored
+    #[test]
+    #[ignore]
+    fn bench_dense_tile() {
+        const ROUNDS: u32 = 200;
+        let (lines, polygons) = dense_geometry();
+        let wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(16)));
+        let ctx = RequestCtx::default();
+        let tile_cfg = wyrm.test_tile_config(tid(), &ctx).unwrap();
+        let mut times = Vec::new();
+        for reuse in [false, true] {
+            let t = std::time::Instant::now();
+            for _ in 0..ROUNDS {
+                std::hint::black_box(dense_tiles(
+                    &lines, &polygons, &tile_cfg, reuse,
+                ));
+            }
+            let elapsed = t.elapsed();
+            println!(
+                "reuse buffers {reuse}: {:.1} tiles/s",
+                f64::from(ROUNDS) / elapsed.as_secs_f64()
+            );
+            times.push(elapsed);
+        }
+        println!(
+            "speedup: {:.2}x",
+            times[0].as_secs_f64() / times[1].as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn nest_rings_containment() {
+        let ring = |x0: f64, y0: f64, w: f64, ccw: bool| {
+            let mut pts = vec![
+                (x0, y0),
+                (x0 + w, y0),
+                (x0 + w, y0 + w),
+                (x0, y0 + w),
+                (x0, y0),
+            ];
+            if !ccw {
+                pts.reverse();
+            }
+            pts
+        };
+        // island in a lake in a park, plus a separate park; windings are
+        // deliberately inconsistent
+        let park = ring(0.0, 0.0, 100.0, false);
+        let lake = ring(10.0, 10.0, 50.0, false);
+        let island = ring(20.0, 20.0, 10.0, true);
+        let other = ring(200.0, 0.0, 10.0, true);
+        let polygons = nest_rings(vec![
+            island.clone(),
+            other.clone(),
+            lake.clone(),
+            park.clone(),
+        ]);
+        assert_eq!(polygons, vec![vec![island], vec![other], vec![park, lake]]);
+    }
 }
@@ -0,0 +1,103 @@
+// id_index.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::Result;
+use pointy::BBox;
+use std::fs::File;
+#[cfg(feature = "dig")]
+use std::io::{BufWriter, Write};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Length of one index record (bytes)
+const RECORD_LEN: u64 = 40;
+
+/// Feature ID index, stored in a `.loam.ids` sidecar file.
+///
+/// Records are fixed-width, sorted by OSM ID:
+/// * OSM ID (`i64`, little endian)
+/// * Bounding box (4 × `f64`, little endian): x/y min, then x/y max
+pub struct IdIndex {
+    /// Path to index file
+    path: PathBuf,
+
+    /// Number of records
+    n_records: u64,
+}
+
+/// Get path to a loam ID index file
+pub fn index_path(loam: &Path) -> PathBuf {
+    let mut path = loam.as_os_str().to_owned();
+    path.push(".ids");
+    PathBuf::from(path)
+}
+
+/// Write an ID index for a loam file.
+///
+/// Features with the same ID (ex. split linestrings) are combined into one
+/// record, with a bounding box covering all of them.
+#[cfg(feature = "dig")]
+pub fn write_index(loam: &Path, mut ids: Vec<(i64, BBox<f64>)>) -> Result<()> {
+    ids.sort_by_key(|(id, _bbox)| *id);
+    ids.dedup_by(|(id, bbox), (prev_id, prev)| {
+        let same = id == prev_id;
+        if same {
+            prev.extend([
+                (bbox.x_min(), bbox.y_min()),
+                (bbox.x_max(), bbox.y_max()),
+            ]);
+        }
+        same
+    });
+    let mut out = BufWriter::new(File::create(index_path(loam))?);
+    for (id, bbox) in &ids {
+        out.write_all(&id.to_le_bytes())?;
+        for v in [bbox.x_min(), bbox.y_min(), bbox.x_max(), bbox.y_max()] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+impl IdIndex {
+    /// Open an ID index for a loam file, if it exists
+    pub fn open(loam: &Path) -> Result<Option<Self>> {
+        let path = index_path(loam);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let n_records = path.metadata()?.len() / RECORD_LEN;
+        Ok(Some(IdIndex { path, n_records }))
+    }
+
+    /// Look up the bounding box of a feature (binary search)
+    pub fn lookup(&self, osm_id: i64) -> Result<Option<BBox<f64>>> {
+        let mut file = File::open(&self.path)?;
+        let mut buf = [0; RECORD_LEN as usize];
+        let (mut lo, mut hi) = (0, self.n_records);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            file.seek(SeekFrom::Start(mid * RECORD_LEN))?;
+            file.read_exact(&mut buf)?;
+            let id = i64::from_le_bytes(field(&buf, 0));
+            if id < osm_id {
+                lo = mid + 1;
+            } else if id > osm_id {
+                hi = mid;
+            } else {
+                let v = |i| f64::from_le_bytes(field(&buf, i));
+                return Ok(Some(BBox::new([(v(1), v(2)), (v(3), v(4))])));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Get one 8-byte field of a record
+fn field(buf: &[u8], i: usize) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[i * 8..(i + 1) * 8]);
+    bytes
+}
@@ -2,14 +2,178 @@
 //
 // Copyright (c) 2019-2024  Minnesota Department of Transportation
 //
-use crate::config::LayerCfg;
+use crate::config::{LayerCfg, LayerStyleCfg};
 use crate::error::{Error, Result};
+use crate::geom::Values;
+use crate::schema::{PatternSchema, SyntaxSchema};
 use mvt::GeomType;
+#[cfg(feature = "dig")]
 use osmpbfreader::Tags;
+use std::borrow::Cow;
 use std::fmt;
+use std::time::SystemTime;
 
 /// Max zoom level
-const ZOOM_MAX: u32 = 30;
+pub const ZOOM_MAX: u32 = 30;
+
+/// Default maximum features queried per layer
+const QUERY_LIMIT: usize = 1_000_000;
+
+/// Default maximum relation members
+const MAX_RELATION_MEMBERS: usize = 50_000;
+
+/// Default maximum nodes in relation member ways
+const MAX_RING_NODES: usize = 2_000_000;
+
+/// Default maximum nodes per linestring
+const MAX_GEOMETRY_NODES: usize = 250_000;
+
+/// Default maximum linestring span (z10 tiles)
+const MAX_LINE_SPAN: f64 = 4.0;
+
+/// Default maximum tag value length (bytes)
+const MAX_VALUE_LEN: usize = 256;
+
+/// Number of objects checked before reordering match patterns
+#[cfg(feature = "dig")]
+const MATCH_SAMPLE: u64 = 10_000;
+
+/// Maximum length of sint values; longer strings are not parsed
+const SINT_MAX_LEN: usize = 32;
+
+/// Width of a zoom 10 tile (Web Mercator meters)
+const Z10_TILE: f64 = 2.0 * std::f64::consts::PI * 6_378_137.0 / 1024.0;
+
+/// Sint tags computed at tile-encode time, not stored in loam files
+pub(crate) const SYNTHETIC_TAGS: &[&str] = &["bbox_w", "bbox_h", "angle"];
+
+/// Shorthand token for renderer ordering tags
+const LAYERING: &str = "@layering";
+
+/// Tag patterns included by `@layering` token, in `Values` order
+const LAYERING_PATTERNS: &[&str] = &["$layer", "?bridge", "?tunnel"];
+
+/// Tag pattern rule prefixes, with descriptions.
+///
+/// The empty prefix must be last, since it matches every pattern.
+const RULE_PREFIXES: [(&str, MustMatch, IncludeValue, FeatureType, &str); 4] = [
+    (
+        ".",
+        MustMatch::Yes,
+        IncludeValue::Yes,
+        FeatureType::MvtString,
+        "must match; value included as string",
+    ),
+    (
+        "?",
+        MustMatch::No,
+        IncludeValue::Yes,
+        FeatureType::MvtString,
+        "optional; value included as string",
+    ),
+    (
+        "$",
+        MustMatch::No,
+        IncludeValue::Yes,
+        FeatureType::MvtSint,
+        "optional; value included as sint",
+    ),
+    (
+        "",
+        MustMatch::Yes,
+        IncludeValue::No,
+        FeatureType::MvtString,
+        "must match; value not included",
+    ),
+];
+
+/// Tag pattern equality operators, with descriptions
+const EQUALITY_OPS: [(Equality, &str, &str); 7] = [
+    (Equality::Equal, "=", "value equals one of the values"),
+    (Equality::NotEqual, "!=", "value does not equal any of the values"),
+    (
+        Equality::PresentNotEqual,
+        "=*!",
+        "tag present, value does not equal any of the values",
+    ),
+    (Equality::Before, "<", "date value before date"),
+    (Equality::BeforeOrOn, "<=", "date value on or before date"),
+    (Equality::After, ">", "date value after date"),
+    (Equality::AfterOrOn, ">=", "date value on or after date"),
+];
+
+/// Pattern date expression units: (unit, description, days, months)
+const DATE_UNITS: [(char, &str, i64, i64); 4] = [
+    ('d', "days", 1, 0),
+    ('w', "weeks", 7, 0),
+    ('m', "months", 0, 1),
+    ('y', "years", 0, 12),
+];
+
+/// Unit conversions for sint values (`$tag:unit` patterns)
+pub(crate) const SINT_UNITS: [SintUnit; 2] = [
+    SintUnit {
+        name: "kmh",
+        suffixes: &[
+            ("km/h", 1.0),
+            ("kmh", 1.0),
+            ("kph", 1.0),
+            ("mph", 1.609_344),
+            ("knots", 1.852),
+        ],
+    },
+    SintUnit {
+        name: "m",
+        suffixes: &[("m", 1.0), ("km", 1_000.0), ("ft", 0.304_8)],
+    },
+];
+
+/// Geometry types, by name
+pub(crate) const GEOM_TYPES: [(&str, GeomType); 3] = [
+    ("point", GeomType::Point),
+    ("linestring", GeomType::Linestring),
+    ("polygon", GeomType::Polygon),
+];
+
+/// Relation member roles for point layers
+pub(crate) const MEMBER_ROLES: [MemberRole; 2] =
+    [MemberRole::Label, MemberRole::AdminCentre];
+
+/// Geometry storage precisions
+pub(crate) const PRECISIONS: [Precision; 2] = [Precision::F32, Precision::F64];
+
+/// Derived feature kind: intersections of a linestring layer
+pub(crate) const INTERSECTIONS: &str = "intersections";
+
+/// Source of generated layers (not extracted from OSM)
+const GENERATED: &str = "generated";
+
+/// Generated shape: one polygon covering an extent
+pub(crate) const EXTENT_SHAPE: &str = "extent";
+
+/// Maximum latitude of Web Mercator (degrees)
+const LAT_MAX: f64 = 85.051_128_779_806_6;
+
+/// Default tag keys which imply a closed way is an area
+const AREA_KEYS: &[&str] = &[
+    "aeroway",
+    "amenity",
+    "boundary",
+    "building",
+    "building:part",
+    "craft",
+    "historic",
+    "landuse",
+    "leisure",
+    "man_made",
+    "military",
+    "natural",
+    "office",
+    "place",
+    "shop",
+    "tourism",
+    "water",
+];
 
 /// Layer rule definition
 #[derive(Debug)]
@@ -28,6 +192,207 @@ pub struct LayerDef {
 
     /// Tag patterns
     patterns: Vec<TagPattern>,
+
+    /// Include all closed ways in polygon layer
+    force_polygon: bool,
+
+    /// Tag keys which imply a closed way is an area
+    area_keys: Vec<String>,
+
+    /// Relation member role for point layers
+    member_role: Option<MemberRole>,
+
+    /// Drop features with no included values
+    drop_empty: bool,
+
+    /// Salvage incomplete relation polygons
+    allow_partial_polygons: bool,
+
+    /// Skip member ways with the same values as relation polygons
+    dedupe_members: bool,
+
+    /// Preferred name languages, in fallback order
+    name_languages: Vec<String>,
+
+    /// Derived features
+    derive: Option<Derive>,
+
+    /// Generated features
+    generate: Option<Generate>,
+
+    /// Layer order within tiles
+    order: i32,
+
+    /// Merge linestrings with equal tag values
+    merge_lines: bool,
+
+    /// Skip objects matching another layer
+    exclude: Option<String>,
+
+    /// Sint tag for ranking into zoom buckets
+    rank_by: Option<String>,
+
+    /// Zoom buckets (point layers)
+    rank_buckets: Vec<RankBucket>,
+
+    /// Maximum features queried
+    query_limit: Option<usize>,
+
+    /// Maximum relation members
+    max_relation_members: usize,
+
+    /// Maximum nodes in relation member ways
+    max_ring_nodes: usize,
+
+    /// Maximum nodes per linestring
+    max_geometry_nodes: usize,
+
+    /// Maximum linestring span (z10 tiles)
+    max_line_span: Option<f64>,
+
+    /// Maximum tag value length (bytes)
+    max_value_len: Option<usize>,
+
+    /// Data attribution
+    attribution: Option<String>,
+
+    /// Data license
+    license: Option<String>,
+
+    /// Demo map style
+    style: Option<LayerStyleCfg>,
+
+    /// Build OSM ID index
+    id_index: bool,
+
+    /// Add synthetic `minzoom` tag
+    emit_minzoom: bool,
+
+    /// Geometry storage precision
+    precision: Precision,
+
+    /// Name of layer whose loam file is shared
+    loam: Option<String>,
+
+    /// Serve-time filter patterns, with index of each stored value
+    filter: Vec<(TagPattern, usize)>,
+
+    /// Constant tags added to every feature (not stored in loam)
+    constants: Vec<(String, String)>,
+
+    /// Date of `now` in date patterns
+    dates_at: Option<Date>,
+}
+
+/// Zoom bucket of a ranked point layer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RankBucket {
+    /// Minimum zoom level
+    pub zoom_min: u32,
+
+    /// Maximum zoom level, before the next bucket
+    pub zoom_max: u32,
+
+    /// Minimum rank value (`None` for last bucket)
+    pub min_rank: Option<i64>,
+}
+
+/// Features derived from another layer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Derive {
+    /// Nodes shared by ways of a linestring layer
+    Intersections {
+        /// Source layer name
+        source: String,
+
+        /// Include dead-end nodes
+        include_ends: bool,
+    },
+}
+
+/// Features generated at dig time (`source: generated`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Generate {
+    /// One polygon covering an extent
+    Extent {
+        /// Bounds (`[west, south, east, north]` in degrees), or `None` for
+        /// OSM file bounds
+        bounds: Option<[f64; 4]>,
+    },
+}
+
+/// Result of checking one tag pattern against an object
+#[derive(Clone, Debug)]
+pub struct PatternCheck {
+    /// Tag pattern
+    pub pattern: String,
+
+    /// Object's value for the pattern tag
+    pub value: Option<String>,
+
+    /// Pattern matched (always true for patterns not requiring a match)
+    pub matched: bool,
+}
+
+/// Match pattern statistics for a layer, gathered while digging.
+///
+/// Patterns are evaluated in `order`; after a sampling phase, it can be
+/// sorted so that patterns which fail most often are checked first.  All
+/// match patterns must pass, so the order never changes the result.
+#[cfg(feature = "dig")]
+#[derive(Debug)]
+pub(crate) struct MatchStats {
+    /// Indices of match patterns, in evaluation order
+    order: Vec<usize>,
+
+    /// Evaluations of each pattern (by pattern index)
+    checked: Vec<u64>,
+
+    /// Failures of each pattern (by pattern index)
+    failed: Vec<u64>,
+
+    /// Number of objects checked
+    n_obj: u64,
+
+    /// Reorder patterns after sampling
+    reorder: bool,
+
+    /// Patterns were reordered
+    reordered: bool,
+}
+
+/// Relation member role used to locate points
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MemberRole {
+    /// `label` member node
+    Label,
+
+    /// `admin_centre` member node
+    AdminCentre,
+}
+
+/// Precision of stored geometry coordinates.
+///
+/// `f32` Web Mercator coordinates are accurate to about 2 meters, which is
+/// enough for most layers, and halves the size of loam files.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Precision {
+    /// 32-bit float coordinates
+    F32,
+
+    /// 64-bit float coordinates
+    #[default]
+    F64,
+}
+
+/// Unit of sint values, with conversions from other units
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SintUnit {
+    /// Unit name (empty for no unit)
+    name: &'static str,
+
+    /// Value suffixes, with conversion factors to the unit
+    suffixes: &'static [(&'static str, f64)],
 }
 
 /// Tag pattern specification for layer rule
@@ -45,11 +410,42 @@ struct TagPattern {
     /// Tag name
     tag: String,
 
+    /// Unit of sint values
+    unit: SintUnit,
+
     /// Pattern equality
     equality: Equality,
 
     /// Pattern values
     values: Vec<String>,
+
+    /// Default value for missing / invalid values
+    default: Option<String>,
+
+    /// Value stored, but not included directly in tiles
+    hidden: bool,
+
+    /// Value computed at tile-encode time (not stored)
+    synthetic: bool,
+
+    /// Date for comparison patterns
+    date: Option<Date>,
+
+    /// Zoom range for included values (`?name:de@14+`)
+    zoom: Option<(u32, u32)>,
+}
+
+/// Calendar date, for comparing date tag values
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Date {
+    /// Year
+    year: i64,
+
+    /// Month (1-12)
+    month: i64,
+
+    /// Day of month (1-31)
+    day: i64,
 }
 
 /// Tag pattern specification to require matching tag
@@ -82,7 +478,8 @@ enum FeatureType {
     MvtSint,
 }
 
-/// Tag pattern specification to match value equal vs. not equal
+/// Tag pattern specification to match value equal vs. not equal, or to
+/// compare dates
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Equality {
     /// Pattern equals value
@@ -90,26 +487,43 @@ enum Equality {
 
     /// Pattern not equal value
     NotEqual,
+
+    /// Tag present, with value not equal to pattern
+    PresentNotEqual,
+
+    /// Date value before pattern date
+    Before,
+
+    /// Date value before or on pattern date
+    BeforeOrOn,
+
+    /// Date value after pattern date
+    After,
+
+    /// Date value after or on pattern date
+    AfterOrOn,
 }
 
 impl fmt::Display for TagPattern {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let prefix = match (self.must_match, self.include, self.feature_type) {
-            (MustMatch::No, _, FeatureType::MvtSint) => "$",
-            (MustMatch::No, _, FeatureType::MvtString) => "?",
-            (MustMatch::Yes, IncludeValue::Yes, _) => ".",
-            _ => "",
-        };
+        let rule = (self.must_match, self.include, self.feature_type);
+        let prefix = RULE_PREFIXES
+            .iter()
+            .find(|(_, m, i, t, _)| (*m, *i, *t) == rule)
+            .map_or("", |(prefix, ..)| *prefix);
         write!(f, "{prefix}{}", &self.tag)?;
+        if !self.unit.name.is_empty() {
+            write!(f, ":{}", self.unit.name)?;
+        }
         if let (Equality::NotEqual, Some("_")) =
             (self.equality, self.values.first().map(String::as_str))
         {
-            return Ok(());
+            return write_zoom(f, self.zoom);
         }
-        let equality = match self.equality {
-            Equality::Equal => "=",
-            Equality::NotEqual => "!=",
-        };
+        let equality = EQUALITY_OPS
+            .iter()
+            .find(|(eq, ..)| *eq == self.equality)
+            .map_or("", |(_, op, _)| *op);
         write!(f, "{equality}")?;
         for (i, val) in self.values.iter().enumerate() {
             if i > 0 {
@@ -117,7 +531,45 @@ impl fmt::Display for TagPattern {
             }
             write!(f, "{val}")?;
         }
-        Ok(())
+        write_zoom(f, self.zoom)
+    }
+}
+
+/// Write the zoom qualifier of a pattern (`@14+`)
+fn write_zoom(f: &mut fmt::Formatter, zoom: Option<(u32, u32)>) -> fmt::Result {
+    match zoom {
+        Some((z0, ZOOM_MAX)) => write!(f, "@{z0}+"),
+        Some((z0, z1)) if z0 == z1 => write!(f, "@{z0}"),
+        Some((z0, z1)) => write!(f, "@{z0}-{z1}"),
+        None => Ok(()),
+    }
+}
+
+impl MemberRole {
+    /// Get the role as a string slice
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MemberRole::Label => "label",
+            MemberRole::AdminCentre => "admin_centre",
+        }
+    }
+
+    /// Get roles to check, in order of preference
+    pub fn fallbacks(self) -> &'static [MemberRole] {
+        match self {
+            MemberRole::Label => &[MemberRole::Label, MemberRole::AdminCentre],
+            MemberRole::AdminCentre => &[MemberRole::AdminCentre],
+        }
+    }
+}
+
+impl Precision {
+    /// Get the precision as a string slice
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precision::F32 => "f32",
+            Precision::F64 => "f64",
+        }
     }
 }
 
@@ -135,20 +587,76 @@ impl TagPattern {
         }
     }
 
-    /// Get tag for include patterns only
+    /// Get tag for (stored) include patterns only
     fn include_tag(&self) -> Option<&str> {
-        match self.include {
-            IncludeValue::Yes => Some(self.tag()),
-            IncludeValue::No => None,
+        match (self.include, self.synthetic) {
+            (IncludeValue::Yes, false) => Some(self.tag()),
+            _ => None,
         }
     }
 
+    /// Get tag for synthetic patterns only
+    fn synthetic_tag(&self) -> Option<&str> {
+        self.synthetic.then(|| self.tag())
+    }
+
     /// Check if the value matches
     fn matches_value(&self, value: Option<&str>) -> bool {
         debug_assert!(self.must_match == MustMatch::Yes);
         match self.equality {
             Equality::Equal => self.matches_value_option(value),
             Equality::NotEqual => !self.matches_value_option(value),
+            Equality::PresentNotEqual => {
+                value.is_some() && !self.matches_value_option(value)
+            }
+            // unparseable dates do not match
+            _ => match (value.and_then(Date::parse), self.date) {
+                (Some(val), Some(date)) => match self.equality {
+                    Equality::Before => val < date,
+                    Equality::BeforeOrOn => val <= date,
+                    Equality::After => val > date,
+                    _ => val >= date,
+                },
+                _ => false,
+            },
+        }
+    }
+
+    /// Check if the pattern compares dates
+    fn is_date(&self) -> bool {
+        matches!(
+            self.equality,
+            Equality::Before
+                | Equality::BeforeOrOn
+                | Equality::After
+                | Equality::AfterOrOn
+        )
+    }
+
+    /// Check if the pattern date is relative to `now`
+    fn is_relative_date(&self) -> bool {
+        self.is_date() && self.values.iter().any(|v| v.starts_with("now"))
+    }
+
+    /// Resolve the date of a comparison pattern.
+    ///
+    /// * `today` Date of `now`.
+    fn resolve_date(&mut self, today: Date) -> Result<()> {
+        if !self.is_date() {
+            return Ok(());
+        }
+        let date = match &self.values[..] {
+            [value] if self.must_match == MustMatch::Yes => {
+                Date::parse_expr(value, today)
+            }
+            _ => None,
+        };
+        match date {
+            Some(date) => {
+                self.date = Some(date);
+                Ok(())
+            }
+            None => Err(Error::InvalidDatePattern(self.to_string())),
         }
     }
 
@@ -163,28 +671,58 @@ impl TagPattern {
 
     /// Parse a tag pattern rule
     fn parse_rule(pat: &str) -> (MustMatch, IncludeValue, FeatureType, &str) {
-        if let Some(pat) = pat.strip_prefix('.') {
-            (MustMatch::Yes, IncludeValue::Yes, FeatureType::MvtString, pat)
-        } else if let Some(pat) = pat.strip_prefix('?') {
-            (MustMatch::No, IncludeValue::Yes, FeatureType::MvtString, pat)
-        } else if let Some(pat) = pat.strip_prefix('$') {
-            (MustMatch::No, IncludeValue::Yes, FeatureType::MvtSint, pat)
-        } else {
-            (MustMatch::Yes, IncludeValue::No, FeatureType::MvtString, pat)
+        for (prefix, must_match, include, feature_type, _) in RULE_PREFIXES {
+            if let Some(pat) = pat.strip_prefix(prefix) {
+                return (must_match, include, feature_type, pat);
+            }
         }
+        (MustMatch::Yes, IncludeValue::No, FeatureType::MvtString, pat)
     }
 
     /// Parse the equality portion
     fn parse_equality(pat: &str) -> (&str, Equality, &str) {
+        // date comparison, unless `<` / `>` is within a value
+        if let Some(i) = pat.find(['<', '>']) {
+            if pat.find('=').map_or(true, |e| i < e) {
+                let (tag, cmp) = pat.split_at(i);
+                return match cmp.split_at(1) {
+                    ("<", v) => match v.strip_prefix('=') {
+                        Some(v) => (tag, Equality::BeforeOrOn, v),
+                        None => (tag, Equality::Before, v),
+                    },
+                    (_, v) => match v.strip_prefix('=') {
+                        Some(v) => (tag, Equality::AfterOrOn, v),
+                        None => (tag, Equality::After, v),
+                    },
+                };
+            }
+        }
         match pat.split_once('=') {
             Some((tag, values)) => match tag.strip_suffix('!') {
                 Some(tag) => (tag, Equality::NotEqual, values),
-                None => (tag, Equality::Equal, values),
+                None => match values.strip_prefix("*!") {
+                    Some(values) => (tag, Equality::PresentNotEqual, values),
+                    None => (tag, Equality::Equal, values),
+                },
             },
             None => (pat, Equality::NotEqual, "_"),
         }
     }
 
+    /// Parse a unit suffix of a sint tag (ex. `maxspeed:kmh`).
+    ///
+    /// Only known units are parsed, since tags can contain `:`.
+    fn parse_unit(tag: &str, feature_type: FeatureType) -> (&str, SintUnit) {
+        if feature_type == FeatureType::MvtSint {
+            if let Some((base, name)) = tag.rsplit_once(':') {
+                if let Some(unit) = SINT_UNITS.iter().find(|u| u.name == name) {
+                    return (base, *unit);
+                }
+            }
+        }
+        (tag, SintUnit::default())
+    }
+
     /// Parse the value(s) portion
     fn parse_values(values: &str) -> Vec<String> {
         values.split('|').map(|v| v.to_string()).collect()
@@ -195,6 +733,10 @@ impl TagPattern {
         let (must_match, include, feature_type, pat) =
             TagPattern::parse_rule(pat);
         let (tag, equality, values) = TagPattern::parse_equality(pat);
+        let (tag, unit) = TagPattern::parse_unit(tag, feature_type);
+        let synthetic = must_match == MustMatch::No
+            && feature_type == FeatureType::MvtSint
+            && SYNTHETIC_TAGS.contains(&tag);
         let tag = tag.to_string();
         let values = TagPattern::parse_values(values);
         TagPattern {
@@ -202,17 +744,159 @@ impl TagPattern {
             include,
             feature_type,
             tag,
+            unit,
             equality,
             values,
+            default: None,
+            hidden: false,
+            synthetic,
+            date: None,
+            zoom: None,
+        }
+    }
+
+    /// Check if the value is included at a zoom level
+    fn check_zoom(&self, zoom: u32) -> bool {
+        self.zoom.is_none_or(|(z0, z1)| zoom >= z0 && zoom <= z1)
+    }
+}
+
+/// Get a (year, month, day) civil date from days since 1970-01-01.
+///
+/// Dates are in the proleptic Gregorian calendar.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Get number of days in a month
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// Get today's date (UTC)
+    fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Date::from_days(secs as i64 / 86_400)
+    }
+
+    /// Make a date from days since 1970-01-01 (proleptic Gregorian)
+    fn from_days(days: i64) -> Self {
+        let (year, month, day) = civil_from_days(days);
+        Date { year, month, day }
+    }
+
+    /// Get days since 1970-01-01
+    fn days(self) -> i64 {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (self.month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// Add a number of months, clamping the day to the month's length
+    fn add_months(self, months: i64) -> Self {
+        let m = self.year * 12 + self.month - 1 + months;
+        let (year, month) = (m.div_euclid(12), m.rem_euclid(12) + 1);
+        let day = self.day.min(days_in_month(year, month));
+        Date { year, month, day }
+    }
+
+    /// Parse a loose OSM date value.
+    ///
+    /// Accepts `YYYY`, `YYYY-MM` and `YYYY-MM-DD`, with an optional `~`
+    /// (approximate) prefix, `s` (decade) suffix on years, or time of day.
+    /// Missing months and days are the first of the year / month.
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let value = value.strip_prefix('~').unwrap_or(value).trim_start();
+        let date = value.split(['T', ' ']).next()?;
+        let mut parts = date.split('-');
+        let year = parts.next()?;
+        let year = year.strip_suffix('s').unwrap_or(year);
+        let month = parts.next();
+        let day = parts.next();
+        if parts.next().is_some() {
+            return None;
+        }
+        let year = parse_date_part(year, 4)?;
+        let month = month.map_or(Some(1), |m| parse_date_part(m, 2))?;
+        let day = day.map_or(Some(1), |d| parse_date_part(d, 2))?;
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+        {
+            return None;
         }
+        Some(Date { year, month, day })
+    }
+
+    /// Parse a pattern date expression: a date, `now`, or `now` plus /
+    /// minus a duration (`<n>d`, `<n>w`, `<n>m` or `<n>y`)
+    fn parse_expr(expr: &str, today: Date) -> Option<Self> {
+        let Some(offset) = expr.strip_prefix("now") else {
+            return Date::parse(expr);
+        };
+        if offset.is_empty() {
+            return Some(today);
+        }
+        let (sign, dur) = match offset.split_at(1) {
+            ("+", dur) => (1, dur),
+            ("-", dur) => (-1, dur),
+            _ => return None,
+        };
+        let unit = dur.chars().last()?;
+        let n: i64 = dur[..dur.len() - unit.len_utf8()].parse().ok()?;
+        let n = sign * n;
+        let (_, _, days, months) =
+            DATE_UNITS.into_iter().find(|(u, ..)| *u == unit)?;
+        Some(Date::from_days(today.days() + n * days).add_months(n * months))
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Parse a numeric date part, with an exact number of digits
+fn parse_date_part(part: &str, digits: usize) -> Option<i64> {
+    if part.len() == digits && part.bytes().all(|b| b.is_ascii_digit()) {
+        part.parse().ok()
+    } else {
+        None
     }
 }
 
 /// Parse the zoom portion of a layer rule
-fn parse_zoom_range(z: &str) -> Result<(u32, u32)> {
+pub(crate) fn parse_zoom_range(z: &str) -> Result<(u32, u32)> {
     if let Some((a, b)) = z.split_once('-') {
         let zoom_min = parse_zoom(a)?;
         let zoom_max = parse_zoom(b)?;
+        if zoom_min > zoom_max {
+            return Err(Error::InvalidZoomRange(z.into()));
+        }
         Ok((zoom_min, zoom_max))
     } else if let Some(z) = z.strip_suffix('+') {
         let zoom_min = parse_zoom(z)?;
@@ -235,101 +919,1764 @@ fn parse_zoom(zoom: &str) -> Result<u32> {
 
 /// Parse tag patterns of a layer rule
 fn parse_patterns(tags: &[String]) -> Result<Vec<TagPattern>> {
+    let today = Date::today();
     let mut patterns = Vec::<TagPattern>::new();
     for pat in tags {
-        let p = TagPattern::parse(pat);
-        let tag = p.tag();
-        if patterns.iter().any(|p| p.tag() == tag) {
-            return Err(Error::DuplicatePattern(pat.to_string()));
+        if pat == LAYERING {
+            for pat in LAYERING_PATTERNS {
+                let mut p = TagPattern::parse(pat);
+                if p.feature_type == FeatureType::MvtSint {
+                    p.default = Some("0".to_string());
+                }
+                push_pattern(&mut patterns, p, pat)?;
+            }
+        } else {
+            let (rule, zoom) = split_zoom(pat)?;
+            let mut p = TagPattern::parse(rule);
+            p.zoom = zoom;
+            p.resolve_date(today)?;
+            push_pattern(&mut patterns, p, pat)?;
         }
-        log::trace!("tag pattern: {p}");
-        patterns.push(p);
     }
     Ok(patterns)
 }
 
-/// Parse geometry type
-fn parse_geom_type(geom_tp: &str) -> Result<GeomType> {
-    match geom_tp {
-        "point" => Ok(GeomType::Point),
-        "linestring" => Ok(GeomType::Linestring),
-        "polygon" => Ok(GeomType::Polygon),
-        _ => Err(Error::UnknownGeometryType()),
+/// Split the zoom qualifier (`@14+`) from an optional include pattern
+fn split_zoom(pat: &str) -> Result<(&str, Option<(u32, u32)>)> {
+    match pat.rsplit_once('@') {
+        Some((rule, zoom)) if rule.starts_with(['?', '$']) => {
+            Ok((rule, Some(parse_zoom_range(zoom)?)))
+        }
+        _ => Ok((pat, None)),
     }
 }
 
-impl TryFrom<&LayerCfg> for LayerDef {
-    type Error = Error;
+/// Check if two patterns are a sint include and a match-only pattern.
+///
+/// These may share a tag, so that a `$tag` can be matched on a value
+/// (as done by `split_by`).
+fn is_sint_match(a: &TagPattern, b: &TagPattern) -> bool {
+    let sint = |p: &TagPattern| {
+        p.must_match == MustMatch::No && p.feature_type == FeatureType::MvtSint
+    };
+    let matching = |p: &TagPattern| {
+        p.must_match == MustMatch::Yes && p.include == IncludeValue::No
+    };
+    (sint(a) && matching(b)) || (matching(a) && sint(b))
+}
 
-    fn try_from(layer: &LayerCfg) -> Result<Self> {
-        let name = layer.name.to_string();
-        let geom_tp = parse_geom_type(&layer.geom_type)?;
-        let (zoom_min, zoom_max) = parse_zoom_range(&layer.zoom)?;
-        log::trace!("zoom: {}-{}", zoom_min, zoom_max);
-        let patterns = parse_patterns(&layer.tags)?;
-        Ok(LayerDef {
-            name,
-            geom_tp,
-            zoom_min,
-            zoom_max,
-            patterns,
-        })
+/// Describe the tag pattern grammar, from the parser tables
+pub(crate) fn pattern_schema() -> PatternSchema {
+    let prefixes = RULE_PREFIXES
+        .iter()
+        .map(|(prefix, .., desc)| SyntaxSchema::new(*prefix, desc))
+        .collect();
+    let mut operators: Vec<_> = EQUALITY_OPS
+        .iter()
+        .map(|(_, op, desc)| SyntaxSchema::new(*op, desc))
+        .collect();
+    operators.push(SyntaxSchema::new("", "tag present (same as `!=_`)"));
+    let values = vec![
+        SyntaxSchema::new("_", "tag missing"),
+        SyntaxSchema::new("|", "separates values (any may match)"),
+    ];
+    let mut dates = vec![
+        SyntaxSchema::new("YYYY[-MM[-DD]]", "calendar date"),
+        SyntaxSchema::new("now", "today (UTC)"),
+    ];
+    dates.extend(DATE_UNITS.iter().map(|(unit, desc, ..)| {
+        SyntaxSchema::new(format!("now+<n>{unit}"), desc)
+    }));
+    dates.extend(DATE_UNITS.iter().map(|(unit, desc, ..)| {
+        SyntaxSchema::new(format!("now-<n>{unit}"), desc)
+    }));
+    PatternSchema {
+        form: "[prefix]tag[operator value[|value...]]".into(),
+        prefixes,
+        operators,
+        values,
+        dates,
+        synthetic_tags: SYNTHETIC_TAGS.iter().map(|t| t.to_string()).collect(),
+        shorthands: vec![SyntaxSchema::new(
+            LAYERING,
+            &LAYERING_PATTERNS.join(" "),
+        )],
     }
 }
 
-impl LayerDef {
-    /// Get the layer name
-    pub fn name(&self) -> &str {
-        &self.name
+/// Push a tag pattern, checking for duplicates
+fn push_pattern(
+    patterns: &mut Vec<TagPattern>,
+    p: TagPattern,
+    pat: &str,
+) -> Result<()> {
+    let tag = p.tag();
+    if patterns
+        .iter()
+        .any(|q| q.tag() == tag && !is_sint_match(q, &p))
+    {
+        return Err(Error::DuplicatePattern(pat.to_string()));
     }
+    log::trace!("tag pattern: {p}");
+    patterns.push(p);
+    Ok(())
+}
 
-    /// Get the geometry type
-    pub fn geom_tp(&self) -> GeomType {
-        self.geom_tp
+/// Parse a sint tag value, rejecting overlong strings
+pub fn parse_sint(value: &str) -> Option<i64> {
+    if value.len() > SINT_MAX_LEN {
+        return None;
     }
+    value.parse().ok()
+}
 
-    /// Get a slice of tag patterns
-    fn patterns(&self) -> &[TagPattern] {
-        &self.patterns
+/// Normalize a sint value when digging.
+///
+/// A leading `~` and thousands separators (`50,000`) are removed, and
+/// decimals are rounded.  Values with a unit suffix (ex. `30 mph`) are
+/// converted to the pattern's unit; without a unit, suffixes are invalid,
+/// so a plain `$ele` pattern drops `1,234 m` (use `$ele:m`).  Returns `None`
+/// if the value cannot be parsed.
+pub(crate) fn normalize_sint(value: &str, unit: SintUnit) -> Option<String> {
+    if parse_sint(value).is_some() {
+        return Some(value.to_string());
     }
+    let value = value.trim();
+    let value = value.strip_prefix('~').unwrap_or(value).trim_start();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || "+-.,".contains(c)))
+        .unwrap_or(value.len());
+    let (num, suffix) = value.split_at(end);
+    let mut groups = num.split(',');
+    let first = groups.next()?;
+    let mut num = first.to_string();
+    for group in groups {
+        // every group after a thousands separator has 3 digits
+        let digits = group.split('.').next().unwrap_or(group);
+        if digits.len() != 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        num.push_str(group);
+    }
+    let mut val: f64 = num.parse().ok()?;
+    let suffix = suffix.trim();
+    if !suffix.is_empty() {
+        let (_, factor) = unit.suffixes.iter().find(|(s, _)| *s == suffix)?;
+        val *= factor;
+    }
+    if !val.is_finite() {
+        return None;
+    }
+    // `as` saturates at i64 bounds
+    Some((val.round() as i64).to_string())
+}
 
-    /// Check if zoom level matches
-    pub fn check_zoom(&self, zoom: u32) -> bool {
-        zoom >= self.zoom_min && zoom <= self.zoom_max
+/// Get geometry type name
+pub fn geom_type_name(geom_tp: GeomType) -> &'static str {
+    GEOM_TYPES
+        .iter()
+        .find(|(_, tp)| *tp == geom_tp)
+        .map_or("", |(name, _)| *name)
+}
+
+/// Parse geometry type
+fn parse_geom_type(geom_tp: &str) -> Result<GeomType> {
+    GEOM_TYPES
+        .iter()
+        .find(|(name, _)| *name == geom_tp)
+        .map(|(_, tp)| *tp)
+        .ok_or(Error::UnknownGeometryType())
+}
+
+/// Tag lookup for OSM objects
+pub trait OsmTags {
+    /// Get the value of a tag
+    fn tag(&self, key: &str) -> Option<&str>;
+}
+
+#[cfg(feature = "dig")]
+impl OsmTags for Tags {
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|v| v.as_str())
     }
+}
 
-    /// Check if OSM tags match all patterns
-    pub fn check_tags(&self, tags: &Tags) -> bool {
-        for pattern in self.patterns() {
-            if let Some(tag) = pattern.match_tag() {
-                let value = tags.get(tag).map(|t| t.as_str());
-                if !pattern.matches_value(value) {
-                    return false;
-                }
-            }
+#[cfg(feature = "dig")]
+impl MatchStats {
+    /// Record one pattern evaluation
+    fn record(&mut self, i: usize, pass: bool) {
+        self.checked[i] += 1;
+        if !pass {
+            self.failed[i] += 1;
         }
-        true
     }
 
-    /// Get an iterator of tags to include
-    pub fn tags(&self) -> impl Iterator<Item = &str> {
-        self.patterns().iter().filter_map(|pat| pat.include_tag())
+    /// Record one checked object, reordering after the sampling phase
+    fn next_obj(&mut self) {
+        self.n_obj += 1;
+        if self.reorder && !self.reordered && self.n_obj == MATCH_SAMPLE {
+            let rate = |i: usize| match self.checked[i] {
+                0 => 0.0,
+                n => self.failed[i] as f64 / n as f64,
+            };
+            let mut order = self.order.clone();
+            // stable sort keeps config order for equal rates
+            order.sort_by(|a, b| rate(*b).total_cmp(&rate(*a)));
+            self.reordered = order != self.order;
+            self.order = order;
+        }
     }
 
-    /// Get an iterator of included tags, values and sint flags
-    pub fn tag_values<'a>(
-        &'a self,
-        values: &'a [Option<String>],
-    ) -> impl Iterator<Item = (&'a str, &'a str, bool)> {
-        self.patterns()
-            .iter()
-            .filter_map(|pat| {
-                pat.include_tag()
-                    .map(|tag| (tag, pat.feature_type == FeatureType::MvtSint))
-            })
-            .zip(values)
-            .filter_map(|((tag, sint), val)| {
-                val.as_ref().map(|val| (tag, &val[..], sint))
-            })
+    /// Print statistics for each pattern, in evaluation order
+    pub(crate) fn print(&self, layer: &LayerDef) {
+        if self.n_obj == 0 {
+            return;
+        }
+        if self.reordered {
+            println!("    patterns reordered after {MATCH_SAMPLE} objects");
+        }
+        for i in &self.order {
+            println!(
+                "    pattern: {} ({} checked, {} failed)",
+                layer.patterns[*i], self.checked[*i], self.failed[*i]
+            );
+        }
+    }
+}
+
+/// Check if OSM tags indicate a closed way is an area
+///
+/// Explicit `area=yes` / `area=no` tags take precedence over `keys`.
+pub fn is_area<T: OsmTags + ?Sized>(tags: &T, keys: &[String]) -> bool {
+    match tags.tag("area") {
+        Some("yes") => true,
+        Some("no") => false,
+        _ => keys.iter().any(|k| tags.tag(k).is_some()),
+    }
+}
+
+/// Parse relation member role
+fn parse_member_role(role: Option<&str>) -> Result<Option<MemberRole>> {
+    match role {
+        None => Ok(None),
+        Some(role) => MEMBER_ROLES
+            .into_iter()
+            .find(|r| r.as_str() == role)
+            .map(Some)
+            .ok_or_else(|| Error::UnknownMemberRole(role.to_string())),
+    }
+}
+
+/// Parse geometry storage precision
+pub(crate) fn parse_precision(precision: Option<&str>) -> Result<Precision> {
+    match precision {
+        None => Ok(Precision::default()),
+        Some(precision) => PRECISIONS
+            .into_iter()
+            .find(|p| p.as_str() == precision)
+            .ok_or_else(|| Error::UnknownPrecision(precision.to_string())),
+    }
+}
+
+/// Parse derived features
+fn parse_derive(layer: &LayerCfg, geom_tp: GeomType) -> Result<Option<Derive>> {
+    match (layer.derive.as_deref(), layer.source.as_deref()) {
+        (None, None) => Ok(None),
+        (None, Some(GENERATED)) => Ok(None),
+        (Some(INTERSECTIONS), Some(source)) => {
+            if geom_tp != GeomType::Point {
+                return Err(Error::InvalidDerive(format!(
+                    "layer '{}': intersections must be points",
+                    layer.name
+                )));
+            }
+            Ok(Some(Derive::Intersections {
+                source: source.to_string(),
+                include_ends: layer.include_ends,
+            }))
+        }
+        (Some(INTERSECTIONS), None) => Err(Error::InvalidDerive(format!(
+            "layer '{}': missing source",
+            layer.name
+        ))),
+        (None, Some(_)) => Err(Error::InvalidDerive(format!(
+            "layer '{}': source without derive",
+            layer.name
+        ))),
+        (Some(derive), _) => Err(Error::InvalidDerive(format!(
+            "layer '{}': {derive}",
+            layer.name
+        ))),
+    }
+}
+
+/// Parse generated features
+fn parse_generate(
+    layer: &LayerCfg,
+    geom_tp: GeomType,
+) -> Result<Option<Generate>> {
+    let generated =
+        layer.derive.is_none() && layer.source.as_deref() == Some(GENERATED);
+    match (generated, layer.shape.as_deref()) {
+        (false, None) => Ok(None),
+        (false, Some(_)) => Err(Error::InvalidGenerate(format!(
+            "layer '{}': shape without source: generated",
+            layer.name
+        ))),
+        (true, Some(EXTENT_SHAPE)) => {
+            if geom_tp != GeomType::Polygon {
+                return Err(Error::InvalidGenerate(format!(
+                    "layer '{}': extent must be polygon",
+                    layer.name
+                )));
+            }
+            let bounds = match &layer.extent {
+                Some(extent) => Some(parse_extent(extent)?),
+                None => None,
+            };
+            Ok(Some(Generate::Extent { bounds }))
+        }
+        (true, None) => Err(Error::InvalidGenerate(format!(
+            "layer '{}': missing shape",
+            layer.name
+        ))),
+        (true, Some(shape)) => Err(Error::InvalidGenerate(format!(
+            "layer '{}': unknown shape {shape}",
+            layer.name
+        ))),
+    }
+}
+
+/// Parse an extent (`west south east north`, in degrees).
+///
+/// Latitudes are clamped to the Web Mercator range.
+pub(crate) fn parse_extent(extent: &str) -> Result<[f64; 4]> {
+    let invalid = || Error::InvalidGenerate(format!("extent: {extent}"));
+    let vals = extent
+        .split_whitespace()
+        .map(|v| v.parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>>>()?;
+    let [west, south, east, north] = vals[..] else {
+        return Err(invalid());
+    };
+    if !(-180.0..=180.0).contains(&west)
+        || !(-180.0..=180.0).contains(&east)
+        || !(-90.0..=90.0).contains(&south)
+        || !(-90.0..=90.0).contains(&north)
+        || west >= east
+        || south >= north
+    {
+        return Err(invalid());
+    }
+    Ok([west, south.max(-LAT_MAX), east, north.min(LAT_MAX)])
+}
+
+/// Check a layer configuration, finding errors for all fields
+pub fn check_layer_cfg(layer: &LayerCfg) -> Vec<(&'static str, Error)> {
+    let mut errors = Vec::new();
+    match parse_geom_type(&layer.geom_type) {
+        Ok(geom_tp) => {
+            if let Err(e) = parse_derive(layer, geom_tp) {
+                errors.push(("derive", e));
+            }
+            if let Err(e) = parse_generate(layer, geom_tp) {
+                errors.push(("shape", e));
+            }
+        }
+        Err(e) => errors.push(("geom_type", e)),
+    }
+    if let Err(e) = parse_zoom_range(&layer.zoom) {
+        errors.push(("zoom", e));
+    }
+    match parse_patterns(&layer.tags) {
+        Ok(patterns) => {
+            if let Err(e) = check_id_index(layer, &patterns) {
+                errors.push(("id_index", e));
+            }
+            if let Err(e) = parse_filter(layer, &patterns) {
+                errors.push(("filter", e));
+            }
+            if let Err(e) = parse_constants(layer, &patterns) {
+                errors.push(("constants", e));
+            }
+        }
+        Err(e) => errors.push(("tags", e)),
+    }
+    if let Err(e) = parse_member_role(layer.member_role.as_deref()) {
+        errors.push(("member_role", e));
+    }
+    if let Err(e) = parse_precision(layer.precision.as_deref()) {
+        errors.push(("precision", e));
+    }
+    if let Err(e) = parse_split(layer) {
+        errors.push(("split_by", e));
+    }
+    if let Err(e) = parse_rank(layer) {
+        errors.push(("rank_zoom", e));
+    }
+    errors
+}
+
+/// Parse constant tags.
+///
+/// Each constant is `tag=value`; `${VAR}` in a value is replaced with the
+/// environment variable.  Tags must not collide with included or synthetic
+/// tags.
+fn parse_constants(
+    layer: &LayerCfg,
+    patterns: &[TagPattern],
+) -> Result<Vec<(String, String)>> {
+    let mut constants: Vec<(String, String)> =
+        Vec::with_capacity(layer.constants.len());
+    for con in &layer.constants {
+        let (tag, value) = con.split_once('=').ok_or_else(|| {
+            Error::InvalidConstant(format!("{con}: expected tag=value"))
+        })?;
+        if tag.is_empty() {
+            return Err(Error::InvalidConstant(format!("{con}: empty tag")));
+        }
+        let collides = patterns
+            .iter()
+            .filter_map(|p| p.include_tag().or_else(|| p.synthetic_tag()))
+            .any(|t| t == tag);
+        if collides || constants.iter().any(|(t, _v)| t == tag) {
+            return Err(Error::InvalidConstant(format!(
+                "{con}: tag {tag} already included"
+            )));
+        }
+        let value = substitute_env(value)
+            .map_err(|e| Error::InvalidConstant(format!("{con}: {e}")))?;
+        constants.push((tag.to_string(), value));
+    }
+    Ok(constants)
+}
+
+/// Substitute `${VAR}` environment variables in a value
+fn substitute_env(value: &str) -> std::result::Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| "unterminated ${".to_string())?;
+        let var = &after[..end];
+        let val = std::env::var(var)
+            .map_err(|_| format!("environment variable {var} is not set"))?;
+        out.push_str(&val);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Check that `osm_id` is stored for layers with an ID index
+fn check_id_index(layer: &LayerCfg, patterns: &[TagPattern]) -> Result<bool> {
+    if layer.id_index && !patterns.iter().any(|p| p.tag() == "osm_id") {
+        return Err(Error::InvalidIdIndex("osm_id tag not included".into()));
+    }
+    Ok(layer.id_index)
+}
+
+/// Check for an included `minzoom` tag shadowed by `emit_minzoom`
+fn check_emit_minzoom(layer: &LayerCfg, patterns: &[TagPattern]) -> bool {
+    if layer.emit_minzoom && patterns.iter().any(|p| p.tag() == "minzoom") {
+        log::warn!(
+            "layer {}: minzoom tag replaced by emit_minzoom",
+            layer.name
+        );
+    }
+    layer.emit_minzoom
+}
+
+/// Parse serve-time filter patterns, finding the index of each stored value
+fn parse_filter(
+    layer: &LayerCfg,
+    patterns: &[TagPattern],
+) -> Result<Vec<(TagPattern, usize)>> {
+    let today = Date::today();
+    let mut filter = Vec::with_capacity(layer.filter.len());
+    for pat in &layer.filter {
+        let mut p = TagPattern::parse(pat);
+        if p.must_match != MustMatch::Yes || p.include != IncludeValue::No {
+            return Err(Error::InvalidFilter(format!(
+                "{pat}: expected tag=a|b"
+            )));
+        }
+        p.resolve_date(today)?;
+        let index = patterns
+            .iter()
+            .filter_map(|p| p.include_tag())
+            .position(|tag| tag == p.tag())
+            .ok_or_else(|| {
+                Error::InvalidFilter(format!("{pat}: tag not included"))
+            })?;
+        filter.push((p, index));
+    }
+    Ok(filter)
+}
+
+/// Parse `rank_by` tag and `rank_zoom` buckets
+fn parse_rank(layer: &LayerCfg) -> Result<Option<(String, Vec<RankBucket>)>> {
+    let Some(tag) = &layer.rank_by else {
+        if layer.rank_zoom.is_empty() {
+            return Ok(None);
+        }
+        return Err(Error::InvalidRank("rank_zoom without rank_by".into()));
+    };
+    if parse_geom_type(&layer.geom_type)? != GeomType::Point {
+        return Err(Error::InvalidRank(format!("{tag}: not a point layer")));
+    }
+    let included = layer.tags.iter().any(|pat| {
+        let pat = TagPattern::parse(pat);
+        pat.tag() == tag && pat.feature_type == FeatureType::MvtSint
+    });
+    if !included {
+        return Err(Error::InvalidRank(format!("{tag}: ${tag} not included")));
+    }
+    let (zoom_min, zoom_max) = parse_zoom_range(&layer.zoom)?;
+    let mut buckets: Vec<RankBucket> = Vec::new();
+    for (i, rz) in layer.rank_zoom.iter().enumerate() {
+        let last = i + 1 == layer.rank_zoom.len();
+        let (zoom, min_rank) = match rz.split_once('=') {
+            Some((zoom, rank)) if !last => match rank.parse() {
+                Ok(rank) => (zoom, Some(rank)),
+                Err(_) => {
+                    return Err(Error::InvalidRank(format!("{rz}: bad rank")))
+                }
+            },
+            None if last => (rz.as_str(), None),
+            _ => {
+                return Err(Error::InvalidRank(format!(
+                    "{rz}: expected zoom=rank (zoom only for last)"
+                )))
+            }
+        };
+        let zoom = parse_zoom(zoom)?;
+        match buckets.last_mut() {
+            Some(prev) => {
+                if zoom <= prev.zoom_min {
+                    return Err(Error::InvalidRank(format!(
+                        "{rz}: zoom not increasing"
+                    )));
+                }
+                if let (Some(rank), Some(prev)) = (min_rank, prev.min_rank) {
+                    if rank >= prev {
+                        return Err(Error::InvalidRank(format!(
+                            "{rz}: rank not decreasing"
+                        )));
+                    }
+                }
+                prev.zoom_max = zoom - 1;
+            }
+            None if zoom != zoom_min => {
+                return Err(Error::InvalidRank(format!(
+                    "{rz}: first zoom must be {zoom_min}"
+                )));
+            }
+            None => (),
+        }
+        if zoom > zoom_max {
+            return Err(Error::InvalidRank(format!("{rz}: zoom out of range")));
+        }
+        buckets.push(RankBucket {
+            zoom_min: zoom,
+            zoom_max,
+            min_rank,
+        });
+    }
+    if buckets.len() < 2 {
+        return Err(Error::InvalidRank(format!("{tag}: need 2+ buckets")));
+    }
+    Ok(Some((tag.clone(), buckets)))
+}
+
+/// Get loam file names (without extension) for a layer.
+///
+/// Ranked point layers have one file per zoom bucket
+/// (`<name>.z<min>-<max>`).
+pub fn loam_names(layer: &LayerCfg) -> Result<Vec<String>> {
+    match parse_rank(layer)? {
+        Some((_tag, buckets)) => Ok(buckets
+            .iter()
+            .map(|b| bucket_name(&layer.name, b))
+            .collect()),
+        None => Ok(vec![layer.loam.as_ref().unwrap_or(&layer.name).clone()]),
+    }
+}
+
+/// Get the loam file name of a zoom bucket
+fn bucket_name(name: &str, bucket: &RankBucket) -> String {
+    format!("{name}.z{}-{}", bucket.zoom_min, bucket.zoom_max)
+}
+
+/// Parse a `split_by` rule (`tag=value|value`)
+fn parse_split(layer: &LayerCfg) -> Result<Option<(String, Vec<String>)>> {
+    let Some(split) = &layer.split_by else {
+        return Ok(None);
+    };
+    let pat = TagPattern::parse(split);
+    if pat.must_match != MustMatch::Yes
+        || pat.include != IncludeValue::No
+        || pat.equality != Equality::Equal
+        || pat.values.iter().any(|v| v.is_empty() || v == "_")
+    {
+        return Err(Error::InvalidSplit(format!("{split}: expected tag=a|b")));
+    }
+    for tag in &layer.tags {
+        let p = TagPattern::parse(tag);
+        if p.tag() != pat.tag() {
+            continue;
+        }
+        if p.must_match == MustMatch::Yes {
+            return Err(Error::InvalidSplit(format!(
+                "{split} conflicts with pattern {tag}"
+            )));
+        }
+        if p.feature_type == FeatureType::MvtSint {
+            if let Some(v) = pat.values.iter().find(|v| parse_sint(v).is_none())
+            {
+                return Err(Error::InvalidSplit(format!(
+                    "{split}: {v} is not a sint for pattern {tag}"
+                )));
+            }
+        }
+    }
+    Ok(Some((pat.tag, pat.values)))
+}
+
+/// Expand a layer with `split_by` into one layer per split value.
+///
+/// Each layer is named `<name>_<value>`, with an added `tag=value` pattern.
+/// A string include pattern for the split tag (`?tag`) becomes
+/// `.tag=value`; a sint include (`$tag`) is kept, along with `tag=value`.
+pub fn expand_layer_cfg(layer: &LayerCfg) -> Result<Vec<LayerCfg>> {
+    let Some((tag, values)) = parse_split(layer)? else {
+        return Ok(vec![layer.clone()]);
+    };
+    let layers = values
+        .iter()
+        .map(|val| {
+            let mut included = false;
+            let mut tags: Vec<String> = layer
+                .tags
+                .iter()
+                .map(|pat| {
+                    let p = TagPattern::parse(pat);
+                    if p.tag() == tag
+                        && p.feature_type == FeatureType::MvtString
+                    {
+                        included = true;
+                        format!(".{tag}={val}")
+                    } else {
+                        pat.clone()
+                    }
+                })
+                .collect();
+            if !included {
+                tags.push(format!("{tag}={val}"));
+            }
+            LayerCfg {
+                name: format!("{}_{val}", layer.name),
+                tags,
+                split_by: None,
+                ..layer.clone()
+            }
+        })
+        .collect();
+    Ok(layers)
+}
+
+impl TryFrom<&LayerCfg> for LayerDef {
+    type Error = Error;
+
+    fn try_from(layer: &LayerCfg) -> Result<Self> {
+        let name = layer.name.to_string();
+        let geom_tp = parse_geom_type(&layer.geom_type)?;
+        let (zoom_min, zoom_max) = parse_zoom_range(&layer.zoom)?;
+        let member_role = parse_member_role(layer.member_role.as_deref())?;
+        let precision = parse_precision(layer.precision.as_deref())?;
+        let derive = parse_derive(layer, geom_tp)?;
+        let generate = parse_generate(layer, geom_tp)?;
+        let (rank_by, rank_buckets) = match parse_rank(layer)? {
+            Some((tag, buckets)) => (Some(tag), buckets),
+            None => (None, Vec::new()),
+        };
+        log::trace!("zoom: {}-{}", zoom_min, zoom_max);
+        let patterns = parse_patterns(&layer.tags)?;
+        let id_index = check_id_index(layer, &patterns)?;
+        let emit_minzoom = check_emit_minzoom(layer, &patterns);
+        let filter = parse_filter(layer, &patterns)?;
+        let constants = parse_constants(layer, &patterns)?;
+        let dates_at = patterns
+            .iter()
+            .chain(filter.iter().map(|(p, _)| p))
+            .any(TagPattern::is_relative_date)
+            .then(Date::today);
+        let area_keys = if layer.area_keys.is_empty() {
+            AREA_KEYS.iter().map(|k| k.to_string()).collect()
+        } else {
+            layer.area_keys.clone()
+        };
+        Ok(LayerDef {
+            name,
+            geom_tp,
+            zoom_min,
+            zoom_max,
+            patterns,
+            force_polygon: layer.force_polygon,
+            area_keys,
+            member_role,
+            drop_empty: layer.drop_empty,
+            allow_partial_polygons: layer.allow_partial_polygons,
+            dedupe_members: layer.dedupe_members.unwrap_or(true),
+            name_languages: Vec::new(),
+            derive,
+            generate,
+            order: layer.order,
+            merge_lines: layer.merge_lines,
+            exclude: layer.exclude_if_matches.clone(),
+            rank_by,
+            rank_buckets,
+            query_limit: layer.query_limit,
+            max_relation_members: layer
+                .max_relation_members
+                .unwrap_or(MAX_RELATION_MEMBERS),
+            max_ring_nodes: layer.max_ring_nodes.unwrap_or(MAX_RING_NODES),
+            max_geometry_nodes: layer
+                .max_geometry_nodes
+                .unwrap_or(MAX_GEOMETRY_NODES)
+                .max(2),
+            max_line_span: layer.max_line_span,
+            max_value_len: layer.max_value_len,
+            attribution: layer.attribution.clone(),
+            license: layer.license.clone(),
+            style: layer.style.clone(),
+            id_index,
+            emit_minzoom,
+            precision,
+            loam: layer.loam.clone(),
+            filter,
+            constants,
+            dates_at,
+        })
+    }
+}
+
+impl LayerDef {
+    /// Set preferred name languages, in fallback order.
+    ///
+    /// If `name` is included, values of `name:xx` tags are stored for each
+    /// language, to select the `name` tag at tile time.
+    pub fn with_name_languages(mut self, langs: &[String]) -> Self {
+        if self.tags().any(|t| t == "name") {
+            for lang in langs {
+                let tag = format!("name:{lang}");
+                if !self.patterns.iter().any(|p| p.tag() == tag) {
+                    let mut p = TagPattern::parse(&format!("?{tag}"));
+                    p.hidden = true;
+                    self.patterns.push(p);
+                }
+            }
+            self.name_languages = langs.to_vec();
+        }
+        self
+    }
+
+    /// Find filter value indexes from stored tags of the loam file.
+    ///
+    /// Shared loam files may store tags in a different order than this
+    /// layer's patterns.
+    pub fn with_stored_tags(mut self, tags: &[String]) -> Result<Self> {
+        for (pat, index) in &mut self.filter {
+            *index =
+                tags.iter().position(|t| t == pat.tag()).ok_or_else(|| {
+                    Error::InvalidFilter(format!("{pat}: tag not stored"))
+                })?;
+        }
+        Ok(self)
+    }
+
+    /// Remove serve-time settings (filter patterns and demo map style).
+    ///
+    /// Used when digging, so that changing them does not alter the content
+    /// hash.
+    #[cfg(feature = "dig")]
+    pub fn without_serve_settings(mut self) -> Self {
+        self.filter.clear();
+        self.style = None;
+        self
+    }
+
+    /// Set default query limit, if not set for the layer
+    pub fn with_query_limit(mut self, limit: Option<usize>) -> Self {
+        self.query_limit = self.query_limit.or(limit);
+        self
+    }
+
+    /// Set default maximum linestring span, if not set for the layer
+    pub fn with_max_line_span(mut self, span: Option<f64>) -> Self {
+        self.max_line_span = self.max_line_span.or(span);
+        self
+    }
+
+    /// Set default maximum tag value length, if not set for the layer
+    pub fn with_max_value_len(mut self, len: Option<usize>) -> Self {
+        self.max_value_len = self.max_value_len.or(len);
+        self
+    }
+
+    /// Get maximum features queried
+    pub fn query_limit(&self) -> usize {
+        self.query_limit.unwrap_or(QUERY_LIMIT)
+    }
+
+    /// Get preferred name languages
+    pub fn name_languages(&self) -> &[String] {
+        &self.name_languages
+    }
+
+    /// Get the layer name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the geometry type
+    pub fn geom_tp(&self) -> GeomType {
+        self.geom_tp
+    }
+
+    /// Get the relation member role (point layers)
+    pub fn member_role(&self) -> Option<MemberRole> {
+        self.member_role
+    }
+
+    /// Get the layer order within tiles
+    pub fn order(&self) -> i32 {
+        self.order
+    }
+
+    /// Get generated features
+    pub fn generate(&self) -> Option<&Generate> {
+        self.generate.as_ref()
+    }
+
+    /// Get values of included tags for generated features.
+    ///
+    /// Patterns with one equal value (ex. `?class=land`) have that value.
+    pub fn generated_values(&self) -> Vec<Option<String>> {
+        self.patterns()
+            .iter()
+            .filter(|pat| pat.include_tag().is_some())
+            .map(|pat| match (pat.equality, &pat.values[..]) {
+                (Equality::Equal, [value]) => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get derived features
+    pub fn derive(&self) -> Option<&Derive> {
+        self.derive.as_ref()
+    }
+
+    /// Get the data attribution
+    pub fn attribution(&self) -> Option<&str> {
+        self.attribution.as_deref()
+    }
+
+    /// Get the data license
+    pub fn license(&self) -> Option<&str> {
+        self.license.as_deref()
+    }
+
+    /// Get the demo map style
+    pub fn style(&self) -> Option<&LayerStyleCfg> {
+        self.style.as_ref()
+    }
+
+    /// Check if an OSM ID index is built
+    pub fn id_index(&self) -> bool {
+        self.id_index
+    }
+
+    /// Check if a synthetic `minzoom` tag is added to features
+    pub fn emit_minzoom(&self) -> bool {
+        self.emit_minzoom
+    }
+
+    /// Get the synthetic `minzoom` tag value for a feature.
+    ///
+    /// This is the zoom bucket minimum if `rank_by` is set, otherwise the
+    /// layer minimum zoom.
+    pub fn feature_minzoom(&self, values: &Values) -> Option<i64> {
+        if !self.emit_minzoom {
+            return None;
+        }
+        let zoom = match self.rank_buckets.get(self.rank_bucket(values)) {
+            Some(bucket) => bucket.zoom_min,
+            None => self.zoom_min,
+        };
+        Some(i64::from(zoom))
+    }
+
+    /// Get the geometry storage precision
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Get the name of a layer whose matching objects are excluded
+    pub fn exclude(&self) -> Option<&str> {
+        self.exclude.as_deref()
+    }
+
+    /// Check if linestrings with equal tag values should be merged
+    pub fn merge_lines(&self) -> bool {
+        self.merge_lines
+    }
+
+    /// Check if features with no included values should be dropped
+    pub fn drop_empty(&self) -> bool {
+        self.drop_empty
+    }
+
+    /// Check if incomplete relation polygons should be salvaged
+    pub fn allow_partial_polygons(&self) -> bool {
+        self.allow_partial_polygons
+    }
+
+    /// Check if member ways of relation polygons should be skipped
+    pub fn dedupe_members(&self) -> bool {
+        self.dedupe_members
+    }
+
+    /// Get zoom buckets (empty unless `rank_by` is set)
+    pub fn rank_buckets(&self) -> &[RankBucket] {
+        &self.rank_buckets
+    }
+
+    /// Get the name of a shared loam layer
+    pub fn loam(&self) -> Option<&str> {
+        self.loam.as_deref()
+    }
+
+    /// Get the loam file name (without extension or zoom bucket)
+    pub fn loam_name(&self) -> &str {
+        self.loam.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Check if stored values match all serve-time filter patterns
+    pub fn check_filter(&self, values: &Values) -> bool {
+        self.filter.iter().all(|(pat, i)| {
+            pat.matches_value(values.get(*i).and_then(|v| v.as_deref()))
+        })
+    }
+
+    /// Get loam file names (without extension)
+    pub fn loam_names(&self) -> Vec<String> {
+        if self.rank_buckets.is_empty() {
+            vec![self.loam_name().to_string()]
+        } else {
+            self.rank_buckets
+                .iter()
+                .map(|b| bucket_name(&self.name, b))
+                .collect()
+        }
+    }
+
+    /// Get the zoom bucket index for a feature.
+    ///
+    /// Features missing the rank tag are in the last bucket.
+    pub fn rank_bucket(&self, values: &Values) -> usize {
+        let rank = self
+            .rank_by
+            .as_deref()
+            .and_then(|tag| self.sint_tag_index(tag))
+            .and_then(|i| parse_sint(values.get(i)?.as_deref()?));
+        rank.and_then(|rank| {
+            self.rank_buckets
+                .iter()
+                .position(|b| b.min_rank.is_some_and(|m| rank >= m))
+        })
+        .unwrap_or(self.rank_buckets.len().saturating_sub(1))
+    }
+
+    /// Get the maximum relation members
+    pub fn max_relation_members(&self) -> usize {
+        self.max_relation_members
+    }
+
+    /// Get the maximum nodes in relation member ways
+    pub fn max_ring_nodes(&self) -> usize {
+        self.max_ring_nodes
+    }
+
+    /// Get the maximum nodes per linestring
+    pub fn max_geometry_nodes(&self) -> usize {
+        self.max_geometry_nodes
+    }
+
+    /// Get the maximum linestring span (Web Mercator meters)
+    pub fn max_line_span(&self) -> f64 {
+        self.max_line_span.unwrap_or(MAX_LINE_SPAN) * Z10_TILE
+    }
+
+    /// Get an iterator of match patterns, without include prefixes.
+    ///
+    /// These (along with geometry rules) determine which objects are
+    /// extracted when digging.
+    pub fn match_patterns(&self) -> impl Iterator<Item = String> + '_ {
+        self.patterns()
+            .iter()
+            .filter(|pat| pat.match_tag().is_some())
+            .map(|pat| pat.to_string().trim_start_matches('.').to_string())
+    }
+
+    /// Check if all closed ways are polygons (ignoring area tags)
+    pub fn force_polygon(&self) -> bool {
+        self.force_polygon
+    }
+
+    /// Get the date of `now` in relative date patterns (`YYYY-MM-DD`)
+    pub fn dates_at(&self) -> Option<String> {
+        self.dates_at.map(|d| d.to_string())
+    }
+
+    /// Get tag keys which imply closed ways are areas
+    pub fn area_keys(&self) -> &[String] {
+        &self.area_keys
+    }
+
+    /// Get the maximum tag value length (bytes)
+    pub fn max_value_len(&self) -> usize {
+        self.max_value_len.unwrap_or(MAX_VALUE_LEN)
+    }
+
+    /// Sanitize a tag value.
+    ///
+    /// Control characters are removed, and values longer than the maximum
+    /// length are truncated at a character boundary.  Returns the value,
+    /// and whether it was truncated.
+    pub fn sanitize_value<'a>(&self, value: &'a str) -> (Cow<'a, str>, bool) {
+        let max = self.max_value_len();
+        if value.len() <= max && !value.chars().any(char::is_control) {
+            return (Cow::Borrowed(value), false);
+        }
+        let mut clean = String::with_capacity(value.len().min(max));
+        for c in value.chars().filter(|c| !c.is_control()) {
+            if clean.len() + c.len_utf8() > max {
+                return (Cow::Owned(clean), true);
+            }
+            clean.push(c);
+        }
+        (Cow::Owned(clean), false)
+    }
+
+    /// Get a slice of tag patterns
+    fn patterns(&self) -> &[TagPattern] {
+        &self.patterns
+    }
+
+    /// Get the minimum zoom level
+    pub fn zoom_min(&self) -> u32 {
+        self.zoom_min
+    }
+
+    /// Get the maximum zoom level
+    pub fn zoom_max(&self) -> u32 {
+        self.zoom_max
+    }
+
+    /// Check if zoom level matches
+    pub fn check_zoom(&self, zoom: u32) -> bool {
+        zoom >= self.zoom_min && zoom <= self.zoom_max
+    }
+
+    /// Check if OSM tags match all patterns
+    pub fn check_tags<T: OsmTags + ?Sized>(&self, tags: &T) -> bool {
+        for pattern in self.patterns() {
+            if let Some(tag) = pattern.match_tag() {
+                let value = tags.tag(tag);
+                if !pattern.matches_value(value) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Make match statistics for checking tags while digging.
+    ///
+    /// * `reorder` Reorder patterns by selectivity after sampling.
+    #[cfg(feature = "dig")]
+    pub(crate) fn match_stats(&self, reorder: bool) -> MatchStats {
+        let n = self.patterns.len();
+        MatchStats {
+            order: (0..n)
+                .filter(|i| self.patterns[*i].match_tag().is_some())
+                .collect(),
+            checked: vec![0; n],
+            failed: vec![0; n],
+            n_obj: 0,
+            reorder,
+            reordered: false,
+        }
+    }
+
+    /// Check if OSM tags match all patterns, recording statistics.
+    ///
+    /// Patterns are evaluated in the order of `stats`, which gives the same
+    /// result as `check_tags`.
+    #[cfg(feature = "dig")]
+    pub(crate) fn check_tags_counted<T: OsmTags + ?Sized>(
+        &self,
+        tags: &T,
+        stats: &mut MatchStats,
+    ) -> bool {
+        stats.next_obj();
+        for n in 0..stats.order.len() {
+            let i = stats.order[n];
+            let pattern = &self.patterns[i];
+            if let Some(tag) = pattern.match_tag() {
+                let pass = pattern.matches_value(tags.tag(tag));
+                stats.record(i, pass);
+                if !pass {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Check if OSM tags have a value matching any layer pattern
+    pub fn check_any_tag<T: OsmTags + ?Sized>(&self, tags: &T) -> bool {
+        self.patterns().iter().any(|pattern| {
+            pattern.match_tag().is_some_and(|tag| {
+                let value = tags.tag(tag);
+                value.is_some() && pattern.matches_value(value)
+            })
+        })
+    }
+
+    /// Check OSM tags against each pattern, for explaining matches
+    pub fn explain_tags<T: OsmTags + ?Sized>(
+        &self,
+        tags: &T,
+    ) -> Vec<PatternCheck> {
+        self.patterns()
+            .iter()
+            .filter(|pattern| !pattern.hidden && !pattern.synthetic)
+            .map(|pattern| {
+                let value = tags.tag(pattern.tag());
+                let matched = match pattern.match_tag() {
+                    Some(_) => pattern.matches_value(value),
+                    None => true,
+                };
+                PatternCheck {
+                    pattern: pattern.to_string(),
+                    value: value.map(str::to_string),
+                    matched,
+                }
+            })
+            .collect()
+    }
+
+    /// Check if a closed way should be a polygon in this layer
+    pub fn check_area<T: OsmTags + ?Sized>(&self, tags: &T) -> bool {
+        self.force_polygon || is_area(tags, &self.area_keys)
+    }
+
+    /// Get an iterator of all tag keys referenced by the layer.
+    ///
+    /// Includes pattern tags (matched or included), `area`, `type` (for
+    /// relations) and area keys.
+    pub fn referenced_keys(&self) -> impl Iterator<Item = &str> {
+        self.patterns
+            .iter()
+            .map(|pat| pat.tag())
+            .chain(["area", "type"])
+            .chain(self.area_keys.iter().map(String::as_str))
+    }
+
+    /// Get the index of an included tag
+    pub fn tag_index(&self, tag: &str) -> Option<usize> {
+        self.tags().position(|t| t == tag)
+    }
+
+    /// Get the stored value of an included tag
+    pub fn tag_value<'a>(
+        &self,
+        values: &'a Values,
+        tag: &str,
+    ) -> Option<&'a str> {
+        values.get(self.tag_index(tag)?)?.as_deref()
+    }
+
+    /// Get the index of an included `sint` tag
+    pub fn sint_tag_index(&self, tag: &str) -> Option<usize> {
+        self.patterns()
+            .iter()
+            .filter(|pat| pat.include_tag().is_some())
+            .position(|pat| {
+                pat.tag() == tag && pat.feature_type == FeatureType::MvtSint
+            })
+    }
+
+    /// Get an iterator of tags to include
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.patterns().iter().filter_map(|pat| pat.include_tag())
+    }
+
+    /// Get an iterator of constant tags and values
+    pub fn constants(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.constants
+            .iter()
+            .map(|(tag, value)| (tag.as_str(), value.as_str()))
+    }
+
+    /// Get an iterator of synthetic tags (`$bbox_w`, `$bbox_h`, `$angle`),
+    /// computed at tile-encode time
+    pub fn synthetic_tags(&self) -> impl Iterator<Item = &str> {
+        self.patterns().iter().filter_map(|pat| pat.synthetic_tag())
+    }
+
+    /// Get an iterator of tags to include, with default values.
+    ///
+    /// Defaults apply to `sint` tags which are missing or invalid.
+    pub fn tag_defaults(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.patterns().iter().filter_map(|pat| {
+            pat.include_tag().map(|tag| (tag, pat.default.as_deref()))
+        })
+    }
+
+    /// Get an iterator of tags to include, with default values, units and
+    /// stored flags.
+    ///
+    /// The unit is `Some` for `sint` tags, which are normalized when dug.
+    /// Values are not stored for zoom-qualified tags which can never be
+    /// included at the layer's zoom levels.
+    pub(crate) fn tag_rules(
+        &self,
+    ) -> impl Iterator<Item = (&str, Option<&str>, Option<SintUnit>, bool)>
+    {
+        self.patterns().iter().filter_map(|pat| {
+            let sint = pat.feature_type == FeatureType::MvtSint;
+            let stored = pat.zoom.is_none_or(|(z0, z1)| {
+                z0 <= self.zoom_max && z1 >= self.zoom_min
+            });
+            pat.include_tag().map(|tag| {
+                (tag, pat.default.as_deref(), sint.then_some(pat.unit), stored)
+            })
+        })
+    }
+
+    /// Check if an included tag's value is included at a zoom level
+    pub(crate) fn check_tag_zoom(&self, tag: &str, zoom: u32) -> bool {
+        self.patterns()
+            .iter()
+            .filter(|pat| pat.include_tag() == Some(tag))
+            .all(|pat| pat.check_zoom(zoom))
+    }
+
+    /// Get an iterator of included tags, values and sint flags
+    pub fn tag_values<'a>(
+        &'a self,
+        values: &'a [Option<String>],
+    ) -> impl Iterator<Item = (&'a str, &'a str, bool)> {
+        self.zoom_values(values, None)
+    }
+
+    /// Get an iterator of included tags, values and sint flags at a zoom
+    /// level.
+    ///
+    /// Values of zoom-qualified tags (`?name:de@14+`) are skipped outside
+    /// their zoom range, even if stored in the loam file.
+    pub(crate) fn tile_values<'a>(
+        &'a self,
+        values: &'a [Option<String>],
+        zoom: u32,
+    ) -> impl Iterator<Item = (&'a str, &'a str, bool)> {
+        self.zoom_values(values, Some(zoom))
+    }
+
+    /// Get an iterator of included tags, values and sint flags
+    fn zoom_values<'a>(
+        &'a self,
+        values: &'a [Option<String>],
+        zoom: Option<u32>,
+    ) -> impl Iterator<Item = (&'a str, &'a str, bool)> {
+        self.patterns()
+            .iter()
+            .filter(|pat| pat.include_tag().is_some())
+            .zip(values)
+            .filter(|(pat, _val)| !pat.hidden)
+            .filter(move |(pat, _val)| zoom.is_none_or(|z| pat.check_zoom(z)))
+            .filter_map(|(pat, val)| {
+                let sint = pat.feature_type == FeatureType::MvtSint;
+                val.as_ref().map(|val| (pat.tag(), &val[..], sint))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i64, month: i64, day: i64) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn zoom_range_parse() {
+        assert_eq!(parse_zoom_range("5").unwrap(), (5, 5));
+        assert_eq!(parse_zoom_range("5+").unwrap(), (5, ZOOM_MAX));
+        assert_eq!(parse_zoom_range("5-8").unwrap(), (5, 8));
+        assert_eq!(parse_zoom_range("0-30").unwrap(), (0, 30));
+        assert!(matches!(
+            parse_zoom_range("8-5"),
+            Err(Error::InvalidZoomRange(z)) if z == "8-5"
+        ));
+        for zoom in ["31", "31+", "5-31"] {
+            assert!(
+                matches!(
+                    parse_zoom_range(zoom),
+                    Err(Error::InvalidZoomLevel(31))
+                ),
+                "{zoom}"
+            );
+        }
+        for zoom in ["", "+", "-", "five", "5++", "5-", "-5", "5-8-9", " 5"] {
+            assert!(
+                matches!(parse_zoom_range(zoom), Err(Error::ParseInt(_))),
+                "{zoom:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn sint_normalize() {
+        let plain = SintUnit::default();
+        let kmh = SINT_UNITS[0];
+        let m = SINT_UNITS[1];
+        let max = i64::MAX.to_string();
+        let min = i64::MIN.to_string();
+        let huge = "9".repeat(400);
+        let cases = [
+            ("~50,000", plain, Some("50000")),
+            ("50,000", plain, Some("50000")),
+            ("~ 7", plain, Some("7")),
+            ("  42 ", plain, Some("42")),
+            ("-3", plain, Some("-3")),
+            ("12.6", plain, Some("13")),
+            ("12,345,678", plain, Some("12345678")),
+            ("30 mph", kmh, Some("48")),
+            ("30mph", kmh, Some("48")),
+            ("50 km/h", kmh, Some("50")),
+            ("10 knots", kmh, Some("19")),
+            ("30 mph", plain, None),
+            ("30 MPH", kmh, None),
+            ("1,234 m", m, Some("1234")),
+            ("1,234.5 m", m, Some("1235")),
+            ("5 km", m, Some("5000")),
+            ("100 ft", m, Some("30")),
+            ("1,234 m", plain, None),
+            ("1,234 m", kmh, None),
+            ("1,5", plain, None),
+            ("1,23", plain, None),
+            ("1,2345", plain, None),
+            ("99999999999999999999", plain, Some(&max[..])),
+            ("-99999999999999999999", plain, Some(&min[..])),
+            (&huge[..], plain, None),
+            ("", plain, None),
+            ("~", plain, None),
+            ("NaN", plain, None),
+            ("unknown", kmh, None),
+        ];
+        for (value, unit, expected) in cases {
+            assert_eq!(
+                normalize_sint(value, unit).as_deref(),
+                expected,
+                "{value} ({})",
+                unit.name
+            );
+        }
+        // normalized values always parse at serve time
+        for (value, unit, _) in cases {
+            if let Some(v) = normalize_sint(value, unit) {
+                assert!(parse_sint(&v).is_some(), "{v}");
+            }
+        }
+    }
+
+    #[test]
+    fn date_days() {
+        assert_eq!(Date::from_days(0), date(1970, 1, 1));
+        assert_eq!(Date::from_days(-1), date(1969, 12, 31));
+        assert_eq!(date(2000, 2, 29).days(), 11_016);
+        assert_eq!(Date::from_days(11_017), date(2000, 3, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(
+            Date::from_days(date(1900, 2, 28).days() + 1),
+            date(1900, 3, 1)
+        );
+        assert_eq!(
+            Date::from_days(date(2100, 2, 28).days() + 1),
+            date(2100, 3, 1)
+        );
+        assert_eq!(
+            Date::from_days(date(2024, 2, 28).days() + 1),
+            date(2024, 2, 29)
+        );
+        assert_eq!(date(2024, 12, 31).days() - date(2024, 1, 1).days(), 365);
+        assert_eq!(date(2023, 12, 31).days() - date(2023, 1, 1).days(), 364);
+        // consecutive days across several 400-year cycles
+        let mut prev = Date::from_days(-800_000);
+        for days in -799_999..800_000 {
+            let d = Date::from_days(days);
+            assert_eq!(d.days(), days, "{d}");
+            assert!(d > prev, "{d}");
+            assert!(d.day >= 1 && d.day <= days_in_month(d.year, d.month));
+            if d.day == 1 {
+                assert_eq!(prev.day, days_in_month(prev.year, prev.month));
+            } else {
+                assert_eq!((d.year, d.month), (prev.year, prev.month));
+            }
+            prev = d;
+        }
+    }
+
+    #[test]
+    fn date_add_months() {
+        let cases = [
+            (date(2024, 1, 31), 1, date(2024, 2, 29)),
+            (date(2023, 1, 31), 1, date(2023, 2, 28)),
+            (date(2024, 3, 31), -1, date(2024, 2, 29)),
+            (date(2024, 5, 31), 1, date(2024, 6, 30)),
+            (date(2024, 2, 29), 12, date(2025, 2, 28)),
+            (date(2024, 2, 29), 48, date(2028, 2, 29)),
+            (date(2024, 12, 15), 1, date(2025, 1, 15)),
+            (date(2024, 1, 15), -1, date(2023, 12, 15)),
+            (date(2024, 1, 15), -25, date(2021, 12, 15)),
+            (date(2024, 7, 4), 0, date(2024, 7, 4)),
+        ];
+        for (d, months, expected) in cases {
+            assert_eq!(d.add_months(months), expected, "{d} + {months}");
+        }
+    }
+
+    #[test]
+    fn date_expr() {
+        let today = date(2024, 3, 31);
+        let cases = [
+            ("now", date(2024, 3, 31)),
+            ("now+0d", date(2024, 3, 31)),
+            ("now-1d", date(2024, 3, 30)),
+            ("now+1d", date(2024, 4, 1)),
+            ("now-31d", date(2024, 2, 29)),
+            ("now-2w", date(2024, 3, 17)),
+            ("now+1w", date(2024, 4, 7)),
+            ("now-1m", date(2024, 2, 29)),
+            ("now+1m", date(2024, 4, 30)),
+            ("now-13m", date(2023, 2, 28)),
+            ("now+1y", date(2025, 3, 31)),
+            ("now-10y", date(2014, 3, 31)),
+            ("2020-05-01", date(2020, 5, 1)),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(Date::parse_expr(expr, today), Some(expected), "{expr}");
+        }
+        let leap = date(2024, 2, 29);
+        assert_eq!(Date::parse_expr("now+1y", leap), Some(date(2025, 2, 28)));
+        assert_eq!(
+            Date::parse_expr("now-4y", leap),
+            Some(leap.add_months(-48))
+        );
+        for expr in [
+            "now1d", "now+", "now+d", "now+1x", "now+1.5d", "now*1d", "nowish",
+        ] {
+            assert_eq!(Date::parse_expr(expr, today), None, "{expr}");
+        }
+    }
+
+    #[test]
+    fn date_parse() {
+        let cases = [
+            ("2020", date(2020, 1, 1)),
+            ("2020-05", date(2020, 5, 1)),
+            ("2020-05-17", date(2020, 5, 17)),
+            (" 2020-05-17 ", date(2020, 5, 17)),
+            ("~2020", date(2020, 1, 1)),
+            ("~1990s", date(1990, 1, 1)),
+            ("1850s", date(1850, 1, 1)),
+            ("2020-02-29", date(2020, 2, 29)),
+            ("2000-02-29", date(2000, 2, 29)),
+            ("2020-05-17T12:30:00Z", date(2020, 5, 17)),
+            ("2020-05-17 12:30", date(2020, 5, 17)),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(Date::parse(value), Some(expected), "{value}");
+        }
+        let reject = [
+            "",
+            "~",
+            "20",
+            "020",
+            "abcd",
+            "2020-02-30",
+            "2021-02-29",
+            "1900-02-29",
+            "2020-04-31",
+            "2020-13-01",
+            "2020-00-10",
+            "2020-05-00",
+            "2020-1-5",
+            "2020-05-17-01",
+            "2020/05/17",
+            "1990ss",
+            "early 1990s",
+        ];
+        for value in reject {
+            assert_eq!(Date::parse(value), None, "{value}");
+        }
+    }
+
+    fn layer_def(zoom: &str, tags: &str) -> Result<LayerDef> {
+        let muon = format!(
+            "name: test\ngeom_type: point\nzoom: {zoom}\ntags: {tags}\n"
+        );
+        let cfg: LayerCfg = muon_rs::from_str(&muon).unwrap();
+        LayerDef::try_from(&cfg)
+    }
+
+    /// Tags from `key=value` pairs
+    struct Kv(&'static [(&'static str, &'static str)]);
+
+    impl OsmTags for Kv {
+        fn tag(&self, key: &str) -> Option<&str> {
+            self.0.iter().find(|(k, _v)| *k == key).map(|(_k, v)| *v)
+        }
+    }
+
+    #[test]
+    fn present_not_equal() {
+        let layer =
+            layer_def("0+", ".highway=*!construction|proposed ?name").unwrap();
+        assert_eq!(
+            layer.patterns()[0].to_string(),
+            ".highway=*!construction|proposed"
+        );
+        for pat in ["highway=*!razed", "?highway=*!construction|proposed"] {
+            assert_eq!(TagPattern::parse(pat).to_string(), pat);
+        }
+        assert!(!layer.check_tags(&Kv(&[("highway", "construction")])));
+        assert!(!layer.check_tags(&Kv(&[("highway", "proposed")])));
+        assert!(!layer.check_tags(&Kv(&[])));
+        assert!(!layer.check_tags(&Kv(&[("name", "Main")])));
+        assert!(layer.check_tags(&Kv(&[("highway", "motorway")])));
+        // `!=` also matches untagged objects
+        let layer = layer_def("0+", "highway!=construction").unwrap();
+        assert_eq!(layer.patterns()[0].to_string(), "highway!=construction");
+        assert!(layer.check_tags(&Kv(&[])));
+    }
+
+    #[test]
+    fn zoom_qualified() {
+        let layer = layer_def(
+            "10+",
+            "?name ?name:de@14+ $lanes@10-12 ?ref@12 ?note@2-8",
+        )
+        .unwrap();
+        let pats: Vec<_> =
+            layer.patterns().iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pats,
+            [
+                "?name",
+                "?name:de@14+",
+                "$lanes@10-12",
+                "?ref@12",
+                "?note@2-8"
+            ]
+        );
+        let stored: Vec<_> = layer.tag_rules().map(|r| r.3).collect();
+        assert_eq!(stored, [true, true, true, true, false]);
+        assert!(layer.check_tag_zoom("name", 10));
+        assert!(!layer.check_tag_zoom("name:de", 13));
+        assert!(layer.check_tag_zoom("name:de", 14));
+        let values = vec![
+            Some("Wien".to_string()),
+            Some("Wien (de)".to_string()),
+            Some("3".to_string()),
+            Some("A1".to_string()),
+            None,
+        ];
+        let tags = |zoom| {
+            layer
+                .tile_values(&values, zoom)
+                .map(|(tag, _val, _sint)| tag)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tags(10), ["name", "lanes"]);
+        assert_eq!(tags(12), ["name", "lanes", "ref"]);
+        assert_eq!(tags(14), ["name", "name:de"]);
+        assert_eq!(layer.tag_values(&values).count(), 4);
+        for tags in ["?name@abc", "?name@", "$lanes@8-5"] {
+            assert!(layer_def("0+", tags).is_err(), "{tags}");
+        }
+    }
+
+    #[test]
+    fn hidden_name_index() {
+        let layer = layer_def("0+", "?name $population ?name:de")
+            .unwrap()
+            .with_name_languages(&["de".into(), "es".into()]);
+        assert_eq!(layer.tag_index("name"), Some(0));
+        assert_eq!(layer.tag_index("name:de"), Some(2));
+        // hidden values follow the include patterns
+        assert_eq!(layer.tag_index("name:es"), Some(3));
+        let values =
+            vec![Some("Wien".into()), None, None, Some("Viena".into())];
+        assert_eq!(layer.tag_value(&values, "name:es"), Some("Viena"));
+        let tags: Vec<_> =
+            layer.tag_values(&values).map(|(tag, _v, _s)| tag).collect();
+        assert_eq!(tags, ["name"]);
+    }
+
+    /// Make a layer with constant tags
+    fn constants_def(constants: &[&str]) -> Result<LayerDef> {
+        let mut muon = String::from("name: test\ngeom_type: linestring\n");
+        for con in constants {
+            muon.push_str(&format!("constants: {con}\n"));
+        }
+        muon.push_str("zoom: 0+\ntags: .highway ?name $angle\n");
+        let cfg: LayerCfg = muon_rs::from_str(&muon).unwrap();
+        LayerDef::try_from(&cfg)
+    }
+
+    #[test]
+    fn constants_collisions() {
+        let layer = constants_def(&["region=mn", "version=3"]).unwrap();
+        let constants: Vec<_> = layer.constants().collect();
+        assert_eq!(constants, [("region", "mn"), ("version", "3")]);
+        // included, synthetic and duplicate tags
+        for con in [&["name=x"][..], &["angle=0"], &["region=mn", "region=wi"]]
+        {
+            let Err(Error::InvalidConstant(msg)) = constants_def(con) else {
+                panic!("{con:?} accepted");
+            };
+            assert!(msg.ends_with("already included"), "{msg}");
+        }
+        // match-only tags are not included, so they do not collide
+        let layer = constants_def(&["highway=yes"]).unwrap();
+        assert_eq!(layer.constants().count(), 1);
+        for con in ["region", "=mn"] {
+            assert!(
+                matches!(constants_def(&[con]), Err(Error::InvalidConstant(_))),
+                "{con}"
+            );
+        }
+    }
+
+    #[test]
+    fn constants_env() {
+        std::env::set_var("EARTHWYRM_TEST_DATASET", "2024-05");
+        std::env::remove_var("EARTHWYRM_TEST_UNSET");
+        let layer = constants_def(&[
+            "dataset=v${EARTHWYRM_TEST_DATASET}-${EARTHWYRM_TEST_DATASET}",
+        ])
+        .unwrap();
+        let constants: Vec<_> = layer.constants().collect();
+        assert_eq!(constants, [("dataset", "v2024-05-2024-05")]);
+        let Err(Error::InvalidConstant(msg)) =
+            constants_def(&["dataset=${EARTHWYRM_TEST_UNSET}"])
+        else {
+            panic!("unset variable accepted");
+        };
+        assert_eq!(
+            msg,
+            "dataset=${EARTHWYRM_TEST_UNSET}: environment variable \
+            EARTHWYRM_TEST_UNSET is not set"
+        );
+        let Err(Error::InvalidConstant(msg)) =
+            constants_def(&["dataset=${EARTHWYRM_TEST_DATASET"])
+        else {
+            panic!("unterminated variable accepted");
+        };
+        assert!(msg.ends_with("unterminated ${"), "{msg}");
+        // no substitution without braces
+        let layer = constants_def(&["cost=$5"]).unwrap();
+        assert_eq!(layer.constants().next(), Some(("cost", "$5")));
+    }
+
+    /// Objects for pattern reordering: one selective pattern (`d=x`) and
+    /// several permissive ones
+    #[cfg(feature = "dig")]
+    fn reorder_objs(n: u64) -> Vec<Kv> {
+        (0..n)
+            .map(|i| match i % 100 {
+                0 => Kv(&[("a", "1"), ("b", "1"), ("c", "1"), ("d", "x")]),
+                1 => Kv(&[("b", "1"), ("c", "1"), ("d", "x")]),
+                2 => Kv(&[("a", "1"), ("b", "1"), ("d", "y")]),
+                _ => Kv(&[("a", "1"), ("b", "1"), ("c", "1"), ("d", "y")]),
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "dig")]
+    fn reorder_same_results() {
+        let layer = layer_def("0+", ".a .b .c .d=x ?name").unwrap();
+        let objs = reorder_objs(5 * MATCH_SAMPLE);
+        let mut fixed = layer.match_stats(false);
+        let mut sorted = layer.match_stats(true);
+        for obj in &objs {
+            let expected = layer.check_tags(obj);
+            assert_eq!(layer.check_tags_counted(obj, &mut fixed), expected);
+            assert_eq!(layer.check_tags_counted(obj, &mut sorted), expected);
+        }
+        assert!(!fixed.reordered);
+        assert_eq!(fixed.order, [0, 1, 2, 3]);
+        assert!(sorted.reordered);
+        assert_eq!(sorted.order[0], 3);
+        // selective pattern first: fewer evaluations after sampling
+        let evals = |stats: &MatchStats| stats.checked.iter().sum::<u64>();
+        assert!(evals(&sorted) < evals(&fixed) / 2);
+    }
+
+    /// Benchmark checking tags, with and without pattern reordering:
+    ///
+    ///   cargo test --release -p earthwyrm bench_reorder -- --ignored
+    #[test]
+    #[ignore]
+    #[cfg(feature = "dig")]
+    fn bench_reorder() {
+        const ROUNDS: u32 = 20;
+        let layer = layer_def("0+", ".a .b .c .d=x ?name").unwrap();
+        let objs = reorder_objs(10 * MATCH_SAMPLE);
+        let mut times = Vec::new();
+        for reorder in [false, true] {
+            let t = std::time::Instant::now();
+            for _ in 0..ROUNDS {
+                let mut stats = layer.match_stats(reorder);
+                for obj in &objs {
+                    std::hint::black_box(
+                        layer.check_tags_counted(obj, &mut stats),
+                    );
+                }
+            }
+            let elapsed = t.elapsed();
+            println!(
+                "reorder {reorder}: {:.1} M objects/s",
+                f64::from(ROUNDS) * objs.len() as f64
+                    / elapsed.as_secs_f64()
+                    / 1e6
+            );
+            times.push(elapsed);
+        }
+        println!(
+            "speedup: {:.2}x",
+            times[0].as_secs_f64() / times[1].as_secs_f64()
+        );
     }
 }
@@ -4,14 +4,56 @@
 //
 #![forbid(unsafe_code)]
 
+mod analyze;
+mod bitmap;
+#[cfg(feature = "dig")]
+mod compact;
 mod config;
+mod decode;
+#[cfg(feature = "dig")]
+mod dem;
 mod error;
 mod geom;
+mod id_index;
 mod layer;
+mod manifest;
+mod meta;
+#[cfg(feature = "dig")]
+mod obj_cache;
+#[cfg(feature = "dig")]
 mod osm;
+#[cfg(feature = "xml")]
+mod osm_xml;
+mod schema;
+#[cfg(feature = "dig")]
+mod skips;
 mod tile;
 
-pub use config::{LayerCfg, LayerGroupCfg, WyrmCfg};
+pub use analyze::{
+    EdgeStats, ExtentAnalysis, ExtentStats, ZoomAnalysis, CANDIDATE_EDGES,
+    CANDIDATE_EXTENTS,
+};
+pub use config::{
+    BindAddr, ConfigIssue, ConfigReport, EmptyTile, HttpCfg, IdPolicy,
+    LayerCfg, LayerGroupCfg, LayerStyleCfg, LogFormat, TileGrid, WyrmCfg,
+};
 pub use error::Error;
+pub use geom::{
+    FeatureInfo, ProblemCount, RingProblem, ValidationReport, Values,
+};
+#[cfg(feature = "testing")]
+pub use geom::{GeomTree, MemFeature};
+pub use layer::{
+    civil_from_days, loam_names, LayerDef, OsmTags, PatternCheck, Precision,
+};
+pub use manifest::{FileHash, LayerCount, Manifest, OsmHeader, MANIFEST};
+pub use meta::{migrate_loam, MigrateStatus, LOAM_FORMAT};
 pub use mvt::TileId;
-pub use tile::Wyrm;
+#[cfg(feature = "dig")]
+pub use osm::{DigStage, ObjMatch};
+pub use schema::{ConfigSchema, FieldSchema, PatternSchema, SyntaxSchema};
+pub use tile::{
+    decode_summary, diff_tiles, overzoom, FeatureFilter, FeatureSummary,
+    GridSnap, LayerDiff, LayerGroup, LayerSummary, RequestCtx, StreamedTile,
+    TagValue, TileDiff, TileExtent, TileFetch, TilePath, TileSummary, Wyrm,
+};
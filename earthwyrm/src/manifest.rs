@@ -0,0 +1,446 @@
+// manifest.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+#[cfg(feature = "dig")]
+use crate::decode::{zigzag, PbfReader};
+use crate::error::{Error, Result};
+use crate::layer::civil_from_days;
+#[cfg(feature = "dig")]
+use crate::layer::LayerDef;
+use crate::meta::Fnv1a;
+#[cfg(feature = "dig")]
+use crate::meta::LoamMeta;
+#[cfg(feature = "dig")]
+use flate2::read::ZlibDecoder;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{read_to_string, write, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Manifest file name (in loam directory)
+pub const MANIFEST: &str = "manifest.muon";
+
+/// Bytes hashed at each end of a file for quick checks
+const SAMPLE_LEN: u64 = 64 * 1024;
+
+/// Maximum size of PBF blob header / blob
+#[cfg(feature = "dig")]
+const BLOB_MAX: usize = 32 * 1024 * 1024;
+
+/// OSM PBF file header
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OsmHeader {
+    /// Bounding box (`left bottom right top`), in degrees
+    #[serde(default)]
+    pub bbox: String,
+
+    /// Source of data
+    #[serde(default)]
+    pub source: String,
+
+    /// Program which wrote the file
+    #[serde(default)]
+    pub writing_program: String,
+
+    /// Replication timestamp (ISO 8601)
+    #[serde(default)]
+    pub replication_timestamp: String,
+
+    /// Replication sequence number
+    #[serde(default)]
+    pub replication_sequence: i64,
+}
+
+/// Size and hashes of one loam file
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FileHash {
+    /// File name
+    pub name: String,
+
+    /// File size (bytes)
+    pub size: u64,
+
+    /// FNV-1a hash of size, first and last 64 KiB (quick check)
+    pub sample: String,
+
+    /// FNV-1a hash of all contents (full check)
+    pub hash: String,
+}
+
+/// Feature count for one layer
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LayerCount {
+    /// Layer name
+    pub name: String,
+
+    /// Number of features
+    pub features: usize,
+
+    /// Content hash of layer definition and matched objects
+    #[serde(default)]
+    pub hash: String,
+
+    /// Hashes of loam files
+    #[serde(default)]
+    pub file: Vec<FileHash>,
+}
+
+/// Dig manifest, describing the source of loam files
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Version of earthwyrm used to dig
+    pub version: String,
+
+    /// Unique ID of dig (UUID format)
+    #[serde(default)]
+    pub dig_id: String,
+
+    /// Time of dig (ISO 8601)
+    pub dig_time: String,
+
+    /// OSM file name (space-separated, if more than one)
+    pub osm_file: String,
+
+    /// OSM file header
+    pub osm: OsmHeader,
+
+    /// Feature counts for all layers
+    #[serde(default)]
+    pub layer: Vec<LayerCount>,
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 UTC timestamp
+fn iso8601(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let sod = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        sod / 3600,
+        (sod / 60) % 60,
+        sod % 60
+    )
+}
+
+/// Make a random (version 4 format) UUID for a dig
+#[cfg(feature = "dig")]
+fn dig_uuid(osm: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut hash = Fnv1a::default();
+    hash.write(&nanos.to_le_bytes());
+    hash.write(&std::process::id().to_le_bytes());
+    hash.write_str(osm);
+    let hi = hash.value();
+    hash.write(&hi.to_le_bytes());
+    let lo = hash.value();
+    let hi = (hi & !0xf000) | 0x4000;
+    let lo = (lo & !(0xc000 << 48)) | (0x8000 << 48);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        hi >> 32,
+        (hi >> 16) & 0xffff,
+        hi & 0xffff,
+        lo >> 48,
+        lo & 0xffff_ffff_ffff
+    )
+}
+
+/// Read a length-prefixed blob (header and data) from a PBF file
+#[cfg(feature = "dig")]
+fn read_blob(file: &mut File) -> Result<(String, Vec<u8>)> {
+    let mut len = [0; 4];
+    file.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > BLOB_MAX {
+        return Err(Error::MalformedOsmHeader());
+    }
+    let mut buf = vec![0; len];
+    file.read_exact(&mut buf)?;
+    let mut tp = String::new();
+    let mut datasize = 0;
+    let mut reader = PbfReader::new(&buf);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => tp = value.as_str()?.to_string(),
+            3 => datasize = value.as_u64()? as usize,
+            _ => (),
+        }
+    }
+    if datasize > BLOB_MAX {
+        return Err(Error::MalformedOsmHeader());
+    }
+    let mut blob = vec![0; datasize];
+    file.read_exact(&mut blob)?;
+    let mut reader = PbfReader::new(&blob);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => return Ok((tp, value.as_bytes()?.to_vec())),
+            3 => {
+                let mut data = Vec::new();
+                ZlibDecoder::new(value.as_bytes()?).read_to_end(&mut data)?;
+                return Ok((tp, data));
+            }
+            _ => (),
+        }
+    }
+    Err(Error::MalformedOsmHeader())
+}
+
+/// Parse a header bounding box (nanodegrees)
+#[cfg(feature = "dig")]
+fn parse_bbox(buf: &[u8]) -> Result<String> {
+    let mut bbox = [0.0; 4];
+    let mut reader = PbfReader::new(buf);
+    while let Some((field, value)) = reader.next_field()? {
+        let deg = zigzag(value.as_u64()?) as f64 * 1e-9;
+        match field {
+            1 => bbox[0] = deg,
+            2 => bbox[2] = deg,
+            3 => bbox[3] = deg,
+            4 => bbox[1] = deg,
+            _ => (),
+        }
+    }
+    let [left, bottom, right, top] = bbox;
+    Ok(format!("{left:.7} {bottom:.7} {right:.7} {top:.7}"))
+}
+
+#[cfg(feature = "dig")]
+impl OsmHeader {
+    /// Read the header block of an OSM PBF file
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::read_file(path.as_ref()).map_err(|e| match e {
+            Error::MalformedTile() => Error::MalformedOsmHeader(),
+            e => e,
+        })
+    }
+
+    /// Read the header block from a file
+    fn read_file(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let (tp, data) = read_blob(&mut file)?;
+        if tp != "OSMHeader" {
+            return Err(Error::MalformedOsmHeader());
+        }
+        let mut header = OsmHeader::default();
+        let mut reader = PbfReader::new(&data);
+        while let Some((field, value)) = reader.next_field()? {
+            match field {
+                1 => header.bbox = parse_bbox(value.as_bytes()?)?,
+                16 => header.writing_program = value.as_str()?.to_string(),
+                17 => header.source = value.as_str()?.to_string(),
+                32 => {
+                    header.replication_timestamp =
+                        iso8601(value.as_u64()? as i64)
+                }
+                33 => header.replication_sequence = value.as_u64()? as i64,
+                _ => (),
+            }
+        }
+        Ok(header)
+    }
+}
+
+impl FileHash {
+    /// Hash a loam file
+    pub fn compute(loam: &Path) -> Result<Self> {
+        let name = loam
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut file = File::open(loam)?;
+        let size = file.metadata()?.len();
+        let sample = sample_hash(&mut file, size)?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut hash = Fnv1a::default();
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hash.write(&buf[..n]);
+        }
+        Ok(FileHash {
+            name,
+            size,
+            sample,
+            hash: hash.finish(),
+        })
+    }
+
+    /// Check a loam file against its hashes.
+    ///
+    /// * `full` Hash all contents, instead of only the size and samples.
+    pub fn check(&self, loam: &Path, full: bool) -> Result<()> {
+        let mismatch = |what| {
+            Err(Error::LoamMismatch(format!("{loam:?}: {what} differs")))
+        };
+        let mut file = File::open(loam)?;
+        let size = file.metadata()?.len();
+        if size != self.size {
+            return mismatch("size");
+        }
+        if full {
+            if FileHash::compute(loam)?.hash != self.hash {
+                return mismatch("hash");
+            }
+        } else if sample_hash(&mut file, size)? != self.sample {
+            return mismatch("sample hash");
+        }
+        Ok(())
+    }
+}
+
+/// Hash the size, first and last `SAMPLE_LEN` bytes of a file
+fn sample_hash(file: &mut File, size: u64) -> Result<String> {
+    let mut hash = Fnv1a::default();
+    hash.write(&size.to_le_bytes());
+    let len = SAMPLE_LEN.min(size);
+    let mut buf = vec![0; len as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buf)?;
+    hash.write(&buf);
+    file.seek(SeekFrom::Start(size - len))?;
+    file.read_exact(&mut buf)?;
+    hash.write(&buf);
+    Ok(hash.finish())
+}
+
+impl Manifest {
+    /// Create a new manifest for a dig
+    pub fn new<P>(osm_files: &[P], osm: OsmHeader) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let osm_file = osm_files
+            .iter()
+            .filter_map(|f| f.as_ref().file_name())
+            .map(|f| f.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            dig_id: dig_uuid(&osm_file),
+            dig_time: iso8601(secs as i64),
+            osm_file,
+            osm,
+            layer: Vec::new(),
+        }
+    }
+
+    /// Add a layer, with feature count and hashes of its loam files.
+    ///
+    /// Files are hashed in `staging`, or in `loam_dir` for unchanged
+    /// layers which were not made again.
+    #[cfg(feature = "dig")]
+    pub fn push_layer(
+        &mut self,
+        layer: &LayerDef,
+        features: usize,
+        staging: &Path,
+        loam_dir: &Path,
+    ) -> Result<()> {
+        let locate = |name: &str| {
+            let file = format!("{name}.loam");
+            let staged = staging.join(&file);
+            if staged.exists() {
+                staged
+            } else {
+                loam_dir.join(file)
+            }
+        };
+        let hash = LoamMeta::read(&locate(layer.name()))?
+            .map(|meta| meta.hash)
+            .unwrap_or_default();
+        let mut file = Vec::new();
+        for name in layer.loam_names() {
+            let loam = locate(&name);
+            // layers (or zoom buckets) with no features have no file
+            if loam.exists() {
+                file.push(FileHash::compute(&loam)?);
+            }
+        }
+        self.layer.push(LayerCount {
+            name: layer.name().to_string(),
+            features,
+            hash,
+            file,
+        });
+        Ok(())
+    }
+
+    /// Check a loam file against the hashes from its dig.
+    ///
+    /// Manifests without file hashes (from older digs) are not checked.
+    ///
+    /// * `full` Hash all contents, instead of only the size and samples.
+    pub fn check_file(&self, loam: &Path, full: bool) -> Result<()> {
+        if self.layer.iter().all(|l| l.file.is_empty()) {
+            return Ok(());
+        }
+        let name = loam
+            .file_name()
+            .map(|f| f.to_string_lossy())
+            .unwrap_or_default();
+        match self
+            .layer
+            .iter()
+            .flat_map(|l| l.file.iter())
+            .find(|f| f.name == name)
+        {
+            Some(hash) => hash.check(loam, full),
+            None => Err(Error::LoamMismatch(format!(
+                "{loam:?}: not in manifest (dig {})",
+                self.dig_id
+            ))),
+        }
+    }
+
+    /// Write manifest to a directory
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        write(dir.join(MANIFEST), muon_rs::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Read manifest from a directory, if it exists.
+    ///
+    /// Logs a warning if it was written by a different version.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST);
+        if !path.exists() {
+            log::warn!("no manifest: {path:?}");
+            return Ok(None);
+        }
+        let manifest: Self = muon_rs::from_str(&read_to_string(path)?)?;
+        let version = env!("CARGO_PKG_VERSION");
+        if manifest.version != version {
+            log::warn!(
+                "loam dug with earthwyrm {}, serving with {version}",
+                manifest.version
+            );
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Get a description of data currency
+    pub fn current_as_of(&self) -> String {
+        if self.osm.replication_timestamp.is_empty() {
+            format!("data dug at {}", self.dig_time)
+        } else {
+            format!("data current as of {}", self.osm.replication_timestamp)
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// meta.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::{Error, Result};
+use crate::layer::{geom_type_name, parse_precision, LayerDef, Precision};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+/// Loam on-disk format written by this version (rosewood/loam crate)
+pub const LOAM_FORMAT: &str = "loam-0.3";
+
+/// Format of metadata written before loam formats were recorded.
+///
+/// These files were all written by `loam-0.3`, so they can still be read.
+const UNVERSIONED: &str = "unversioned";
+
+/// Mechanical loam format migration
+struct Migration {
+    /// Format migrated from
+    from: &'static str,
+
+    /// Format migrated to
+    to: &'static str,
+
+    /// Re-write one layer's loam files (read old, write new), given its
+    /// loam path and metadata.  Metadata is written after this succeeds.
+    run: fn(&Path, &LoamMeta) -> Result<()>,
+}
+
+/// Known mechanical migrations (re-written without digging)
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: UNVERSIONED,
+    to: LOAM_FORMAT,
+    run: metadata_only,
+}];
+
+/// Status of a layer after migration
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MigrateStatus {
+    /// No loam metadata (not dug)
+    NoMeta,
+
+    /// Already in the current format
+    Current,
+
+    /// Migrated from an older format
+    Migrated(String),
+
+    /// No mechanical migration from a format; dig is required
+    Unsupported(String),
+}
+
+/// Loam file metadata, stored in a `.loam.meta` sidecar file
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoamMeta {
+    /// Type for geometry (`point`, `linestring` or `polygon`)
+    pub geom_type: String,
+
+    /// Included tags, in order of stored values
+    pub tags: Vec<String>,
+
+    /// Content hash of layer definition and matched objects
+    #[serde(default)]
+    pub hash: String,
+
+    /// Hash of layer definition, extract rules, OSM files and DEM (checked
+    /// before extracting)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
+
+    /// Number of features
+    #[serde(default)]
+    pub features: usize,
+
+    /// Date of `now` in relative date patterns, when dug
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dates_at: Option<String>,
+
+    /// Geometry storage precision (`f32`; `None` for `f64`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<String>,
+
+    /// Loam on-disk format (`None` if written before formats were recorded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loam_format: Option<String>,
+}
+
+/// FNV-1a hasher (stable across runs and platforms)
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    /// Hash some bytes
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Hash a length-prefixed string
+    pub fn write_str(&mut self, s: &str) {
+        self.write(&s.len().to_le_bytes());
+        self.write(s.as_bytes());
+    }
+
+    /// Get the hash value
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Get the hash value as a hex string
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Get path to a loam metadata file
+pub fn meta_path(loam: &Path) -> PathBuf {
+    let mut path = loam.as_os_str().to_owned();
+    path.push(".meta");
+    PathBuf::from(path)
+}
+
+impl From<&LayerDef> for LoamMeta {
+    fn from(layer: &LayerDef) -> Self {
+        LoamMeta {
+            geom_type: geom_type_name(layer.geom_tp()).to_string(),
+            tags: layer.tags().map(str::to_string).collect(),
+            hash: String::new(),
+            source: String::new(),
+            features: 0,
+            dates_at: layer.dates_at(),
+            precision: match layer.precision() {
+                Precision::F32 => Some(Precision::F32.as_str().to_string()),
+                Precision::F64 => None,
+            },
+            loam_format: Some(LOAM_FORMAT.to_string()),
+        }
+    }
+}
+
+impl LoamMeta {
+    /// Set content hash
+    #[cfg(feature = "dig")]
+    pub fn with_hash(mut self, hash: String) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Set source hash
+    #[cfg(feature = "dig")]
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Check if source matches another loam file's metadata.
+    ///
+    /// If so, the layer is unchanged without extracting objects.
+    #[cfg(feature = "dig")]
+    pub fn same_source(&self, other: &Self) -> bool {
+        !self.source.is_empty()
+            && self.source == other.source
+            && self.loam_format == other.loam_format
+    }
+
+    /// Check if content matches another loam file's metadata
+    #[cfg(feature = "dig")]
+    pub fn same_content(&self, other: &Self) -> bool {
+        self.geom_type == other.geom_type
+            && self.tags == other.tags
+            && self.hash == other.hash
+            && self.precision == other.precision
+            && self.loam_format == other.loam_format
+    }
+
+    /// Get the geometry storage precision
+    pub fn precision(&self) -> Result<Precision> {
+        parse_precision(self.precision.as_deref())
+    }
+
+    /// Get the loam on-disk format
+    pub fn loam_format(&self) -> &str {
+        self.loam_format.as_deref().unwrap_or(UNVERSIONED)
+    }
+
+    /// Check that a loam file was written in a readable format.
+    ///
+    /// This is checked before opening the tree, so an incompatible file is
+    /// not mistaken for a corrupt one.
+    pub fn check_format(loam: &Path) -> Result<()> {
+        let Some(meta) = LoamMeta::read(loam)? else {
+            return Ok(());
+        };
+        match meta.loam_format() {
+            LOAM_FORMAT | UNVERSIONED => Ok(()),
+            format => Err(Error::LoamVersionMismatch {
+                layer: layer_name(loam),
+                written_by: format.to_string(),
+                expected: LOAM_FORMAT.to_string(),
+            }),
+        }
+    }
+
+    /// Write metadata for a loam file
+    pub fn write(&self, loam: &Path) -> Result<()> {
+        write(meta_path(loam), muon_rs::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Read metadata for a loam file, if it exists
+    pub fn read(loam: &Path) -> Result<Option<Self>> {
+        let path = meta_path(loam);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let meta = read_to_string(path)?;
+        Ok(Some(muon_rs::from_str(&meta)?))
+    }
+
+    /// Validate loam metadata against a layer definition
+    pub fn validate(loam: &Path, layer: &LayerDef) -> Result<Option<Self>> {
+        let Some(meta) = LoamMeta::read(loam)? else {
+            log::warn!("layer '{}': no loam metadata", layer.name());
+            return Ok(None);
+        };
+        let geom_type = geom_type_name(layer.geom_tp());
+        if meta.geom_type != geom_type {
+            return Err(Error::LoamMismatch(format!(
+                "layer '{}': loam contains {} but config says {}; \
+                re-run dig",
+                layer.name(),
+                meta.geom_type,
+                geom_type,
+            )));
+        }
+        // shared loam layers use the precision of the loam file
+        let precision = meta.precision()?;
+        if layer.loam().is_none() && precision != layer.precision() {
+            return Err(Error::LoamMismatch(format!(
+                "layer '{}': loam stored as {} but config says {}; \
+                re-run dig",
+                layer.name(),
+                precision.as_str(),
+                layer.precision().as_str(),
+            )));
+        }
+        if !meta.tags.iter().map(String::as_str).eq(layer.tags()) {
+            if let Some(base) = layer.loam() {
+                return Err(Error::LoamMismatch(format!(
+                    "layer '{}': tags must match shared loam layer '{base}'",
+                    layer.name(),
+                )));
+            }
+            log::warn!(
+                "layer '{}': loam tags differ from config; re-run dig",
+                layer.name()
+            );
+        }
+        Ok(Some(meta))
+    }
+}
+
+/// Get layer name from a loam path
+fn layer_name(loam: &Path) -> String {
+    loam.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Migration which only records the new format in metadata
+fn metadata_only(_loam: &Path, _meta: &LoamMeta) -> Result<()> {
+    Ok(())
+}
+
+/// Migrate a layer's loam files to the current format.
+///
+/// Migrations are applied in sequence until the current format is reached.
+/// Layers in a format without a mechanical migration must be dug again.
+pub fn migrate_loam(loam: &Path) -> Result<MigrateStatus> {
+    let Some(mut meta) = LoamMeta::read(loam)? else {
+        return Ok(MigrateStatus::NoMeta);
+    };
+    let from = meta.loam_format().to_string();
+    while meta.loam_format() != LOAM_FORMAT {
+        let format = meta.loam_format().to_string();
+        let Some(migration) = MIGRATIONS.iter().find(|m| m.from == format)
+        else {
+            return Ok(MigrateStatus::Unsupported(format));
+        };
+        log::debug!(
+            "{}: migrating {} to {}",
+            layer_name(loam),
+            migration.from,
+            migration.to
+        );
+        (migration.run)(loam, &meta)?;
+        meta.loam_format = Some(migration.to.to_string());
+        meta.write(loam)?;
+    }
+    if from == LOAM_FORMAT {
+        Ok(MigrateStatus::Current)
+    } else {
+        Ok(MigrateStatus::Migrated(from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayerCfg;
+
+    /// Write metadata with a loam format to a temp dir
+    fn write_meta(name: &str, format: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-meta-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg: LayerCfg = muon_rs::from_str(
+            "name: water\ngeom_type: polygon\nzoom: 0+\ntags: .natural=water\n",
+        )
+        .unwrap();
+        let layer = LayerDef::try_from(&cfg).unwrap();
+        let mut meta = LoamMeta::from(&layer);
+        meta.loam_format = format.map(str::to_string);
+        let loam = dir.join("water.loam");
+        meta.write(&loam).unwrap();
+        loam
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let loam = write_meta("mismatch", Some("loam-0.1"));
+        match LoamMeta::check_format(&loam) {
+            Err(Error::LoamVersionMismatch {
+                layer,
+                written_by,
+                expected,
+            }) => {
+                assert_eq!(layer, "water");
+                assert_eq!(written_by, "loam-0.1");
+                assert_eq!(expected, LOAM_FORMAT);
+            }
+            res => panic!("unexpected: {res:?}"),
+        }
+        assert_eq!(
+            migrate_loam(&loam).unwrap(),
+            MigrateStatus::Unsupported("loam-0.1".into())
+        );
+        std::fs::remove_dir_all(loam.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn migrate_unversioned() {
+        let loam = write_meta("unversioned", None);
+        LoamMeta::check_format(&loam).unwrap();
+        assert_eq!(
+            migrate_loam(&loam).unwrap(),
+            MigrateStatus::Migrated(UNVERSIONED.into())
+        );
+        assert_eq!(migrate_loam(&loam).unwrap(), MigrateStatus::Current);
+        std::fs::remove_dir_all(loam.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+// obj_cache.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::{Error, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use osmpbfreader::{
+    Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{rename, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Object cache file format version
+const VERSION: u32 = 2;
+
+/// Header of an object cache file.
+///
+/// Cache files are gzip-compressed JSON lines: this header, followed by
+/// one compact object per line (matched objects and their dependencies).
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CacheHeader {
+    /// File format version
+    version: u32,
+
+    /// Hash of layer match rules
+    hash: String,
+
+    /// OSM file identity (name, length and modified time)
+    pbf: String,
+
+    /// Tag keys kept in cached objects
+    keys: BTreeSet<String>,
+
+    /// Number of objects
+    objects: usize,
+}
+
+/// Compact cached object, with tags reduced to the header keys
+#[derive(Debug, Deserialize, Serialize)]
+enum CacheObj {
+    /// Node (ID, latitude, longitude, tags)
+    N(i64, i32, i32, Vec<(String, String)>),
+
+    /// Way (ID, node IDs, tags)
+    W(i64, Vec<i64>, Vec<(String, String)>),
+
+    /// Relation (ID, members, tags)
+    R(i64, Vec<(CacheRef, String)>, Vec<(String, String)>),
+}
+
+/// Relation member reference of a cached object
+#[derive(Debug, Deserialize, Serialize)]
+enum CacheRef {
+    /// Node member
+    N(i64),
+
+    /// Way member
+    W(i64),
+
+    /// Relation member
+    R(i64),
+}
+
+/// Get path to a layer object cache file
+pub fn cache_path(dir: &Path, layer: &str) -> PathBuf {
+    dir.join(format!("{layer}.objs"))
+}
+
+impl CacheHeader {
+    /// Create a new cache header
+    ///
+    /// * `hash` Hash of layer match rules.
+    /// * `pbf` OSM file identity.
+    /// * `keys` Tag keys to keep in cached objects.
+    pub fn new<'a>(
+        hash: String,
+        pbf: String,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        CacheHeader {
+            version: VERSION,
+            hash,
+            pbf,
+            keys: keys.into_iter().map(str::to_string).collect(),
+            objects: 0,
+        }
+    }
+
+    /// Check if a cache was extracted with the same rules and OSM file.
+    ///
+    /// It must also keep all tag keys needed by `other`; changing included
+    /// tags only requires extracting again when a new key is added.
+    pub fn same_source(&self, other: &Self) -> bool {
+        self.version == other.version
+            && self.hash == other.hash
+            && self.pbf == other.pbf
+            && other.keys.is_subset(&self.keys)
+    }
+
+    /// Reduce tags to cached keys
+    fn tags(&self, tags: &Tags) -> Vec<(String, String)> {
+        let mut tags: Vec<_> = tags
+            .iter()
+            .filter(|(k, _v)| self.keys.contains(k.as_str()))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    /// Make a compact cached object
+    fn compact(&self, obj: &OsmObj) -> CacheObj {
+        match obj {
+            OsmObj::Node(node) => CacheObj::N(
+                node.id.0,
+                node.decimicro_lat,
+                node.decimicro_lon,
+                self.tags(&node.tags),
+            ),
+            OsmObj::Way(way) => CacheObj::W(
+                way.id.0,
+                way.nodes.iter().map(|n| n.0).collect(),
+                self.tags(&way.tags),
+            ),
+            OsmObj::Relation(rel) => CacheObj::R(
+                rel.id.0,
+                rel.refs
+                    .iter()
+                    .map(|rf| {
+                        let member = match rf.member {
+                            OsmId::Node(id) => CacheRef::N(id.0),
+                            OsmId::Way(id) => CacheRef::W(id.0),
+                            OsmId::Relation(id) => CacheRef::R(id.0),
+                        };
+                        (member, rf.role.to_string())
+                    })
+                    .collect(),
+                self.tags(&rel.tags),
+            ),
+        }
+    }
+
+    /// Read the header of a cache file, if it exists and is readable
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(GzDecoder::new(File::open(path)?));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match serde_json::from_str(&line) {
+            Ok(header) => Ok(Some(header)),
+            Err(e) => {
+                log::warn!("{path:?}: {e}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Make tags from cached key / value pairs
+fn make_tags(kv: Vec<(String, String)>) -> Tags {
+    let mut tags = Tags::new();
+    for (k, v) in kv {
+        tags.insert(k.into(), v.into());
+    }
+    tags
+}
+
+impl From<CacheObj> for OsmObj {
+    fn from(obj: CacheObj) -> Self {
+        match obj {
+            CacheObj::N(id, decimicro_lat, decimicro_lon, tags) => {
+                OsmObj::Node(Node {
+                    id: NodeId(id),
+                    tags: make_tags(tags),
+                    decimicro_lat,
+                    decimicro_lon,
+                })
+            }
+            CacheObj::W(id, nodes, tags) => OsmObj::Way(Way {
+                id: WayId(id),
+                tags: make_tags(tags),
+                nodes: nodes.into_iter().map(NodeId).collect(),
+            }),
+            CacheObj::R(id, refs, tags) => OsmObj::Relation(Relation {
+                id: RelationId(id),
+                tags: make_tags(tags),
+                refs: refs
+                    .into_iter()
+                    .map(|(member, role)| Ref {
+                        member: match member {
+                            CacheRef::N(id) => OsmId::Node(NodeId(id)),
+                            CacheRef::W(id) => OsmId::Way(WayId(id)),
+                            CacheRef::R(id) => OsmId::Relation(RelationId(id)),
+                        },
+                        role: role.into(),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+}
+
+/// Write an object cache file.
+///
+/// The file is written to a temporary path, then renamed, so an
+/// interrupted write never leaves a fresh-looking cache.
+pub fn write_objs(
+    path: &Path,
+    mut header: CacheHeader,
+    objs: &BTreeMap<OsmId, OsmObj>,
+) -> Result<()> {
+    header.objects = objs.len();
+    let tmp = path.with_extension("objs.tmp");
+    let file = BufWriter::new(File::create(&tmp)?);
+    let mut out = GzEncoder::new(file, Compression::fast());
+    serde_json::to_writer(&mut out, &header).map_err(io::Error::from)?;
+    out.write_all(b"\n")?;
+    for obj in objs.values() {
+        let obj = header.compact(obj);
+        serde_json::to_writer(&mut out, &obj).map_err(io::Error::from)?;
+        out.write_all(b"\n")?;
+    }
+    out.finish()?.flush()?;
+    rename(tmp, path)?;
+    Ok(())
+}
+
+/// Read all objects from a cache file
+pub fn read_objs(path: &Path) -> Result<BTreeMap<OsmId, OsmObj>> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut lines = reader.lines();
+    let header: CacheHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(io::Error::from)?,
+        None => return Err(Error::ObjCache(format!("{path:?}: empty"))),
+    };
+    let mut objs = BTreeMap::new();
+    for line in lines {
+        let obj: CacheObj =
+            serde_json::from_str(&line?).map_err(io::Error::from)?;
+        let obj = OsmObj::from(obj);
+        objs.insert(obj.id(), obj);
+    }
+    if objs.len() != header.objects {
+        return Err(Error::ObjCache(format!(
+            "{path:?}: {} of {} objects",
+            objs.len(),
+            header.objects
+        )));
+    }
+    Ok(objs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make tags from `key=value` pairs
+    fn tags(kv: &[(&str, &str)]) -> Tags {
+        let mut tags = Tags::new();
+        for (k, v) in kv {
+            tags.insert((*k).into(), (*v).into());
+        }
+        tags
+    }
+
+    #[test]
+    fn round_trip_compact() {
+        let path = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-water.objs", std::process::id()));
+        let mut objs = BTreeMap::new();
+        let node = OsmObj::Node(Node {
+            id: NodeId(1),
+            tags: tags(&[("created_by", "JOSM")]),
+            decimicro_lat: 450_000_000,
+            decimicro_lon: -930_000_000,
+        });
+        let way = OsmObj::Way(Way {
+            id: WayId(10),
+            tags: tags(&[("natural", "water"), ("source", "survey")]),
+            nodes: vec![NodeId(1), NodeId(2), NodeId(1)],
+        });
+        let rel = OsmObj::Relation(Relation {
+            id: RelationId(30),
+            tags: tags(&[("type", "multipolygon"), ("name", "Lake")]),
+            refs: vec![Ref {
+                member: OsmId::Way(WayId(10)),
+                role: "outer".into(),
+            }],
+        });
+        for obj in [node, way, rel] {
+            objs.insert(obj.id(), obj);
+        }
+        let keys = ["natural", "name", "type"];
+        let header = CacheHeader::new("h".into(), "p".into(), keys);
+        write_objs(&path, header, &objs).unwrap();
+        let cached = read_objs(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cached.len(), 3);
+        let node = cached[&OsmId::Node(NodeId(1))].node().unwrap();
+        assert!(node.tags.is_empty());
+        assert_eq!(node.decimicro_lon, -930_000_000);
+        let way = cached[&OsmId::Way(WayId(10))].way().unwrap();
+        assert_eq!(way.tags, tags(&[("natural", "water")]));
+        assert_eq!(way.nodes, [NodeId(1), NodeId(2), NodeId(1)]);
+        let rel = cached[&OsmId::Relation(RelationId(30))].relation().unwrap();
+        assert_eq!(
+            rel.tags,
+            tags(&[("type", "multipolygon"), ("name", "Lake")])
+        );
+        assert_eq!(rel.refs[0].member, OsmId::Way(WayId(10)));
+        assert_eq!(rel.refs[0].role.as_str(), "outer");
+    }
+
+    #[test]
+    fn staleness() {
+        let cached = CacheHeader::new("h".into(), "p".into(), ["a", "b"]);
+        let same = |hash: &str, pbf: &str, keys: &[&str]| {
+            let header =
+                CacheHeader::new(hash.into(), pbf.into(), keys.iter().copied());
+            cached.same_source(&header)
+        };
+        assert!(same("h", "p", &["a", "b"]));
+        // dropping an included tag
+        assert!(same("h", "p", &["b"]));
+        // adding an included tag
+        assert!(!same("h", "p", &["a", "b", "c"]));
+        // match rules or OSM file changed
+        assert!(!same("x", "p", &["a"]));
+        assert!(!same("h", "x", &["a"]));
+        let mut old = CacheHeader::new("h".into(), "p".into(), ["a", "b"]);
+        old.version = 1;
+        assert!(!old.same_source(&cached));
+    }
+}
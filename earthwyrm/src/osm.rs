@@ -2,31 +2,269 @@
 //
 // Copyright (c) 2021-2024  Minnesota Department of Transportation
 //
-use crate::config::WyrmCfg;
-use crate::error::Result;
-use crate::geom::Values;
-use crate::layer::LayerDef;
+use crate::compact::{
+    compact_objs, CompactDeps, CompactMap, CompactNode, CompactObj,
+    CompactRelation, CompactTags, CompactWay,
+};
+use crate::config::{IdPolicy, WyrmCfg};
+use crate::dem::Dem;
+use crate::error::{Error, Result};
+use crate::geom::{
+    bbox_all, signed_area, web_mercator, GeomTree, Narrow, Values,
+    MERCATOR_LAT_MAX,
+};
+use crate::id_index::write_index;
+use crate::layer::{
+    geom_type_name, is_area, normalize_sint, parse_extent, parse_sint, Derive,
+    Generate, LayerDef, MatchStats, MemberRole, OsmTags, PatternCheck,
+    Precision,
+};
+use crate::manifest::{Manifest, OsmHeader, MANIFEST};
+use crate::meta::{Fnv1a, LoamMeta};
+use crate::obj_cache::{cache_path, read_objs, write_objs, CacheHeader};
+#[cfg(feature = "xml")]
+use crate::osm_xml::OsmXml;
+use crate::skips::{SkipLog, SkipReason};
 use mvt::{GeomType, WebMercatorPos, Wgs84Pos};
 use osmpbfreader::{
-    Node, NodeId, OsmId, OsmObj, OsmPbfReader, Relation, Tags, Way,
+    Node, NodeId, OsmId, OsmObj, OsmPbfReader, RelationId, Tags, WayId,
 };
 use rosewood::{gis, gis::Gis, BulkWriter};
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::SystemTime;
 
 /// OSM object map
 type ObjMap = BTreeMap<OsmId, OsmObj>;
 
-/// Tool to extract data from an OSM file
+/// Node degree map (number of incident way segments)
+type DegreeMap = BTreeMap<NodeId, usize>;
+
+/// Polygon ring, with outer flag
+type Ring = (bool, Vec<(f64, f64)>);
+
+/// Relation keys which do not describe a feature (old-style multipolygons)
+const META_KEYS: &[&str] = &["type", "created_by", "source", "note"];
+
+/// Maximum number of coordinates in geometry cache
+const CACHE_COORDS: usize = 1 << 24;
+
+/// Default free space needed to dig, as a multiple of OSM file size
+const DIG_SPACE_FACTOR: f64 = 2.0;
+
+/// Start of OSM PBF file, after blob header length:
+/// field 1 (type), length 9, `OSMHeader`
+const PBF_MAGIC: &[u8] = b"\x0a\x09OSMHeader";
+
+/// Starts of OSM XML files (plain or bzip2-compressed)
+const XML_MAGIC: &[&[u8]] = &[b"<?xml", b"<osm", b"BZh"];
+
+/// Offset of namespaced OSM IDs, per input file index
+const NAMESPACE_OFFSET: i64 = 1 << 48;
+
+/// Distance from extract bounds (fraction of size) to close chains along
+const EDGE_TOLERANCE: f64 = 0.001;
+
+/// Cache of assembled geometry, shared between layers.
+///
+/// Entries are evicted in least-recently-used order once the total
+/// coordinate count exceeds the capacity.
+struct GeomCache {
+    /// Capacity (coordinates)
+    capacity: usize,
+
+    /// Current coordinate count
+    n_coords: usize,
+
+    /// Access counter
+    tick: u64,
+
+    /// Cached rings, with last access tick
+    entries: BTreeMap<OsmId, (u64, Rc<Vec<Ring>>)>,
+
+    /// Access order (tick to ID)
+    order: BTreeMap<u64, OsmId>,
+
+    /// Number of cache hits
+    hits: u64,
+
+    /// Number of cache misses
+    misses: u64,
+}
+
+/// Stage of a dig
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DigStage {
+    /// Extract matched objects into the object cache
+    Extract,
+
+    /// Make loam files from the object cache
+    Geometry,
+
+    /// Extract stale layers, then make loam files
+    #[default]
+    All,
+}
+
+/// OSM file format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OsmFormat {
+    /// Protocol buffer binary format (`.pbf`)
+    Pbf,
+
+    /// XML format (`.osm` or `.osm.bz2`)
+    Xml,
+}
+
+/// Source of OSM objects
+enum OsmSource {
+    /// PBF file reader
+    Pbf(OsmPbfReader<File>),
+
+    /// Objects read from an XML file
+    #[cfg(feature = "xml")]
+    Xml(OsmXml),
+}
+
+/// Tool to extract data from one OSM file
 struct OsmExtractor {
-    pbf: OsmPbfReader<File>,
+    path: PathBuf,
+    source: OsmSource,
+    header: OsmHeader,
+}
+
+/// OSM object checked against layer patterns
+#[derive(Clone, Debug)]
+pub struct ObjMatch {
+    /// Object type (`node`, `way` or `relation`)
+    pub kind: &'static str,
+
+    /// Object ID
+    pub id: i64,
+
+    /// Object matches layer (including geometry rules)
+    pub matched: bool,
+
+    /// Pattern checks
+    pub checks: Vec<PatternCheck>,
 }
 
 /// Geometry layer maker
-struct GeometryMaker {
+struct GeometryMaker<'a> {
     layer: LayerDef,
-    objs: ObjMap,
+    objs: CompactMap,
+    deps: CompactDeps,
+    dem: Option<&'a Dem>,
+    cache: &'a RefCell<GeomCache>,
+    skips: &'a RefCell<SkipLog>,
+    n_dropped: Cell<usize>,
+    bytes_dropped: Cell<usize>,
+    n_salvaged: Cell<usize>,
+    n_skipped: Cell<usize>,
+    n_split: Cell<usize>,
+    n_span_split: Cell<usize>,
+    n_truncated: Cell<usize>,
+    n_unparsed: Cell<usize>,
+    n_unstored: Cell<usize>,
+    bytes_unstored: Cell<usize>,
+    degrees: DegreeMap,
+    bounds: Option<[f64; 4]>,
+    source: String,
+}
+
+/// Generator of synthetic layers (`source: generated`)
+struct ShapeGenerator {
+    layer: LayerDef,
+    bounds: [f64; 4],
+}
+
+impl GeomCache {
+    /// Create a new geometry cache
+    fn new(capacity: usize) -> Self {
+        GeomCache {
+            capacity,
+            n_coords: 0,
+            tick: 0,
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Get cached rings for an object
+    fn get(&mut self, id: OsmId) -> Option<Rc<Vec<Ring>>> {
+        self.tick += 1;
+        match self.entries.get_mut(&id) {
+            Some((tick, rings)) => {
+                self.order.remove(tick);
+                *tick = self.tick;
+                self.order.insert(self.tick, id);
+                self.hits += 1;
+                Some(Rc::clone(rings))
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Insert rings for an object
+    fn insert(&mut self, id: OsmId, rings: Rc<Vec<Ring>>) {
+        let len = n_coords(&rings);
+        if len > self.capacity {
+            return;
+        }
+        while self.n_coords + len > self.capacity {
+            let Some((_tick, old)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_tick, old)) = self.entries.remove(&old) {
+                self.n_coords -= n_coords(&old);
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, id);
+        if let Some((tick, old)) = self.entries.insert(id, (self.tick, rings)) {
+            self.order.remove(&tick);
+            self.n_coords -= n_coords(&old);
+        }
+        self.n_coords += len;
+    }
+
+    /// Get the hit rate (percent)
+    fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups > 0 {
+            100.0 * self.hits as f64 / lookups as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Count coordinates in a slice of rings
+fn n_coords(rings: &[Ring]) -> usize {
+    rings.iter().map(|(_outer, pts)| pts.len()).sum()
+}
+
+impl FromStr for DigStage {
+    type Err = Error;
+
+    fn from_str(stage: &str) -> Result<Self> {
+        match stage {
+            "extract" => Ok(DigStage::Extract),
+            "geometry" => Ok(DigStage::Geometry),
+            "all" => Ok(DigStage::All),
+            _ => Err(Error::InvalidDigStage(stage.into())),
+        }
+    }
 }
 
 impl OsmExtractor {
@@ -35,347 +273,4400 @@ impl OsmExtractor {
     where
         P: AsRef<Path>,
     {
-        let osm = File::open(path)?;
-        let pbf = OsmPbfReader::new(osm);
-        Ok(OsmExtractor { pbf })
+        let path = path.as_ref().to_path_buf();
+        #[cfg(feature = "xml")]
+        if osm_format(&path)? == OsmFormat::Xml {
+            let xml = OsmXml::read(&path)?;
+            let header = xml.header.clone();
+            let source = OsmSource::Xml(xml);
+            return Ok(OsmExtractor {
+                path,
+                source,
+                header,
+            });
+        }
+        let header = OsmHeader::read(&path).unwrap_or_else(|e| {
+            log::warn!("OSM header: {e}");
+            OsmHeader::default()
+        });
+        let source = OsmSource::Pbf(OsmPbfReader::new(File::open(&path)?));
+        Ok(OsmExtractor {
+            path,
+            source,
+            header,
+        })
+    }
+
+    /// Extract objects matching a predicate, with their dependencies
+    fn extract_objs<F>(&mut self, pred: F) -> Result<ObjMap>
+    where
+        F: FnMut(&OsmObj) -> bool,
+    {
+        match &mut self.source {
+            OsmSource::Pbf(pbf) => Ok(pbf.get_objs_and_deps(pred)?),
+            #[cfg(feature = "xml")]
+            OsmSource::Xml(xml) => Ok(xml.objs_and_deps(pred)),
+        }
     }
 
     /// Extract a objects for a map layer
-    fn extract_layer(&mut self, layer: &LayerDef) -> Result<ObjMap> {
+    fn extract_layer(
+        &mut self,
+        layer: &LayerDef,
+        skips: &mut SkipLog,
+        stats: &mut MatchStats,
+    ) -> Result<ObjMap> {
         log::debug!("extracting layer: {}", layer.name());
-        Ok(self.pbf.get_objs_and_deps(|obj| layer.check_obj(obj))?)
+        if layer.geom_tp() == GeomType::Point && layer.member_role().is_none() {
+            return self.extract_nodes(layer, skips, stats);
+        }
+        let pred = |obj: &OsmObj| {
+            let matched = layer.check_obj_with(obj, |tags| {
+                layer.check_tags_counted(tags, stats)
+            });
+            if !matched {
+                if let Some(reason) = layer.skip_reason(obj) {
+                    skips.record(layer.name(), obj.id(), reason);
+                }
+            }
+            matched
+        };
+        self.extract_objs(pred)
     }
-}
 
-impl LayerDef {
-    /// Check if an OSM object matches a layer's tag patterns
-    fn check_obj(&self, obj: &OsmObj) -> bool {
-        let tags = obj.tags();
-        match self.geom_tp() {
-            GeomType::Point | GeomType::Linestring => self.check_tags(tags),
-            GeomType::Polygon => {
-                // polygons are relations or closed ways
-                (obj.is_relation() || obj.is_way()) && self.check_tags(tags)
+    /// Extract matching nodes for a point layer.
+    ///
+    /// Nodes have no dependencies, so the file is streamed without
+    /// collecting any other objects.
+    fn extract_nodes(
+        &mut self,
+        layer: &LayerDef,
+        skips: &mut SkipLog,
+        stats: &mut MatchStats,
+    ) -> Result<ObjMap> {
+        let mut objs = ObjMap::new();
+        let mut check = |node: Node| {
+            if layer.check_tags_counted(&node.tags, stats) {
+                objs.insert(OsmId::Node(node.id), OsmObj::Node(node));
+            } else if layer.check_any_tag(&node.tags) {
+                skips.record(
+                    layer.name(),
+                    OsmId::Node(node.id),
+                    SkipReason::PatternMismatch,
+                );
+            }
+        };
+        match &mut self.source {
+            OsmSource::Pbf(pbf) => {
+                pbf.rewind()?;
+                for obj in pbf.par_iter() {
+                    if let OsmObj::Node(node) = obj? {
+                        check(node);
+                    }
+                }
+            }
+            #[cfg(feature = "xml")]
+            OsmSource::Xml(xml) => {
+                for obj in xml.objs.values() {
+                    if let OsmObj::Node(node) = obj {
+                        check(node.clone());
+                    }
+                }
             }
         }
+        Ok(objs)
     }
 }
 
-impl GeometryMaker {
-    /// Create a new geometry layer maker
-    fn new(layer: LayerDef, objs: ObjMap) -> Self {
-        Self { layer, objs }
+/// Extract objects for a map layer from all input files.
+///
+/// Objects are merged in input order, resolving ID collisions by `policy`.
+/// References to objects missing from the merged map are then looked up
+/// in every file, so relation members can come from another file.  With
+/// the `namespace` policy, objects found this way are namespaced the same
+/// as the rest of their file.
+///
+/// Match pattern statistics are printed, and patterns are reordered by
+/// selectivity if `reorder` is set.
+fn extract_merged(
+    extractors: &mut [OsmExtractor],
+    layer: &LayerDef,
+    skips: &mut SkipLog,
+    policy: IdPolicy,
+    reorder: bool,
+) -> Result<ObjMap> {
+    let mut merged = ObjMap::new();
+    let mut stats = layer.match_stats(reorder);
+    // namespaced IDs of each file
+    let mut namespaces = Vec::with_capacity(extractors.len());
+    for (index, extractor) in extractors.iter_mut().enumerate() {
+        let objs = extractor.extract_layer(layer, skips, &mut stats)?;
+        let collisions =
+            merge_objs(&mut merged, objs, index, policy).map_err(|id| {
+                let (kind, id) = id_kind(id);
+                Error::IdCollision(format!(
+                    "layer '{}': {kind} {id} in {:?}",
+                    layer.name(),
+                    extractor.path
+                ))
+            })?;
+        let n = collisions.len();
+        if n > 0 {
+            println!(
+                "  layer: {} ({n} ID collisions in {:?}, {policy:?})",
+                layer.name(),
+                extractor.path
+            );
+        }
+        namespaces.push(match policy {
+            IdPolicy::Namespace => collisions,
+            _ => HashSet::new(),
+        });
+    }
+    if extractors.len() > 1 {
+        resolve_missing(extractors, &namespaces, &mut merged)?;
     }
+    println!("  layer: {} ({} objects extracted)", layer.name(), merged.len());
+    stats.print(layer);
+    Ok(merged)
+}
 
-    /// Make point geometry from a `Node`
-    fn node_point(&self, node: &Node) -> Option<gis::Points<f64, Values>> {
-        let values = self.tag_values(node.id.0, &node.tags);
-        let mut point = gis::Points::new(values);
-        for pt in self.lookup_nodes(&[node.id]) {
-            point.push(pt);
+/// Merge objects from one input file into a map.
+///
+/// Returns the colliding IDs (objects which differ), or the first colliding
+/// ID with the `error` policy.
+///
+/// With the `namespace` policy, colliding objects are added with offset
+/// IDs, and references to them are offset to match.  Other objects are
+/// only added if missing, so that earlier files keep their references.
+fn merge_objs(
+    merged: &mut ObjMap,
+    objs: ObjMap,
+    index: usize,
+    policy: IdPolicy,
+) -> std::result::Result<HashSet<OsmId>, OsmId> {
+    let collisions: HashSet<OsmId> = objs
+        .iter()
+        .filter(|(id, obj)| merged.get(id).is_some_and(|o| o != *obj))
+        .map(|(id, _obj)| *id)
+        .collect();
+    match policy {
+        IdPolicy::Error => {
+            if let Some(id) = collisions.iter().min() {
+                return Err(*id);
+            }
+        }
+        IdPolicy::LastWins => (),
+        IdPolicy::Namespace => {
+            for obj in objs.into_values() {
+                let obj = namespace_obj(obj, &collisions, index);
+                merged.entry(obj.id()).or_insert(obj);
+            }
+            return Ok(collisions);
         }
-        log::debug!("added point ({:?})", point.data());
-        Some(point)
     }
+    merged.extend(objs);
+    Ok(collisions)
+}
 
-    /// Make linestring geometry from a `Way`
-    fn way_linestring(
-        &self,
-        way: &Way,
-    ) -> Option<gis::Linestrings<f64, Values>> {
-        let values = self.tag_values(way.id.0, &way.tags);
-        let mut linestring = gis::Linestrings::new(values);
-        if way.nodes.is_empty() {
-            log::warn!("no nodes ({:?})", linestring.data());
-            return None;
-        }
-        let (w0, w1) = end_points(&way.nodes);
-        log::trace!("way {:?} .. {:?}", w0.0, w1.0);
-        let len = way.nodes.len();
-        linestring.push(self.lookup_nodes(&way.nodes));
-        log::debug!("added way with {len} nodes ({:?})", linestring.data());
-        Some(linestring)
+/// Get the type name and ID number of an OSM ID
+fn id_kind(id: OsmId) -> (&'static str, i64) {
+    match id {
+        OsmId::Node(n) => ("node", n.0),
+        OsmId::Way(w) => ("way", w.0),
+        OsmId::Relation(r) => ("relation", r.0),
     }
+}
 
-    /// Make polygon geometry from a `Relation`
-    fn rel_polygon(
-        &self,
-        rel: &Relation,
-    ) -> Option<gis::Polygons<f64, Values>> {
-        let values = self.tag_values(rel.id.0, &rel.tags);
-        let mut ways = Vec::new();
-        let mut polygon = gis::Polygons::new(values);
-        for rf in &rel.refs {
-            let outer = if rf.role == "outer" {
-                true
-            } else if rf.role == "inner" {
-                false
-            } else {
-                continue;
-            };
-            let nodes = self.way_nodes(rf.member);
-            if nodes.is_empty() {
-                // relations on edges of dump area
-                // can have empty member ways
-                continue;
-            }
-            let (w0, w1) = end_points(&nodes);
-            log::trace!(
-                "{:?} way {:?} .. {:?} ({})",
-                rf.role,
-                w0.0,
-                w1.0,
-                ways.len()
-            );
-            ways.push(nodes);
-            while ways.len() > 1 {
-                if !connect_ways(&mut ways) {
-                    break;
-                }
+/// Add an offset to an OSM ID
+fn offset_id(id: OsmId, offset: i64) -> OsmId {
+    match id {
+        OsmId::Node(n) => OsmId::Node(NodeId(n.0 + offset)),
+        OsmId::Way(w) => OsmId::Way(WayId(w.0 + offset)),
+        OsmId::Relation(r) => OsmId::Relation(RelationId(r.0 + offset)),
+    }
+}
+
+/// Offset an OSM ID by input file index, if it collides
+fn namespace_id(id: OsmId, collisions: &HashSet<OsmId>, index: usize) -> OsmId {
+    if collisions.contains(&id) {
+        offset_id(id, NAMESPACE_OFFSET * index as i64)
+    } else {
+        id
+    }
+}
+
+/// Get the ID within one input file of a (possibly namespaced) OSM ID.
+///
+/// Returns `None` if the ID is namespaced to another file.
+fn file_id(id: OsmId, index: usize) -> Option<OsmId> {
+    let (_kind, n) = id_kind(id);
+    if n < NAMESPACE_OFFSET {
+        return Some(id);
+    }
+    let ns = n / NAMESPACE_OFFSET;
+    (ns == index as i64).then(|| offset_id(id, -ns * NAMESPACE_OFFSET))
+}
+
+/// Offset colliding IDs of an object and its references
+fn namespace_obj(
+    mut obj: OsmObj,
+    collisions: &HashSet<OsmId>,
+    index: usize,
+) -> OsmObj {
+    let node_id =
+        |id: NodeId| match namespace_id(OsmId::Node(id), collisions, index) {
+            OsmId::Node(n) => n,
+            _ => id,
+        };
+    match &mut obj {
+        OsmObj::Node(node) => node.id = node_id(node.id),
+        OsmObj::Way(way) => {
+            let id = OsmId::Way(way.id);
+            if let OsmId::Way(w) = namespace_id(id, collisions, index) {
+                way.id = w;
             }
-            while let Some(ring) = find_ring(&mut ways) {
-                let len = ring.len();
-                let pts = self.lookup_nodes(&ring);
-                if outer {
-                    polygon.push_outer(pts);
-                } else {
-                    polygon.push_inner(pts);
-                }
-                log::debug!(
-                    "added {:?} way with {} nodes ({:?})",
-                    rf.role,
-                    len,
-                    polygon.data(),
-                );
+            for nd in way.nodes.iter_mut() {
+                *nd = node_id(*nd);
             }
         }
-        if ways.is_empty() {
-            Some(polygon)
-        } else {
-            log::debug!("broken polygon ({:?})", polygon.data());
-            None
+        OsmObj::Relation(rel) => {
+            let id = OsmId::Relation(rel.id);
+            if let OsmId::Relation(r) = namespace_id(id, collisions, index) {
+                rel.id = r;
+            }
+            for member in rel.refs.iter_mut() {
+                member.member = namespace_id(member.member, collisions, index);
+            }
         }
     }
+    obj
+}
 
-    /// Make polygon geometry from a `Way`
-    fn way_polygon(&self, way: &Way) -> Option<gis::Polygons<f64, Values>> {
-        if way.is_open() || way.nodes.is_empty() {
-            return None;
-        }
-        let (w0, w1) = end_points(&way.nodes);
-        if w0 != w1 {
-            log::trace!("way {} not closed {} .. {}", way.id.0, w0.0, w1.0);
-            return None;
+/// Get IDs referenced by objects, but missing from a map
+fn missing_refs(objs: &ObjMap) -> HashSet<OsmId> {
+    let mut missing = HashSet::new();
+    for obj in objs.values() {
+        match obj {
+            OsmObj::Node(_) => (),
+            OsmObj::Way(way) => missing.extend(
+                way.nodes
+                    .iter()
+                    .map(|nd| OsmId::Node(*nd))
+                    .filter(|id| !objs.contains_key(id)),
+            ),
+            OsmObj::Relation(rel) => missing.extend(
+                rel.refs
+                    .iter()
+                    .map(|r| r.member)
+                    .filter(|id| !objs.contains_key(id)),
+            ),
         }
-        let values = self.tag_values(way.id.0, &way.tags);
-        let len = way.nodes.len();
-        let pts = self.lookup_nodes(&way.nodes);
-        let mut polygon = gis::Polygons::new(values);
-        polygon.push_outer(pts);
-        log::debug!("added way with {len} nodes ({:?})", polygon.data());
-        Some(polygon)
     }
+    missing
+}
 
-    /// Get the member way nodes for a relation
-    fn way_nodes(&self, id: OsmId) -> Vec<NodeId> {
-        if let Some(member) = self.objs.get(&id) {
-            if let Some(way) = member.way() {
-                if way.nodes.len() > 1 {
-                    return way.nodes.clone();
-                }
+/// Look up missing referenced objects in all input files.
+///
+/// Namespaced IDs are only looked up in their own file.  Objects found in
+/// a file are namespaced by its colliding IDs (`namespaces`), so that their
+/// references match the rest of the file.  Objects already in the map are
+/// never replaced.  Lookups repeat (for nested relations) until no more
+/// objects are found.
+fn resolve_missing(
+    extractors: &mut [OsmExtractor],
+    namespaces: &[HashSet<OsmId>],
+    objs: &mut ObjMap,
+) -> Result<()> {
+    loop {
+        let missing = missing_refs(objs);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let n_objs = objs.len();
+        for (index, extractor) in extractors.iter_mut().enumerate() {
+            let wanted: HashSet<OsmId> = missing
+                .iter()
+                .filter_map(|id| file_id(*id, index))
+                .collect();
+            if wanted.is_empty() {
+                continue;
+            }
+            let found =
+                extractor.extract_objs(|obj| wanted.contains(&obj.id()))?;
+            for obj in found.into_values() {
+                let obj = namespace_obj(obj, &namespaces[index], index);
+                objs.entry(obj.id()).or_insert(obj);
             }
         }
-        Vec::new()
+        if objs.len() == n_objs {
+            return Ok(());
+        }
+        log::debug!("resolved {} objects across files", objs.len() - n_objs);
     }
+}
 
-    /// Lookup points for a slice of nodes
-    fn lookup_nodes(&self, nodes: &[NodeId]) -> Vec<(f64, f64)> {
-        let mut pts = Vec::with_capacity(nodes.len());
-        for node in nodes {
-            let nid = OsmId::Node(*node);
-            if let Some(OsmObj::Node(node)) = self.objs.get(&nid) {
-                let pos = Wgs84Pos::new(node.lat(), node.lon());
-                let pos = WebMercatorPos::from(pos);
-                pts.push((pos.x, pos.y));
-            } else {
-                log::error!("node not found: {:?}", node);
-                return Vec::new();
+/// Call a function for each object in an OSM file, until it returns false
+fn for_each_obj<F>(path: &Path, mut f: F) -> Result<()>
+where
+    F: FnMut(OsmObj) -> bool,
+{
+    #[cfg(feature = "xml")]
+    if osm_format(path)? == OsmFormat::Xml {
+        for obj in OsmXml::read(path)?.objs.into_values() {
+            if !f(obj) {
+                break;
             }
         }
-        pts
+        return Ok(());
+    }
+    let mut reader = OsmPbfReader::new(File::open(path)?);
+    for obj in reader.par_iter() {
+        if !f(obj?) {
+            break;
+        }
     }
+    Ok(())
+}
 
-    /// Get values for included tags
-    fn tag_values(&self, id: i64, tags: &Tags) -> Values {
-        self.layer
-            .tags()
-            .map(|tag| {
-                (tag == "osm_id")
-                    .then(|| id.to_string())
-                    .or_else(|| tags.get(tag).map(|v| v.to_string()))
-            })
-            .collect()
+/// Get the type name and ID of an OSM object
+fn obj_kind_id(obj: &OsmObj) -> (&'static str, i64) {
+    match obj {
+        OsmObj::Node(node) => ("node", node.id.0),
+        OsmObj::Way(way) => ("way", way.id.0),
+        OsmObj::Relation(rel) => ("relation", rel.id.0),
     }
+}
 
-    /// Make all points for a layer
-    fn make_points<P>(&self, loam: P) -> Result<()>
-    where
-        P: AsRef<Path>,
-    {
-        let mut writer = BulkWriter::new(loam)?;
-        let mut n_point = 0;
-        for node in self.objs.iter().filter_map(|(_, obj)| obj.node()) {
-            if let Some(geom) = self.node_point(node) {
-                writer.push(&geom)?;
-                n_point += 1;
-            }
-        }
-        println!("  layer: {} ({n_point} points)", self.layer.name());
-        if n_point > 0 {
-            writer.finish()?;
-        } else {
-            writer.cancel()?;
+impl LayerDef {
+    /// Check an OSM object, explaining which patterns matched
+    fn obj_match(&self, obj: &OsmObj) -> ObjMatch {
+        let (kind, id) = obj_kind_id(obj);
+        ObjMatch {
+            kind,
+            id,
+            matched: self.check_obj(obj),
+            checks: self.explain_tags(obj.tags()),
         }
-        Ok(())
     }
 
-    /// Make all linestrings for a layer
-    fn make_linestrings<P>(&self, loam: P) -> Result<()>
+    /// Scan an OSM file for objects matching the layer.
+    ///
+    /// No geometry is made; dependencies (way nodes, etc.) are not
+    /// resolved.  Returns the total number of matching objects.
+    ///
+    /// Objects are read in parallel, so matches are passed to `sink` after
+    /// the scan, sorted by type (nodes, ways, relations) and ID.  The same
+    /// matches are passed for every run.
+    ///
+    /// * `pbf` Path to OSM file.
+    /// * `limit` Maximum number of matches passed to `sink`.
+    /// * `sink` Called for each matching object, up to `limit`.
+    pub fn scan_matches<P, F>(
+        &self,
+        pbf: P,
+        limit: usize,
+        mut sink: F,
+    ) -> Result<usize>
     where
         P: AsRef<Path>,
+        F: FnMut(&ObjMatch),
     {
-        let mut writer = BulkWriter::new(loam)?;
-        let mut n_line = 0;
-        for way in self.objs.iter().filter_map(|(_, obj)| obj.way()) {
-            if let Some(geom) = self.way_linestring(way) {
-                writer.push(&geom)?;
-                n_line += 1;
+        let mut n_match = 0;
+        // lowest object IDs, up to limit
+        let mut matches = BTreeMap::new();
+        for_each_obj(pbf.as_ref(), |obj| {
+            if self.check_obj(&obj) {
+                if limit > 0 {
+                    matches.insert(obj.id(), self.obj_match(&obj));
+                    if matches.len() > limit {
+                        matches.pop_last();
+                    }
+                }
+                n_match += 1;
             }
+            true
+        })?;
+        for m in matches.values() {
+            sink(m);
         }
-        println!("  layer: {} ({n_line} linestrings)", self.layer.name());
-        if n_line > 0 {
-            writer.finish()?;
-        } else {
-            writer.cancel()?;
-        }
-        Ok(())
+        Ok(n_match)
     }
 
-    /// Make all polygons for a layer
-    fn make_polygons<P>(&self, loam: P) -> Result<()>
+    /// Explain why one OSM object does (or does not) match the layer.
+    ///
+    /// * `pbf` Path to OSM file.
+    /// * `kind` Object type (`node`, `way` or `relation`).
+    /// * `id` Object ID.
+    ///
+    /// Returns `None` if the object is not found.
+    pub fn explain_match<P>(
+        &self,
+        pbf: P,
+        kind: &str,
+        id: i64,
+    ) -> Result<Option<ObjMatch>>
     where
         P: AsRef<Path>,
     {
-        let mut writer = BulkWriter::new(loam)?;
-        let mut n_poly = 0;
-        for (_id, obj) in self.objs.iter() {
-            if let Some(rel) = obj.relation() {
-                // NOTE: check tags again because relations are nebulous
-                if self.layer.check_tags(&rel.tags) {
-                    if let Some(geom) = self.rel_polygon(rel) {
-                        writer.push(&geom)?;
-                        n_poly += 1;
-                    }
-                }
-            }
-            if let Some(way) = obj.way() {
-                if let Some(geom) = self.way_polygon(way) {
-                    writer.push(&geom)?;
-                    n_poly += 1;
-                }
+        let mut found = None;
+        for_each_obj(pbf.as_ref(), |obj| {
+            if obj_kind_id(&obj) == (kind, id) {
+                found = Some(self.obj_match(&obj));
+                return false;
             }
+            true
+        })?;
+        Ok(found)
+    }
+
+    /// Make a hash of the rules which determine extracted objects.
+    ///
+    /// Include-only tag changes do not affect the hash.
+    ///
+    /// * `exclude` Extract hash of the `exclude_if_matches` layer.
+    fn extract_hash(&self, exclude: Option<&str>) -> String {
+        let mut hash = Fnv1a::default();
+        hash.write_str(geom_type_name(self.geom_tp()));
+        hash.write_str(self.member_role().map_or("", MemberRole::as_str));
+        hash.write(&[u8::from(self.force_polygon())]);
+        for key in self.area_keys() {
+            hash.write_str(key);
         }
-        println!("  layer: {} ({n_poly} polygons)", self.layer.name());
-        if n_poly > 0 {
-            writer.finish()?;
-        } else {
-            writer.cancel()?;
+        for pat in self.match_patterns() {
+            hash.write_str(&pat);
         }
-        Ok(())
+        hash.write_str(exclude.unwrap_or_default());
+        // relative date patterns match different objects each day
+        hash.write_str(&self.dates_at().unwrap_or_default());
+        hash.finish()
+    }
+
+    /// Check if an OSM object matches a layer's tag patterns
+    fn check_obj(&self, obj: &OsmObj) -> bool {
+        self.check_obj_with(obj, |tags| self.check_tags(tags))
     }
 
-    /// Make all geometry for a layer
-    fn make_geometry<P>(&self, loam: P) -> Result<()>
+    /// Check if an OSM object matches, using a tag pattern check
+    fn check_obj_with<F>(&self, obj: &OsmObj, mut check: F) -> bool
     where
-        P: AsRef<Path>,
+        F: FnMut(&Tags) -> bool,
     {
-        match self.layer.geom_tp() {
-            GeomType::Point => self.make_points(loam),
-            GeomType::Linestring => self.make_linestrings(loam),
-            GeomType::Polygon => self.make_polygons(loam),
+        let tags = obj.tags();
+        match self.geom_tp() {
+            GeomType::Point => match self.member_role() {
+                // member role points are made from relations
+                Some(_) => obj.is_relation() && check(tags),
+                None => check(tags),
+            },
+            GeomType::Linestring => {
+                // ways tagged `area=yes` are not linestrings
+                !(obj.is_way() && is_area(tags, &[])) && check(tags)
+            }
+            GeomType::Polygon => match obj {
+                // polygons are relations or closed ways; old-style
+                // multipolygons are checked using outer way tags later
+                OsmObj::Relation(_) => {
+                    check(tags) || is_old_style_multipolygon(tags)
+                }
+                OsmObj::Way(way) => {
+                    way.is_closed() && self.check_area(tags) && check(tags)
+                }
+                OsmObj::Node(_) => false,
+            },
         }
     }
-}
 
-/// Connect ways on matching node Ids
-fn connect_ways(ways: &mut Vec<Vec<NodeId>>) -> bool {
-    let len = ways.len();
-    for i in 0..len - 1 {
-        let (a0, a1) = end_points(&ways[i]);
-        for j in i + 1..len {
-            let (b0, b1) = end_points(&ways[j]);
-            if a0 == b0 || a0 == b1 || a1 == b0 || a1 == b1 {
-                let mut way = ways.swap_remove(j);
-                // Do not reverse way `a` if both ends connect
-                if a1 != b0 && a1 != b1 {
-                    log::trace!("reversed {:?} <-> {:?}", a1.0, a0.0);
-                    ways[i].reverse();
-                }
-                let (_a0, a1) = end_points(&ways[i]);
-                if b1 == a1 {
-                    log::trace!("reversed {:?} <-> {:?}", b1.0, b0.0);
-                    way.reverse();
-                }
-                let (b0, _b1) = end_points(&way);
-                assert_eq!(a1, b0);
-                ways[i].pop();
-                ways[i].extend(way);
-                log::debug!("connected @ {:?}", a1.0);
-                return true;
+    /// Get the reason an object did not match the layer.
+    ///
+    /// Returns `None` unless the object has a value matching at least one
+    /// of the layer's patterns, to avoid counting unrelated objects.
+    fn skip_reason(&self, obj: &OsmObj) -> Option<SkipReason> {
+        let tags = obj.tags();
+        if !self.check_any_tag(tags) {
+            return None;
+        }
+        if !self.check_tags(tags) {
+            return Some(SkipReason::PatternMismatch);
+        }
+        match (self.geom_tp(), obj) {
+            (GeomType::Linestring, OsmObj::Way(_)) => Some(SkipReason::IsArea),
+            (GeomType::Polygon, OsmObj::Way(way)) if !way.is_closed() => {
+                Some(SkipReason::UnclosedWay)
             }
+            (GeomType::Polygon, OsmObj::Way(_)) => Some(SkipReason::NotArea),
+            _ => None,
         }
     }
-    false
 }
 
-/// Find a ring in a `Vec` of ways
-fn find_ring(ways: &mut Vec<Vec<NodeId>>) -> Option<Vec<NodeId>> {
-    let len = ways.len();
-    for i in 0..len {
-        let (w0, w1) = end_points(&ways[i]);
-        if w0 == w1 {
-            return Some(ways.swap_remove(i));
+impl<'a> GeometryMaker<'a> {
+    /// Create a new geometry layer maker
+    fn new(
+        layer: LayerDef,
+        objs: CompactMap,
+        deps: CompactDeps,
+        dem: Option<&'a Dem>,
+        cache: &'a RefCell<GeomCache>,
+        skips: &'a RefCell<SkipLog>,
+    ) -> Self {
+        Self {
+            layer,
+            objs,
+            deps,
+            dem,
+            cache,
+            skips,
+            n_dropped: Cell::new(0),
+            bytes_dropped: Cell::new(0),
+            n_salvaged: Cell::new(0),
+            n_skipped: Cell::new(0),
+            n_split: Cell::new(0),
+            n_span_split: Cell::new(0),
+            n_truncated: Cell::new(0),
+            n_unparsed: Cell::new(0),
+            n_unstored: Cell::new(0),
+            bytes_unstored: Cell::new(0),
+            degrees: DegreeMap::new(),
+            bounds: None,
+            source: String::new(),
         }
     }
-    None
-}
 
-/// Get the end point nodes of a way
-fn end_points(way: &[NodeId]) -> (NodeId, NodeId) {
-    assert!(way.len() > 1);
-    let len = way.len() - 1;
-    (way[0], way[len])
-}
+    /// Set source hash, for skipping unchanged layers before extracting
+    fn with_source(mut self, source: String) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Set node degrees for derived `intersections` points
+    fn with_degrees(mut self, degrees: DegreeMap) -> Self {
+        self.degrees = degrees;
+        self
+    }
+
+    /// Set extract bounds (`west south east north`, in degrees), for
+    /// closing partial polygons
+    fn with_bounds(mut self, bounds: Option<[f64; 4]>) -> Self {
+        self.bounds = bounds.map(|[west, south, east, north]| {
+            let lat = |lat: f64| lat.clamp(-MERCATOR_LAT_MAX, MERCATOR_LAT_MAX);
+            let (x0, y0) = web_mercator(west, lat(south));
+            let (x1, y1) = web_mercator(east, lat(north));
+            [x0, y0, x1, y1]
+        });
+        self
+    }
+
+    /// Record a skipped object
+    fn skip(&self, id: OsmId, reason: SkipReason) {
+        self.skips
+            .borrow_mut()
+            .record(self.layer.name(), id, reason);
+    }
+
+    /// Check if a feature should be dropped (`drop_empty` layers).
+    ///
+    /// * `id` Feature object ID.
+    /// * `values` Included tag values.
+    /// * `n_nodes` Number of nodes in feature geometry.
+    fn drop_empty(&self, id: OsmId, values: &Values, n_nodes: usize) -> bool {
+        if !self.layer.drop_empty() {
+            return false;
+        }
+        // `osm_id` is always present, so it does not count
+        let empty = self
+            .layer
+            .tags()
+            .zip(values)
+            .all(|(tag, val)| tag == "osm_id" || val.is_none());
+        if empty {
+            self.n_dropped.set(self.n_dropped.get() + 1);
+            let bytes = n_nodes * 2 * std::mem::size_of::<f64>();
+            self.bytes_dropped.set(self.bytes_dropped.get() + bytes);
+            self.skip(id, SkipReason::EmptyValues);
+        }
+        empty
+    }
+
+    /// Count nodes in relation member ways
+    fn rel_nodes(&self, rel: &CompactRelation) -> usize {
+        rel.refs
+            .iter()
+            .filter_map(|rf| self.way_node_ids(rf.member))
+            .map(<[NodeId]>::len)
+            .sum()
+    }
+
+    /// Check relation size limits, skipping oversize relations
+    fn check_rel_limits(&self, rel: &CompactRelation) -> bool {
+        let n_members = rel.refs.len();
+        let max_members = self.layer.max_relation_members();
+        if n_members > max_members {
+            log::warn!(
+                "relation {} skipped: {n_members} members (max {max_members})",
+                rel.id.0
+            );
+            self.n_skipped.set(self.n_skipped.get() + 1);
+            self.skip(OsmId::Relation(rel.id), SkipReason::OversizeRelation);
+            return false;
+        }
+        let n_nodes = self.rel_nodes(rel);
+        let max_nodes = self.layer.max_ring_nodes();
+        if n_nodes > max_nodes {
+            log::warn!(
+                "relation {} skipped: {n_nodes} ring nodes (max {max_nodes})",
+                rel.id.0
+            );
+            self.n_skipped.set(self.n_skipped.get() + 1);
+            self.skip(OsmId::Relation(rel.id), SkipReason::OversizeRelation);
+            return false;
+        }
+        true
+    }
+
+    /// Get assembled geometry from cache, or assemble and cache it
+    fn assemble<F>(&self, id: OsmId, f: F) -> Option<Rc<Vec<Ring>>>
+    where
+        F: FnOnce() -> Option<Vec<Ring>>,
+    {
+        if let Some(rings) = self.cache.borrow_mut().get(id) {
+            return Some(rings);
+        }
+        let rings = Rc::new(f()?);
+        self.cache.borrow_mut().insert(id, Rc::clone(&rings));
+        Some(rings)
+    }
+
+    /// Get assembled points of a way
+    fn way_points(&self, way: &CompactWay) -> Option<Rc<Vec<Ring>>> {
+        self.assemble(OsmId::Way(way.id), || {
+            Some(vec![(
+                true,
+                self.lookup_nodes(OsmId::Way(way.id), &way.nodes),
+            )])
+        })
+    }
+
+    /// Make point geometry from a `Node`
+    fn node_point(
+        &self,
+        node: &CompactNode,
+    ) -> Option<gis::Points<f64, Values>> {
+        let mut values = self.tag_values(node.id.0, &node.tags);
+        self.sample_elevation(node, &mut values);
+        if let (Some(degree), Some(i)) =
+            (self.degrees.get(&node.id), self.layer.sint_tag_index("degree"))
+        {
+            values[i] = Some(degree.to_string());
+        }
+        let id = OsmId::Node(node.id);
+        if self.drop_empty(id, &values, 1) {
+            return None;
+        }
+        let mut point = gis::Points::new(values);
+        for pt in self.lookup_nodes(id, &[node.id]) {
+            point.push(pt);
+        }
+        log::debug!("added point ({:?})", point.data());
+        Some(point)
+    }
+
+    /// Fill missing `$ele` value by sampling the DEM
+    fn sample_elevation(&self, node: &CompactNode, values: &mut Values) {
+        let (Some(dem), Some(i)) = (self.dem, self.layer.sint_tag_index("ele"))
+        else {
+            return;
+        };
+        if let Some(val @ None) = values.get_mut(i) {
+            if let Some(ele) = dem.elevation(node.lat(), node.lon()) {
+                log::trace!("node {} sampled ele: {ele:.1}", node.id.0);
+                *val = Some((ele.round() as i64).to_string());
+            }
+        }
+    }
+
+    /// Make linestring geometry from a `Way`.
+    ///
+    /// Ways longer than `max_geometry_nodes` or wider than `max_line_span`
+    /// are split into multiple linestrings, sharing end points.
+    fn way_linestring(
+        &self,
+        way: &CompactWay,
+    ) -> Vec<gis::Linestrings<f64, Values>> {
+        let values = self.tag_values(way.id.0, &way.tags);
+        if self.drop_empty(OsmId::Way(way.id), &values, way.nodes.len()) {
+            return Vec::new();
+        }
+        if way.nodes.is_empty() {
+            log::warn!("no nodes ({values:?})");
+            self.skip(OsmId::Way(way.id), SkipReason::MissingNodes);
+            return Vec::new();
+        }
+        let (w0, w1) = end_points(&way.nodes);
+        log::trace!("way {:?} .. {:?}", w0.0, w1.0);
+        let len = way.nodes.len();
+        let Some(rings) = self.way_points(way) else {
+            return Vec::new();
+        };
+        let mut linestrings = Vec::new();
+        for (_outer, pts) in rings.iter() {
+            let name = format!("way {}", way.id.0);
+            self.split_linestring(&name, pts, &values, &mut linestrings);
+        }
+        log::debug!("added way with {len} nodes ({values:?})");
+        linestrings
+    }
+
+    /// Split points into linestrings by `max_geometry_nodes` and
+    /// `max_line_span`, sharing end points
+    fn split_linestring(
+        &self,
+        name: &str,
+        pts: &[(f64, f64)],
+        values: &Values,
+        linestrings: &mut Vec<gis::Linestrings<f64, Values>>,
+    ) {
+        let max_nodes = self.layer.max_geometry_nodes();
+        let max_span = self.layer.max_line_span();
+        if pts.len() > max_nodes {
+            log::warn!("{name} split: {} nodes (max {max_nodes})", pts.len());
+            self.n_split.set(self.n_split.get() + 1);
+        }
+        let mut span_split = false;
+        // overlap chunks by one point to keep them connected
+        let mut start = 0;
+        loop {
+            let end = (start + max_nodes).min(pts.len());
+            let chunks = split_span(&pts[start..end], max_span);
+            span_split |= chunks.len() > 1;
+            for chunk in chunks {
+                let mut linestring = gis::Linestrings::new(values.clone());
+                linestring.push(chunk.to_vec());
+                linestrings.push(linestring);
+            }
+            if end == pts.len() {
+                break;
+            }
+            start = end - 1;
+        }
+        if span_split {
+            log::debug!("{name} split by span ({max_span:.0} m)");
+            self.n_span_split.set(self.n_span_split.get() + 1);
+        }
+    }
+
+    /// Make linestring geometry from a `route` relation.
+    ///
+    /// Member ways are joined into chains in member order, with the
+    /// relation's tag values; one geometry is made for each route, even if
+    /// ways are shared with other routes.
+    fn rel_linestring(
+        &self,
+        rel: &CompactRelation,
+    ) -> Vec<gis::Linestrings<f64, Values>> {
+        let values = self.tag_values(rel.id.0, &rel.tags);
+        let id = OsmId::Relation(rel.id);
+        if self.drop_empty(id, &values, self.rel_nodes(rel))
+            || !self.check_rel_limits(rel)
+        {
+            return Vec::new();
+        }
+        let mut members = Vec::new();
+        for rf in &rel.refs {
+            // stops and platforms are not part of the route path
+            if rf.role.starts_with("stop") || rf.role.starts_with("platform") {
+                continue;
+            }
+            let mut nodes = self.way_nodes(rf.member);
+            if nodes.is_empty() {
+                continue;
+            }
+            let directed = match &*rf.role {
+                "forward" => true,
+                "backward" => {
+                    nodes.reverse();
+                    true
+                }
+                _ => false,
+            };
+            members.push((directed, nodes));
+        }
+        let mut linestrings = Vec::new();
+        let name = format!("relation {}", rel.id.0);
+        for chain in order_route(members) {
+            let pts = self.lookup_nodes(id, &chain);
+            if pts.len() > 1 {
+                self.split_linestring(&name, &pts, &values, &mut linestrings);
+            }
+        }
+        log::debug!(
+            "added route with {} linestrings ({values:?})",
+            linestrings.len()
+        );
+        linestrings
+    }
+
+    /// Make polygon geometry from a `multipolygon` or `boundary` relation.
+    ///
+    /// Old-style multipolygons, with only a `type` tag, use the tags of
+    /// their outer ways, if all tagged outer ways agree.
+    fn rel_multipolygon(
+        &self,
+        rel: &CompactRelation,
+    ) -> Option<gis::Polygons<f64, Values>> {
+        match rel.tags.tag("type") {
+            Some("multipolygon") => {
+                if is_old_style(rel) {
+                    let tags = self.outer_tags(rel)?;
+                    if self.layer.check_tags(&tags) {
+                        log::debug!("old-style multipolygon {}", rel.id.0);
+                        return self.rel_polygon(rel, &tags);
+                    }
+                    return None;
+                }
+                // NOTE: check tags again because relations are nebulous
+                if self.layer.check_tags(&rel.tags) {
+                    return self.rel_polygon(rel, &rel.tags);
+                }
+                None
+            }
+            Some("boundary") if self.layer.check_tags(&rel.tags) => {
+                self.rel_polygon(rel, &rel.tags)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get tags shared by all tagged outer member ways of a relation
+    fn outer_tags(&self, rel: &CompactRelation) -> Option<CompactTags> {
+        let mut shared: Option<&CompactTags> = None;
+        for rf in rel.refs.iter().filter(|rf| &*rf.role == "outer") {
+            let Some(way) = self.objs.get(&rf.member).and_then(|o| o.way())
+            else {
+                continue;
+            };
+            if way.tags.is_empty() {
+                continue;
+            }
+            match shared {
+                Some(tags) if *tags != way.tags => {
+                    log::debug!("relation {} outer tags disagree", rel.id.0);
+                    return None;
+                }
+                Some(_) => (),
+                None => shared = Some(&way.tags),
+            }
+        }
+        shared.cloned()
+    }
+
+    /// Make polygon geometry from a `Relation`
+    fn rel_polygon(
+        &self,
+        rel: &CompactRelation,
+        tags: &CompactTags,
+    ) -> Option<gis::Polygons<f64, Values>> {
+        let mut values = self.tag_values(rel.id.0, tags);
+        let id = OsmId::Relation(rel.id);
+        if self.drop_empty(id, &values, self.rel_nodes(rel)) {
+            return None;
+        }
+        if !self.check_rel_limits(rel) {
+            return None;
+        }
+        let rings = match self.rel_rings(rel) {
+            Some(rings) => rings,
+            None if self.layer.allow_partial_polygons() => {
+                let rings = self.assemble_rings(rel, true)?;
+                if let Some(i) = self.layer.tag_index("partial") {
+                    values[i] = Some("yes".to_string());
+                }
+                self.n_salvaged.set(self.n_salvaged.get() + 1);
+                log::debug!("salvaged partial polygon ({values:?})");
+                Rc::new(rings)
+            }
+            None => {
+                log::debug!("broken polygon ({values:?})");
+                self.skip(id, SkipReason::BrokenPolygon);
+                return None;
+            }
+        };
+        let mut polygon = gis::Polygons::new(values);
+        for (outer, pts) in rings.iter() {
+            if *outer {
+                polygon.push_outer(pts.clone());
+            } else {
+                polygon.push_inner(pts.clone());
+            }
+        }
+        Some(polygon)
+    }
+
+    /// Get assembled polygon rings of a relation
+    fn rel_rings(&self, rel: &CompactRelation) -> Option<Rc<Vec<Ring>>> {
+        self.assemble(OsmId::Relation(rel.id), || {
+            self.assemble_rings(rel, false)
+        })
+    }
+
+    /// Assemble polygon rings from `outer` / `inner` relation members.
+    ///
+    /// * `salvage` Close unconnected chains along the extract bounds (or
+    ///             with a straight segment), instead of failing.
+    fn assemble_rings(
+        &self,
+        rel: &CompactRelation,
+        salvage: bool,
+    ) -> Option<Vec<Ring>> {
+        let mut members = Vec::new();
+        for rf in &rel.refs {
+            let outer = match &*rf.role {
+                "outer" => true,
+                "inner" => false,
+                _ => continue,
+            };
+            let nodes = self.way_nodes(rf.member);
+            if nodes.is_empty() {
+                // relations on edges of dump area
+                // can have empty member ways
+                continue;
+            }
+            let (w0, w1) = end_points(&nodes);
+            log::trace!("{:?} way {:?} .. {:?}", rf.role, w0.0, w1.0);
+            members.push((outer, nodes));
+        }
+        let (rings, chains) = stitch_rings(members);
+        let mut rings: Vec<Ring> = rings
+            .into_iter()
+            .map(|(outer, ring)| {
+                log::debug!(
+                    "added {} way with {} nodes (relation {})",
+                    if outer { "outer" } else { "inner" },
+                    ring.len(),
+                    rel.id.0,
+                );
+                (outer, self.lookup_nodes(OsmId::Relation(rel.id), &ring))
+            })
+            .collect();
+        if !salvage {
+            return chains.is_empty().then_some(rings);
+        }
+        // incomplete relations, usually at edges of extract
+        for (outer, chain) in chains.into_iter().filter(|(_, w)| w.len() > 2) {
+            let pts = self.lookup_nodes(OsmId::Relation(rel.id), &chain);
+            if pts.is_empty() {
+                continue;
+            }
+            let ring = close_chain(pts, self.bounds);
+            log::debug!(
+                "closed {} chain with {} nodes (relation {})",
+                if outer { "outer" } else { "inner" },
+                ring.len(),
+                rel.id.0
+            );
+            rings.push((outer, ring));
+        }
+        Some(rings)
+    }
+
+    /// Make point geometry from a `Relation` member node
+    fn rel_point(
+        &self,
+        rel: &CompactRelation,
+        role: MemberRole,
+    ) -> Option<gis::Points<f64, Values>> {
+        let values = self.tag_values(rel.id.0, &rel.tags);
+        if self.drop_empty(OsmId::Relation(rel.id), &values, 1) {
+            return None;
+        }
+        if !self.check_rel_limits(rel) {
+            return None;
+        }
+        let mut point = gis::Points::new(values);
+        let pt = role
+            .fallbacks()
+            .iter()
+            .find_map(|role| self.member_node(rel, role.as_str()))
+            .or_else(|| rings_centroid(&self.rel_rings(rel)?))?;
+        point.push(pt);
+        log::debug!("added relation point ({:?})", point.data());
+        Some(point)
+    }
+
+    /// Get position of a relation member node with a given role
+    fn member_node(
+        &self,
+        rel: &CompactRelation,
+        role: &str,
+    ) -> Option<(f64, f64)> {
+        rel.refs
+            .iter()
+            .filter(|rf| &*rf.role == role)
+            .find_map(|rf| match rf.member {
+                OsmId::Node(nid) => {
+                    self.lookup_nodes(OsmId::Relation(rel.id), &[nid]).pop()
+                }
+                _ => None,
+            })
+    }
+
+    /// Make polygon geometry from a `Way`
+    fn way_polygon(
+        &self,
+        way: &CompactWay,
+    ) -> Option<gis::Polygons<f64, Values>> {
+        let id = OsmId::Way(way.id);
+        // dependency ways (relation members) are not logged as skipped
+        let skip = |reason| {
+            if self.layer.check_tags(&way.tags) {
+                self.skip(id, reason);
+            }
+        };
+        if way.nodes.is_empty() {
+            skip(SkipReason::MissingNodes);
+            return None;
+        }
+        let (w0, w1) = end_points(&way.nodes);
+        if way.is_open() || w0 != w1 {
+            log::trace!("way {} not closed {} .. {}", way.id.0, w0.0, w1.0);
+            skip(SkipReason::UnclosedWay);
+            return None;
+        }
+        if !self.layer.check_area(&way.tags) {
+            log::trace!("way {} not an area", way.id.0);
+            skip(SkipReason::NotArea);
+            return None;
+        }
+        let values = self.tag_values(way.id.0, &way.tags);
+        if self.drop_empty(id, &values, way.nodes.len()) {
+            return None;
+        }
+        let len = way.nodes.len();
+        let mut polygon = gis::Polygons::new(values);
+        for (_outer, pts) in self.way_points(way)?.iter() {
+            polygon.push_outer(pts.clone());
+        }
+        log::debug!("added way with {len} nodes ({:?})", polygon.data());
+        Some(polygon)
+    }
+
+    /// Get node IDs of a way, from objects or dependencies
+    fn way_node_ids(&self, id: OsmId) -> Option<&[NodeId]> {
+        match (self.objs.get(&id), id) {
+            (Some(obj), _) => obj.way().map(|way| way.nodes.as_slice()),
+            (None, OsmId::Way(way)) => self.deps.way_nodes(way),
+            _ => None,
+        }
+    }
+
+    /// Get the member way nodes for a relation
+    fn way_nodes(&self, id: OsmId) -> Vec<NodeId> {
+        match self.way_node_ids(id) {
+            Some(nodes) if nodes.len() > 1 => nodes.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the position of a node (degrees latitude / longitude)
+    fn node_pos(&self, id: NodeId) -> Option<(f64, f64)> {
+        self.deps.node(id).or_else(|| {
+            let node = self.objs.get(&OsmId::Node(id))?.node()?;
+            Some((node.lat(), node.lon()))
+        })
+    }
+
+    /// Lookup points for a slice of nodes.
+    ///
+    /// * `owner` Object referencing the nodes, for skip log.
+    /// * `nodes` Node IDs.
+    fn lookup_nodes(&self, owner: OsmId, nodes: &[NodeId]) -> Vec<(f64, f64)> {
+        let mut pts = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            if let Some((lat, lon)) = self.node_pos(*node) {
+                let pos = Wgs84Pos::new(lat, lon);
+                let pos = WebMercatorPos::from(pos);
+                pts.push((pos.x, pos.y));
+            } else {
+                log::error!("node not found: {:?}", node);
+                self.skip(owner, SkipReason::MissingNodes);
+                return Vec::new();
+            }
+        }
+        pts
+    }
+
+    /// Get values for included tags.
+    ///
+    /// Values of `sint` tags are normalized, so they parse when serving.
+    fn tag_values(&self, id: i64, tags: &CompactTags) -> Values {
+        self.layer
+            .tag_rules()
+            .map(|(tag, default, sint, stored)| {
+                if !stored {
+                    if let Some(v) = tags.tag(tag) {
+                        self.n_unstored.set(self.n_unstored.get() + 1);
+                        self.bytes_unstored
+                            .set(self.bytes_unstored.get() + v.len());
+                    }
+                    return None;
+                }
+                let val =
+                    (tag == "osm_id").then(|| id.to_string()).or_else(|| {
+                        tags.tag(tag).map(|v| {
+                            let (v, truncated) = self.layer.sanitize_value(v);
+                            if truncated {
+                                self.n_truncated
+                                    .set(self.n_truncated.get() + 1);
+                            }
+                            v.into_owned()
+                        })
+                    });
+                let val = match (sint, val) {
+                    (Some(unit), Some(v)) => {
+                        let norm = normalize_sint(&v, unit);
+                        if norm.is_none() {
+                            log::debug!("{tag}: unparseable sint {v}");
+                            self.n_unparsed.set(self.n_unparsed.get() + 1);
+                        }
+                        norm
+                    }
+                    (_, val) => val,
+                };
+                match default {
+                    Some(def) if !is_sint(val.as_deref()) => {
+                        Some(def.to_string())
+                    }
+                    _ => val,
+                }
+            })
+            .collect()
+    }
+
+    /// Make all points for a layer.
+    ///
+    /// Layers with `rank_by` are written to one loam file per zoom bucket.
+    fn make_points<P>(&self, loam: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let names = self.layer.loam_names();
+        let mut writers = Vec::with_capacity(names.len());
+        for name in &names {
+            let path = loam.as_ref().with_file_name(format!("{name}.loam"));
+            writers.push(LoamWriter::new(path, self.layer.precision())?);
+        }
+        let mut counts = vec![0usize; writers.len()];
+        let mut push = |geom: gis::Points<f64, Values>| -> Result<()> {
+            let i = self.layer.rank_bucket(geom.data());
+            writers[i].push(&geom)?;
+            counts[i] += 1;
+            Ok(())
+        };
+        if let Some(role) = self.layer.member_role() {
+            for rel in self.objs.iter().filter_map(|(_, obj)| obj.relation()) {
+                // NOTE: check tags again to skip dependency relations
+                if self.layer.check_tags(&rel.tags) {
+                    if let Some(geom) = self.rel_point(rel, role) {
+                        push(geom)?;
+                    }
+                }
+            }
+        } else {
+            for node in self.objs.iter().filter_map(|(_, obj)| obj.node()) {
+                if let Some(geom) = self.node_point(node) {
+                    push(geom)?;
+                }
+            }
+        }
+        let n_point = counts.iter().sum();
+        println!("  layer: {} ({n_point} points)", self.layer.name());
+        for ((name, writer), n) in names.iter().zip(writers).zip(counts) {
+            if names.len() > 1 {
+                println!("    bucket: {name} ({n} points)");
+            }
+            if n > 0 {
+                writer.finish()?;
+            } else {
+                writer.cancel()?;
+            }
+        }
+        Ok(n_point)
+    }
+
+    /// Make all linestrings for a layer
+    fn make_linestrings<P>(&self, loam: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = LoamWriter::new(loam, self.layer.precision())?;
+        let (mut n_line, mut n_route) = (0, 0);
+        // untagged member ways of routes are not separate linestrings
+        let mut members = HashSet::new();
+        for rel in self.objs.iter().filter_map(|(_, obj)| obj.relation()) {
+            // NOTE: check tags again to skip dependency relations
+            if rel.tags.tag("type") != Some("route")
+                || !self.layer.check_tags(&rel.tags)
+            {
+                continue;
+            }
+            for geom in self.rel_linestring(rel) {
+                writer.push(&geom)?;
+                n_line += 1;
+            }
+            members.extend(member_ways(rel));
+            n_route += 1;
+        }
+        for way in self.objs.iter().filter_map(|(_, obj)| obj.way()) {
+            if is_area(&way.tags, &[]) {
+                log::trace!("way {} is an area", way.id.0);
+                continue;
+            }
+            if members.contains(&way.id) && !self.layer.check_tags(&way.tags) {
+                log::trace!("way {} is route member", way.id.0);
+                continue;
+            }
+            for geom in self.way_linestring(way) {
+                writer.push(&geom)?;
+                n_line += 1;
+            }
+        }
+        if n_route > 0 {
+            println!(
+                "  layer: {} ({n_line} linestrings, {n_route} routes)",
+                self.layer.name()
+            );
+        } else {
+            println!("  layer: {} ({n_line} linestrings)", self.layer.name());
+        }
+        if n_line > 0 {
+            writer.finish()?;
+        } else {
+            writer.cancel()?;
+        }
+        Ok(n_line)
+    }
+
+    /// Make all polygons for a layer
+    fn make_polygons<P>(&self, loam: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = LoamWriter::new(loam, self.layer.precision())?;
+        let mut n_poly = 0;
+        let n_suppressed = self.for_each_polygon(|geom| {
+            n_poly += 1;
+            writer.push(geom)
+        })?;
+        if n_suppressed > 0 {
+            println!(
+                "  layer: {} ({n_poly} polygons, {n_suppressed} member ways \
+                suppressed)",
+                self.layer.name()
+            );
+        } else {
+            println!("  layer: {} ({n_poly} polygons)", self.layer.name());
+        }
+        if n_poly > 0 {
+            writer.finish()?;
+        } else {
+            writer.cancel()?;
+        }
+        Ok(n_poly)
+    }
+
+    /// Make polygons from relations and closed ways.
+    ///
+    /// Returns the number of member ways suppressed (see `dedupe_members`).
+    fn for_each_polygon<F>(&self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&gis::Polygons<f64, Values>) -> Result<()>,
+    {
+        let mut n_suppressed = 0;
+        // member ways of relation polygons
+        let mut members = HashSet::new();
+        // outer ways of old-style multipolygons, which have the same tags
+        let mut outers = HashSet::new();
+        // relation polygon values of member ways (when deduplicating)
+        let mut dupes: HashMap<WayId, Vec<Values>> = HashMap::new();
+        for rel in self.objs.values().filter_map(|obj| obj.relation()) {
+            if let Some(geom) = self.rel_multipolygon(rel) {
+                f(&geom)?;
+                members.extend(member_ways(rel));
+                if is_old_style(rel) {
+                    outers.extend(outer_ways(rel));
+                } else if self.layer.dedupe_members() {
+                    for id in member_ways(rel) {
+                        dupes.entry(id).or_default().push(geom.data().clone());
+                    }
+                }
+            }
+        }
+        for way in self.objs.values().filter_map(|obj| obj.way()) {
+            let matches = self.layer.check_tags(&way.tags);
+            if members.contains(&way.id) && !matches {
+                log::trace!("way {} is relation member", way.id.0);
+                continue;
+            }
+            if outers.contains(&way.id) {
+                log::debug!("way {} is old-style outer way", way.id.0);
+                n_suppressed += usize::from(matches);
+                continue;
+            }
+            if let Some(geom) = self.way_polygon(way) {
+                let dupe = dupes.get(&way.id).is_some_and(|values| {
+                    values.iter().any(|v| self.same_values(v, geom.data()))
+                });
+                if dupe {
+                    log::debug!("way {} is relation member", way.id.0);
+                    n_suppressed += 1;
+                    continue;
+                }
+                f(&geom)?;
+            }
+        }
+        Ok(n_suppressed)
+    }
+
+    /// Check if polygon values are the same, except for `osm_id`/`partial`
+    fn same_values(&self, a: &Values, b: &Values) -> bool {
+        let osm_id = self.layer.tag_index("osm_id");
+        let partial = self.layer.tag_index("partial");
+        a.len() == b.len()
+            && a.iter().zip(b).enumerate().all(|(i, (va, vb))| {
+                va == vb || Some(i) == osm_id || Some(i) == partial
+            })
+    }
+
+    /// Make content hash of layer definition and matched objects.
+    ///
+    /// Objects are hashed in ID order, with tags sorted by key, so the
+    /// hash is reproducible for identical input.
+    fn content_hash(&self) -> String {
+        let mut hash = Fnv1a::default();
+        hash.write_str(&format!("{:?}", self.layer));
+        for (id, obj) in &self.objs {
+            hash.write_str(&format!("{id:?}"));
+            // compact tags are sorted by key
+            for (key, value) in obj.tags().iter() {
+                hash.write_str(key);
+                hash.write_str(value);
+            }
+            match obj {
+                CompactObj::Node(node) => {
+                    hash.write(&node.decimicro_lat.to_le_bytes());
+                    hash.write(&node.decimicro_lon.to_le_bytes());
+                }
+                CompactObj::Way(way) => {
+                    for nid in &way.nodes {
+                        hash.write(&nid.0.to_le_bytes());
+                    }
+                }
+                CompactObj::Relation(rel) => {
+                    for rf in &rel.refs {
+                        hash.write_str(&format!("{:?}", rf.member));
+                        hash.write_str(&rf.role);
+                    }
+                }
+            }
+        }
+        // dependencies have no tags
+        for (nid, lat, lon) in self.deps.nodes() {
+            hash.write_str(&format!("{:?}", OsmId::Node(*nid)));
+            hash.write(&lat.to_le_bytes());
+            hash.write(&lon.to_le_bytes());
+        }
+        for (wid, nodes) in self.deps.ways() {
+            hash.write_str(&format!("{:?}", OsmId::Way(*wid)));
+            for nid in nodes {
+                hash.write(&nid.0.to_le_bytes());
+            }
+        }
+        for (nid, degree) in &self.degrees {
+            hash.write(&nid.0.to_le_bytes());
+            hash.write(&degree.to_le_bytes());
+        }
+        if self.layer.sint_tag_index("ele").is_some() {
+            hash.write_str(self.dem.map_or("", Dem::identity));
+        }
+        hash.finish()
+    }
+
+    /// Make loam file (and metadata) for a layer in a directory.
+    ///
+    /// Returns the number of features in the layer.
+    ///
+    /// * `loam_dir` Current loam directory.
+    /// * `force` Rebuild layer even if unchanged.
+    fn make_loam(
+        &self,
+        dir: &Path,
+        loam_dir: &Path,
+        force: bool,
+    ) -> Result<usize> {
+        let file = format!("{}.loam", self.layer.name());
+        let mut meta = LoamMeta::from(&self.layer)
+            .with_hash(self.content_hash())
+            .with_source(self.source.clone());
+        let current = loam_dir.join(&file);
+        // zoom bucketed layers have metadata, but no loam file by name
+        let bucketed = !self.layer.rank_buckets().is_empty();
+        if !force && (bucketed || current.exists()) {
+            if let Some(old) = LoamMeta::read(&current)? {
+                if old.same_content(&meta) {
+                    println!(
+                        "  layer: {} (unchanged, skipped)",
+                        self.layer.name()
+                    );
+                    return Ok(old.features);
+                }
+            }
+        }
+        let loam = dir.join(file);
+        meta.features = self.make_geometry(&loam)?;
+        if meta.features > 0 {
+            meta.write(&loam)?;
+            if self.layer.id_index() {
+                self.make_id_index(dir)?;
+            }
+        }
+        Ok(meta.features)
+    }
+
+    /// Make ID index files for all loam files of a layer
+    fn make_id_index(&self, dir: &Path) -> Result<()> {
+        for name in self.layer.loam_names() {
+            let loam = dir.join(format!("{name}.loam"));
+            // zoom buckets with no features have no file
+            if !loam.exists() {
+                continue;
+            }
+            let tree = GeomTree::new(
+                self.layer.geom_tp(),
+                self.layer.precision(),
+                &loam,
+            )?;
+            let ids = tree.feature_ids(&self.layer, bbox_all())?;
+            println!("    indexed {} IDs", ids.len());
+            write_index(&loam, ids)?;
+        }
+        Ok(())
+    }
+
+    /// Make all geometry for a layer, returning number of features
+    fn make_geometry<P>(&self, loam: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let n_features = match self.layer.geom_tp() {
+            GeomType::Point => self.make_points(loam)?,
+            GeomType::Linestring => self.make_linestrings(loam)?,
+            GeomType::Polygon => self.make_polygons(loam)?,
+        };
+        let n_dropped = self.n_dropped.get();
+        if n_dropped > 0 {
+            println!(
+                "    dropped {n_dropped} empty features (~{} bytes saved)",
+                self.bytes_dropped.get()
+            );
+        }
+        let n_salvaged = self.n_salvaged.get();
+        if n_salvaged > 0 {
+            println!("    salvaged {n_salvaged} partial polygons");
+        }
+        let n_skipped = self.n_skipped.get();
+        if n_skipped > 0 {
+            println!("    skipped {n_skipped} oversize relations");
+        }
+        let n_split = self.n_split.get();
+        if n_split > 0 {
+            println!("    split {n_split} oversize linestrings");
+        }
+        let n_span_split = self.n_span_split.get();
+        if n_span_split > 0 {
+            println!("    split {n_span_split} wide linestrings");
+        }
+        let n_truncated = self.n_truncated.get();
+        if n_truncated > 0 {
+            println!("    truncated {n_truncated} long tag values");
+        }
+        let n_unparsed = self.n_unparsed.get();
+        if n_unparsed > 0 {
+            println!("    dropped {n_unparsed} unparseable sint values");
+        }
+        if let Some(rss) = peak_rss() {
+            println!("    peak RSS: {} MiB", rss >> 20);
+        }
+        let n_unstored = self.n_unstored.get();
+        if n_unstored > 0 {
+            println!(
+                "    skipped {n_unstored} zoom-qualified values (~{} bytes saved)",
+                self.bytes_unstored.get()
+            );
+        }
+        Ok(n_features)
+    }
+}
+
+impl ShapeGenerator {
+    /// Create a shape generator.
+    ///
+    /// * `osm_bounds` Union of OSM file header bounds, if any.
+    fn new(layer: LayerDef, osm_bounds: Option<[f64; 4]>) -> Result<Self> {
+        let Some(Generate::Extent { bounds }) = layer.generate() else {
+            return Err(Error::InvalidGenerate(format!(
+                "layer '{}': not generated",
+                layer.name()
+            )));
+        };
+        let bounds = bounds.or(osm_bounds).ok_or_else(|| {
+            Error::InvalidGenerate(format!(
+                "layer '{}': no extent, and OSM file has no bounds",
+                layer.name()
+            ))
+        })?;
+        Ok(ShapeGenerator { layer, bounds })
+    }
+
+    /// Make the extent polygon, in Web Mercator coordinates
+    fn extent_polygon(&self) -> gis::Polygons<f64, Values> {
+        let [west, south, east, north] = self.bounds;
+        let corner = |lat, lon| {
+            let pos = WebMercatorPos::from(Wgs84Pos::new(lat, lon));
+            (pos.x, pos.y)
+        };
+        let mut polygon = gis::Polygons::new(self.layer.generated_values());
+        // counter-clockwise exterior ring
+        polygon.push_outer(vec![
+            corner(south, west),
+            corner(south, east),
+            corner(north, east),
+            corner(north, west),
+            corner(south, west),
+        ]);
+        polygon
+    }
+
+    /// Hash the layer definition and bounds
+    fn content_hash(&self) -> String {
+        let mut hash = Fnv1a::default();
+        hash.write_str(&format!("{:?}", self.layer));
+        for b in self.bounds {
+            hash.write(&b.to_le_bytes());
+        }
+        hash.finish()
+    }
+
+    /// Make loam file (and metadata) for a layer in a directory.
+    ///
+    /// * `loam_dir` Current loam directory.
+    /// * `force` Rebuild layer even if unchanged.
+    fn make_loam(
+        &self,
+        dir: &Path,
+        loam_dir: &Path,
+        force: bool,
+    ) -> Result<usize> {
+        let file = format!("{}.loam", self.layer.name());
+        let mut meta =
+            LoamMeta::from(&self.layer).with_hash(self.content_hash());
+        let current = loam_dir.join(&file);
+        if !force && current.exists() {
+            if let Some(old) = LoamMeta::read(&current)? {
+                if old.same_content(&meta) {
+                    println!(
+                        "  layer: {} (unchanged, skipped)",
+                        self.layer.name()
+                    );
+                    return Ok(old.features);
+                }
+            }
+        }
+        let loam = dir.join(file);
+        let mut writer = LoamWriter::new(&loam, self.layer.precision())?;
+        writer.push(&self.extent_polygon())?;
+        let [west, south, east, north] = self.bounds;
+        println!(
+            "  layer: {} (extent {west} {south} {east} {north})",
+            self.layer.name()
+        );
+        writer.finish()?;
+        meta.features = 1;
+        meta.write(&loam)?;
+        Ok(meta.features)
+    }
+}
+
+/// Loam file writer, with f64 or f32 coordinates
+enum LoamWriter<G: Narrow> {
+    /// f64 coordinates
+    F64(BulkWriter<f64, G>),
+
+    /// f32 coordinates
+    F32 {
+        /// Bulk writer
+        writer: BulkWriter<f32, G::F32>,
+
+        /// Loam file path
+        path: PathBuf,
+
+        /// Number of points written
+        n_points: usize,
+    },
+}
+
+impl<G> LoamWriter<G>
+where
+    G: Narrow + Gis<f64>,
+    G::F32: Gis<f32>,
+{
+    /// Create a new loam writer
+    fn new<P>(path: P, precision: Precision) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        Ok(match precision {
+            Precision::F64 => LoamWriter::F64(BulkWriter::new(path)?),
+            Precision::F32 => LoamWriter::F32 {
+                writer: BulkWriter::new(path)?,
+                path: path.to_path_buf(),
+                n_points: 0,
+            },
+        })
+    }
+
+    /// Push geometry
+    fn push(&mut self, geom: &G) -> Result<()> {
+        match self {
+            LoamWriter::F64(writer) => writer.push(geom)?,
+            LoamWriter::F32 {
+                writer, n_points, ..
+            } => {
+                let (geom, n) = geom.narrow();
+                writer.push(&geom)?;
+                *n_points += n;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish writing the loam file
+    fn finish(self) -> Result<()> {
+        match self {
+            LoamWriter::F64(writer) => writer.finish()?,
+            LoamWriter::F32 {
+                writer,
+                path,
+                n_points,
+            } => {
+                writer.finish()?;
+                // each point saves 4 bytes for both X and Y
+                let size = std::fs::metadata(&path)?.len();
+                let saved = 8 * n_points as u64;
+                println!(
+                    "    f32 precision: {size} bytes (~{saved} bytes saved)"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Cancel writing the loam file
+    fn cancel(self) -> Result<()> {
+        match self {
+            LoamWriter::F64(writer) => writer.cancel()?,
+            LoamWriter::F32 { writer, .. } => writer.cancel()?,
+        }
+        Ok(())
+    }
+}
+
+/// Get the union of OSM file header bounds
+fn osm_bounds(extractors: &[OsmExtractor]) -> Option<[f64; 4]> {
+    extractors
+        .iter()
+        .filter_map(|e| parse_extent(&e.header.bbox).ok())
+        .reduce(|a, b| {
+            [
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ]
+        })
+}
+
+/// Detect the format of an OSM file, by extension or magic bytes
+fn osm_format(osm: &Path) -> Result<OsmFormat> {
+    let name = osm.to_string_lossy().to_lowercase();
+    if name.ends_with(".pbf") {
+        return Ok(OsmFormat::Pbf);
+    }
+    if name.ends_with(".osm") || name.ends_with(".osm.bz2") {
+        return Ok(OsmFormat::Xml);
+    }
+    let mut file = File::open(osm)?;
+    let mut buf = [0; 4 + PBF_MAGIC.len()];
+    let n = file.read(&mut buf)?;
+    if XML_MAGIC.iter().any(|m| buf[..n].starts_with(m)) {
+        Ok(OsmFormat::Xml)
+    } else {
+        Ok(OsmFormat::Pbf)
+    }
+}
+
+/// Check that a file looks like an OSM file, returning its length
+fn check_osm(osm: &Path) -> Result<u64> {
+    let mut file = File::open(osm)?;
+    let len = file.metadata()?.len();
+    if osm_format(osm)? == OsmFormat::Xml {
+        if cfg!(feature = "xml") {
+            return Ok(len);
+        }
+        return Err(Error::OsmXml(format!(
+            "{osm:?}: reading XML requires the `xml` feature"
+        )));
+    }
+    let mut buf = [0; 4 + PBF_MAGIC.len()];
+    if file.read_exact(&mut buf).is_err() || &buf[4..] != PBF_MAGIC {
+        return Err(Error::InvalidPbf(format!("{osm:?}: not an OSM PBF file")));
+    }
+    // header blob must fit in the file, with room for data blobs
+    let header_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    if len <= 4 + u64::from(header_len) {
+        return Err(Error::InvalidPbf(format!("{osm:?}: truncated")));
+    }
+    Ok(len)
+}
+
+/// Get OSM file identity (name, length and modified time)
+fn pbf_identity(osm: &Path) -> Result<String> {
+    let meta = std::fs::metadata(osm)?;
+    let secs = meta
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let name = osm
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(format!("{name}:{}:{secs}", meta.len()))
+}
+
+/// Get total size of loam files in a directory
+fn loam_size(dir: &Path) -> u64 {
+    let Ok(entries) = dir.read_dir() else {
+        return 0;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "loam"))
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Split a linestring at existing vertices into chunks with bounded span.
+///
+/// Chunks share end points, so they abut.  A single segment wider than
+/// `max_span` is not split.
+fn split_span(pts: &[(f64, f64)], max_span: f64) -> Vec<&[(f64, f64)]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let (mut x_min, mut x_max) = (f64::MAX, f64::MIN);
+    let (mut y_min, mut y_max) = (f64::MAX, f64::MIN);
+    for (i, (x, y)) in pts.iter().enumerate() {
+        x_min = x_min.min(*x);
+        x_max = x_max.max(*x);
+        y_min = y_min.min(*y);
+        y_max = y_max.max(*y);
+        let span = (x_max - x_min).max(y_max - y_min);
+        if span > max_span && i - start > 1 {
+            chunks.push(&pts[start..i]);
+            start = i - 1;
+            let (x0, y0) = pts[start];
+            (x_min, x_max) = (x0.min(*x), x0.max(*x));
+            (y_min, y_max) = (y0.min(*y), y0.max(*y));
+        }
+    }
+    chunks.push(&pts[start..]);
+    chunks
+}
+
+/// Check if relation tags are an old-style multipolygon.
+///
+/// These have only `type=multipolygon`, with feature tags on outer ways.
+fn is_old_style_multipolygon(tags: &osmpbfreader::Tags) -> bool {
+    tags.tag("type") == Some("multipolygon")
+        && tags.iter().all(|(k, _v)| META_KEYS.contains(&k.as_str()))
+}
+
+/// Check if a compact relation is an old-style multipolygon.
+///
+/// Compact relation tags only keep `type` when no other tag is referenced.
+fn is_old_style(rel: &CompactRelation) -> bool {
+    rel.tags.iter().all(|(k, _v)| k == "type")
+}
+
+/// Get IDs of outer member ways of a relation
+fn outer_ways(rel: &CompactRelation) -> impl Iterator<Item = WayId> + '_ {
+    rel.refs.iter().filter_map(|rf| match rf.member {
+        OsmId::Way(id) if &*rf.role == "outer" => Some(id),
+        _ => None,
+    })
+}
+
+/// Get IDs of member ways of a relation
+fn member_ways(rel: &CompactRelation) -> impl Iterator<Item = WayId> + '_ {
+    rel.refs.iter().filter_map(|rf| match rf.member {
+        OsmId::Way(id) => Some(id),
+        _ => None,
+    })
+}
+
+/// Get IDs of objects matching a layer (excluding dependencies)
+fn matching_ids(layer: &LayerDef, objs: &ObjMap) -> HashSet<OsmId> {
+    objs.iter()
+        .filter(|(_id, obj)| layer.check_obj(obj))
+        .map(|(id, _obj)| *id)
+        .collect()
+}
+
+/// Make a hash of everything which determines a layer's loam file, before
+/// extracting objects.
+///
+/// * `extract` Extract hash of layer.
+/// * `pbf` Identity of OSM files.
+/// * `dem` Elevation model.
+fn source_hash(
+    layer: &LayerDef,
+    extract: &str,
+    pbf: &str,
+    dem: Option<&Dem>,
+) -> String {
+    let mut hash = Fnv1a::default();
+    hash.write_str(&format!("{layer:?}"));
+    hash.write_str(extract);
+    hash.write_str(pbf);
+    if layer.sint_tag_index("ele").is_some() {
+        hash.write_str(dem.map_or("", Dem::identity));
+    }
+    hash.finish()
+}
+
+/// Check if a layer's current loam file was dug from the same source.
+///
+/// Returns the number of features, if unchanged.
+fn unchanged_source(
+    layer: &LayerDef,
+    loam_dir: &Path,
+    source: &str,
+) -> Result<Option<usize>> {
+    let current = loam_dir.join(format!("{}.loam", layer.name()));
+    let meta = LoamMeta::from(layer).with_source(source.to_string());
+    Ok(LoamMeta::read(&current)?
+        .filter(|old| old.same_source(&meta))
+        .map(|old| old.features))
+}
+
+/// Check if a layer is the source of any derived layer
+fn is_derive_source(layer: &LayerDef, derived: &[LayerDef]) -> bool {
+    derived.iter().any(|dl| {
+        matches!(
+            dl.derive(),
+            Some(Derive::Intersections { source, .. }) if source == layer.name()
+        )
+    })
+}
+
+/// Get tag keys needed from cached objects of a layer.
+///
+/// Derived layers check tags of their source layer's objects.
+fn cache_keys<'a>(
+    layer: &'a LayerDef,
+    derived: &'a [LayerDef],
+) -> impl Iterator<Item = &'a str> {
+    layer.referenced_keys().chain(
+        derived
+            .iter()
+            .filter(|dl| is_derive_source(layer, std::slice::from_ref(dl)))
+            .flat_map(LayerDef::referenced_keys),
+    )
+}
+
+/// Remove objects claimed by an excluded layer, returning number removed.
+///
+/// Only matching objects are removed; dependencies of other objects (way
+/// nodes and relation members) are kept, even if claimed.
+fn exclude_claimed(
+    layer: &LayerDef,
+    objs: &mut ObjMap,
+    claimed: &HashMap<String, HashSet<OsmId>>,
+) -> usize {
+    let Some(ids) = layer.exclude().and_then(|name| claimed.get(name)) else {
+        return 0;
+    };
+    let mut removed: HashSet<OsmId> = objs
+        .iter()
+        .filter(|(id, obj)| ids.contains(id) && layer.check_obj(obj))
+        .map(|(id, _obj)| *id)
+        .collect();
+    // repeat, since a kept member way needs its nodes
+    loop {
+        let needed: HashSet<OsmId> = objs
+            .iter()
+            .filter(|(id, _obj)| !removed.contains(id))
+            .flat_map(|(_id, obj)| obj_refs(obj))
+            .collect();
+        let before = removed.len();
+        removed.retain(|id| !needed.contains(id));
+        if removed.len() == before {
+            break;
+        }
+    }
+    objs.retain(|id, _obj| !removed.contains(id));
+    removed.len()
+}
+
+/// Get IDs of objects referenced by an object (way nodes / members)
+fn obj_refs(obj: &OsmObj) -> Vec<OsmId> {
+    match obj {
+        OsmObj::Node(_) => Vec::new(),
+        OsmObj::Way(way) => way.nodes.iter().map(|n| OsmId::Node(*n)).collect(),
+        OsmObj::Relation(rel) => rel.refs.iter().map(|r| r.member).collect(),
+    }
+}
+
+/// Find junction nodes of ways in a source layer, for a derived layer
+///
+/// Returns `None` if `source` is not the source of `layer`.
+fn way_junctions(
+    source: &LayerDef,
+    layer: &LayerDef,
+    objs: &ObjMap,
+) -> Result<Option<(ObjMap, DegreeMap)>> {
+    let Some(Derive::Intersections {
+        source: name,
+        include_ends,
+    }) = layer.derive()
+    else {
+        return Ok(None);
+    };
+    if name != source.name() {
+        return Ok(None);
+    }
+    if source.geom_tp() != GeomType::Linestring {
+        return Err(Error::InvalidDerive(format!(
+            "layer '{}': source '{name}' is not a linestring layer",
+            layer.name()
+        )));
+    }
+    // number of ways and incident segments for each node
+    let mut counts = BTreeMap::<NodeId, (usize, usize)>::new();
+    for way in objs.values().filter_map(|obj| obj.way()) {
+        // NOTE: check tags again to skip dependency ways
+        if !source.check_tags(&way.tags) || is_area(&way.tags, &[]) {
+            continue;
+        }
+        let closed = way.is_closed();
+        let last = way.nodes.len().saturating_sub(1);
+        for (i, nid) in way.nodes.iter().enumerate() {
+            if closed && i == last {
+                continue;
+            }
+            let segments = if !closed && (i == 0 || i == last) {
+                1
+            } else {
+                2
+            };
+            let count = counts.entry(*nid).or_default();
+            count.0 += 1;
+            count.1 += segments;
+        }
+    }
+    let mut nodes = ObjMap::new();
+    let mut degrees = DegreeMap::new();
+    for (nid, (n_ways, degree)) in counts {
+        if n_ways < 2 && !(*include_ends && degree == 1) {
+            continue;
+        }
+        if let Some(OsmObj::Node(node)) = objs.get(&OsmId::Node(nid)) {
+            if layer.check_tags(&node.tags) {
+                nodes.insert(OsmId::Node(nid), OsmObj::Node(node.clone()));
+                degrees.insert(nid, degree);
+            }
+        }
+    }
+    Ok(Some((nodes, degrees)))
+}
+
+/// Stitch member ways into rings, using an index of way end points.
+///
+/// Returns rings ordered by their closing (last) member way, and any
+/// unconnected chains.  Each takes the outer flag of its last member way,
+/// matching incremental assembly in member order.
+fn stitch_rings(
+    ways: Vec<(bool, Vec<NodeId>)>,
+) -> (Vec<(bool, Vec<NodeId>)>, Vec<(bool, Vec<NodeId>)>) {
+    let mut ends: HashMap<NodeId, Vec<usize>> = HashMap::new();
+    for (i, (_outer, way)) in ways.iter().enumerate() {
+        let (w0, w1) = end_points(way);
+        if w0 != w1 {
+            ends.entry(w0).or_default().push(i);
+            ends.entry(w1).or_default().push(i);
+        }
+    }
+    let mut used = vec![false; ways.len()];
+    let mut rings = Vec::new();
+    let mut chains = Vec::new();
+    for i in 0..ways.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        // index of last member way in chain
+        let mut last = i;
+        let mut chain = ways[i].1.clone();
+        let mut reversed = false;
+        loop {
+            let (c0, c1) = end_points(&chain);
+            if c0 == c1 {
+                rings.push((last, chain));
+                break;
+            }
+            let next = ends
+                .get(&c1)
+                .and_then(|idx| idx.iter().copied().find(|j| !used[*j]));
+            match next {
+                Some(j) => {
+                    used[j] = true;
+                    last = last.max(j);
+                    let (_outer, way) = &ways[j];
+                    chain.pop();
+                    if way[0] == c1 {
+                        chain.extend(way);
+                    } else {
+                        log::trace!("reversed {:?} <-> {:?}", way[0].0, c1.0);
+                        chain.extend(way.iter().rev());
+                    }
+                    log::debug!("connected @ {:?}", c1.0);
+                }
+                // extend from the other end before giving up
+                None if !reversed => {
+                    chain.reverse();
+                    reversed = true;
+                }
+                None => {
+                    chains.push((last, chain));
+                    break;
+                }
+            }
+        }
+    }
+    rings.sort_by_key(|(last, _)| *last);
+    let outer = |(last, way): (usize, Vec<NodeId>)| (ways[last].0, way);
+    (
+        rings.into_iter().map(outer).collect(),
+        chains.into_iter().map(outer).collect(),
+    )
+}
+
+/// Order route member ways into chains.
+///
+/// Consecutive members are joined where they connect; directed members
+/// (`forward` / `backward` roles, already reversed for `backward`) are
+/// never flipped.  Returns chains in member order.
+fn order_route(ways: Vec<(bool, Vec<NodeId>)>) -> Vec<Vec<NodeId>> {
+    // chains, with flag for fixed direction
+    let mut chains: Vec<(bool, Vec<NodeId>)> = Vec::new();
+    for (directed, mut way) in ways {
+        let (w0, w1) = end_points(&way);
+        if let Some((fixed, chain)) = chains.last_mut() {
+            let (c0, c1) = end_points(chain);
+            if !*fixed && c0 != c1 && (c0 == w0 || (c0 == w1 && !directed)) {
+                // first member of chain was backwards
+                if c1 != w0 && (c1 != w1 || directed) {
+                    chain.reverse();
+                }
+            }
+            let c1 = end_points(chain).1;
+            if c1 != w0 && c1 == w1 && !directed {
+                way.reverse();
+            }
+            if c1 == way[0] {
+                chain.pop();
+                chain.extend(way);
+                *fixed = true;
+                continue;
+            }
+        }
+        chains.push((directed, way));
+    }
+    chains.into_iter().map(|(_fixed, chain)| chain).collect()
+}
+
+/// Check if a value is a valid `sint`
+fn is_sint(val: Option<&str>) -> bool {
+    val.is_some_and(|v| parse_sint(v).is_some())
+}
+
+/// Calculate area-weighted centroid of outer rings.
+///
+/// Each ring is weighted by its absolute area, so rings wound in opposite
+/// directions don't cancel out.
+fn rings_centroid(rings: &[Ring]) -> Option<(f64, f64)> {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for (_outer, pts) in rings.iter().filter(|(outer, _)| *outer) {
+        let (mut a, mut x, mut y) = (0.0, 0.0, 0.0);
+        for (p0, p1) in pts.iter().zip(pts.iter().skip(1)) {
+            let cross = p0.0 * p1.1 - p1.0 * p0.1;
+            a += cross;
+            x += (p0.0 + p1.0) * cross;
+            y += (p0.1 + p1.1) * cross;
+        }
+        // ring centroid is (x / 3a, y / 3a), weighted by |a|
+        let sign = a.signum();
+        area += a.abs();
+        cx += x * sign;
+        cy += y * sign;
+    }
+    (area != 0.0).then(|| (cx / (3.0 * area), cy / (3.0 * area)))
+}
+
+/// Close a partial polygon chain.
+///
+/// When both ends are on (or beyond) the edges of the extract bounds, the
+/// ring follows the edges between them, like coastlines, in the direction
+/// enclosing less area.  Otherwise, a straight segment closes it.
+fn close_chain(
+    mut pts: Vec<(f64, f64)>,
+    bounds: Option<[f64; 4]>,
+) -> Vec<(f64, f64)> {
+    let first = pts[0];
+    let last = pts[pts.len() - 1];
+    let edges = bounds.and_then(|b| {
+        Some((b, perimeter_pos(b, last)?, perimeter_pos(b, first)?))
+    });
+    if let Some((b, t0, t1)) = edges {
+        let ring = |ccw| {
+            let mut ring = pts.clone();
+            let path = std::iter::once(clamp_point(b, last))
+                .chain(perimeter_corners(b, t0, t1, ccw))
+                .chain([clamp_point(b, first), first]);
+            for pt in path {
+                if ring.last() != Some(&pt) {
+                    ring.push(pt);
+                }
+            }
+            ring
+        };
+        let (ccw, cw) = (ring(true), ring(false));
+        return if signed_area(&ccw).abs() <= signed_area(&cw).abs() {
+            ccw
+        } else {
+            cw
+        };
+    }
+    pts.push(first);
+    pts
+}
+
+/// Clamp a point to bounds
+fn clamp_point([x0, y0, x1, y1]: [f64; 4], (x, y): (f64, f64)) -> (f64, f64) {
+    (x.clamp(x0, x1), y.clamp(y0, y1))
+}
+
+/// Get the position of a point along the perimeter of bounds.
+///
+/// Position is 0-4, counter-clockwise from the south-west corner, along
+/// the south, east, north and west edges.  Returns `None` if the point is
+/// not near an edge.
+fn perimeter_pos(b: [f64; 4], pt: (f64, f64)) -> Option<f64> {
+    let [x0, y0, x1, y1] = b;
+    let (w, h) = (x1 - x0, y1 - y0);
+    if !(w > 0.0 && h > 0.0) {
+        return None;
+    }
+    let tolerance = EDGE_TOLERANCE * w.max(h);
+    let (x, y) = clamp_point(b, pt);
+    let edges = [
+        (y - y0, (x - x0) / w),
+        (x1 - x, 1.0 + (y - y0) / h),
+        (y1 - y, 2.0 + (x1 - x) / w),
+        (x - x0, 3.0 + (y1 - y) / h),
+    ];
+    let (dist, pos) = edges
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap_or_default();
+    (dist <= tolerance).then_some(pos)
+}
+
+/// Get corners of bounds passed going from one perimeter position to
+/// another
+fn perimeter_corners(
+    [x0, y0, x1, y1]: [f64; 4],
+    t0: f64,
+    t1: f64,
+    ccw: bool,
+) -> Vec<(f64, f64)> {
+    // counter-clockwise from south-west
+    let corners = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+    let corner = |i: f64| corners[(i.rem_euclid(4.0)) as usize];
+    let mut path = Vec::new();
+    if ccw {
+        let t1 = if t1 < t0 { t1 + 4.0 } else { t1 };
+        let mut i = t0.floor() + 1.0;
+        while i < t1 {
+            path.push(corner(i));
+            i += 1.0;
+        }
+    } else {
+        let t1 = if t1 > t0 { t1 - 4.0 } else { t1 };
+        let mut i = t0.ceil() - 1.0;
+        while i > t1 {
+            path.push(corner(i));
+            i -= 1.0;
+        }
+    }
+    path
+}
+
+/// Get the end point nodes of a way
+fn end_points(way: &[NodeId]) -> (NodeId, NodeId) {
+    assert!(way.len() > 1);
+    let len = way.len() - 1;
+    (way[0], way[len])
+}
+
+/// Remove a staging directory after a failed dig.
+///
+/// Cleanup failures are only logged, so the original error is returned.
+fn remove_staging(staging: &Path) {
+    if let Err(e) = std::fs::remove_dir_all(staging) {
+        log::warn!("removing staging directory {staging:?}: {e}");
+    }
+}
+
+impl WyrmCfg {
+    /// Extract `osm` layer groups, creating a loam file for each layer.
+    ///
+    /// Matched objects of each layer are cached in the object cache
+    /// directory, and only extracted again when the layer's match rules or
+    /// the OSM files change.  With more than one OSM file, objects are
+    /// merged by `osm_id_policy`.  Layers are written to a staging
+    /// directory, then moved into the loam directory only after all layers
+    /// succeed.
+    ///
+    /// * `osm` Paths to OSM files.
+    /// * `stage` Dig stage.
+    /// * `keep_staging` Keep staging directory on failure (for debugging).
+    /// * `force` Rebuild layers even if matched objects are unchanged.
+    pub fn extract_osm<P>(
+        &self,
+        osm: &[P],
+        stage: DigStage,
+        keep_staging: bool,
+        force: bool,
+    ) -> Result<()>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        if osm.is_empty() {
+            return Err(Error::NoOsmFile("no OSM files".into()));
+        }
+        let mut osm_len = 0;
+        for path in osm {
+            osm_len += check_osm(path.as_ref())?;
+        }
+        std::fs::create_dir_all(self.cache_dir())?;
+        if stage == DigStage::Extract {
+            return self.extract_layers(osm, stage, None, force);
+        }
+        let staging = self.staging_dir();
+        std::fs::create_dir_all(&staging).map_err(|e| {
+            Error::NotWritable(format!("{:?}: {e}", self.loam_dir()))
+        })?;
+        log::debug!("staging directory: {staging:?}");
+        if let Err(e) = self.check_space(&staging, osm_len) {
+            remove_staging(&staging);
+            return Err(e);
+        }
+        match self.extract_layers(osm, stage, Some(&staging), force) {
+            Ok(()) => {
+                self.swap_staging(&staging)?;
+                std::fs::remove_dir_all(&staging)?;
+                Ok(())
+            }
+            Err(e) => {
+                if keep_staging {
+                    println!("Keeping staging directory: {staging:?}");
+                } else {
+                    remove_staging(&staging);
+                }
+                Err(e)
+            }
+        }
+    }
 
-impl WyrmCfg {
-    /// Extract `osm` layer groups, creating a loam file for each layer
-    pub fn extract_osm<P>(&self, osm: P) -> Result<()>
+    /// Check for enough free space to dig
+    fn check_space(&self, staging: &Path, osm_len: u64) -> Result<()> {
+        let factor = self.dig_space_factor.unwrap_or(DIG_SPACE_FACTOR);
+        let needed = ((osm_len as f64) * factor) as u64;
+        let needed = needed.max(loam_size(&self.loam_dir()));
+        let available = fs2::available_space(staging)?;
+        log::debug!("dig space: {needed} needed, {available} available");
+        if needed > available {
+            return Err(Error::InsufficientSpace { needed, available });
+        }
+        Ok(())
+    }
+
+    /// Get path to a new staging directory
+    fn staging_dir(&self) -> PathBuf {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut path = self.loam_dir();
+        path.push(format!(".staging-{secs}"));
+        path
+    }
+
+    /// Move all staged loam files into the loam directory.
+    ///
+    /// The manifest is moved last, so a changed manifest means all files
+    /// from the dig are in place (servers reload on it).
+    fn swap_staging(&self, staging: &Path) -> Result<()> {
+        let loam_dir = self.loam_dir();
+        let mut entries = staging
+            .read_dir()?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name() == MANIFEST);
+        for entry in entries {
+            let path = loam_dir.join(entry.file_name());
+            log::debug!("renaming {:?} to {path:?}", entry.path());
+            std::fs::rename(entry.path(), path)?;
+        }
+        Ok(())
+    }
+
+    /// Extract `osm` layer groups, using the object cache.
+    ///
+    /// Loam files are only made when `staging` is set.
+    fn extract_layers<P>(
+        &self,
+        osm: &[P],
+        stage: DigStage,
+        staging: Option<&Path>,
+        force: bool,
+    ) -> Result<()>
     where
         P: AsRef<Path> + std::fmt::Debug,
     {
-        let mut extractor = OsmExtractor::new(&osm)?;
+        let mut extractors = Vec::with_capacity(osm.len());
+        for path in osm {
+            extractors.push(OsmExtractor::new(path)?);
+        }
+        let dem = match &self.dem_dir {
+            Some(dir) => Some(Dem::load(dir)?),
+            None => None,
+        };
+        let mut pbf = Vec::with_capacity(osm.len());
+        for path in osm {
+            pbf.push(pbf_identity(path.as_ref())?);
+        }
+        let mut pbf = pbf.join(";");
+        if osm.len() > 1 {
+            pbf.push_str(&format!(";{:?}", self.osm_id_policy));
+        }
+        let cache_dir = self.cache_dir();
+        let cache = RefCell::new(GeomCache::new(CACHE_COORDS));
+        let skips = RefCell::new(match &self.skip_log {
+            Some(path) => SkipLog::with_file(path)?,
+            None => SkipLog::default(),
+        });
+        let loam_dir = self.loam_dir();
+        // header of the first file describes the data
+        let mut manifest = Manifest::new(osm, extractors[0].header.clone());
         println!("Extracting layers from {:?}", osm);
         for group in &self.layer_group {
             if group.osm {
-                for layer in &group.layer {
-                    let layer = LayerDef::try_from(layer)?;
-                    let objs = extractor.extract_layer(&layer)?;
-                    let loam = self.loam_path(layer.name());
-                    let maker = GeometryMaker::new(layer, objs);
-                    maker.make_geometry(loam)?;
+                let mut layers = Vec::new();
+                // layers sharing a loam file are only filtered when served
+                for layer in group.layer.iter().filter(|l| l.loam.is_none()) {
+                    layers.push(
+                        LayerDef::try_from(layer)?
+                            .with_name_languages(&group.name_languages)
+                            .with_max_line_span(self.max_line_span)
+                            .with_max_value_len(self.max_value_len)
+                            .without_serve_settings(),
+                    );
+                }
+                let (generated, layers): (Vec<_>, Vec<_>) =
+                    layers.into_iter().partition(|l| l.generate().is_some());
+                let (derived, layers): (Vec<_>, Vec<_>) =
+                    layers.into_iter().partition(|l| l.derive().is_some());
+                let mut junctions = BTreeMap::new();
+                // objects claimed by layers which are excluded from others
+                let claim_names: HashSet<String> = layers
+                    .iter()
+                    .filter_map(|l| l.exclude().map(str::to_string))
+                    .collect();
+                let mut claimed = HashMap::<String, HashSet<OsmId>>::new();
+                let mut hashes = HashMap::<String, String>::new();
+                for layer in layers {
+                    let exclude = layer
+                        .exclude()
+                        .and_then(|name| hashes.get(name))
+                        .map(String::as_str);
+                    let hash = layer.extract_hash(exclude);
+                    hashes.insert(layer.name().to_string(), hash.clone());
+                    let source = source_hash(&layer, &hash, &pbf, dem.as_ref());
+                    // objects of claimed and source layers are needed later
+                    if let Some(staging) = staging.filter(|_| {
+                        !force
+                            && !claim_names.contains(layer.name())
+                            && !is_derive_source(&layer, &derived)
+                    }) {
+                        if let Some(n) =
+                            unchanged_source(&layer, &loam_dir, &source)?
+                        {
+                            println!(
+                                "  layer: {} (unchanged, skipped)",
+                                layer.name()
+                            );
+                            manifest
+                                .push_layer(&layer, n, staging, &loam_dir)?;
+                            continue;
+                        }
+                    }
+                    let header = CacheHeader::new(
+                        hash,
+                        pbf.clone(),
+                        cache_keys(&layer, &derived),
+                    );
+                    let path = cache_path(&cache_dir, layer.name());
+                    let fresh = !(force && stage != DigStage::Geometry)
+                        && CacheHeader::read(&path)?
+                            .is_some_and(|h| h.same_source(&header));
+                    let objs = if fresh {
+                        if staging.is_none()
+                            && !claim_names.contains(layer.name())
+                        {
+                            println!("  layer: {} (cached)", layer.name());
+                            continue;
+                        }
+                        read_objs(&path)?
+                    } else if stage == DigStage::Geometry {
+                        return Err(Error::ObjCache(format!(
+                            "layer '{}' missing or stale; \
+                            run dig --stage extract",
+                            layer.name()
+                        )));
+                    } else {
+                        let mut objs = extract_merged(
+                            &mut extractors,
+                            &layer,
+                            &mut skips.borrow_mut(),
+                            self.osm_id_policy,
+                            self.reorder_patterns.unwrap_or(true),
+                        )?;
+                        if let Some(name) = layer.exclude() {
+                            let n =
+                                exclude_claimed(&layer, &mut objs, &claimed);
+                            if n > 0 {
+                                println!(
+                                    "  layer: {} (excluded {n} objects \
+                                    matching {name})",
+                                    layer.name()
+                                );
+                            }
+                        }
+                        write_objs(&path, header, &objs)?;
+                        objs
+                    };
+                    if claim_names.contains(layer.name()) {
+                        claimed.insert(
+                            layer.name().to_string(),
+                            matching_ids(&layer, &objs),
+                        );
+                    }
+                    let Some(staging) = staging else {
+                        continue;
+                    };
+                    for dl in &derived {
+                        if let Some(j) = way_junctions(&layer, dl, &objs)? {
+                            junctions.insert(dl.name().to_string(), j);
+                        }
+                    }
+                    let (objs, deps) = compact_objs(&layer, objs);
+                    let maker = GeometryMaker::new(
+                        layer,
+                        objs,
+                        deps,
+                        dem.as_ref(),
+                        &cache,
+                        &skips,
+                    )
+                    .with_bounds(osm_bounds(&extractors))
+                    .with_source(source);
+                    let n = maker.make_loam(staging, &loam_dir, force)?;
+                    manifest.push_layer(&maker.layer, n, staging, &loam_dir)?;
+                }
+                let Some(staging) = staging else {
+                    continue;
+                };
+                for layer in generated {
+                    let generator =
+                        ShapeGenerator::new(layer, osm_bounds(&extractors))?;
+                    let n = generator.make_loam(staging, &loam_dir, force)?;
+                    manifest.push_layer(
+                        &generator.layer,
+                        n,
+                        staging,
+                        &loam_dir,
+                    )?;
+                }
+                for layer in derived {
+                    let Some((objs, degrees)) = junctions.remove(layer.name())
+                    else {
+                        return Err(Error::InvalidDerive(format!(
+                            "layer '{}': unknown source layer",
+                            layer.name()
+                        )));
+                    };
+                    let (objs, deps) = compact_objs(&layer, objs);
+                    let maker = GeometryMaker::new(
+                        layer,
+                        objs,
+                        deps,
+                        dem.as_ref(),
+                        &cache,
+                        &skips,
+                    )
+                    .with_degrees(degrees);
+                    let n = maker.make_loam(staging, &loam_dir, force)?;
+                    manifest.push_layer(&maker.layer, n, staging, &loam_dir)?;
                 }
             }
         }
+        skips.borrow_mut().finish()?;
+        let Some(staging) = staging else {
+            return Ok(());
+        };
+        let cache = cache.borrow();
+        println!(
+            "Geometry cache: {} hits, {} misses ({:.1}% hit rate)",
+            cache.hits,
+            cache.misses,
+            cache.hit_rate()
+        );
+        manifest.write(staging)?;
         Ok(())
     }
 }
+
+/// Get peak resident set size (bytes), where available (Linux)
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm(&status)
+}
+
+/// Parse peak resident set size (`VmHWM`) from `/proc/self/status`
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::uncompacted_objs;
+    use crate::config::LayerCfg;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use osmpbfreader::{Ref, Relation, Way};
+    use std::io::Write;
+
+    /// Append a varint
+    fn varint(buf: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        buf.push(v as u8);
+    }
+
+    /// Append a varint field
+    fn field_varint(buf: &mut Vec<u8>, field: u64, v: u64) {
+        varint(buf, field << 3);
+        varint(buf, v);
+    }
+
+    /// Append a length-delimited field
+    fn field_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(buf, (field << 3) | 2);
+        varint(buf, bytes.len() as u64);
+        buf.extend_from_slice(bytes);
+    }
+
+    /// Append a packed varint field
+    fn field_packed<I>(buf: &mut Vec<u8>, field: u64, values: I)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut packed = Vec::new();
+        for v in values {
+            varint(&mut packed, v);
+        }
+        field_bytes(buf, field, &packed);
+    }
+
+    /// Encode a signed value with zigzag encoding
+    fn zigzag_enc(v: i64) -> u64 {
+        ((v << 1) ^ (v >> 63)) as u64
+    }
+
+    /// Delta-encode values (zigzag)
+    fn delta(values: impl IntoIterator<Item = i64>) -> Vec<u64> {
+        let mut prev = 0;
+        values
+            .into_iter()
+            .map(|v| {
+                let d = v - prev;
+                prev = v;
+                zigzag_enc(d)
+            })
+            .collect()
+    }
+
+    /// PBF string table
+    #[derive(Default)]
+    struct StringTable(Vec<String>);
+
+    impl StringTable {
+        /// Get the index of a string, adding it if needed
+        fn index(&mut self, s: &str) -> u64 {
+            if self.0.is_empty() {
+                self.0.push(String::new());
+            }
+            match self.0.iter().position(|t| t == s) {
+                Some(i) => i as u64,
+                None => {
+                    self.0.push(s.to_string());
+                    (self.0.len() - 1) as u64
+                }
+            }
+        }
+
+        /// Append tag key and value fields
+        fn tags(&mut self, buf: &mut Vec<u8>, tags: &Tags) {
+            let (keys, vals): (Vec<u64>, Vec<u64>) = tags
+                .iter()
+                .map(|(k, v)| (self.index(k), self.index(v)))
+                .unzip();
+            field_packed(buf, 2, keys);
+            field_packed(buf, 3, vals);
+        }
+    }
+
+    /// Append a blob (zlib compressed) with its header
+    fn write_blob(file: &mut Vec<u8>, tp: &str, data: &[u8]) {
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(data).unwrap();
+        let mut blob = Vec::new();
+        field_varint(&mut blob, 2, data.len() as u64);
+        field_bytes(&mut blob, 3, &enc.finish().unwrap());
+        let mut header = Vec::new();
+        field_bytes(&mut header, 1, tp.as_bytes());
+        field_varint(&mut header, 3, blob.len() as u64);
+        file.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        file.extend(header);
+        file.extend(blob);
+    }
+
+    /// Encode objects as an OSM PBF file
+    fn pbf_bytes(objs: &[OsmObj]) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let (mut nodes, mut ways, mut rels) =
+            (Vec::new(), Vec::new(), Vec::new());
+        for obj in objs {
+            match obj {
+                OsmObj::Node(node) => {
+                    let mut buf = Vec::new();
+                    field_varint(&mut buf, 1, zigzag_enc(node.id.0));
+                    strings.tags(&mut buf, &node.tags);
+                    // granularity 100 nanodegrees (decimicro)
+                    let lat = i64::from(node.decimicro_lat);
+                    let lon = i64::from(node.decimicro_lon);
+                    field_varint(&mut buf, 8, zigzag_enc(lat));
+                    field_varint(&mut buf, 9, zigzag_enc(lon));
+                    field_bytes(&mut nodes, 1, &buf);
+                }
+                OsmObj::Way(way) => {
+                    let mut buf = Vec::new();
+                    field_varint(&mut buf, 1, way.id.0 as u64);
+                    strings.tags(&mut buf, &way.tags);
+                    let refs = delta(way.nodes.iter().map(|n| n.0));
+                    field_packed(&mut buf, 8, refs);
+                    field_bytes(&mut ways, 3, &buf);
+                }
+                OsmObj::Relation(rel) => {
+                    let mut buf = Vec::new();
+                    field_varint(&mut buf, 1, rel.id.0 as u64);
+                    strings.tags(&mut buf, &rel.tags);
+                    let roles: Vec<u64> = rel
+                        .refs
+                        .iter()
+                        .map(|r| strings.index(&r.role))
+                        .collect();
+                    field_packed(&mut buf, 8, roles);
+                    let ids =
+                        delta(rel.refs.iter().map(|r| id_kind(r.member).1));
+                    field_packed(&mut buf, 9, ids);
+                    let types = rel.refs.iter().map(|r| match r.member {
+                        OsmId::Node(_) => 0,
+                        OsmId::Way(_) => 1,
+                        OsmId::Relation(_) => 2,
+                    });
+                    field_packed(&mut buf, 10, types);
+                    field_bytes(&mut rels, 4, &buf);
+                }
+            }
+        }
+        let mut table = Vec::new();
+        for s in &strings.0 {
+            field_bytes(&mut table, 1, s.as_bytes());
+        }
+        let mut block = Vec::new();
+        field_bytes(&mut block, 1, &table);
+        for group in [nodes, ways, rels] {
+            if !group.is_empty() {
+                field_bytes(&mut block, 2, &group);
+            }
+        }
+        let mut header = Vec::new();
+        field_bytes(&mut header, 4, b"OsmSchema-V0.6");
+        field_bytes(&mut header, 16, b"earthwyrm-test");
+        let mut file = Vec::new();
+        write_blob(&mut file, "OSMHeader", &header);
+        write_blob(&mut file, "OSMData", &block);
+        file
+    }
+
+    /// Write a PBF fixture file
+    fn write_pbf(name: &str, objs: &[OsmObj]) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}.osm.pbf", std::process::id()));
+        std::fs::write(&path, pbf_bytes(objs)).unwrap();
+        path
+    }
+
+    /// Make tags from `key=value` pairs
+    fn tags(kv: &[(&str, &str)]) -> Tags {
+        let mut tags = Tags::new();
+        for (k, v) in kv {
+            tags.insert((*k).into(), (*v).into());
+        }
+        tags
+    }
+
+    /// Make a node (decimicro degrees)
+    fn node(id: i64, lat: i32, lon: i32, kv: &[(&str, &str)]) -> OsmObj {
+        OsmObj::Node(Node {
+            id: NodeId(id),
+            tags: tags(kv),
+            decimicro_lat: lat,
+            decimicro_lon: lon,
+        })
+    }
+
+    /// Make a way
+    fn way(id: i64, nodes: &[i64], kv: &[(&str, &str)]) -> OsmObj {
+        OsmObj::Way(Way {
+            id: WayId(id),
+            tags: tags(kv),
+            nodes: nodes.iter().map(|n| NodeId(*n)).collect(),
+        })
+    }
+
+    /// Make a relation of way members
+    fn rel(id: i64, members: &[(i64, &str)], kv: &[(&str, &str)]) -> OsmObj {
+        OsmObj::Relation(Relation {
+            id: RelationId(id),
+            tags: tags(kv),
+            refs: members
+                .iter()
+                .map(|(w, role)| Ref {
+                    member: OsmId::Way(WayId(*w)),
+                    role: (*role).into(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Make a layer definition
+    fn layer_def(muon: &str) -> LayerDef {
+        let cfg: LayerCfg = muon_rs::from_str(muon).unwrap();
+        LayerDef::try_from(&cfg).unwrap()
+    }
+
+    /// Water polygon layer
+    const WATER: &str =
+        "name: water\ngeom_type: polygon\nzoom: 0+\ntags: .natural=water ?name\n";
+
+    /// Two fixtures, with way 10 and node 1 in both (differing).
+    ///
+    /// Relation 30 in file A has an outer way (11) from file B, which
+    /// shares node 1.
+    fn id_fixtures() -> [PathBuf; 2] {
+        let water = [("natural", "water"), ("name", "A lake")];
+        let a = write_pbf(
+            "ids-a",
+            &[
+                node(1, 450_000_000, -930_000_000, &[]),
+                node(2, 450_010_000, -930_000_000, &[]),
+                node(3, 450_010_000, -930_010_000, &[]),
+                way(10, &[1, 2, 3, 1], &water),
+                rel(
+                    30,
+                    &[(11, "outer")],
+                    &[
+                        ("type", "multipolygon"),
+                        ("natural", "water"),
+                        ("name", "Pond"),
+                    ],
+                ),
+            ],
+        );
+        let water = [("natural", "water"), ("name", "B lake")];
+        let b = write_pbf(
+            "ids-b",
+            &[
+                node(1, 460_000_000, -940_000_000, &[]),
+                node(5, 460_010_000, -940_000_000, &[]),
+                node(6, 460_010_000, -940_010_000, &[]),
+                node(7, 460_020_000, -940_010_000, &[]),
+                way(10, &[1, 5, 6, 1], &water),
+                way(11, &[1, 6, 7, 1], &[]),
+            ],
+        );
+        [a, b]
+    }
+
+    /// Extract the water layer from ID fixtures
+    fn extract_ids(policy: IdPolicy) -> Result<ObjMap> {
+        let paths = id_fixtures();
+        let mut extractors: Vec<_> = paths
+            .iter()
+            .map(|p| OsmExtractor::new(p).unwrap())
+            .collect();
+        let layer = layer_def(WATER);
+        let mut skips = SkipLog::default();
+        let res =
+            extract_merged(&mut extractors, &layer, &mut skips, policy, false);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+        res
+    }
+
+    /// Get way node IDs
+    fn way_nodes(objs: &ObjMap, id: i64) -> Vec<i64> {
+        match objs.get(&OsmId::Way(WayId(id))) {
+            Some(OsmObj::Way(way)) => way.nodes.iter().map(|n| n.0).collect(),
+            _ => panic!("way {id} missing"),
+        }
+    }
+
+    /// Get node latitude (decimicro degrees)
+    fn node_lat(objs: &ObjMap, id: i64) -> i32 {
+        match objs.get(&OsmId::Node(NodeId(id))) {
+            Some(OsmObj::Node(node)) => node.decimicro_lat,
+            _ => panic!("node {id} missing"),
+        }
+    }
+
+    /// Get the `name` tag of an object
+    fn name(objs: &ObjMap, id: OsmId) -> &str {
+        objs[&id]
+            .tags()
+            .get("name")
+            .map(|v| v.as_str())
+            .unwrap_or("")
+    }
+
+    #[test]
+    fn id_policy_last_wins() {
+        let objs = extract_ids(IdPolicy::LastWins).unwrap();
+        assert!(missing_refs(&objs).is_empty());
+        // way 10 and node 1 from file B replace those from A
+        assert_eq!(name(&objs, OsmId::Way(WayId(10))), "B lake");
+        assert_eq!(way_nodes(&objs, 10), [1, 5, 6, 1]);
+        assert_eq!(node_lat(&objs, 1), 460_000_000);
+        // relation member from file B resolved across files
+        assert_eq!(name(&objs, OsmId::Relation(RelationId(30))), "Pond");
+        assert_eq!(way_nodes(&objs, 11), [1, 6, 7, 1]);
+    }
+
+    #[test]
+    fn id_policy_error() {
+        match extract_ids(IdPolicy::Error) {
+            Err(Error::IdCollision(msg)) => {
+                assert!(msg.contains("layer 'water': node 1"), "{msg}");
+                assert!(msg.contains("ids-b"), "{msg}");
+            }
+            res => panic!("expected collision: {res:?}"),
+        }
+    }
+
+    #[test]
+    fn id_policy_namespace() {
+        let objs = extract_ids(IdPolicy::Namespace).unwrap();
+        assert!(missing_refs(&objs).is_empty());
+        let ns = NAMESPACE_OFFSET;
+        // both versions of way 10 and node 1 survive
+        assert_eq!(name(&objs, OsmId::Way(WayId(10))), "A lake");
+        assert_eq!(way_nodes(&objs, 10), [1, 2, 3, 1]);
+        assert_eq!(node_lat(&objs, 1), 450_000_000);
+        assert_eq!(name(&objs, OsmId::Way(WayId(10 + ns))), "B lake");
+        assert_eq!(way_nodes(&objs, 10 + ns), [1 + ns, 5, 6, 1 + ns]);
+        assert_eq!(node_lat(&objs, 1 + ns), 460_000_000);
+        // way 11 (resolved from file B) uses file B's node 1
+        assert_eq!(name(&objs, OsmId::Relation(RelationId(30))), "Pond");
+        assert_eq!(way_nodes(&objs, 11), [1 + ns, 6, 7, 1 + ns]);
+        assert!(!objs.contains_key(&OsmId::Way(WayId(11 + ns))));
+    }
+
+    #[test]
+    fn namespace_file_id() {
+        let ns = NAMESPACE_OFFSET;
+        let way = |id| OsmId::Way(WayId(id));
+        assert_eq!(file_id(way(10), 0), Some(way(10)));
+        assert_eq!(file_id(way(10), 2), Some(way(10)));
+        assert_eq!(file_id(way(-10), 1), Some(way(-10)));
+        assert_eq!(file_id(way(10 + ns), 1), Some(way(10)));
+        assert_eq!(file_id(way(10 + ns), 2), None);
+        assert_eq!(file_id(way(10 + 2 * ns), 2), Some(way(10)));
+        let collisions = HashSet::from([way(10)]);
+        assert_eq!(namespace_id(way(10), &collisions, 2), way(10 + 2 * ns));
+        assert_eq!(namespace_id(way(11), &collisions, 2), way(11));
+    }
+
+    /// Water polygon layer, including OSM ID
+    const WATER_ID: &str = "name: water\ngeom_type: polygon\nzoom: 0+\n\
+        tags: .natural=water ?name ?osm_id\n";
+
+    /// Make water polygons from a fixture, returning their tag values
+    fn polygon_values(name: &str, objs: &[OsmObj]) -> Vec<Values> {
+        layer_polygons(WATER_ID, name, objs)
+    }
+
+    /// Make a geometry maker for a fixture, and call a function with it
+    fn with_maker<T>(
+        muon: &str,
+        name: &str,
+        objs: &[OsmObj],
+        bounds: Option<[f64; 4]>,
+        f: impl FnOnce(&GeometryMaker) -> T,
+    ) -> T {
+        with_compact_maker(muon, name, objs, bounds, compact_objs, f)
+    }
+
+    /// Make a geometry maker with a compact function, and call a function
+    /// with it
+    fn with_compact_maker<T>(
+        muon: &str,
+        name: &str,
+        objs: &[OsmObj],
+        bounds: Option<[f64; 4]>,
+        compact: fn(&LayerDef, ObjMap) -> (CompactMap, CompactDeps),
+        f: impl FnOnce(&GeometryMaker) -> T,
+    ) -> T {
+        let path = write_pbf(name, objs);
+        let layer = layer_def(muon);
+        let mut extractor = OsmExtractor::new(&path).unwrap();
+        let mut skips = SkipLog::default();
+        let mut stats = layer.match_stats(false);
+        let objs = extractor
+            .extract_layer(&layer, &mut skips, &mut stats)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        let (objs, deps) = compact(&layer, objs);
+        let cache = RefCell::new(GeomCache::new(CACHE_COORDS));
+        let skips = RefCell::new(skips);
+        let maker = GeometryMaker::new(layer, objs, deps, None, &cache, &skips)
+            .with_bounds(bounds);
+        f(&maker)
+    }
+
+    /// Make polygons from a fixture, returning skip log entries (sorted)
+    fn polygon_skips(muon: &str, name: &str, objs: &[OsmObj]) -> Vec<String> {
+        let path = write_pbf(name, objs);
+        let log_path = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}.skips", std::process::id()));
+        let layer = layer_def(muon);
+        let mut extractor = OsmExtractor::new(&path).unwrap();
+        let mut skips = SkipLog::with_file(&log_path).unwrap();
+        let mut stats = layer.match_stats(false);
+        let objs = extractor
+            .extract_layer(&layer, &mut skips, &mut stats)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        let (objs, deps) = compact_objs(&layer, objs);
+        let cache = RefCell::new(GeomCache::new(CACHE_COORDS));
+        let skips = RefCell::new(skips);
+        GeometryMaker::new(layer, objs, deps, None, &cache, &skips)
+            .for_each_polygon(|_geom| Ok(()))
+            .unwrap();
+        skips.into_inner().finish().unwrap();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        std::fs::remove_file(log_path).unwrap();
+        let mut lines = log.lines();
+        assert_eq!(lines.next(), Some("layer\tkind\tid\treason"));
+        let mut lines: Vec<String> = lines.map(String::from).collect();
+        lines.sort();
+        lines
+    }
+
+    /// Make polygons from a fixture, returning their tag values
+    fn layer_polygons(muon: &str, name: &str, objs: &[OsmObj]) -> Vec<Values> {
+        let mut values = with_maker(muon, name, objs, None, |maker| {
+            let mut values = Vec::new();
+            maker
+                .for_each_polygon(|geom| {
+                    values.push(geom.data().clone());
+                    Ok(())
+                })
+                .unwrap();
+            values
+        });
+        values.sort();
+        values
+    }
+
+    /// Get a relation from a geometry maker
+    fn relation<'a>(maker: &'a GeometryMaker, id: i64) -> &'a CompactRelation {
+        maker.objs[&OsmId::Relation(RelationId(id))]
+            .relation()
+            .unwrap()
+    }
+
+    /// Make tag values
+    fn values(vals: &[&str]) -> Values {
+        vals.iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    /// Nodes of a lake (1-4) with an island (5-7)
+    fn lake_nodes() -> Vec<OsmObj> {
+        vec![
+            node(1, 450_000_000, -930_000_000, &[]),
+            node(2, 450_100_000, -930_000_000, &[]),
+            node(3, 450_100_000, -929_900_000, &[]),
+            node(4, 450_000_000, -929_900_000, &[]),
+            node(5, 450_040_000, -929_960_000, &[]),
+            node(6, 450_060_000, -929_960_000, &[]),
+            node(7, 450_060_000, -929_940_000, &[]),
+        ]
+    }
+
+    /// Dig a layer, then export it as GeoJSON
+    fn dig_export(
+        muon: &str,
+        name: &str,
+        objs: &[OsmObj],
+    ) -> serde_json::Value {
+        let loam = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}.loam", std::process::id()));
+        with_maker(muon, name, objs, None, |maker| {
+            maker.make_geometry(&loam).unwrap()
+        });
+        let layer = layer_def(muon);
+        let tree =
+            GeomTree::new(layer.geom_tp(), layer.precision(), &loam).unwrap();
+        let mut out = Vec::new();
+        let n = tree.export_geojson(&layer, None, &mut out).unwrap();
+        std::fs::remove_file(&loam).unwrap();
+        let collection: serde_json::Value =
+            serde_json::from_slice(&out).unwrap();
+        assert_eq!(collection["features"].as_array().unwrap().len(), n);
+        collection
+    }
+
+    /// Check that exported coordinates match nodes (decimicro degrees)
+    fn assert_coords(coords: &serde_json::Value, nodes: &[(i32, i32)]) {
+        let mut pts = Vec::new();
+        let mut stack = vec![coords];
+        while let Some(v) = stack.pop() {
+            let arr = v.as_array().unwrap();
+            match (arr[0].as_f64(), arr.get(1).and_then(|v| v.as_f64())) {
+                (Some(lon), Some(lat)) => pts.push((lon, lat)),
+                _ => stack.extend(arr),
+            }
+        }
+        let near = |(lon, lat): (f64, f64), (nlat, nlon): (i32, i32)| {
+            (lon - f64::from(nlon) / 1e7).abs() < 1e-6
+                && (lat - f64::from(nlat) / 1e7).abs() < 1e-6
+        };
+        for pt in &pts {
+            assert!(nodes.iter().any(|n| near(*pt, *n)), "{pt:?}");
+        }
+        for n in nodes {
+            assert!(pts.iter().any(|pt| near(*pt, *n)), "{n:?}");
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let lake = [
+            (450_000_000, -930_000_000),
+            (450_100_000, -930_000_000),
+            (450_100_000, -929_900_000),
+            (450_000_000, -929_900_000),
+        ];
+        let island = [
+            (450_040_000, -929_960_000),
+            (450_060_000, -929_960_000),
+            (450_060_000, -929_940_000),
+        ];
+        let mut objs = lake_nodes();
+        objs.push(node(8, 450_050_000, -929_950_000, &[("amenity", "cafe")]));
+        objs.push(way(10, &[1, 2, 3, 4, 1], &[]));
+        objs.push(way(11, &[5, 6, 7, 5], &[]));
+        objs.push(rel(
+            20,
+            &[(10, "outer"), (11, "inner")],
+            &[
+                ("type", "multipolygon"),
+                ("natural", "water"),
+                ("name", "Lake"),
+            ],
+        ));
+        objs.push(way(12, &[1, 5, 6], &[("highway", "primary")]));
+        // polygon with a hole
+        let water = dig_export(WATER, "export-water", &objs);
+        let feature = &water["features"][0];
+        assert_eq!(feature["geometry"]["type"], "Polygon");
+        assert_eq!(
+            feature["geometry"]["coordinates"].as_array().unwrap().len(),
+            2
+        );
+        assert_eq!(feature["properties"]["name"], "Lake");
+        assert_eq!(feature["properties"]["natural"], "water");
+        let nodes: Vec<_> = lake.iter().chain(&island).copied().collect();
+        assert_coords(&feature["geometry"]["coordinates"], &nodes);
+        // linestring
+        let muon = "name: road\ngeom_type: linestring\nzoom: 0+\n\
+            tags: ?highway\n";
+        let roads = dig_export(muon, "export-road", &objs);
+        let feature = &roads["features"][0];
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        assert_eq!(feature["properties"]["highway"], "primary");
+        assert_coords(
+            &feature["geometry"]["coordinates"],
+            &[lake[0], island[0], island[1]],
+        );
+        // point
+        let muon = "name: cafe\ngeom_type: point\nzoom: 0+\n\
+            tags: .amenity=cafe\n";
+        let cafes = dig_export(muon, "export-cafe", &objs);
+        let feature = &cafes["features"][0];
+        assert_eq!(feature["geometry"]["type"], "Point");
+        assert_coords(
+            &feature["geometry"]["coordinates"],
+            &[(450_050_000, -929_950_000)],
+        );
+    }
+
+    /// Dig a layer from an OSM file, returning loam bytes and GeoJSON export
+    #[cfg(feature = "xml")]
+    fn dig_path(
+        muon: &str,
+        name: &str,
+        path: &Path,
+    ) -> (Vec<u8>, serde_json::Value) {
+        let layer = layer_def(muon);
+        let mut extractor = OsmExtractor::new(path).unwrap();
+        let mut skips = SkipLog::default();
+        let mut stats = layer.match_stats(false);
+        let objs = extractor
+            .extract_layer(&layer, &mut skips, &mut stats)
+            .unwrap();
+        let (objs, deps) = compact_objs(&layer, objs);
+        let cache = RefCell::new(GeomCache::new(CACHE_COORDS));
+        let skips = RefCell::new(skips);
+        let loam = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-{name}.loam", std::process::id()));
+        GeometryMaker::new(layer, objs, deps, None, &cache, &skips)
+            .make_geometry(&loam)
+            .unwrap();
+        let layer = layer_def(muon);
+        let tree =
+            GeomTree::new(layer.geom_tp(), layer.precision(), &loam).unwrap();
+        let mut out = Vec::new();
+        tree.export_geojson(&layer, None, &mut out).unwrap();
+        let bytes = std::fs::read(&loam).unwrap();
+        std::fs::remove_file(&loam).unwrap();
+        (bytes, serde_json::from_slice(&out).unwrap())
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn xml_fixture_dig() {
+        use bzip2::write::BzEncoder;
+        let osm = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("data")
+            .join("lake.osm");
+        let (loam, collection) = dig_path(WATER_ID, "xml-lake", &osm);
+        let features = collection["features"].as_array().unwrap();
+        let mut names: Vec<_> = features
+            .iter()
+            .map(|f| f["properties"]["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        // deleted way 13 is not included
+        assert_eq!(names, ["Lake", "Pond & Marsh"]);
+        let lake = features
+            .iter()
+            .find(|f| f["properties"]["name"] == "Lake")
+            .unwrap();
+        let coords = &lake["geometry"]["coordinates"];
+        assert_eq!(coords.as_array().unwrap().len(), 2);
+        assert_coords(
+            &coords[0],
+            &[
+                (450_000_000, -930_000_000),
+                (450_100_000, -930_000_000),
+                (450_100_000, -929_900_000),
+                (450_000_000, -929_900_000),
+            ],
+        );
+        assert_coords(
+            &coords[1],
+            &[
+                (450_040_000, -929_960_000),
+                (450_060_000, -929_960_000),
+                (450_060_000, -929_940_000),
+            ],
+        );
+        // same file, bzip2 compressed
+        let bz2 = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-xml-lake.osm.bz2", std::process::id()));
+        let mut enc = BzEncoder::new(
+            File::create(&bz2).unwrap(),
+            bzip2::Compression::default(),
+        );
+        enc.write_all(&std::fs::read(&osm).unwrap()).unwrap();
+        enc.finish().unwrap();
+        let (loam_bz2, _) = dig_path(WATER_ID, "xml-lake-bz2", &bz2);
+        std::fs::remove_file(&bz2).unwrap();
+        assert_eq!(loam, loam_bz2);
+    }
+
+    #[test]
+    fn generated_extent() {
+        use crate::tile::{decode_summary, TagValue, TileFetch, Wyrm};
+        use mvt::TileId;
+        const LAND: &str = "name: land\ngeom_type: polygon\nzoom: 0+\n\
+            source: generated\nshape: extent\n\
+            extent: -93.5 44.5 -92.5 45.5\ntags: .class=land\n";
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-generated", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // configured extent is used instead of OSM bounds
+        let osm_bounds = Some([-94.0, 44.0, -92.0, 46.0]);
+        let generator =
+            ShapeGenerator::new(layer_def(LAND), osm_bounds).unwrap();
+        assert_eq!(generator.make_loam(&dir, &dir, true).unwrap(), 1);
+        let loam = dir.join("land.loam");
+        let layer = layer_def(LAND);
+        let tree =
+            GeomTree::new(layer.geom_tp(), layer.precision(), &loam).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(tree.export_geojson(&layer, None, &mut out).unwrap(), 1);
+        let collection: serde_json::Value =
+            serde_json::from_slice(&out).unwrap();
+        let land = &collection["features"][0];
+        assert_eq!(land["properties"]["class"], "land");
+        assert_coords(
+            &land["geometry"]["coordinates"],
+            &[
+                (445_000_000, -935_000_000),
+                (445_000_000, -925_000_000),
+                (455_000_000, -925_000_000),
+                (455_000_000, -935_000_000),
+            ],
+        );
+        // served like any other polygon layer
+        let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+            layer_group: tile\n  layer: land\n    geom_type: polygon\n    \
+            zoom: 0+\n    source: generated\n    shape: extent\n    \
+            extent: -93.5 44.5 -92.5 45.5\n    tags: .class=land\n";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let wyrm = Wyrm::try_from(&cfg.with_loam_dir(dir.clone())).unwrap();
+        for tid in [TileId::new(0, 0, 0), TileId::new(0, 1, 2)] {
+            let tid = tid.unwrap();
+            let TileFetch::Tile(tile) =
+                wyrm.try_fetch_tile("tile", tid).unwrap()
+            else {
+                panic!("tile {tid} not fetched");
+            };
+            let summary = decode_summary(&tile).unwrap();
+            assert_eq!(summary.layers[0].name, "land");
+            let feature = &summary.layers[0].features[0];
+            assert_eq!(feature.geom_type, "polygon");
+            assert_eq!(
+                feature.tags,
+                [("class".into(), TagValue::String("land".into()))]
+            );
+        }
+        // outside of extent
+        let tid = TileId::new(3, 1, 2).unwrap();
+        assert_eq!(wyrm.try_fetch_tile("tile", tid).unwrap(), TileFetch::Empty);
+        std::fs::remove_dir_all(&dir).unwrap();
+        // OSM bounds, without a configured extent
+        const LAND_OSM: &str = "name: land\ngeom_type: polygon\nzoom: 0+\n\
+            source: generated\nshape: extent\ntags: .class=land\n";
+        let generator =
+            ShapeGenerator::new(layer_def(LAND_OSM), osm_bounds).unwrap();
+        assert_eq!(generator.bounds, [-94.0, 44.0, -92.0, 46.0]);
+        assert!(ShapeGenerator::new(layer_def(LAND_OSM), None).is_err());
+    }
+
+    #[test]
+    fn present_not_equal_ways() {
+        let layer = layer_def(
+            "name: roads\ngeom_type: linestring\nzoom: 0+\n\
+            tags: .highway=*!construction|proposed|razed ?name\n",
+        );
+        let ways = [
+            (way(1, &[1, 2], &[("highway", "construction")]), false),
+            (way(2, &[1, 2], &[]), false),
+            (way(3, &[1, 2], &[("name", "Main")]), false),
+            (way(4, &[1, 2], &[("highway", "motorway")]), true),
+        ];
+        let mut stats = layer.match_stats(true);
+        for (obj, matches) in &ways {
+            assert_eq!(layer.check_obj(obj), *matches, "{obj:?}");
+            let counted = layer.check_obj_with(obj, |t| {
+                layer.check_tags_counted(t, &mut stats)
+            });
+            assert_eq!(counted, *matches, "{obj:?}");
+        }
+    }
+
+    /// Make loam file bytes, with or without compacting objects
+    fn loam_bytes(
+        muon: &str,
+        name: &str,
+        objs: &[OsmObj],
+        compact: bool,
+    ) -> Vec<u8> {
+        let loam = std::env::temp_dir().join(format!(
+            "earthwyrm-{}-{name}-{compact}.loam",
+            std::process::id()
+        ));
+        let compact_fn: fn(&LayerDef, ObjMap) -> (CompactMap, CompactDeps) =
+            if compact {
+                compact_objs
+            } else {
+                |_layer: &LayerDef, objs| uncompacted_objs(objs)
+            };
+        let n =
+            with_compact_maker(muon, name, objs, None, compact_fn, |maker| {
+                maker.make_geometry(&loam).unwrap()
+            });
+        assert!(n > 0, "{name}");
+        let bytes = std::fs::read(&loam).unwrap();
+        std::fs::remove_file(&loam).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn compact_identical_loam() {
+        const BUILDING: &str = "name: building\ngeom_type: polygon\n\
+            zoom: 0+\ntags: .building ?name ?osm_id\n";
+        // many tags not referenced by the layer
+        let extra = [
+            ("addr:city", "Saint Paul"),
+            ("addr:street", "Main Street"),
+            ("roof:shape", "flat"),
+            ("source", "survey"),
+        ];
+        let hall: Vec<_> = [("building", "yes"), ("name", "Hall")]
+            .into_iter()
+            .chain(extra)
+            .collect();
+        let mut objs = lake_nodes();
+        objs.extend([
+            node(8, 450_200_000, -930_000_000, &extra),
+            node(9, 450_300_000, -930_000_000, &[]),
+            node(10, 450_300_000, -929_900_000, &[]),
+            way(10, &[1, 2, 3, 4, 1], &hall),
+            way(11, &[5, 6, 7, 5], &extra),
+            way(12, &[8, 9, 10, 8], &[("building", "shed")]),
+            way(13, &[8, 9, 10, 8], &extra),
+            rel(
+                30,
+                &[(13, "outer"), (11, "inner")],
+                &[("type", "multipolygon"), ("building", "yes")],
+            ),
+        ]);
+        assert_eq!(
+            loam_bytes(BUILDING, "compact-building", &objs, true),
+            loam_bytes(BUILDING, "compact-building", &objs, false)
+        );
+    }
+
+    #[test]
+    fn compact_identical_relations() {
+        const WATER: &str = "name: water\ngeom_type: polygon\n\
+            zoom: 0+\ntags: .natural=water ?name ?osm_id\n";
+        let extra = [("source", "survey"), ("note", "check shoreline")];
+        let mut objs = lake_nodes();
+        objs.extend([
+            node(8, 450_200_000, -930_000_000, &extra),
+            node(9, 450_300_000, -930_000_000, &[]),
+            node(10, 450_300_000, -929_900_000, &[]),
+            node(11, 450_200_000, -929_900_000, &[]),
+            node(12, 450_240_000, -929_960_000, &[]),
+            node(13, 450_260_000, -929_960_000, &[]),
+            node(14, 450_260_000, -929_940_000, &[]),
+            way(10, &[1, 2, 3, 4, 1], &extra),
+            way(11, &[5, 6, 7, 5], &extra),
+            // old-style multipolygon: tags on the outer way
+            way(12, &[8, 9, 10, 11, 8], &[("natural", "water")]),
+            way(13, &[12, 13, 14, 12], &[]),
+            // outer ring split into two ways
+            way(14, &[1, 2, 3], &[]),
+            way(15, &[3, 4, 1], &extra),
+            rel(
+                30,
+                &[(10, "outer"), (11, "inner")],
+                &[("type", "multipolygon"), ("natural", "water")],
+            ),
+            rel(
+                31,
+                &[(12, "outer"), (13, "inner")],
+                &[("type", "multipolygon")],
+            ),
+            // outer way shared with relation 30
+            rel(
+                32,
+                &[(10, "outer")],
+                &[
+                    ("type", "multipolygon"),
+                    ("natural", "water"),
+                    ("name", "Lake"),
+                ],
+            ),
+            rel(
+                33,
+                &[(14, "outer"), (15, "outer"), (11, "inner")],
+                &[
+                    ("type", "multipolygon"),
+                    ("natural", "water"),
+                    ("name", "Split Lake"),
+                ],
+            ),
+            // missing member way
+            rel(
+                34,
+                &[(10, "outer"), (99, "inner")],
+                &[("type", "multipolygon"), ("natural", "water")],
+            ),
+            // not a polygon relation type
+            rel(
+                35,
+                &[(12, "outer")],
+                &[("type", "site"), ("natural", "water")],
+            ),
+        ]);
+        assert_eq!(
+            loam_bytes(WATER, "compact-water", &objs, true),
+            loam_bytes(WATER, "compact-water", &objs, false)
+        );
+    }
+
+    #[test]
+    fn compact_identical_routes() {
+        let mut objs: Vec<OsmObj> = (1..=8)
+            .map(|i| {
+                node(i64::from(i), 450_000_000, -930_000_000 + i * 1000, &[])
+            })
+            .collect();
+        let road = [("highway", "primary"), ("name", "Main Street")];
+        objs.extend([
+            way(10, &[1, 2], &road),
+            way(11, &[3, 2], &road),
+            way(12, &[3, 4], &road),
+            way(13, &[5, 4], &road),
+            way(14, &[4, 6], &[]),
+            way(15, &[6, 7, 8], &[("railway", "rail")]),
+            way(16, &[7, 8], &[("public_transport", "platform")]),
+            rel(
+                40,
+                &[(10, ""), (11, ""), (12, "forward"), (13, "backward")],
+                &[("type", "route"), ("route", "bus"), ("ref", "5")],
+            ),
+            rel(
+                41,
+                &[(12, ""), (14, ""), (16, "platform")],
+                &[("type", "route"), ("route", "bus"), ("ref", "7")],
+            ),
+            // route not matching the layer, sharing a way
+            rel(
+                42,
+                &[(14, ""), (15, "")],
+                &[("type", "route"), ("route", "train"), ("ref", "N")],
+            ),
+        ]);
+        assert_eq!(
+            loam_bytes(BUS, "compact-bus", &objs, true),
+            loam_bytes(BUS, "compact-bus", &objs, false)
+        );
+    }
+
+    #[test]
+    fn dig_failure_keeps_loam() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-dig-failure", std::process::id()));
+        let loam_dir = dir.join("loam");
+        std::fs::create_dir_all(&loam_dir).unwrap();
+        let muon = "bind_address: 127.0.0.1:0\nlayer_group: tile\n  \
+            osm: true\n  layer: water\n    geom_type: polygon\n    \
+            zoom: 0+\n    tags: .natural=water ?name\n  layer: roads\n    \
+            geom_type: linestring\n    zoom: 0+\n    tags: .highway\n";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let cfg = cfg
+            .with_loam_dir(loam_dir.clone())
+            .with_cache_dir(dir.join("cache"));
+        for name in ["water.loam", "roads.loam"] {
+            std::fs::write(loam_dir.join(name), name).unwrap();
+        }
+        // a directory in place of a staged loam file fails mid-dig, after
+        // the first layer is written
+        let staging = cfg.staging_dir();
+        let name = staging.file_name().unwrap().to_str().unwrap();
+        let secs: u64 =
+            name.strip_prefix(".staging-").unwrap().parse().unwrap();
+        for s in secs..secs + 3 {
+            let roads = loam_dir.join(format!(".staging-{s}/roads.loam"));
+            std::fs::create_dir_all(roads).unwrap();
+        }
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(
+                10,
+                &[1, 2, 3, 4, 1],
+                &[("natural", "water"), ("name", "Lake")],
+            ),
+            way(11, &[5, 6, 7], &[("highway", "primary")]),
+        ]);
+        let osm = write_pbf("dig-failure", &objs);
+        let res = cfg.extract_osm(&[&osm], DigStage::All, false, true);
+        std::fs::remove_file(&osm).unwrap();
+        assert!(res.is_err());
+        // original files are untouched
+        for name in ["water.loam", "roads.loam"] {
+            assert_eq!(
+                std::fs::read_to_string(loam_dir.join(name)).unwrap(),
+                name
+            );
+        }
+        assert!(!loam_dir.join(MANIFEST).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn vm_hwm() {
+        let status = "Name:\tearthwyrm\nVmPeak:\t  123456 kB\n\
+            VmHWM:\t   20480 kB\nVmRSS:\t   10240 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(20 << 20));
+        assert_eq!(parse_vm_hwm("VmHWM: lots\n"), None);
+        assert_eq!(parse_vm_hwm(""), None);
+    }
+
+    #[test]
+    fn old_style_lake() {
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(
+                10,
+                &[1, 2, 3, 4, 1],
+                &[("natural", "water"), ("name", "Lake")],
+            ),
+            way(11, &[5, 6, 7, 5], &[]),
+            rel(
+                30,
+                &[(10, "outer"), (11, "inner")],
+                &[("type", "multipolygon")],
+            ),
+        ]);
+        // one polygon, from the relation, with outer way tags
+        let polygons = polygon_values("old-style-lake", &objs);
+        assert_eq!(polygons, [values(&["water", "Lake", "30"])]);
+    }
+
+    #[test]
+    fn old_style_outer_tags_agree() {
+        let mut objs = lake_nodes();
+        let water = [("natural", "water"), ("name", "Lake")];
+        objs.extend([
+            way(10, &[1, 2, 3], &water),
+            way(12, &[3, 4, 1], &water),
+            rel(
+                30,
+                &[(10, "outer"), (12, "outer")],
+                &[("type", "multipolygon"), ("source", "survey")],
+            ),
+        ]);
+        let polygons = polygon_values("old-style-agree", &objs);
+        assert_eq!(polygons, [values(&["water", "Lake", "30"])]);
+    }
+
+    #[test]
+    fn skip_log_reasons() {
+        const LAKE: &str = "name: lake\ngeom_type: polygon\nzoom: 0+\n\
+            drop_empty: true\ntags: .natural=water .water=lake ?name\n";
+        let lake = [("natural", "water"), ("water", "lake")];
+        let with = |kv: &[(&'static str, &'static str)]| {
+            let mut kv = kv.to_vec();
+            kv.extend(lake);
+            kv
+        };
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(10, &[1, 2, 3, 4, 1], &with(&[("name", "Big")])),
+            way(11, &[5, 6, 7, 5], &[("natural", "water")]),
+            way(12, &[1, 2, 3], &with(&[("name", "Open")])),
+            way(13, &[5, 6, 7, 5], &with(&[("name", "Flat"), ("area", "no")])),
+            way(14, &[5, 6, 99, 5], &with(&[("name", "Gone")])),
+            way(15, &[5, 6, 7, 5], &lake),
+            way(16, &[1, 2, 3], &[]),
+            rel(
+                30,
+                &[(16, "outer")],
+                &with(&[("type", "multipolygon"), ("name", "Broken")]),
+            ),
+        ]);
+        let skips = polygon_skips(LAKE, "skip-log", &objs);
+        assert_eq!(
+            skips,
+            [
+                "lake\trelation\t30\tbroken_polygon",
+                "lake\tway\t11\tpattern_mismatch",
+                "lake\tway\t12\tunclosed_way",
+                "lake\tway\t13\tnot_area",
+                "lake\tway\t14\tmissing_nodes",
+                "lake\tway\t15\tempty_values",
+            ]
+        );
+    }
+
+    #[test]
+    fn old_style_outer_tags_disagree() {
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(10, &[1, 2, 3, 1], &[("natural", "water"), ("name", "East")]),
+            way(12, &[1, 3, 4, 1], &[("natural", "water"), ("name", "West")]),
+            rel(
+                30,
+                &[(10, "outer"), (12, "outer")],
+                &[("type", "multipolygon")],
+            ),
+        ]);
+        // no relation polygon; outer ways are separate polygons
+        let polygons = polygon_values("old-style-disagree", &objs);
+        assert_eq!(
+            polygons,
+            [
+                values(&["water", "East", "10"]),
+                values(&["water", "West", "12"])
+            ]
+        );
+    }
+
+    #[test]
+    fn closed_way_classify() {
+        let road = layer_def(
+            "name: road\ngeom_type: linestring\nzoom: 0+\n\
+            tags: ?name highway=primary|pedestrian\n",
+        );
+        let area = layer_def(
+            "name: area\ngeom_type: polygon\nzoom: 0+\n\
+            tags: ?name highway=primary|pedestrian\n",
+        );
+        let admin = layer_def(
+            "name: admin\ngeom_type: polygon\nzoom: 0+\n\
+            tags: ?name boundary=administrative\n",
+        );
+        let roundabout = way(
+            10,
+            &[1, 2, 3, 1],
+            &[("highway", "primary"), ("junction", "roundabout")],
+        );
+        let plaza = way(
+            11,
+            &[1, 2, 3, 1],
+            &[("highway", "pedestrian"), ("area", "yes")],
+        );
+        let boundary =
+            way(12, &[1, 2, 3, 1], &[("boundary", "administrative")]);
+        assert!(road.check_obj(&roundabout));
+        assert!(!area.check_obj(&roundabout));
+        assert!(!road.check_obj(&plaza));
+        assert!(area.check_obj(&plaza));
+        assert!(admin.check_obj(&boundary));
+        // area=no overrides area keys
+        let no = way(
+            13,
+            &[1, 2, 3, 1],
+            &[("boundary", "administrative"), ("area", "no")],
+        );
+        assert!(!admin.check_obj(&no));
+        // open ways are never polygons
+        let open =
+            way(14, &[1, 2, 3], &[("highway", "pedestrian"), ("area", "yes")]);
+        assert!(!area.check_obj(&open));
+    }
+
+    /// Water layer salvaging partial polygons
+    const WATER_PARTIAL: &str = "name: water\ngeom_type: polygon\n\
+        zoom: 0+\nallow_partial_polygons: true\n\
+        tags: .natural=water ?partial\n";
+
+    /// Check that two points are nearly equal
+    fn assert_near(a: (f64, f64), b: (f64, f64)) {
+        assert!((a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3, "{a:?}");
+    }
+
+    #[test]
+    fn salvage_inner_chain() {
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(10, &[1, 2, 3, 4, 1], &[]),
+            // broken inner way: 7 .. 5 is missing
+            way(11, &[5, 6, 7], &[]),
+            rel(
+                30,
+                &[(10, "outer"), (11, "inner")],
+                &[("type", "multipolygon"), ("natural", "water")],
+            ),
+        ]);
+        with_maker(WATER_PARTIAL, "salvage-inner", &objs, None, |maker| {
+            let rel = relation(maker, 30);
+            assert!(maker.assemble_rings(rel, false).is_none());
+            let rings = maker.assemble_rings(rel, true).unwrap();
+            assert_eq!(rings.len(), 2);
+            let (outer, ring) = &rings[0];
+            assert!(*outer);
+            assert_eq!(ring.len(), 5);
+            // the closed chain stays an inner ring (a hole)
+            let (outer, ring) = &rings[1];
+            assert!(!*outer);
+            assert_eq!(ring.len(), 4);
+            assert_eq!(ring.first(), ring.last());
+            let polygon = maker.rel_multipolygon(rel).unwrap();
+            assert_eq!(polygon.data(), &values(&["water", "yes"]));
+        });
+    }
+
+    #[test]
+    fn salvage_along_bounds() {
+        let mut objs = vec![
+            // beyond west edge of extract
+            node(1, 455_000_000, -930_100_000, &[]),
+            node(2, 455_000_000, -925_000_000, &[]),
+            // beyond south edge of extract
+            node(3, 449_900_000, -925_000_000, &[]),
+        ];
+        objs.extend([
+            way(10, &[1, 2, 3], &[]),
+            rel(
+                30,
+                &[(10, "outer")],
+                &[("type", "multipolygon"), ("natural", "water")],
+            ),
+        ]);
+        let bounds = [-93.0, 45.0, -92.0, 46.0];
+        let corner = web_mercator(-93.0, 45.0);
+        with_maker(WATER_PARTIAL, "salvage-edge", &objs, Some(bounds), |m| {
+            let rings = m.assemble_rings(relation(m, 30), true).unwrap();
+            let [(outer, ring)] = &rings[..] else {
+                panic!("rings: {rings:?}");
+            };
+            assert!(*outer);
+            // closed along the edges through the south-west corner, not
+            // around the rest of the extent
+            assert_eq!(ring.len(), 7);
+            assert_near(ring[3], web_mercator(-92.5, 45.0));
+            assert_near(ring[4], corner);
+            assert_near(ring[5], web_mercator(-93.0, 45.5));
+            assert_eq!(ring[0], ring[6]);
+        });
+        // without bounds, closed with a straight segment
+        with_maker(WATER_PARTIAL, "salvage-line", &objs, None, |m| {
+            let rings = m.assemble_rings(relation(m, 30), true).unwrap();
+            assert_eq!(rings.len(), 1);
+            assert_eq!(rings[0].1.len(), 4);
+        });
+    }
+
+    #[test]
+    fn close_chain_perimeter() {
+        let b = [0.0, 0.0, 10.0, 10.0];
+        assert_eq!(perimeter_pos(b, (5.0, 0.0)), Some(0.5));
+        assert_eq!(perimeter_pos(b, (10.0, 5.0)), Some(1.5));
+        assert_eq!(perimeter_pos(b, (5.0, 12.0)), Some(2.5));
+        assert_eq!(perimeter_pos(b, (-1.0, 5.0)), Some(3.5));
+        assert_eq!(perimeter_pos(b, (5.0, 5.0)), None);
+        // chain from north edge to south edge, nearer the east
+        let pts = vec![(8.0, 10.0), (6.0, 5.0), (8.0, 0.0)];
+        let ring = close_chain(pts.clone(), Some(b));
+        assert_eq!(
+            ring,
+            [
+                (8.0, 10.0),
+                (6.0, 5.0),
+                (8.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 10.0),
+                (8.0, 10.0)
+            ]
+        );
+        // end in the middle of the extent
+        let pts = vec![(8.0, 10.0), (6.0, 5.0)];
+        let ring = close_chain(pts, Some(b));
+        assert_eq!(ring, [(8.0, 10.0), (6.0, 5.0), (8.0, 10.0)]);
+    }
+
+    /// Connect ways on matching node Ids (previous incremental assembly)
+    fn old_connect_ways(ways: &mut Vec<Vec<NodeId>>) -> bool {
+        let len = ways.len();
+        for i in 0..len - 1 {
+            let (a0, a1) = end_points(&ways[i]);
+            for j in i + 1..len {
+                let (b0, b1) = end_points(&ways[j]);
+                if a0 == b0 || a0 == b1 || a1 == b0 || a1 == b1 {
+                    let mut way = ways.swap_remove(j);
+                    if a1 != b0 && a1 != b1 {
+                        ways[i].reverse();
+                    }
+                    let (_a0, a1) = end_points(&ways[i]);
+                    if b1 == a1 {
+                        way.reverse();
+                    }
+                    ways[i].pop();
+                    ways[i].extend(way);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Find a ring in a `Vec` of ways (previous incremental assembly)
+    fn old_find_ring(ways: &mut Vec<Vec<NodeId>>) -> Option<Vec<NodeId>> {
+        let i = ways.iter().position(|w| {
+            let (w0, w1) = end_points(w);
+            w0 == w1
+        })?;
+        Some(ways.swap_remove(i))
+    }
+
+    /// Assemble rings the previous way, adding one member at a time
+    fn old_assemble(
+        members: Vec<(bool, Vec<NodeId>)>,
+    ) -> Vec<(bool, Vec<NodeId>)> {
+        let mut ways = Vec::new();
+        let mut rings = Vec::new();
+        for (outer, nodes) in members {
+            ways.push(nodes);
+            while ways.len() > 1 {
+                if !old_connect_ways(&mut ways) {
+                    break;
+                }
+            }
+            while let Some(ring) = old_find_ring(&mut ways) {
+                rings.push((outer, ring));
+            }
+        }
+        rings
+    }
+
+    /// Normalize a ring for rotation and direction
+    fn normal_ring(ring: &[NodeId]) -> Vec<i64> {
+        let mut ids: Vec<i64> = ring[1..].iter().map(|n| n.0).collect();
+        let rotate = |ids: &mut Vec<i64>| {
+            let pos = (0..ids.len()).min_by_key(|p| ids[*p]).unwrap();
+            ids.rotate_left(pos);
+        };
+        rotate(&mut ids);
+        let mut rev: Vec<i64> = ids.iter().rev().copied().collect();
+        rotate(&mut rev);
+        ids.min(rev)
+    }
+
+    /// Compare stitched rings with previous incremental assembly
+    fn assert_same_rings(members: &[(bool, &[i64])]) {
+        let members: Vec<(bool, Vec<NodeId>)> = members
+            .iter()
+            .map(|(o, w)| (*o, w.iter().map(|n| NodeId(*n)).collect()))
+            .collect();
+        let normal = |rings: Vec<(bool, Vec<NodeId>)>| {
+            rings
+                .into_iter()
+                .map(|(o, r)| (o, normal_ring(&r)))
+                .collect::<Vec<_>>()
+        };
+        let old = normal(old_assemble(members.clone()));
+        let (rings, chains) = stitch_rings(members);
+        assert!(chains.is_empty());
+        assert_eq!(normal(rings), old);
+    }
+
+    #[test]
+    fn stitch_matches_old_assembly() {
+        // outer square in two ways, inner triangle in two ways
+        assert_same_rings(&[
+            (true, &[1, 2, 3]),
+            (true, &[1, 4, 3]),
+            (false, &[5, 6, 7]),
+            (false, &[5, 7]),
+        ]);
+        // members out of order, with mixed directions
+        assert_same_rings(&[
+            (true, &[1, 2]),
+            (true, &[3, 1]),
+            (true, &[2, 3]),
+            (false, &[4, 5, 6, 4]),
+        ]);
+        // closed inner way before the outer ring closes
+        assert_same_rings(&[
+            (true, &[1, 2, 3]),
+            (false, &[7, 8, 9, 7]),
+            (true, &[3, 4, 1]),
+        ]);
+    }
+
+    #[test]
+    fn scan_matches_sorted() {
+        let water = [("natural", "water")];
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(14, &[1, 2, 3, 1], &water),
+            way(12, &[1, 2, 4, 1], &water),
+            way(11, &[5, 6, 7, 5], &[]),
+            way(13, &[2, 3, 4, 2], &water),
+        ]);
+        let path = write_pbf("scan-sorted", &objs);
+        let layer = layer_def(WATER);
+        let scan = |limit| {
+            let mut ids = Vec::new();
+            let n = layer.scan_matches(&path, limit, |m| ids.push(m.id));
+            (n.unwrap(), ids)
+        };
+        assert_eq!(scan(10), (3, vec![12, 13, 14]));
+        assert_eq!(scan(2), (3, vec![12, 13]));
+        assert_eq!(scan(0), (3, vec![]));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Make a square ring, counter-clockwise or clockwise
+    fn square(x: f64, y: f64, w: f64, ccw: bool) -> Ring {
+        let mut pts =
+            vec![(x, y), (x + w, y), (x + w, y + w), (x, y + w), (x, y)];
+        if !ccw {
+            pts.reverse();
+        }
+        (true, pts)
+    }
+
+    #[test]
+    fn centroid_winding() {
+        let c = rings_centroid(&[square(0.0, 0.0, 2.0, true)]).unwrap();
+        assert_eq!(c, (1.0, 1.0));
+        let c = rings_centroid(&[square(0.0, 0.0, 2.0, false)]).unwrap();
+        assert_eq!(c, (1.0, 1.0));
+        // opposite windings don't cancel out
+        let rings = [square(0.0, 0.0, 2.0, true), square(4.0, 0.0, 2.0, false)];
+        assert_eq!(rings_centroid(&rings).unwrap(), (3.0, 1.0));
+        // weighted by area
+        let rings = [square(0.0, 0.0, 2.0, false), square(8.0, 0.0, 4.0, true)];
+        assert_eq!(rings_centroid(&rings).unwrap(), (8.2, 1.8));
+        // inner rings are ignored
+        let mut inner = square(0.5, 0.5, 1.0, false);
+        inner.0 = false;
+        let rings = [square(0.0, 0.0, 2.0, true), inner];
+        assert_eq!(rings_centroid(&rings).unwrap(), (1.0, 1.0));
+        assert_eq!(rings_centroid(&[]), None);
+    }
+
+    /// Make node IDs
+    fn node_ids(ids: &[i64]) -> Vec<NodeId> {
+        ids.iter().map(|id| NodeId(*id)).collect()
+    }
+
+    #[test]
+    fn order_route_directed() {
+        let members = vec![
+            (false, node_ids(&[1, 2])),
+            // drawn backwards, so it is flipped
+            (false, node_ids(&[3, 2])),
+            (true, node_ids(&[3, 4])),
+            // `backward` member, already reversed
+            (true, node_ids(&[4, 5])),
+            // directed member which doesn't connect is never flipped
+            (true, node_ids(&[7, 6])),
+        ];
+        let chains = order_route(members);
+        assert_eq!(chains, [node_ids(&[1, 2, 3, 4, 5]), node_ids(&[7, 6])]);
+        // a directed member against the chain starts a new one
+        let members =
+            vec![(false, node_ids(&[1, 2])), (true, node_ids(&[3, 2]))];
+        let chains = order_route(members);
+        assert_eq!(chains, [node_ids(&[1, 2]), node_ids(&[3, 2])]);
+    }
+
+    #[test]
+    fn layering_bridge() {
+        const ROADS: &str = "name: roads\ngeom_type: linestring\n\
+            zoom: 0+\ntags: .highway ?name @layering ?ref\n";
+        let mut objs: Vec<OsmObj> = (1..=4)
+            .map(|i| {
+                node(i64::from(i), 450_000_000, -930_000_000 + i * 1000, &[])
+            })
+            .collect();
+        objs.extend([
+            way(
+                10,
+                &[1, 2],
+                &[
+                    ("highway", "primary"),
+                    ("name", "High Bridge"),
+                    ("bridge", "yes"),
+                    ("layer", "1"),
+                ],
+            ),
+            way(11, &[2, 3], &[("highway", "primary"), ("layer", "up")]),
+            way(12, &[3, 4], &[("highway", "primary"), ("tunnel", "yes")]),
+        ]);
+        with_maker(ROADS, "layering", &objs, None, |maker| {
+            // `@layering` is expanded in place, in a defined order
+            let tags: Vec<_> = maker.layer.tags().collect();
+            assert_eq!(
+                tags,
+                ["highway", "name", "layer", "bridge", "tunnel", "ref"]
+            );
+            let values = |id| {
+                let way = maker.objs[&OsmId::Way(WayId(id))].way().unwrap();
+                let lines = maker.way_linestring(way);
+                assert_eq!(lines.len(), 1);
+                lines[0].data().clone()
+            };
+            let val = |v: &str| Some(v.to_string());
+            assert_eq!(
+                values(10),
+                [
+                    val("primary"),
+                    val("High Bridge"),
+                    val("1"),
+                    val("yes"),
+                    None,
+                    None
+                ]
+            );
+            // unparseable and missing layers are 0
+            assert_eq!(
+                values(11),
+                [val("primary"), None, val("0"), None, None, None]
+            );
+            assert_eq!(
+                values(12),
+                [val("primary"), None, val("0"), None, val("yes"), None]
+            );
+        });
+    }
+
+    /// Bus route layer
+    const BUS: &str =
+        "name: bus\ngeom_type: linestring\nzoom: 0+\ntags: .route=bus ?ref\n";
+
+    #[test]
+    fn bus_routes_shared_way() {
+        let mut objs: Vec<OsmObj> = (1..=6)
+            .map(|i| {
+                node(i64::from(i), 450_000_000, -930_000_000 + i * 1000, &[])
+            })
+            .collect();
+        let road = [("highway", "primary")];
+        objs.extend([
+            way(10, &[1, 2], &road),
+            way(11, &[3, 2], &road),
+            way(12, &[3, 4], &road),
+            way(13, &[5, 4], &road),
+            way(14, &[4, 6], &road),
+            rel(
+                40,
+                &[(10, ""), (11, ""), (12, "forward"), (13, "backward")],
+                &[("type", "route"), ("route", "bus"), ("ref", "5")],
+            ),
+            // way 12 is shared by both routes
+            rel(
+                41,
+                &[(12, ""), (14, "")],
+                &[("type", "route"), ("route", "bus"), ("ref", "7")],
+            ),
+        ]);
+        with_maker(BUS, "bus-routes", &objs, None, |maker| {
+            let route = |id, nodes: &[i64]| {
+                let rel = relation(maker, id);
+                let lines = maker.rel_linestring(rel);
+                assert_eq!(lines.len(), 1);
+                let pts: Vec<Vec<(f64, f64)>> = lines[0]
+                    .iter()
+                    .map(|line| {
+                        let mut pts = Vec::new();
+                        for seg in line.segments() {
+                            if pts.is_empty() {
+                                pts.push((seg.p0.x, seg.p0.y));
+                            }
+                            pts.push((seg.p1.x, seg.p1.y));
+                        }
+                        pts
+                    })
+                    .collect();
+                let owner = OsmId::Relation(RelationId(id));
+                let expected = maker.lookup_nodes(owner, &node_ids(nodes));
+                assert_eq!(pts, [expected]);
+                lines[0].data().clone()
+            };
+            // one named chain for each route, in route direction
+            assert_eq!(route(40, &[1, 2, 3, 4, 5]), values(&["bus", "5"]));
+            assert_eq!(route(41, &[3, 4, 6]), values(&["bus", "7"]));
+        });
+    }
+
+    #[test]
+    fn stitch_mixed_roles() {
+        // ring closed by an outer member takes the outer role
+        let members = [(false, &[1, 2, 3][..]), (true, &[3, 4, 1][..])];
+        assert_same_rings(&members);
+        // ... and by an inner member, the inner role
+        let members = [
+            (true, &[10, 11][..]),
+            (false, &[11, 12, 10][..]),
+            (true, &[1, 2, 3, 1][..]),
+        ];
+        assert_same_rings(&members);
+        let members: Vec<(bool, Vec<NodeId>)> = members
+            .iter()
+            .map(|(o, w)| (*o, w.iter().map(|n| NodeId(*n)).collect()))
+            .collect();
+        let (rings, _chains) = stitch_rings(members);
+        let roles: Vec<bool> = rings.iter().map(|(o, _)| *o).collect();
+        assert_eq!(roles, [false, true]);
+    }
+
+    /// Forest and meadow polygon layer
+    const FOREST: &str = "name: forest\ngeom_type: polygon\nzoom: 0+\n\
+        tags: .landuse=forest|meadow ?osm_id\n";
+
+    /// Forest layer, keeping member ways
+    const FOREST_KEEP: &str = "name: forest\ngeom_type: polygon\nzoom: 0+\n\
+        dedupe_members: false\ntags: .landuse=forest|meadow ?osm_id\n";
+
+    /// Forest multipolygon (30) with tagged outer way (10)
+    fn forest() -> Vec<OsmObj> {
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(10, &[1, 2, 3, 4, 1], &[("landuse", "forest")]),
+            way(11, &[5, 6, 7, 5], &[]),
+            rel(
+                30,
+                &[(10, "outer"), (11, "inner")],
+                &[("type", "multipolygon"), ("landuse", "forest")],
+            ),
+        ]);
+        objs
+    }
+
+    #[test]
+    fn forest_member_way() {
+        let polygons = layer_polygons(FOREST, "forest", &forest());
+        assert_eq!(polygons, [values(&["forest", "30"])]);
+        // both are kept when not deduplicating
+        let polygons = layer_polygons(FOREST_KEEP, "forest-keep", &forest());
+        assert_eq!(
+            polygons,
+            [values(&["forest", "10"]), values(&["forest", "30"])]
+        );
+    }
+
+    #[test]
+    fn forest_meadow_inner() {
+        let mut objs = forest();
+        objs.retain(|obj| obj.id() != OsmId::Way(WayId(11)));
+        objs.push(way(11, &[5, 6, 7, 5], &[("landuse", "meadow")]));
+        // inner meadow is a separate feature, not a duplicate
+        let polygons = layer_polygons(FOREST, "forest-meadow", &objs);
+        assert_eq!(
+            polygons,
+            [values(&["forest", "30"]), values(&["meadow", "11"])]
+        );
+    }
+
+    #[test]
+    fn old_style_lake_keep_members() {
+        let mut objs = lake_nodes();
+        objs.extend([
+            way(10, &[1, 2, 3, 4, 1], &[("landuse", "forest")]),
+            way(11, &[5, 6, 7, 5], &[]),
+            rel(
+                30,
+                &[(10, "outer"), (11, "inner")],
+                &[("type", "multipolygon")],
+            ),
+        ]);
+        // outer way of an old-style multipolygon is never a duplicate
+        let polygons = layer_polygons(FOREST_KEEP, "old-style-keep", &objs);
+        assert_eq!(polygons, [values(&["forest", "30"])]);
+    }
+
+    /// Point layer for crossings
+    const CROSSING: &str = "name: crossing\ngeom_type: point\nzoom: 0+\n\
+        tags: .highway=crossing ?name\n";
+
+    #[test]
+    fn extract_hash_match_rules() {
+        let hash = layer_def(WATER).extract_hash(None);
+        // included tags only
+        let include = layer_def(
+            "name: water\ngeom_type: polygon\nzoom: 0+\n\
+            tags: .natural=water ?name ?ref $ele\n",
+        );
+        assert_eq!(include.extract_hash(None), hash);
+        // match rules
+        let matched = layer_def(
+            "name: water\ngeom_type: polygon\nzoom: 0+\n\
+            tags: .natural=water|wetland ?name\n",
+        );
+        assert_ne!(matched.extract_hash(None), hash);
+        assert_ne!(layer_def(WATER).extract_hash(Some("lake")), hash);
+    }
+
+    #[test]
+    fn source_unchanged() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-source", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let layer = layer_def(WATER);
+        let source = source_hash(&layer, "rules", "a.osm.pbf", None);
+        assert_eq!(unchanged_source(&layer, &dir, &source).unwrap(), None);
+        let mut meta = LoamMeta::from(&layer).with_source(source.clone());
+        meta.features = 5;
+        meta.write(&dir.join("water.loam")).unwrap();
+        assert_eq!(unchanged_source(&layer, &dir, &source).unwrap(), Some(5));
+        // different OSM file
+        let other = source_hash(&layer, "rules", "b.osm.pbf", None);
+        assert_eq!(unchanged_source(&layer, &dir, &other).unwrap(), None);
+        // DEM only matters with an `ele` tag
+        let dem = Dem::default();
+        assert_eq!(
+            source_hash(&layer, "rules", "a.osm.pbf", Some(&dem)),
+            source
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exclude_keeps_dependencies() {
+        let crossing = [("highway", "crossing")];
+        let path = write_pbf(
+            "exclude-deps",
+            &[
+                node(1, 450_000_000, -930_000_000, &[]),
+                node(2, 450_010_000, -930_000_000, &crossing),
+                node(3, 450_020_000, -930_000_000, &[]),
+                // crossing not on any way
+                node(4, 450_030_000, -930_000_000, &crossing),
+                way(10, &[1, 2, 3], &[("highway", "footway")]),
+            ],
+        );
+        let crossings = layer_def(
+            "name: crossings\ngeom_type: point\nzoom: 0+\n\
+            tags: .highway=crossing\n",
+        );
+        let paths = layer_def(
+            "name: paths\ngeom_type: linestring\nzoom: 0+\ntags: .highway\n\
+            exclude_if_matches: crossings\n",
+        );
+        let extract = |layer: &LayerDef| {
+            let mut extractors = vec![OsmExtractor::new(&path).unwrap()];
+            let mut skips = SkipLog::default();
+            let policy = IdPolicy::default();
+            extract_merged(&mut extractors, layer, &mut skips, policy, false)
+                .unwrap()
+        };
+        let claimed = HashMap::from([(
+            "crossings".to_string(),
+            matching_ids(&crossings, &extract(&crossings)),
+        )]);
+        let mut objs = extract(&paths);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(objs.len(), 5);
+        // node 2 is a way node, so it is kept
+        assert_eq!(exclude_claimed(&paths, &mut objs, &claimed), 1);
+        let ids: Vec<_> = objs.keys().copied().collect();
+        assert_eq!(
+            ids,
+            [
+                OsmId::Node(NodeId(1)),
+                OsmId::Node(NodeId(2)),
+                OsmId::Node(NodeId(3)),
+                OsmId::Way(WayId(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_nodes_matches_deps() {
+        let crossing = [("highway", "crossing"), ("name", "Main")];
+        let path = write_pbf(
+            "nodes",
+            &[
+                node(1, 450_000_000, -930_000_000, &crossing),
+                node(2, 450_010_000, -930_000_000, &[("highway", "crossing")]),
+                node(3, 450_020_000, -930_000_000, &[("highway", "stop")]),
+                node(4, 450_030_000, -930_000_000, &[]),
+                node(5, 450_040_000, -930_000_000, &crossing),
+                way(10, &[2, 3, 4], &[("highway", "residential")]),
+            ],
+        );
+        let layer = layer_def(CROSSING);
+        assert!(layer.member_role().is_none());
+        let mut extractor = OsmExtractor::new(&path).unwrap();
+        let mut skips = SkipLog::default();
+        let mut stats = layer.match_stats(false);
+        let fast = extractor
+            .extract_layer(&layer, &mut skips, &mut stats)
+            .unwrap();
+        let full = extractor.extract_objs(|obj| layer.check_obj(obj)).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(fast, full);
+        // tagged way member (2) included; way and its other nodes excluded
+        let ids: Vec<_> = fast.keys().copied().collect();
+        assert_eq!(ids, [1, 2, 5].map(|id| OsmId::Node(NodeId(id))));
+    }
+}
@@ -0,0 +1,278 @@
+// osm_xml.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::{Error, Result};
+use crate::manifest::OsmHeader;
+use bzip2::read::BzDecoder;
+use osmpbfreader::objects::{Node, Ref, Relation, Tags, Way};
+use osmpbfreader::{NodeId, OsmId, OsmObj, RelationId, WayId};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::path::Path;
+
+/// OSM object map
+type ObjMap = BTreeMap<OsmId, OsmObj>;
+
+/// Start of bzip2-compressed file
+const BZ2_MAGIC: &[u8] = b"BZh";
+
+/// Objects read from an OSM XML file
+pub struct OsmXml {
+    /// File header (`bounds` and `generator`)
+    pub header: OsmHeader,
+
+    /// All objects in the file
+    pub objs: ObjMap,
+}
+
+/// Result of parsing one element
+type ParseResult<T> = std::result::Result<T, String>;
+
+/// Make an XML error
+fn xml_err<E: std::fmt::Display>(path: &Path, e: E) -> Error {
+    Error::OsmXml(format!("{path:?}: {e}"))
+}
+
+/// Get attributes of an XML element
+fn attributes(e: &BytesStart) -> ParseResult<HashMap<String, String>> {
+    let mut attrs = HashMap::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        let value = attr.unescape_value().map_err(|e| e.to_string())?;
+        attrs.insert(key, value.into_owned());
+    }
+    Ok(attrs)
+}
+
+/// Parse a required attribute
+fn attr<T: std::str::FromStr>(
+    attrs: &HashMap<String, String>,
+    name: &str,
+) -> ParseResult<T> {
+    attrs
+        .get(name)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("missing/invalid `{name}`"))
+}
+
+/// Parse a latitude / longitude attribute, in decimicro degrees
+fn decimicro(attrs: &HashMap<String, String>, name: &str) -> ParseResult<i32> {
+    let deg: f64 = attr(attrs, name)?;
+    Ok((deg * 1e7).round() as i32)
+}
+
+/// Check if an object is deleted (JOSM `action` or `visible` attributes)
+fn is_deleted(attrs: &HashMap<String, String>) -> bool {
+    attrs.get("action").is_some_and(|a| a == "delete")
+        || attrs.get("visible").is_some_and(|v| v == "false")
+}
+
+/// Make a header from `osm` or `bounds` element attributes
+fn read_header(
+    header: &mut OsmHeader,
+    name: &[u8],
+    attrs: &HashMap<String, String>,
+) {
+    match name {
+        b"osm" => {
+            if let Some(generator) = attrs.get("generator") {
+                header.writing_program.clone_from(generator);
+            }
+        }
+        b"bounds" => {
+            let bounds = ["minlon", "minlat", "maxlon", "maxlat"]
+                .map(|k| attrs.get(k).map(String::as_str).unwrap_or("0"));
+            header.bbox = bounds.join(" ");
+        }
+        _ => (),
+    }
+}
+
+/// Start an object element
+fn start_obj(
+    name: &[u8],
+    attrs: &HashMap<String, String>,
+) -> ParseResult<Option<OsmObj>> {
+    let obj = match name {
+        b"node" => OsmObj::Node(Node {
+            id: NodeId(attr(attrs, "id")?),
+            tags: Tags::new(),
+            decimicro_lat: decimicro(attrs, "lat")?,
+            decimicro_lon: decimicro(attrs, "lon")?,
+        }),
+        b"way" => OsmObj::Way(Way {
+            id: WayId(attr(attrs, "id")?),
+            tags: Tags::new(),
+            nodes: Vec::new(),
+        }),
+        b"relation" => OsmObj::Relation(Relation {
+            id: RelationId(attr(attrs, "id")?),
+            tags: Tags::new(),
+            refs: Vec::new(),
+        }),
+        _ => return Ok(None),
+    };
+    Ok(Some(obj))
+}
+
+/// Add a child element (`tag`, `nd` or `member`) to an object
+fn add_child(
+    obj: &mut OsmObj,
+    name: &[u8],
+    attrs: &HashMap<String, String>,
+) -> ParseResult<()> {
+    match (name, obj) {
+        (b"tag", obj) => {
+            let key: String = attr(attrs, "k")?;
+            let value: String = attr(attrs, "v")?;
+            let tags = match obj {
+                OsmObj::Node(node) => &mut node.tags,
+                OsmObj::Way(way) => &mut way.tags,
+                OsmObj::Relation(rel) => &mut rel.tags,
+            };
+            tags.insert(key.into(), value.into());
+        }
+        (b"nd", OsmObj::Way(way)) => {
+            way.nodes.push(NodeId(attr(attrs, "ref")?))
+        }
+        (b"member", OsmObj::Relation(rel)) => {
+            let id = attr(attrs, "ref")?;
+            let member = match attrs.get("type").map(String::as_str) {
+                Some("node") => OsmId::Node(NodeId(id)),
+                Some("way") => OsmId::Way(WayId(id)),
+                Some("relation") => OsmId::Relation(RelationId(id)),
+                _ => return Err("invalid member type".into()),
+            };
+            let role = attrs.get("role").map(String::as_str).unwrap_or("");
+            rel.refs.push(Ref {
+                member,
+                role: role.into(),
+            });
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+impl OsmXml {
+    /// Read an OSM XML file (`.osm` or `.osm.bz2`)
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut magic = [0; BZ2_MAGIC.len()];
+        let bz2 = file.read_exact(&mut magic).is_ok() && magic == BZ2_MAGIC;
+        file.rewind()?;
+        if bz2 {
+            Self::read_from(path, BufReader::new(BzDecoder::new(file)))
+        } else {
+            Self::read_from(path, BufReader::new(file))
+        }
+    }
+
+    /// Read objects from a stream of OSM XML
+    fn read_from<R: BufRead>(path: &Path, reader: R) -> Result<Self> {
+        let mut reader = Reader::from_reader(reader);
+        let mut buf = Vec::new();
+        let mut header = OsmHeader::default();
+        let mut objs = ObjMap::new();
+        let mut current: Option<(OsmObj, bool)> = None;
+        loop {
+            let (e, empty) = match reader.read_event_into(&mut buf) {
+                Ok(Event::Start(e)) => (e, false),
+                Ok(Event::Empty(e)) => (e, true),
+                Ok(Event::End(e)) => {
+                    if let Some((obj, deleted)) = current.take() {
+                        if matches!(
+                            e.name().as_ref(),
+                            b"node" | b"way" | b"relation"
+                        ) {
+                            if !deleted {
+                                objs.insert(obj.id(), obj);
+                            }
+                        } else {
+                            current = Some((obj, deleted));
+                        }
+                    }
+                    buf.clear();
+                    continue;
+                }
+                Ok(Event::Eof) => break,
+                Ok(_) => {
+                    buf.clear();
+                    continue;
+                }
+                Err(e) => {
+                    return Err(xml_err(
+                        path,
+                        format!("{e} (at {})", reader.buffer_position()),
+                    ))
+                }
+            };
+            let name = e.name();
+            let attrs = attributes(&e).map_err(|e| xml_err(path, e))?;
+            match &mut current {
+                Some((obj, _)) => add_child(obj, name.as_ref(), &attrs)
+                    .map_err(|e| xml_err(path, e))?,
+                None => {
+                    read_header(&mut header, name.as_ref(), &attrs);
+                    let obj = start_obj(name.as_ref(), &attrs)
+                        .map_err(|e| xml_err(path, e))?;
+                    if let Some(obj) = obj {
+                        let deleted = is_deleted(&attrs);
+                        if empty {
+                            if !deleted {
+                                objs.insert(obj.id(), obj);
+                            }
+                        } else {
+                            current = Some((obj, deleted));
+                        }
+                    }
+                }
+            }
+            buf.clear();
+        }
+        log::debug!("read {} objects from {path:?}", objs.len());
+        Ok(OsmXml { header, objs })
+    }
+
+    /// Get objects matching a predicate, with their dependencies.
+    ///
+    /// Dependencies are way nodes and relation members (recursively), the
+    /// same as `OsmPbfReader::get_objs_and_deps`.
+    pub fn objs_and_deps<F>(&self, mut pred: F) -> ObjMap
+    where
+        F: FnMut(&OsmObj) -> bool,
+    {
+        let mut pending: Vec<OsmId> = self
+            .objs
+            .values()
+            .filter(|obj| pred(obj))
+            .map(OsmObj::id)
+            .collect();
+        let mut objs = ObjMap::new();
+        while let Some(id) = pending.pop() {
+            if objs.contains_key(&id) {
+                continue;
+            }
+            // objects outside the extract are missing
+            let Some(obj) = self.objs.get(&id) else {
+                continue;
+            };
+            match obj {
+                OsmObj::Node(_) => (),
+                OsmObj::Way(way) => {
+                    pending.extend(way.nodes.iter().map(|n| OsmId::Node(*n)))
+                }
+                OsmObj::Relation(rel) => {
+                    pending.extend(rel.refs.iter().map(|rf| rf.member))
+                }
+            }
+            objs.insert(id, obj.clone());
+        }
+        objs
+    }
+}
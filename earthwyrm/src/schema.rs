@@ -0,0 +1,148 @@
+// schema.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::config::{
+    EMPTY_TILE_VALUES, GRID_VALUES, GROUP_FIELDS, HTTP_FIELDS,
+    ID_POLICY_VALUES, LAYER_FIELDS, LOG_FORMAT_VALUES, STYLE_FIELDS,
+    WYRM_FIELDS,
+};
+use crate::layer::{
+    pattern_schema, EXTENT_SHAPE, GEOM_TYPES, INTERSECTIONS, MEMBER_ROLES,
+    PRECISIONS,
+};
+use serde_derive::Serialize;
+
+/// Description of a configuration field
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldSchema {
+    /// Field name
+    pub name: String,
+
+    /// Value type
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// Field description
+    pub description: String,
+
+    /// Accepted values (enum fields only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+}
+
+/// Description of a syntax token
+#[derive(Clone, Debug, Serialize)]
+pub struct SyntaxSchema {
+    /// Token
+    pub token: String,
+
+    /// Token description
+    pub description: String,
+}
+
+/// Tag pattern grammar
+#[derive(Clone, Debug, Serialize)]
+pub struct PatternSchema {
+    /// Pattern form
+    pub form: String,
+
+    /// Rule prefixes
+    pub prefixes: Vec<SyntaxSchema>,
+
+    /// Equality / comparison operators
+    pub operators: Vec<SyntaxSchema>,
+
+    /// Special values
+    pub values: Vec<SyntaxSchema>,
+
+    /// Date expressions, for comparison operators
+    pub dates: Vec<SyntaxSchema>,
+
+    /// Sint tags computed at tile-encode time
+    pub synthetic_tags: Vec<String>,
+
+    /// Shorthand tokens
+    pub shorthands: Vec<SyntaxSchema>,
+}
+
+/// Description of all configuration fields, by section
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigSchema {
+    /// Top-level fields
+    pub config: Vec<FieldSchema>,
+
+    /// `http` fields
+    pub http: Vec<FieldSchema>,
+
+    /// `layer_group` fields
+    pub layer_group: Vec<FieldSchema>,
+
+    /// `layer` fields (within `layer_group`)
+    pub layer: Vec<FieldSchema>,
+
+    /// `style` fields (within `layer`)
+    pub style: Vec<FieldSchema>,
+
+    /// Tag pattern grammar (`tags`, `filter` and `split_by`)
+    pub tag_pattern: PatternSchema,
+}
+
+impl SyntaxSchema {
+    /// Create a syntax token description
+    pub(crate) fn new(token: impl Into<String>, description: &str) -> Self {
+        SyntaxSchema {
+            token: token.into(),
+            description: description.to_string(),
+        }
+    }
+}
+
+/// Get accepted values of an enum field
+fn field_values(section: &str, name: &str) -> Vec<String> {
+    let values: Vec<&str> = match (section, name) {
+        ("config", "empty_tile") => EMPTY_TILE_VALUES.to_vec(),
+        ("config", "log_format") => LOG_FORMAT_VALUES.to_vec(),
+        ("config", "osm_id_policy") => ID_POLICY_VALUES.to_vec(),
+        ("layer_group", "grid") => GRID_VALUES.to_vec(),
+        ("layer", "geom_type") => GEOM_TYPES.iter().map(|(n, _)| *n).collect(),
+        ("layer", "member_role") => {
+            MEMBER_ROLES.iter().map(|r| r.as_str()).collect()
+        }
+        ("layer", "precision") => {
+            PRECISIONS.iter().map(|p| p.as_str()).collect()
+        }
+        ("layer", "derive") => vec![INTERSECTIONS],
+        ("layer", "shape") => vec![EXTENT_SHAPE],
+        _ => Vec::new(),
+    };
+    values.into_iter().map(str::to_string).collect()
+}
+
+/// Describe the fields of one section
+fn section_fields(
+    section: &str,
+    fields: &[(&str, &str, &str)],
+) -> Vec<FieldSchema> {
+    fields
+        .iter()
+        .map(|(name, kind, description)| FieldSchema {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            description: description.to_string(),
+            values: field_values(section, name),
+        })
+        .collect()
+}
+
+/// Build the configuration schema
+pub(crate) fn config_schema() -> ConfigSchema {
+    ConfigSchema {
+        config: section_fields("config", WYRM_FIELDS),
+        http: section_fields("http", HTTP_FIELDS),
+        layer_group: section_fields("layer_group", GROUP_FIELDS),
+        layer: section_fields("layer", LAYER_FIELDS),
+        style: section_fields("style", STYLE_FIELDS),
+        tag_pattern: pattern_schema(),
+    }
+}
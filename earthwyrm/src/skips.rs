@@ -0,0 +1,150 @@
+// skips.rs
+//
+// Copyright (c) 2024  Minnesota Department of Transportation
+//
+use crate::error::Result;
+use osmpbfreader::OsmId;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Entries logged for each layer / reason before sampling
+const LOG_FIRST: u64 = 1000;
+
+/// Sampling interval for entries after the first `LOG_FIRST`
+const LOG_SAMPLE: u64 = 1000;
+
+/// Maximum number of lines in a skip log
+const LOG_MAX_LINES: u64 = 1_000_000;
+
+/// Reason an OSM object was skipped during a dig
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SkipReason {
+    /// Some, but not all, layer patterns matched
+    PatternMismatch,
+
+    /// Closed way is not an area (polygon layers)
+    NotArea,
+
+    /// Way is an area (linestring layers)
+    IsArea,
+
+    /// Way is not closed (polygon layers)
+    UnclosedWay,
+
+    /// Relation members could not be assembled into rings
+    BrokenPolygon,
+
+    /// Relation exceeds member or node limits
+    OversizeRelation,
+
+    /// Referenced nodes are missing from the OSM file
+    MissingNodes,
+
+    /// No included tag values (`drop_empty` layers)
+    EmptyValues,
+}
+
+/// Log of OSM objects skipped during a dig.
+///
+/// Skips are always counted by layer and reason; entries are only written
+/// when a log file is set, sampled to limit its size.
+#[derive(Default)]
+pub struct SkipLog {
+    /// Log file writer
+    writer: Option<BufWriter<File>>,
+
+    /// Number of lines written
+    n_lines: u64,
+
+    /// Skip counts by layer and reason
+    counts: BTreeMap<(String, SkipReason), u64>,
+}
+
+impl SkipReason {
+    /// Get reason as a string
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::PatternMismatch => "pattern_mismatch",
+            SkipReason::NotArea => "not_area",
+            SkipReason::IsArea => "is_area",
+            SkipReason::UnclosedWay => "unclosed_way",
+            SkipReason::BrokenPolygon => "broken_polygon",
+            SkipReason::OversizeRelation => "oversize_relation",
+            SkipReason::MissingNodes => "missing_nodes",
+            SkipReason::EmptyValues => "empty_values",
+        }
+    }
+}
+
+/// Get the type name and ID of an OSM ID
+fn id_kind(id: OsmId) -> (&'static str, i64) {
+    match id {
+        OsmId::Node(id) => ("node", id.0),
+        OsmId::Way(id) => ("way", id.0),
+        OsmId::Relation(id) => ("relation", id.0),
+    }
+}
+
+impl SkipLog {
+    /// Create a skip log, writing entries to a file
+    pub fn with_file(path: &Path) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "layer\tkind\tid\treason")?;
+        Ok(SkipLog {
+            writer: Some(writer),
+            ..Default::default()
+        })
+    }
+
+    /// Record a skipped object
+    pub fn record(&mut self, layer: &str, id: OsmId, reason: SkipReason) {
+        let n = match self.counts.get_mut(&(layer.to_string(), reason)) {
+            Some(n) => n,
+            None => self.counts.entry((layer.to_string(), reason)).or_default(),
+        };
+        *n += 1;
+        let n = *n;
+        if n > LOG_FIRST && n % LOG_SAMPLE != 0 {
+            return;
+        }
+        if self.n_lines >= LOG_MAX_LINES {
+            return;
+        }
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        let (kind, id) = id_kind(id);
+        let reason = reason.as_str();
+        if let Err(e) = writeln!(writer, "{layer}\t{kind}\t{id}\t{reason}") {
+            log::warn!("skip log: {e}");
+            self.writer = None;
+            return;
+        }
+        self.n_lines += 1;
+    }
+
+    /// Print a summary of skip reasons by layer, and flush the log file
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.counts.is_empty() {
+            println!("Skipped objects:");
+            let width = self
+                .counts
+                .keys()
+                .map(|(layer, _)| layer.len())
+                .max()
+                .unwrap_or_default();
+            for ((layer, reason), n) in &self.counts {
+                println!("  {layer:width$}  {:18} {n:>10}", reason.as_str());
+            }
+        }
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+            if self.n_lines >= LOG_MAX_LINES {
+                println!("  (skip log truncated at {LOG_MAX_LINES} lines)");
+            }
+        }
+        Ok(())
+    }
+}
@@ -2,19 +2,164 @@
 //
 // Copyright (c) 2019-2024  Minnesota Department of Transportation
 //
-use crate::config::{LayerGroupCfg, WyrmCfg};
+use crate::analyze::{
+    EncodeStats, ExtentAnalysis, SplitMix, ZoomAnalysis, CANDIDATE_EDGES,
+};
+use crate::bitmap::TileBitmap;
+use crate::config::{EmptyTile, LayerGroupCfg, TileGrid, WyrmCfg};
+use crate::decode::{zigzag, FieldValue, PbfReader};
 use crate::error::{Error, Result};
-use crate::geom::GeomTree;
-use crate::layer::LayerDef;
-use mvt::{Layer, MapGrid, Tile, TileId};
-use pointy::{BBox, Transform};
+use crate::geom::{
+    bbox_around, lon_lat, signed_area, web_mercator, write_collection_end,
+    write_collection_start, FeatureInfo, GeoJsonFeatures, GeomTree, KeptLayer,
+    ValidationReport, Values, MERCATOR_LAT_MAX,
+};
+use crate::id_index::IdIndex;
+use crate::layer::{loam_names, LayerDef, ZOOM_MAX};
+use crate::manifest::Manifest;
+use crate::meta::LoamMeta;
+use mvt::{GeomData, GeomEncoder, GeomType, Layer, MapGrid, Tile, TileId};
+use pointy::{BBox, Pt, Transform};
+use serde_derive::Serialize;
+use serde_json::{json, Map, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::Write;
-use std::time::Instant;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Feature filter callback.
+///
+/// Called with the layer name and stored tag values of each feature.  Values
+/// are in the order of the layer's include patterns (`.`, `?` and `$` rules,
+/// with `@layering` expanded in place), followed by hidden `name:xx` values
+/// for the group's `name_languages`.  Use [LayerDef::tag_index] (from
+/// [LayerGroup::layers]) to find a tag's value.  Features are dropped when
+/// the filter returns `false`.
+pub type FeatureFilter = dyn Fn(&str, &Values) -> bool;
+
+/// Maximum tile extent multiplier
+const SCALE_MAX: u32 = 4;
+
+/// Default tile size (bytes) for streaming responses
+const STREAM_THRESHOLD: usize = 1 << 20;
+
+/// Approximate ratio of encoded tile size to loam file size
+const ENCODED_RATIO: f64 = 0.25;
+
+/// Default maximum points in a batch query request
+const MAX_BATCH_POINTS: usize = 10_000;
+
+/// Grid cell size (Web Mercator meters) for bucketing batch query points
+const BATCH_CELL: f64 = 1024.0;
+
+/// Layers with this many features are not degraded to fit tile budget
+const DEGRADE_FLOOR: usize = 8;
+
+/// Tolerance (Web Mercator meters) for tile bounds outside the grid
+const OUTLINE_TOLERANCE: f64 = 0.001;
+
+/// Default maximum zoom level of tile bitmaps
+const TILE_BITMAP_ZOOM: u32 = 10;
+
+/// Highest allowed zoom level of tile bitmaps (32 MiB at zoom 14)
+const TILE_BITMAP_ZOOM_MAX: u32 = 14;
+
+/// Number of features between deadline checks
+const DEADLINE_INTERVAL: usize = 64;
+
+/// Buffer around overzoomed tiles, as a fraction of tile extent
+const OVERZOOM_BUFFER: f64 = 1.0 / 64.0;
+
+/// Request context for fetching tiles
+#[derive(Default)]
+pub struct RequestCtx<'a> {
+    /// Feature filter
+    pub filter: Option<&'a FeatureFilter>,
+
+    /// Preferred name language (ex. `es`)
+    pub lang: Option<&'a str>,
+
+    /// Tile extent multiplier (ex. `2` for high-DPI tiles), limited to 1-4
+    pub scale: Option<u32>,
+
+    /// Deadline for making the tile; no features are added after it
+    pub deadline: Option<Instant>,
+}
+
+/// Tile extent, with edge buffer
+///
+/// Features are clipped to the tile bounding box, expanded by the edge on
+/// all sides.  The edge is specified in pixels, so it scales along with the
+/// extent for high-DPI tiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileExtent {
+    /// Width and height in pixels
+    extent: u32,
+
+    /// Edge buffer in pixels (`None` for zoom-based default)
+    edge: Option<u32>,
+}
+
+/// World-space grid for snapping vertices at one zoom level
+///
+/// Grid spacing is one tile pixel, so a vertex shared by adjacent tiles
+/// maps to the same position in both.
+#[derive(Clone, Copy, Debug)]
+pub struct GridSnap {
+    /// Grid origin X (west edge of world)
+    x0: f64,
+
+    /// Grid origin Y (north edge of world)
+    y0: f64,
+
+    /// Grid spacing (X)
+    dx: f64,
+
+    /// Grid spacing (Y)
+    dy: f64,
+
+    /// Tile grid (points are projected before snapping)
+    grid: TileGrid,
+}
+
+/// Result of fetching a tile
+#[derive(Debug, PartialEq)]
+pub enum TileFetch<T = Vec<u8>> {
+    /// Encoded MVT tile (or [StreamedTile])
+    Tile(T),
+
+    /// Tile is empty (no features, or zoom out of range)
+    Empty,
+
+    /// Unknown layer group name
+    UnknownGroup,
+}
+
+/// Summary of a tile written layer by layer.
+///
+/// See [Wyrm::write_tile_stream].
+#[derive(Debug, PartialEq)]
+pub struct StreamedTile {
+    /// Tile ID
+    tid: TileId,
+
+    /// Encoded size (bytes)
+    size: usize,
+
+    /// Features were cut off at the request deadline
+    truncated: bool,
+}
 
 /// Tile configuration
-pub struct TileCfg {
-    /// Tile extent; width and height in pixels
-    tile_extent: u32,
+pub struct TileCfg<'a> {
+    /// Tile extent
+    tile_extent: TileExtent,
 
     /// Tile ID
     tid: TileId,
@@ -22,8 +167,41 @@ pub struct TileCfg {
     /// Bounding box of tile (including edge extent)
     bbox: BBox<f64>,
 
-    /// Transform from spatial to tile coordinates
+    /// Bounding box of tile (excluding edge extent)
+    outline: BBox<f64>,
+
+    /// Tile grid
+    grid: TileGrid,
+
+    /// Bounding box of tile, projected to tile grid (including edge extent)
+    encode_bbox: BBox<f64>,
+
+    /// Transform from projected spatial to tile coordinates
     transform: Transform<f64>,
+
+    /// Vertex snapping grid
+    snap: GridSnap,
+
+    /// Request context
+    ctx: &'a RequestCtx<'a>,
+
+    /// Number of layer queries truncated at query limit
+    truncated: Cell<usize>,
+
+    /// Number of points dropped for non-finite tile coordinates
+    non_finite: Cell<usize>,
+
+    /// Number of tag values truncated at maximum length
+    truncated_values: Cell<usize>,
+
+    /// Feature caps for layers degraded to fit the tile size budget
+    feature_caps: RefCell<HashMap<String, usize>>,
+
+    /// Encoding statistics (only recorded for analysis)
+    stats: Option<RefCell<EncodeStats>>,
+
+    /// Deadline passed while adding features
+    expired: Cell<bool>,
 }
 
 /// Layer tree
@@ -31,17 +209,74 @@ struct LayerTree {
     /// Layer definition
     layer_def: LayerDef,
 
-    /// R-Tree of geometry
-    tree: GeomTree,
+    /// R-Trees of geometry, with minimum zoom level of each
+    trees: Vec<(u32, GeomTree)>,
+
+    /// Loam file size of each tree (bytes), for tile size estimates
+    loam_bytes: Vec<u64>,
+
+    /// OSM ID indexes for each tree (if built)
+    id_indexes: Option<Vec<IdIndex>>,
 }
 
 /// Group of layers for making tiles
-struct LayerGroup {
+pub struct LayerGroup {
     /// Name of group
     name: String,
 
     /// Layer definitions / trees
     layers: Vec<LayerTree>,
+
+    /// Minimum zoom level of all layers
+    zoom_min: u32,
+
+    /// Maximum zoom level of all layers
+    zoom_max: u32,
+
+    /// Encoded blank tile (layers with no features)
+    blank: Vec<u8>,
+
+    /// Include `debug` layer with tile outline
+    debug_outline: bool,
+
+    /// Include layers with no features
+    keep_empty_layers: bool,
+
+    /// Directory of pre-rendered tiles
+    static_tiles: Option<PathBuf>,
+
+    /// Zoom levels to overzoom missing static tiles from a parent
+    static_overzoom: u32,
+
+    /// Tile URL version
+    version: Option<String>,
+
+    /// Data attribution
+    attribution: Option<String>,
+
+    /// Data license
+    license: Option<String>,
+
+    /// Tile size budget (bytes)
+    max_tile_bytes: Option<usize>,
+
+    /// Time budget for making a tile
+    deadline: Option<Duration>,
+
+    /// Tile grid
+    grid: TileGrid,
+
+    /// Maximum zoom of tile bitmap (`None` if not used)
+    bitmap_zoom: Option<u32>,
+
+    /// Path to tile bitmap sidecar file
+    bitmap_path: PathBuf,
+
+    /// Key of tile bitmap (loam files and tile extent)
+    bitmap_key: u64,
+
+    /// Tile bitmap, read or built on first use
+    bitmap: OnceLock<Option<TileBitmap>>,
 }
 
 /// Wyrm tile fetcher.
@@ -55,199 +290,4614 @@ pub struct Wyrm {
     /// Map grid configuration
     grid: MapGrid,
 
-    /// Tile extent; width and height in pixels
-    tile_extent: u32,
+    /// Tile extent
+    tile_extent: TileExtent,
 
     /// Tile layer groups
     groups: Vec<LayerGroup>,
+
+    /// Response for empty tiles
+    empty_tile: EmptyTile,
+
+    /// Tile size (bytes) for streaming responses
+    stream_threshold: usize,
+
+    /// Serve GeoJSON tiles
+    geojson_tiles: bool,
+
+    /// Maximum points in a batch query request
+    max_batch_points: usize,
+
+    /// Dig manifest
+    manifest: Option<Manifest>,
 }
 
-impl TileCfg {
-    /// Get the zoom level
-    pub fn zoom(&self) -> u32 {
-        self.tid.z()
-    }
+/// Tile request path parsing
+pub trait TilePath: Sized {
+    /// Parse a tile request path (`group/z/x/<y>.mvt`).
+    ///
+    /// * `group` Name of layer group.
+    /// * `z` Zoom level.
+    /// * `x` Tile X.
+    /// * `tail` Tile Y with `.mvt` extension.
+    fn parse_path(group: &str, z: u32, x: u32, tail: &str) -> Result<Self>;
 
-    /// Get the bounding box (including edge extent)
-    pub fn bbox(&self) -> BBox<f64> {
-        self.bbox
+    /// Parse a tile request path with optional scale suffix
+    /// (`group/z/x/<y>@2x.mvt`).
+    ///
+    /// Returns the tile and extent multiplier (`1` without suffix).
+    fn parse_path_scaled(
+        group: &str,
+        z: u32,
+        x: u32,
+        tail: &str,
+    ) -> Result<(Self, u32)> {
+        let Some((y, suffix)) = tail.split_once('@') else {
+            return Ok((Self::parse_path(group, z, x, tail)?, 1));
+        };
+        let scale = suffix
+            .strip_suffix("x.mvt")
+            .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|s| s.parse::<u32>().ok())
+            .filter(|s| (1..=SCALE_MAX).contains(s))
+            .ok_or_else(|| {
+                Error::InvalidTileRequest(format!(
+                    "{group}/{z}/{x}/{tail}: scale"
+                ))
+            })?;
+        Ok((Self::parse_path(group, z, x, &format!("{y}.mvt"))?, scale))
     }
 
-    /// Get the tile transform
-    pub fn transform(&self) -> Transform<f64> {
-        self.transform
+    /// Parse a GeoJSON tile request path (`group/z/x/<y>.json`)
+    fn parse_path_geojson(
+        group: &str,
+        z: u32,
+        x: u32,
+        tail: &str,
+    ) -> Result<Self> {
+        let y = tail.strip_suffix(".json").ok_or_else(|| {
+            Error::InvalidTileRequest(format!(
+                "{group}/{z}/{x}/{tail}: extension"
+            ))
+        })?;
+        Self::parse_path(group, z, x, &format!("{y}.mvt"))
     }
+
+    /// Parse a Bing-style quadkey (ex. `0231010`).
+    ///
+    /// Each digit (`0`-`3`) selects a child quadrant, so the zoom level is
+    /// the quadkey length.
+    fn from_quadkey(quadkey: &str) -> Result<Self>;
+
+    /// Format as a Bing-style quadkey
+    fn to_quadkey(&self) -> String;
 }
 
-impl LayerGroup {
-    /// Create a new layer group
-    fn new(group: &LayerGroupCfg, wyrm: &WyrmCfg) -> Result<Self> {
-        let name = group.name.to_string();
-        let mut layers = vec![];
-        for layer_cfg in &group.layer {
-            let layer_def = LayerDef::try_from(layer_cfg)?;
-            layers.push(LayerTree::new(layer_def, wyrm)?);
+impl TilePath for TileId {
+    fn parse_path(group: &str, z: u32, x: u32, tail: &str) -> Result<Self> {
+        let invalid = |msg: &str| {
+            Error::InvalidTileRequest(format!("{group}/{z}/{x}/{tail}: {msg}"))
+        };
+        if group.is_empty()
+            || !group
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(invalid("group name"));
         }
-        log::info!("{} layers in {group}", layers.len());
-        Ok(LayerGroup { name, layers })
+        if z > ZOOM_MAX {
+            return Err(invalid("zoom"));
+        }
+        let y = tail
+            .strip_suffix(".mvt")
+            .ok_or_else(|| invalid("extension"))?;
+        if y.is_empty() || !y.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid("y"));
+        }
+        let y: u32 = y.parse().map_err(|_| invalid("y"))?;
+        let max = 1u64 << z;
+        if u64::from(x) >= max {
+            return Err(invalid("x out of range"));
+        }
+        if u64::from(y) >= max {
+            return Err(invalid("y out of range"));
+        }
+        TileId::new(x, y, z).map_err(|_| invalid("tile ID"))
     }
 
-    /// Get the group name
-    pub fn name(&self) -> &str {
-        &self.name
+    fn from_quadkey(quadkey: &str) -> Result<Self> {
+        let invalid = |msg: &str| {
+            Error::InvalidTileRequest(format!("quadkey {quadkey}: {msg}"))
+        };
+        if quadkey.len() > ZOOM_MAX as usize {
+            return Err(invalid("too long"));
+        }
+        let (mut x, mut y) = (0, 0);
+        for b in quadkey.bytes() {
+            let quad = match b {
+                b'0'..=b'3' => u32::from(b - b'0'),
+                _ => return Err(invalid("digit")),
+            };
+            x = (x << 1) | (quad & 1);
+            y = (y << 1) | (quad >> 1);
+        }
+        let z = quadkey.len() as u32;
+        TileId::new(x, y, z).map_err(|_| invalid("tile ID"))
     }
 
-    /// Fetch a tile
-    fn fetch_tile(&self, tile_cfg: &TileCfg) -> Result<Tile> {
-        let t = Instant::now();
-        let tile = self.query_tile(tile_cfg)?;
-        log::info!(
-            "{}/{}, fetched {} bytes in {:.2?}",
-            self.name(),
-            tile_cfg.tid,
-            tile.compute_size(),
-            t.elapsed()
-        );
-        Ok(tile)
+    fn to_quadkey(&self) -> String {
+        (1..=self.z())
+            .rev()
+            .map(|i| {
+                let mask = 1 << (i - 1);
+                let quad = u8::from(self.x() & mask != 0)
+                    | (u8::from(self.y() & mask != 0) << 1);
+                char::from(b'0' + quad)
+            })
+            .collect()
     }
+}
 
-    /// Query one tile from trees
-    fn query_tile(&self, tile_cfg: &TileCfg) -> Result<Tile> {
-        let mut tile = Tile::new(tile_cfg.tile_extent);
-        for layer_tree in &self.layers {
-            let layer = layer_tree.query_tile(&tile, tile_cfg)?;
-            if layer.num_features() > 0 {
-                tile.add_layer(layer)?;
-            }
+/// Summary of a decoded MVT tile
+#[derive(Debug, Default, Serialize)]
+pub struct TileSummary {
+    /// Layers in tile
+    pub layers: Vec<LayerSummary>,
+}
+
+/// Summary of one decoded MVT layer
+#[derive(Debug, Default, Serialize)]
+pub struct LayerSummary {
+    /// Layer name
+    pub name: String,
+
+    /// Tile extent
+    pub extent: u32,
+
+    /// Features in layer
+    pub features: Vec<FeatureSummary>,
+}
+
+/// Summary of one decoded MVT feature
+#[derive(Debug, Default, Serialize)]
+pub struct FeatureSummary {
+    /// Feature ID
+    pub id: Option<u64>,
+
+    /// Geometry type (`point`, `linestring`, `polygon` or `unknown`)
+    pub geom_type: &'static str,
+
+    /// Number of geometry commands / parameters
+    pub geom_len: usize,
+
+    /// Tag keys and values
+    pub tags: Vec<(String, TagValue)>,
+
+    /// Geometry commands / parameters
+    #[serde(skip)]
+    geom: Vec<u64>,
+}
+
+/// Differences between two decoded MVT tiles
+#[derive(Debug, Default, Serialize)]
+pub struct TileDiff {
+    /// Encoded size of first tile (bytes)
+    pub bytes_a: usize,
+
+    /// Encoded size of second tile (bytes)
+    pub bytes_b: usize,
+
+    /// Layers only in second tile
+    pub layers_added: Vec<LayerDiff>,
+
+    /// Layers only in first tile
+    pub layers_removed: Vec<LayerDiff>,
+
+    /// Layers in both tiles, with different features
+    pub layers_changed: Vec<LayerDiff>,
+}
+
+/// Differences of one layer between two tiles
+#[derive(Debug, Default, Serialize)]
+pub struct LayerDiff {
+    /// Layer name
+    pub name: String,
+
+    /// Number of features in first tile
+    pub features_a: usize,
+
+    /// Number of features in second tile
+    pub features_b: usize,
+
+    /// Features only in second tile
+    pub added: usize,
+
+    /// Features only in first tile
+    pub removed: usize,
+}
+
+/// Decoded MVT tag value
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TagValue {
+    /// String value
+    String(String),
+
+    /// Floating-point value
+    Float(f64),
+
+    /// Signed integer value (`int` or `sint`)
+    Int(i64),
+
+    /// Unsigned integer value
+    Uint(u64),
+
+    /// Boolean value
+    Bool(bool),
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagValue::String(v) => write!(f, "{v:?}"),
+            TagValue::Float(v) => write!(f, "{v}"),
+            TagValue::Int(v) => write!(f, "{v}"),
+            TagValue::Uint(v) => write!(f, "{v}"),
+            TagValue::Bool(v) => write!(f, "{v}"),
         }
-        Ok(tile)
     }
+}
 
-    /// Write group layers to a tile
-    fn write_tile<W: Write>(
-        &self,
-        out: &mut W,
-        tile_cfg: TileCfg,
-    ) -> Result<()> {
-        let tile = self.fetch_tile(&tile_cfg)?;
-        if tile.num_layers() > 0 {
-            tile.write_to(out)?;
-            Ok(())
-        } else {
-            log::debug!("tile {} empty (no layers)", tile_cfg.tid);
-            Err(Error::TileEmpty())
+impl fmt::Display for TileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in &self.layers {
+            writeln!(
+                f,
+                "layer: {} (extent {}, {} features)",
+                layer.name,
+                layer.extent,
+                layer.features.len()
+            )?;
+            for feature in &layer.features {
+                match feature.id {
+                    Some(id) => write!(f, "  {} #{id}", feature.geom_type)?,
+                    None => write!(f, "  {}", feature.geom_type)?,
+                }
+                for (key, value) in &feature.tags {
+                    write!(f, " {key}={value}")?;
+                }
+                writeln!(f)?;
+            }
         }
+        Ok(())
     }
 }
 
-impl TryFrom<&WyrmCfg> for Wyrm {
-    type Error = Error;
+impl TileSummary {
+    /// Retain only layers with a matching name
+    pub fn retain_layer(&mut self, name: &str) {
+        self.layers.retain(|layer| layer.name == name);
+    }
+}
 
-    fn try_from(wyrm_cfg: &WyrmCfg) -> Result<Self> {
-        // Only Web Mercator supported for now
-        let grid = MapGrid::default();
-        let mut groups = vec![];
-        for group in &wyrm_cfg.layer_group {
-            groups.push(LayerGroup::new(group, wyrm_cfg)?);
+/// Decode a tag value message
+fn decode_value(buf: &[u8]) -> Result<TagValue> {
+    let mut reader = PbfReader::new(buf);
+    let mut value = None;
+    while let Some((field, val)) = reader.next_field()? {
+        value = Some(match (field, val) {
+            (1, _) => TagValue::String(val.as_str()?.to_string()),
+            (2, FieldValue::Fixed32(v)) => {
+                TagValue::Float(f64::from(f32::from_bits(v)))
+            }
+            (3, FieldValue::Fixed64(v)) => TagValue::Float(f64::from_bits(v)),
+            (4, _) => TagValue::Int(val.as_u64()? as i64),
+            (5, _) => TagValue::Uint(val.as_u64()?),
+            (6, _) => TagValue::Int(zigzag(val.as_u64()?)),
+            (7, _) => TagValue::Bool(val.as_u64()? != 0),
+            _ => continue,
+        });
+    }
+    value.ok_or(Error::MalformedTile())
+}
+
+/// Decode a feature message
+fn decode_feature(
+    buf: &[u8],
+    keys: &[String],
+    values: &[TagValue],
+) -> Result<FeatureSummary> {
+    let mut reader = PbfReader::new(buf);
+    let mut feature = FeatureSummary {
+        geom_type: "unknown",
+        ..Default::default()
+    };
+    while let Some((field, val)) = reader.next_field()? {
+        match field {
+            1 => feature.id = Some(val.as_u64()?),
+            2 => {
+                let tags = PbfReader::packed_varints(val.as_bytes()?)?;
+                for pair in tags.chunks(2) {
+                    let (Some(k), Some(v)) = (pair.first(), pair.get(1)) else {
+                        return Err(Error::MalformedTile());
+                    };
+                    let key = keys.get(*k as usize);
+                    let value = values.get(*v as usize);
+                    match (key, value) {
+                        (Some(key), Some(value)) => {
+                            feature.tags.push((key.clone(), value.clone()))
+                        }
+                        _ => return Err(Error::MalformedTile()),
+                    }
+                }
+            }
+            3 => {
+                feature.geom_type = match val.as_u64()? {
+                    1 => "point",
+                    2 => "linestring",
+                    3 => "polygon",
+                    _ => "unknown",
+                }
+            }
+            4 => {
+                feature.geom = PbfReader::packed_varints(val.as_bytes()?)?;
+                feature.geom_len = feature.geom.len();
+            }
+            _ => (),
         }
-        Ok(Wyrm {
-            grid,
-            tile_extent: wyrm_cfg.tile_extent,
-            groups,
-        })
     }
+    Ok(feature)
 }
 
-impl Wyrm {
-    /// Query features in a bounding box
-    pub fn query_features(&self, bbox: BBox<f64>) -> Result<()> {
-        for group in &self.groups {
-            log::debug!("query_features group: {:?}", group.name);
-            for layer in &group.layers {
-                layer.query_features(bbox)?;
+/// Decode a layer message
+fn decode_layer(buf: &[u8]) -> Result<LayerSummary> {
+    // keys and values may follow features, so decode them first
+    let mut reader = PbfReader::new(buf);
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    while let Some((field, val)) = reader.next_field()? {
+        match field {
+            3 => keys.push(val.as_str()?.to_string()),
+            4 => values.push(decode_value(val.as_bytes()?)?),
+            _ => (),
+        }
+    }
+    let mut reader = PbfReader::new(buf);
+    let mut layer = LayerSummary {
+        extent: 4096,
+        ..Default::default()
+    };
+    while let Some((field, val)) = reader.next_field()? {
+        match field {
+            1 => layer.name = val.as_str()?.to_string(),
+            2 => layer.features.push(decode_feature(
+                val.as_bytes()?,
+                &keys,
+                &values,
+            )?),
+            5 => {
+                layer.extent = u32::try_from(val.as_u64()?)
+                    .map_err(|_| Error::MalformedTile())?
             }
+            _ => (),
         }
-        Ok(())
     }
+    Ok(layer)
+}
 
-    /// Fetch one tile.
+/// Decode an MVT tile into a summary of its layers and features.
+///
+/// * `bytes` Encoded MVT data.
+pub fn decode_summary(bytes: &[u8]) -> Result<TileSummary> {
+    let mut reader = PbfReader::new(bytes);
+    let mut summary = TileSummary::default();
+    while let Some((field, val)) = reader.next_field()? {
+        if field == 3 {
+            summary.layers.push(decode_layer(val.as_bytes()?)?);
+        }
+    }
+    Ok(summary)
+}
+
+impl FeatureSummary {
+    /// Make a key for comparing features, independent of tag order
+    fn diff_key(&self) -> String {
+        let mut tags: Vec<_> =
+            self.tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        tags.sort();
+        format!("{:?} {} {:?} {tags:?}", self.id, self.geom_type, self.geom)
+    }
+}
+
+impl LayerDiff {
+    /// Compare features of a layer in two tiles.
     ///
-    /// * `out` Writer to write MVT data.
-    /// * `group_name` Name of layer group.
-    /// * `tid` Tile ID.
-    pub fn fetch_tile<W: Write>(
-        &self,
-        out: &mut W,
-        group_name: &str,
-        tid: TileId,
-    ) -> Result<()> {
-        for group in &self.groups {
-            if group_name == group.name() {
-                let tile_cfg = self.tile_config(tid);
-                return group.write_tile(out, tile_cfg);
-            }
+    /// Features are compared by decoded content, ignoring order.
+    fn new(name: &str, a: &[FeatureSummary], b: &[FeatureSummary]) -> Self {
+        let mut counts = HashMap::<String, isize>::new();
+        for feature in a {
+            *counts.entry(feature.diff_key()).or_default() += 1;
         }
-        log::debug!("unknown group name: {}", group_name);
-        Err(Error::UnknownGroupName())
-    }
-
-    /// Create tile config for a tile ID
-    fn tile_config(&self, tid: TileId) -> TileCfg {
-        let tile_extent = self.tile_extent;
-        let mut bbox = self.grid.tile_bbox(tid);
-        // increase bounding box by edge extent
-        let edge = zoom_edge(tid);
-        let edge_x = edge * (bbox.x_max() - bbox.x_min());
-        let edge_y = edge * (bbox.y_max() - bbox.y_min());
-        bbox.extend([
-            (bbox.x_min() - edge_x, bbox.y_min() - edge_y),
-            (bbox.x_max() + edge_x, bbox.y_max() + edge_y),
-        ]);
-        let ts = f64::from(tile_extent);
-        let transform = self.grid.tile_transform(tid).scale(ts, ts);
-        TileCfg {
-            tile_extent,
-            tid,
-            bbox,
-            transform,
+        for feature in b {
+            *counts.entry(feature.diff_key()).or_default() -= 1;
+        }
+        let removed = counts.values().filter(|n| **n > 0).sum::<isize>();
+        let added = -counts.values().filter(|n| **n < 0).sum::<isize>();
+        LayerDiff {
+            name: name.to_string(),
+            features_a: a.len(),
+            features_b: b.len(),
+            added: added as usize,
+            removed: removed as usize,
         }
     }
+
+    /// Check if features are the same
+    fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0
+    }
 }
 
-/// Calculate edge ratio based on tile zoom
+impl TileDiff {
+    /// Check if tile contents are the same (ignoring encoded size)
+    pub fn is_empty(&self) -> bool {
+        self.layers_added.is_empty()
+            && self.layers_removed.is_empty()
+            && self.layers_changed.is_empty()
+    }
+
+    /// Get the number of features added or removed, in all layers
+    pub fn n_changed(&self) -> usize {
+        self.layers_added
+            .iter()
+            .chain(&self.layers_removed)
+            .chain(&self.layers_changed)
+            .map(|l| l.added + l.removed)
+            .sum()
+    }
+}
+
+impl fmt::Display for TileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in &self.layers_added {
+            writeln!(f, "  + {} ({} features)", layer.name, layer.features_b)?;
+        }
+        for layer in &self.layers_removed {
+            writeln!(f, "  - {} ({} features)", layer.name, layer.features_a)?;
+        }
+        for layer in &self.layers_changed {
+            writeln!(
+                f,
+                "  ~ {} ({} -> {} features, +{} -{})",
+                layer.name,
+                layer.features_a,
+                layer.features_b,
+                layer.added,
+                layer.removed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two encoded MVT tiles.
 ///
-/// Edge must be larger for higher zoom levels to prevent corrupt polygons.
-fn zoom_edge(tid: TileId) -> f64 {
-    match tid.z() {
-        0..=12 => 1.0 / 32.0,
-        13 => 1.0 / 16.0,
-        14 => 1.0 / 8.0,
-        15 => 1.0 / 4.0,
-        16 => 1.0 / 2.0,
-        _ => 1.0,
+/// Tiles are decoded, so the same layers and features in a different
+/// encoding order have no differences.
+///
+/// * `a` First tile.
+/// * `b` Second tile.
+pub fn diff_tiles(a: &[u8], b: &[u8]) -> Result<TileDiff> {
+    let tile_a = decode_summary(a)?;
+    let tile_b = decode_summary(b)?;
+    let mut diff = TileDiff {
+        bytes_a: a.len(),
+        bytes_b: b.len(),
+        ..Default::default()
+    };
+    for la in &tile_a.layers {
+        match tile_b.layers.iter().find(|lb| lb.name == la.name) {
+            Some(lb) => {
+                let layer =
+                    LayerDiff::new(&la.name, &la.features, &lb.features);
+                if !layer.is_empty() {
+                    diff.layers_changed.push(layer);
+                }
+            }
+            None => diff.layers_removed.push(LayerDiff::new(
+                &la.name,
+                &la.features,
+                &[],
+            )),
+        }
+    }
+    for lb in &tile_b.layers {
+        if !tile_a.layers.iter().any(|la| la.name == lb.name) {
+            diff.layers_added
+                .push(LayerDiff::new(&lb.name, &[], &lb.features));
+        }
     }
+    Ok(diff)
 }
 
-impl LayerTree {
-    /// Create a new layer tree
-    fn new(layer_def: LayerDef, wyrm: &WyrmCfg) -> Result<Self> {
-        let loam = wyrm.loam_path(layer_def.name());
-        let tree = GeomTree::new(layer_def.geom_tp(), loam)?;
-        Ok(LayerTree { layer_def, tree })
+/// Tile point (tile coordinates)
+type TilePt = (f64, f64);
+
+impl FeatureSummary {
+    /// Decode geometry into paths of tile points, for tests
+    #[cfg(test)]
+    pub(crate) fn paths(&self) -> Vec<Vec<TilePt>> {
+        decode_paths(&self.geom).unwrap()
     }
+}
 
-    /// Query layer features in a bounding box
-    fn query_features(&self, bbox: BBox<f64>) -> Result<()> {
-        self.tree.query_features(&self.layer_def, bbox)
+/// Decode MVT geometry commands into paths.
+///
+/// Each `MoveTo` starts a new path; `ClosePath` adds no point.
+fn decode_paths(geom: &[u64]) -> Result<Vec<Vec<TilePt>>> {
+    let mut paths: Vec<Vec<TilePt>> = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    let mut i = 0;
+    while i < geom.len() {
+        let (cmd, count) = (geom[i] & 0x7, (geom[i] >> 3) as usize);
+        i += 1;
+        match cmd {
+            1 | 2 => {
+                for _ in 0..count {
+                    let (Some(dx), Some(dy)) = (geom.get(i), geom.get(i + 1))
+                    else {
+                        return Err(Error::MalformedTile());
+                    };
+                    x += zigzag(*dx);
+                    y += zigzag(*dy);
+                    i += 2;
+                    let pt = (x as f64, y as f64);
+                    match paths.last_mut() {
+                        Some(path) if cmd == 2 => path.push(pt),
+                        _ => paths.push(vec![pt]),
+                    }
+                }
+            }
+            7 => (),
+            _ => return Err(Error::MalformedTile()),
+        }
     }
+    Ok(paths)
+}
 
-    /// Query tile features
-    fn query_tile(&self, tile: &Tile, tile_cfg: &TileCfg) -> Result<Layer> {
-        let layer = tile.create_layer(self.layer_def.name());
+/// Check if a point is within a clip box
+fn pt_within(pt: TilePt, clip: BBox<f64>) -> bool {
+    pt.0 >= clip.x_min()
+        && pt.0 <= clip.x_max()
+        && pt.1 >= clip.y_min()
+        && pt.1 <= clip.y_max()
+}
+
+/// Clip a segment to a box (Liang-Barsky), returning the clipped segment
+fn clip_segment(
+    p0: TilePt,
+    p1: TilePt,
+    clip: BBox<f64>,
+) -> Option<[TilePt; 2]> {
+    let (dx, dy) = (p1.0 - p0.0, p1.1 - p0.1);
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for (p, q) in [
+        (-dx, p0.0 - clip.x_min()),
+        (dx, clip.x_max() - p0.0),
+        (-dy, p0.1 - clip.y_min()),
+        (dy, clip.y_max() - p0.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = f64::max(t0, r);
+            } else {
+                t1 = f64::min(t1, r);
+            }
+        }
+    }
+    (t0 <= t1).then(|| {
+        [
+            (p0.0 + t0 * dx, p0.1 + t0 * dy),
+            (p0.0 + t1 * dx, p0.1 + t1 * dy),
+        ]
+    })
+}
+
+/// Clip a linestring to a box, returning connected runs
+fn clip_line(line: &[TilePt], clip: BBox<f64>) -> Vec<Vec<TilePt>> {
+    let mut runs = Vec::new();
+    let mut run: Vec<TilePt> = Vec::new();
+    for seg in line.windows(2) {
+        match clip_segment(seg[0], seg[1], clip) {
+            Some([c0, c1]) => {
+                if run.last() != Some(&c0) {
+                    if run.len() > 1 {
+                        runs.push(std::mem::take(&mut run));
+                    }
+                    run.clear();
+                    run.push(c0);
+                }
+                run.push(c1);
+                // segment leaves the box
+                if c1 != seg[1] {
+                    runs.push(std::mem::take(&mut run));
+                }
+            }
+            None => {
+                if run.len() > 1 {
+                    runs.push(std::mem::take(&mut run));
+                }
+                run.clear();
+            }
+        }
+    }
+    if run.len() > 1 {
+        runs.push(run);
+    }
+    runs
+}
+
+/// Clip a polygon ring to a box (Sutherland-Hodgman)
+fn clip_ring(ring: &[TilePt], clip: BBox<f64>) -> Vec<TilePt> {
+    let mut pts = ring.to_vec();
+    // each edge: inside check, and intersection with edge line
+    let edges: [(fn(TilePt, BBox<f64>) -> bool, bool, f64); 4] = [
+        (|p, c| p.0 >= c.x_min(), true, clip.x_min()),
+        (|p, c| p.0 <= c.x_max(), true, clip.x_max()),
+        (|p, c| p.1 >= c.y_min(), false, clip.y_min()),
+        (|p, c| p.1 <= c.y_max(), false, clip.y_max()),
+    ];
+    for (inside, vertical, v) in edges {
+        let input = std::mem::take(&mut pts);
+        let Some(&last) = input.last() else {
+            break;
+        };
+        let mut prev = last;
+        for &pt in &input {
+            let cross = |a: TilePt, b: TilePt| {
+                if vertical {
+                    let t = (v - a.0) / (b.0 - a.0);
+                    (v, a.1 + t * (b.1 - a.1))
+                } else {
+                    let t = (v - a.1) / (b.1 - a.1);
+                    (a.0 + t * (b.0 - a.0), v)
+                }
+            };
+            match (inside(pt, clip), inside(prev, clip)) {
+                (true, true) => pts.push(pt),
+                (true, false) => {
+                    pts.push(cross(prev, pt));
+                    pts.push(pt);
+                }
+                (false, true) => pts.push(cross(prev, pt)),
+                (false, false) => (),
+            }
+            prev = pt;
+        }
+    }
+    pts
+}
+
+/// Round points to tile coordinates, removing consecutive duplicates
+fn round_dedup(pts: &[TilePt]) -> Vec<TilePt> {
+    let mut out: Vec<TilePt> = Vec::with_capacity(pts.len());
+    for pt in pts {
+        let pt = (pt.0.round(), pt.1.round());
+        if out.last() != Some(&pt) {
+            out.push(pt);
+        }
+    }
+    out
+}
+
+/// Encode a decoded feature's geometry, scaled and clipped
+fn overzoom_geom(
+    feature: &FeatureSummary,
+    t: Transform<f64>,
+    clip: BBox<f64>,
+) -> Result<Option<GeomData>> {
+    let geom_tp = match feature.geom_type {
+        "point" => GeomType::Point,
+        "linestring" => GeomType::Linestring,
+        "polygon" => GeomType::Polygon,
+        _ => return Ok(None),
+    };
+    let mut enc = GeomEncoder::new(geom_tp).bbox(clip);
+    // winding of the first (exterior) ring
+    let mut exterior = None;
+    // inner rings of a discarded outer ring are also discarded
+    let mut skip_inner = false;
+    for path in decode_paths(&feature.geom)? {
+        let path: Vec<TilePt> = path
+            .into_iter()
+            .map(|(x, y)| {
+                let p = t * Pt::new(x, y);
+                (p.x, p.y)
+            })
+            .collect();
+        match geom_tp {
+            GeomType::Point => {
+                for pt in path.into_iter().filter(|pt| pt_within(*pt, clip)) {
+                    let (x, y) = (pt.0.round(), pt.1.round());
+                    enc.add_point(x, y)?;
+                }
+            }
+            GeomType::Linestring => {
+                for run in clip_line(&path, clip) {
+                    let run = round_dedup(&run);
+                    if run.len() >= 2 {
+                        enc.complete_geom()?;
+                        for (x, y) in run {
+                            enc.add_point(x, y)?;
+                        }
+                    }
+                }
+            }
+            GeomType::Polygon => {
+                // exterior rings have the same winding as the first ring
+                let ccw = signed_area(&path) >= 0.0;
+                let outer = *exterior.get_or_insert(ccw) == ccw;
+                if outer {
+                    skip_inner = false;
+                } else if skip_inner {
+                    continue;
+                }
+                let mut ring = round_dedup(&clip_ring(&path, clip));
+                if ring.len() > 1 && ring.first() == ring.last() {
+                    ring.pop();
+                }
+                // discard degenerate rings
+                if ring.len() < 3 {
+                    skip_inner = outer;
+                    continue;
+                }
+                enc.complete_geom()?;
+                for (x, y) in ring {
+                    enc.add_point(x, y)?;
+                }
+                enc.complete_geom()?;
+            }
+        }
+    }
+    let geom = enc.encode()?;
+    Ok((!geom.is_empty()).then_some(geom))
+}
+
+/// Make a child tile from a parent tile ("overzoom").
+///
+/// Geometry of each parent feature is scaled to the child tile, and
+/// clipped to its extent plus a buffer.  Features entirely outside the
+/// child tile are dropped; layer names and tags are kept.
+///
+/// * `parent_bytes` Encoded MVT parent tile.
+/// * `parent_tid` Parent tile ID.
+/// * `child_tid` Child tile ID (at a higher zoom level).
+/// * `extent` Tile extent of child tile.
+pub fn overzoom(
+    parent_bytes: &[u8],
+    parent_tid: TileId,
+    child_tid: TileId,
+    extent: u32,
+) -> Result<Vec<u8>> {
+    let dz = child_tid
+        .z()
+        .checked_sub(parent_tid.z())
+        .unwrap_or_default();
+    if dz == 0
+        || child_tid.x() >> dz != parent_tid.x()
+        || child_tid.y() >> dz != parent_tid.y()
+    {
+        return Err(Error::InvalidTileRequest(format!(
+            "{child_tid:?} is not a child of {parent_tid:?}"
+        )));
+    }
+    let summary = decode_summary(parent_bytes)?;
+    let n = f64::from(1u32 << dz);
+    let edge = f64::from(extent) * OVERZOOM_BUFFER;
+    let clip = BBox::new([
+        (-edge, -edge),
+        (f64::from(extent) + edge, f64::from(extent) + edge),
+    ]);
+    let cx = f64::from(child_tid.x() - (parent_tid.x() << dz));
+    let cy = f64::from(child_tid.y() - (parent_tid.y() << dz));
+    let mut tile = Tile::new(extent);
+    for ls in &summary.layers {
+        if ls.extent == 0 {
+            return Err(Error::MalformedTile());
+        }
+        let parent_extent = f64::from(ls.extent);
+        let scale = f64::from(extent) * n / parent_extent;
+        // parent coordinates of child tile origin
+        let (ox, oy) = (cx * parent_extent / n, cy * parent_extent / n);
+        let t = Transform::with_translate(-ox, -oy).scale(scale, scale);
+        let mut layer = tile.create_layer(&ls.name);
+        for feature in &ls.features {
+            let Some(geom) = overzoom_geom(feature, t, clip)? else {
+                continue;
+            };
+            let mut feat = layer.into_feature(geom);
+            if let Some(id) = feature.id {
+                feat.set_id(id);
+            }
+            for (key, value) in &feature.tags {
+                match value {
+                    TagValue::String(v) => feat.add_tag_string(key, v),
+                    TagValue::Float(v) => feat.add_tag_double(key, *v),
+                    TagValue::Int(v) => feat.add_tag_sint(key, *v),
+                    TagValue::Uint(v) => feat.add_tag_uint(key, *v),
+                    TagValue::Bool(v) => feat.add_tag_bool(key, *v),
+                }
+            }
+            layer = feat.into_layer();
+        }
+        tile.add_layer(layer)?;
+    }
+    Ok(tile.to_bytes()?)
+}
+
+impl TileCfg<'_> {
+    /// Get the zoom level (of the tile grid)
+    pub fn zoom(&self) -> u32 {
+        self.grid.zoom(self.tid)
+    }
+
+    /// Project a Web Mercator point to the tile grid
+    pub fn project(&self, pt: (f64, f64)) -> (f64, f64) {
+        self.grid.project(pt)
+    }
+
+    /// Convert a point on the tile grid to WGS84 longitude / latitude
+    pub fn lon_lat(&self, pt: (f64, f64)) -> (f64, f64) {
+        self.grid.lon_lat(pt)
+    }
+
+    /// Record a layer query truncated at its query limit
+    pub(crate) fn truncate(&self, layer_def: &LayerDef) {
+        log::warn!(
+            "layer {}: tile {} truncated at {} features",
+            layer_def.name(),
+            self.tid,
+            layer_def.query_limit()
+        );
+        self.truncated.set(self.truncated.get() + 1);
+    }
+
+    /// Check if a point (already transformed to tile coordinates) is finite.
+    ///
+    /// Non-finite points are counted, to be dropped by the caller.
+    pub(crate) fn check_finite(&self, p: Pt<f64>) -> bool {
+        let finite = p.x.is_finite() && p.y.is_finite();
+        debug_assert!(finite, "tile {}: non-finite point {p:?}", self.tid);
+        if !finite {
+            self.non_finite.set(self.non_finite.get() + 1);
+        }
+        finite
+    }
+
+    /// Check whether the request deadline has passed.
+    ///
+    /// The clock is only read every `DEADLINE_INTERVAL` features (`n`); once
+    /// passed, it stays passed.
+    pub(crate) fn deadline_passed(&self, n: usize) -> bool {
+        if self.expired.get() {
+            return true;
+        }
+        let Some(deadline) = self.ctx.deadline else {
+            return false;
+        };
+        if n % DEADLINE_INTERVAL == 0 && Instant::now() >= deadline {
+            log::warn!("tile {}: deadline passed, tile truncated", self.tid);
+            self.expired.set(true);
+        }
+        self.expired.get()
+    }
+
+    /// Record a tag value truncated at maximum length
+    pub(crate) fn truncate_value(&self) {
+        self.truncated_values.set(self.truncated_values.get() + 1);
+    }
+
+    /// Log dropped points and truncated tag values
+    fn log_dropped(&self) {
+        let n = self.non_finite.get();
+        if n > 0 {
+            log::warn!("tile {}: dropped {n} non-finite points", self.tid);
+        }
+        let n = self.truncated_values.get();
+        if n > 0 {
+            log::debug!("tile {}: truncated {n} long tag values", self.tid);
+        }
+    }
+
+    /// Record a vertex (before snapping) for encoding statistics
+    pub(crate) fn stats_vertex(&self, pt: (f64, f64)) {
+        if let Some(stats) = &self.stats {
+            let p = self.transform * Pt::from(self.project(pt));
+            let extent = f64::from(self.tile_extent.extent());
+            stats.borrow_mut().vertex(p.x / extent, p.y / extent);
+        }
+    }
+
+    /// End a run of connected vertices for encoding statistics
+    pub(crate) fn stats_run(&self) {
+        if let Some(stats) = &self.stats {
+            stats.borrow_mut().end_run();
+        }
+    }
+
+    /// End a feature for encoding statistics
+    pub(crate) fn stats_feature(&self) {
+        if let Some(stats) = &self.stats {
+            stats.borrow_mut().end_feature();
+        }
+    }
+
+    /// Get the feature cap for a layer
+    pub(crate) fn feature_cap(&self, layer: &str) -> Option<usize> {
+        self.feature_caps.borrow().get(layer).copied()
+    }
+
+    /// Get the Web Mercator bounding box (including edge extent)
+    pub fn bbox(&self) -> BBox<f64> {
+        self.bbox
+    }
+
+    /// Get the bounding box projected to the tile grid, for encoding
+    pub fn encode_bbox(&self) -> BBox<f64> {
+        self.encode_bbox
+    }
+
+    /// Get the tile transform, from projected points (see
+    /// [project](Self::project))
+    pub fn transform(&self) -> Transform<f64> {
+        self.transform
+    }
+
+    /// Get the vertex snapping grid
+    pub fn snap(&self) -> GridSnap {
+        self.snap
+    }
+
+    /// Check if a feature passes layer and request filters
+    pub fn check_feature(&self, layer_def: &LayerDef, values: &Values) -> bool {
+        layer_def.check_filter(values)
+            && self.ctx.filter.is_none_or(|f| f(layer_def.name(), values))
+    }
+
+    /// Get the preferred name language
+    pub fn lang(&self) -> Option<&str> {
+        self.ctx.lang
+    }
+}
+
+/// Sort layers by `order`, keeping config order for ties
+fn sort_layers(layers: &mut [LayerTree]) {
+    layers.sort_by_key(|l| l.layer_def.order());
+}
+
+impl LayerGroup {
+    /// Create a new layer group
+    fn new(
+        group: &LayerGroupCfg,
+        wyrm: &WyrmCfg,
+        manifest: Option<&Manifest>,
+    ) -> Result<Self> {
+        let name = group.name.to_string();
+        let mut layers = vec![];
+        for layer_cfg in &group.layer {
+            let layer_def = LayerDef::try_from(layer_cfg)?
+                .with_name_languages(&group.name_languages)
+                .with_query_limit(wyrm.query_limit)
+                .with_max_value_len(wyrm.max_value_len);
+            layers.push(LayerTree::new(layer_def, wyrm, manifest)?);
+        }
+        sort_layers(&mut layers);
+        log::info!("{} layers in {group}", layers.len());
+        let zoom_min = layers
+            .iter()
+            .map(|l| l.layer_def.zoom_min())
+            .min()
+            .unwrap_or(u32::MAX);
+        let zoom_max = layers
+            .iter()
+            .map(|l| l.layer_def.zoom_max())
+            .max()
+            .unwrap_or(0);
+        let blank = blank_tile(&layers, wyrm.tile_extent.extent())?;
+        let version = match group.url_version.as_deref() {
+            Some("mtime") => Some(format!("m{:016x}", loam_hash(group, wyrm)?)),
+            Some(version) => Some(version.to_string()),
+            None => None,
+        };
+        // tiles are never empty with debug outlines
+        let bitmap_zoom = (group.grid == TileGrid::WebMercator
+            && !group.debug_outline)
+            .then(|| {
+                group
+                    .tile_bitmap_zoom
+                    .unwrap_or(TILE_BITMAP_ZOOM)
+                    .min(TILE_BITMAP_ZOOM_MAX)
+            });
+        let bitmap_key = loam_hash(group, wyrm)?
+            ^ u64::from(wyrm.tile_extent)
+            ^ (u64::from(wyrm.edge_extent.unwrap_or(u32::MAX)) << 32);
+        Ok(LayerGroup {
+            name,
+            layers,
+            zoom_min,
+            zoom_max,
+            blank,
+            debug_outline: group.debug_outline,
+            keep_empty_layers: group.keep_empty_layers,
+            static_tiles: group.static_tiles.as_ref().map(PathBuf::from),
+            static_overzoom: group.static_overzoom.unwrap_or_default(),
+            version,
+            attribution: group.attribution.clone(),
+            license: group.license.clone(),
+            max_tile_bytes: group.max_tile_bytes,
+            deadline: group.tile_deadline_ms.map(Duration::from_millis),
+            grid: group.grid,
+            bitmap_zoom,
+            bitmap_path: wyrm.loam_dir().join(format!("{}.bitmap", group.name)),
+            bitmap_key,
+            bitmap: OnceLock::new(),
+        })
+    }
+
+    /// Get the tile grid
+    pub fn grid(&self) -> TileGrid {
+        self.grid
+    }
+
+    /// Get the group data attribution
+    pub fn attribution(&self) -> Option<&str> {
+        self.attribution.as_deref()
+    }
+
+    /// Get the group data license
+    pub fn license(&self) -> Option<&str> {
+        self.license.as_deref()
+    }
+
+    /// Get all attributions (group, then layers), without duplicates
+    pub fn attributions(&self) -> Vec<&str> {
+        let mut attributions = Vec::new();
+        let layers = self.layers().filter_map(LayerDef::attribution);
+        for attr in self.attribution().into_iter().chain(layers) {
+            if !attributions.contains(&attr) {
+                attributions.push(attr);
+            }
+        }
+        attributions
+    }
+
+    /// Get the tile URL version
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Get the group name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check if the group has a name (ignoring case)
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Get the layer definitions, in tile order
+    pub fn layers(&self) -> impl Iterator<Item = &LayerDef> {
+        self.layers.iter().map(|l| &l.layer_def)
+    }
+
+    /// Get the zoom range of all layers
+    pub fn zoom_range(&self) -> Option<(u32, u32)> {
+        (!self.layers.is_empty()).then_some((self.zoom_min, self.zoom_max))
+    }
+
+    /// Check if zoom level matches any layer
+    fn check_zoom(&self, zoom: u32) -> bool {
+        zoom >= self.zoom_min && zoom <= self.zoom_max
+    }
+
+    /// Fetch a tile
+    fn fetch_tile(&self, tile_cfg: &TileCfg) -> Result<Tile> {
+        let t = Instant::now();
+        let tile = self.query_tile(tile_cfg)?;
+        self.log_fetched(tile_cfg, tile.compute_size(), t);
+        Ok(tile)
+    }
+
+    /// Log a fetched tile
+    fn log_fetched(&self, tile_cfg: &TileCfg, size: usize, t: Instant) {
+        tile_cfg.log_dropped();
+        let truncated = tile_cfg.truncated.get();
+        if truncated > 0 {
+            log::info!(
+                "{}/{}, fetched {size} bytes in {:.2?} ({truncated} layers \
+                truncated)",
+                self.name(),
+                tile_cfg.tid,
+                t.elapsed()
+            );
+        } else {
+            log::info!(
+                "{}/{}, fetched {size} bytes in {:.2?}",
+                self.name(),
+                tile_cfg.tid,
+                t.elapsed()
+            );
+        }
+    }
+
+    /// Write a tile, encoding each layer as soon as it is queried.
+    ///
+    /// Each layer is written as a single-layer tile, then flushed.  An MVT
+    /// tile is a sequence of layers, so the output is the same as encoding
+    /// the whole tile at once.  With a tile size budget, every layer must
+    /// be queried before any can be written, so the tile is buffered.
+    ///
+    /// Returns `None` if no layers were written.
+    fn write_tile<W: Write>(
+        &self,
+        out: &mut W,
+        tile_cfg: &TileCfg,
+    ) -> Result<Option<StreamedTile>> {
+        let tid = tile_cfg.tid;
+        if self.max_tile_bytes.is_some() {
+            let tile = self.fetch_tile(tile_cfg)?;
+            if tile.num_layers() == 0 {
+                return Ok(None);
+            }
+            return Ok(Some(StreamedTile {
+                tid,
+                size: write_flush(out, &tile)?,
+                truncated: tile_cfg.expired.get(),
+            }));
+        }
+        let t = Instant::now();
+        let extent = tile_cfg.tile_extent.extent();
+        let mut size = 0;
+        let mut n_layers = 0;
+        // empty layers are held until a layer with features is written
+        let mut empty = Vec::new();
+        for layer_tree in &self.layers {
+            if tile_cfg.deadline_passed(0) {
+                break;
+            }
+            let mut tile = Tile::new(extent);
+            let layer = layer_tree.query_tile(&tile, tile_cfg)?;
+            if layer.num_features() > 0 {
+                for tile in empty.drain(..) {
+                    size += write_flush(out, &tile)?;
+                    n_layers += 1;
+                }
+                tile.add_layer(layer)?;
+                size += write_flush(out, &tile)?;
+                n_layers += 1;
+            } else if self.keep_empty(layer_tree, tile_cfg) {
+                tile.add_layer(layer)?;
+                empty.push(tile);
+            }
+        }
+        if n_layers > 0 {
+            for tile in empty {
+                size += write_flush(out, &tile)?;
+                n_layers += 1;
+            }
+        }
+        if self.debug_outline || tile_cfg.expired.get() {
+            let mut tile = Tile::new(extent);
+            let layer = debug_layer(&tile, tile_cfg)?;
+            tile.add_layer(layer)?;
+            size += write_flush(out, &tile)?;
+            n_layers += 1;
+        }
+        self.log_fetched(tile_cfg, size, t);
+        Ok((n_layers > 0).then(|| StreamedTile {
+            tid,
+            size,
+            truncated: tile_cfg.expired.get(),
+        }))
+    }
+
+    /// Query one tile from trees
+    fn query_tile(&self, tile_cfg: &TileCfg) -> Result<Tile> {
+        let mut tile = match self.max_tile_bytes {
+            Some(max_bytes) => self.fit_budget(tile_cfg, max_bytes)?,
+            None => {
+                let mut tile = Tile::new(tile_cfg.tile_extent.extent());
+                self.add_layers(&mut tile, tile_cfg)?;
+                tile
+            }
+        };
+        if self.debug_outline || tile_cfg.expired.get() {
+            let layer = debug_layer(&tile, tile_cfg)?;
+            tile.add_layer(layer)?;
+        }
+        Ok(tile)
+    }
+
+    /// Query one tile, degrading it to fit within the size budget.
+    ///
+    /// Each layer is queried once, keeping its features.  Layers are then
+    /// trimmed in priority order, starting with the last layer in the tile:
+    /// its feature count is cut in half (keeping the largest geometries)
+    /// until the tile fits, or it has no more than `DEGRADE_FLOOR` features.
+    /// Earlier layers are only trimmed after later ones reach the floor.
+    fn fit_budget(&self, tile_cfg: &TileCfg, max_bytes: usize) -> Result<Tile> {
+        let mut kept = Vec::with_capacity(self.layers.len());
+        for layer_tree in &self.layers {
+            if tile_cfg.deadline_passed(0) {
+                kept.push(KeptLayer::default());
+                continue;
+            }
+            kept.push(layer_tree.query_tile_kept(tile_cfg)?);
+        }
+        let any_features = kept.iter().any(|k| !k.is_empty());
+        let mut caps: Vec<usize> = kept.iter().map(KeptLayer::len).collect();
+        let mut sizes = Vec::with_capacity(kept.len());
+        for (i, kept) in kept.iter().enumerate() {
+            sizes.push(self.kept_size(i, kept, caps[i], tile_cfg)?);
+        }
+        let mut degraded = Vec::new();
+        loop {
+            // an MVT tile is a sequence of layers
+            let size: usize = sizes.iter().sum();
+            if size <= max_bytes {
+                break;
+            }
+            if tile_cfg.expired.get() {
+                log::warn!(
+                    "{}/{}: {size} bytes over budget at deadline",
+                    self.name(),
+                    tile_cfg.tid
+                );
+                break;
+            }
+            let Some(i) =
+                (0..caps.len()).rev().find(|i| caps[*i] > DEGRADE_FLOOR)
+            else {
+                log::warn!(
+                    "{}/{}: {size} bytes exceeds budget of {max_bytes}",
+                    self.name(),
+                    tile_cfg.tid
+                );
+                break;
+            };
+            caps[i] /= 2;
+            sizes[i] = self.kept_size(i, &kept[i], caps[i], tile_cfg)?;
+            let name = self.layers[i].layer_def.name();
+            if !degraded.contains(&name) {
+                degraded.push(name);
+            }
+        }
+        // values truncated while sizing layers are counted again below
+        tile_cfg.truncated_values.set(0);
+        let mut tile = Tile::new(tile_cfg.tile_extent.extent());
+        for (i, (layer_tree, kept)) in self.layers.iter().zip(&kept).enumerate()
+        {
+            let name = layer_tree.layer_def.name();
+            if degraded.contains(&name) {
+                // GeoJSON tiles are degraded with the same caps
+                tile_cfg
+                    .feature_caps
+                    .borrow_mut()
+                    .insert(name.to_string(), caps[i]);
+            }
+            if any_features
+                && (!kept.is_empty() || self.keep_empty(layer_tree, tile_cfg))
+            {
+                let layer = tile.create_layer(name);
+                let layer =
+                    kept.build(&layer_tree.layer_def, layer, tile_cfg, caps[i]);
+                tile.add_layer(layer)?;
+            }
+        }
+        if !degraded.is_empty() {
+            log::info!(
+                "{}/{}: degraded to {} bytes ({})",
+                self.name(),
+                tile_cfg.tid,
+                tile.compute_size(),
+                degraded.join(", ")
+            );
+        }
+        Ok(tile)
+    }
+
+    /// Get the encoded size of one layer, built from kept features
+    fn kept_size(
+        &self,
+        i: usize,
+        kept: &KeptLayer,
+        cap: usize,
+        tile_cfg: &TileCfg,
+    ) -> Result<usize> {
+        let layer_tree = &self.layers[i];
+        if kept.is_empty() && !self.keep_empty(layer_tree, tile_cfg) {
+            return Ok(0);
+        }
+        let mut tile = Tile::new(tile_cfg.tile_extent.extent());
+        let name = layer_tree.layer_def.name();
+        let layer = tile.create_layer(name);
+        let layer = kept.build(&layer_tree.layer_def, layer, tile_cfg, cap);
+        tile.add_layer(layer)?;
+        Ok(tile.compute_size())
+    }
+
+    /// Query group layers and add them to a tile
+    fn add_layers(&self, tile: &mut Tile, tile_cfg: &TileCfg) -> Result<()> {
+        let mut any_features = false;
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer_tree in &self.layers {
+            if tile_cfg.deadline_passed(0) {
+                break;
+            }
+            let layer = layer_tree.query_tile(tile, tile_cfg)?;
+            any_features |= layer.num_features() > 0;
+            if layer.num_features() > 0 || self.keep_empty(layer_tree, tile_cfg)
+            {
+                layers.push(layer);
+            }
+        }
+        // a tile with no features is empty, even with empty layers kept
+        if any_features {
+            for layer in layers {
+                tile.add_layer(layer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if an empty layer should be kept in a tile.
+    ///
+    /// With `keep_empty_layers`, layers are kept at zoom levels within
+    /// their range.
+    fn keep_empty(&self, layer_tree: &LayerTree, tile_cfg: &TileCfg) -> bool {
+        self.keep_empty_layers
+            && layer_tree.layer_def.check_zoom(tile_cfg.zoom())
+    }
+
+    /// Check whether a tile may have data, using the tile bitmap.
+    ///
+    /// The bitmap is read from its sidecar file on first use, or built
+    /// from all feature bounds if missing or out of date.
+    fn may_have_data(
+        &self,
+        world: BBox<f64>,
+        tile_extent: TileExtent,
+        tid: TileId,
+    ) -> bool {
+        self.bitmap(world, tile_extent)
+            .map_or(true, |bitmap| bitmap.has_data(tid))
+    }
+
+    /// Get the tile bitmap, if used
+    fn bitmap(
+        &self,
+        world: BBox<f64>,
+        tile_extent: TileExtent,
+    ) -> Option<&TileBitmap> {
+        let zoom = self.bitmap_zoom?;
+        self.bitmap
+            .get_or_init(|| self.load_bitmap(world, tile_extent, zoom))
+            .as_ref()
+    }
+
+    /// Estimate the encoded size of a tile (bytes), using the tile bitmap.
+    ///
+    /// Loam file sizes of layers at the tile's zoom level are scaled by the
+    /// fraction of marked bitmap tiles it covers.
+    fn estimate_size(
+        &self,
+        world: BBox<f64>,
+        tile_extent: TileExtent,
+        tid: TileId,
+    ) -> Option<usize> {
+        let zoom = self.grid.zoom(tid);
+        if !self.check_zoom(zoom) {
+            return Some(0);
+        }
+        let bitmap = self.bitmap(world, tile_extent)?;
+        let bytes: u64 = self.layers.iter().map(|l| l.loam_bytes(zoom)).sum();
+        Some((bytes as f64 * bitmap.fraction(tid) * ENCODED_RATIO) as usize)
+    }
+
+    /// Read the tile bitmap sidecar file, or build (and write) it
+    fn load_bitmap(
+        &self,
+        world: BBox<f64>,
+        tile_extent: TileExtent,
+        zoom: u32,
+    ) -> Option<TileBitmap> {
+        match TileBitmap::read(&self.bitmap_path, zoom, self.bitmap_key) {
+            Ok(Some(bitmap)) => return Some(bitmap),
+            Ok(None) => (),
+            Err(e) => log::warn!("{:?}: {e}", self.bitmap_path),
+        }
+        match self.build_bitmap(world, tile_extent, zoom) {
+            Ok(bitmap) => {
+                if let Err(e) = bitmap.write(&self.bitmap_path, self.bitmap_key)
+                {
+                    log::warn!("{:?}: {e}", self.bitmap_path);
+                }
+                Some(bitmap)
+            }
+            Err(e) => {
+                log::warn!("{}: tile bitmap: {e}", self.name);
+                None
+            }
+        }
+    }
+
+    /// Build the tile bitmap from feature bounds.
+    ///
+    /// Bounds are expanded by the edge buffer of each zoom level, so that
+    /// any feature returned by a tile query marks that tile.  Tiles beyond
+    /// the bitmap zoom use the largest buffer of any higher zoom.
+    fn build_bitmap(
+        &self,
+        world: BBox<f64>,
+        tile_extent: TileExtent,
+        zoom: u32,
+    ) -> Result<TileBitmap> {
+        let t = Instant::now();
+        let width = world.x_max() - world.x_min();
+        let margin =
+            |z: u32| tile_extent.edge_ratio(z) * width / f64::from(z).exp2();
+        let margins: Vec<f64> = (0..=zoom)
+            .map(|z| {
+                if z < zoom {
+                    margin(z)
+                } else {
+                    (z..=ZOOM_MAX).map(margin).fold(0.0, f64::max)
+                }
+            })
+            .collect();
+        let mut bitmap = TileBitmap::new(zoom);
+        for layer in &self.layers {
+            for tree in layer.all_trees() {
+                tree.for_each_bbox(world, |bbox| {
+                    for (z, margin) in margins.iter().enumerate() {
+                        bitmap.mark(world, z as u32, bbox, *margin);
+                    }
+                })?;
+            }
+        }
+        log::info!(
+            "{}: tile bitmap built in {:.2?} ({} tiles at zoom {zoom})",
+            self.name,
+            t.elapsed(),
+            bitmap.count(zoom)
+        );
+        Ok(bitmap)
+    }
+
+    /// Get the extent of all layers
+    fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        let mut extent: Option<BBox<f64>> = None;
+        for layer in &self.layers {
+            if let Some(bbox) = layer.extent(world)? {
+                match &mut extent {
+                    Some(ext) => ext.extend([
+                        (bbox.x_min(), bbox.y_min()),
+                        (bbox.x_max(), bbox.y_max()),
+                    ]),
+                    None => extent = Some(bbox),
+                }
+            }
+        }
+        Ok(extent)
+    }
+
+    /// Query group layers of a tile as GeoJSON.
+    ///
+    /// Returns a `FeatureCollection` for each layer, by name.  With a tile
+    /// size budget, the MVT tile is made first, so that the same layers are
+    /// degraded.
+    fn query_tile_geojson(
+        &self,
+        tile_cfg: &TileCfg,
+    ) -> Result<Map<String, Value>> {
+        let t = Instant::now();
+        if self.max_tile_bytes.is_some() {
+            self.query_tile(tile_cfg)?;
+            tile_cfg.truncated.set(0);
+            tile_cfg.non_finite.set(0);
+            tile_cfg.truncated_values.set(0);
+        }
+        let mut layers = Map::new();
+        let mut any_features = false;
+        for layer_tree in &self.layers {
+            if tile_cfg.deadline_passed(0) {
+                break;
+            }
+            let features = layer_tree.query_tile_geojson(tile_cfg)?;
+            any_features |= !features.is_empty();
+            if !features.is_empty() || self.keep_empty(layer_tree, tile_cfg) {
+                let collection = json!({
+                    "type": "FeatureCollection",
+                    "features": features,
+                });
+                layers.insert(layer_tree.layer_def.name().into(), collection);
+            }
+        }
+        if !any_features {
+            layers.clear();
+        }
+        tile_cfg.log_dropped();
+        log::info!(
+            "{}/{}, fetched GeoJSON ({} layers) in {:.2?}",
+            self.name(),
+            tile_cfg.tid,
+            layers.len(),
+            t.elapsed()
+        );
+        Ok(layers)
+    }
+}
+
+impl StreamedTile {
+    /// Get the tile ID
+    pub fn tid(&self) -> TileId {
+        self.tid
+    }
+
+    /// Get the encoded size (bytes)
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Check if features were cut off at the request deadline.
+    ///
+    /// Truncated tiles also have a `debug` layer feature tagged
+    /// `truncated=1`.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Write a tile and flush, returning its encoded size (bytes)
+fn write_flush<W: Write>(out: &mut W, tile: &Tile) -> Result<usize> {
+    tile.write_to(out)?;
+    out.flush()?;
+    Ok(tile.compute_size())
+}
+
+/// Hash loam file names, sizes and modified times of a group.
+///
+/// The hash is stable as long as the loam files are unchanged, for URL
+/// versions and tile bitmap keys.
+fn loam_hash(group: &LayerGroupCfg, wyrm: &WyrmCfg) -> Result<u64> {
+    // FNV-1a hash of layer names, sizes and modified times
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for layer in &group.layer {
+        for name in loam_names(layer)? {
+            let Ok(meta) = std::fs::metadata(wyrm.loam_path(&name)) else {
+                // empty zoom bucket
+                continue;
+            };
+            let secs = meta
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let bytes = name.bytes().chain(meta.len().to_le_bytes());
+            for b in bytes.chain(secs.to_le_bytes()) {
+                hash ^= u64::from(b);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// Read a tile from a static tile directory (`z/x/y.mvt`)
+fn read_static(
+    dir: &std::path::Path,
+    grid: TileGrid,
+    tid: TileId,
+) -> Result<Option<Vec<u8>>> {
+    let z = grid.zoom(tid);
+    let path = dir.join(format!("{z}/{}/{}.mvt", tid.x(), tid.y()));
+    match std::fs::read(&path) {
+        Ok(tile) => {
+            log::debug!("static tile: {path:?}");
+            Ok(Some(tile))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Make a `debug` layer containing the tile outline
+fn debug_layer(tile: &Tile, tile_cfg: &TileCfg) -> Result<Layer> {
+    let layer = tile.create_layer("debug");
+    let outline = tile_cfg.outline;
+    // clockwise in tile coordinates (exterior ring)
+    let mut enc = GeomEncoder::new(GeomType::Polygon)
+        .bbox(tile_cfg.encode_bbox())
+        .transform(tile_cfg.transform());
+    for pt in [
+        (outline.x_min(), outline.y_max()),
+        (outline.x_max(), outline.y_max()),
+        (outline.x_max(), outline.y_min()),
+        (outline.x_min(), outline.y_min()),
+    ] {
+        let (x, y) = tile_cfg.project(pt);
+        enc.add_point(x, y)?;
+    }
+    enc.complete_geom()?;
+    let mut feature = layer.into_feature(enc.encode()?);
+    let tid = tile_cfg.tid;
+    feature.add_tag_string(
+        "tid",
+        &format!("{}/{}/{}", tile_cfg.zoom(), tid.x(), tid.y()),
+    );
+    if tile_cfg.expired.get() {
+        feature.add_tag_uint("truncated", 1);
+    }
+    Ok(feature.into_layer())
+}
+
+/// Encode a blank tile containing empty layers
+fn blank_tile(layers: &[LayerTree], tile_extent: u32) -> Result<Vec<u8>> {
+    let mut tile = Tile::new(tile_extent);
+    for layer_tree in layers {
+        let layer = tile.create_layer(layer_tree.layer_def.name());
+        tile.add_layer(layer)?;
+    }
+    Ok(tile.to_bytes()?)
+}
+
+impl TryFrom<&WyrmCfg> for Wyrm {
+    type Error = Error;
+
+    fn try_from(wyrm_cfg: &WyrmCfg) -> Result<Self> {
+        // Only Web Mercator supported for now
+        let grid = MapGrid::default();
+        let manifest = Manifest::read(&wyrm_cfg.loam_dir())?;
+        let mut groups = vec![];
+        for group in &wyrm_cfg.layer_group {
+            groups.push(LayerGroup::new(group, wyrm_cfg, manifest.as_ref())?);
+        }
+        Ok(Wyrm {
+            grid,
+            tile_extent: TileExtent::new(
+                wyrm_cfg.tile_extent,
+                wyrm_cfg.edge_extent,
+            ),
+            groups,
+            empty_tile: wyrm_cfg.empty_tile,
+            stream_threshold: wyrm_cfg
+                .stream_threshold
+                .unwrap_or(STREAM_THRESHOLD),
+            geojson_tiles: wyrm_cfg.http.geojson_tiles,
+            max_batch_points: wyrm_cfg
+                .max_batch_points
+                .unwrap_or(MAX_BATCH_POINTS),
+            manifest,
+        })
+    }
+}
+
+impl Wyrm {
+    /// Make a Web Mercator bounding box around a WGS84 position.
+    ///
+    /// * `lat` Latitude (-90 to 90 degrees).
+    /// * `lon` Longitude (-180 to 180 degrees).
+    /// * `radius_m` Radius (meters), scaled for latitude.
+    pub fn bbox_around(lat: f64, lon: f64, radius_m: f64) -> Result<BBox<f64>> {
+        bbox_around(lat, lon, radius_m)
+    }
+
+    /// Query features in a bounding box
+    pub fn query_features(&self, bbox: BBox<f64>) -> Result<()> {
+        for group in &self.groups {
+            log::debug!("query_features group: {:?}", group.name);
+            for layer in &group.layers {
+                layer.query_features(bbox)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Query features in a bounding box as GeoJSON.
+    ///
+    /// Returns a `FeatureCollection`, with WGS84 coordinates.  Each
+    /// feature has a `layer` property naming its source layer.
+    pub fn query_geojson(&self, bbox: BBox<f64>) -> Result<String> {
+        let mut features = Vec::new();
+        for group in &self.groups {
+            for layer in &group.layers {
+                layer.query_geojson(bbox, &mut features)?;
+            }
+        }
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": features,
+        });
+        Ok(collection.to_string())
+    }
+
+    /// Query features near a point as GeoJSON.
+    ///
+    /// See [Wyrm::query_geojson].
+    ///
+    /// * `lat` Latitude (degrees).
+    /// * `lon` Longitude (degrees).
+    /// * `radius_m` Radius (meters), scaled for latitude.
+    pub fn query_geojson_near(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+    ) -> Result<String> {
+        self.query_geojson(bbox_around(lat, lon, radius_m)?)
+    }
+
+    /// Fetch one tile.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    pub fn fetch_tile<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+    ) -> Result<()> {
+        self.fetch_tile_ctx(out, group_name, tid, &RequestCtx::default())
+    }
+
+    /// Fetch one tile, filtering features.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `filter` Feature filter (see [FeatureFilter]).
+    pub fn fetch_tile_filtered<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+        filter: &FeatureFilter,
+    ) -> Result<()> {
+        let ctx = RequestCtx {
+            filter: Some(filter),
+            ..Default::default()
+        };
+        self.fetch_tile_ctx(out, group_name, tid, &ctx)
+    }
+
+    /// Fetch one tile, with a scaled tile extent.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `scale` Tile extent multiplier (ex. `2` for 512px tiles), limited
+    ///   to 1-4.
+    pub fn fetch_tile_scaled<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+        scale: u32,
+    ) -> Result<()> {
+        let ctx = RequestCtx {
+            scale: Some(scale),
+            ..Default::default()
+        };
+        self.fetch_tile_ctx(out, group_name, tid, &ctx)
+    }
+
+    /// Fetch one tile, within a time budget.
+    ///
+    /// Once `deadline` passes, no more features are added; layers already
+    /// queried are kept, and a `debug` layer feature is tagged
+    /// `truncated=1`.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `deadline` Deadline for making the tile.
+    pub fn fetch_tile_deadline<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+        deadline: Instant,
+    ) -> Result<()> {
+        let ctx = RequestCtx {
+            deadline: Some(deadline),
+            ..Default::default()
+        };
+        self.fetch_tile_ctx(out, group_name, tid, &ctx)
+    }
+
+    /// Fetch one tile, with a request context.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `ctx` Request context.
+    ///
+    /// Returns `Error::TileEmpty` or `Error::UnknownGroupName` when no tile
+    /// is written; see [try_fetch_tile_ctx](Self::try_fetch_tile_ctx).
+    pub fn fetch_tile_ctx<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+        ctx: &RequestCtx,
+    ) -> Result<()> {
+        match self.try_fetch_tile_ctx(group_name, tid, ctx)? {
+            TileFetch::Tile(tile) => Ok(out.write_all(&tile)?),
+            TileFetch::Empty => Err(Error::TileEmpty()),
+            TileFetch::UnknownGroup => Err(Error::UnknownGroupName()),
+        }
+    }
+
+    /// Fetch one tile.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    ///
+    /// Empty tiles and unknown groups are not errors; `Err` is only
+    /// returned for real failures (I/O, corrupt loam files, etc).
+    pub fn try_fetch_tile(
+        &self,
+        group_name: &str,
+        tid: TileId,
+    ) -> Result<TileFetch> {
+        self.try_fetch_tile_ctx(group_name, tid, &RequestCtx::default())
+    }
+
+    /// Fetch one tile, without blocking the async runtime.
+    ///
+    /// Reading file-backed trees blocks, so the fetch runs on the `tokio`
+    /// blocking thread pool.  See [Wyrm::try_fetch_tile].
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    #[cfg(feature = "async")]
+    pub async fn fetch_tile_async(
+        self: Arc<Self>,
+        group_name: String,
+        tid: TileId,
+    ) -> Result<TileFetch> {
+        self.run_blocking(move |wyrm| wyrm.try_fetch_tile(&group_name, tid))
+            .await?
+    }
+
+    /// Run a blocking function on the `tokio` blocking thread pool.
+    ///
+    /// Use for any query which reads file-backed trees from an async task.
+    /// A panic in the function is `Error::BlockingTask`.
+    #[cfg(feature = "async")]
+    pub async fn run_blocking<T, F>(self: Arc<Self>, f: F) -> Result<T>
+    where
+        F: FnOnce(Arc<Self>) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        tokio::task::spawn_blocking(move || f(self))
+            .await
+            .map_err(|e| Error::BlockingTask(e.to_string()))
+    }
+
+    /// Fetch one tile, with a request context.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `ctx` Request context.
+    pub fn try_fetch_tile_ctx(
+        &self,
+        group_name: &str,
+        tid: TileId,
+        ctx: &RequestCtx,
+    ) -> Result<TileFetch> {
+        let mut out = Vec::new();
+        Ok(match self.write_tile_stream(&mut out, group_name, tid, ctx)? {
+            TileFetch::Tile(_tile) => TileFetch::Tile(out),
+            TileFetch::Empty => TileFetch::Empty,
+            TileFetch::UnknownGroup => TileFetch::UnknownGroup,
+        })
+    }
+
+    /// Write one tile, streaming each layer as soon as it is encoded.
+    ///
+    /// The writer is flushed after each layer, so the first bytes are
+    /// written before later layers are queried.  If the deadline passes,
+    /// remaining layers are skipped and a `debug` layer feature is tagged
+    /// `truncated=1`, but earlier layers have already been written.
+    ///
+    /// Nothing is written for empty tiles or unknown groups.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `ctx` Request context.
+    pub fn write_tile_stream<W: Write>(
+        &self,
+        out: &mut W,
+        group_name: &str,
+        tid: TileId,
+        ctx: &RequestCtx,
+    ) -> Result<TileFetch<StreamedTile>> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::info_span!("fetch_tile", group = group_name, tid = %tid)
+                .entered();
+        for group in &self.groups {
+            if group.is_named(group_name) {
+                let grid = group.grid();
+                if !group.check_zoom(grid.zoom(tid)) {
+                    log::debug!("tile {tid} empty (zoom out of range)");
+                    return Ok(TileFetch::Empty);
+                }
+                if grid.is_polar(tid) {
+                    log::debug!("tile {tid} empty (beyond Web Mercator)");
+                    return Ok(TileFetch::Empty);
+                }
+                if !group.may_have_data(self.grid.bbox(), self.tile_extent, tid)
+                {
+                    log::debug!("tile {tid} empty (tile bitmap)");
+                    return Ok(TileFetch::Empty);
+                }
+                let tile_cfg = self.tile_config(tid, ctx, grid)?;
+                return match group.write_tile(out, &tile_cfg)? {
+                    Some(tile) => Ok(TileFetch::Tile(tile)),
+                    None => {
+                        log::debug!("tile {tid} empty (no layers)");
+                        Ok(TileFetch::Empty)
+                    }
+                };
+            }
+        }
+        log::debug!("unknown group name: {}", group_name);
+        Ok(TileFetch::UnknownGroup)
+    }
+
+    /// Fetch one tile as GeoJSON, for clients which cannot decode MVT.
+    ///
+    /// Features are queried, clipped and capped the same as MVT tiles.
+    /// The result is a JSON object, with a `FeatureCollection` for each
+    /// layer by name.  Coordinates are WGS84, and properties are the
+    /// layer's included tags.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    /// * `ctx` Request context.
+    pub fn fetch_tile_geojson(
+        &self,
+        group_name: &str,
+        tid: TileId,
+        ctx: &RequestCtx,
+    ) -> Result<TileFetch<String>> {
+        let Some(group) = self.groups.iter().find(|g| g.is_named(group_name))
+        else {
+            log::debug!("unknown group name: {}", group_name);
+            return Ok(TileFetch::UnknownGroup);
+        };
+        let grid = group.grid();
+        if !group.check_zoom(grid.zoom(tid))
+            || grid.is_polar(tid)
+            || !group.may_have_data(self.grid.bbox(), self.tile_extent, tid)
+        {
+            log::debug!("tile {tid} empty");
+            return Ok(TileFetch::Empty);
+        }
+        let tile_cfg = self.tile_config(tid, ctx, grid)?;
+        let layers = group.query_tile_geojson(&tile_cfg)?;
+        if layers.is_empty() {
+            log::debug!("tile {tid} empty (no layers)");
+            return Ok(TileFetch::Empty);
+        }
+        Ok(TileFetch::Tile(Value::Object(layers).to_string()))
+    }
+
+    /// Fetch one tile, combining layers of multiple groups.
+    ///
+    /// * `out` Writer to write MVT data.
+    /// * `group_names` Names of layer groups.
+    /// * `tid` Tile ID.
+    pub fn fetch_tile_multi<W: Write>(
+        &self,
+        out: &mut W,
+        group_names: &[&str],
+        tid: TileId,
+    ) -> Result<()> {
+        let ctx = RequestCtx::default();
+        match self.try_fetch_tile_multi_ctx(group_names, tid, &ctx)? {
+            TileFetch::Tile(tile) => Ok(out.write_all(&tile)?),
+            TileFetch::Empty => Err(Error::TileEmpty()),
+            TileFetch::UnknownGroup => Err(Error::UnknownGroupName()),
+        }
+    }
+
+    /// Fetch one tile combining layers of multiple groups, with a request
+    /// context.
+    ///
+    /// The tile is only empty if every group is empty.  Layer names must be
+    /// unique across all groups (`Error::DuplicateLayerName`).
+    ///
+    /// * `group_names` Names of layer groups.
+    /// * `tid` Tile ID.
+    /// * `ctx` Request context.
+    pub fn try_fetch_tile_multi_ctx(
+        &self,
+        group_names: &[&str],
+        tid: TileId,
+        ctx: &RequestCtx,
+    ) -> Result<TileFetch> {
+        let mut groups = Vec::with_capacity(group_names.len());
+        let mut layer_names = HashMap::new();
+        for name in group_names {
+            let Some(group) = self.groups.iter().find(|g| g.is_named(name))
+            else {
+                log::debug!("unknown group name: {name}");
+                return Ok(TileFetch::UnknownGroup);
+            };
+            for layer in group.layers() {
+                if let Some(other) = layer_names.insert(layer.name(), name) {
+                    return Err(Error::DuplicateLayerName(format!(
+                        "{} (groups {other} and {name})",
+                        layer.name()
+                    )));
+                }
+            }
+            groups.push(group);
+        }
+        let grid = groups.first().map_or(TileGrid::default(), |g| g.grid());
+        if let Some(group) = groups.iter().find(|g| g.grid() != grid) {
+            return Err(Error::GridMismatch(format!(
+                "{} (groups {} and {})",
+                tid,
+                group_names[0],
+                group.name()
+            )));
+        }
+        if grid.is_polar(tid) {
+            log::debug!("composite tile {tid} empty (beyond Web Mercator)");
+            return Ok(TileFetch::Empty);
+        }
+        let tile_cfg = self.tile_config(tid, ctx, grid)?;
+        let mut tile = Tile::new(tile_cfg.tile_extent.extent());
+        let world = self.grid.bbox();
+        for group in &groups {
+            if group.check_zoom(tile_cfg.zoom())
+                && group.may_have_data(world, self.tile_extent, tid)
+            {
+                group.add_layers(&mut tile, &tile_cfg)?;
+            }
+        }
+        tile_cfg.log_dropped();
+        if groups.iter().any(|g| g.debug_outline) || tile_cfg.expired.get() {
+            let layer = debug_layer(&tile, &tile_cfg)?;
+            tile.add_layer(layer)?;
+        }
+        if tile.num_layers() > 0 {
+            Ok(TileFetch::Tile(tile.to_bytes()?))
+        } else {
+            log::debug!("composite tile {tid} empty (no layers)");
+            Ok(TileFetch::Empty)
+        }
+    }
+
+    /// Get the zoom range of a layer group.
+    ///
+    /// Returns `None` for unknown group names or groups with no layers.
+    pub fn group_zoom(&self, group_name: &str) -> Option<(u32, u32)> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .and_then(LayerGroup::zoom_range)
+    }
+
+    /// Get the configured response for empty tiles
+    pub fn empty_tile(&self) -> EmptyTile {
+        self.empty_tile
+    }
+
+    /// Get the tile size (bytes) for streaming responses
+    pub fn stream_threshold(&self) -> usize {
+        self.stream_threshold
+    }
+
+    /// Estimate the encoded size of a tile (bytes), before querying it.
+    ///
+    /// The estimate is rough, from loam file sizes and the group's tile
+    /// bitmap.  It is meant for choosing whether to stream a response,
+    /// since a tile's actual size is only known once it is written.
+    ///
+    /// Returns `None` for unknown groups, or groups without a tile bitmap.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tid` Tile ID.
+    pub fn estimate_tile_size(
+        &self,
+        group_name: &str,
+        tid: TileId,
+    ) -> Option<usize> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))?
+            .estimate_size(self.grid.bbox(), self.tile_extent, tid)
+    }
+
+    /// Check if GeoJSON tiles are served
+    pub fn geojson_tiles(&self) -> bool {
+        self.geojson_tiles
+    }
+
+    /// Get the maximum points in a batch query request
+    pub fn max_batch_points(&self) -> usize {
+        self.max_batch_points
+    }
+
+    /// Get a blank tile for a layer group.
+    ///
+    /// The tile contains all layers of the group, with no features.
+    pub fn empty_tile_bytes(&self, group_name: &str) -> Result<&[u8]> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .map(|g| &g.blank[..])
+            .ok_or(Error::UnknownGroupName())
+    }
+
+    /// Validate polygon layers of a group.
+    ///
+    /// Returns a report for each polygon layer.
+    pub fn validate(&self, group_name: &str) -> Result<Vec<ValidationReport>> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let world = self.grid.bbox();
+        let mut reports = Vec::new();
+        for layer in &group.layers {
+            if layer.layer_def.geom_tp() == GeomType::Polygon {
+                reports.push(layer.validate(world)?);
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Export a layer as a streamed GeoJSON FeatureCollection.
+    ///
+    /// Returns the number of features written.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `layer_name` Name of layer.
+    /// * `bbox` Web Mercator bounding box (entire grid if `None`).
+    /// * `out` Writer for GeoJSON.
+    pub fn export_geojson<W: Write>(
+        &self,
+        group_name: &str,
+        layer_name: &str,
+        bbox: Option<BBox<f64>>,
+        mut out: W,
+    ) -> Result<usize> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let layer = group
+            .layers
+            .iter()
+            .find(|l| l.layer_def.name().eq_ignore_ascii_case(layer_name))
+            .ok_or_else(|| Error::UnknownLayerName(layer_name.into()))?;
+        let bbox = bbox.unwrap_or_else(|| self.grid.bbox());
+        let mut n_features = 0;
+        write_collection_start(&mut out)?;
+        for tree in layer.all_trees() {
+            tree.export_features(
+                &layer.layer_def,
+                bbox,
+                &mut out,
+                &mut n_features,
+            )?;
+        }
+        write_collection_end(&mut out)?;
+        Ok(n_features)
+    }
+
+    /// Query features of a layer near a batch of points.
+    ///
+    /// Points are bucketed into a coarse grid, with one tree query for
+    /// each cell, so nearby points share traversal work.  Returns matches
+    /// for each point, in input order.  Features without a stored `osm_id`
+    /// are not included.
+    ///
+    /// * `layer_name` Name of layer (first match in any group).
+    /// * `points` WGS84 positions (longitude, latitude).
+    /// * `radius_m` Search radius (meters).
+    pub fn query_batch(
+        &self,
+        layer_name: &str,
+        points: &[(f64, f64)],
+        radius_m: f64,
+    ) -> Result<Vec<Vec<FeatureInfo>>> {
+        let layer = self
+            .groups
+            .iter()
+            .flat_map(|g| g.layers.iter())
+            .find(|l| l.layer_def.name().eq_ignore_ascii_case(layer_name))
+            .ok_or_else(|| Error::UnknownLayerName(layer_name.into()))?;
+        let mut cells = BTreeMap::<(i64, i64), Vec<(usize, BBox<f64>)>>::new();
+        for (i, (lon, lat)) in points.iter().enumerate() {
+            let bbox = bbox_around(*lat, *lon, radius_m)?;
+            let x = (bbox.x_min() + bbox.x_max()) / 2.0;
+            let y = (bbox.y_min() + bbox.y_max()) / 2.0;
+            let cell = (
+                (x / BATCH_CELL).floor() as i64,
+                (y / BATCH_CELL).floor() as i64,
+            );
+            cells.entry(cell).or_default().push((i, bbox));
+        }
+        let mut matches = Vec::with_capacity(points.len());
+        matches.resize_with(points.len(), Vec::new);
+        for pts in cells.values() {
+            layer.query_batch(pts, &mut matches)?;
+        }
+        Ok(matches)
+    }
+
+    /// Find a feature by OSM ID, using the layer's ID index.
+    ///
+    /// The bounding box from the index is used to query the layer tree,
+    /// matching the stored `osm_id` tag.  Returns `None` if no feature
+    /// has that ID.
+    ///
+    /// * `layer_name` Name of layer (first match in any group).
+    /// * `osm_id` OSM ID of node, way or relation.
+    pub fn find_feature(
+        &self,
+        layer_name: &str,
+        osm_id: i64,
+    ) -> Result<Option<FeatureInfo>> {
+        let layer = self
+            .groups
+            .iter()
+            .flat_map(|g| g.layers.iter())
+            .find(|l| l.layer_def.name().eq_ignore_ascii_case(layer_name))
+            .ok_or_else(|| Error::UnknownLayerName(layer_name.into()))?;
+        layer.find_feature(osm_id)
+    }
+
+    /// Get an iterator of tile IDs covering the data extent of a group.
+    ///
+    /// Tiles are in row-major order, by zoom level.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `zoom` Range of zoom levels (limited to group's zoom range).
+    pub fn tile_iter(
+        &self,
+        group_name: &str,
+        zoom: RangeInclusive<u32>,
+    ) -> Result<impl Iterator<Item = TileId>> {
+        self.tile_iter_bbox(group_name, zoom, None)
+    }
+
+    /// Get an iterator of tile IDs covering the data extent of a group,
+    /// within a bounding box.
+    ///
+    /// Tiles are in row-major order, by zoom level.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `zoom` Range of zoom levels (limited to group's zoom range).
+    /// * `bbox` Web Mercator bounding box (`None` for whole extent).
+    pub fn tile_iter_bbox(
+        &self,
+        group_name: &str,
+        zoom: RangeInclusive<u32>,
+        bbox: Option<BBox<f64>>,
+    ) -> Result<impl Iterator<Item = TileId>> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let world = self.grid.bbox();
+        let mut extent = group.extent(world)?;
+        if let Some(bbox) = bbox {
+            extent = extent.and_then(|ext| {
+                let x0 = ext.x_min().max(bbox.x_min());
+                let y0 = ext.y_min().max(bbox.y_min());
+                let x1 = ext.x_max().min(bbox.x_max());
+                let y1 = ext.y_max().min(bbox.y_max());
+                (x0 <= x1 && y0 <= y1).then(|| BBox::new([(x0, y0), (x1, y1)]))
+            });
+        }
+        let (zmin, zmax) = match (extent, group.zoom_range()) {
+            (Some(_), Some((zmin, zmax))) => (
+                (*zoom.start()).max(zmin),
+                (*zoom.end()).min(zmax).min(ZOOM_MAX),
+            ),
+            // no data: empty range
+            _ => (1, 0),
+        };
+        let extent = extent.unwrap_or(world);
+        let grid = group.grid();
+        Ok((zmin..=zmax)
+            .flat_map(move |z| covering_tiles(world, extent, z, grid)))
+    }
+
+    /// Get a random sample of tile IDs covering the data extent of a group.
+    ///
+    /// Tiles are sorted by zoom level, without duplicates.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `zoom` Range of zoom levels (limited to group's zoom range).
+    /// * `n_tiles` Number of tiles to sample at each zoom level.
+    /// * `seed` Random seed.
+    pub fn sample_tiles(
+        &self,
+        group_name: &str,
+        zoom: RangeInclusive<u32>,
+        n_tiles: usize,
+        seed: u64,
+    ) -> Result<Vec<TileId>> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let world = self.grid.bbox();
+        let (Some(extent), Some((zmin, zmax))) =
+            (group.extent(world)?, group.zoom_range())
+        else {
+            return Ok(Vec::new());
+        };
+        let zmin = (*zoom.start()).max(zmin);
+        let zmax = (*zoom.end()).min(zmax).min(ZOOM_MAX);
+        let grid = group.grid();
+        let mut rng = SplitMix::new(seed);
+        let mut tids = Vec::new();
+        for z in zmin..=zmax {
+            let (xs, ys, tz) = covering_range(world, extent, z, grid);
+            let mut sample: Vec<_> = (0..n_tiles)
+                .filter_map(|_| {
+                    let x = rng.in_range(*xs.start(), *xs.end());
+                    let y = rng.in_range(*ys.start(), *ys.end());
+                    TileId::new(x, y, tz).ok()
+                })
+                .collect();
+            sample.sort_by_key(|tid| (tid.x(), tid.y()));
+            sample.dedup();
+            tids.extend(sample);
+        }
+        Ok(tids)
+    }
+
+    /// Analyze encoding of sampled tiles, for choosing tile and edge
+    /// extents.
+    ///
+    /// Tiles are encoded with the largest candidate edge, recording the
+    /// vertex count of each feature, quantization error at each candidate
+    /// tile extent, and vertices within each candidate edge buffer.
+    ///
+    /// * `group_name` Name of layer group.
+    /// * `tids` Sampled tile IDs (see [sample_tiles](Self::sample_tiles)).
+    pub fn analyze(
+        &self,
+        group_name: &str,
+        tids: &[TileId],
+    ) -> Result<ExtentAnalysis> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let ctx = RequestCtx::default();
+        let extent = self.tile_extent.extent();
+        let edge = CANDIDATE_EDGES[CANDIDATE_EDGES.len() - 1];
+        let edge = (edge * f64::from(extent)).ceil() as u32;
+        let tile_extent = TileExtent::new(extent, Some(edge));
+        let mut zooms = BTreeMap::new();
+        for tid in tids {
+            let mut tile_cfg = match self.tile_config_extent(
+                *tid,
+                &ctx,
+                group.grid(),
+                tile_extent,
+            ) {
+                Ok(tile_cfg) => tile_cfg,
+                Err(Error::TileOutOfRange(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let zoom = tile_cfg.zoom();
+            let outline = tile_cfg.outline;
+            let (_lon, lat) = lon_lat(
+                (outline.x_min() + outline.x_max()) / 2.0,
+                (outline.y_min() + outline.y_max()) / 2.0,
+            );
+            let tile_m =
+                (outline.x_max() - outline.x_min()) * lat.to_radians().cos();
+            tile_cfg.stats = Some(RefCell::new(EncodeStats::new(zoom, tile_m)));
+            let mut tile = Tile::new(extent);
+            group.add_layers(&mut tile, &tile_cfg)?;
+            if let Some(stats) = tile_cfg.stats.take() {
+                zooms
+                    .entry(zoom)
+                    .or_insert_with(|| ZoomAnalysis::new(zoom))
+                    .merge(stats.into_inner().finish());
+            }
+        }
+        Ok(ExtentAnalysis {
+            group: group_name.to_string(),
+            tile_extent: extent,
+            zooms: zooms.into_values().collect(),
+        })
+    }
+
+    /// Build tile bitmaps for all groups, writing sidecar files.
+    ///
+    /// Bitmaps are otherwise built on first use, when missing or out of
+    /// date (after a dig).
+    pub fn build_tile_bitmaps(&self) -> Result<()> {
+        let world = self.grid.bbox();
+        for group in &self.groups {
+            if let Some(zoom) = group.bitmap_zoom {
+                let bitmap =
+                    group.build_bitmap(world, self.tile_extent, zoom)?;
+                bitmap.write(&group.bitmap_path, group.bitmap_key)?;
+                let _ = group.bitmap.set(Some(bitmap));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the time budget for making tiles of a layer group
+    pub fn tile_deadline(&self, group_name: &str) -> Option<Duration> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .and_then(|g| g.deadline)
+    }
+
+    /// Get the tile grid of a layer group
+    pub fn tile_grid(&self, group_name: &str) -> Option<TileGrid> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .map(LayerGroup::grid)
+    }
+
+    /// Read a pre-rendered tile from a group's static tile directory.
+    ///
+    /// Missing tiles are made from a parent tile, up to `static_overzoom`
+    /// levels above.  Returns `None` if the group has no static directory,
+    /// or the tile (and parents) are not present.
+    pub fn static_tile(
+        &self,
+        group_name: &str,
+        tid: TileId,
+    ) -> Result<Option<Vec<u8>>> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .ok_or(Error::UnknownGroupName())?;
+        let Some(dir) = &group.static_tiles else {
+            return Ok(None);
+        };
+        if let Some(tile) = read_static(dir, group.grid(), tid)? {
+            return Ok(Some(tile));
+        }
+        // overzoom from the nearest parent tile
+        let levels = group.static_overzoom.min(group.grid().zoom(tid));
+        for dz in 1..=levels {
+            let parent =
+                TileId::new(tid.x() >> dz, tid.y() >> dz, tid.z() - dz)?;
+            if let Some(tile) = read_static(dir, group.grid(), parent)? {
+                log::debug!("overzoom {tid:?} from {parent:?}");
+                let extent = self.tile_extent.extent();
+                return Ok(Some(overzoom(&tile, parent, tid, extent)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the tile URL version of a layer group
+    pub fn group_version(&self, group_name: &str) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|g| g.is_named(group_name))
+            .and_then(LayerGroup::version)
+    }
+
+    /// Get an iterator of all layer groups
+    pub fn groups(&self) -> impl Iterator<Item = &LayerGroup> {
+        self.groups.iter()
+    }
+
+    /// Get the dig manifest (if loam files have one)
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Create a wyrm with no layer groups, for tests
+    #[cfg(test)]
+    pub(crate) fn with_tile_extent(tile_extent: TileExtent) -> Self {
+        Wyrm {
+            grid: MapGrid::default(),
+            tile_extent,
+            groups: Vec::new(),
+            empty_tile: EmptyTile::default(),
+            stream_threshold: STREAM_THRESHOLD,
+            geojson_tiles: false,
+            max_batch_points: MAX_BATCH_POINTS,
+            manifest: None,
+        }
+    }
+
+    /// Create Web Mercator tile config for a tile ID, for tests
+    #[cfg(test)]
+    pub(crate) fn test_tile_config<'a>(
+        &self,
+        tid: TileId,
+        ctx: &'a RequestCtx<'a>,
+    ) -> Result<TileCfg<'a>> {
+        self.tile_config(tid, ctx, TileGrid::WebMercator)
+    }
+
+    /// Create tile config for a tile ID.
+    ///
+    /// Tiles with non-finite or degenerate bounds, bounds outside the grid,
+    /// or a transform producing non-finite coordinates are
+    /// `Error::TileOutOfRange`.
+    ///
+    /// For `wgs84` grids, the outline is the Web Mercator bounding box of
+    /// the tile, clamped to the Web Mercator latitude limit.
+    fn tile_config<'a>(
+        &self,
+        tid: TileId,
+        ctx: &'a RequestCtx<'a>,
+        grid: TileGrid,
+    ) -> Result<TileCfg<'a>> {
+        let tile_extent = self.tile_extent.scaled(ctx.scale.unwrap_or(1));
+        self.tile_config_extent(tid, ctx, grid, tile_extent)
+    }
+
+    /// Create tile config for a tile ID, with a tile extent
+    fn tile_config_extent<'a>(
+        &self,
+        tid: TileId,
+        ctx: &'a RequestCtx<'a>,
+        grid: TileGrid,
+        tile_extent: TileExtent,
+    ) -> Result<TileCfg<'a>> {
+        let (outline, transform, snap) = match grid {
+            TileGrid::WebMercator => (
+                self.grid.tile_bbox(tid),
+                tile_extent.transform(&self.grid, tid),
+                tile_extent.grid_snap(&self.grid, tid.z()),
+            ),
+            TileGrid::Wgs84 => {
+                if tid.z() == 0 || tid.y() >= 1 << (tid.z() - 1) {
+                    return Err(Error::TileOutOfRange(tid));
+                }
+                (
+                    wgs84_outline(tid),
+                    tile_extent.transform_wgs84(tid),
+                    tile_extent.grid_snap_wgs84(grid.zoom(tid)),
+                )
+            }
+        };
+        if !check_outline(self.grid.bbox(), outline, transform, grid) {
+            log::warn!("tile {tid} out of range: {outline:?}");
+            return Err(Error::TileOutOfRange(tid));
+        }
+        let bbox = tile_extent.clip_bbox(outline, grid.zoom(tid));
+        let encode_bbox = BBox::new([
+            grid.project((bbox.x_min(), bbox.y_min())),
+            grid.project((bbox.x_max(), bbox.y_max())),
+        ]);
+        Ok(TileCfg {
+            tile_extent,
+            tid,
+            bbox,
+            outline,
+            grid,
+            encode_bbox,
+            transform,
+            snap,
+            ctx,
+            truncated: Cell::new(0),
+            non_finite: Cell::new(0),
+            truncated_values: Cell::new(0),
+            feature_caps: RefCell::new(HashMap::new()),
+            stats: None,
+            expired: Cell::new(false),
+        })
+    }
+}
+
+/// Check that a tile outline is finite, non-degenerate and within the
+/// world bounds, with finite tile coordinates at its corners
+fn check_outline(
+    world: BBox<f64>,
+    outline: BBox<f64>,
+    t: Transform<f64>,
+    grid: TileGrid,
+) -> bool {
+    let corners = [
+        (outline.x_min(), outline.y_min()),
+        (outline.x_max(), outline.y_max()),
+    ];
+    let finite = corners.iter().all(|(x, y)| {
+        let (px, py) = grid.project((*x, *y));
+        let p = t * Pt::new(px, py);
+        x.is_finite() && y.is_finite() && p.x.is_finite() && p.y.is_finite()
+    });
+    finite
+        && outline.x_min() < outline.x_max()
+        && outline.y_min() < outline.y_max()
+        && outline.x_min() >= world.x_min() - OUTLINE_TOLERANCE
+        && outline.y_min() >= world.y_min() - OUTLINE_TOLERANCE
+        && outline.x_max() <= world.x_max() + OUTLINE_TOLERANCE
+        && outline.y_max() <= world.y_max() + OUTLINE_TOLERANCE
+}
+
+/// Get tile IDs covering a bounding box at one zoom level of a grid
+fn covering_tiles(
+    world: BBox<f64>,
+    bbox: BBox<f64>,
+    z: u32,
+    grid: TileGrid,
+) -> impl Iterator<Item = TileId> {
+    let (xs, ys, tz) = covering_range(world, bbox, z, grid);
+    ys.flat_map(move |y| {
+        xs.clone().filter_map(move |x| TileId::new(x, y, tz).ok())
+    })
+}
+
+/// Get tile X and Y ranges covering a bounding box at one zoom level of a
+/// grid, with the tile zoom level
+fn covering_range(
+    world: BBox<f64>,
+    bbox: BBox<f64>,
+    z: u32,
+    grid: TileGrid,
+) -> (RangeInclusive<u32>, RangeInclusive<u32>, u32) {
+    let (world, nx, ny) = match grid {
+        TileGrid::WebMercator => (world, 1u32 << z, 1u32 << z),
+        TileGrid::Wgs84 => {
+            (BBox::new([(-180.0, -90.0), (180.0, 90.0)]), 2u32 << z, 1u32 << z)
+        }
+    };
+    let bbox = BBox::new([
+        grid.project((bbox.x_min(), bbox.y_min())),
+        grid.project((bbox.x_max(), bbox.y_max())),
+    ]);
+    let width = world.x_max() - world.x_min();
+    let height = world.y_max() - world.y_min();
+    let tile = |v: f64, n: u32| {
+        (v * f64::from(n)).floor().clamp(0.0, f64::from(n - 1)) as u32
+    };
+    let x0 = tile((bbox.x_min() - world.x_min()) / width, nx);
+    let x1 = tile((bbox.x_max() - world.x_min()) / width, nx);
+    // tile Y increases southward
+    let y0 = tile((world.y_max() - bbox.y_max()) / height, ny);
+    let y1 = tile((world.y_max() - bbox.y_min()) / height, ny);
+    (x0..=x1, y0..=y1, grid.tile_zoom(z))
+}
+
+impl TileExtent {
+    /// Create a new tile extent.
+    ///
+    /// * `extent` Width and height in pixels.
+    /// * `edge` Edge buffer in pixels (`None` for zoom-based default).
+    pub fn new(extent: u32, edge: Option<u32>) -> Self {
+        TileExtent { extent, edge }
+    }
+
+    /// Get extent scaled by a multiplier (edge is scaled too).
+    ///
+    /// The multiplier is limited to 1-4, and the result saturates instead
+    /// of overflowing.
+    pub fn scaled(self, scale: u32) -> Self {
+        let scale = scale.clamp(1, SCALE_MAX);
+        TileExtent {
+            extent: self.extent.saturating_mul(scale),
+            edge: self.edge.map(|e| e.saturating_mul(scale)),
+        }
+    }
+
+    /// Get the width and height in pixels
+    pub fn extent(&self) -> u32 {
+        self.extent
+    }
+
+    /// Get edge as a ratio of tile size at a zoom level
+    pub fn edge_ratio(&self, zoom: u32) -> f64 {
+        match self.edge {
+            Some(edge) => f64::from(edge) / f64::from(self.extent.max(1)),
+            None => zoom_edge(zoom),
+        }
+    }
+
+    /// Get clipping bounding box, expanding a tile outline by the edge
+    pub fn clip_bbox(&self, outline: BBox<f64>, zoom: u32) -> BBox<f64> {
+        let edge = self.edge_ratio(zoom);
+        let edge_x = edge * (outline.x_max() - outline.x_min());
+        let edge_y = edge * (outline.y_max() - outline.y_min());
+        BBox::new([
+            (outline.x_min() - edge_x, outline.y_min() - edge_y),
+            (outline.x_max() + edge_x, outline.y_max() + edge_y),
+        ])
+    }
+
+    /// Get transform from spatial to tile coordinates
+    pub fn transform(&self, grid: &MapGrid, tid: TileId) -> Transform<f64> {
+        let ts = f64::from(self.extent);
+        grid.tile_transform(tid).scale(ts, ts)
+    }
+
+    /// Get vertex snapping grid at a zoom level
+    pub fn grid_snap(&self, grid: &MapGrid, zoom: u32) -> GridSnap {
+        let world = grid.bbox();
+        let n = f64::from(zoom).exp2() * f64::from(self.extent);
+        GridSnap {
+            x0: world.x_min(),
+            y0: world.y_max(),
+            dx: (world.x_max() - world.x_min()) / n,
+            dy: (world.y_max() - world.y_min()) / n,
+            grid: TileGrid::WebMercator,
+        }
+    }
+
+    /// Get transform from projected (longitude / latitude) to tile
+    /// coordinates, for a `wgs84` grid tile
+    fn transform_wgs84(&self, tid: TileId) -> Transform<f64> {
+        let size = wgs84_tile_size(tid.z() - 1);
+        let lon = -180.0 + f64::from(tid.x()) * size;
+        let lat = 90.0 - f64::from(tid.y()) * size;
+        let ts = f64::from(self.extent) / size;
+        Transform::with_translate(-lon, -lat).scale(ts, -ts)
+    }
+
+    /// Get vertex snapping grid at a `wgs84` zoom level
+    fn grid_snap_wgs84(&self, zoom: u32) -> GridSnap {
+        let d = wgs84_tile_size(zoom) / f64::from(self.extent);
+        GridSnap {
+            x0: -180.0,
+            y0: 90.0,
+            dx: d,
+            dy: d,
+            grid: TileGrid::Wgs84,
+        }
+    }
+}
+
+/// Get the size (degrees) of `wgs84` grid tiles at a zoom level
+fn wgs84_tile_size(zoom: u32) -> f64 {
+    180.0 / f64::from(zoom).exp2()
+}
+
+/// Get the Web Mercator outline of a `wgs84` grid tile, clamped to the
+/// Web Mercator latitude limit
+fn wgs84_outline(tid: TileId) -> BBox<f64> {
+    let size = wgs84_tile_size(tid.z() - 1);
+    let lon0 = -180.0 + f64::from(tid.x()) * size;
+    let lat1 = 90.0 - f64::from(tid.y()) * size;
+    let lat0 = (lat1 - size).max(-MERCATOR_LAT_MAX);
+    let lat1 = lat1.min(MERCATOR_LAT_MAX);
+    BBox::new([web_mercator(lon0, lat0), web_mercator(lon0 + size, lat1)])
+}
+
+impl TileGrid {
+    /// Get the zoom level of a tile ID.
+    ///
+    /// For `wgs84` grids, tile IDs have one more zoom level than the grid,
+    /// since there are two root tiles (see [tile_zoom](Self::tile_zoom)).
+    pub fn zoom(self, tid: TileId) -> u32 {
+        match self {
+            TileGrid::WebMercator => tid.z(),
+            TileGrid::Wgs84 => tid.z().saturating_sub(1),
+        }
+    }
+
+    /// Get the tile ID zoom level for a grid zoom level.
+    ///
+    /// A `wgs84` tile `z/x/y` has tile ID `TileId::new(x, y, z + 1)`.
+    pub fn tile_zoom(self, zoom: u32) -> u32 {
+        match self {
+            TileGrid::WebMercator => zoom,
+            TileGrid::Wgs84 => zoom + 1,
+        }
+    }
+
+    /// Project a Web Mercator point to the grid (longitude / latitude
+    /// degrees for `wgs84`)
+    pub fn project(self, pt: (f64, f64)) -> (f64, f64) {
+        match self {
+            TileGrid::WebMercator => pt,
+            TileGrid::Wgs84 => lon_lat(pt.0, pt.1),
+        }
+    }
+
+    /// Convert a point on the grid to WGS84 longitude / latitude (inverse
+    /// of [project](Self::project))
+    pub fn lon_lat(self, pt: (f64, f64)) -> (f64, f64) {
+        match self {
+            TileGrid::WebMercator => lon_lat(pt.0, pt.1),
+            TileGrid::Wgs84 => pt,
+        }
+    }
+
+    /// Check if a tile is entirely beyond the Web Mercator latitude limit
+    fn is_polar(self, tid: TileId) -> bool {
+        match self {
+            TileGrid::WebMercator => false,
+            TileGrid::Wgs84 if tid.z() == 0 => false,
+            TileGrid::Wgs84 => {
+                let size = wgs84_tile_size(tid.z() - 1);
+                let lat1 = 90.0 - f64::from(tid.y()) * size;
+                lat1 - size >= MERCATOR_LAT_MAX || lat1 <= -MERCATOR_LAT_MAX
+            }
+        }
+    }
+}
+
+impl GridSnap {
+    /// Snap a point to the nearest grid vertex.
+    ///
+    /// The point is projected to the tile grid first (see
+    /// [TileCfg::project]).
+    pub fn snap(&self, pt: (f64, f64)) -> (f64, f64) {
+        let pt = self.grid.project(pt);
+        let x = ((pt.0 - self.x0) / self.dx).round();
+        let y = ((self.y0 - pt.1) / self.dy).round();
+        (self.x0 + x * self.dx, self.y0 - y * self.dy)
+    }
+}
+
+/// Calculate edge ratio based on tile zoom
+///
+/// Edge must be larger for higher zoom levels to prevent corrupt polygons.
+fn zoom_edge(zoom: u32) -> f64 {
+    match zoom {
+        0..=12 => 1.0 / 32.0,
+        13 => 1.0 / 16.0,
+        14 => 1.0 / 8.0,
+        15 => 1.0 / 4.0,
+        16 => 1.0 / 2.0,
+        _ => 1.0,
+    }
+}
+
+impl LayerTree {
+    /// Create a new layer tree.
+    ///
+    /// Each loam file is checked against the dig manifest (if any) when
+    /// opened, so a partially copied loam directory is not served.
+    fn new(
+        layer_def: LayerDef,
+        wyrm: &WyrmCfg,
+        manifest: Option<&Manifest>,
+    ) -> Result<Self> {
+        let loam = wyrm.loam_path(layer_def.loam_name());
+        // precision is read from the loam metadata when present
+        let (layer_def, precision) =
+            match LoamMeta::validate(&loam, &layer_def)? {
+                Some(meta) => {
+                    (layer_def.with_stored_tags(&meta.tags)?, meta.precision()?)
+                }
+                None => {
+                    let precision = layer_def.precision();
+                    (layer_def, precision)
+                }
+            };
+        let names = layer_def.loam_names();
+        let bucketed = names.len() > 1;
+        let zooms = layer_def
+            .rank_buckets()
+            .iter()
+            .map(|b| b.zoom_min)
+            .chain(std::iter::repeat(0));
+        let mut trees = Vec::with_capacity(names.len());
+        let mut loam_bytes = Vec::with_capacity(names.len());
+        let mut id_indexes = layer_def.id_index().then(Vec::new);
+        for (name, zoom) in names.iter().zip(zooms) {
+            let loam = wyrm.loam_path(name);
+            // zoom buckets with no features have no file
+            if bucketed && !loam.exists() {
+                log::debug!("no loam file for bucket {name}");
+                continue;
+            }
+            if let Some(manifest) = manifest {
+                manifest.check_file(&loam, wyrm.full_check())?;
+            }
+            if let Some(indexes) = &mut id_indexes {
+                match IdIndex::open(&loam)? {
+                    Some(index) => indexes.push(index),
+                    None => {
+                        log::warn!("no ID index for {name}, dig again");
+                        id_indexes = None;
+                    }
+                }
+            }
+            loam_bytes.push(std::fs::metadata(&loam)?.len());
+            let tree = GeomTree::new(layer_def.geom_tp(), precision, loam)?;
+            trees.push((zoom, tree));
+        }
+        Ok(LayerTree {
+            layer_def,
+            trees,
+            loam_bytes,
+            id_indexes,
+        })
+    }
+
+    /// Get the loam file size of trees queried at a zoom level (bytes)
+    fn loam_bytes(&self, zoom: u32) -> u64 {
+        if !self.layer_def.check_zoom(zoom) {
+            return 0;
+        }
+        self.trees
+            .iter()
+            .zip(&self.loam_bytes)
+            .filter(|((z, _tree), _bytes)| *z <= zoom)
+            .map(|(_tree, bytes)| bytes)
+            .sum()
+    }
+
+    /// Get all geometry trees
+    fn all_trees(&self) -> impl Iterator<Item = &GeomTree> {
+        self.trees.iter().map(|(_zoom, tree)| tree)
+    }
+
+    /// Query layer features in a bounding box
+    fn query_features(&self, bbox: BBox<f64>) -> Result<()> {
+        for tree in self.all_trees() {
+            tree.query_features(&self.layer_def, bbox)?;
+        }
+        Ok(())
+    }
+
+    /// Query layer features as GeoJSON
+    fn query_geojson(
+        &self,
+        bbox: BBox<f64>,
+        features: &mut Vec<Value>,
+    ) -> Result<()> {
+        for tree in self.all_trees() {
+            tree.query_geojson(&self.layer_def, bbox, features)?;
+        }
+        Ok(())
+    }
+
+    /// Query features near a batch of points
+    fn query_batch(
+        &self,
+        points: &[(usize, BBox<f64>)],
+        matches: &mut [Vec<FeatureInfo>],
+    ) -> Result<()> {
+        let mut bbox: Option<BBox<f64>> = None;
+        for (_i, b) in points {
+            match &mut bbox {
+                Some(bb) => {
+                    bb.extend([(b.x_min(), b.y_min()), (b.x_max(), b.y_max())])
+                }
+                None => bbox = Some(*b),
+            }
+        }
+        let Some(bbox) = bbox else {
+            return Ok(());
+        };
+        for tree in self.all_trees() {
+            tree.query_batch(&self.layer_def, bbox, points, matches)?;
+        }
+        Ok(())
+    }
+
+    /// Find features by OSM ID
+    fn find_feature(&self, osm_id: i64) -> Result<Option<FeatureInfo>> {
+        let name = self.layer_def.name();
+        let indexes = self
+            .id_indexes
+            .as_ref()
+            .ok_or_else(|| Error::NoIdIndex(name.into()))?;
+        let mut bbox: Option<BBox<f64>> = None;
+        let mut features = Vec::new();
+        for (index, tree) in indexes.iter().zip(self.all_trees()) {
+            let Some(b) = index.lookup(osm_id)? else {
+                continue;
+            };
+            tree.find_features(&self.layer_def, b, osm_id, &mut features)?;
+            match &mut bbox {
+                Some(bb) => {
+                    bb.extend([(b.x_min(), b.y_min()), (b.x_max(), b.y_max())])
+                }
+                None => bbox = Some(b),
+            }
+        }
+        Ok(bbox
+            .filter(|_| !features.is_empty())
+            .map(|bbox| FeatureInfo {
+                layer: name.into(),
+                osm_id,
+                bbox,
+                features,
+            }))
+    }
+
+    /// Get the extent of all features
+    fn extent(&self, world: BBox<f64>) -> Result<Option<BBox<f64>>> {
+        let mut extent: Option<BBox<f64>> = None;
+        for tree in self.all_trees() {
+            if let Some(bbox) = tree.extent(world)? {
+                match &mut extent {
+                    Some(ext) => ext.extend([
+                        (bbox.x_min(), bbox.y_min()),
+                        (bbox.x_max(), bbox.y_max()),
+                    ]),
+                    None => extent = Some(bbox),
+                }
+            }
+        }
+        Ok(extent)
+    }
+
+    /// Validate polygon geometry
+    fn validate(&self, world: BBox<f64>) -> Result<ValidationReport> {
+        let mut report = ValidationReport::new(self.layer_def.name());
+        for tree in self.all_trees() {
+            report.merge(tree.validate(&self.layer_def, world)?);
+        }
+        Ok(report)
+    }
+
+    /// Query tile features as GeoJSON
+    fn query_tile_geojson(&self, tile_cfg: &TileCfg) -> Result<Vec<Value>> {
+        let mut features = GeoJsonFeatures::new(&self.layer_def, tile_cfg);
         if self.layer_def.check_zoom(tile_cfg.zoom()) {
-            self.tree.query_tile(&self.layer_def, layer, tile_cfg)
-        } else {
-            Ok(layer)
+            let zoom = tile_cfg.zoom();
+            // zoom buckets with higher minimum zoom are skipped
+            for (_zoom, tree) in self.trees.iter().filter(|(z, _)| *z <= zoom) {
+                tree.query_tile_geojson(
+                    &self.layer_def,
+                    &mut features,
+                    tile_cfg,
+                )?;
+            }
+        }
+        Ok(features.into_features())
+    }
+
+    /// Query tile features, keeping them to fit a size budget
+    fn query_tile_kept(&self, tile_cfg: &TileCfg) -> Result<KeptLayer> {
+        let mut kept = KeptLayer::default();
+        let zoom = tile_cfg.zoom();
+        if self.layer_def.check_zoom(zoom) {
+            // zoom buckets with higher minimum zoom are skipped
+            for (_zoom, tree) in self.trees.iter().filter(|(z, _)| *z <= zoom) {
+                tree.query_tile_kept(&self.layer_def, &mut kept, tile_cfg)?;
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Query tile features
+    fn query_tile(&self, tile: &Tile, tile_cfg: &TileCfg) -> Result<Layer> {
+        let mut layer = tile.create_layer(self.layer_def.name());
+        let zoom = tile_cfg.zoom();
+        if !self.layer_def.check_zoom(zoom) {
+            return Ok(layer);
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "query_layer",
+            layer = self.layer_def.name(),
+            features = tracing::field::Empty,
+            elapsed_us = tracing::field::Empty,
+            truncated = tracing::field::Empty,
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        let t = Instant::now();
+        #[cfg(feature = "tracing")]
+        let n_truncated = tile_cfg.truncated.get();
+        // zoom buckets with higher minimum zoom are skipped
+        for (_zoom, tree) in self.trees.iter().filter(|(z, _)| *z <= zoom) {
+            layer = tree.query_tile(&self.layer_def, layer, tile_cfg)?;
+        }
+        #[cfg(feature = "tracing")]
+        {
+            span.record("features", layer.num_features());
+            span.record("elapsed_us", t.elapsed().as_micros() as u64);
+            span.record("truncated", tile_cfg.truncated.get() > n_truncated);
+        }
+        Ok(layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayerCfg;
+    use crate::geom::MemFeature;
+    use std::rc::Rc;
+
+    /// Tiles at several zoom levels
+    fn tiles() -> Vec<TileId> {
+        [
+            (0, 0, 0),
+            (1, 0, 1),
+            (26, 48, 7),
+            (987, 1472, 12),
+            (15000, 23000, 16),
+        ]
+        .into_iter()
+        .chain([(123_456, 234_567, 18), (0, (1 << 20) - 1, 20)])
+        .map(|(x, y, z)| TileId::new(x, y, z).unwrap())
+        .collect()
+    }
+
+    /// Check that two values are nearly equal, relative to a scale
+    fn assert_near(a: f64, b: f64, scale: f64) {
+        assert!((a - b).abs() <= scale * 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn extent_scaled() {
+        let te = TileExtent::new(256, Some(16));
+        assert_eq!(te.scaled(2), TileExtent::new(512, Some(32)));
+        assert_eq!(te.scaled(1), te);
+        assert_eq!(te.scaled(0), te);
+        assert_eq!(te.scaled(4).extent(), 1024);
+        // limited to maximum scale
+        assert_eq!(te.scaled(5).extent(), 1024);
+        assert_eq!(te.scaled(u32::MAX), TileExtent::new(1024, Some(64)));
+        let te = TileExtent::new(256, None);
+        assert_eq!(te.scaled(2), TileExtent::new(512, None));
+        let te = TileExtent::new(u32::MAX / 2, Some(u32::MAX));
+        assert_eq!(te.scaled(4), TileExtent::new(u32::MAX, Some(u32::MAX)));
+    }
+
+    #[test]
+    fn extent_edge_ratio() {
+        let te = TileExtent::new(256, Some(16));
+        for zoom in [0, 12, 16, 20] {
+            assert_eq!(te.edge_ratio(zoom), 1.0 / 16.0);
+        }
+        // edge scales along with extent
+        assert_eq!(te.scaled(2).edge_ratio(5), 1.0 / 16.0);
+        assert_eq!(TileExtent::new(4096, Some(0)).edge_ratio(14), 0.0);
+        assert_eq!(TileExtent::new(0, Some(8)).edge_ratio(0), 8.0);
+        let te = TileExtent::new(256, None);
+        let ratios = [
+            (0, 1.0 / 32.0),
+            (12, 1.0 / 32.0),
+            (13, 1.0 / 16.0),
+            (14, 1.0 / 8.0),
+            (15, 1.0 / 4.0),
+            (16, 1.0 / 2.0),
+            (17, 1.0),
+            (ZOOM_MAX, 1.0),
+        ];
+        for (zoom, ratio) in ratios {
+            assert_eq!(te.edge_ratio(zoom), ratio, "zoom {zoom}");
+        }
+    }
+
+    #[test]
+    fn extent_clip_bbox() {
+        let grid = MapGrid::default();
+        for tid in tiles() {
+            let outline = grid.tile_bbox(tid);
+            let width = outline.x_max() - outline.x_min();
+            let height = outline.y_max() - outline.y_min();
+            let zero = TileExtent::new(256, Some(0));
+            assert_eq!(zero.clip_bbox(outline, tid.z()), outline);
+            let te = TileExtent::new(256, Some(16));
+            let bbox = te.clip_bbox(outline, tid.z());
+            assert_near(bbox.x_min(), outline.x_min() - width / 16.0, width);
+            assert_near(bbox.x_max(), outline.x_max() + width / 16.0, width);
+            assert_near(bbox.y_min(), outline.y_min() - height / 16.0, height);
+            assert_near(bbox.y_max(), outline.y_max() + height / 16.0, height);
+            // doubling the edge doubles the expansion
+            let te2 = TileExtent::new(256, Some(32));
+            let bbox2 = te2.clip_bbox(outline, tid.z());
+            assert_near(
+                outline.x_min() - bbox2.x_min(),
+                2.0 * (outline.x_min() - bbox.x_min()),
+                width,
+            );
+            assert_near(
+                bbox2.y_max() - outline.y_max(),
+                2.0 * (bbox.y_max() - outline.y_max()),
+                height,
+            );
+            // same bounds for a high-DPI tile
+            assert_eq!(te.scaled(2).clip_bbox(outline, tid.z()), bbox);
+            // zoom-based default edge
+            let te = TileExtent::new(256, None);
+            let edge = zoom_edge(tid.z()) * width;
+            let bbox = te.clip_bbox(outline, tid.z());
+            assert_near(bbox.x_min(), outline.x_min() - edge, width);
+            assert_near(bbox.x_max(), outline.x_max() + edge, width);
+        }
+    }
+
+    #[test]
+    fn extent_transform() {
+        let grid = MapGrid::default();
+        for extent in [256, 512, 4096] {
+            let te = TileExtent::new(extent, Some(0));
+            let ts = f64::from(extent);
+            for tid in tiles() {
+                let outline = grid.tile_bbox(tid);
+                let t = te.transform(&grid, tid);
+                // tile Y increases southward
+                let nw = t * Pt::new(outline.x_min(), outline.y_max());
+                let se = t * Pt::new(outline.x_max(), outline.y_min());
+                assert_near(nw.x, 0.0, ts);
+                assert_near(nw.y, 0.0, ts);
+                assert_near(se.x, ts, ts);
+                assert_near(se.y, ts, ts);
+                let mid = t * Pt::new(
+                    (outline.x_min() + outline.x_max()) / 2.0,
+                    (outline.y_min() + outline.y_max()) / 2.0,
+                );
+                assert_near(mid.x, ts / 2.0, ts);
+                assert_near(mid.y, ts / 2.0, ts);
+            }
+        }
+    }
+
+    #[test]
+    fn extent_grid_snap() {
+        let grid = MapGrid::default();
+        let world = grid.bbox();
+        let te = TileExtent::new(256, Some(0));
+        for zoom in [0, 7, 16] {
+            let snap = te.grid_snap(&grid, zoom);
+            let n = f64::from(zoom).exp2() * 256.0;
+            assert_near(snap.dx, (world.x_max() - world.x_min()) / n, 1.0);
+            assert_near(snap.dy, (world.y_max() - world.y_min()) / n, 1.0);
+            assert_eq!(snap.x0, world.x_min());
+            assert_eq!(snap.y0, world.y_max());
+        }
+    }
+
+    #[test]
+    fn corner_tiles_finite() {
+        let grid = MapGrid::default();
+        let ctx = RequestCtx::default();
+        let mut tids = Vec::new();
+        for z in [1, 10, 18, 20] {
+            let max = (1 << z) - 1;
+            for (x, y) in [(0, 0), (max, 0), (0, max), (max, max)] {
+                tids.push(TileId::new(x, y, z).unwrap());
+            }
+        }
+        for tid in tids {
+            let outline = grid.tile_bbox(tid);
+            let (x0, y0) = (outline.x_min(), outline.y_min());
+            let (x1, y1) = (outline.x_max(), outline.y_max());
+            let line = vec![(x0, y0), (x1, y1)];
+            let ring = vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)];
+            let layers = vec![
+                mem_layer("pts", "point", tile_points(tid, 3)),
+                mem_layer("line", "linestring", vec![(vec![None], vec![line])]),
+                mem_layer("area", "polygon", vec![(vec![None], vec![ring])]),
+            ];
+            let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+            wyrm.groups.push(test_group(layers, None));
+            let tile_cfg = match wyrm.test_tile_config(tid, &ctx) {
+                Ok(tile_cfg) => tile_cfg,
+                Err(Error::TileOutOfRange(_)) => continue,
+                Err(e) => panic!("tile {tid}: {e:?}"),
+            };
+            let tile = wyrm.groups[0].fetch_tile(&tile_cfg).unwrap();
+            let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+            assert_eq!(summary.layers.len(), 3, "tile {tid}");
+            for layer in &summary.layers {
+                for feature in &layer.features {
+                    for (x, y) in feature.paths().into_iter().flatten() {
+                        assert!(x.is_finite() && y.is_finite(), "tile {tid}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sanitize_long_value() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        // 10 KB value, with embedded NULs and multi-byte characters
+        let value = "caf\u{e9}\0 note\0".repeat(800);
+        assert!(value.len() >= 10_000);
+        let mut features = tile_points(tid, 1);
+        features[0].0 = vec![Some(value)];
+        let layers = vec![mem_layer("pts", "point", features)];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+        let tile = wyrm.groups[0].fetch_tile(&tile_cfg).unwrap();
+        assert_eq!(tile_cfg.truncated_values.get(), 1);
+        let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+        let feature = &summary.layers[0].features[0];
+        let TagValue::String(name) = &feature.tags[0].1 else {
+            panic!("name not a string: {:?}", feature.tags);
+        };
+        // default maximum length is 256 bytes
+        assert!(name.len() <= 256, "{}", name.len());
+        assert!(name.len() > 252, "{}", name.len());
+        assert!(!name.chars().any(char::is_control));
+        assert!(name.starts_with("caf\u{e9} note"));
+    }
+
+    #[test]
+    fn snap_shared_edge() {
+        let left = TileId::new(7865, 11874, 15).unwrap();
+        let right = TileId::new(7866, 11874, 15).unwrap();
+        let bbox = MapGrid::default().tile_bbox(left);
+        let w = bbox.x_max() - bbox.x_min();
+        // right side is within the edge buffer of the left tile
+        let x0 = bbox.x_min() + 0.4137 * w;
+        let x1 = bbox.x_max() + 0.1731 * w;
+        let y0 = bbox.y_min() + 0.3119 * w;
+        let y1 = bbox.y_min() + 0.7243 * w;
+        let ring = vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)];
+        let features = vec![(vec![Some("lake".into())], vec![ring])];
+        let layers = vec![mem_layer("lake", "polygon", features)];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(64)));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let coords = |tid| {
+            let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+            let tile = wyrm.groups[0].fetch_tile(&tile_cfg).unwrap();
+            let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+            let paths = summary.layers[0].features[0].paths();
+            let mut xs: Vec<f64> = paths[0].iter().map(|p| p.0).collect();
+            let mut ys: Vec<f64> = paths[0].iter().map(|p| p.1).collect();
+            xs.sort_by(f64::total_cmp);
+            xs.dedup();
+            ys.sort_by(f64::total_cmp);
+            ys.dedup();
+            (xs, ys)
+        };
+        let (xs_left, ys_left) = coords(left);
+        let (xs_right, ys_right) = coords(right);
+        assert_eq!(xs_left.len(), 2);
+        assert_eq!(xs_right.len(), 2);
+        // right edge, offset by one tile extent
+        assert_eq!(xs_left[1], xs_right[1] + 256.0);
+        // right tile is clipped at its edge buffer
+        assert_eq!(xs_right[0], -64.0);
+        // top and bottom edges match exactly across the seam
+        assert_eq!(ys_left.len(), 2);
+        assert_eq!(ys_left, ys_right);
+    }
+
+    #[test]
+    fn zero_edge_within_tile() {
+        let ctx = RequestCtx::default();
+        for edge in [0, 8, 64] {
+            let wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(edge)));
+            let ts = 256.0;
+            let margin = f64::from(edge);
+            for tid in tiles() {
+                let cfg =
+                    wyrm.tile_config(tid, &ctx, TileGrid::WebMercator).unwrap();
+                let bbox = cfg.bbox();
+                if edge == 0 {
+                    assert_eq!(bbox, wyrm.grid.tile_bbox(tid));
+                }
+                // every point within the clip bounds lands in the tile
+                // (plus edge)
+                let t = cfg.transform();
+                for i in 0..=8 {
+                    for j in 0..=8 {
+                        let fx = f64::from(i) / 8.0;
+                        let fy = f64::from(j) / 8.0;
+                        let x =
+                            bbox.x_min() + fx * (bbox.x_max() - bbox.x_min());
+                        let y =
+                            bbox.y_min() + fy * (bbox.y_max() - bbox.y_min());
+                        let p = t * Pt::new(x, y);
+                        let tol = ts * 1e-9;
+                        assert!(
+                            p.x >= -margin - tol && p.x <= ts + margin + tol
+                        );
+                        assert!(
+                            p.y >= -margin - tol && p.y <= ts + margin + tol
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Make an in-memory layer tree
+    fn mem_layer(
+        name: &str,
+        geom_type: &str,
+        features: Vec<MemFeature>,
+    ) -> LayerTree {
+        mem_layer_cfg(name, geom_type, "zoom: 0+\n", features)
+    }
+
+    /// Make an in-memory layer tree, with extra config lines (incl. `zoom`)
+    fn mem_layer_cfg(
+        name: &str,
+        geom_type: &str,
+        extra: &str,
+        features: Vec<MemFeature>,
+    ) -> LayerTree {
+        let muon = format!(
+            "name: {name}\ngeom_type: {geom_type}\n{extra}tags: ?name\n"
+        );
+        let cfg: LayerCfg = muon_rs::from_str(&muon).unwrap();
+        let layer_def = LayerDef::try_from(&cfg).unwrap();
+        let tree = GeomTree::from_geometries(layer_def.geom_tp(), features);
+        LayerTree {
+            layer_def,
+            trees: vec![(0, tree)],
+            loam_bytes: vec![0],
+            id_indexes: None,
+        }
+    }
+
+    /// Make a layer group of one in-memory layer, with a tile bitmap
+    fn bitmap_group(
+        geom_type: &str,
+        features: Vec<MemFeature>,
+        bitmap_zoom: u32,
+    ) -> LayerGroup {
+        let layers = vec![mem_layer("test", geom_type, features)];
+        test_group(layers, Some(bitmap_zoom))
+    }
+
+    /// Make a layer group named `test`
+    fn test_group(
+        layers: Vec<LayerTree>,
+        bitmap_zoom: Option<u32>,
+    ) -> LayerGroup {
+        let blank = blank_tile(&layers, 256).unwrap();
+        LayerGroup {
+            name: "test".into(),
+            layers,
+            zoom_min: 0,
+            zoom_max: ZOOM_MAX,
+            blank,
+            debug_outline: false,
+            keep_empty_layers: false,
+            static_tiles: None,
+            static_overzoom: 0,
+            version: None,
+            attribution: None,
+            license: None,
+            max_tile_bytes: None,
+            deadline: None,
+            grid: TileGrid::WebMercator,
+            bitmap_zoom,
+            // directory does not exist, so the bitmap is never written
+            bitmap_path: PathBuf::from("/nonexistent/earthwyrm/test.bitmap"),
+            bitmap_key: 0,
+            bitmap: OnceLock::new(),
+        }
+    }
+
+    /// Decode the point of each feature in the first layer of a tile
+    fn decode_points(bytes: &[u8]) -> Vec<(f64, f64)> {
+        let summary = decode_summary(bytes).unwrap();
+        summary.layers[0]
+            .features
+            .iter()
+            .map(|f| {
+                // one MoveTo command, with one point
+                assert_eq!(f.geom[0], 9);
+                let x = zigzag(f.geom[1]) as f64;
+                let y = zigzag(f.geom[2]) as f64;
+                (x, y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grid_accuracy() {
+        // near the equator, mid-latitude, and near both poles
+        let places =
+            [(10.3, 0.2), (-93.2, 44.9), (25.7, 84.5), (-150.4, -84.5)];
+        let extent = 4096;
+        let zoom = 6;
+        let ext = f64::from(extent);
+        for grid in [TileGrid::WebMercator, TileGrid::Wgs84] {
+            for (lon, lat) in places {
+                let pt = web_mercator(lon, lat);
+                let layer =
+                    mem_layer("a", "point", vec![(vec![None], vec![vec![pt]])]);
+                let mut group = test_group(vec![layer], None);
+                group.grid = grid;
+                let mut wyrm =
+                    Wyrm::with_tile_extent(TileExtent::new(extent, Some(0)));
+                wyrm.groups.push(group);
+                // tile ID, and expected position within the tile (0-1)
+                let (tid, fx, fy) = match grid {
+                    TileGrid::WebMercator => {
+                        let world = wyrm.grid.bbox();
+                        let w = world.x_max() - world.x_min();
+                        let n = f64::from(1u32 << zoom);
+                        let tx = (pt.0 - world.x_min()) / w * n;
+                        let ty = (world.y_max() - pt.1) / w * n;
+                        let tid = TileId::new(tx as u32, ty as u32, zoom);
+                        (tid.unwrap(), tx.fract(), ty.fract())
+                    }
+                    TileGrid::Wgs84 => {
+                        let size = wgs84_tile_size(zoom);
+                        let tx = (lon + 180.0) / size;
+                        let ty = (90.0 - lat) / size;
+                        let tid = TileId::new(tx as u32, ty as u32, zoom + 1);
+                        (tid.unwrap(), tx.fract(), ty.fract())
+                    }
+                };
+                let Ok(TileFetch::Tile(bytes)) =
+                    wyrm.try_fetch_tile("test", tid)
+                else {
+                    panic!("{grid:?} {lon},{lat}: no tile {tid}");
+                };
+                let (x, y) = decode_points(&bytes)[0];
+                // within rounding of one tile unit
+                assert!(
+                    (x - fx * ext).abs() <= 1.0,
+                    "{grid:?} {lon},{lat}: {x}"
+                );
+                assert!(
+                    (y - fy * ext).abs() <= 1.0,
+                    "{grid:?} {lon},{lat}: {y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bitmap_never_skips_data() {
+        let grid = MapGrid::default();
+        let world = grid.bbox();
+        let base = TileId::new(987, 1472, 12).unwrap();
+        let outline = grid.tile_bbox(base);
+        let w = outline.x_max() - outline.x_min();
+        // position relative to base tile (0-1 within it)
+        let at = |fx: f64, fy: f64| {
+            (outline.x_min() + fx * w, outline.y_min() + fy * w)
+        };
+        let pt = |fx, fy| (vec![None], vec![vec![at(fx, fy)]]);
+        let layers = [
+            (
+                "point",
+                vec![
+                    pt(0.0, 0.0),
+                    pt(0.5, 0.5),
+                    pt(0.999, 0.5),
+                    pt(1.0, 1.0),
+                    pt(1.01, 0.5),
+                    pt(-0.03, 1.02),
+                ],
+            ),
+            (
+                "linestring",
+                vec![(
+                    vec![None],
+                    vec![
+                        vec![at(0.1, 0.1), at(0.9, 0.1)],
+                        vec![at(1.02, 0.2), at(1.02, 0.8)],
+                        vec![at(-0.5, 1.001), at(1.5, 1.001)],
+                    ],
+                )],
+            ),
+            (
+                "polygon",
+                vec![(
+                    vec![None],
+                    vec![vec![
+                        at(0.95, 0.95),
+                        at(1.05, 0.95),
+                        at(1.05, 1.05),
+                        at(0.95, 1.05),
+                        at(0.95, 0.95),
+                    ]],
+                )],
+            ),
+        ];
+        let extent = BBox::new([at(-1.0, -1.0), at(2.0, 2.0)]);
+        let ctx = RequestCtx::default();
+        let mut n_skipped = 0;
+        for (geom_type, features) in layers {
+            for bitmap_zoom in [10, 14] {
+                for edge in [Some(0), Some(16), Some(64), None] {
+                    let tile_extent = TileExtent::new(256, edge);
+                    let wyrm = Wyrm::with_tile_extent(tile_extent);
+                    let group =
+                        bitmap_group(geom_type, features.clone(), bitmap_zoom);
+                    let layer_tree = &group.layers[0];
+                    for z in 0..=16 {
+                        let (xs, ys, z) =
+                            covering_range(world, extent, z, group.grid);
+                        for (x, y) in
+                            ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+                        {
+                            let tid = TileId::new(x, y, z).unwrap();
+                            let tile_cfg =
+                                wyrm.test_tile_config(tid, &ctx).unwrap();
+                            let tile = Tile::new(256);
+                            let layer = layer_tree
+                                .query_tile(&tile, &tile_cfg)
+                                .unwrap();
+                            let has_data = layer.num_features() > 0;
+                            let may =
+                                group.may_have_data(world, tile_extent, tid);
+                            assert!(
+                                may || !has_data,
+                                "{geom_type} {tid} skipped (edge {edge:?}, \
+                                bitmap zoom {bitmap_zoom})"
+                            );
+                            if !may {
+                                n_skipped += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // the bitmap must still skip some empty tiles
+        assert!(n_skipped > 0);
+    }
+
+    /// Writer which records what happened before its first write
+    struct FirstWrite {
+        /// Layers queried so far
+        queried: Rc<RefCell<Vec<String>>>,
+
+        /// Start of request
+        start: Instant,
+
+        /// Layers queried and elapsed time at first write
+        first: Option<(Vec<String>, Duration)>,
+
+        /// Bytes written
+        bytes: Vec<u8>,
+    }
+
+    impl Write for FirstWrite {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.first.is_none() && !buf.is_empty() {
+                let queried = self.queried.borrow().clone();
+                self.first = Some((queried, self.start.elapsed()));
+            }
+            self.bytes.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Make points spread over a tile
+    fn tile_points(tid: TileId, n: u32) -> Vec<MemFeature> {
+        let outline = MapGrid::default().tile_bbox(tid);
+        let w = outline.x_max() - outline.x_min();
+        (0..n * n)
+            .map(|i| {
+                let fx = (f64::from(i % n) + 0.5) / f64::from(n);
+                let fy = (f64::from(i / n) + 0.5) / f64::from(n);
+                let pt = (outline.x_min() + fx * w, outline.y_min() + fy * w);
+                (vec![Some(format!("p{i}"))], vec![vec![pt]])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_limit_per_point() {
+        let muon = "name: pts\ngeom_type: point\nzoom: 0+\n\
+            query_limit: 2\ntags: ?osm_id\n";
+        let cfg: LayerCfg = muon_rs::from_str(muon).unwrap();
+        let layer_def = LayerDef::try_from(&cfg).unwrap();
+        // three features near each of two points, in one batch cell
+        let features = (1..=6)
+            .map(|id| {
+                let x = if id <= 3 { 100.0 } else { 600.0 };
+                (
+                    vec![Some(id.to_string())],
+                    vec![vec![(x + f64::from(id), 50.0)]],
+                )
+            })
+            .collect();
+        let tree = GeomTree::from_geometries(layer_def.geom_tp(), features);
+        let layer = LayerTree {
+            layer_def,
+            trees: vec![(0, tree)],
+            loam_bytes: vec![0],
+            id_indexes: None,
+        };
+        let points = [
+            (0, BBox::new([(90.0, 40.0), (110.0, 60.0)])),
+            (1, BBox::new([(590.0, 40.0), (610.0, 60.0)])),
+        ];
+        let mut matches = vec![Vec::new(), Vec::new()];
+        layer.query_batch(&points, &mut matches).unwrap();
+        assert_eq!(matches[0].len(), 2);
+        assert_eq!(matches[1].len(), 2);
+        assert!(matches[0].iter().all(|m| m.osm_id <= 3));
+        assert!(matches[1].iter().all(|m| m.osm_id > 3));
+    }
+
+    #[test]
+    fn fetch_variants() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-fetch-variants", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let loam = dir.join("pois.loam");
+        GeomTree::write_loam(&loam, GeomType::Point, tile_points(tid, 8))
+            .unwrap();
+        let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+            layer_group: tile\n  layer: pois\n    \
+            geom_type: point\n    zoom: 8-14\n    tags: ?name\n";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let wyrm = Wyrm::try_from(&cfg.with_loam_dir(dir.clone())).unwrap();
+        let TileFetch::Tile(tile) = wyrm.try_fetch_tile("tile", tid).unwrap()
+        else {
+            panic!("tile {tid} not fetched");
+        };
+        let summary = decode_summary(&tile).unwrap();
+        assert_eq!(summary.layers[0].features.len(), 64);
+        // no features in tiles, and zoom out of range (with features)
+        for tid in [
+            TileId::new(302, 400, 10).unwrap(),
+            TileId::new(1210, 1600, 12).unwrap(),
+            TileId::new(9600, 12800, 15).unwrap(),
+        ] {
+            assert_eq!(
+                wyrm.try_fetch_tile("tile", tid).unwrap(),
+                TileFetch::Empty
+            );
+        }
+        assert_eq!(
+            wyrm.try_fetch_tile("roads", tid).unwrap(),
+            TileFetch::UnknownGroup
+        );
+        // corrupt the loam file in place (same length) after opening
+        let len = std::fs::metadata(&loam).unwrap().len();
+        let mut file =
+            std::fs::OpenOptions::new().write(true).open(&loam).unwrap();
+        let garbage = vec![0xFF; usize::try_from(len - 32).unwrap()];
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(16)).unwrap();
+        file.write_all(&garbage).unwrap();
+        file.sync_all().unwrap();
+        assert!(wyrm.try_fetch_tile("tile", tid).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn group_names_round_trip() {
+        let dir = std::env::temp_dir()
+            .join(format!("earthwyrm-{}-group-names", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tid = TileId::new(300, 400, 10).unwrap();
+        GeomTree::write_loam(
+            dir.join("pois.loam"),
+            GeomType::Point,
+            tile_points(tid, 2),
+        )
+        .unwrap();
+        let muon = "bind_address: 127.0.0.1:0\ntile_extent: 256\n\
+            layer_group: Tile\n  layer: pois\n    \
+            geom_type: point\n    zoom: 0+\n    tags: ?name\n";
+        let cfg: WyrmCfg = muon_rs::from_str(muon).unwrap();
+        let wyrm = Wyrm::try_from(&cfg.with_loam_dir(dir.clone())).unwrap();
+        // deprecated uppercase name is matched case-insensitively
+        let expected = wyrm.try_fetch_tile("Tile", tid).unwrap();
+        assert!(matches!(expected, TileFetch::Tile(_)));
+        for name in ["tile", "TILE", "tIlE"] {
+            assert_eq!(wyrm.try_fetch_tile(name, tid).unwrap(), expected);
+        }
+        // names accepted by the config survive a URL round trip
+        for name in ["tile", "Tile", "my_tiles", "base-2"] {
+            let url = format!("{name}/{}/{}/{}.mvt", tid.z(), tid.x(), tid.y());
+            let mut parts = url.splitn(4, '/');
+            let group = parts.next().unwrap();
+            let z = parts.next().unwrap().parse().unwrap();
+            let x = parts.next().unwrap().parse().unwrap();
+            let tail = parts.next().unwrap();
+            assert_eq!(group, name);
+            assert_eq!(TileId::parse_path(group, z, x, tail).unwrap(), tid);
+        }
+        // names rejected by the config are rejected in requests
+        for name in ["", "my tiles", "tile.v2", "wässer"] {
+            assert!(TileId::parse_path(name, 10, 300, "400.mvt").is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deadline_partial_tile() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = vec![
+            mem_layer("a", "point", tile_points(tid, 10)),
+            mem_layer("b", "point", tile_points(tid, 4)),
+        ];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(layers, None));
+        let deadline = Instant::now() + Duration::from_millis(20);
+        // the first feature takes until the deadline
+        let filter: &FeatureFilter = &|_layer, _values| {
+            while Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            true
+        };
+        let ctx = RequestCtx {
+            filter: Some(filter),
+            deadline: Some(deadline),
+            ..Default::default()
+        };
+        let TileFetch::Tile(bytes) =
+            wyrm.try_fetch_tile_ctx("test", tid, &ctx).unwrap()
+        else {
+            panic!("no tile");
+        };
+        // clock is next read after `DEADLINE_INTERVAL` features
+        assert_eq!(layer_names(&bytes), ["a", "debug"]);
+        let summary = decode_summary(&bytes).unwrap();
+        assert_eq!(summary.layers[0].features.len(), DEADLINE_INTERVAL);
+        let debug = &summary.layers[1].features[0];
+        assert!(debug
+            .tags
+            .contains(&("truncated".into(), TagValue::Uint(1))));
+        // no deadline: whole tile
+        let ctx = RequestCtx {
+            filter: Some(filter),
+            ..Default::default()
+        };
+        let TileFetch::Tile(bytes) =
+            wyrm.try_fetch_tile_ctx("test", tid, &ctx).unwrap()
+        else {
+            panic!("no tile");
+        };
+        assert_eq!(layer_names(&bytes), ["a", "b"]);
+    }
+
+    #[test]
+    fn geojson_mvt_counts() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let outline = MapGrid::default().tile_bbox(tid);
+        let (x0, y0) = (outline.x_min(), outline.y_min());
+        let w = outline.x_max() - x0;
+        let name = |n: &str| vec![Some(n.to_string())];
+        let square = |x: f64, y: f64, s: f64| {
+            vec![(x, y), (x + s, y), (x + s, y + s), (x, y + s), (x, y)]
+        };
+        let mut points = tile_points(tid, 3);
+        // outside of tile
+        points.push((name("far"), vec![vec![(x0 + 3.0 * w, y0)]]));
+        let lines = vec![
+            // crossing the tile
+            (
+                name("across"),
+                vec![vec![
+                    (x0 - w, y0 + 0.5 * w),
+                    (x0 + 2.0 * w, y0 + 0.5 * w),
+                ]],
+            ),
+            (
+                name("inside"),
+                vec![vec![
+                    (x0 + 0.1 * w, y0 + 0.1 * w),
+                    (x0 + 0.9 * w, y0 + 0.2 * w),
+                ]],
+            ),
+            (name("far"), vec![vec![(x0 + 3.0 * w, y0), (x0 + 4.0 * w, y0)]]),
+        ];
+        let polygons = vec![
+            (name("inside"), vec![square(x0 + 0.2 * w, y0 + 0.2 * w, 0.3 * w)]),
+            (name("edge"), vec![square(x0 + 0.8 * w, y0 + 0.8 * w, 0.5 * w)]),
+            (name("far"), vec![square(x0 + 3.0 * w, y0, 0.5 * w)]),
+        ];
+        let layers = vec![
+            mem_layer("points", "point", points),
+            mem_layer("lines", "linestring", lines),
+            mem_layer("polygons", "polygon", polygons),
+        ];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(16)));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let TileFetch::Tile(mvt) =
+            wyrm.try_fetch_tile_ctx("test", tid, &ctx).unwrap()
+        else {
+            panic!("no MVT tile");
+        };
+        let TileFetch::Tile(json) =
+            wyrm.fetch_tile_geojson("test", tid, &ctx).unwrap()
+        else {
+            panic!("no GeoJSON tile");
+        };
+        let summary = decode_summary(&mvt).unwrap();
+        let mvt_counts: Vec<(String, usize)> = summary
+            .layers
+            .iter()
+            .map(|l| (l.name.clone(), l.features.len()))
+            .collect();
+        let json: Value = serde_json::from_str(&json).unwrap();
+        let json_counts: Vec<(String, usize)> = ["points", "lines", "polygons"]
+            .iter()
+            .map(|name| {
+                let features = json[name]["features"].as_array().unwrap();
+                (name.to_string(), features.len())
+            })
+            .collect();
+        assert_eq!(mvt_counts, json_counts);
+        assert_eq!(
+            mvt_counts,
+            [
+                ("points".to_string(), 9),
+                ("lines".to_string(), 2),
+                ("polygons".to_string(), 2),
+            ]
+        );
+        assert_eq!(json.as_object().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn filter_halves_features() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = vec![
+            mem_layer("a", "point", tile_points(tid, 10)),
+            mem_layer("b", "point", tile_points(tid, 4)),
+        ];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(layers, None));
+        let counts = |bytes: &[u8]| -> Vec<usize> {
+            let summary = decode_summary(bytes).unwrap();
+            summary.layers.iter().map(|l| l.features.len()).collect()
+        };
+        let mut all = Vec::new();
+        wyrm.fetch_tile(&mut all, "test", tid).unwrap();
+        assert_eq!(counts(&all), [100, 16]);
+        let seen = RefCell::new(Vec::<String>::new());
+        let keep = Cell::new(false);
+        let filter = |layer: &str, values: &Values| {
+            // stored values in pattern order: `?name` only
+            assert_eq!(values.len(), 1);
+            let name = values[0].as_deref().unwrap();
+            seen.borrow_mut().push(format!("{layer}:{name}"));
+            keep.set(!keep.get());
+            keep.get()
+        };
+        let mut half = Vec::new();
+        wyrm.fetch_tile_filtered(&mut half, "test", tid, &filter)
+            .unwrap();
+        assert_eq!(counts(&half), [50, 8]);
+        let seen = seen.into_inner();
+        assert_eq!(seen.len(), 116);
+        assert!(seen.contains(&"a:p99".to_string()));
+        assert!(seen.contains(&"b:p15".to_string()));
+        // no filter installed: same tile as before
+        let mut again = Vec::new();
+        wyrm.fetch_tile(&mut again, "test", tid).unwrap();
+        assert_eq!(again, all);
+    }
+
+    #[test]
+    fn stream_first_layer() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let names = ["a", "b", "c", "d"];
+        let layers = names
+            .iter()
+            .map(|name| mem_layer(name, "point", tile_points(tid, 100)))
+            .collect();
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+        wyrm.groups.push(test_group(layers, None));
+        let queried = Rc::new(RefCell::new(Vec::<String>::new()));
+        let recorder = Rc::clone(&queried);
+        let filter = move |layer: &str, _values: &Values| {
+            let mut queried = recorder.borrow_mut();
+            if queried.last().is_none_or(|l| l != layer) {
+                queried.push(layer.to_string());
+            }
+            true
+        };
+        let ctx = RequestCtx {
+            filter: Some(&filter),
+            ..Default::default()
+        };
+        let mut out = FirstWrite {
+            queried: Rc::clone(&queried),
+            start: Instant::now(),
+            first: None,
+            bytes: Vec::new(),
+        };
+        let fetch = wyrm.write_tile_stream(&mut out, "test", tid, &ctx);
+        let total = out.start.elapsed();
+        let TileFetch::Tile(streamed) = fetch.unwrap() else {
+            panic!("tile {tid} empty");
+        };
+        let (first, ttfb) = out.first.unwrap();
+        // first bytes are written before later layers are queried
+        assert_eq!(first, ["a"]);
+        assert_eq!(*queried.borrow(), names);
+        assert!(ttfb <= total);
+        // same bytes as encoding the whole tile at once
+        assert_eq!(streamed.size(), out.bytes.len());
+        assert!(!streamed.truncated());
+        let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+        let tile = wyrm.groups[0].fetch_tile(&tile_cfg).unwrap();
+        assert_eq!(tile.num_layers(), names.len());
+        assert_eq!(out.bytes, tile.to_bytes().unwrap());
+        assert_eq!(
+            wyrm.try_fetch_tile("test", tid).unwrap(),
+            TileFetch::Tile(out.bytes)
+        );
+    }
+
+    #[test]
+    fn stream_empty() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let far = TileId::new(900, 100, 10).unwrap();
+        let layers = vec![mem_layer("a", "point", tile_points(tid, 4))];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let mut out = Vec::new();
+        let fetch = wyrm.write_tile_stream(&mut out, "test", far, &ctx);
+        assert_eq!(fetch.unwrap(), TileFetch::Empty);
+        let fetch = wyrm.write_tile_stream(&mut out, "none", tid, &ctx);
+        assert_eq!(fetch.unwrap(), TileFetch::UnknownGroup);
+        assert!(out.is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn fetch_async_health_latency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| mem_layer(name, "point", tile_points(tid, 150)))
+            .collect();
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+        wyrm.groups.push(test_group(layers, None));
+        let wyrm = Arc::new(wyrm);
+        let expected = wyrm.try_fetch_tile("test", tid).unwrap();
+        // one runtime thread: any blocking fetch would stall health checks
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let checks = Arc::new(AtomicUsize::new(0));
+        let (max_latency, first_done, fetched) = rt.block_on(async {
+            let (done_tx, mut done_rx) = tokio::sync::watch::channel(false);
+            let counter = Arc::clone(&checks);
+            let health = tokio::spawn(async move {
+                let mut max_latency = Duration::ZERO;
+                while !*done_rx.borrow_and_update() {
+                    let t = Instant::now();
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    max_latency = max_latency.max(t.elapsed());
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                max_latency
+            });
+            let mut first_done = None;
+            let mut fetched = Vec::new();
+            let mut tasks = tokio::task::JoinSet::new();
+            for _ in 0..8 {
+                let wyrm = Arc::clone(&wyrm);
+                tasks.spawn(wyrm.fetch_tile_async("test".into(), tid));
+            }
+            while let Some(res) = tasks.join_next().await {
+                first_done.get_or_insert(checks.load(Ordering::Relaxed));
+                fetched.push(res.unwrap().unwrap());
+            }
+            done_tx.send(true).unwrap();
+            (health.await.unwrap(), first_done.unwrap(), fetched)
+        });
+        // health checks ran while heavy tiles were being built
+        assert!(first_done > 0, "{first_done}");
+        assert!(max_latency < Duration::from_millis(500), "{max_latency:?}");
+        assert_eq!(fetched.len(), 8);
+        assert!(fetched.iter().all(|f| *f == expected));
+    }
+
+    #[test]
+    fn decode_summary_known() {
+        let mut tile = Tile::new(4096);
+        let layer = tile.create_layer("roads");
+        let mut enc = GeomEncoder::new(GeomType::Linestring);
+        enc.add_point(0.0, 0.0).unwrap();
+        enc.add_point(10.0, 20.0).unwrap();
+        let mut feature = layer.into_feature(enc.encode().unwrap());
+        feature.set_id(7);
+        feature.add_tag_string("name", "Main");
+        feature.add_tag_sint("lanes", -2);
+        tile.add_layer(feature.into_layer()).unwrap();
+        let layer = tile.create_layer("pois");
+        let mut enc = GeomEncoder::new(GeomType::Point);
+        enc.add_point(5.0, 6.0).unwrap();
+        let mut feature = layer.into_feature(enc.encode().unwrap());
+        feature.add_tag_uint("count", 3);
+        feature.add_tag_bool("open", true);
+        feature.add_tag_double("height", 2.5);
+        tile.add_layer(feature.into_layer()).unwrap();
+        let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+        assert_eq!(summary.layers.len(), 2);
+        let roads = &summary.layers[0];
+        assert_eq!((&roads.name[..], roads.extent), ("roads", 4096));
+        let road = &roads.features[0];
+        assert_eq!(road.id, Some(7));
+        assert_eq!(road.geom_type, "linestring");
+        // MoveTo (0,0), LineTo (+10,+20), zigzag encoded
+        assert_eq!(road.geom, [9, 0, 0, 10, 20, 40]);
+        assert_eq!(road.geom_len, 6);
+        assert_eq!(
+            road.tags,
+            [
+                ("name".into(), TagValue::String("Main".into())),
+                ("lanes".into(), TagValue::Int(-2)),
+            ]
+        );
+        let pois = &summary.layers[1];
+        assert_eq!(pois.name, "pois");
+        let poi = &pois.features[0];
+        assert_eq!(poi.id, None);
+        assert_eq!(poi.geom_type, "point");
+        assert_eq!(poi.geom, [9, 10, 12]);
+        assert_eq!(
+            poi.tags,
+            [
+                ("count".into(), TagValue::Uint(3)),
+                ("open".into(), TagValue::Bool(true)),
+                ("height".into(), TagValue::Float(2.5)),
+            ]
+        );
+        assert!(decode_summary(&[0x1a, 0x05, 0x0a]).is_err());
+    }
+
+    #[test]
+    fn diff_tiles_order() {
+        // (layer, [(x, y, tags)])
+        type Spec<'a> =
+            [(&'a str, &'a [(f64, f64, &'a [(&'a str, &'a str)])])];
+        let encode = |spec: &Spec| {
+            let mut tile = Tile::new(4096);
+            for (name, features) in spec {
+                let mut layer = tile.create_layer(name);
+                for (x, y, tags) in features.iter() {
+                    let mut enc = GeomEncoder::new(GeomType::Point);
+                    enc.add_point(*x, *y).unwrap();
+                    let mut feature = layer.into_feature(enc.encode().unwrap());
+                    for (key, value) in tags.iter() {
+                        feature.add_tag_string(key, value);
+                    }
+                    layer = feature.into_layer();
+                }
+                tile.add_layer(layer).unwrap();
+            }
+            tile.to_bytes().unwrap()
+        };
+        let a = encode(&[
+            (
+                "roads",
+                &[
+                    (1.0, 2.0, &[("name", "Main"), ("ref", "1")]),
+                    (3.0, 4.0, &[("name", "Oak")]),
+                ],
+            ),
+            ("pois", &[(5.0, 6.0, &[("name", "Cafe")])]),
+        ]);
+        // same content: layers, features and tags in a different order
+        let b = encode(&[
+            ("pois", &[(5.0, 6.0, &[("name", "Cafe")])]),
+            (
+                "roads",
+                &[
+                    (3.0, 4.0, &[("name", "Oak")]),
+                    (1.0, 2.0, &[("ref", "1"), ("name", "Main")]),
+                ],
+            ),
+        ]);
+        assert_ne!(a, b);
+        let diff = diff_tiles(&a, &b).unwrap();
+        assert!(diff.is_empty(), "{diff}");
+        assert_eq!(diff.n_changed(), 0);
+        // one feature moved
+        let c = encode(&[
+            ("pois", &[(5.0, 6.0, &[("name", "Cafe")])]),
+            (
+                "roads",
+                &[
+                    (3.0, 5.0, &[("name", "Oak")]),
+                    (1.0, 2.0, &[("ref", "1"), ("name", "Main")]),
+                ],
+            ),
+        ]);
+        let diff = diff_tiles(&a, &c).unwrap();
+        assert_eq!(diff.layers_changed.len(), 1);
+        assert_eq!(diff.layers_changed[0].name, "roads");
+        assert_eq!(diff.n_changed(), 2);
+    }
+
+    #[test]
+    fn overzoom_two_levels() {
+        let mut tile = Tile::new(4096);
+        let layer = tile.create_layer("pois");
+        let mut enc = GeomEncoder::new(GeomType::Point);
+        enc.add_point(1100.0, 3000.0).unwrap();
+        let mut feature = layer.into_feature(enc.encode().unwrap());
+        feature.set_id(1);
+        feature.add_tag_string("name", "Inside");
+        let layer = feature.into_layer();
+        let mut enc = GeomEncoder::new(GeomType::Point);
+        enc.add_point(100.0, 100.0).unwrap();
+        let mut feature = layer.into_feature(enc.encode().unwrap());
+        feature.add_tag_string("name", "Outside");
+        tile.add_layer(feature.into_layer()).unwrap();
+        let layer = tile.create_layer("roads");
+        let mut enc = GeomEncoder::new(GeomType::Linestring);
+        enc.add_point(1024.0, 2048.0).unwrap();
+        enc.add_point(2048.0, 3072.0).unwrap();
+        let mut feature = layer.into_feature(enc.encode().unwrap());
+        feature.add_tag_sint("lanes", 2);
+        tile.add_layer(feature.into_layer()).unwrap();
+        let parent = TileId::new(300, 400, 10).unwrap();
+        // second column, third row of 4x4 grandchildren
+        let child = TileId::new(1201, 1602, 12).unwrap();
+        let bytes = tile.to_bytes().unwrap();
+        let child_bytes = overzoom(&bytes, parent, child, 4096).unwrap();
+        assert_eq!(layer_names(&child_bytes), ["pois", "roads"]);
+        let summary = decode_summary(&child_bytes).unwrap();
+        let pois = &summary.layers[0];
+        assert_eq!(pois.features.len(), 1);
+        let poi = &pois.features[0];
+        assert_eq!(poi.id, Some(1));
+        assert_eq!(
+            poi.tags,
+            [("name".into(), TagValue::String("Inside".into()))]
+        );
+        // ((1100 - 1024) * 4, (3000 - 2048) * 4)
+        assert_eq!(poi.paths(), [[(304.0, 3808.0)]]);
+        let road = &summary.layers[1].features[0];
+        assert_eq!(road.tags, [("lanes".into(), TagValue::Int(2))]);
+        assert_eq!(road.paths(), [[(0.0, 0.0), (4096.0, 4096.0)]]);
+        // not a child tile
+        let other = TileId::new(1210, 1602, 12).unwrap();
+        assert!(overzoom(&bytes, parent, other, 4096).is_err());
+        // layer `a` with extent 0
+        let zero = [0x1a, 0x07, 0x78, 0x02, 0x0a, 0x01, b'a', 0x28, 0x00];
+        assert!(overzoom(&zero, parent, child, 4096).is_err());
+    }
+
+    #[test]
+    fn group_zoom_skips_query() {
+        let near = TileId::new(300, 400, 10).unwrap();
+        let far = TileId::new(1, 1, 2).unwrap();
+        let mut points = tile_points(near, 2);
+        points.extend(tile_points(far, 2));
+        let layers = vec![mem_layer("a", "point", points)];
+        let mut group = test_group(layers, None);
+        // layer is 0+, so only the group range can skip the query
+        group.zoom_min = 8;
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, None));
+        wyrm.groups.push(group);
+        let queried = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&queried);
+        let filter = move |_layer: &str, _values: &Values| {
+            counter.set(counter.get() + 1);
+            true
+        };
+        let ctx = RequestCtx {
+            filter: Some(&filter),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let fetch = wyrm.write_tile_stream(&mut out, "test", far, &ctx);
+        assert_eq!(fetch.unwrap(), TileFetch::Empty);
+        assert_eq!(queried.get(), 0);
+        assert!(out.is_empty());
+        let fetch = wyrm.try_fetch_tile_ctx("test", far, &ctx);
+        assert_eq!(fetch.unwrap(), TileFetch::Empty);
+        assert_eq!(queried.get(), 0);
+        let fetch = wyrm.write_tile_stream(&mut out, "test", near, &ctx);
+        assert!(matches!(fetch.unwrap(), TileFetch::Tile(_)));
+        assert_eq!(queried.get(), 4);
+    }
+
+    #[test]
+    fn debug_outline_corners() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = vec![mem_layer("a", "point", tile_points(tid, 2))];
+        let mut group = test_group(layers, None);
+        let ctx = RequestCtx::default();
+        for extent in [256, 4096] {
+            let wyrm =
+                Wyrm::with_tile_extent(TileExtent::new(extent, Some(16)));
+            let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+            group.debug_outline = false;
+            let tile = group.fetch_tile(&tile_cfg).unwrap();
+            assert_eq!(layer_names(&tile.to_bytes().unwrap()), ["a"]);
+            group.debug_outline = true;
+            let tile = group.fetch_tile(&tile_cfg).unwrap();
+            let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+            let debug = &summary.layers[1];
+            assert_eq!(debug.name, "debug");
+            assert_eq!(debug.features.len(), 1);
+            let outline = &debug.features[0];
+            assert_eq!(outline.geom_type, "polygon");
+            assert_eq!(
+                outline.tags,
+                [("tid".into(), TagValue::String("10/300/400".into()))]
+            );
+            let paths = outline.paths();
+            assert_eq!(paths.len(), 1);
+            let e = f64::from(extent);
+            let xs: Vec<f64> = paths[0].iter().map(|p| p.0).collect();
+            let ys: Vec<f64> = paths[0].iter().map(|p| p.1).collect();
+            let min_max = |v: &[f64]| {
+                (
+                    v.iter().copied().fold(f64::MAX, f64::min),
+                    v.iter().copied().fold(f64::MIN, f64::max),
+                )
+            };
+            // corners from 0,0 to extent,extent
+            assert_eq!(paths[0].len(), 4);
+            assert_eq!(min_max(&xs), (0.0, e));
+            assert_eq!(min_max(&ys), (0.0, e));
+            for corner in [(0.0, 0.0), (e, 0.0), (e, e), (0.0, e)] {
+                assert!(paths[0].contains(&corner), "{corner:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn query_limit_truncates() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = vec![
+            mem_layer_cfg(
+                "a",
+                "point",
+                "zoom: 0+\nquery_limit: 3\n",
+                tile_points(tid, 4),
+            ),
+            mem_layer("b", "point", tile_points(tid, 4)),
+        ];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let tile_cfg = wyrm.test_tile_config(tid, &ctx).unwrap();
+        let tile = wyrm.groups[0].fetch_tile(&tile_cfg).unwrap();
+        // only the limited layer is reported as truncated
+        assert_eq!(tile_cfg.truncated.get(), 1);
+        let summary = decode_summary(&tile.to_bytes().unwrap()).unwrap();
+        let counts: Vec<_> =
+            summary.layers.iter().map(|l| l.features.len()).collect();
+        assert_eq!(counts, [3, 16]);
+    }
+
+    /// Get layer names of an encoded tile
+    fn layer_names(bytes: &[u8]) -> Vec<String> {
+        let summary = decode_summary(bytes).unwrap();
+        summary.layers.into_iter().map(|l| l.name).collect()
+    }
+
+    #[test]
+    fn budget_trims_last_layer() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let layers = vec![
+            mem_layer("a", "point", tile_points(tid, 8)),
+            mem_layer("b", "point", tile_points(tid, 16)),
+        ];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(layers, None));
+        let fetch = |wyrm: &Wyrm| match wyrm.try_fetch_tile("test", tid) {
+            Ok(TileFetch::Tile(bytes)) => bytes,
+            _ => panic!("no tile"),
+        };
+        let counts = |bytes: &[u8]| -> Vec<usize> {
+            let summary = decode_summary(bytes).unwrap();
+            summary.layers.iter().map(|l| l.features.len()).collect()
+        };
+        let full = fetch(&wyrm);
+        assert_eq!(counts(&full), [64, 256]);
+        let budget = full.len() / 2;
+        wyrm.groups[0].max_tile_bytes = Some(budget);
+        let bytes = fetch(&wyrm);
+        assert!(bytes.len() <= budget, "{} > {budget}", bytes.len());
+        // the first (highest priority) layer is intact
+        assert_eq!(counts(&bytes), [64, 64]);
+        // with a tiny budget, both layers are trimmed to the floor
+        wyrm.groups[0].max_tile_bytes = Some(1);
+        let bytes = fetch(&wyrm);
+        assert_eq!(counts(&bytes), [8, 8]);
+    }
+
+    #[test]
+    fn layer_order_keep_empty() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let next = TileId::new(302, 400, 10).unwrap();
+        let far = TileId::new(900, 100, 10).unwrap();
+        let mut layers = vec![
+            mem_layer_cfg(
+                "a",
+                "point",
+                "zoom: 0+\norder: 2\n",
+                tile_points(tid, 2),
+            ),
+            mem_layer_cfg("b", "point", "zoom: 0+\norder: 1\n", Vec::new()),
+            mem_layer_cfg("c", "point", "zoom: 12+\n", Vec::new()),
+            mem_layer_cfg(
+                "d",
+                "point",
+                "zoom: 0+\norder: 1\n",
+                tile_points(next, 2),
+            ),
+            mem_layer_cfg("e", "point", "zoom: 0+\n", Vec::new()),
+        ];
+        sort_layers(&mut layers);
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(layers, None));
+        let ctx = RequestCtx::default();
+        let fetch = |wyrm: &Wyrm, tid| {
+            let mut out = Vec::new();
+            match wyrm.write_tile_stream(&mut out, "test", tid, &ctx).unwrap() {
+                TileFetch::Tile(_) => {
+                    // streamed and buffered tiles are the same
+                    let tile = wyrm.try_fetch_tile("test", tid).unwrap();
+                    assert_eq!(tile, TileFetch::Tile(out.clone()));
+                    Some(layer_names(&out))
+                }
+                _ => None,
+            }
+        };
+        // empty layers dropped
+        assert_eq!(fetch(&wyrm, tid).unwrap(), ["a"]);
+        assert_eq!(fetch(&wyrm, next).unwrap(), ["d"]);
+        assert_eq!(fetch(&wyrm, far), None);
+        // empty layers kept in order, only within their zoom range
+        wyrm.groups[0].keep_empty_layers = true;
+        assert_eq!(fetch(&wyrm, tid).unwrap(), ["e", "b", "d", "a"]);
+        assert_eq!(fetch(&wyrm, next).unwrap(), ["e", "b", "d", "a"]);
+        // a tile with no features is still empty
+        assert_eq!(fetch(&wyrm, far), None);
+        // budget path is the same
+        wyrm.groups[0].max_tile_bytes = Some(1 << 20);
+        assert_eq!(fetch(&wyrm, tid).unwrap(), ["e", "b", "d", "a"]);
+        assert_eq!(fetch(&wyrm, far), None);
+    }
+
+    #[test]
+    fn estimate_tile_size() {
+        let tid = TileId::new(300, 400, 10).unwrap();
+        let mut layer = mem_layer("a", "point", tile_points(tid, 4));
+        layer.loam_bytes = vec![4 << 20];
+        let mut wyrm = Wyrm::with_tile_extent(TileExtent::new(256, Some(0)));
+        wyrm.groups.push(test_group(vec![layer], Some(10)));
+        // all data is within the world tile
+        let world = TileId::new(0, 0, 0).unwrap();
+        assert_eq!(wyrm.estimate_tile_size("test", world), Some(1 << 20));
+        let at = wyrm.estimate_tile_size("test", tid).unwrap();
+        assert!(at > 0 && at <= 1 << 20);
+        // beyond bitmap zoom, data is assumed to be spread evenly
+        let child = TileId::new(600, 800, 11).unwrap();
+        assert_eq!(wyrm.estimate_tile_size("test", child), Some(at / 4));
+        let far = TileId::new(900, 100, 10).unwrap();
+        assert_eq!(wyrm.estimate_tile_size("test", far), Some(0));
+        assert_eq!(wyrm.estimate_tile_size("none", tid), None);
+        // no estimate without a tile bitmap
+        let layer = mem_layer("a", "point", tile_points(tid, 4));
+        wyrm.groups[0] = test_group(vec![layer], None);
+        assert_eq!(wyrm.estimate_tile_size("test", tid), None);
+    }
+
+    #[test]
+    fn parse_path_accept() {
+        let tid = TileId::parse_path("tile", 7, 26, "48.mvt").unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z()), (26, 48, 7));
+        let tid = TileId::parse_path("a_b-9", 0, 0, "0.mvt").unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z()), (0, 0, 0));
+        let max = (1 << ZOOM_MAX) - 1;
+        let tail = format!("{max}.mvt");
+        let tid = TileId::parse_path("tile", ZOOM_MAX, max, &tail).unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z()), (max, max, ZOOM_MAX));
+        let (tid, scale) =
+            TileId::parse_path_scaled("tile", 3, 1, "2@2x.mvt").unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z(), scale), (1, 2, 3, 2));
+        let tid = TileId::parse_path_geojson("tile", 3, 1, "2.json").unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z()), (1, 2, 3));
+    }
+
+    #[test]
+    fn parse_path_reject() {
+        let cases = [
+            // extension
+            ("tile", 1, 0, "0.png"),
+            ("tile", 1, 0, "0"),
+            ("tile", 1, 0, "0.mvt.gz"),
+            // y not digits
+            ("tile", 1, 0, ".mvt"),
+            ("tile", 1, 0, "-1.mvt"),
+            ("tile", 1, 0, "+1.mvt"),
+            ("tile", 1, 0, "1a.mvt"),
+            ("tile", 1, 0, " 1.mvt"),
+            ("tile", 1, 0, "99999999999.mvt"),
+            // x / y out of range
+            ("tile", 0, 1, "0.mvt"),
+            ("tile", 0, 0, "1.mvt"),
+            ("tile", 3, 8, "0.mvt"),
+            ("tile", 3, 0, "8.mvt"),
+            // zoom
+            ("tile", ZOOM_MAX + 1, 0, "0.mvt"),
+            ("tile", u32::MAX, 0, "0.mvt"),
+            // group
+            ("", 1, 0, "0.mvt"),
+            ("ti le", 1, 0, "0.mvt"),
+            ("tile.mvt", 1, 0, "0.mvt"),
+            ("../tile", 1, 0, "0.mvt"),
+            ("t\u{e9}l\u{e9}", 1, 0, "0.mvt"),
+        ];
+        for (group, z, x, tail) in cases {
+            assert!(
+                matches!(
+                    TileId::parse_path(group, z, x, tail),
+                    Err(Error::InvalidTileRequest(_))
+                ),
+                "{group}/{z}/{x}/{tail}"
+            );
+        }
+        for tail in ["0@0x.mvt", "0@x.mvt", "0@2.mvt", "0@99x.mvt"] {
+            assert!(TileId::parse_path_scaled("tile", 1, 0, tail).is_err());
+        }
+        assert!(TileId::parse_path_geojson("tile", 1, 0, "0.mvt").is_err());
+    }
+
+    #[test]
+    fn quadkey_round_trip() {
+        let tid = TileId::from_quadkey("0231010").unwrap();
+        assert_eq!((tid.x(), tid.y(), tid.z()), (26, 48, 7));
+        assert_eq!(tid.to_quadkey(), "0231010");
+        let root = TileId::from_quadkey("").unwrap();
+        assert_eq!((root.x(), root.y(), root.z()), (0, 0, 0));
+        assert_eq!(root.to_quadkey(), "");
+        for z in 0..=ZOOM_MAX {
+            let max = (1u64 << z) - 1;
+            for (x, y) in [(0, 0), (max, 0), (0, max), (max, max), (max / 3, 0)]
+            {
+                let tid = TileId::new(x as u32, y as u32, z).unwrap();
+                let quadkey = tid.to_quadkey();
+                assert_eq!(quadkey.len(), z as usize);
+                assert_eq!(TileId::from_quadkey(&quadkey).unwrap(), tid);
+            }
+        }
+    }
+
+    #[test]
+    fn quadkey_reject() {
+        for quadkey in ["4", "0123x", "01-2", " 0", "0.mvt"] {
+            assert!(
+                matches!(
+                    TileId::from_quadkey(quadkey),
+                    Err(Error::InvalidTileRequest(_))
+                ),
+                "{quadkey}"
+            );
         }
+        let long = "0".repeat(ZOOM_MAX as usize + 1);
+        assert!(TileId::from_quadkey(&long).is_err());
+        let max = "3".repeat(ZOOM_MAX as usize);
+        assert!(TileId::from_quadkey(&max).is_ok());
     }
 }